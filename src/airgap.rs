@@ -0,0 +1,286 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Directory convention for shuttling PSBTs to an air-gapped signing device and back, for
+//! instance on an SD card or a USB stick.
+//!
+//! The directory contains three subdirectories:
+//! - `psbt` with the unsigned PSBTs exported for signing, named `<seq>-<txid>.psbt`, where `<seq>`
+//!   is a zero-padded sequence number of the export and `<txid>` is the id of the unsigned
+//!   transaction;
+//! - `signed` receiving PSBTs signed by the device, which may have any name with `.psbt` extension;
+//! - `txs` receiving signed transactions extracted from the signed PSBTs, named `<seq>-<txid>.tx`
+//!   after the exported PSBT they originate from.
+//!
+//! Each file written into the directory is accompanied with a checksum file named
+//! `<file>.sha256` in the format produced by `sha256sum`, such that the files can be verified
+//! after being carried on a removable media. Checksums of the signed PSBTs are verified if the
+//! signing device provides them.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fs, io};
+
+use amplify::hex::ToHex;
+use amplify::IoError;
+use bpstd::{ConsensusEncode, Tx, Txid};
+use psbt::{DecodeError, Psbt};
+use sha2::{Digest, Sha256};
+
+use crate::DryRun;
+
+/// Name of the air-gap subdirectory containing unsigned PSBTs exported for signing.
+pub const AIRGAP_PSBT: &str = "psbt";
+/// Name of the air-gap subdirectory receiving PSBTs signed by the device.
+pub const AIRGAP_SIGNED: &str = "signed";
+/// Name of the air-gap subdirectory receiving extracted signed transactions.
+pub const AIRGAP_TXS: &str = "txs";
+/// Extension of the checksum files.
+pub const AIRGAP_CHECKSUM: &str = "sha256";
+
+/// Errors working with air-gap directories.
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AirgapError {
+    /// I/O error in the air-gap directory: {0}
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// invalid PSBT file {0:?}: {1}
+    InvalidPsbt(PathBuf, DecodeError),
+
+    /// file {0:?} doesn't match its SHA256 checksum; the file is probably corrupted.
+    ChecksumMismatch(PathBuf),
+
+    /// transaction {0} is already exported as {1:?}.
+    AlreadyExported(Txid, PathBuf),
+
+    /// signed PSBT {0:?} spends transaction {1} which was not exported into the directory.
+    UnknownPsbt(PathBuf, Txid),
+}
+
+/// PSBT signed by the air-gapped device.
+#[derive(Clone, Debug)]
+pub struct SignedPsbt {
+    pub path: PathBuf,
+    /// Sequence number of the exported PSBT which was signed.
+    pub seq: u32,
+    pub psbt: Psbt,
+    /// Time of the last modification of the file, if supported by the file system.
+    pub modified: Option<SystemTime>,
+}
+
+/// Air-gap directory, see module-level docs for the directory layout.
+#[derive(Clone, Debug)]
+pub struct AirgapDir {
+    dir: PathBuf,
+    changes: DryRun,
+}
+
+impl AirgapDir {
+    /// Opens air-gap directory, creating it and its subdirectories when they don't exist.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, AirgapError> {
+        Self::open_with_changes(dir, &none!())
+    }
+
+    /// Opens air-gap directory like [`Self::open`], routing all the writes through the
+    /// [`DryRun`] collector.
+    pub fn open_with_changes(
+        dir: impl Into<PathBuf>,
+        changes: &DryRun,
+    ) -> Result<Self, AirgapError> {
+        let dir = dir.into();
+        for subdir in [AIRGAP_PSBT, AIRGAP_SIGNED, AIRGAP_TXS] {
+            let subdir = dir.join(subdir);
+            if !subdir.is_dir() && changes.perform(format!("create directory {}", subdir.display()))
+            {
+                fs::create_dir_all(subdir)?;
+            }
+        }
+        Ok(AirgapDir {
+            dir,
+            changes: changes.clone(),
+        })
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    /// Exported PSBTs by their sequence numbers, together with the ids of their unsigned
+    /// transactions.
+    pub fn exported(&self) -> Result<BTreeMap<u32, (Txid, PathBuf)>, AirgapError> {
+        Ok(self
+            .files(AIRGAP_PSBT, "psbt")?
+            .into_iter()
+            .filter_map(|path| {
+                let (seq, txid) = parse_name(&path)?;
+                Some((seq, (txid, path)))
+            })
+            .collect())
+    }
+
+    /// Exports unsigned PSBT into the `psbt` subdirectory under the next sequence number,
+    /// returning the path to the created file.
+    pub fn export(&self, psbt: &Psbt) -> Result<PathBuf, AirgapError> {
+        let txid = psbt.txid();
+        let exported = self.exported()?;
+        if let Some((_, path)) = exported.values().find(|(id, _)| *id == txid) {
+            return Err(AirgapError::AlreadyExported(txid, path.clone()));
+        }
+        let seq = exported.keys().last().map(|seq| seq + 1).unwrap_or(1);
+        let path = self.dir.join(AIRGAP_PSBT).join(format!("{seq:06}-{txid}.psbt"));
+        let mut data = vec![];
+        psbt.encode(psbt.version, &mut data)?;
+        write_with_checksum(&self.changes, &path, &data)?;
+        Ok(path)
+    }
+
+    /// Reads signed PSBTs for which no transaction was extracted yet, verifying their checksums
+    /// when checksum files are present.
+    pub fn signed(&self) -> Result<Vec<SignedPsbt>, AirgapError> {
+        let exported = self.exported()?;
+        let extracted = self
+            .files(AIRGAP_TXS, "tx")?
+            .iter()
+            .filter_map(|path| parse_name(path))
+            .map(|(seq, _)| seq)
+            .collect::<Vec<_>>();
+
+        let mut signed = vec![];
+        for path in self.files(AIRGAP_SIGNED, "psbt")? {
+            let data = fs::read(&path)?;
+            verify_checksum(&path, &data)?;
+            let psbt = Psbt::decode(&mut data.as_slice())
+                .map_err(|err| AirgapError::InvalidPsbt(path.clone(), err))?;
+            let txid = psbt.txid();
+            let Some((&seq, _)) = exported.iter().find(|(_, (id, _))| *id == txid) else {
+                return Err(AirgapError::UnknownPsbt(path, txid));
+            };
+            if extracted.contains(&seq) {
+                continue;
+            }
+            let modified = fs::metadata(&path)?.modified().ok();
+            signed.push(SignedPsbt {
+                path,
+                seq,
+                psbt,
+                modified,
+            });
+        }
+        Ok(signed)
+    }
+
+    /// Saves signed transaction extracted from the signed PSBT into `txs` subdirectory,
+    /// returning the path to the created file.
+    pub fn save_tx(&self, signed: &SignedPsbt, tx: &Tx) -> Result<PathBuf, AirgapError> {
+        let path = self.dir.join(AIRGAP_TXS).join(format!("{:06}-{}.tx", signed.seq, tx.txid()));
+        let mut data = vec![];
+        tx.consensus_encode(&mut data)?;
+        write_with_checksum(&self.changes, &path, &data)?;
+        Ok(path)
+    }
+
+    fn files(&self, subdir: &str, ext: &str) -> Result<Vec<PathBuf>, AirgapError> {
+        let mut files = vec![];
+        let dir = self.dir.join(subdir);
+        // Subdirectories are not created in dry-run mode
+        if !dir.is_dir() {
+            return Ok(files);
+        }
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().and_then(|e| e.to_str()) == Some(ext) {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+}
+
+/// Parses sequence number and transaction id from the name of the file created in the air-gap
+/// directory.
+fn parse_name(path: &Path) -> Option<(u32, Txid)> {
+    let (seq, txid) = path.file_stem()?.to_str()?.split_once('-')?;
+    Some((seq.parse().ok()?, txid.parse().ok()?))
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(".");
+    name.push(AIRGAP_CHECKSUM);
+    path.with_file_name(name)
+}
+
+fn write_with_checksum(changes: &DryRun, path: &Path, data: &[u8]) -> Result<(), AirgapError> {
+    changes.write(path, data)?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let hash = Sha256::digest(data).to_hex();
+    changes.write(checksum_path(path), format!("{hash}  {name}\n"))?;
+    Ok(())
+}
+
+fn verify_checksum(path: &Path, data: &[u8]) -> Result<(), AirgapError> {
+    let checksum_path = checksum_path(path);
+    if !checksum_path.is_file() {
+        return Ok(());
+    }
+    let checksum = fs::read_to_string(checksum_path)?;
+    let expected = checksum.split_whitespace().next().unwrap_or_default();
+    if !expected.eq_ignore_ascii_case(&Sha256::digest(data).to_hex()) {
+        return Err(AirgapError::ChecksumMismatch(path.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use psbt::PsbtVer;
+
+    use super::*;
+
+    #[test]
+    fn test_export_import() {
+        let dir = std::env::temp_dir().join(format!("bp-airgap-{}", std::process::id()));
+        let airgap = AirgapDir::open(&dir).unwrap();
+        let psbt = Psbt::create(PsbtVer::V0);
+        let txid = psbt.txid();
+
+        let path = airgap.export(&psbt).unwrap();
+        assert_eq!(path, dir.join(AIRGAP_PSBT).join(format!("000001-{txid}.psbt")));
+        assert!(matches!(airgap.export(&psbt), Err(AirgapError::AlreadyExported(..))));
+
+        let signed_path = dir.join(AIRGAP_SIGNED).join("signed.psbt");
+        fs::copy(&path, &signed_path).unwrap();
+        fs::copy(checksum_path(&path), checksum_path(&signed_path)).unwrap();
+        let signed = airgap.signed().unwrap();
+        assert_eq!(signed.len(), 1);
+        assert_eq!(signed[0].seq, 1);
+
+        fs::write(checksum_path(&signed_path), "00  signed.psbt\n").unwrap();
+        assert!(matches!(airgap.signed(), Err(AirgapError::ChecksumMismatch(_))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Amendment of already constructed, but not yet signed, PSBTs with additional inputs and
+//! outputs.
+
+use bpstd::{Outpoint, Sats, ScriptPubkey};
+use psbt::{Input, Prevout, Psbt, PsbtConstructor, PsbtVer};
+
+use crate::SEQ_NO_RBF;
+
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AmendError {
+    /// PSBT {0} doesn't support addition of inputs and outputs; only PSBT v2 can be amended.
+    Version(PsbtVer),
+
+    /// inputs of the PSBT were marked as non-modifiable by its constructor.
+    InputsUnmodifiable,
+
+    /// outputs of the PSBT were marked as non-modifiable by its constructor.
+    OutputsUnmodifiable,
+
+    /// PSBT already contains signatures, which would be invalidated by the amendment.
+    Signed,
+
+    /// the PSBT already spends {0}.
+    DuplicateInput(Outpoint),
+
+    /// {0} is not an unspent output of the wallet.
+    UnknownCoin(Outpoint),
+}
+
+/// Addition of inputs and outputs to an existing PSBT v2 before any of its inputs is signed.
+///
+/// The fee of the amended PSBT is always the difference between the values of its inputs and
+/// outputs, so it changes with each amendment and can be read with [`Psbt::fee`].
+pub trait PsbtAmender: PsbtConstructor {
+    /// Adds wallet coin as a new input, using the sequence number of the existing inputs.
+    fn add_input(&self, psbt: &mut Psbt, outpoint: Outpoint) -> Result<(), AmendError> {
+        check_amendable(psbt)?;
+        if psbt.inputs().any(|input| input.previous_outpoint == outpoint) {
+            return Err(AmendError::DuplicateInput(outpoint));
+        }
+        let utxo = self.utxo(outpoint).ok_or(AmendError::UnknownCoin(outpoint))?;
+        let seq_no =
+            psbt.inputs().next().and_then(|input| input.sequence_number).unwrap_or(SEQ_NO_RBF);
+        psbt.construct_input(
+            Prevout::new(outpoint, utxo.value),
+            self.descriptor(),
+            utxo.terminal,
+            seq_no,
+        )
+        .map_err(|_| AmendError::InputsUnmodifiable)?;
+        Ok(())
+    }
+
+    /// Adds a new output paying `amount` to `script`.
+    fn add_output(
+        &self,
+        psbt: &mut Psbt,
+        script: ScriptPubkey,
+        amount: Sats,
+    ) -> Result<(), AmendError> {
+        check_amendable(psbt)?;
+        psbt.construct_output(script, amount).map_err(|_| AmendError::OutputsUnmodifiable)?;
+        Ok(())
+    }
+}
+
+impl<T: PsbtConstructor> PsbtAmender for T {}
+
+fn check_amendable(psbt: &Psbt) -> Result<(), AmendError> {
+    if psbt.version != PsbtVer::V2 {
+        return Err(AmendError::Version(psbt.version));
+    }
+    if psbt.inputs().any(is_signed) {
+        return Err(AmendError::Signed);
+    }
+    Ok(())
+}
+
+fn is_signed(input: &Input) -> bool {
+    !input.partial_sigs.is_empty()
+        || input.tap_key_sig.is_some()
+        || !input.tap_script_sig.is_empty()
+        || input.final_script_sig.is_some()
+        || input.final_witness.is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bpstd::{Address, Keychain, Txid, Vout};
+    use psbt::{Beneficiary, TxParams};
+
+    use super::*;
+    use crate::fixtures::TestCoins;
+
+    #[test]
+    fn test_amend_psbt() {
+        let mut coins = TestCoins::new(Sats::from(10_000u64)).with_count(2);
+        let outpoint = |vout| Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(vout));
+        let address = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        let beneficiaries = [Beneficiary::new(address, Sats::from(5_000u64))];
+        let params = TxParams::with(Sats::from(500u64));
+        let (mut psbt, _) = coins
+            .construct_psbt([outpoint(0)], &beneficiaries, TxParams {
+                change_keychain: Keychain::INNER,
+                ..params
+            })
+            .unwrap();
+        let fee = psbt.fee().unwrap();
+
+        psbt.version = PsbtVer::V0;
+        assert!(matches!(coins.add_input(&mut psbt, outpoint(0)), Err(AmendError::Version(_))));
+        psbt.version = PsbtVer::V2;
+        assert!(matches!(
+            coins.add_input(&mut psbt, outpoint(0)),
+            Err(AmendError::DuplicateInput(_))
+        ));
+        assert!(matches!(coins.add_input(&mut psbt, outpoint(2)), Err(AmendError::UnknownCoin(_))));
+
+        coins.add_input(&mut psbt, outpoint(1)).unwrap();
+        assert_eq!(psbt.fee(), Some(fee + Sats::from(10_000u64)));
+        coins.add_output(&mut psbt, address.script_pubkey(), Sats::from(9_000u64)).unwrap();
+        assert_eq!(psbt.fee(), Some(fee + Sats::from(1_000u64)));
+        assert_eq!(psbt.inputs().count(), 2);
+
+        psbt.inputs_mut().next().unwrap().final_witness = Some(none!());
+        assert!(matches!(
+            coins.add_output(&mut psbt, address.script_pubkey(), Sats::from(100u64)),
+            Err(AmendError::Signed)
+        ));
+    }
+}
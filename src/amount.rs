@@ -0,0 +1,407 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bpstd::{Address, AddressParseError, Sats};
+use psbt::{Beneficiary, Payment};
+
+use crate::standardness::dust_threshold;
+
+/// Number of satoshis in existence, used to detect overflowing amounts.
+pub const MAX_MONEY: Sats = Sats(21_000_000 * Sats::BTC.0);
+
+const BTC_DECIMALS: usize = 8;
+
+/// Errors parsing bitcoin amount.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum AmountParseError {
+    /// empty amount string.
+    Empty,
+
+    /// invalid amount '{0}'; only decimal digits with an optional `.` decimal separator and `_`
+    /// digit group separators are allowed.
+    InvalidDigits(String),
+
+    /// amount '{0}' uses `,` which is ambiguous across locales; use `.` as the decimal separator
+    /// and `_` for digit grouping.
+    LocaleSeparator(String),
+
+    /// amount '{0}' has more than 8 decimal digits, which is below a single satoshi.
+    ExcessPrecision(String),
+
+    /// amount '{0}' is given in satoshis, which can't have a fractional part.
+    FractionalSats(String),
+
+    /// millisatoshi amounts are not supported for on-chain payments.
+    MilliSats,
+
+    /// unknown amount unit '{0}'; use either `btc`, `sat` or `k` (thousands of satoshis).
+    UnknownUnit(String),
+
+    /// amount '{0}' exceeds the number of bitcoins in existence.
+    Overflow(String),
+
+    /// invalid balance share '{0}'; it must be either `MAX`, `MAX*<weight>` with a non-zero
+    /// weight or `<percent>%` in range 1..=100.
+    InvalidShare(String),
+}
+
+/// Errors parsing payment instruction in form of `<amount>@<address>`.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum PaymentParseError {
+    #[display("invalid payment format '{0}'; it must have form of `<amount>@<address>`")]
+    InvalidFormat(String),
+
+    #[from]
+    Amount(AmountParseError),
+
+    #[from]
+    Address(AddressParseError),
+
+    #[display(
+        "payment of {amount} sats to {address} is below the dust threshold of {threshold} sats \
+         for its script type"
+    )]
+    Dust {
+        address: Address,
+        amount: Sats,
+        threshold: u64,
+    },
+}
+
+/// Denomination of bitcoin amounts.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+pub enum Unit {
+    #[display("btc")]
+    Btc,
+    #[display("sat")]
+    Sat,
+}
+
+/// Bitcoin amount, which can be parsed from either satoshi or BTC-denominated string.
+///
+/// Integer values without unit suffix are interpreted as satoshis, and values with decimal point
+/// but without the suffix - as BTC. The `k` suffix denotes thousands of satoshis. Examples of
+/// accepted strings: `100000`, `100_000 sat`, `150k`, `0.5btc`, `1.234_567_89`.
+///
+/// By default, the amount is displayed in satoshis; the alternate form (`{:#}`) displays it in
+/// BTC with a unit suffix.
+#[derive(Wrapper, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, From)]
+#[wrapper(Deref)]
+pub struct Amount(Sats);
+
+impl Amount {
+    #[inline]
+    pub fn sats(self) -> Sats { self.0 }
+
+    /// Formats the amount in BTC with all 8 decimal digits and without a unit suffix.
+    pub fn to_btc_string(&self) -> String {
+        let (btc, sats) = self.0.btc_sats();
+        format!("{btc}.{sats:0>BTC_DECIMALS$}")
+    }
+
+    /// Formats the amount in satoshis, grouping digits by thousands with `_`.
+    pub fn to_grouped_sats_string(&self) -> String {
+        let digits = self.0.sats().to_string();
+        let mut s = String::with_capacity(digits.len() * 4 / 3);
+        for (pos, c) in digits.chars().enumerate() {
+            if pos > 0 && (digits.len() - pos) % 3 == 0 {
+                s.push('_');
+            }
+            s.push(c);
+        }
+        s
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{} {}", self.to_btc_string(), Unit::Btc)
+        } else {
+            Display::fmt(&self.0, f)
+        }
+    }
+}
+
+impl FromStr for Amount {
+    type Err = AmountParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(AmountParseError::Empty);
+        }
+        let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let number = number.trim_end();
+        // Number of the decimal digits allowed by the unit
+        let (unit, decimals) = match unit.to_ascii_lowercase().as_str() {
+            "" if number.contains('.') => (Unit::Btc, BTC_DECIMALS),
+            "" | "sat" | "sats" => (Unit::Sat, 0),
+            "k" | "ksat" | "ksats" => (Unit::Sat, 3),
+            "btc" => (Unit::Btc, BTC_DECIMALS),
+            "msat" | "msats" => return Err(AmountParseError::MilliSats),
+            _ => return Err(AmountParseError::UnknownUnit(unit.to_owned())),
+        };
+
+        if number.contains(',') {
+            return Err(AmountParseError::LocaleSeparator(s.to_owned()));
+        }
+        let digits = number.replace('_', "");
+        let (int, frac) = digits.split_once('.').unwrap_or((&digits, ""));
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if int.is_empty() || !is_digits(int) || !is_digits(frac) || digits.ends_with('.') {
+            return Err(AmountParseError::InvalidDigits(s.to_owned()));
+        }
+
+        let overflow = || AmountParseError::Overflow(s.to_owned());
+        let int = u64::from_str(int).map_err(|_| overflow())?;
+        if frac.len() > decimals {
+            return Err(match unit {
+                Unit::Sat => AmountParseError::FractionalSats(s.to_owned()),
+                Unit::Btc => AmountParseError::ExcessPrecision(s.to_owned()),
+            });
+        }
+        let frac = match decimals {
+            0 => 0,
+            _ => u64::from_str(&format!("{frac:0<decimals$}"))
+                .expect("string consists of decimal digits"),
+        };
+        let sats = int
+            .checked_mul(10u64.pow(decimals as u32))
+            .and_then(|sats| sats.checked_add(frac))
+            .ok_or_else(overflow)?;
+        if sats > MAX_MONEY.0 {
+            return Err(overflow());
+        }
+        Ok(Amount(Sats(sats)))
+    }
+}
+
+/// Share of the funds remaining after fixed payments and the fee.
+///
+/// Shares are given either as a weight (`MAX` has weight 1, `MAX*2` - weight 2), in which case
+/// the remaining funds are split between the weighted shares proportionally, or as a percentage
+/// of the remaining funds (`50%`). Percentages are allocated before the weighted shares.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Share {
+    Weight(u32),
+    Percent(u8),
+}
+
+impl Share {
+    /// Share taking all remaining funds (or an equal part of them, if there are other `MAX`
+    /// shares).
+    pub const MAX: Share = Share::Weight(1);
+}
+
+impl Display for Share {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Share::Weight(1) => f.write_str("MAX"),
+            Share::Weight(weight) => write!(f, "MAX*{weight}"),
+            Share::Percent(percent) => write!(f, "{percent}%"),
+        }
+    }
+}
+
+impl FromStr for Share {
+    type Err = AmountParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let err = || AmountParseError::InvalidShare(s.to_owned());
+        if s == "MAX" {
+            return Ok(Share::MAX);
+        }
+        if let Some(weight) = s.strip_prefix("MAX*") {
+            return match u32::from_str(weight) {
+                Ok(weight) if weight > 0 => Ok(Share::Weight(weight)),
+                _ => Err(err()),
+            };
+        }
+        match s.strip_suffix('%').map(u8::from_str) {
+            Some(Ok(percent)) if (1..=100).contains(&percent) => Ok(Share::Percent(percent)),
+            _ => Err(err()),
+        }
+    }
+}
+
+/// Parses amount string into [`Sats`] using [`Amount`] rules. Can be used as a `clap` value
+/// parser.
+pub fn parse_sats(s: &str) -> Result<Sats, AmountParseError> {
+    Amount::from_str(s).map(Amount::sats)
+}
+
+/// Parses payment amount, which is either `MAX` or an [`Amount`].
+pub fn parse_payment(s: &str) -> Result<Payment, AmountParseError> {
+    if s.trim() == "MAX" {
+        return Ok(Payment::Max);
+    }
+    parse_sats(s).map(Payment::Fixed)
+}
+
+/// Parses address copied by a user, normalizing it: a `bitcoin:` URI prefix with its query is
+/// removed and upper-case bech32 addresses (used in QR codes) are converted into lower case. Can
+/// be used as a `clap` value parser.
+pub fn parse_address(s: &str) -> Result<Address, AddressParseError> {
+    let s = s.trim();
+    let s = match s.split_once(':') {
+        Some((scheme, uri)) if scheme.eq_ignore_ascii_case("bitcoin") => {
+            uri.split_once('?').map_or(uri, |(address, _)| address)
+        }
+        _ => s,
+    };
+    // Case of base58 addresses is significant, thus only the upper-case bech32 addresses are
+    // re-tried in lower case
+    Address::from_str(s).or_else(|err| {
+        let lower = s.to_ascii_lowercase();
+        let bech32 = ["bc1", "tb1", "bcrt1"].iter().any(|hrp| lower.starts_with(hrp));
+        match bech32 && !s.bytes().any(|b| b.is_ascii_lowercase()) {
+            true => Address::from_str(&lower),
+            false => Err(err),
+        }
+    })
+}
+
+/// Parses beneficiary in form of `<amount>@<address>`, where the amount is either `MAX` or an
+/// [`Amount`], normalizing the address with [`parse_address`] and checking the amount with
+/// [`check_dust`]. Can be used as a `clap` value parser.
+pub fn parse_beneficiary(s: &str) -> Result<Beneficiary, PaymentParseError> {
+    let (amount, address) =
+        s.rsplit_once('@').ok_or_else(|| PaymentParseError::InvalidFormat(s.to_owned()))?;
+    let beneficiary = Beneficiary::new(parse_address(address)?, parse_payment(amount)?);
+    check_dust(&beneficiary)?;
+    Ok(beneficiary)
+}
+
+/// Checks that a fixed payment is not below the dust threshold for the script type of the
+/// beneficiary address, such that the transaction paying it is relayed by the network.
+pub fn check_dust(beneficiary: &Beneficiary) -> Result<(), PaymentParseError> {
+    let Payment::Fixed(amount) = beneficiary.amount else {
+        return Ok(());
+    };
+    let threshold = dust_threshold(&beneficiary.address.script_pubkey());
+    if amount.sats() < threshold {
+        return Err(PaymentParseError::Dust {
+            address: beneficiary.address,
+            amount,
+            threshold,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_amount_parse() {
+        fn sats(s: &str) -> u64 { Amount::from_str(s).unwrap().sats().sats() }
+
+        assert_eq!(sats("100000"), 100_000);
+        assert_eq!(sats("100_000 sat"), 100_000);
+        assert_eq!(sats("100000sats"), 100_000);
+        assert_eq!(sats("0.5btc"), 50_000_000);
+        assert_eq!(sats("0.5 BTC"), 50_000_000);
+        assert_eq!(sats("1.234_567_89"), 123_456_789);
+        assert_eq!(sats("21000000 btc"), MAX_MONEY.sats());
+        assert_eq!(sats("150k"), 150_000);
+        assert_eq!(sats("1.5 ksat"), 1_500);
+        assert_eq!(sats("0.01btc"), 1_000_000);
+
+        assert_eq!(Amount::from_str(""), Err(AmountParseError::Empty));
+        assert_eq!(Amount::from_str("1msat"), Err(AmountParseError::MilliSats));
+        assert!(matches!(Amount::from_str("0,5"), Err(AmountParseError::LocaleSeparator(_))));
+        assert!(matches!(Amount::from_str("0.5 sat"), Err(AmountParseError::FractionalSats(_))));
+        assert!(matches!(Amount::from_str("1.2345k"), Err(AmountParseError::FractionalSats(_))));
+        assert!(matches!(
+            Amount::from_str("0.123456789"),
+            Err(AmountParseError::ExcessPrecision(_))
+        ));
+        assert!(matches!(Amount::from_str("21000000.1"), Err(AmountParseError::Overflow(_))));
+        assert!(matches!(Amount::from_str("-1"), Err(AmountParseError::InvalidDigits(_))));
+        assert!(matches!(Amount::from_str(".5"), Err(AmountParseError::InvalidDigits(_))));
+        assert!(matches!(Amount::from_str("5.btc"), Err(AmountParseError::InvalidDigits(_))));
+        assert!(matches!(Amount::from_str("1 eur"), Err(AmountParseError::UnknownUnit(_))));
+    }
+
+    #[test]
+    fn test_share_parse() {
+        assert_eq!(Share::from_str("MAX"), Ok(Share::MAX));
+        assert_eq!(Share::from_str("MAX*3"), Ok(Share::Weight(3)));
+        assert_eq!(Share::from_str("25%"), Ok(Share::Percent(25)));
+        assert_eq!(Share::Weight(2).to_string(), "MAX*2");
+        assert_eq!(Share::Percent(100).to_string(), "100%");
+        for invalid in ["MAX*0", "MAX*", "0%", "101%", "50", "max"] {
+            assert!(matches!(Share::from_str(invalid), Err(AmountParseError::InvalidShare(_))));
+        }
+    }
+
+    #[test]
+    fn test_amount_str_round_trip() {
+        let amount = Amount::from(Sats(123_456_789));
+        assert_eq!(amount.to_btc_string(), "1.23456789");
+        assert_eq!(amount.to_grouped_sats_string(), "123_456_789");
+        assert_eq!(Amount::from_str(&amount.to_string()).unwrap(), amount);
+        assert_eq!(Amount::from_str(&format!("{amount:#}")).unwrap(), amount);
+    }
+
+    #[test]
+    fn test_address_normalization() {
+        let bech32 = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        let base58 = Address::from_str("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap();
+        for s in [
+            "BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ",
+            "bitcoin:bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq",
+            "BITCOIN:BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ?amount=0.1",
+            " bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq\n",
+        ] {
+            assert_eq!(parse_address(s), Ok(bech32), "{s}");
+        }
+        assert_eq!(parse_address("bitcoin:1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2?label=x"), Ok(base58));
+        assert!(parse_address("1bvbmseystwetqtfn5au4m4gfg7xjanvn2").is_err());
+        assert!(parse_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdQ").is_err());
+    }
+
+    #[test]
+    fn test_beneficiary_dust() {
+        let p2wpkh = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        let p2pkh = "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2";
+        assert!(parse_beneficiary(&format!("294@{p2wpkh}")).is_ok());
+        assert!(parse_beneficiary(&format!("MAX@{p2wpkh}")).is_ok());
+        assert!(matches!(
+            parse_beneficiary(&format!("293@{p2wpkh}")),
+            Err(PaymentParseError::Dust { threshold: 294, .. })
+        ));
+        assert!(matches!(
+            parse_beneficiary(&format!("0.5k@{p2pkh}")),
+            Err(PaymentParseError::Dust { threshold: 546, .. })
+        ));
+    }
+}
@@ -26,11 +26,12 @@ extern crate serde_crate as serde;
 
 use std::process::ExitCode;
 
-use bpwallet::cli::LogLevel;
+use bpwallet::cli::{complete, LogLevel};
 use bpwallet::hot::{DataError, HotArgs};
 use clap::Parser;
 
 fn main() -> ExitCode {
+    complete::<HotArgs>();
     if let Err(err) = run() {
         eprintln!("Error: {err}");
         ExitCode::FAILURE
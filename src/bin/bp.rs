@@ -26,13 +26,15 @@ extern crate serde_crate as serde;
 
 use std::process::ExitCode;
 
-use bpwallet::cli::{Args, BpCommand, Config, DescrStdOpts, Exec, ExecError, LogLevel};
+use bpwallet::cli::{complete, Args, BpCommand, Config, DescrStdOpts, Exec, ExecError, LogLevel};
+use bpwallet::Change;
 use clap::Parser;
 
 fn main() -> ExitCode {
+    complete::<Args<BpCommand, DescrStdOpts>>();
     if let Err(err) = run() {
         eprintln!("Error: {err}");
-        ExitCode::FAILURE
+        ExitCode::from(err.exit_code())
     } else {
         ExitCode::SUCCESS
     }
@@ -48,7 +50,30 @@ fn run() -> Result<(), ExecError> {
     eprintln!("    by LNP/BP Standards Association\n");
 
     // TODO: Update arguments basing on the configuration
-    let conf = Config::load(&args.conf_path("bp"));
+    let mut conf = Config::load(&args.conf_path("bp"), &args.changes);
+    if args.resolver.persist_tls(&mut conf) {
+        conf.store(&args.conf_path("bp"), &args.changes);
+    }
+    args.display.discreet |= conf.discreet;
+    args.display.thousands_sep = args.display.thousands_sep.or(conf.thousands_sep);
+    args.display.btc |= conf.display_btc;
+    args.display.precision = args.display.precision.or(conf.btc_precision.map(|p| p.min(8)));
     debug!("Executing command: {}", args.command);
-    args.exec(conf, "bp")
+    let changes = args.changes.clone();
+    let res = args.exec(conf, "bp");
+    if changes.is_enabled() {
+        print_changes(&changes.changes());
+    }
+    res
+}
+
+fn print_changes(changes: &[Change]) {
+    if changes.is_empty() {
+        eprintln!("\nDry run: the command makes no changes");
+        return;
+    }
+    eprintln!("\nDry run: the following changes were not made");
+    for change in changes {
+        eprintln!("{change}");
+    }
 }
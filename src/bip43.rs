@@ -182,6 +182,32 @@ impl Bip43 {
     pub const MULTI_WSH: Bip43 = Bip43::Bip48Native;
     /// Constructs derivation standard corresponding to a multi-sig BIP87.
     pub const DESCRIPTOR: Bip43 = Bip43::Bip87;
+
+    /// Deduces derivation standard and account number from the account derivation path, if
+    /// the path is exactly the one the standard defines for the account.
+    ///
+    /// Generic BIP-43 purposes are not recognized, since they don't define the structure of the
+    /// account derivation.
+    pub fn deduce_account(
+        derivation: &DerivationPath<HardenedIndex>,
+        testnet: bool,
+    ) -> Option<(Bip43, HardenedIndex)> {
+        [
+            Bip43::Bip44,
+            Bip43::Bip84,
+            Bip43::Bip49,
+            Bip43::Bip86,
+            Bip43::Bip87,
+            Bip43::Bip48Nested,
+            Bip43::Bip48Native,
+        ]
+        .into_iter()
+        .find_map(|scheme| {
+            let account = *derivation.get(scheme.account_depth()? as usize - 1)?;
+            (scheme.to_account_derivation(account, testnet) == *derivation)
+                .then_some((scheme, account))
+        })
+    }
 }
 
 /// Methods for derivation standard enumeration types.
@@ -233,7 +259,12 @@ pub trait DerivationStandard: Eq + Clone {
         &self,
         path: &DerivationPath,
     ) -> Result<HardenedIndex, Option<NormalIndex>> {
-        let coin = self.coin_type_depth().and_then(|i| path.get(i as usize)).ok_or(None)?;
+        // Depth counts from the master key, so the key at depth `i` is the path segment `i - 1`
+        let coin = self
+            .coin_type_depth()
+            .and_then(|i| (i as usize).checked_sub(1))
+            .and_then(|i| path.get(i))
+            .ok_or(None)?;
         match coin {
             DerivationIndex::Normal(idx) => Err(Some(*idx)),
             DerivationIndex::Hardened(idx) => Ok(*idx),
@@ -253,7 +284,11 @@ pub trait DerivationStandard: Eq + Clone {
         &self,
         path: &DerivationPath,
     ) -> Result<HardenedIndex, Option<NormalIndex>> {
-        let coin = self.account_depth().and_then(|i| path.get(i as usize)).ok_or(None)?;
+        let coin = self
+            .account_depth()
+            .and_then(|i| (i as usize).checked_sub(1))
+            .and_then(|i| path.get(i))
+            .ok_or(None)?;
         match coin {
             DerivationIndex::Normal(idx) => Err(Some(*idx)),
             DerivationIndex::Hardened(idx) => Ok(*idx),
@@ -459,4 +494,36 @@ mod tests {
             purpose: HardenedIndex::hardened(1),
         });
     }
+
+    #[test]
+    fn test_deduce_account() {
+        let account = HardenedIndex::hardened(5);
+        for scheme in [Bip43::Bip44, Bip43::Bip84, Bip43::Bip86, Bip43::Bip48Native] {
+            let derivation = scheme.to_account_derivation(account, true);
+            assert_eq!(Bip43::deduce_account(&derivation, true), Some((scheme, account)));
+            assert_eq!(Bip43::deduce_account(&derivation, false), None);
+        }
+        let custom = DerivationPath::<HardenedIndex>::from_str("1000h/7h/0h").unwrap();
+        assert_eq!(Bip43::deduce_account(&custom, true), None);
+        let short = DerivationPath::<HardenedIndex>::from_str("84h/1h").unwrap();
+        assert_eq!(Bip43::deduce_account(&short, true), None);
+    }
+
+    #[test]
+    fn test_extract_indexes() {
+        for (scheme, path) in [
+            (Bip43::Bip44, "44h/1h/5h/0/3"),
+            (Bip43::Bip84, "84h/1h/5h/0/3"),
+            (Bip43::Bip86, "86h/1h/5h/0/3"),
+        ] {
+            let path = DerivationPath::<DerivationIndex>::from_str(path).unwrap();
+            assert_eq!(scheme.extract_coin_type(&path), Ok(HardenedIndex::hardened(1)));
+            assert_eq!(scheme.extract_account_index(&path), Ok(HardenedIndex::hardened(5)));
+        }
+        let unhardened = DerivationPath::<DerivationIndex>::from_str("84h/1/5h").unwrap();
+        assert_eq!(Bip43::Bip84.extract_coin_type(&unhardened), Err(Some(NormalIndex::normal(1))));
+        let short = DerivationPath::<DerivationIndex>::from_str("84h/1h").unwrap();
+        assert_eq!(Bip43::Bip84.extract_account_index(&short), Err(None));
+        assert_eq!(Bip43::Bip45.extract_coin_type(&short), Err(None));
+    }
 }
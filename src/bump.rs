@@ -0,0 +1,197 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Replacement of unconfirmed wallet transactions with the ones paying a higher fee (BIP-125
+//! replace-by-fee).
+
+use bpstd::{Descriptor, Keychain, Outpoint, Sats, Txid, Vout};
+use psbt::{Prevout, Psbt, PsbtConstructor, PsbtVer};
+
+use crate::data::Inpoint;
+use crate::{FeeDeductor, FeeRate, Layer2, Wallet, SEQ_NO_RBF};
+
+/// Fee rate by which the nodes require a replacement to increase the fee of the replaced
+/// transaction, applied to the replacement size (BIP-125 rule 4).
+pub const INCREMENTAL_RELAY_FEE: FeeRate = FeeRate::from_sat_per_vb(1);
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum BumpError {
+    /// transaction {0} is not known to the wallet.
+    UnknownTx(Txid),
+
+    /// transaction {0} is already mined.
+    Mined(Txid),
+
+    /// transaction {0} doesn't signal BIP-125 replaceability.
+    NotReplaceable(Txid),
+
+    /// input {0} doesn't belong to the wallet, so the replacement can't be signed.
+    ForeignInput(Outpoint),
+
+    /// output {0} is spent by {1}, which would be evicted together with the replaced transaction.
+    Spent(Outpoint, Inpoint),
+
+    /// transaction has no change output to pay the fee increase from.
+    NoChange,
+
+    /// fee rate {requested} doesn't exceed the fee rate of the replaced transaction ({current}).
+    FeeRateTooLow {
+        current: FeeRate,
+        requested: FeeRate,
+    },
+
+    /// fee increase of {increase} sats leaves the change of {change} sats below the dust limit.
+    InsufficientChange { change: Sats, increase: Sats },
+}
+
+/// Metadata of a replacement transaction constructed by [`Wallet::bump_fee`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BumpMeta {
+    pub replaced: Txid,
+    pub change_vout: Vout,
+    /// Value of the change output after the fee increase.
+    pub change: Sats,
+    /// Fee of the replacement.
+    pub fee: Sats,
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Constructs PSBT replacing an unconfirmed wallet transaction with the one paying the fee at
+    /// the given fee rate.
+    ///
+    /// The replacement spends the same coins with sequence numbers signalling replaceability and
+    /// pays to the same outputs, except the change output, which value is reduced by the fee
+    /// increase; if there are several change outputs, the largest one is used. The fee is
+    /// increased at least by [`INCREMENTAL_RELAY_FEE`] applied to the replacement weight, as
+    /// required for its relay.
+    pub fn bump_fee(&self, txid: Txid, fee_rate: FeeRate) -> Result<(Psbt, BumpMeta), BumpError> {
+        let tx = self.transactions().get(&txid).ok_or(BumpError::UnknownTx(txid))?;
+        if tx.status.is_mined() {
+            return Err(BumpError::Mined(txid));
+        }
+        if !tx.signals_rbf() {
+            return Err(BumpError::NotReplaceable(txid));
+        }
+        if fee_rate <= tx.fee_rate() {
+            return Err(BumpError::FeeRateTooLow {
+                current: tx.fee_rate(),
+                requested: fee_rate,
+            });
+        }
+        let inputs = tx
+            .inputs
+            .iter()
+            .map(|input| match input.derived_addr() {
+                Some(derived) => Ok((Prevout::new(input.outpoint, input.value), derived.terminal)),
+                None => Err(BumpError::ForeignInput(input.outpoint)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some((outpoint, inpoint)) =
+            tx.outputs.iter().find_map(|output| Some((output.outpoint, output.spent?)))
+        {
+            return Err(BumpError::Spent(outpoint, inpoint));
+        }
+        let (change, change_terminal) = tx
+            .outputs
+            .iter()
+            .filter_map(|output| Some((output, output.derived_addr()?.terminal)))
+            .filter(|(_, terminal)| terminal.keychain == Keychain::INNER)
+            .max_by_key(|(output, _)| output.value)
+            .ok_or(BumpError::NoChange)?;
+
+        let construct = |change_value: Sats| {
+            let mut psbt = Psbt::create(PsbtVer::V2);
+            for spec in self.descriptor().xpubs() {
+                psbt.xpubs.insert(*spec.xpub(), spec.origin().clone());
+            }
+            for (prevout, terminal) in &inputs {
+                psbt.construct_input_expect(*prevout, self.descriptor(), *terminal, SEQ_NO_RBF);
+            }
+            for output in &tx.outputs {
+                if output.outpoint == change.outpoint {
+                    psbt.construct_change_expect(self.descriptor(), change_terminal, change_value);
+                    continue;
+                }
+                let script = match output.derived_addr() {
+                    Some(derived) => derived.addr.script_pubkey(),
+                    None => output.beneficiary.script_pubkey().expect("outputs have scripts"),
+                };
+                psbt.construct_output_expect(script, output.value);
+            }
+            psbt
+        };
+
+        let weight = self.estimate_weight(&construct(change.value));
+        let fee = fee_rate
+            .fee_for_weight(weight)
+            .max(tx.fee + INCREMENTAL_RELAY_FEE.fee_for_weight(weight));
+        let increase = fee - tx.fee;
+        let change_value = change
+            .value
+            .checked_sub(increase)
+            .filter(|value| *value > self.descriptor().class().dust_limit())
+            .ok_or(BumpError::InsufficientChange {
+                change: change.value,
+                increase,
+            })?;
+        Ok((construct(change_value), BumpMeta {
+            replaced: txid,
+            change_vout: change.outpoint.vout,
+            change: change_value,
+            fee,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{publish_payment, test_wallet};
+    use crate::SimIndexer;
+
+    #[test]
+    fn test_bump_fee() {
+        let mut wallet = test_wallet();
+        let indexer = SimIndexer::default();
+        let (_, payment) = publish_payment(&mut wallet, &indexer, true);
+        let txid = payment.txid();
+        assert_eq!(wallet.transactions()[&txid].fee, Sats(500));
+
+        let low = FeeRate::from_sat_per_vb(1);
+        assert!(matches!(wallet.bump_fee(txid, low), Err(BumpError::FeeRateTooLow { .. })));
+
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        let (psbt, meta) = wallet.bump_fee(txid, fee_rate).unwrap();
+        assert_eq!(meta.replaced, txid);
+        assert_eq!(meta.change_vout, Vout::from_u32(1));
+        assert_eq!(psbt.fee(), Some(meta.fee));
+        assert_eq!(meta.fee, fee_rate.fee_for_weight(wallet.estimate_weight(&psbt)));
+        assert_eq!(meta.change, Sats(69_500) - (meta.fee - Sats(500)));
+        let outputs = psbt.outputs().map(|output| output.amount).collect::<Vec<_>>();
+        assert_eq!(outputs, vec![Sats(30_000), meta.change]);
+        assert!(psbt.inputs().all(|input| input.sequence_number == Some(SEQ_NO_RBF)));
+
+        let high = FeeRate::from_sat_per_vb(1_000);
+        assert!(matches!(wallet.bump_fee(txid, high), Err(BumpError::InsufficientChange { .. })));
+    }
+}
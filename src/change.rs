@@ -0,0 +1,305 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splitting of the transaction change into multiple outputs.
+//!
+//! A single change output is easily told apart from the payments by its amount (for instance, it
+//! is the only output with a non-round amount), so the change may be split into several outputs
+//! of pseudo-random amounts. The amounts are derived from the transaction id, which keeps the
+//! construction reproducible.
+//!
+//! Change outputs paying to the subsequent derivation indexes may be linked by index-pattern
+//! heuristics, so the change index may be also selected from a small window following the next
+//! sequential index, see [`change_window_offset`].
+
+use std::fmt::Display;
+
+use amplify::ByteArray;
+use bpstd::{
+    Descriptor, InternalPk, NormalIndex, Sats, ScriptPubkey, SpkClass, Terminal, Vout, WPubkeyHash,
+};
+use psbt::{Psbt, PsbtConstructor, PsbtMeta};
+use sha2::{Digest, Sha256};
+
+use crate::FeeRate;
+
+const CHANGE_SPLIT_TAG: &[u8] = b"bp-wallet:change-split";
+
+/// Maximal number of outputs the change can be split into.
+pub const MAX_CHANGE_OUTPUTS: u8 = 8;
+
+/// Minimal amount of each of the outputs the change is split into.
+pub const MIN_CHANGE_SPLIT: Sats = Sats(10_000);
+
+const CHANGE_WINDOW_TAG: &[u8] = b"bp-wallet:change-window";
+
+/// Maximal size of the window the change derivation index is selected from, which keeps the gaps
+/// between the used change addresses below the gap limit.
+pub const MAX_CHANGE_WINDOW: u8 = 10;
+
+/// Offset of the change derivation index from the next sequential index `terminal`, selected from
+/// the window of the given size (bounded by [`MAX_CHANGE_WINDOW`]).
+///
+/// The selection is weighted towards the smaller offsets, such that the window doesn't exhaust
+/// the keychain faster than needed. The offset is derived from the descriptor and the sequential
+/// index instead of a random source, which keeps the construction reproducible for the
+/// coordinators sharing the wallet.
+pub fn change_window_offset(descriptor: &impl Display, terminal: Terminal, window: u8) -> u32 {
+    let window = window.min(MAX_CHANGE_WINDOW) as u64;
+    if window < 2 {
+        return 0;
+    }
+    let mut engine = Sha256::new();
+    engine.update(CHANGE_WINDOW_TAG);
+    engine.update(descriptor.to_string().as_bytes());
+    engine.update(terminal.to_string().as_bytes());
+    let hash = engine.finalize();
+    let mut random = [0u8; 8];
+    random.copy_from_slice(&hash[..8]);
+    // Offset `n` has weight `window - n`
+    let mut point = u64::from_le_bytes(random) % (window * (window + 1) / 2);
+    let mut offset = 0;
+    while point >= window - offset {
+        point -= window - offset;
+        offset += 1;
+    }
+    offset as u32
+}
+
+/// Splits the change of a constructed transaction into multiple outputs.
+pub trait ChangeSplitter: PsbtConstructor {
+    /// Splits the change output of the PSBT into up to `count` outputs (bounded by
+    /// [`MAX_CHANGE_OUTPUTS`]) having pseudo-random amounts summing up to the original change.
+    ///
+    /// The number of outputs is reduced such that each of them gets at least
+    /// [`MIN_CHANGE_SPLIT`]; the change below twice of that amount is left as is. The additional
+    /// outputs pay to the next derivation indexes of the change keychain, which are shifted, so
+    /// the outputs are discovered by the wallet on sync.
+    ///
+    /// If the fee rate is given, the fee for the additional outputs is paid from the change, so
+    /// the transaction keeps paying the fee rate it was constructed for. Otherwise, the split
+    /// doesn't change the fee: when the fee is deducted from the outputs, the split must be done
+    /// before the deduction. Returns the numbers and terminals of the added outputs, which are
+    /// appended to the end of the PSBT.
+    fn split_change(
+        &mut self,
+        psbt: &mut Psbt,
+        meta: &PsbtMeta,
+        count: u8,
+        fee_rate: Option<FeeRate>,
+    ) -> Vec<(Vout, Terminal)> {
+        let (Some(vout), Some(terminal)) = (meta.change_vout, meta.change_terminal) else {
+            return vec![];
+        };
+        let Some((change, script_len)) = psbt
+            .outputs()
+            .nth(vout.into_usize())
+            .map(|output| (output.amount, output.script.as_slice().len()))
+        else {
+            return vec![];
+        };
+        // All the change outputs share the script type
+        let output_weight = 4 * (8 + if script_len < 0xFD { 1 } else { 3 } + script_len) as u32;
+        let split_fee = |count: u64| match fee_rate {
+            Some(fee_rate) => fee_rate.fee_for_weight(output_weight * (count as u32 - 1)),
+            None => Sats::ZERO,
+        };
+        let mut count =
+            (count.min(MAX_CHANGE_OUTPUTS) as u64).min(change.sats() / MIN_CHANGE_SPLIT.sats());
+        while count >= 2 && change < Sats(MIN_CHANGE_SPLIT.sats() * count) + split_fee(count) {
+            count -= 1;
+        }
+        if count < 2 {
+            return vec![];
+        }
+
+        let txid = psbt.txid();
+        let weights = (0..count)
+            .map(|no| {
+                let mut engine = Sha256::new();
+                engine.update(CHANGE_SPLIT_TAG);
+                engine.update(txid.to_byte_array());
+                engine.update(no.to_le_bytes());
+                let hash = engine.finalize();
+                let mut random = [0u8; 8];
+                random.copy_from_slice(&hash[..8]);
+                1 + u64::from_le_bytes(random) % 1000
+            })
+            .collect::<Vec<_>>();
+        let total_weight = weights.iter().sum::<u64>();
+        let rest = change.sats() - MIN_CHANGE_SPLIT.sats() * count - split_fee(count).sats();
+        let mut amounts = weights
+            .iter()
+            .map(|weight| MIN_CHANGE_SPLIT + Sats(rest * weight / total_weight))
+            .collect::<Vec<_>>();
+        // The rounding remainder goes to the original change output
+        let remainder = change - split_fee(count) - amounts.iter().copied().sum::<Sats>();
+        amounts[0] += remainder;
+
+        let mut vouts = Vec::with_capacity(amounts.len() - 1);
+        for amount in &amounts[1..] {
+            let index = self.next_derivation_index(terminal.keychain, true);
+            if index == terminal.index {
+                // Wallets with a static change address have nothing to split into
+                break;
+            }
+            let change_terminal = Terminal::new(terminal.keychain, index);
+            let output = psbt.construct_change_expect(self.descriptor(), change_terminal, *amount);
+            vouts.push((Vout::from_u32(output.index() as u32), change_terminal));
+        }
+        let split = vouts.len() + 1;
+        let kept = amounts[..split].iter().copied().sum::<Sats>();
+        psbt.outputs_mut()
+            .find(|output| output.index() == vout.into_usize())
+            .expect("change output is present")
+            .amount = change - split_fee(split as u64) - (kept - amounts[0]);
+        vouts
+    }
+}
+
+impl<T: PsbtConstructor> ChangeSplitter for T {}
+
+/// Derives script pubkey for the terminal of a single-key descriptor directly from the account
+/// xpub, without the descriptor script derivation. Matching the result against the script
+/// derived by the descriptor guards against bugs in the descriptor handling, which may send the
+/// change to a script the wallet can't spend.
+///
+/// Returns `None` for multi-key descriptors and script types other than P2WPKH and P2TR.
+pub fn rederive_script<K, D: Descriptor<K>>(
+    descriptor: &D,
+    terminal: Terminal,
+) -> Option<ScriptPubkey> {
+    let mut xpubs = descriptor.xpubs();
+    let account = xpubs.next()?;
+    if xpubs.next().is_some() {
+        return None;
+    }
+    let xpub = account.xpub().derive_pub([NormalIndex::from(terminal.keychain), terminal.index]);
+    match descriptor.class() {
+        SpkClass::P2wpkh => Some(ScriptPubkey::p2wpkh(WPubkeyHash::from(xpub.to_compr_pk()))),
+        SpkClass::P2tr => Some(ScriptPubkey::p2tr_key_only(InternalPk::from(xpub.to_xonly_pk()))),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{Derive, Keychain, NormalIndex, Outpoint, Txid};
+    use psbt::{Beneficiary, TxParams};
+
+    use super::*;
+    use crate::fixtures::{test_payee, TestCoins};
+    use crate::{FeeDeductor, FeeEstimator, FeeParams};
+
+    #[test]
+    fn test_split_change() {
+        let mut coins = TestCoins::new(Sats(100_000));
+        let outpoint = Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(0));
+        let beneficiaries = [Beneficiary::new(test_payee(), Sats(10_000))];
+        let (psbt, meta) =
+            coins.construct_psbt([outpoint], &beneficiaries, TxParams::with(Sats(1_000))).unwrap();
+
+        let mut split = psbt.clone();
+        let vouts = coins.split_change(&mut split, &meta, 4, None);
+        assert_eq!(vouts.iter().map(|(vout, _)| vout.into_u32()).collect::<Vec<_>>(), vec![
+            2, 3, 4
+        ]);
+        let amounts = split.outputs().skip(1).map(|output| output.amount).collect::<Vec<_>>();
+        assert_eq!(amounts.iter().copied().sum::<Sats>(), Sats(89_000));
+        assert!(amounts.iter().all(|amount| *amount >= MIN_CHANGE_SPLIT));
+        assert_ne!(amounts[1], amounts[2]);
+        let scripts = split.outputs().map(|output| output.script.clone()).collect::<Vec<_>>();
+        assert!((1..5).all(|no| !scripts[no + 1..].contains(&scripts[no])));
+        for (vout, terminal) in vouts {
+            let script = rederive_script(&coins.descriptor, terminal).unwrap();
+            assert_eq!(scripts[vout.into_usize()], script);
+            assert_eq!(
+                script,
+                coins.descriptor.derive(terminal.keychain, terminal.index).to_script_pubkey()
+            );
+        }
+
+        // Reproducible for the same transaction
+        let mut again = psbt.clone();
+        coins.next_index = NormalIndex::normal(1);
+        coins.split_change(&mut again, &meta, 4, None);
+        assert_eq!(again, split);
+
+        // Bounded by the minimal amount of the outputs
+        let mut bounded = psbt.clone();
+        bounded.outputs_mut().nth(1).unwrap().amount = Sats(35_000);
+        assert_eq!(coins.split_change(&mut bounded, &meta, MAX_CHANGE_OUTPUTS, None).len(), 2);
+        let mut small = psbt;
+        small.outputs_mut().nth(1).unwrap().amount = Sats(15_000);
+        assert!(coins.split_change(&mut small, &meta, 4, None).is_empty());
+        assert_eq!(small.outputs().nth(1).unwrap().amount, Sats(15_000));
+    }
+
+    #[test]
+    fn test_split_change_fee() {
+        let mut coins = TestCoins::new(Sats(100_000));
+        let outpoint = Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(0));
+        let beneficiaries = [Beneficiary::new(test_payee(), Sats(10_000))];
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        let (mut psbt, meta, fee) = coins
+            .construct_psbt_at(&[outpoint], &beneficiaries, FeeParams::with(fee_rate))
+            .unwrap();
+
+        let vouts = coins.split_change(&mut psbt, &meta, 4, Some(fee_rate));
+        assert_eq!(vouts.len(), 3);
+        let split_fee = psbt.fee().unwrap();
+        assert!(split_fee > fee);
+        let required = fee_rate.fee_for_weight(coins.estimate_weight(&psbt));
+        assert!(split_fee >= required && split_fee <= required + Sats(3));
+
+        // The split is reduced when the change doesn't cover the fee of the additional outputs
+        let fee_rate = FeeRate::from_sat_per_vb(300);
+        let (mut psbt, meta, _) = coins
+            .construct_psbt_at(&[outpoint], &beneficiaries, FeeParams::with(fee_rate))
+            .unwrap();
+        let change = psbt.outputs().nth(1).unwrap().amount;
+        assert!(change >= Sats(MIN_CHANGE_SPLIT.sats() * 4));
+        let vouts = coins.split_change(&mut psbt, &meta, 4, Some(fee_rate));
+        assert_eq!(vouts.len(), 1);
+        assert!(psbt.outputs().skip(1).all(|output| output.amount >= MIN_CHANGE_SPLIT));
+    }
+
+    #[test]
+    fn test_change_window_offset() {
+        let descriptor = "wpkh([643a7adc/84h/1h/0h]tpub/<0;1>/*)";
+        let terminal = |index| Terminal::new(Keychain::INNER, NormalIndex::normal(index));
+        assert_eq!(change_window_offset(&descriptor, terminal(0), 0), 0);
+        assert_eq!(change_window_offset(&descriptor, terminal(0), 1), 0);
+        let offsets = (0..200)
+            .map(|no| change_window_offset(&descriptor, terminal(no), 4))
+            .collect::<Vec<_>>();
+        assert!(offsets.iter().all(|offset| *offset < 4));
+        assert!(offsets.iter().any(|offset| *offset > 0));
+        // Smaller offsets are more likely
+        let count = |n| offsets.iter().filter(|offset| **offset == n).count();
+        assert!(count(0) > count(3));
+        assert_eq!(
+            change_window_offset(&descriptor, terminal(7), u8::MAX),
+            change_window_offset(&descriptor, terminal(7), MAX_CHANGE_WINDOW)
+        );
+    }
+}
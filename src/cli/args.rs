@@ -20,21 +20,37 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
-use std::process::exit;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use bpstd::XpubDerivable;
+use bpstd::{Txid, XpubDerivable};
 use clap::Subcommand;
+use colored::Colorize;
 use descriptors::Descriptor;
 use strict_encoding::Ident;
 
 use crate::cli::{
-    Config, DescrStdOpts, DescriptorOpts, ExecError, GeneralOpts, ResolverOpt, WalletOpts,
+    own_wallets, Config, DescrStdOpts, DescriptorOpts, DisplayOpts, ExecError, GeneralOpts,
+    ResolverOpt, WalletEvent, WalletOpts,
 };
-use crate::fs::FsTextStore;
-use crate::indexers::esplora;
-use crate::{AnyIndexer, Wallet};
+use crate::fs::{DryRunStoreFactory, FsStoreFactory};
+use crate::indexers::electrum::{connect_tls, ConfigBuilder, Socks5Config};
+use crate::indexers::esplora::ClientKind;
+use crate::indexers::{esplora, TlsOpts};
+use crate::rates::{
+    CachedRates, MempoolRates, RateProvider, RatesError, StaticRates, MEMPOOL_PRICE_API,
+};
+use crate::{
+    AnyIndexer, BroadcastFanout, CacheDelta, CacheSummary, DryRun, IndexerConfig, IndexerKind,
+    Layer2, NoLayer2, OwnWallets, Phase, Timings, TxStatus, TxStore, Wallet, WalletAddr,
+    WalletCache, WalletStore, WalletStoreFactory,
+};
+
+/// Name of the file in the data directory keeping transactions downloaded from the indexers.
+pub const TX_STORE_FILE: &str = "txstore.dat";
 
 /// Command-line arguments
 #[derive(Parser)]
@@ -57,9 +73,31 @@ pub struct Args<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts = DescrStd
     #[clap(long, global = true)]
     pub sync: bool,
 
+    /// Do not save wallet changes to disk; a warning is printed if the command leaves unsaved
+    /// changes.
+    #[clap(long, global = true)]
+    pub no_autosave: bool,
+
+    /// Print durations of the command phases (wallet loading, address derivation, indexer
+    /// requests, cache rebuild and saving) once the command completes.
+    #[clap(long, global = true)]
+    pub timings: bool,
+
+    /// Execute the command without persisting anything or broadcasting transactions, printing
+    /// the changes which would be made instead.
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+
+    /// Collector of the changes made by the command, enabled with `--dry-run`.
+    #[clap(skip)]
+    pub changes: DryRun,
+
     #[command(flatten)]
     pub general: GeneralOpts,
 
+    #[command(flatten)]
+    pub display: DisplayOpts,
+
     /// Command to execute.
     #[clap(subcommand)]
     pub command: C,
@@ -72,7 +110,12 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
             wallet: self.wallet.clone(),
             resolver: self.resolver.clone(),
             sync: self.sync,
+            no_autosave: self.no_autosave,
+            timings: self.timings,
+            dry_run: self.dry_run,
+            changes: self.changes.clone(),
             general: self.general.clone(),
+            display: self.display.clone(),
             command: cmd.clone(),
         }
     }
@@ -86,7 +129,21 @@ pub trait Exec {
 }
 
 impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
-    pub fn process(&mut self) { self.general.process(); }
+    pub fn process(&mut self) {
+        self.general.process();
+        if self.dry_run && !self.changes.is_enabled() {
+            self.changes = DryRun::enabled();
+        }
+    }
+
+    /// Factory of the wallet stores, which respects `--dry-run` and the cache compression
+    /// setting.
+    pub fn store_factory(&self, conf: &Config) -> DryRunStoreFactory {
+        DryRunStoreFactory {
+            changes: self.changes.clone(),
+            compress: conf.cache_compression,
+        }
+    }
 
     pub fn conf_path(&self, name: &'static str) -> PathBuf {
         let mut conf_path = self.general.base_dir();
@@ -95,66 +152,244 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
         conf_path
     }
 
-    pub fn indexer(&self) -> Result<AnyIndexer, ExecError> {
+    /// Constructs indexer client, using TLS options from the configuration for the server URL.
+    /// If no indexer is given in the command line, the indexer configured for the wallet is used.
+    pub fn indexer(
+        &self,
+        conf: &Config,
+        preset: Option<&IndexerConfig>,
+    ) -> Result<AnyIndexer, ExecError> {
+        let server = self.indexer_config(preset)?;
+        self.connect(conf, &server)
+    }
+
+    /// Constructs indexer for publishing transactions, which broadcasts them to the indexer
+    /// returned by [`Self::indexer`] and also to the wallet indexer, if a different one is given
+    /// in the command line, and to the servers from `--broadcast` arguments and the
+    /// configuration. Failures to connect to the additional servers are reported as warnings.
+    pub fn broadcaster(
+        &self,
+        conf: &Config,
+        preset: Option<&IndexerConfig>,
+    ) -> Result<BroadcastFanout<AnyIndexer>, ExecError> {
         let network = self.general.network.to_string();
-        Ok(match (&self.resolver.esplora, &self.resolver.electrum, &self.resolver.mempool) {
-            (None, Some(url), None) => AnyIndexer::Electrum(Box::new(electrum::Client::new(url)?)),
-            (Some(url), None, None) => AnyIndexer::Esplora(Box::new(esplora::Client::new_esplora(
-                &url.replace("{network}", &network),
-            )?)),
-            (None, None, Some(url)) => AnyIndexer::Mempool(Box::new(esplora::Client::new_mempool(
-                &url.replace("{network}", &network),
-            )?)),
-            _ => {
-                eprintln!(
-                    "Error: no blockchain indexer specified; use either --esplora --mempool or \
-                     --electrum argument"
-                );
-                exit(1);
+        let name = |server: &IndexerConfig| server.to_string().replace("{network}", &network);
+        let server = self.indexer_config(preset)?;
+        let mut fanout = BroadcastFanout::new(name(&server), self.connect(conf, &server)?);
+        let extra =
+            self.resolver.broadcast.iter().chain(&conf.broadcast).map(|url| IndexerConfig {
+                kind: IndexerKind::Esplora,
+                url: url.clone(),
+                proxy: None,
+            });
+        let wallet_server = preset.filter(|preset| preset.url != server.url).cloned();
+        for server in wallet_server.into_iter().chain(extra) {
+            match self.connect(conf, &server) {
+                Ok(indexer) => fanout.push(name(&server), indexer),
+                Err(err) => eprintln!("Warning: unable to connect to {}: {err}", name(&server)),
+            }
+        }
+        Ok(fanout)
+    }
+
+    /// Indexer server given in the command line or, if none is given, configured for the wallet.
+    fn indexer_config(&self, preset: Option<&IndexerConfig>) -> Result<IndexerConfig, ExecError> {
+        let resolver = &self.resolver;
+        let (kind, url) = match (&resolver.esplora, &resolver.electrum, &resolver.mempool) {
+            (None, Some(url), None) => (IndexerKind::Electrum, url),
+            (Some(url), None, None) => (IndexerKind::Esplora, url),
+            (None, None, Some(url)) => (IndexerKind::Mempool, url),
+            (None, None, None) => return preset.cloned().ok_or(ExecError::NoIndexer),
+            _ => return Err(ExecError::NoIndexer),
+        };
+        Ok(IndexerConfig {
+            kind,
+            url: url.clone(),
+            proxy: None,
+        })
+    }
+
+    /// Connects to the indexer server, using TLS options from the configuration for the server
+    /// URL. The proxy given in the command line overrides the one configured for the server.
+    fn connect(&self, conf: &Config, server: &IndexerConfig) -> Result<AnyIndexer, ExecError> {
+        let network = self.general.network.to_string();
+        let IndexerConfig { kind, url, proxy } = server;
+        let kind = *kind;
+        let proxy = self.resolver.proxy.as_deref().or(proxy.as_deref());
+        let tls = conf.tls.get(url).filter(|tls| !tls.is_empty());
+        if proxy.is_some() && tls.is_some() {
+            return Err(ExecError::ProxyTls(url.clone()));
+        }
+        let tls = tls.map(TlsOpts::client_config).transpose()?;
+        Ok(match kind {
+            IndexerKind::Electrum => match (tls, proxy) {
+                (Some(tls), _) => AnyIndexer::ElectrumTls(Box::new(connect_tls(url, tls)?)),
+                (None, Some(proxy)) => {
+                    let addr =
+                        proxy.trim_start_matches("socks5h://").trim_start_matches("socks5://");
+                    let socks5 = Some(Socks5Config::new(addr));
+                    let config = ConfigBuilder::new().socks5(socks5).build();
+                    AnyIndexer::Electrum(Box::new(electrum::Client::from_config(url, config)?))
+                }
+                (None, None) => AnyIndexer::Electrum(Box::new(electrum::Client::new(url)?)),
+            },
+            IndexerKind::Esplora | IndexerKind::Mempool => {
+                let endpoint = url.replace("{network}", &network);
+                let client_kind = match kind {
+                    IndexerKind::Mempool => ClientKind::Mempool,
+                    _ => ClientKind::Esplora,
+                };
+                let client = match (tls, proxy) {
+                    (Some(tls), _) => esplora::Client::with_tls(&endpoint, client_kind, tls),
+                    (None, Some(proxy)) => {
+                        esplora::Client::with_proxy(&endpoint, client_kind, proxy)?
+                    }
+                    (None, None) if kind == IndexerKind::Mempool => {
+                        esplora::Client::new_mempool(&endpoint)?
+                    }
+                    (None, None) => esplora::Client::new_esplora(&endpoint)?,
+                };
+                match kind {
+                    IndexerKind::Mempool => AnyIndexer::Mempool(Box::new(client)),
+                    _ => AnyIndexer::Esplora(Box::new(client)),
+                }
             }
         })
     }
 
+    /// Returns directory of the persisted wallet, or `None` if the wallet is given by a
+    /// descriptor in the command line.
+    pub fn wallet_dir(&self, conf: &Config) -> Option<PathBuf> {
+        if self.wallet.descriptor_opts.is_some() {
+            return None;
+        }
+        Some(self.wallet.wallet_path.clone().unwrap_or_else(|| {
+            let name = self
+                .wallet
+                .name
+                .as_ref()
+                .map(Ident::to_string)
+                .unwrap_or(conf.default_wallet.clone());
+            self.general.wallet_dir(name)
+        }))
+    }
+
+    /// Constructs exchange rate provider according to the configuration. Historical rates are
+    /// cached in the wallet directory.
+    pub fn rate_provider(
+        &self,
+        conf: &Config,
+    ) -> Result<CachedRates<Box<dyn RateProvider>>, ExecError> {
+        let provider: Box<dyn RateProvider> = match conf.rates_file {
+            Some(ref path) => Box::new(StaticRates::load(path)?),
+            None => Box::new(MempoolRates::new(MEMPOOL_PRICE_API).map_err(RatesError::from)?),
+        };
+        let cache_path = self.wallet_dir(conf).map(|mut path| {
+            path.push("rates.yaml");
+            path
+        });
+        Ok(CachedRates::with(provider, cache_path))
+    }
+
+    /// Loads other wallets from the data directory for detecting transfers between them and the
+    /// wallet with the given descriptor.
     #[allow(clippy::multiple_bound_locations)]
-    pub fn bp_wallet<D: Descriptor>(
+    pub fn own_wallets<D: Descriptor>(&self, except: &D) -> Result<OwnWallets, ExecError>
+    where for<'de> D: serde::Serialize + serde::Deserialize<'de> {
+        own_wallets(&self.general, &FsStoreFactory, except)
+    }
+
+    #[allow(clippy::multiple_bound_locations)]
+    pub fn bp_wallet<D: Descriptor>(&self, conf: &Config) -> Result<CliWallet<D>, ExecError>
+    where for<'de> D: From<O::Descr> + serde::Serialize + serde::Deserialize<'de> {
+        self.bp_wallet_with(conf, &self.store_factory(conf))
+    }
+
+    /// Loads only the summary of the wallet cache, which is enough to report the wallet balance,
+    /// without loading the wallet transactions. Returns `None` if the wallet has to be synced or
+    /// is given by a descriptor, in which case the whole wallet must be loaded.
+    pub fn bp_wallet_summary(&self, conf: &Config) -> Result<Option<CacheSummary>, ExecError> {
+        if self.sync || self.wallet.descriptor_opts.is_some() {
+            return Ok(None);
+        }
+        eprint!("Loading balances");
+        let (_, path) = self.wallet_location(conf);
+        let store = self.store_factory(conf).open(path)?;
+        let summary = WalletCache::<<NoLayer2 as Layer2>::Cache>::load_summary(&store)?;
+        eprintln!("success");
+        Ok(Some(summary))
+    }
+
+    /// Name and directory of the persisted wallet used by the command, printing where the wallet
+    /// is loaded from.
+    fn wallet_location(&self, conf: &Config) -> (String, PathBuf) {
+        if let Some(wallet_path) = self.wallet.wallet_path.clone() {
+            eprint!(" from specified wallet directory ... ");
+            (wallet_path.display().to_string(), wallet_path)
+        } else {
+            let name = self
+                .wallet
+                .name
+                .as_ref()
+                .map(Ident::to_string)
+                .unwrap_or(conf.default_wallet.clone());
+            eprint!(" from wallet {name} ... ");
+            let path = self.general.wallet_dir(&name);
+            (name, path)
+        }
+    }
+
+    /// Loads the wallet like [`Self::bp_wallet`], opening persisted wallets with the stores
+    /// produced by the provided factory.
+    #[allow(clippy::multiple_bound_locations)]
+    pub fn bp_wallet_with<D: Descriptor, F: WalletStoreFactory>(
         &self,
         conf: &Config,
-    ) -> Result<Wallet<XpubDerivable, D>, ExecError>
+        factory: &F,
+    ) -> Result<CliWallet<D>, ExecError>
     where
         for<'de> D: From<O::Descr> + serde::Serialize + serde::Deserialize<'de>,
+        F::Store: WalletStore<XpubDerivable, D, NoLayer2>,
     {
+        let mut timings = Timings::new();
+        let load = Instant::now();
         eprint!("Loading descriptor");
         let sync = self.sync || self.wallet.descriptor_opts.is_some();
 
+        let mut wallet_name = None;
         let mut wallet: Wallet<XpubDerivable, D> =
             if let Some(d) = self.wallet.descriptor_opts.descriptor() {
                 eprintln!(" from command-line argument");
                 eprint!("Syncing");
                 Wallet::new_layer1(d.into(), self.general.network)
             } else {
-                let path = if let Some(wallet_path) = self.wallet.wallet_path.clone() {
-                    eprint!(" from specified wallet directory ... ");
-                    wallet_path
-                } else {
-                    let wallet_name = self
-                        .wallet
-                        .name
-                        .as_ref()
-                        .map(Ident::to_string)
-                        .unwrap_or(conf.default_wallet.clone());
-                    eprint!(" from wallet {wallet_name} ... ");
-                    self.general.wallet_dir(wallet_name)
-                };
-                let provider = FsTextStore::new(path)?;
-                let wallet = Wallet::load(provider, true)?;
+                let (name, path) = self.wallet_location(conf);
+                wallet_name = Some(name);
+                let provider = factory.open(path)?;
+                let wallet = Wallet::load(provider, false)?;
                 eprintln!("success");
                 wallet
             };
+        timings.record(Phase::Load, load.elapsed());
 
+        let mut presync = None;
         if sync {
-            let indexer = self.indexer()?;
+            let indexer = self.indexer(conf, wallet.indexer())?;
+            let known = wallet
+                .transactions()
+                .iter()
+                .map(|(txid, tx)| (*txid, tx.status))
+                .collect::<BTreeMap<_, _>>();
+            let known_addr = wallet.address_balance().collect::<BTreeSet<_>>();
+            let store_path = self.general.base_dir().join(TX_STORE_FILE);
+            let capacity = conf.tx_store_size.unwrap_or(TxStore::DEFAULT_CAPACITY);
+            let mut store = TxStore::load(&store_path, capacity).unwrap_or_else(|err| {
+                eprintln!("Warning: unable to read transaction store, ignoring it: {err}");
+                TxStore::new(capacity)
+            });
             eprint!("Syncing");
-            if let Some(errors) = wallet.update(&indexer).into_err() {
+            let (report, errors) = wallet.update_with_store(&indexer, &mut store).split();
+            if let Some(errors) = errors {
                 eprintln!(" partial, some requests has failed:");
                 for err in errors {
                     eprintln!("- {err}");
@@ -162,8 +397,118 @@ impl<C: Clone + Eq + Debug + Subcommand, O: DescriptorOpts> Args<C, O> {
             } else {
                 eprintln!(" success");
             }
+            for (keychain, index) in &report.resumed {
+                eprintln!("Resumed interrupted sync of keychain {keychain} from index {index}");
+            }
+            timings.extend(&report.timings);
+            let store_desc = || format!("save transaction store {}", store_path.display());
+            if store.is_dirty() && self.changes.perform(store_desc()) {
+                if let Err(err) = timings.measure(Phase::Store, || store.save(&store_path)) {
+                    eprintln!("Warning: unable to save transaction store: {err}");
+                }
+            }
+            // Invoices and hooks are processed only for persisted wallets, since for ad-hoc
+            // descriptors all the history is new on each run
+            let invoices = match wallet_name {
+                Some(_) => {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|d| d.as_secs())
+                        .unwrap_or_default();
+                    wallet.update_invoices(now)
+                }
+                None => vec![],
+            };
+            for (address, invoice) in &invoices {
+                eprintln!(
+                    "Invoice for {} to {address} is {} ({} received)",
+                    self.display.amount_with_unit(invoice.amount),
+                    invoice.status,
+                    self.display.amount_with_unit(invoice.received)
+                );
+            }
+            if let Some(name) = wallet_name.filter(|_| !conf.hooks.is_empty()) {
+                let mut events = WalletEvent::detect(&known, wallet.transactions());
+                events.extend(
+                    invoices.iter().filter_map(|(address, invoice)| {
+                        WalletEvent::with_invoice(*address, invoice)
+                    }),
+                );
+                if self.changes.is_enabled() {
+                    for event in &events {
+                        if let Some(cmd) = conf.hooks.command_for(event) {
+                            self.changes.perform(format!("run hook `{cmd}` for {event} event"));
+                        }
+                    }
+                } else {
+                    conf.hooks.run(&name, &events);
+                }
+            }
+            presync = Some((known, known_addr));
         }
 
-        Ok(wallet)
+        Ok(CliWallet {
+            wallet,
+            autosave: !self.no_autosave,
+            timings: self.timings.then_some(timings),
+            presync,
+        })
+    }
+}
+
+/// Wallet used by a command, which saves its changes once the command completes.
+///
+/// Wallet components are loaded without autosave, so the changes are written to disk only once.
+/// Errors happening during the save are reported to the user. If autosave is disabled with
+/// `--no-autosave`, the user is warned about the changes which are left unsaved. With
+/// `--timings`, the durations of the command phases are printed after the wallet is saved.
+#[derive(Debug)]
+pub struct CliWallet<D: Descriptor> {
+    wallet: Wallet<XpubDerivable, D>,
+    autosave: bool,
+    timings: Option<Timings>,
+    /// Transaction statuses and address statistics before the sync, if the wallet was synced.
+    presync: Option<(BTreeMap<Txid, TxStatus>, BTreeSet<WalletAddr>)>,
+}
+
+impl<D: Descriptor> CliWallet<D> {
+    /// Changes of the wallet cache made by the sync performed on loading the wallet, or `None`
+    /// if the wallet was not synced.
+    pub fn sync_delta(&self) -> Option<CacheDelta> {
+        let (known_tx, known_addr) = self.presync.as_ref()?;
+        Some(self.wallet.cache_delta(known_tx, known_addr))
+    }
+}
+
+impl<D: Descriptor> Deref for CliWallet<D> {
+    type Target = Wallet<XpubDerivable, D>;
+
+    fn deref(&self) -> &Self::Target { &self.wallet }
+}
+
+impl<D: Descriptor> DerefMut for CliWallet<D> {
+    fn deref_mut(&mut self) -> &mut Self::Target { &mut self.wallet }
+}
+
+impl<D: Descriptor> Drop for CliWallet<D> {
+    fn drop(&mut self) {
+        if self.wallet.is_dirty() && !self.autosave {
+            eprintln!(
+                "{}",
+                "Wallet has unsaved changes, which are discarded due to --no-autosave"
+                    .bright_yellow()
+            );
+        } else if self.wallet.is_dirty() {
+            let store = Instant::now();
+            if let Err(err) = self.wallet.store() {
+                eprintln!("{} {err}", "Error: unable to save wallet changes:".bright_red());
+            }
+            if let Some(timings) = &mut self.timings {
+                timings.record(Phase::Store, store.elapsed());
+            }
+        }
+        if let Some(timings) = &self.timings {
+            eprintln!("\nTimings:\n{timings}");
+        }
     }
 }
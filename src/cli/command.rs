@@ -20,23 +20,64 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::fs::File;
+use std::io::Write;
+use std::ops::Range;
 use std::path::{Path, PathBuf};
-use std::process::exit;
-use std::{fs, io};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fs, io, slice, thread};
 
+use amplify::hex::ToHex;
 use amplify::IoError;
-use bpstd::psbt::{Beneficiary, TxParams};
-use bpstd::{ConsensusEncode, Derive, IdxBase, Keychain, NormalIndex, Sats, Tx, XpubDerivable};
+use bpstd::{
+    Address, AddressNetwork, ConsensusDecode, ConsensusEncode, Derive, Descriptor, Idx, IdxBase,
+    Keychain, NormalIndex, Outpoint, Sats, StdDescr, Terminal, Tx, Txid, XpubDerivable,
+};
+use clap_complete::{ArgValueCandidates, Shell};
 use colored::Colorize;
-use descriptors::Descriptor;
 use nonasync::persistence::PersistenceError;
-use psbt::{ConstructionError, Payment, Psbt, PsbtConstructor, PsbtVer, UnfinalizedInputs};
+use psbt::{ConstructionError, Psbt, PsbtConstructor, PsbtVer, UnfinalizedInputs};
 use strict_encoding::Ident;
 
-use crate::cli::{Args, Config, DescriptorOpts, Exec};
-use crate::fs::FsTextStore;
-use crate::{coinselect, AnyIndexerError, Indexer, OpType, Wallet, WalletAddr, WalletUtxo};
+use crate::cli::import::parse_descriptor;
+#[cfg(feature = "remote")]
+use crate::cli::RemoteOpts;
+use crate::cli::{
+    balance_at, bump_fee, clone_wallet, collab_party, collab_proofs, collab_session, construct,
+    construct_cpfp, create_wallet, derive_addresses, fee_overview, fee_report, files_report,
+    finalize_psbt, gains_report, health_report, import_tx, list_wallets, publish_tx, sweep,
+    verify_utxos, wallet_names, workspace_status, write_completions, write_manpages, Args, Config,
+    ConstructParams, DescriptorOpts, Exec, Finalization, ImportError, ImportSource, ImportedWallet,
+    SignerBackend, SignerError, SweepParams, WalletBackup, WalletLabels, DEFAULT_ELECTRUM,
+    DEFAULT_ESPLORA, DEFAULT_MEMPOOL, TX_STORE_FILE,
+};
+use crate::fs::{FsStoreFactory, FsTextStore};
+#[cfg(feature = "signers")]
+use crate::hot::TestVectors;
+use crate::indexers::{TlsError, TxStore, GAP_LIMIT};
+use crate::rates::{Currency, RateProvider, RatesError};
+#[cfg(feature = "remote")]
+use crate::remote::{AnyBackend, RemoteError, RemoteStore, SyncOutcome};
+use crate::standardness::StandardnessError;
+use crate::{
+    parse_address, parse_fee, parse_recipient, parse_sats, signals_rbf, AirgapDir, AirgapError,
+    AmendError, Amount, AnyIndexer, AnyIndexerError, BlockHeight, BroadcastFanout, BroadcastResult,
+    BumpError, CollabError, CollabSession, ConfirmationError, Confirmations, Contact, CpfpError,
+    Date, DeductError, DerivationStandard, Disposal, DryRun, FeeParseError, FeeRate, FeeSpec,
+    HealthReport, Indexer, IndexerConfig, IndexerKind, Layer2, Layer2Cache, LotMethod, MerkleBlock,
+    MerkleProofError, NoLayer2, OpType, OwnWallets, OwnershipProof, PackageError,
+    PaymentParseError, Period, PsbtAmender, PsbtMemo, Recipient, ResolutionSource, ResolveError,
+    Severity, ShuffleSeed, SplitError, SweepError, TemplateError, TxStatus, Wallet, WalletAddr,
+    WalletStoreFactory, WalletTemplate, Workspace, WorkspaceError, AIRGAP_SIGNED,
+    MAX_CHANGE_OUTPUTS, MAX_CHANGE_WINDOW, WORKSPACE_SIGNED,
+};
+
+/// Layer 2 data of the history rows of the wallets operated by the command-line tool.
+type CliLayer2Tx = <<NoLayer2 as Layer2>::Cache as Layer2Cache>::Tx;
+/// Layer 2 data of the coins of the wallets operated by the command-line tool.
+type CliLayer2Coin = <<NoLayer2 as Layer2>::Cache as Layer2Cache>::Coin;
 
 #[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
 pub enum Command {
@@ -48,6 +89,7 @@ pub enum Command {
     #[display("default")]
     Default {
         /// Name of the wallet to make it default
+        #[clap(add = ArgValueCandidates::new(wallet_names))]
         default: Option<Ident>,
     },
 
@@ -56,11 +98,92 @@ pub enum Command {
     Create {
         /// The name for the new wallet
         name: Ident,
+
+        /// Use a single fixed terminal (like `&0/0`) as the only receiving address of the
+        /// wallet, for instance for a donation page
+        #[clap(long, value_name = "TERMINAL")]
+        static_address: Option<Terminal>,
+
+        /// Create the wallet from a named template instead of a descriptor, using the keys given
+        /// with `--key`. Run `templates` command to see the available templates
+        #[clap(long, value_name = "NAME", requires = "key")]
+        template: Option<WalletTemplate>,
+
+        /// Key for the wallet template, which must be derived according to the template
+        /// derivation scheme. Repeat for the templates requiring multiple keys
+        #[clap(long, value_name = "KEY", requires = "template")]
+        key: Vec<XpubDerivable>,
+    },
+
+    /// List wallet templates which can be used with `create --template`
+    #[display("templates")]
+    Templates,
+
+    /// Create a copy of a named wallet under a new name
+    #[display("clone")]
+    Clone {
+        /// Name of the wallet to copy
+        #[clap(add = ArgValueCandidates::new(wallet_names))]
+        name: Ident,
+
+        /// The name for the new wallet
+        new_name: Ident,
+
+        /// Do not copy the data retrieved from the indexer, such that the new wallet gets
+        /// re-synchronized from scratch
+        #[clap(long)]
+        without_cache: bool,
+    },
+
+    /// Create a named wallet from the wallet exported by other wallet software, importing its
+    /// labels when available
+    #[display("import")]
+    Import {
+        /// Wallet software which has produced the export file
+        #[clap(long, value_name = "SOFTWARE")]
+        from: ImportSource,
+
+        /// Wallet export file
+        file: PathBuf,
+
+        /// The name for the new wallet
+        name: Ident,
+
+        /// Additionally import labels from a file in BIP-329 format
+        #[clap(long, value_name = "FILE")]
+        labels: Option<PathBuf>,
+    },
+
+    /// Print the information required to restore a named wallet: its descriptor, network and
+    /// the height of the first transaction
+    #[display("export-descriptor")]
+    ExportDescriptor {
+        /// Name of the wallet to export
+        #[clap(add = ArgValueCandidates::new(wallet_names))]
+        name: Ident,
+    },
+
+    /// Synchronize wallet with the blockchain indexer, running configured hooks for the detected
+    /// wallet events
+    #[display("sync")]
+    Sync {
+        /// Print new and changed transactions and address statistics as JSON to the standard
+        /// output. Use together with `--no-autosave` to leave the wallet files unchanged
+        #[clap(long)]
+        emit_delta: bool,
     },
 
+    /// Save all wallet data to disk, rewriting the wallet files even if there are no changes
+    #[display("save")]
+    Save,
+
     /// Generate a new wallet address(es)
     #[display("address")]
+    #[clap(args_conflicts_with_subcommands = true)]
     Address {
+        #[clap(subcommand)]
+        command: Option<AddressCommand>,
+
         /// Use change keychain
         #[clap(short = '1', long)]
         change: bool,
@@ -82,6 +205,54 @@ pub enum Command {
         count: u8,
     },
 
+    /// Export data derived from the wallet descriptor
+    #[display("descriptor {command}")]
+    Descriptor {
+        #[clap(subcommand)]
+        command: DescriptorCommand,
+    },
+
+    /// Inspect wallet keychains
+    #[display("keychain {command}")]
+    Keychain {
+        #[clap(subcommand)]
+        command: KeychainCommand,
+    },
+
+    /// Show or change wallet settings
+    #[display("settings")]
+    Settings {
+        /// Minimal number of confirmations a coin must have to be spent by `construct`
+        #[clap(long, value_name = "N")]
+        min_conf: Option<Confirmations>,
+
+        /// Kind of the indexer used for the wallet when no indexer is given in the command line
+        #[clap(long, value_name = "KIND")]
+        indexer: Option<IndexerKind>,
+
+        /// URL of the wallet indexer server; defaults to the default server for the indexer kind
+        #[clap(long, value_name = "URL", requires = "indexer")]
+        indexer_url: Option<String>,
+
+        /// Proxy server to connect to the wallet indexer through, like `socks5://127.0.0.1:9050`
+        #[clap(long, value_name = "URL", requires = "indexer")]
+        indexer_proxy: Option<String>,
+
+        /// Remove the indexer configured for the wallet
+        #[clap(long, conflicts_with = "indexer")]
+        no_indexer: bool,
+
+        /// Select change addresses from a window of up to N derivation indexes following the next
+        /// one, such that the change outputs are not linkable by the index pattern. Use 0 to
+        /// select the change addresses sequentially
+        #[clap(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u8).range(0..=MAX_CHANGE_WINDOW as i64)
+        )]
+        change_window: Option<u8>,
+    },
+
     /// Finalize a PSBT, optionally extracting and publishing the signed transaction
     #[display("finalize")]
     Finalize {
@@ -112,6 +283,118 @@ pub enum Command {
     },
 }
 
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum DescriptorCommand {
+    /// Dump derived script pubkeys and addresses, for instance to feed them to external scanners
+    #[display("scripts")]
+    Scripts {
+        /// Keychain to derive scripts from; defaults to the first descriptor keychain
+        #[clap(short, long)]
+        keychain: Option<Keychain>,
+
+        /// Range of derivation indexes, in form of `<start>..<end>` with the end excluded
+        #[clap(short, long, value_parser = parse_index_range, default_value = "0..1000")]
+        range: Range<u32>,
+
+        /// Output format
+        #[clap(short, long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+    },
+}
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum ExportFormat {
+    /// Comma-separated values with a header row
+    Csv,
+    /// JSON array of objects
+    Json,
+}
+
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum OutputFormat {
+    /// Unsigned transaction in raw hex encoding, as accepted by `fundrawtransaction`
+    #[value(name = "rawtx")]
+    RawTx,
+    /// PSBT version 0 (BIP-174)
+    Psbt0,
+    /// PSBT version 2 (BIP-370)
+    Psbt2,
+}
+
+/// Parses range of normal derivation indexes in form of `<start>..<end>`, with the end
+/// excluded.
+fn parse_index_range(s: &str) -> Result<Range<u32>, String> {
+    let (start, end) =
+        s.split_once("..").ok_or_else(|| format!("invalid range '{s}'; use `<start>..<end>`"))?;
+    let parse = |idx: &str| {
+        u32::from_str(idx.trim()).map_err(|err| format!("invalid range boundary '{idx}': {err}"))
+    };
+    let range = parse(start)?..parse(end)?;
+    if range.is_empty() {
+        return Err(format!("range '{s}' is empty"));
+    }
+    if range.end > NormalIndex::MAX.index() + 1 {
+        return Err(format!("range '{s}' exceeds the maximal normal derivation index"));
+    }
+    Ok(range)
+}
+
+/// Parses coin of a collaborative transaction in form of `<outpoint>[=<proof>]`.
+fn parse_collab_coin(s: &str) -> Result<(Outpoint, Option<OwnershipProof>), String> {
+    let (outpoint, proof) = match s.split_once('=') {
+        Some((outpoint, proof)) => (outpoint, Some(proof)),
+        None => (s, None),
+    };
+    let outpoint = Outpoint::from_str(outpoint).map_err(|err| err.to_string())?;
+    let proof = proof.map(OwnershipProof::from_str).transpose().map_err(|err| err.to_string())?;
+    Ok((outpoint, proof))
+}
+
+/// Parses transaction output in form of `<address>:<amount>`.
+fn parse_txout(s: &str) -> Result<(Address, Sats), String> {
+    let (address, amount) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("invalid output '{s}'; use `<address>:<amount>`"))?;
+    let address = parse_address(address).map_err(|err| err.to_string())?;
+    let amount = parse_sats(amount).map_err(|err| err.to_string())?;
+    Ok((address, amount))
+}
+
+/// Parses raw transaction given either as a hex string or as a name of a file containing the
+/// transaction in hex or binary consensus encoding.
+fn parse_raw_tx(s: &str) -> Result<Tx, String> {
+    let path = Path::new(s);
+    if !path.is_file() {
+        return Tx::from_str(s.trim()).map_err(|err| format!("invalid transaction: {err}"));
+    }
+    let data = fs::read(path).map_err(|err| format!("unable to read '{s}': {err}"))?;
+    match std::str::from_utf8(&data).ok().map(|hex| Tx::from_str(hex.trim())) {
+        Some(Ok(tx)) => Ok(tx),
+        _ => Tx::consensus_deserialize(data).map_err(|err| format!("invalid transaction: {err}")),
+    }
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum AddressCommand {
+    /// Print every credit and debit of a wallet address, with the transaction heights and
+    /// counterparties, for support and forensic investigations
+    #[display("history")]
+    History {
+        /// Address of the wallet, which may be copied in upper case or as a `bitcoin:` URI
+        #[clap(value_parser = parse_address)]
+        address: Address,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum KeychainCommand {
+    /// Show per-keychain derivation index usage and the remaining index space
+    #[display("status")]
+    Status,
+}
+
 #[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
 pub enum BpCommand {
     #[clap(flatten)]
@@ -128,6 +411,24 @@ pub enum BpCommand {
         /// Print information about individual UTXOs
         #[clap(short, long)]
         utxo: bool,
+
+        /// Display the total balance also in the given fiat currency (like `USD`)
+        #[clap(long, value_name = "CURRENCY")]
+        display_fiat: Option<Currency>,
+
+        /// Reconstruct the balance and coins as of the end of the block at the given height
+        /// from the wallet transaction history, for accounting cut-offs and audits
+        #[clap(long, value_name = "HEIGHT", conflicts_with_all = ["addr", "display_fiat"])]
+        at_height: Option<BlockHeight>,
+
+        /// Reconstruct the balance and coins as of the end of the given day (`YYYY-MM-DD`, UTC)
+        /// from the wallet transaction history, using the times of the blocks
+        #[clap(
+            long,
+            value_name = "DATE",
+            conflicts_with_all = ["addr", "display_fiat", "at_height"]
+        )]
+        at_date: Option<Date>,
     },
 
     /// Display history of wallet operations
@@ -140,6 +441,16 @@ pub enum BpCommand {
         /// Print operation details
         #[clap(long)]
         details: bool,
+
+        /// Display amounts also in the given fiat currency (like `USD`), using exchange rates at
+        /// the time of the transaction mining
+        #[clap(long, value_name = "CURRENCY")]
+        display_fiat: Option<Currency>,
+
+        /// Detect transfers to and from other wallets in the data directory, naming these
+        /// wallets instead of the counterparty addresses
+        #[clap(long)]
+        transfers: bool,
     },
 
     /// Inspect transaction
@@ -151,6 +462,146 @@ pub enum BpCommand {
         psbt: PathBuf,
     },
 
+    /// Sign PSBT file with an external signer and save the signed PSBT back to the file
+    #[display("sign")]
+    Sign {
+        /// Signer to use: `hwi[:<fingerprint>]` for a hardware wallet accessed via HWI,
+        /// `hot:<file>` for a signing account created with `bp-hot derive`, or `command:<exe>`
+        /// for an executable reading the PSBT from STDIN and writing the signed PSBT to STDOUT.
+        ///
+        /// If not given, the signer from the configuration file is used.
+        #[clap(long)]
+        signer: Option<SignerBackend>,
+
+        /// Name of a PSBT file to sign
+        psbt: PathBuf,
+    },
+
+    /// Manage PSBT drafts created by `construct`
+    #[display("psbt {command}")]
+    Psbt {
+        #[clap(subcommand)]
+        command: PsbtCommand,
+    },
+
+    /// Audit wallet coins against the wallet descriptor
+    #[display("audit {command}")]
+    Audit {
+        #[clap(subcommand)]
+        command: AuditCommand,
+    },
+
+    /// Check the wallet health: consistency of the cache, wallet files, descriptor and network,
+    /// address index gaps, sync staleness and address reuse. Problems are printed starting from
+    /// the most severe ones; exits with code 2 on warnings and 3 on critical problems
+    #[display("doctor")]
+    Doctor,
+
+    /// Tools for developers of this and other wallet software
+    #[cfg(feature = "signers")]
+    #[display("dev {command}")]
+    Dev {
+        #[clap(subcommand)]
+        command: DevCommand,
+    },
+
+    /// Synchronize the wallet files with other machines through a remote storage, encrypting
+    /// them client-side
+    #[cfg(feature = "remote")]
+    #[display("sync-store {command}")]
+    SyncStore {
+        #[clap(subcommand)]
+        command: SyncStoreCommand,
+    },
+
+    /// Build a transaction together with other parties (coinjoin-style), where each party pays
+    /// for its own inputs and outputs
+    #[display("collab {command}")]
+    Collab {
+        #[clap(subcommand)]
+        command: CollabCommand,
+    },
+
+    /// Collect signatures from multiple cosigners in a workspace directory
+    #[display("workspace {command}")]
+    Workspace {
+        #[clap(subcommand)]
+        command: WorkspaceCommand,
+    },
+
+    /// Exchange PSBTs and signed transactions with an air-gapped signing device through a
+    /// directory on a removable media
+    #[display("airgap {command}")]
+    Airgap {
+        #[clap(subcommand)]
+        command: AirgapCommand,
+    },
+
+    /// Manage named counterparty addresses, displayed in place of the addresses
+    #[display("contact {command}")]
+    Contact {
+        #[clap(subcommand)]
+        command: ContactCommand,
+    },
+
+    /// Track expected incoming payments. Payments are matched to the invoices by their
+    /// addresses on each sync
+    #[display("invoice {command}")]
+    Invoice {
+        #[clap(subcommand)]
+        command: InvoiceCommand,
+    },
+
+    /// Manually manage the wallet cache, without using a blockchain indexer
+    #[display("cache {command}")]
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Inspect wallet-created transactions which are not mined yet
+    #[display("pending {command}")]
+    Pending {
+        #[clap(subcommand)]
+        command: PendingCommand,
+    },
+
+    /// Produce reports aggregating the wallet history
+    #[display("report {command}")]
+    Report {
+        #[clap(subcommand)]
+        command: ReportCommand,
+    },
+
+    /// Open interactive terminal dashboard of the wallet
+    #[cfg(feature = "tui")]
+    #[display("ui")]
+    Ui,
+
+    /// Print shell script registering completion of `bp` arguments, including names of the
+    /// wallets. For instance, add `source <(bp completions bash)` to `~/.bashrc`
+    #[display("completions")]
+    Completions {
+        /// Shell to generate the script for
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+
+    /// Generate manual pages for `bp` and all its commands
+    #[display("manpages")]
+    Manpages {
+        /// Directory to write the manual pages to
+        dir: PathBuf,
+    },
+
+    /// Show recommended fee rates and mempool congestion reported by the indexer
+    #[display("fees")]
+    Fees {
+        /// Print the mempool fee histogram
+        #[clap(long)]
+        histogram: bool,
+    },
+
     /// Compose a new PSBT for bitcoin payment
     #[display("construct")]
     Construct {
@@ -158,222 +609,1270 @@ pub enum BpCommand {
         #[clap(short = '2')]
         v2: bool,
 
-        /// Bitcoin invoice in form of `<sats>@<address>`. To spend full wallet balance use
+        /// Format of the constructed transaction: PSBT v0 (default), PSBT v2 or raw unsigned
+        /// transaction in hex encoding
+        #[clap(long, value_enum, conflicts_with = "v2")]
+        output_format: Option<OutputFormat>,
+
+        /// Bitcoin invoice in form of `<amount>@<address>`. To spend full wallet balance use
         /// `MAX` for the amount.
         ///
+        /// The amount is given in satoshis, in thousands of satoshis with `k` suffix (`150k`), or
+        /// in BTC when it has a decimal point or `btc` suffix (for instance `0.5btc@<address>`).
+        /// Amounts below the dust threshold for the address type are refused.
+        ///
+        /// The address may be copied in upper case or as a `bitcoin:` URI; it must belong to the
+        /// wallet network.
+        ///
+        /// Instead of the address a BIP-353 human-readable name can be used in form of
+        /// `user@domain.tld:<amount>`; the amount may be omitted if the payment instructions
+        /// published by the name owner specify it. A wallet contact can be paid with
+        /// `<contact>:<amount>`; contacts given by extended keys are paid to a fresh address.
+        ///
         /// If multiple `MAX` addresses provided the wallet balance is split between them in equal
-        /// proportions.
+        /// proportions. Unequal proportions can be given with weights (`MAX*2@<address>`) or as a
+        /// percentage of the balance (`50%@<address>`); the balance here is the value of the
+        /// spent coins remaining after fixed payments and the fee.
+        #[clap(long, value_parser = parse_recipient)]
+        to: Vec<Recipient>,
+
+        /// When a human-readable name has no DNS payment instructions, fall back to the HTTPS
+        /// well-known endpoint of the name domain, which is not DNSSEC-validated
         #[clap(long)]
-        to: Vec<Beneficiary>,
+        well_known: bool,
 
-        /// Fee
-        fee: Sats,
+        /// Memo for the transaction, saved to the wallet data
+        #[clap(long)]
+        memo: Option<String>,
 
-        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
-        psbt: Option<PathBuf>,
-    },
-}
+        /// Embed the memo into the PSBT, so cosigners can see it when signing
+        #[clap(long, requires = "memo")]
+        embed_memo: bool,
 
-#[derive(Debug, Display, Error, From)]
-#[non_exhaustive]
-#[display(inner)]
-pub enum ExecError {
-    #[from]
-    #[from(io::Error)]
-    Io(IoError),
+        /// Do not signal BIP-125 replaceability of the transaction, which is signalled by default
+        #[clap(long)]
+        no_rbf: bool,
 
-    #[from]
-    Store(PersistenceError),
+        /// Construct a package where the parent transaction is accompanied by a child spending
+        /// its change output (CPFP) and paying the given additional fee, in satoshis or BTC.
+        ///
+        /// The child PSBT is saved next to the parent one with `-child` suffix in the file name.
+        #[clap(long, value_parser = parse_sats, conflicts_with = "deduct_fee")]
+        package_fee_boost: Option<Sats>,
 
-    #[from]
-    ConstructPsbt(ConstructionError),
+        /// Deduct the fee from the outputs with the given numbers (starting from zero, in the
+        /// order of `--to` arguments) proportionally to their amounts, instead of paying it from
+        /// the wallet funds.
+        #[clap(long, value_delimiter = ',')]
+        deduct_fee: Vec<usize>,
 
-    #[from]
-    DecodePsbt(psbt::DecodeError),
+        /// Spend only coins having at least the given number of confirmations, overriding the
+        /// wallet setting (see `settings --min-conf`)
+        #[clap(long, value_name = "N")]
+        min_conf: Option<Confirmations>,
 
-    #[from]
-    Unfinalized(UnfinalizedInputs),
+        /// Allow paying to the addresses of the wallet itself, which is refused otherwise as a
+        /// likely mistake
+        #[clap(long)]
+        allow_self_send: bool,
 
-    /// indexer failed with {0}
-    #[from]
-    #[cfg_attr(feature = "electrum", from(electrum::Error))]
-    #[cfg_attr(feature = "electrum", from(crate::indexers::electrum::ElectrumError))]
-    #[cfg_attr(feature = "esplora", from(esplora::Error))]
-    #[display(doc_comments)]
-    Indexer(AnyIndexerError),
-}
+        /// Shuffle the transaction inputs and outputs in the order derived from the given hex
+        /// seed. The construction doesn't use any randomness, so coordinators constructing the
+        /// transaction from the same wallet state with the same arguments (including the seed)
+        /// get the same PSBT, which they can cross-check by the printed proposal hash
+        #[clap(long, value_name = "HEX", conflicts_with = "package_fee_boost")]
+        deterministic_seed: Option<ShuffleSeed>,
 
-impl<O: DescriptorOpts> Exec for Args<Command, O> {
-    type Error = ExecError;
-    const CONF_FILE_NAME: &'static str = "bp.toml";
+        /// Split the change into up to N outputs of pseudo-random amounts, paying to the
+        /// subsequent change addresses, so the change can't be told from the payments by its
+        /// amount. Each of the outputs gets at least 10000 sats, which may reduce their number
+        #[clap(
+            long,
+            value_name = "N",
+            value_parser = clap::value_parser!(u8).range(1..=MAX_CHANGE_OUTPUTS as i64),
+            conflicts_with = "package_fee_boost"
+        )]
+        change_outputs: Option<u8>,
 
-    fn exec(self, mut config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
-        match &self.command {
-            Command::List => {
-                let dir = self.general.base_dir();
-                let Ok(dir) = fs::read_dir(dir).inspect_err(|err| {
-                    error!("Error reading wallet directory: {err:?}");
-                    eprintln!("System directory is not initialized");
-                    println!("no wallets found");
-                }) else {
-                    return Ok(());
-                };
-                println!("Known wallets:");
-                let mut count = 0usize;
-                for wallet in dir {
-                    let Ok(entry) = wallet else {
-                        continue;
-                    };
-                    let Ok(meta) = entry.metadata() else {
-                        continue;
-                    };
-                    if !meta.is_dir() {
-                        continue;
-                    }
-                    count += 1;
-                    let name = entry.file_name().into_string().expect("invalid directory name");
-                    print!(
-                        "{name}{}",
-                        if config.default_wallet == name { "\t[default]\t" } else { "\t\t" }
-                    );
-                    let provider = FsTextStore::new(entry.path().clone())?;
-                    let wallet = match Wallet::<XpubDerivable, O::Descr>::load(provider, true) {
-                        Err(err) => {
-                            error!("Error loading wallet descriptor: {err}");
-                            println!("# broken wallet descriptor");
-                            continue;
-                        }
-                        Ok(wallet) => wallet,
-                    };
-                    println!("\t{}", wallet.descriptor());
-                }
-                if count == 0 {
-                    println!("no wallets found");
-                }
-            }
-            Command::Default { default } => {
-                if let Some(default) = default {
-                    config.default_wallet = default.to_string();
-                    config.store(&self.conf_path(conf_filename));
-                } else {
-                    println!("Default wallet is '{}'", config.default_wallet);
-                }
-            }
-            Command::Create { name } => {
-                if !self.wallet.descriptor_opts.is_some() {
-                    eprintln!("Error: you must provide an argument specifying wallet descriptor");
-                    exit(1);
-                }
-                print!("Saving the wallet as '{name}' ... ");
-                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
-                let name = name.to_string();
-                let provider = FsTextStore::new(self.general.wallet_dir(&name))?;
-                wallet.make_persistent(provider, true)?;
-                wallet.set_name(name);
-                if let Err(err) = wallet.store() {
-                    println!("error: {err}");
-                } else {
-                    println!("success");
-                }
-            }
-            Command::Address {
-                change,
-                keychain,
-                index,
-                dry_run: no_shift,
-                count: no,
-            } => {
-                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
-                let keychain = match (change, keychain) {
-                    (false, None) => wallet.default_keychain(),
-                    (true, None) => (*change as u8).into(),
-                    (false, Some(keychain)) => *keychain,
-                    _ => unreachable!(),
-                };
-                if !wallet.keychains().contains(&keychain) {
-                    eprintln!(
-                        "Error: the specified keychain {keychain} is not a part of the descriptor"
-                    );
-                    exit(1);
-                }
-                let index =
-                    index.unwrap_or_else(|| wallet.next_derivation_index(keychain, !*no_shift));
-                println!("\nTerm.\tAddress");
-                for derived_addr in
-                    wallet.addresses(keychain).skip(index.index() as usize).take(*no as usize)
-                {
-                    println!("{}\t{}", derived_addr.terminal, derived_addr.addr);
-                }
-            }
-            Command::Finalize {
-                publish,
-                psbt: psbt_path,
-                tx,
-            } => {
-                let mut psbt = psbt_read(psbt_path)?;
-                if psbt.is_finalized() {
-                    eprintln!("The PSBT is already finalized");
-                } else {
-                    let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                    psbt_finalize(&mut psbt, wallet.descriptor())?;
-                }
+        /// Re-derive the scripts of the change outputs from the account key, bypassing the
+        /// descriptor, and refuse the transaction if they don't match. Supported only for
+        /// single-key P2WPKH and P2TR wallets
+        #[clap(long)]
+        verify_change: bool,
 
-                psbt_write(&psbt, psbt_path)?;
-                if let Ok(tx) = psbt_extract(&psbt, *publish, tx.as_deref()) {
-                    if *publish {
-                        let indexer = self.indexer()?;
-                        eprint!("Publishing transaction via {} ... ", indexer.name());
-                        indexer.publish(&tx)?;
-                        eprintln!("success");
-                    }
-                }
-            }
-            Command::Extract {
-                publish,
-                psbt: psbt_path,
-                tx,
-            } => {
-                let mut psbt = psbt_read(psbt_path)?;
-                if !psbt.is_finalized() {
-                    let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                    psbt_finalize(&mut psbt, wallet.descriptor())?;
-                }
+        /// Fee rate of the transaction (like `2.5sat/vb`). The fee is computed from the
+        /// estimated weight of the signed transaction, including its change output
+        #[clap(
+            long,
+            value_name = "RATE",
+            required_unless_present_any = ["fixed_fee", "legacy_fee", "conf_target"]
+        )]
+        fee_rate: Option<FeeRate>,
 
-                if let Ok(tx) = psbt_extract(&psbt, *publish, tx.as_deref()) {
-                    if *publish {
-                        let indexer = self.indexer()?;
-                        eprint!("Publishing transaction via {} ... ", indexer.name());
-                        indexer.publish(&tx)?;
-                        eprintln!("success");
-                    }
-                }
-            }
-        }
+        /// Confirmation target in blocks; the fee rate recommended by the indexer for this target
+        /// is used instead of `--fee-rate`
+        #[clap(long, value_name = "BLOCKS", conflicts_with_all = ["fee_rate", "fixed_fee"])]
+        conf_target: Option<u16>,
 
-        Ok(())
-    }
-}
+        /// Absolute fee overriding the fee rate, in satoshis or BTC (when given with decimal point
+        /// or `btc` suffix)
+        #[clap(
+            long = "fee",
+            value_name = "AMOUNT",
+            value_parser = parse_sats,
+            conflicts_with = "fee_rate"
+        )]
+        fixed_fee: Option<Sats>,
 
-impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
-    type Error = ExecError;
-    const CONF_FILE_NAME: &'static str = "bp.toml";
+        /// Deprecated: fee given as a positional argument, either as an amount or as a fee rate.
+        ///
+        /// Use `--fee-rate` or `--fee` instead; when any of them is given, the only positional
+        /// argument is the name of the PSBT file.
+        #[clap(value_name = "FEE")]
+        legacy_fee: Option<String>,
 
-    fn exec(mut self, config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
-        match &self.command {
-            BpCommand::General(cmd) => self.translate(cmd).exec(config, conf_filename)?,
-            BpCommand::Balance {
-                addr: false,
-                utxo: false,
-            } => {
-                let runtime = self.bp_wallet::<O::Descr>(&config)?;
-                println!("\nWallet total balance: {} ṩ", runtime.balance());
-            }
-            BpCommand::Balance {
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose PSBTs moving all spendable funds of the wallet to fresh addresses of another
+    /// wallet or descriptor, for instance when migrating to new keys.
+    ///
+    /// Coins which don't fit into a single standard transaction are swept with multiple
+    /// transactions, each paying to its own address of the target.
+    #[display("sweep")]
+    Sweep {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Name of the wallet receiving the funds. Its next receive addresses are issued, so
+        /// the wallet must be synced before
+        #[clap(
+            long,
+            value_name = "NAME",
+            required_unless_present = "to_descriptor",
+            conflicts_with = "to_descriptor",
+            add = ArgValueCandidates::new(wallet_names)
+        )]
+        to_wallet: Option<Ident>,
+
+        /// Descriptor receiving the funds, like `wpkh([fp/84h/0h/0h]xpub/<0;1>/*)`. The funds
+        /// are paid to its receive addresses starting from the first one
+        #[clap(long, value_name = "DESCRIPTOR", value_parser = parse_descriptor)]
+        to_descriptor: Option<StdDescr>,
+
+        /// Do not signal BIP-125 replaceability of the transactions, which is signalled by
+        /// default
+        #[clap(long)]
+        no_rbf: bool,
+
+        /// Allow sweeping to the addresses of the wallet itself, which is refused otherwise as a
+        /// likely mistake
+        #[clap(long)]
+        allow_self_send: bool,
+
+        /// Fee deducted from the swept funds, either in satoshis or BTC, which is paid by each
+        /// of the transactions, or as a fee rate (like `2.5sat/vb`)
+        #[clap(value_parser = parse_fee)]
+        fee: FeeSpec,
+
+        /// Name of a PSBT file to save. When multiple transactions are constructed, their
+        /// number is appended to the file name. If not given, prints PSBTs to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose PSBT replacing an unconfirmed wallet transaction with the one paying a higher
+    /// fee (BIP-125 replace-by-fee).
+    ///
+    /// The replacement spends the same coins and pays to the same outputs, taking the fee
+    /// increase from the change output. Use `--sync` to make sure the transaction is still
+    /// unconfirmed.
+    #[display("bump-fee")]
+    BumpFee {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Transaction to replace
+        txid: Txid,
+
+        /// Fee rate of the replacement (like `10sat/vb`), which must exceed the fee rate of the
+        /// replaced transaction
+        fee_rate: FeeRate,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+
+    /// Compose PSBT of a child transaction accelerating an unconfirmed transaction paying to
+    /// the wallet (child-pays-for-parent).
+    ///
+    /// The child spends the largest wallet output of the transaction, returning the funds to a
+    /// change address, and pays the fee bringing the transaction together with its unconfirmed
+    /// ancestors to the given fee rate.
+    #[display("cpfp")]
+    Cpfp {
+        /// Encode PSBT as V2
+        #[clap(short = '2')]
+        v2: bool,
+
+        /// Transaction to accelerate
+        txid: Txid,
+
+        /// Fee rate of the package of the child and the accelerated transactions (like
+        /// `10sat/vb`)
+        fee_rate: FeeRate,
+
+        /// Name of a PSBT file to save. If not given, prints PSBT to STDOUT
+        psbt: Option<PathBuf>,
+    },
+}
+
+#[cfg(feature = "signers")]
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum DevCommand {
+    /// Print deterministic test vectors in JSON format: addresses derived from well-known seeds
+    /// for BIP-43 schemes, and unsigned and signed PSBTs with their sighashes and transactions
+    #[display("vectors")]
+    Vectors,
+}
+
+#[cfg(feature = "remote")]
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum SyncStoreCommand {
+    /// Upload the wallet files changed since the last sync
+    #[display("push")]
+    Push {
+        #[clap(flatten)]
+        remote: RemoteOpts,
+
+        /// Overwrite the changes of the remote copy which were not pulled yet
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Download the wallet files changed on other machines since the last sync
+    #[display("pull")]
+    Pull {
+        #[clap(flatten)]
+        remote: RemoteOpts,
+
+        /// Overwrite the local changes made since the last sync
+        #[clap(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum AuditCommand {
+    /// Verify that each P2TR coin output key is the BIP-86 tweak of the internal key derived
+    /// from the descriptor, such that the coin has no hidden script spending paths
+    #[display("taproot")]
+    Taproot,
+
+    /// Cross-check the coins known to the wallet against the blockchain indexer, detecting coins
+    /// which were spent or reorganized out since the last sync. Recommended before constructing
+    /// large transactions
+    #[display("utxo-verify")]
+    UtxoVerify,
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum WorkspaceCommand {
+    /// Create a workspace directory for the unsigned PSBT. Cosigners put their signed copies
+    /// into `signed` subdirectory of the workspace, naming the files after themselves (like
+    /// `signed/alice.psbt`)
+    #[display("init")]
+    Init {
+        /// Workspace directory to create
+        dir: PathBuf,
+
+        /// Unsigned PSBT file to collect signatures for
+        psbt: PathBuf,
+    },
+
+    /// Show which cosigners have signed which inputs, saving the combined and finalized PSBT
+    /// as `final.psbt` once enough signatures are collected
+    #[display("status")]
+    Status {
+        /// Workspace directory
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum CollabCommand {
+    /// Start a new collaborative transaction session, saving it into a file which is passed
+    /// between the parties
+    #[display("init")]
+    Init {
+        /// Session file to create
+        session: PathBuf,
+
+        /// Fee rate of the transaction, like `2 sat/vb`
+        fee_rate: FeeRate,
+    },
+
+    /// Join the session with the wallet coins and outputs, returning the rest to a wallet change
+    /// address. For the coins given without a proof of ownership the messages to sign are
+    /// printed
+    #[display("join")]
+    Join {
+        /// Session file
+        session: PathBuf,
+
+        /// Name of the party in the session
+        #[clap(long)]
+        party: String,
+
+        /// Wallet coin to spend in form of `<outpoint>[=<proof>]`, where the proof is a hex
+        /// signature made with the coin key
+        #[clap(long = "coin", required = true, value_parser = parse_collab_coin)]
+        coins: Vec<(Outpoint, Option<OwnershipProof>)>,
+
+        /// Output to pay to in form of `<address>:<amount>`
+        #[clap(long = "to", value_parser = parse_txout)]
+        outputs: Vec<(Address, Sats)>,
+    },
+
+    /// Assemble the unsigned PSBT from the inputs and outputs of all the session parties
+    #[display("finalize")]
+    Finalize {
+        /// Session file
+        session: PathBuf,
+
+        /// File to save the PSBT to
+        psbt: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum AirgapCommand {
+    /// Export unsigned PSBT for signing into `psbt` subdirectory of the air-gap directory,
+    /// assigning it the next sequence number and writing its SHA256 checksum
+    #[display("export")]
+    Export {
+        /// Air-gap directory, which is created if it doesn't exist
+        dir: PathBuf,
+
+        /// Unsigned PSBT file to export
+        psbt: PathBuf,
+    },
+
+    /// Finalize PSBTs signed by the device and put into `signed` subdirectory, saving the
+    /// extracted transactions into `txs` subdirectory
+    #[display("import")]
+    Import {
+        /// Air-gap directory
+        dir: PathBuf,
+
+        /// Publish the extracted transactions
+        #[clap(short, long)]
+        publish: bool,
+
+        /// Keep watching the directory, finalizing and publishing signed PSBTs as they appear
+        #[clap(long)]
+        watch: bool,
+
+        /// Interval between the directory checks in watch mode, in seconds
+        #[clap(long, default_value = "5", requires = "watch")]
+        interval: u64,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum PendingCommand {
+    /// List pending transactions with their lifecycle status
+    #[display("list")]
+    List,
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum ContactCommand {
+    /// Assign name to a counterparty address, replacing the address previously known under the
+    /// same name
+    #[display("add")]
+    Add {
+        /// Contact name
+        contact: Ident,
+
+        /// Address of the contact, or its extended public key with the script template in form
+        /// of `wpkh(<xpub>)` or `tr(<xpub>)`. For the extended keys a fresh address is derived
+        /// for each payment, avoiding address reuse
+        destination: Contact,
+    },
+
+    /// Remove contact
+    #[display("remove")]
+    Remove {
+        /// Contact name
+        contact: Ident,
+    },
+
+    /// List contacts
+    #[display("list")]
+    List,
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum InvoiceCommand {
+    /// Issue a new address for an expected payment and print it
+    #[display("create")]
+    Create {
+        /// Expected amount
+        #[clap(long, value_parser = parse_sats)]
+        amount: Sats,
+
+        /// Number of seconds after which the invoice expires if not paid
+        #[clap(long)]
+        expires_in: Option<u64>,
+
+        /// Memo to record with the invoice
+        #[clap(long)]
+        memo: Option<String>,
+    },
+
+    /// List invoices and their status
+    #[display("list")]
+    List,
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum CacheCommand {
+    /// Add a transaction to the wallet cache, resolving its inputs and outputs against the
+    /// wallet descriptor. Without a merkle proof the transaction is added as unconfirmed
+    #[display("add-tx")]
+    AddTx {
+        /// Raw transaction in hex, or a file containing it in hex or binary form
+        #[clap(value_parser = parse_raw_tx)]
+        tx: Tx,
+
+        /// Hex-encoded merkle proof of the transaction inclusion into a block, as produced by
+        /// `bitcoin-cli gettxoutproof`
+        #[clap(long, requires = "height")]
+        proof: Option<MerkleBlock>,
+
+        /// Height of the block containing the transaction
+        #[clap(long, requires = "proof")]
+        height: Option<BlockHeight>,
+
+        /// Previous transaction providing outputs spent by the added transaction, required to
+        /// resolve the inputs which are not known to the wallet
+        #[clap(long, value_parser = parse_raw_tx)]
+        prev: Vec<Tx>,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum ReportCommand {
+    /// Report miner fees paid by the wallet transactions per calendar period. Unmined
+    /// transactions are not included
+    #[display("fees")]
+    Fees {
+        /// Calendar period to aggregate fees over
+        #[clap(short, long, value_enum, default_value_t)]
+        period: Period,
+
+        /// Export the report in the given format instead of printing a table
+        #[clap(short, long, value_enum)]
+        format: Option<ExportFormat>,
+
+        /// Count transfers to other wallets in the data directory
+        #[clap(long)]
+        transfers: bool,
+    },
+
+    /// Report capital gains realized by the wallet transactions. Funds received by the wallet
+    /// form lots valued at the exchange rate of the mining time, which are disposed by the
+    /// transactions decreasing the wallet balance (including miner fees). Unmined transactions
+    /// are not included
+    #[display("gains")]
+    Gains {
+        /// Fiat currency to value the funds in (like `USD`)
+        #[clap(long, value_name = "CURRENCY")]
+        currency: Currency,
+
+        /// Order in which the lots are disposed
+        #[clap(long, value_enum, default_value_t)]
+        method: LotMethod,
+
+        /// Report only disposals made on the given day (`YYYY-MM-DD`, UTC) or later
+        #[clap(long, value_name = "DATE")]
+        from: Option<Date>,
+
+        /// Report only disposals made on the given day (`YYYY-MM-DD`, UTC) or earlier
+        #[clap(long, value_name = "DATE")]
+        to: Option<Date>,
+
+        /// Export the report in the given format instead of printing a table. CSV has a row per
+        /// disposal with the columns of IRS Form 8949, which are accepted by common tax tools
+        #[clap(short, long, value_enum)]
+        format: Option<ExportFormat>,
+    },
+}
+
+#[derive(Subcommand, Clone, PartialEq, Eq, Debug, Display)]
+pub enum PsbtCommand {
+    /// Abandon a PSBT draft which will never be signed or published, releasing the change
+    /// address it has reserved
+    #[display("abandon")]
+    Abandon {
+        /// PSBT file or id of the draft transaction
+        draft: String,
+    },
+
+    /// Convert PSBT into a raw unsigned transaction in hex encoding
+    #[display("to-raw")]
+    ToRaw {
+        /// Name of a PSBT file to convert
+        psbt: PathBuf,
+
+        /// File to save the transaction hex to. If not given, prints it to STDOUT
+        tx: Option<PathBuf>,
+    },
+
+    /// Add a wallet coin as a new input to an unsigned PSBT v2, updating the file in place
+    #[display("add-input")]
+    AddInput {
+        /// PSBT file to amend
+        psbt: PathBuf,
+
+        /// Wallet coin to spend
+        outpoint: Outpoint,
+    },
+
+    /// Fill in a PSBT created elsewhere with the wallet knowledge, allowing it to be signed by
+    /// the wallet signers, updating the file in place
+    ///
+    /// Adds spent outputs and full spent transactions, BIP-32 derivations of the wallet keys and
+    /// taproot internal keys. Spent transactions are taken from the transaction store or, if
+    /// an indexer is configured, downloaded.
+    #[display("fill")]
+    Fill {
+        /// PSBT file to fill in
+        psbt: PathBuf,
+    },
+
+    /// Report which keys are required to sign each of the PSBT inputs and which of them have
+    /// already signed, including the multisig threshold progress
+    ///
+    /// Uses only the wallet descriptor and the PSBT, without any private keys.
+    #[display("who-can-sign")]
+    WhoCanSign {
+        /// PSBT file to inspect
+        psbt: PathBuf,
+    },
+
+    /// Add a new output to an unsigned PSBT v2, updating the file in place
+    #[display("add-output")]
+    AddOutput {
+        /// PSBT file to amend
+        psbt: PathBuf,
+
+        /// Output to add in form of `<address>:<amount>`
+        #[clap(value_parser = parse_txout)]
+        output: (Address, Sats),
+    },
+}
+
+#[derive(Debug, Display, Error, From)]
+#[non_exhaustive]
+#[display(inner)]
+pub enum ExecError {
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    #[from]
+    Store(PersistenceError),
+
+    #[from]
+    ConstructPsbt(ConstructionError),
+
+    #[from]
+    DecodePsbt(psbt::DecodeError),
+
+    #[from]
+    Unfinalized(UnfinalizedInputs),
+
+    #[from]
+    Rates(RatesError),
+
+    #[from]
+    Resolve(ResolveError),
+
+    #[from]
+    Package(PackageError),
+
+    #[from]
+    Split(SplitError),
+
+    #[from]
+    Deduct(DeductError),
+
+    #[from]
+    Sweep(SweepError),
+
+    #[from]
+    Bump(BumpError),
+
+    #[from]
+    Cpfp(CpfpError),
+
+    #[from]
+    NonStandard(StandardnessError),
+
+    #[from]
+    Payment(PaymentParseError),
+
+    #[cfg(feature = "remote")]
+    Remote(RemoteError),
+
+    #[from]
+    Signer(SignerError),
+
+    #[from]
+    Tls(TlsError),
+
+    #[from]
+    Confirmation(ConfirmationError),
+
+    #[from]
+    Workspace(WorkspaceError),
+
+    #[from]
+    Airgap(AirgapError),
+
+    #[from]
+    Import(ImportError),
+
+    #[from]
+    Amend(AmendError),
+
+    #[from]
+    Collab(CollabError),
+
+    #[from]
+    MerkleProof(MerkleProofError),
+
+    /// the merkle proof doesn't prove inclusion of transaction {0} into the block
+    #[display(doc_comments)]
+    NotProven(Txid),
+
+    /// transaction {0} neither spends from nor pays to the wallet
+    #[display(doc_comments)]
+    IrrelevantTx(Txid),
+
+    /// {0} coin(s) have no proof of ownership; sign the printed messages with the coin keys and
+    /// provide the signatures as `<outpoint>=<proof>`
+    #[display(doc_comments)]
+    MissingProofs(usize),
+
+    /// taproot audit has failed for {0} coin(s)
+    #[display(doc_comments)]
+    TaprootAudit(usize),
+
+    /// {0} coin(s) of the wallet are not confirmed as unspent by the indexer; run `bp sync` to
+    /// update the wallet
+    #[display(doc_comments)]
+    UtxoAudit(usize),
+
+    /// wallet health check has failed with {0} severity
+    #[display(doc_comments)]
+    Unhealthy(Severity),
+
+    #[from]
+    Template(TemplateError),
+
+    /// no blockchain indexer specified; use either --esplora --mempool or --electrum argument, or
+    /// configure the wallet indexer with `settings --indexer`
+    #[display(doc_comments)]
+    NoIndexer,
+
+    /// indexer server {0} has custom TLS options, which can't be used together with a proxy
+    #[display(doc_comments)]
+    ProxyTls(String),
+
+    /// you must provide an argument specifying wallet descriptor
+    #[display(doc_comments)]
+    NoDescriptor,
+
+    /// the specified keychain {0} is not a part of the descriptor
+    #[display(doc_comments)]
+    UnknownKeychain(Keychain),
+
+    /// wallet '{0}' does not exist
+    #[display(doc_comments)]
+    WalletNotFound(String),
+
+    /// wallet directory name '{0}' is not a valid UTF-8 string
+    #[display(doc_comments)]
+    InvalidWalletName(String),
+
+    /// wallet '{0}' already exists
+    #[display(doc_comments)]
+    WalletExists(String),
+
+    /// unknown contact '{0}'; add it with `bp contact add`
+    #[display(doc_comments)]
+    UnknownContact(String),
+
+    /// no fee specified; use `--fee-rate`, `--conf-target` or `--fee` argument
+    #[display(doc_comments)]
+    NoFee,
+
+    /// indexer {0} doesn't provide recommended fee rates; use `--fee-rate` argument instead
+    #[display(doc_comments)]
+    NoFeeEstimates(String),
+
+    /// the fee is given both by an argument and positionally; with `--fee-rate` or `--fee` the
+    /// only positional argument is the PSBT file name
+    #[display(doc_comments)]
+    FeeConflict,
+
+    /// invalid fee '{0}': {1}
+    #[display(doc_comments)]
+    InvalidFee(String, FeeParseError),
+
+    /// address {0} doesn't belong to the wallet
+    #[display(doc_comments)]
+    ForeignAddress(Address),
+
+    /// address {0} belongs to a different network than the wallet
+    #[display(doc_comments)]
+    WrongNetwork(Address),
+
+    /// address {0} belongs to the wallet itself ({1}); use `--allow-self-send` if the payment
+    /// to the own address is intended
+    #[display(doc_comments)]
+    SelfSend(Address, Terminal),
+
+    /// change output #{0} pays to {1}, while the key of the wallet account at {2} gives {3};
+    /// the transaction is not saved since the change may be unspendable
+    #[display(doc_comments)]
+    ChangeMismatch(u32, Address, Terminal, Address),
+
+    /// change output #{0} can't be verified, since its script is not re-derivable for
+    /// multi-key descriptors and script types other than P2WPKH and P2TR
+    #[display(doc_comments)]
+    ChangeUnverifiable(u32),
+
+    /// the wallet given by a descriptor can't be saved; use `create`
+    #[display(doc_comments)]
+    NotPersisted,
+
+    /// no remote storage specified; use --remote argument or `sync_remote` configuration option
+    #[display(doc_comments)]
+    NoRemote,
+
+    /// indexer failed with {0}
+    #[from]
+    #[cfg_attr(feature = "electrum", from(electrum::Error))]
+    #[cfg_attr(feature = "electrum", from(crate::indexers::electrum::ElectrumError))]
+    #[cfg_attr(feature = "esplora", from(esplora::Error))]
+    #[display(doc_comments)]
+    Indexer(AnyIndexerError),
+}
+
+#[cfg(feature = "remote")]
+impl From<RemoteError> for ExecError {
+    fn from(err: RemoteError) -> Self { ExecError::Remote(err) }
+}
+
+impl ExecError {
+    /// Process exit code for the error. Health check failures are reported with code 2 for
+    /// warnings and 3 for critical problems; all other errors use code 1.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ExecError::Unhealthy(Severity::Warning) => 2,
+            ExecError::Unhealthy(Severity::Critical) => 3,
+            _ => 1,
+        }
+    }
+}
+
+impl<O: DescriptorOpts> Exec for Args<Command, O> {
+    type Error = ExecError;
+    const CONF_FILE_NAME: &'static str = "bp.toml";
+
+    fn exec(self, mut config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
+        match &self.command {
+            Command::List => {
+                let Some(wallets) = list_wallets::<O::Descr, _>(
+                    &self.general,
+                    &config.default_wallet,
+                    &FsStoreFactory,
+                )?
+                else {
+                    eprintln!("System directory is not initialized");
+                    println!("no wallets found");
+                    return Ok(());
+                };
+                println!("Known wallets:");
+                for wallet in &wallets {
+                    print!(
+                        "{}{}",
+                        wallet.name,
+                        if wallet.is_default { "\t[default]\t" } else { "\t\t" }
+                    );
+                    match &wallet.descriptor {
+                        Some(descriptor) => println!("\t{descriptor}"),
+                        None => println!("# broken wallet descriptor"),
+                    }
+                }
+                if wallets.is_empty() {
+                    println!("no wallets found");
+                }
+            }
+            Command::Default { default } => {
+                if let Some(default) = default {
+                    config.default_wallet = default.to_string();
+                    config.store(&self.conf_path(conf_filename), &self.changes);
+                } else {
+                    println!("Default wallet is '{}'", config.default_wallet);
+                }
+            }
+            Command::Create {
+                name,
+                static_address,
+                template: Some(template),
+                key,
+            } => {
+                let network = self.general.network;
+                let descriptor = template.expand(key, network.is_testnet())?;
+                print!("Saving the wallet as '{name}' ... ");
+                let mut wallet = Wallet::<XpubDerivable, StdDescr>::new_layer1(descriptor, network);
+                let name = name.to_string();
+                let provider = self.store_factory(&config).open(self.general.wallet_dir(&name))?;
+                create_wallet(&mut wallet, provider, name, *static_address)?;
+                println!("success");
+            }
+            Command::Create {
+                name,
+                static_address,
+                template: None,
+                ..
+            } => {
+                if !self.wallet.descriptor_opts.is_some() {
+                    return Err(ExecError::NoDescriptor);
+                }
+                print!("Saving the wallet as '{name}' ... ");
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let name = name.to_string();
+                let provider = self.store_factory(&config).open(self.general.wallet_dir(&name))?;
+                create_wallet(&mut wallet, provider, name, *static_address)?;
+                println!("success");
+            }
+            Command::Clone {
+                name,
+                new_name,
+                without_cache,
+            } => {
+                print!("Cloning wallet '{name}' as '{new_name}' ... ");
+                clone_wallet::<O::Descr, _>(
+                    &self.general,
+                    &self.store_factory(&config),
+                    name.as_str(),
+                    new_name.as_str(),
+                    *without_cache,
+                )?;
+                println!("success");
+            }
+            Command::Import {
+                from,
+                file,
+                name,
+                labels,
+            } => {
+                let dir = self.general.wallet_dir(name.as_str());
+                if dir.exists() {
+                    return Err(ExecError::WalletExists(name.to_string()));
+                }
+                let mut imported = ImportedWallet::parse(*from, &fs::read_to_string(file)?)?;
+                if let Some(path) = labels {
+                    imported.labels.extend(WalletLabels::from_bip329(&fs::read_to_string(path)?)?);
+                }
+                let network = self.general.network;
+                if imported.is_testnet() != network.is_testnet() {
+                    return Err(ImportError::WrongNetwork(network).into());
+                }
+                print!("Importing {from} wallet as '{name}' ... ");
+                let mut wallet =
+                    Wallet::<XpubDerivable, StdDescr>::new_layer1(imported.descriptor, network);
+                let count = imported.labels.len();
+                imported.labels.apply(&mut wallet);
+                let provider = self.store_factory(&config).open(dir)?;
+                create_wallet(&mut wallet, provider, name.to_string(), None)?;
+                println!("success");
+                if count > 0 {
+                    println!("{count} label(s) imported");
+                }
+            }
+            Command::Templates => {
+                let testnet = self.general.network.is_testnet();
+                println!("Template\tKeys\tDerivation\tDescriptor");
+                for template in WalletTemplate::ALL {
+                    println!(
+                        "{template}\t{}\t{}\t{}{}",
+                        template.keys(),
+                        template.scheme().account_template_string(testnet),
+                        template.descriptor(),
+                        if template.is_supported() { "" } else { "\t(placeholder)" }
+                    );
+                }
+            }
+            Command::ExportDescriptor { name } => {
+                let provider = FsStoreFactory.open(self.general.wallet_dir(name.to_string()))?;
+                let wallet = Wallet::<XpubDerivable, O::Descr>::load(provider, false)?;
+                print!("{}", WalletBackup::with(name.as_str(), &wallet));
+            }
+            Command::Sync { emit_delta } => {
+                let mut args = self.clone();
+                args.sync = true;
+                let wallet = args.bp_wallet::<O::Descr>(&config)?;
+                if *emit_delta {
+                    let delta = wallet.sync_delta().expect("the wallet is synced");
+                    println!(
+                        "{}",
+                        serde_json::to_string_pretty(&delta)
+                            .expect("unable to generate JSON representation")
+                    );
+                }
+            }
+            Command::Save => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if !wallet.is_persisted() {
+                    return Err(ExecError::NotPersisted);
+                }
+                eprint!("Saving the wallet ... ");
+                wallet.store_all()?;
+                eprintln!("success");
+            }
+            Command::Address {
+                command: Some(AddressCommand::History { address }),
+                ..
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let terminal =
+                    wallet.terminal_of(address).ok_or(ExecError::ForeignAddress(*address))?;
+                println!("History of {} at {terminal}", self.display.address(address));
+                println!(
+                    "\nHeight\t{:<1$}\t{2:>13}\tOutpoint\tSpent by",
+                    "Txid",
+                    self.display.txid_width(),
+                    format!("Amount, {}", self.display.unit()),
+                );
+                let unit = self.display.unit();
+                for row in wallet.address_history(terminal) {
+                    println!(
+                        "{}\t{}\t{}{: >12}\t{}\t{}",
+                        row.height,
+                        self.display.txid(&row.txid),
+                        row.operation,
+                        self.display.amount(row.amount),
+                        row.outpoint,
+                        row.spent.map_or_else(|| s!("unspent"), |inpoint| inpoint.to_string())
+                    );
+                    for (cp, value) in &row.counterparties {
+                        println!(
+                            "\t* {: >12}{unit}\t{}\t{}",
+                            self.display.signed_amount(*value),
+                            if *value > 0 { "payer      " } else { "beneficiary" },
+                            match wallet.counterparty_contact(cp) {
+                                Some(name) => format!("contact {name}"),
+                                None => self.display.counterparty(cp),
+                            }
+                        );
+                    }
+                }
+            }
+            Command::Address {
+                command: None,
+                change,
+                keychain,
+                index,
+                dry_run: no_shift,
+                count: no,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let keychain = match (change, keychain) {
+                    (false, None) => wallet.default_keychain(),
+                    (true, None) => (*change as u8).into(),
+                    (false, Some(keychain)) => *keychain,
+                    _ => unreachable!(),
+                };
+                let addresses =
+                    derive_addresses(&mut wallet, keychain, *index, !*no_shift, *no as usize)?;
+                println!("\nTerm.\tAddress\tKey origin");
+                for derived_addr in addresses {
+                    println!(
+                        "{}\t{}\t{}",
+                        derived_addr.terminal,
+                        self.display.address(&derived_addr.addr),
+                        self.display.key_origins(&wallet.key_origin(derived_addr.terminal))
+                    );
+                }
+            }
+            Command::Descriptor {
+                command:
+                    DescriptorCommand::Scripts {
+                        keychain,
+                        range,
+                        format,
+                    },
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let keychain = keychain.unwrap_or_else(|| wallet.default_keychain());
+                if !wallet.keychains().contains(&keychain) {
+                    return Err(ExecError::UnknownKeychain(keychain));
+                }
+                let from = NormalIndex::try_from_index(range.start)
+                    .expect("index range is validated when parsed");
+                let addresses = wallet.addresses_from(keychain, from).take(range.len());
+                // Addresses are written as they are derived, such that large ranges are never
+                // kept in memory
+                let mut out = io::BufWriter::new(io::stdout().lock());
+                match format {
+                    ExportFormat::Csv => {
+                        writeln!(out, "keychain,index,scriptPubkey,address")?;
+                        for derived in addresses {
+                            writeln!(
+                                out,
+                                "{},{},{},{}",
+                                derived.terminal.keychain,
+                                derived.terminal.index,
+                                derived.addr.script_pubkey().to_hex(),
+                                derived.addr
+                            )?;
+                        }
+                    }
+                    ExportFormat::Json => {
+                        write!(out, "[")?;
+                        for (no, derived) in addresses.enumerate() {
+                            let item = serde_json::json!({
+                                "keychain": derived.terminal.keychain,
+                                "index": derived.terminal.index.index(),
+                                "scriptPubkey": derived.addr.script_pubkey().to_hex(),
+                                "address": derived.addr.to_string(),
+                            });
+                            write!(out, "{}\n  {item}", if no == 0 { "" } else { "," })?;
+                        }
+                        writeln!(out, "\n]")?;
+                    }
+                }
+                out.flush()?;
+            }
+            Command::Keychain {
+                command: KeychainCommand::Status,
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                println!("\nKeychain\tNext index\t# used\t# unpublished\t{:>10}", "Remaining");
+                for keychain in wallet.keychains() {
+                    let status = wallet.keychain_status(keychain);
+                    print!(
+                        "{keychain:>8}\t{:>10}\t{:>6}\t{:>13}\t{:>10}",
+                        status.next_index,
+                        status.used,
+                        status.unpublished(),
+                        status.remaining()
+                    );
+                    if status.is_exhausting() {
+                        print!("\t{}", "close to exhaustion".bright_red());
+                    }
+                    if status.exceeds_gap_limit() {
+                        print!("\t{}", format!("exceeds gap limit of {GAP_LIMIT}").bright_yellow());
+                    }
+                    println!();
+                }
+            }
+            Command::Settings {
+                min_conf,
+                indexer,
+                indexer_url,
+                indexer_proxy,
+                no_indexer,
+                change_window,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if let Some(min_conf) = min_conf {
+                    wallet.set_min_confirmations(*min_conf);
+                }
+                if let Some(window) = change_window {
+                    wallet.set_change_window(*window);
+                }
+                if let Some(kind) = *indexer {
+                    let url = indexer_url.clone().unwrap_or_else(|| {
+                        match kind {
+                            IndexerKind::Electrum => DEFAULT_ELECTRUM,
+                            IndexerKind::Esplora => DEFAULT_ESPLORA,
+                            IndexerKind::Mempool => DEFAULT_MEMPOOL,
+                        }
+                        .to_owned()
+                    });
+                    let proxy = indexer_proxy.clone();
+                    wallet.set_indexer(Some(IndexerConfig { kind, url, proxy }));
+                } else if *no_indexer {
+                    wallet.set_indexer(None);
+                }
+                println!("Minimal confirmations for spending: {}", wallet.min_confirmations());
+                match wallet.indexer() {
+                    Some(indexer) => {
+                        println!("Indexer: {indexer}");
+                        if let Some(proxy) = &indexer.proxy {
+                            println!("Indexer proxy: {proxy}");
+                        }
+                    }
+                    None => println!("Indexer: not configured"),
+                }
+                match wallet.change_window() {
+                    0 | 1 => println!("Change addresses: sequential"),
+                    window => println!("Change addresses: window of {window} indexes"),
+                }
+                if let Some(terminal) = wallet.static_terminal() {
+                    println!("Static receiving address: {terminal}");
+                }
+                let locked = wallet.locked_balance(wallet.min_confirmations());
+                if locked > Sats::ZERO {
+                    println!(
+                        "Balance locked until more confirmations: {}",
+                        self.display.amount_with_unit(locked)
+                    );
+                }
+            }
+            Command::Finalize {
+                publish,
+                psbt: psbt_path,
+                tx,
+            } => {
+                let mut psbt = psbt_read(psbt_path)?;
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let finalization = finalize_psbt(&mut wallet, &mut psbt);
+                finalization_print(&finalization);
+                psbt_write(&psbt, psbt_path, &self.changes)?;
+                let tx = tx_write_or_print(finalization.tx, *publish, tx.as_deref(), &self.changes);
+                if let Ok(tx) = tx {
+                    if *publish {
+                        let broadcaster = self.broadcaster(&config, wallet.indexer())?;
+                        eprint!("Publishing transaction via {} ... ", endpoints(&broadcaster));
+                        let refused = publish_tx(&mut wallet, &broadcaster, &tx, &self.changes)?;
+                        eprintln!("success");
+                        refused_print(refused);
+                    }
+                }
+            }
+            Command::Extract {
+                publish,
+                psbt: psbt_path,
+                tx,
+            } => {
+                let mut psbt = psbt_read(psbt_path)?;
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let finalization = finalize_psbt(&mut wallet, &mut psbt);
+                if finalization.finalized.is_some() {
+                    finalization_print(&finalization);
+                }
+                let tx = tx_write_or_print(finalization.tx, *publish, tx.as_deref(), &self.changes);
+                if let Ok(tx) = tx {
+                    if *publish {
+                        let broadcaster = self.broadcaster(&config, wallet.indexer())?;
+                        eprint!("Publishing transaction via {} ... ", endpoints(&broadcaster));
+                        let refused = publish_tx(&mut wallet, &broadcaster, &tx, &self.changes)?;
+                        eprintln!("success");
+                        refused_print(refused);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
+    type Error = ExecError;
+    const CONF_FILE_NAME: &'static str = "bp.toml";
+
+    fn exec(mut self, config: Config, conf_filename: &'static str) -> Result<(), Self::Error> {
+        match &self.command {
+            BpCommand::General(cmd) => self.translate(cmd).exec(config, conf_filename)?,
+            BpCommand::Balance {
+                utxo,
+                at_height,
+                at_date,
+                ..
+            } if at_height.is_some() || at_date.is_some() => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let historical = balance_at(&wallet, *at_height, *at_date);
+                let cutoff = match (at_height, at_date) {
+                    (Some(height), _) => format!("block {height}"),
+                    (None, Some(date)) => format!("the end of {date}"),
+                    (None, None) => unreachable!("guarded by the match arm"),
+                };
+                if *utxo {
+                    let width = self.display.outpoint_width();
+                    let amount = format!("Amount, {}", self.display.unit());
+                    println!("\nHeight\t{amount:>12}\t{:width$}\tAddress", "Outpoint");
+                    for coin in &historical.coins {
+                        println!(
+                            "{}\t{: >12}\t{:width$}\t{}",
+                            coin.status.map(|info| info.height),
+                            self.display.amount(coin.value),
+                            self.display.outpoint(&coin.outpoint),
+                            coin.terminal
+                        );
+                    }
+                }
+                let balance = self.display.amount_with_unit(historical.balance);
+                println!("\nWallet balance at {cutoff}: {balance}");
+                if let Some(height) = historical.height.filter(|_| historical.is_ahead_of_sync()) {
+                    eprintln!(
+                        "Warning: the wallet is synced only up to block {}, while the balance is \
+                         requested at block {height}",
+                        historical.synced
+                    );
+                }
+            }
+            BpCommand::Balance {
+                addr: false,
+                utxo: false,
+                display_fiat,
+                ..
+            } => {
+                // Without syncing the balance is known from the cache summary, so the wallet
+                // transactions are not loaded
+                let (balance, _wallet) = match self.bp_wallet_summary(&config)? {
+                    Some(summary) => (summary.balance(), None),
+                    None => {
+                        let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                        (wallet.balance(), Some(wallet))
+                    }
+                };
+                print!("\nWallet total balance: {}", self.display.amount_with_unit(balance));
+                if let Some(currency) = display_fiat {
+                    match self.rate_provider(&config)?.current_rate(currency) {
+                        Ok(rate) => print!(
+                            " ≈ {} {currency}",
+                            self.display.fiat(Currency::convert(balance, rate))
+                        ),
+                        Err(err) => eprint!("\nWarning: unable to get {currency} rate: {err}"),
+                    }
+                }
+                println!();
+            }
+            BpCommand::Balance {
                 addr: true,
                 utxo: false,
+                display_fiat,
+                ..
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                println!("\nTerm.\t{:62}\t# used\tVol., ṩ\tBalance, ṩ", "Address");
+                let width = self.display.address_width();
+                let unit = self.display.unit();
+                println!(
+                    "\nTerm.\t{:width$}\t# used\tVol., {unit}\tBalance, {unit}\tKey origin",
+                    "Address"
+                );
                 for info in wallet.address_balance() {
                     let WalletAddr {
                         addr,
@@ -382,150 +1881,1397 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
                         volume,
                         balance,
                     } = info;
-                    println!("{terminal}\t{:62}\t{used}\t{volume}\t{balance}", addr.to_string());
+                    println!(
+                        "{terminal}\t{:width$}\t{used}\t{}\t{}\t{}",
+                        self.display.address(&addr),
+                        self.display.amount(volume),
+                        self.display.amount(balance),
+                        self.display.key_origins(&wallet.key_origin(terminal))
+                    );
+                }
+                self.command = BpCommand::Balance {
+                    addr: false,
+                    utxo: false,
+                    display_fiat: display_fiat.clone(),
+                    at_height: None,
+                    at_date: None,
+                };
+                self.sync = false;
+                self.exec(config, conf_filename)?;
+            }
+            BpCommand::Balance {
+                addr: false,
+                utxo: true,
+                display_fiat,
+                ..
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                println!("Balance of {}", wallet.descriptor());
+                let width = self.display.outpoint_width();
+                println!(
+                    "\nHeight\t{:>12}\t{:width$}\tAddress\tKey origin{}",
+                    format!("Amount, {}", self.display.unit()),
+                    "Outpoint",
+                    self.display.layer2_headers::<CliLayer2Coin>()
+                );
+                for row in wallet.coins() {
+                    println!(
+                        "{}\t{: >12}\t{:width$}\t{}\t{}{}",
+                        row.height,
+                        self.display.amount(row.amount),
+                        self.display.outpoint(&row.outpoint),
+                        self.display.derived_addr(&row.address),
+                        self.display.key_origins(&wallet.key_origin(row.address.terminal)),
+                        self.display.layer2_columns(&row.layer2)
+                    );
+                }
+                self.command = BpCommand::Balance {
+                    addr: false,
+                    utxo: false,
+                    display_fiat: display_fiat.clone(),
+                    at_height: None,
+                    at_date: None,
+                };
+                self.sync = false;
+                self.exec(config, conf_filename)?;
+            }
+            BpCommand::Balance {
+                addr: true,
+                utxo: true,
+                display_fiat,
+                ..
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                println!("Balance of {}", wallet.descriptor());
+                let width = self.display.outpoint_width();
+                println!(
+                    "\nHeight\t{:>12}\t{:width$}{}",
+                    format!("Amount, {}", self.display.unit()),
+                    "Outpoint",
+                    self.display.layer2_headers::<CliLayer2Coin>()
+                );
+                for (derived_addr, utxos) in wallet.address_coins() {
+                    println!(
+                        "{}\t{}\t{}",
+                        self.display.address(&derived_addr.addr),
+                        derived_addr.terminal,
+                        self.display.key_origins(&wallet.key_origin(derived_addr.terminal))
+                    );
+                    for row in utxos {
+                        println!(
+                            "{}\t{: >12}\t{:width$}{}",
+                            row.height,
+                            self.display.amount(row.amount),
+                            self.display.outpoint(&row.outpoint),
+                            self.display.layer2_columns(&row.layer2)
+                        );
+                    }
+                    println!()
                 }
                 self.command = BpCommand::Balance {
                     addr: false,
                     utxo: false,
+                    display_fiat: display_fiat.clone(),
+                    at_height: None,
+                    at_date: None,
                 };
                 self.sync = false;
                 self.exec(config, conf_filename)?;
             }
-            BpCommand::Balance {
-                addr: false,
-                utxo: true,
+            BpCommand::History {
+                txid,
+                details,
+                display_fiat,
+                transfers,
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let own = match transfers {
+                    true => self.own_wallets(wallet.descriptor())?,
+                    false => OwnWallets::new(),
+                };
+                let rates =
+                    display_fiat.as_ref().map(|_| self.rate_provider(&config)).transpose()?;
+                println!("History of {}", wallet.descriptor());
+                print!(
+                    "\nHeight\t{:<1$}\t{2:>13}\t{3:>16}\tRBF",
+                    "Txid",
+                    if *txid { 64 } else { self.display.txid_width() },
+                    format!("Amount, {}", self.display.unit()),
+                    format!("Fee rate, {}", self.display.fee_unit)
+                );
+                if let Some(currency) = display_fiat {
+                    print!("\t{:>13}", format!("Amount, {currency}"));
+                }
+                if *transfers {
+                    print!("\tTransfer");
+                }
+                println!("{}", self.display.layer2_headers::<CliLayer2Tx>());
+                let unit = self.display.unit();
+                // Rows are constructed one by one, such that the memory use doesn't grow with the
+                // wallet history size
+                for row in wallet.history_by_height() {
+                    print!(
+                        "{}\t{}\t{}{: >12}\t{: >16}\t{}",
+                        row.height,
+                        if *txid { row.txid.to_string() } else { self.display.txid(&row.txid) },
+                        row.operation,
+                        self.display.amount(row.amount),
+                        self.display.fee_rate(row.fee_rate),
+                        if row.rbf { "yes" } else { "no" }
+                    );
+                    if let (Some(rates), Some(currency)) = (&rates, display_fiat) {
+                        let rate = match row.time {
+                            TxStatus::Mined(time) => rates.historical_rate(currency, time),
+                            _ => rates.current_rate(currency),
+                        };
+                        match rate {
+                            Ok(rate) => {
+                                print!(
+                                    "\t{}{: >12}",
+                                    row.operation,
+                                    self.display.fiat(Currency::convert(row.amount, rate))
+                                )
+                            }
+                            Err(err) => {
+                                warn!("Unable to get {currency} rate for {}: {err}", row.txid);
+                                print!("\t{: >13}", "n/a");
+                            }
+                        }
+                    }
+                    if *transfers {
+                        let wallets = own.transfer_wallets(&row);
+                        print!("\t{}", wallets.into_iter().collect::<Vec<_>>().join(", "));
+                    }
+                    println!("{}", self.display.layer2_columns(slice::from_ref(&row.layer2)));
+                    if *details {
+                        for (cp, value) in &row.own {
+                            println!(
+                                "\t* {: >12}{unit}\t{}\t{}",
+                                self.display.signed_amount(*value),
+                                if *value < 0 {
+                                    "taken from"
+                                } else if row.operation == OpType::Credit {
+                                    "moved to  "
+                                } else {
+                                    "change    "
+                                },
+                                self.display.derived_addr(cp)
+                            );
+                        }
+                        for (cp, value) in &row.counterparties {
+                            println!(
+                                "\t* {: >12}{unit}\t{}\t{}",
+                                self.display.signed_amount(*value),
+                                if *value > 0 {
+                                    "received  "
+                                } else if row.operation == OpType::Credit {
+                                    "change?   "
+                                } else {
+                                    "paid to   "
+                                },
+                                match (own.counterparty_wallet(cp), wallet.counterparty_contact(cp))
+                                {
+                                    (Some(name), _) => format!("wallet {name}"),
+                                    (None, Some(name)) => format!("contact {name}"),
+                                    (None, None) => self.display.counterparty(cp),
+                                }
+                            );
+                        }
+                        println!(
+                            "\t* {: >12}{unit}\tminer fee",
+                            self.display.signed_amount(-row.fee.sats_i64())
+                        );
+                        if let Some(provenance) = wallet.tx_provenance(row.txid) {
+                            print!(
+                                "\t  source: {} on {}",
+                                provenance.source,
+                                Date::from_timestamp(provenance.acquired)
+                            );
+                            if let Some(synced) = provenance.synced {
+                                print!(", reported by indexer on {}", Date::from_timestamp(synced));
+                            }
+                            println!();
+                        }
+                        println!();
+                    }
+                }
+                if let Some(rates) =
+                    rates.filter(|_| self.changes.perform(s!("save exchange rates cache")))
+                {
+                    rates.store()?;
+                }
+            }
+            BpCommand::Tx { tx } => {
+                println!(
+                    "{}",
+                    serde_yaml::to_string(&tx).expect("unable to generate YAML representation")
+                );
+                let rbf = tx.inputs.iter().any(|input| signals_rbf(input.sequence));
+                println!("# BIP-125 replaceability signalled: {}", if rbf { "yes" } else { "no" });
+            }
+            #[cfg(feature = "tui")]
+            BpCommand::Ui => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = match self.indexer(&config, wallet.indexer()) {
+                    Ok(indexer) => Some(indexer),
+                    Err(ExecError::NoIndexer) => None,
+                    Err(err) => return Err(err),
+                };
+                crate::tui::run(
+                    &mut wallet,
+                    self.general.network,
+                    indexer.as_ref(),
+                    &self.display,
+                )?;
+                return Ok(());
+            }
+            BpCommand::Completions { shell } => {
+                write_completions("bp", *shell, &mut io::stdout())?;
+                return Ok(());
+            }
+            BpCommand::Manpages { dir } => {
+                if !self.changes.perform(format!("write manual pages to {}", dir.display())) {
+                    return Ok(());
+                }
+                for page in write_manpages::<Args<BpCommand, O>>("bp", dir)? {
+                    eprintln!("Written {}", page.display());
+                }
+            }
+            BpCommand::Fees { histogram } => {
+                let indexer = self.indexer(&config, None)?;
+                eprint!("Requesting fee rates from {} ... ", indexer.name());
+                let overview = fee_overview(indexer.fee_snapshot()?);
+                eprintln!("success");
+                let snapshot = &overview.snapshot;
+                println!("Target, blocks\t{:>16}", format!("Fee rate, {}", self.display.fee_unit));
+                for (target, rate) in &snapshot.targets {
+                    println!("{target:>14}\t{:>16}", self.display.fee_rate(*rate));
+                }
+                if snapshot.histogram.is_empty() {
+                    println!("\nMempool fee histogram is not provided by the indexer");
+                    return Ok(());
+                }
+                println!(
+                    "\nMempool size: {} vbytes, ~{} block(s) to clear",
+                    snapshot.mempool_vsize(),
+                    snapshot.mempool_blocks()
+                );
+                for (blocks, rate) in &overview.next_blocks {
+                    println!(
+                        "Minimal fee rate for the next {blocks} block(s): {}",
+                        rate.display(self.display.fee_unit)
+                    );
+                }
+                if *histogram {
+                    println!(
+                        "\n{:>16}\t   Size, vbytes\t  Depth, vbytes",
+                        format!("Fee rate, {}", self.display.fee_unit)
+                    );
+                    for (rate, vsize, depth) in &overview.depth {
+                        println!("{:>16}\t{vsize:>15}\t{depth:>15}", self.display.fee_rate(*rate));
+                    }
+                }
+            }
+            BpCommand::Inspect { psbt } => {
+                let psbt = psbt_read(psbt)?;
+                println!(
+                    "{}",
+                    serde_yaml::to_string(&psbt).expect("unable to generate YAML representation")
+                );
+                // Contacts are reported only for the persisted wallets, since others can't have
+                // them
+                if self.wallet_dir(&config).is_some_and(|dir| dir.exists()) {
+                    let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                    for output in psbt.outputs() {
+                        if let Some(name) = wallet.contact_of(&output.script) {
+                            eprintln!(
+                                "Output #{} pays {} to contact {name}",
+                                output.index(),
+                                self.display.amount_with_unit(output.amount)
+                            );
+                        }
+                    }
+                }
+            }
+            BpCommand::Contact {
+                command:
+                    ContactCommand::Add {
+                        contact,
+                        destination,
+                    },
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if let Contact::Static(address) = destination {
+                    if address.network != AddressNetwork::from(wallet.network()) {
+                        return Err(ExecError::WrongNetwork(*address));
+                    }
+                }
+                if let Some(prev) = wallet.add_contact(contact.to_string(), destination.clone()) {
+                    eprintln!("Contact {contact} is moved from {prev}");
+                }
+                println!("{contact}\t{destination}");
+            }
+            BpCommand::Contact {
+                command: ContactCommand::Remove { contact },
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let name = contact.to_string();
+                let removed =
+                    wallet.remove_contact(&name).ok_or(ExecError::UnknownContact(name.clone()))?;
+                println!("Contact {name} for {removed} is removed");
+            }
+            BpCommand::Contact {
+                command: ContactCommand::List,
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                if wallet.contacts().is_empty() {
+                    println!("no contacts");
+                    return Ok(());
+                }
+                for (name, contact) in wallet.contacts() {
+                    match contact {
+                        Contact::Static(address) => println!("{name}\t{address}"),
+                        Contact::Dynamic(contact) => {
+                            println!("{name}\t{contact}\tnext index {}", contact.next_index)
+                        }
+                    }
+                }
+            }
+            BpCommand::Cache {
+                command:
+                    CacheCommand::AddTx {
+                        tx,
+                        proof,
+                        height,
+                        prev,
+                    },
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let txid = tx.txid();
+                let status = import_tx(&mut wallet, tx.clone(), proof.as_ref().zip(*height), prev)?;
+                match status {
+                    TxStatus::Mined(info) => {
+                        println!("Transaction {txid} mined at height {} is added", info.height)
+                    }
+                    _ => println!("Unconfirmed transaction {txid} is added"),
+                }
+                println!("Wallet balance: {}", self.display.amount_with_unit(wallet.balance()));
+            }
+            BpCommand::Invoice {
+                command:
+                    InvoiceCommand::Create {
+                        amount,
+                        expires_in,
+                        memo,
+                    },
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                let expires = expires_in.map(|secs| now.saturating_add(secs));
+                let address = wallet.create_invoice(*amount, now, expires, memo.clone());
+                println!("{address}");
+            }
+            BpCommand::Invoice {
+                command: InvoiceCommand::List,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or_default();
+                wallet.update_invoices(now);
+                if wallet.invoices().is_empty() {
+                    println!("no invoices");
+                    return Ok(());
+                }
+                println!(
+                    "{:<1$}\t{2:>16}\t{3:>16}\tStatus   \tCreated   \tMemo",
+                    "Address",
+                    self.display.address_width(),
+                    "Amount",
+                    "Received"
+                );
+                for (address, invoice) in wallet.invoices() {
+                    println!(
+                        "{:<1$}\t{2:>16}\t{3:>16}\t{4:<9}\t{5}\t{6}",
+                        self.display.address(address),
+                        self.display.address_width(),
+                        self.display.amount(invoice.amount),
+                        self.display.amount(invoice.received),
+                        invoice.status.to_string(),
+                        Date::from_timestamp(invoice.created),
+                        invoice.memo.as_deref().unwrap_or_default()
+                    );
+                }
+            }
+            BpCommand::Audit {
+                command: AuditCommand::Taproot,
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let audit = wallet.audit_taproot();
+                if audit.is_empty() {
+                    println!("no taproot coins");
+                    return Ok(());
+                }
+                println!("{:<1$}\tTerminal\tStatus", "Outpoint", self.display.outpoint_width());
+                let mut failed = 0usize;
+                for record in &audit {
+                    let status = record.status.to_string();
+                    let status = if record.status.is_verified() {
+                        status.bright_green()
+                    } else {
+                        failed += 1;
+                        status.bright_red()
+                    };
+                    println!(
+                        "{:<3$}\t{:>8}\t{}",
+                        self.display.outpoint(&record.outpoint),
+                        record.terminal.to_string(),
+                        status,
+                        self.display.outpoint_width()
+                    );
+                }
+                if failed > 0 {
+                    return Err(ExecError::TaprootAudit(failed));
+                }
+                println!("All {} taproot coin(s) are key-spend only", audit.len());
+            }
+            BpCommand::Audit {
+                command: AuditCommand::UtxoVerify,
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = self.indexer(&config, wallet.indexer())?;
+                eprintln!("Verifying wallet coins with {}", indexer.name());
+                let statuses = verify_utxos(&wallet, &indexer)?;
+                println!("{:<1$}\tStatus", "Outpoint", self.display.outpoint_width());
+                let mut failed = 0usize;
+                for (outpoint, status) in &statuses {
+                    let status_str = status.to_string();
+                    let status_str = if status.is_unspent() {
+                        status_str.bright_green()
+                    } else {
+                        failed += 1;
+                        status_str.bright_red()
+                    };
+                    println!(
+                        "{:<2$}\t{}",
+                        self.display.outpoint(outpoint),
+                        status_str,
+                        self.display.outpoint_width()
+                    );
+                }
+                if failed > 0 {
+                    return Err(ExecError::UtxoAudit(failed));
+                }
+                println!("All {} coin(s) are unspent", statuses.len());
+            }
+            #[cfg(feature = "remote")]
+            BpCommand::SyncStore { command } => {
+                let (remote, force, push) = match command {
+                    SyncStoreCommand::Push { remote, force } => (remote, *force, true),
+                    SyncStoreCommand::Pull { remote, force } => (remote, *force, false),
+                };
+                let dir = self.wallet_dir(&config).ok_or(ExecError::NotPersisted)?;
+                let location = remote
+                    .remote
+                    .clone()
+                    .or_else(|| config.sync_remote.clone())
+                    .ok_or(ExecError::NoRemote)?;
+                let name = dir.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+                let store = RemoteStore::new(
+                    AnyBackend::with(&location, remote.auth.clone()),
+                    format!("{}.{name}", self.general.network),
+                    remote.password.clone(),
+                );
+                let outcome = match push {
+                    true => store.push(&dir, force, &self.changes)?,
+                    false => store.pull(&dir, force, &self.changes)?,
+                };
+                match outcome {
+                    SyncOutcome::UpToDate => println!("Wallet is up to date with {location}"),
+                    SyncOutcome::Pushed(files) => {
+                        println!("Uploaded {} file(s) to {location}", files.len())
+                    }
+                    SyncOutcome::Pulled {
+                        downloaded,
+                        removed,
+                    } => {
+                        println!("Downloaded {} file(s) from {location}", downloaded.len());
+                        if !removed.is_empty() {
+                            println!("Removed {} file(s) deleted from {location}", removed.len());
+                        }
+                    }
+                }
+            }
+            #[cfg(feature = "signers")]
+            BpCommand::Dev {
+                command: DevCommand::Vectors,
+            } => {
+                let vectors = TestVectors::generate();
+                let seeds = vectors.seeds.iter().map(|seed| {
+                    serde_json::json!({
+                        "name": seed.name,
+                        "seed": seed.seed.to_hex(),
+                        "mainnetMaster": seed.mainnet_master.to_string(),
+                        "testnetMaster": seed.testnet_master.to_string(),
+                    })
+                });
+                let addresses = vectors.addresses.iter().map(|vector| {
+                    serde_json::json!({
+                        "seed": vector.seed,
+                        "scheme": vector.scheme.to_string(),
+                        "derivation": format!("m{}", vector.derivation),
+                        "address": vector.address.to_string(),
+                    })
+                });
+                let psbts = vectors.psbts.iter().map(|vector| {
+                    serde_json::json!({
+                        "seed": vector.seed,
+                        "scheme": vector.scheme.to_string(),
+                        "descriptor": vector.descriptor.to_string(),
+                        "unsigned": vector.unsigned.to_string(),
+                        "sighashes": vector.sighashes.iter().map(|s| s.to_string()).collect::<Vec<_>>(),
+                        "signed": vector.signed.to_string(),
+                        "txid": vector.tx.txid().to_string(),
+                        "tx": format!("{:x}", vector.tx),
+                    })
+                });
+                let json = serde_json::json!({
+                    "seeds": seeds.collect::<Vec<_>>(),
+                    "addresses": addresses.collect::<Vec<_>>(),
+                    "psbts": psbts.collect::<Vec<_>>(),
+                });
+                println!("{json:#}");
+            }
+            BpCommand::Doctor => {
+                let store = match self.wallet_dir(&config) {
+                    Some(dir) if dir.is_dir() => Some(FsTextStore::new(dir)?),
+                    _ => None,
+                };
+                let wallet = match self.bp_wallet::<O::Descr>(&config) {
+                    Ok(wallet) => wallet,
+                    Err(err) => {
+                        // Wallet which can't be loaded most probably has damaged files
+                        let Some(store) = store else { return Err(err) };
+                        let report = files_report(&store);
+                        if report.is_healthy() {
+                            return Err(err);
+                        }
+                        eprintln!("failed: {err}");
+                        print_health_report(&report);
+                        return Err(ExecError::Unhealthy(Severity::Critical));
+                    }
+                };
+                let report = health_report(&wallet, store.as_ref());
+                print_health_report(&report);
+                match report.severity() {
+                    None | Some(Severity::Info) => {}
+                    Some(severity) => return Err(ExecError::Unhealthy(severity)),
+                }
+            }
+            BpCommand::Collab {
+                command: CollabCommand::Init { session, fee_rate },
+            } => {
+                let collab = collab_session(session, *fee_rate);
+                collab.save(session, &self.changes)?;
+                println!("Session {} is created with fee rate {fee_rate}", collab.id);
+            }
+            BpCommand::Collab {
+                command:
+                    CollabCommand::Join {
+                        session,
+                        party,
+                        coins,
+                        outputs,
+                    },
+            } => {
+                let mut collab = CollabSession::load(session)?;
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let (proofs, requests) = collab_proofs(&wallet, &collab, coins)?;
+                for request in &requests {
+                    println!(
+                        "Message for {} ({}):\t{}",
+                        request.outpoint,
+                        request.terminal,
+                        request.message.to_hex()
+                    );
+                }
+                if !requests.is_empty() {
+                    return Err(ExecError::MissingProofs(requests.len()));
+                }
+                let collab_party = collab_party(&mut wallet, &collab, &proofs, outputs)?;
+                let fee = collab.fee_share(&collab_party);
+                collab.join(party, collab_party)?;
+                collab.save(session, &self.changes)?;
+                println!(
+                    "Joined session {} as '{party}' paying fee of up to {fee} sats",
+                    collab.id
+                );
+            }
+            BpCommand::Collab {
+                command:
+                    CollabCommand::Finalize {
+                        session,
+                        psbt: psbt_file,
+                    },
             } => {
+                let collab = CollabSession::load(session)?;
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                println!("Balance of {}", wallet.descriptor());
-                println!("\nHeight\t{:>12}\t{:68}\tAddress", "Amount, ṩ", "Outpoint");
-                for row in wallet.coins() {
+                let mut psbt = collab.to_psbt()?;
+                let own = wallet.complete_collab_psbt(&mut psbt);
+                let parties = collab.parties().len();
+                for (name, party) in collab.parties() {
                     println!(
-                        "{}\t{: >12}\t{:68}\t{}",
-                        row.height, row.amount, row.outpoint, row.address
+                        "{name}\t{} input(s)\t{} output(s)\tfee {} sats",
+                        party.inputs.len(),
+                        party.outputs.len(),
+                        party.fee_share(collab.fee_rate, parties)
                     );
                 }
-                self.command = BpCommand::Balance {
-                    addr: false,
-                    utxo: false,
+                println!(
+                    "Transaction {} spends {own} wallet input(s), paying fee of {} sats",
+                    psbt.txid(),
+                    psbt.fee().unwrap_or_default()
+                );
+                psbt_write(&psbt, psbt_file, &self.changes)?;
+            }
+            BpCommand::Workspace {
+                command: WorkspaceCommand::Init { dir, psbt },
+            } => {
+                let psbt = psbt_read(psbt)?;
+                let workspace = Workspace::init_with_changes(dir, psbt, &self.changes)?;
+                println!(
+                    "Workspace for transaction {} is created; cosigners should put signed PSBTs \
+                     into {}",
+                    workspace.unsigned().txid(),
+                    workspace.dir().join(WORKSPACE_SIGNED).display()
+                );
+            }
+            BpCommand::Workspace {
+                command: WorkspaceCommand::Status { dir },
+            } => {
+                let workspace = Workspace::open_with_changes(dir, &self.changes)?;
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let status = workspace_status(&workspace, wallet.descriptor())?;
+
+                println!("Transaction {}", status.txid);
+                if status.cosigners.is_empty() {
+                    println!("No signed PSBTs are provided yet");
+                } else {
+                    println!("Signed PSBTs are provided by {}", status.cosigners.join(", "));
+                }
+                for (no, input) in status.inputs.iter().enumerate() {
+                    let finalized = if input.finalized { " (finalized)" } else { "" };
+                    println!("\nInput #{no}: {} signature(s){finalized}", input.count());
+                    for (cosigner, signers) in &input.signers {
+                        for fp in signers {
+                            let key = fp
+                                .and_then(|fp| status.keys.get(&fp).map(String::as_str))
+                                .unwrap_or("key not from the wallet descriptor");
+                            println!("\t{cosigner}\t{key}");
+                        }
+                    }
+                }
+                println!();
+
+                match status.final_psbt {
+                    Some(path) => println!(
+                        "Signature threshold is met; the finalized PSBT is saved to {}",
+                        path.display().to_string().bright_green()
+                    ),
+                    None => println!(
+                        "{} input(s) still require more signatures",
+                        status.unsigned_inputs.to_string().bright_yellow()
+                    ),
+                }
+            }
+            BpCommand::Airgap {
+                command: AirgapCommand::Export { dir, psbt },
+            } => {
+                let psbt = psbt_read(psbt)?;
+                let airgap = AirgapDir::open_with_changes(dir, &self.changes)?;
+                let path = airgap.export(&psbt)?;
+                println!(
+                    "Transaction {} is exported to {}; put the signed PSBT into {}",
+                    psbt.txid(),
+                    path.display(),
+                    airgap.dir().join(AIRGAP_SIGNED).display()
+                );
+            }
+            BpCommand::Airgap {
+                command:
+                    AirgapCommand::Import {
+                        dir,
+                        publish,
+                        watch,
+                        interval,
+                    },
+            } => {
+                let airgap = AirgapDir::open_with_changes(dir, &self.changes)?;
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let broadcaster = match *publish || *watch {
+                    true => Some(self.broadcaster(&config, wallet.indexer())?),
+                    false => None,
                 };
-                self.sync = false;
-                self.exec(config, conf_filename)?;
+                // Signed PSBTs which are not fully signed yet are reported in watch mode only
+                // once, unless they get modified.
+                let mut reported = BTreeMap::new();
+                loop {
+                    for signed in airgap.signed()? {
+                        if reported.get(&signed.path) == Some(&signed.modified) {
+                            continue;
+                        }
+                        let mut psbt = signed.psbt.clone();
+                        eprintln!("Processing signed PSBT {}", signed.path.display());
+                        let finalization = finalize_psbt(&mut wallet, &mut psbt);
+                        finalization_print(&finalization);
+                        let Ok(tx) = finalization.tx else {
+                            reported.insert(signed.path, signed.modified);
+                            continue;
+                        };
+                        if let Some(broadcaster) = &broadcaster {
+                            eprint!(
+                                "Publishing transaction {} via {} ... ",
+                                tx.txid(),
+                                endpoints(broadcaster)
+                            );
+                            match publish_tx(&mut wallet, broadcaster, &tx, &self.changes) {
+                                Ok(refused) => {
+                                    eprintln!("success");
+                                    refused_print(refused);
+                                }
+                                // Failed transactions are retried on the next directory check
+                                Err(err) if *watch => {
+                                    eprintln!("{}", err.to_string().bright_red());
+                                    continue;
+                                }
+                                Err(err) => return Err(err),
+                            }
+                        }
+                        let path = airgap.save_tx(&signed, &tx)?;
+                        println!("Transaction {} is saved to {}", tx.txid(), path.display());
+                        wallet.store()?;
+                    }
+                    // Dry run previews a single directory check only
+                    if !*watch || self.changes.is_enabled() {
+                        break;
+                    }
+                    thread::sleep(Duration::from_secs(*interval));
+                }
             }
-            BpCommand::Balance {
-                addr: true,
-                utxo: true,
+            BpCommand::Report {
+                command:
+                    ReportCommand::Fees {
+                        period,
+                        format,
+                        transfers,
+                    },
             } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                println!("Balance of {}", wallet.descriptor());
-                println!("\nHeight\t{:>12}\t{:68}", "Amount, ṩ", "Outpoint");
-                for (derived_addr, utxos) in wallet.address_coins() {
-                    println!("{}\t{}", derived_addr.addr, derived_addr.terminal);
-                    for row in utxos {
-                        println!("{}\t{: >12}\t{:68}", row.height, row.amount, row.outpoint);
+                let own = match transfers {
+                    true => self.own_wallets(wallet.descriptor())?,
+                    false => OwnWallets::new(),
+                };
+                let report = fee_report(&wallet, *period, &own);
+                let mut out = io::BufWriter::new(io::stdout().lock());
+                match format {
+                    None => {
+                        writeln!(
+                            out,
+                            "{:<10}\t   Txs\t{:>12}\tTransfers",
+                            period.to_string(),
+                            format!("Fee, {}", self.display.unit())
+                        )?;
+                        for (bucket, total) in &report.periods {
+                            writeln!(
+                                out,
+                                "{bucket:<10}\t{: >6}\t{: >12}\t{: >9}",
+                                total.txs,
+                                self.display.amount(total.fees),
+                                total.transfers
+                            )?;
+                        }
+                        let fees = self.display.amount_with_unit(report.total);
+                        writeln!(out, "\nTotal fees paid: {fees}")?;
+                    }
+                    Some(ExportFormat::Csv) => {
+                        writeln!(out, "period,start,txs,fees,transfers")?;
+                        for (bucket, total) in &report.periods {
+                            writeln!(
+                                out,
+                                "{bucket},{},{},{},{}",
+                                bucket.start, total.txs, total.fees, total.transfers
+                            )?;
+                        }
+                    }
+                    Some(ExportFormat::Json) => {
+                        write!(out, "[")?;
+                        for (no, (bucket, total)) in report.periods.iter().enumerate() {
+                            let item = serde_json::json!({
+                                "period": bucket.to_string(),
+                                "start": bucket.start.to_string(),
+                                "txs": total.txs,
+                                "fees": total.fees.sats(),
+                                "transfers": total.transfers,
+                            });
+                            write!(out, "{}\n  {item}", if no == 0 { "" } else { "," })?;
+                        }
+                        writeln!(out, "\n]")?;
                     }
-                    println!()
                 }
-                self.command = BpCommand::Balance {
-                    addr: false,
-                    utxo: false,
+                out.flush()?;
+            }
+            BpCommand::Report {
+                command:
+                    ReportCommand::Gains {
+                        currency,
+                        method,
+                        from,
+                        to,
+                        format,
+                    },
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let rates = self.rate_provider(&config)?;
+                let report = gains_report(&wallet, *method, *from, *to, |time| {
+                    rates.historical_rate(currency, time)
+                })?;
+                if self.changes.perform(s!("save exchange rates cache")) {
+                    rates.store()?;
+                }
+                let disposals = &report.disposals;
+                let acquired = |disposal: &Disposal| match disposal.acquired {
+                    Some(time) => Date::from_timestamp(time).to_string(),
+                    None => s!("unknown"),
                 };
-                self.sync = false;
-                self.exec(config, conf_filename)?;
+                let mut out = io::BufWriter::new(io::stdout().lock());
+                match format {
+                    None => {
+                        writeln!(
+                            out,
+                            "Acquired  \tDisposed  \t{:>17}\t{:>13}\t{:>13}\t{:>13}\tTxid",
+                            "Amount, BTC",
+                            format!("Proceeds, {currency}"),
+                            format!("Cost, {currency}"),
+                            format!("Gain, {currency}")
+                        )?;
+                        for disposal in disposals {
+                            writeln!(
+                                out,
+                                "{:<10}\t{}\t{:>17}\t{:>13}\t{:>13}\t{:>13}\t{}",
+                                acquired(disposal),
+                                Date::from_timestamp(disposal.disposed),
+                                Amount::from(disposal.amount).to_btc_string(),
+                                self.display.fiat(disposal.proceeds),
+                                self.display.fiat(disposal.cost),
+                                self.display.fiat(disposal.gain()),
+                                self.display.txid(&disposal.txid)
+                            )?;
+                        }
+                        writeln!(
+                            out,
+                            "\nTotal realized gain: {} {currency}",
+                            self.display.fiat(report.gain)
+                        )?;
+                        writeln!(
+                            out,
+                            "Funds held: {} acquired for {} {currency}",
+                            self.display.amount_with_unit(report.held),
+                            self.display.fiat(report.cost)
+                        )?;
+                    }
+                    Some(ExportFormat::Csv) => {
+                        writeln!(
+                            out,
+                            "Description,Date Acquired,Date Sold,Proceeds,Cost Basis,Gain or Loss"
+                        )?;
+                        for disposal in disposals {
+                            writeln!(
+                                out,
+                                "{} BTC,{},{},{:.2},{:.2},{:.2}",
+                                Amount::from(disposal.amount).to_btc_string(),
+                                acquired(disposal),
+                                Date::from_timestamp(disposal.disposed),
+                                disposal.proceeds,
+                                disposal.cost,
+                                disposal.gain()
+                            )?;
+                        }
+                    }
+                    Some(ExportFormat::Json) => {
+                        write!(out, "[")?;
+                        for (no, disposal) in disposals.iter().enumerate() {
+                            let item = serde_json::json!({
+                                "txid": disposal.txid.to_string(),
+                                "acquired": disposal.acquired.map(|_| acquired(disposal)),
+                                "disposed": Date::from_timestamp(disposal.disposed).to_string(),
+                                "amount": disposal.amount.sats(),
+                                "proceeds": disposal.proceeds,
+                                "cost": disposal.cost,
+                                "gain": disposal.gain(),
+                            });
+                            write!(out, "{}\n  {item}", if no == 0 { "" } else { "," })?;
+                        }
+                        writeln!(out, "\n]")?;
+                    }
+                }
+                out.flush()?;
+                if report.has_unknown_cost() {
+                    eprintln!(
+                        "Warning: some of the disposed funds are not covered by the wallet \
+                         history, so their cost is unknown and taken as zero"
+                    );
+                }
             }
-            BpCommand::History { txid, details } => {
+            BpCommand::Pending {
+                command: PendingCommand::List,
+            } => {
                 let wallet = self.bp_wallet::<O::Descr>(&config)?;
-                println!("History of {}", wallet.descriptor());
+                if wallet.pending().is_empty() {
+                    println!("no pending transactions");
+                    return Ok(());
+                }
+                let unit = self.display.unit();
                 println!(
-                    "\nHeight\t{:<1$}\t    Amount, ṩ\tFee rate, ṩ/vbyte",
+                    "{:<64}\tStatus   \t{:>12}\t{:>12}\tMemo",
                     "Txid",
-                    if *txid { 64 } else { 18 }
+                    format!("Amount, {unit}"),
+                    format!("Fee, {unit}")
                 );
-                let mut rows = wallet.history().collect::<Vec<_>>();
-                rows.sort_by_key(|row| row.height);
-                for row in rows {
+                for (txid, pending) in wallet.pending() {
                     println!(
-                        "{}\t{}\t{}{: >12}\t{: >8.2}",
-                        row.height,
-                        if *txid { row.txid.to_string() } else { format!("{:#}", row.txid) },
-                        row.operation,
-                        row.amount,
-                        row.fee.sats() as f64 * 4.0 / row.weight as f64
+                        "{txid}\t{:<9}\t{: >12}\t{: >12}\t{}",
+                        pending.status,
+                        self.display.amount(pending.amount),
+                        self.display.amount(pending.fee),
+                        wallet.tx_annotation(*txid).unwrap_or_default()
                     );
-                    if *details {
-                        for (cp, value) in &row.own {
-                            println!(
-                                "\t* {value: >-12}ṩ\t{}\t{cp}",
-                                if *value < 0 {
-                                    "taken from"
-                                } else if row.operation == OpType::Credit {
-                                    "moved to  "
-                                } else {
-                                    "change    "
-                                }
-                            );
-                        }
-                        for (cp, value) in &row.counterparties {
-                            println!(
-                                "\t* {value: >-12}ṩ\t{}\t{cp}",
-                                if *value > 0 {
-                                    "received  "
-                                } else if row.operation == OpType::Credit {
-                                    "change?   "
-                                } else {
-                                    "paid to   "
-                                }
-                            );
-                        }
-                        println!("\t* {: >-12}ṩ\tminer fee", -row.fee.sats_i64());
-                        println!();
+                }
+            }
+            BpCommand::Sign {
+                signer,
+                psbt: psbt_path,
+            } => {
+                let signer =
+                    signer.as_ref().or(config.signer.as_ref()).ok_or(SignerError::NotConfigured)?;
+                let psbt = psbt_read(psbt_path)?;
+                eprintln!("Signing transaction {} with {signer}", psbt.txid());
+                if let Some(memo) = psbt.memo() {
+                    eprintln!("Memo: {memo}");
+                }
+                let signed = signer.sign(&psbt)?;
+                if signed.txid() != psbt.txid() {
+                    return Err(SignerError::Failed(
+                        signer.to_string(),
+                        s!("signed PSBT contains a different transaction"),
+                    )
+                    .into());
+                }
+                psbt_write(&signed, psbt_path, &self.changes)?;
+            }
+            BpCommand::Psbt {
+                command: PsbtCommand::Abandon { draft },
+            } => {
+                let txid = match Txid::from_str(draft) {
+                    Ok(txid) => txid,
+                    Err(_) => psbt_read(Path::new(draft))?.txid(),
+                };
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let abandoned = wallet.abandon_draft(txid);
+                match abandoned.change {
+                    None => eprintln!("Transaction {txid} is not a known draft"),
+                    Some(change) if abandoned.index_released => {
+                        eprintln!("Released change derivation index {change}")
                     }
+                    Some(change) => eprintln!(
+                        "Change derivation index {change} can't be released since later indexes \
+                         were already used"
+                    ),
+                }
+                if abandoned.tx_removed {
+                    eprintln!("Removed unmined transaction {txid} from the wallet cache");
                 }
             }
-            BpCommand::Tx { tx } => {
-                println!(
-                    "{}",
-                    serde_yaml::to_string(&tx).expect("unable to generate YAML representation")
+            BpCommand::Psbt {
+                command: PsbtCommand::ToRaw { psbt, tx },
+            } => {
+                let psbt = psbt_read(psbt)?;
+                unsigned_tx_write_or_print(&psbt, tx.as_deref(), &self.changes)?;
+            }
+            BpCommand::Psbt {
+                command:
+                    PsbtCommand::AddInput {
+                        psbt: psbt_file,
+                        outpoint,
+                    },
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut psbt = psbt_read(psbt_file)?;
+                let txid = psbt.txid();
+                wallet.add_input(&mut psbt, *outpoint)?;
+                amend_psbt(&mut wallet, txid, &psbt, Sats::ZERO);
+                psbt_write(&psbt, psbt_file, &self.changes)?;
+            }
+            BpCommand::Psbt {
+                command: PsbtCommand::Fill { psbt: psbt_file },
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let indexer = match self.indexer(&config, wallet.indexer()) {
+                    Ok(indexer) => Some(indexer),
+                    Err(ExecError::NoIndexer) => None,
+                    Err(err) => return Err(err),
+                };
+                let store_path = self.general.base_dir().join(TX_STORE_FILE);
+                let capacity = config.tx_store_size.unwrap_or(TxStore::DEFAULT_CAPACITY);
+                let mut store =
+                    TxStore::load(&store_path, capacity).unwrap_or_else(|_| TxStore::new(capacity));
+                let mut psbt = psbt_read(psbt_file)?;
+                let report = wallet.fill_psbt(&mut psbt, |txid| {
+                    store
+                        .get(&txid)
+                        .cloned()
+                        .or_else(|| indexer.as_ref()?.transaction(txid).ok().flatten())
+                });
+                psbt_write(&psbt, psbt_file, &self.changes)?;
+                eprintln!(
+                    "Filled in spent outputs for {} input(s) and key derivations for {} input(s) \
+                     and output(s)",
+                    report.utxos, report.derivations
                 );
+                for outpoint in report.unknown {
+                    eprintln!("Warning: transaction spent by input {outpoint} is unknown");
+                }
             }
-            BpCommand::Inspect { psbt } => {
+            BpCommand::Psbt {
+                command: PsbtCommand::WhoCanSign { psbt },
+            } => {
+                let wallet = self.bp_wallet::<O::Descr>(&config)?;
                 let psbt = psbt_read(psbt)?;
-                println!(
-                    "{}",
-                    serde_yaml::to_string(&psbt).expect("unable to generate YAML representation")
-                );
+                let mut incomplete = 0usize;
+                for (no, input) in wallet.cosigners(&psbt).iter().enumerate() {
+                    let progress = match input.threshold {
+                        Some(threshold) => format!("{} of {threshold}", input.signed()),
+                        None => format!("{} of ? (unknown threshold)", input.signed()),
+                    };
+                    let status = if input.finalized {
+                        "finalized".to_owned()
+                    } else if input.is_complete() {
+                        "complete".to_owned()
+                    } else {
+                        incomplete += 1;
+                        format!("signed {progress}")
+                    };
+                    let owner = input
+                        .terminal
+                        .map(|terminal| terminal.to_string())
+                        .unwrap_or_else(|| "foreign".to_owned());
+                    println!("Input #{no} {}\t{owner}\t{status}", input.outpoint);
+                    if input.keys.is_empty() {
+                        println!("\tno known keys");
+                    }
+                    for key in &input.keys {
+                        let signed = if key.signed { "signed" } else { "missing" };
+                        println!("\t{}\t{signed}", key.origin);
+                    }
+                }
+                if incomplete > 0 {
+                    eprintln!("{incomplete} input(s) require more signatures");
+                } else {
+                    eprintln!("All inputs have enough signatures");
+                }
+            }
+            BpCommand::Psbt {
+                command:
+                    PsbtCommand::AddOutput {
+                        psbt: psbt_file,
+                        output: (address, amount),
+                    },
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut psbt = psbt_read(psbt_file)?;
+                let txid = psbt.txid();
+                wallet.add_output(&mut psbt, address.script_pubkey(), *amount)?;
+                amend_psbt(&mut wallet, txid, &psbt, *amount);
+                psbt_write(&psbt, psbt_file, &self.changes)?;
             }
             BpCommand::Construct {
                 v2,
-                to: beneficiaries,
-                fee,
+                output_format,
+                to: recipients,
+                well_known,
+                memo,
+                embed_memo,
+                no_rbf,
+                package_fee_boost,
+                deduct_fee,
+                min_conf,
+                allow_self_send,
+                deterministic_seed,
+                change_outputs,
+                verify_change,
+                fee_rate,
+                conf_target,
+                fixed_fee,
+                legacy_fee,
                 psbt: psbt_file,
             } => {
+                let fee_rate = match conf_target {
+                    Some(target) => {
+                        let indexer = self.indexer(&config, None)?;
+                        eprint!("Requesting fee rates from {} ... ", indexer.name());
+                        let snapshot = indexer.fee_snapshot()?;
+                        eprintln!("success");
+                        let rate = snapshot
+                            .target_rate(*target)
+                            .ok_or_else(|| ExecError::NoFeeEstimates(indexer.name().to_owned()))?;
+                        eprintln!(
+                            "Using fee rate of {} for the confirmation within {target} block(s)",
+                            rate.display(self.display.fee_unit)
+                        );
+                        Some(rate)
+                    }
+                    None => *fee_rate,
+                };
+                let flag_fee = fixed_fee.map(FeeSpec::Absolute).or(fee_rate.map(FeeSpec::Rate));
+                let (fee, psbt_file) = match (flag_fee, legacy_fee) {
+                    (Some(fee), file) if psbt_file.is_none() => {
+                        (fee, file.as_ref().map(PathBuf::from))
+                    }
+                    (Some(_), _) => return Err(ExecError::FeeConflict),
+                    (None, Some(legacy)) => {
+                        let fee = parse_fee(legacy)
+                            .map_err(|err| ExecError::InvalidFee(legacy.clone(), err))?;
+                        let flag = match fee {
+                            FeeSpec::Absolute(_) => "--fee",
+                            FeeSpec::Rate(_) => "--fee-rate",
+                        };
+                        eprintln!(
+                            "Warning: the positional fee argument is deprecated and will be \
+                             removed; use `{flag} {legacy}` instead, or `--fee-rate <RATE>` to \
+                             compute the fee from the transaction weight"
+                        );
+                        (fee, psbt_file.clone())
+                    }
+                    (None, None) => return Err(ExecError::NoFee),
+                };
+                let output_format = match output_format {
+                    Some(format) => *format,
+                    None if *v2 => OutputFormat::Psbt2,
+                    None => OutputFormat::Psbt0,
+                };
+                let params = ConstructParams {
+                    recipients: recipients.clone(),
+                    well_known: *well_known,
+                    memo: memo.clone(),
+                    embed_memo: *embed_memo,
+                    no_rbf: *no_rbf,
+                    package_fee_boost: *package_fee_boost,
+                    deduct_fee: deduct_fee.clone(),
+                    min_conf: *min_conf,
+                    fee,
+                    version: match output_format {
+                        OutputFormat::Psbt2 => PsbtVer::V2,
+                        OutputFormat::Psbt0 | OutputFormat::RawTx => PsbtVer::V0,
+                    },
+                    allow_self_send: *allow_self_send,
+                    shuffle: deterministic_seed.clone(),
+                    change_outputs: *change_outputs,
+                    verify_change: *verify_change,
+                };
                 let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let constructed = construct(&mut wallet, self.general.network, &params)?;
 
-                // Do coin selection
-                let total_amount =
-                    beneficiaries.iter().try_fold(Sats::ZERO, |sats, b| match b.amount {
-                        Payment::Max => Err(()),
-                        Payment::Fixed(s) => sats.checked_add(s).ok_or(()),
-                    });
-                let coins: Vec<_> = match total_amount {
-                    Ok(sats) if sats > Sats::ZERO => {
-                        wallet.coinselect(sats + *fee, coinselect::all).collect()
+                for resolved in &constructed.resolved {
+                    eprintln!(
+                        "Resolved {} to {}",
+                        resolved.name,
+                        self.display.address(&resolved.address)
+                    );
+                    eprintln!("  - payment instructions: {}", resolved.uri);
+                    eprintln!("  - obtained from {}", resolved.source);
+                    if let Some(doh) = constructed
+                        .doh_resolver
+                        .as_ref()
+                        .filter(|_| resolved.source == ResolutionSource::Dnssec)
+                    {
+                        eprintln!("  - DNS resolver: {doh}");
+                    }
+                }
+                for derived in &constructed.self_send {
+                    eprintln!(
+                        "Paying to the own address {} at {}",
+                        self.display.address(&derived.addr),
+                        derived.terminal
+                    );
+                }
+                if constructed.aggregation {
+                    eprintln!(
+                        "Warning: you are not paying to anybody but just aggregating all your \
+                         balances to a single UTXO",
+                    );
+                    if constructed.locked > Sats::ZERO {
+                        eprintln!(
+                            "Warning: {} having less than {} confirmation(s) are not aggregated",
+                            self.display.amount_with_unit(constructed.locked),
+                            min_conf.unwrap_or(wallet.min_confirmations())
+                        );
                     }
-                    _ => {
+                }
+                if let Some(fee_rate) = constructed.fee_rate {
+                    eprintln!(
+                        "Fee of {} is deducted at {fee_rate}",
+                        self.display.amount_with_unit(constructed.fee)
+                    );
+                }
+                for change in &constructed.change {
+                    eprintln!(
+                        "Change #{} of {} to {} at {} ({}{})",
+                        change.vout.into_u32(),
+                        self.display.amount_with_unit(change.amount),
+                        self.display.address(&change.address),
+                        change.terminal,
+                        if change.own { "wallet descriptor" } else { "NOT IN THE WALLET" },
+                        if change.verified { ", re-derived" } else { "" }
+                    );
+                    if !change.own {
                         eprintln!(
-                            "Warning: you are not paying to anybody but just aggregating all your \
-                             balances to a single UTXO",
+                            "Warning: the change address is not derived by the wallet descriptor \
+                             and the change may be lost"
                         );
-                        wallet.utxos().map(WalletUtxo::into_outpoint).collect()
                     }
-                };
+                }
+                eprintln!("Proposal hash: {}", constructed.proposal_hash());
+                output_write_or_print(
+                    &constructed.psbt,
+                    output_format,
+                    psbt_file.as_deref(),
+                    &self.changes,
+                )?;
 
-                // TODO: Support lock time and RBFs
-                let params = TxParams::with(*fee);
-                let (mut psbt, _) = wallet.construct_psbt(coins, beneficiaries, params)?;
-                psbt.version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
-                psbt_write_or_print(&psbt, psbt_file.as_deref())?;
+                if let Some((child, meta)) = &constructed.child {
+                    eprintln!(
+                        "Child transaction {} spends parent change {} with additional fee {}",
+                        child.txid(),
+                        meta.anchor,
+                        self.display.amount(meta.child_fee)
+                    );
+                    let child_file = psbt_file.as_deref().map(|path| suffixed_path(path, "-child"));
+                    output_write_or_print(
+                        child,
+                        output_format,
+                        child_file.as_deref(),
+                        &self.changes,
+                    )?;
+                }
+            }
+            BpCommand::Sweep {
+                v2,
+                to_wallet,
+                to_descriptor,
+                no_rbf,
+                allow_self_send,
+                fee,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let mut target_wallet = None;
+                let mut target_descr = None;
+                match (to_wallet, to_descriptor) {
+                    (Some(name), _) => {
+                        let name = name.to_string();
+                        let dir = self.general.wallet_dir(&name);
+                        if !dir.is_dir() {
+                            return Err(ExecError::WalletNotFound(name));
+                        }
+                        let provider = self.store_factory(&config).open(dir)?;
+                        target_wallet =
+                            Some(Wallet::<XpubDerivable, O::Descr>::load(provider, false)?);
+                    }
+                    (None, Some(descr)) => {
+                        target_descr = Some(Wallet::<XpubDerivable, StdDescr>::new_layer1(
+                            descr.clone(),
+                            wallet.network(),
+                        ));
+                    }
+                    (None, None) => unreachable!("clap requires the sweep target"),
+                }
+                let params = SweepParams {
+                    fee: *fee,
+                    no_rbf: *no_rbf,
+                    allow_self_send: *allow_self_send,
+                    version: if *v2 { PsbtVer::V2 } else { PsbtVer::V0 },
+                };
+                let next_target = || match (&mut target_wallet, &mut target_descr) {
+                    (Some(target), _) => target.next_address(Keychain::OUTER, true),
+                    (None, Some(target)) => target.next_address(Keychain::OUTER, true),
+                    (None, None) => unreachable!("the sweep target is created"),
+                };
+                let swept = sweep(&mut wallet, next_target, &params)?;
+                if swept.locked > Sats::ZERO {
+                    eprintln!(
+                        "Warning: {} having less than {} confirmation(s) are not swept",
+                        self.display.amount_with_unit(swept.locked),
+                        swept.min_conf
+                    );
+                }
+                let count = swept.txs.len();
+                for (no, tx) in swept.txs.iter().enumerate() {
+                    eprintln!(
+                        "Transaction {} sweeps {} to {} paying {} of fee",
+                        tx.psbt.txid(),
+                        self.display.amount_with_unit(tx.amount),
+                        self.display.address(&tx.target),
+                        self.display.amount_with_unit(tx.fee)
+                    );
+                    let file = psbt_file.as_deref().map(|path| match count {
+                        1 => path.to_owned(),
+                        _ => suffixed_path(path, &format!("-{}", no + 1)),
+                    });
+                    psbt_write_or_print(&tx.psbt, file.as_deref(), &self.changes)?;
+                }
+                if let Some(mut target) = target_wallet {
+                    target.store()?;
+                }
+            }
+            BpCommand::BumpFee {
+                v2,
+                txid,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                let (psbt, meta) = bump_fee(&mut wallet, *txid, *fee_rate, version)?;
+                eprintln!(
+                    "Transaction {} replaces {txid} paying {} of fee; the change is reduced to {}",
+                    psbt.txid(),
+                    self.display.amount_with_unit(meta.fee),
+                    self.display.amount_with_unit(meta.change)
+                );
+                psbt_write_or_print(&psbt, psbt_file.as_deref(), &self.changes)?;
+            }
+            BpCommand::Cpfp {
+                v2,
+                txid,
+                fee_rate,
+                psbt: psbt_file,
+            } => {
+                let mut wallet = self.bp_wallet::<O::Descr>(&config)?;
+                let version = if *v2 { PsbtVer::V2 } else { PsbtVer::V0 };
+                let (psbt, meta) = construct_cpfp(&mut wallet, *txid, *fee_rate, version)?;
+                eprintln!(
+                    "Transaction {} spends {} paying {} of fee, which brings {} transaction(s) to \
+                     {}",
+                    psbt.txid(),
+                    meta.anchor,
+                    self.display.amount_with_unit(meta.fee),
+                    meta.ancestors.len() + 1,
+                    meta.package_fee_rate
+                );
+                psbt_write_or_print(&psbt, psbt_file.as_deref(), &self.changes)?;
             }
         };
 
@@ -535,6 +3281,39 @@ impl<O: DescriptorOpts> Exec for Args<BpCommand, O> {
     }
 }
 
+/// Appends the suffix to the file name, keeping the file extension.
+fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// Moves wallet records of the amended PSBT to its new transaction id, updating the pending
+/// ledger with the new fee and the `added` amount paid to beneficiaries.
+fn amend_psbt<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    txid: Txid,
+    psbt: &Psbt,
+    added: Sats,
+) {
+    let new_txid = psbt.txid();
+    wallet.rekey_tx(txid, new_txid);
+    eprintln!("Transaction id changed from {txid} to {new_txid}");
+    match psbt.fee() {
+        Some(fee) => {
+            if let Some(pending) = wallet.pending().get(&new_txid).copied() {
+                wallet.amend_pending(new_txid, pending.amount + added, fee);
+            }
+            eprintln!("Transaction fee: {fee} sats");
+        }
+        None => eprintln!("Warning: inputs don't cover the outputs; add more inputs to the PSBT"),
+    }
+}
+
 fn psbt_read(psbt_path: &Path) -> Result<Psbt, ExecError> {
     eprint!("Reading PSBT from file {} ... ", psbt_path.display());
     let mut psbt_file = File::open(psbt_path)?;
@@ -543,18 +3322,21 @@ fn psbt_read(psbt_path: &Path) -> Result<Psbt, ExecError> {
     Ok(psbt)
 }
 
-fn psbt_write(psbt: &Psbt, psbt_path: &Path) -> Result<(), ExecError> {
+fn psbt_write(psbt: &Psbt, psbt_path: &Path, changes: &DryRun) -> Result<(), ExecError> {
     eprint!("Saving PSBT to file {} ... ", psbt_path.display());
-    let mut psbt_file = File::create(psbt_path)?;
-    psbt.encode(psbt.version, &mut psbt_file)?;
+    changes.write(psbt_path, psbt.serialize(psbt.version))?;
     eprintln!("success");
     Ok(())
 }
 
-fn psbt_write_or_print(psbt: &Psbt, psbt_path: Option<&Path>) -> Result<(), ExecError> {
+fn psbt_write_or_print(
+    psbt: &Psbt,
+    psbt_path: Option<&Path>,
+    changes: &DryRun,
+) -> Result<(), ExecError> {
     match psbt_path {
         Some(file_name) => {
-            psbt_write(psbt, file_name)?;
+            psbt_write(psbt, file_name, changes)?;
         }
         None => match psbt.version {
             PsbtVer::V0 => println!("{psbt}"),
@@ -564,42 +3346,85 @@ fn psbt_write_or_print(psbt: &Psbt, psbt_path: Option<&Path>) -> Result<(), Exec
     Ok(())
 }
 
-fn psbt_finalize<D: Descriptor<K, V>, K, V>(
-    psbt: &mut Psbt,
-    descriptor: &D,
+fn unsigned_tx_write_or_print(
+    psbt: &Psbt,
+    tx_path: Option<&Path>,
+    changes: &DryRun,
+) -> Result<(), ExecError> {
+    let tx = Tx::from(psbt.to_unsigned_tx());
+    match tx_path {
+        Some(file_name) => {
+            eprint!("Saving unsigned transaction to file {} ... ", file_name.display());
+            changes.write(file_name, format!("{tx:x}\n"))?;
+            eprintln!("success");
+        }
+        None => println!("{tx:x}"),
+    }
+    Ok(())
+}
+
+fn output_write_or_print(
+    psbt: &Psbt,
+    format: OutputFormat,
+    path: Option<&Path>,
+    changes: &DryRun,
 ) -> Result<(), ExecError> {
-    eprint!("Finalizing PSBT ... ");
-    let inputs = psbt.finalize(descriptor);
+    match format {
+        OutputFormat::RawTx => unsigned_tx_write_or_print(psbt, path, changes),
+        OutputFormat::Psbt0 | OutputFormat::Psbt2 => psbt_write_or_print(psbt, path, changes),
+    }
+}
+
+fn finalization_print(finalization: &Finalization) {
+    let Some(finalized) = finalization.finalized else {
+        eprintln!("The PSBT is already finalized");
+        return;
+    };
     eprint!(
-        "{} of {} inputs were finalized",
-        inputs.to_string().bright_green(),
-        psbt.inputs().count()
+        "Finalizing PSBT ... {} of {} inputs were finalized",
+        finalized.to_string().bright_green(),
+        finalization.inputs
     );
-    if psbt.is_finalized() {
+    if finalization.tx.is_ok() {
         eprintln!(", transaction is ready for the extraction");
     } else {
         eprintln!(" and some non-finalized inputs remains");
     }
-    Ok(())
 }
 
-fn psbt_extract(psbt: &Psbt, publish: bool, tx: Option<&Path>) -> Result<Tx, ExecError> {
+fn endpoints(broadcaster: &BroadcastFanout<AnyIndexer>) -> String {
+    broadcaster.endpoints().collect::<Vec<_>>().join(", ")
+}
+
+fn refused_print(refused: Vec<BroadcastResult<AnyIndexerError>>) {
+    for res in refused {
+        if let Err(err) = res.result {
+            eprintln!("Warning: {} has refused the transaction: {err}", res.endpoint);
+        }
+    }
+}
+
+fn tx_write_or_print(
+    tx: Result<Tx, UnfinalizedInputs>,
+    publish: bool,
+    path: Option<&Path>,
+    changes: &DryRun,
+) -> Result<Tx, ExecError> {
     eprint!("Extracting signed transaction ... ");
-    match psbt.extract() {
+    match tx {
         Ok(extracted) => {
             eprintln!("success");
-            if !publish && tx.is_none() {
+            if !publish && path.is_none() {
                 println!("{extracted}");
             }
-            if let Some(file) = tx {
+            if let Some(file) = path {
                 eprint!("Saving transaction to file {} ...", file.display());
-                let mut file = File::create(file)?;
-                extracted.consensus_encode(&mut file)?;
+                changes.write(file, extracted.consensus_serialize())?;
                 eprintln!("success");
             }
             Ok(extracted)
         }
-        Err(e) if publish || tx.is_some() => {
+        Err(e) if publish || path.is_some() => {
             eprintln!(
                 "PSBT still contains {} non-finalized inputs, failing to extract transaction",
                 e.0.to_string().bright_red()
@@ -612,3 +3437,21 @@ fn psbt_extract(psbt: &Psbt, publish: bool, tx: Option<&Path>) -> Result<Tx, Exe
         }
     }
 }
+
+fn print_health_report(report: &HealthReport) {
+    for (check, issue) in &report.issues {
+        let severity = format!("{:>8}", issue.severity.to_string().to_uppercase());
+        let severity = match issue.severity {
+            Severity::Info => severity.bright_blue(),
+            Severity::Warning => severity.bright_yellow(),
+            Severity::Critical => severity.bright_red(),
+        };
+        println!("{severity}  [{check}] {}", issue.problem);
+        println!("{:>8}  fix: {}", "", issue.action);
+    }
+    if report.is_healthy() {
+        println!("No problems found by {} check(s)", report.checks);
+    } else {
+        println!("{} problem(s) found by {} check(s)", report.issues.len(), report.checks);
+    }
+}
@@ -0,0 +1,99 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fs, io};
+
+use bpstd::Network;
+use clap::CommandFactory;
+use clap_complete::env::Shells;
+use clap_complete::{CompleteEnv, CompletionCandidate, Shell};
+
+use crate::cli::{GeneralOpts, DATA_DIR, DATA_DIR_ENV};
+
+/// Environment variable used by the shell to request argument completions from the binary.
+pub const COMPLETE_ENV: &str = "COMPLETE";
+
+/// Completes command-line arguments and exits the process if the binary is called by the shell
+/// completion script (see [`write_completions`]). Otherwise does nothing.
+///
+/// Must be called at the very beginning of `main`, before the arguments are parsed.
+pub fn complete<C: CommandFactory>() {
+    CompleteEnv::with_factory(C::command).var(COMPLETE_ENV).complete()
+}
+
+/// Writes shell script registering completion of the binary arguments. Argument values are
+/// completed dynamically, by calling the binary, so the completions follow the wallet data.
+pub fn write_completions(bin: &str, shell: Shell, out: &mut dyn io::Write) -> io::Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&shell.to_string())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, shell.to_string()))?;
+    completer.write_registration(COMPLETE_ENV, bin, bin, bin, out)
+}
+
+/// Writes manual pages for the binary and all its commands to the directory, returning the
+/// list of the written files.
+pub fn write_manpages<C: CommandFactory>(
+    bin: &'static str,
+    dir: &Path,
+) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    clap_mangen::generate_to(C::command().name(bin), dir)?;
+    let mut pages = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "1") {
+            pages.push(path);
+        }
+    }
+    pages.sort();
+    Ok(pages)
+}
+
+/// Provides names of the wallets for completion of the wallet name arguments.
+///
+/// Since the completion happens before the arguments are parsed, the data directory and the
+/// network are taken from the environment variables or defaults, and not from the command line.
+pub fn wallet_names() -> Vec<CompletionCandidate> {
+    let mut general = GeneralOpts {
+        data_dir: env::var_os(DATA_DIR_ENV).map(PathBuf::from).unwrap_or(DATA_DIR.into()),
+        network: env::var("LNPBP_NETWORK")
+            .ok()
+            .and_then(|network| Network::from_str(&network).ok())
+            .unwrap_or(Network::Testnet3),
+        no_prefix: false,
+    };
+    general.process();
+    let Ok(dir) = fs::read_dir(general.base_dir()) else {
+        return vec![];
+    };
+    let mut names = dir
+        .flatten()
+        .filter(|entry| entry.metadata().is_ok_and(|meta| meta.is_dir()))
+        .map(|entry| entry.file_name())
+        .collect::<Vec<OsString>>();
+    names.sort();
+    names.into_iter().map(CompletionCandidate::new).collect()
+}
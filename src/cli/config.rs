@@ -20,26 +20,94 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Hooks, SignerBackend};
+use crate::indexers::TlsOpts;
+use crate::DryRun;
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(crate = "serde_crate", rename_all = "camelCase")]
 pub struct Config {
     pub default_wallet: String,
+
+    #[serde(default)]
+    pub hooks: Hooks,
+
+    /// TOML file with static exchange rates; if not given, the rates are taken from
+    /// mempool.space.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rates_file: Option<PathBuf>,
+
+    /// Signer backend used by `sign` command when it is not given explicitly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<SignerBackend>,
+
+    /// TLS options for indexer servers, keyed by the server URL.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tls: BTreeMap<String, TlsOpts>,
+
+    /// Maximal number of transactions kept in the data directory transaction store, which
+    /// prevents re-downloading them on each sync.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_store_size: Option<usize>,
+
+    /// Always mask amounts in the human-readable output, like with `--discreet` argument.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub discreet: bool,
+
+    /// Character separating thousands in amounts, like with `--thousands-sep` argument.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thousands_sep: Option<char>,
+
+    /// Always show amounts in BTC, like with `--btc` argument.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub display_btc: bool,
+
+    /// Number of decimal digits of the amounts shown in BTC, like with `--precision` argument.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub btc_precision: Option<u8>,
+
+    /// Compress wallet caches with zstd, reducing the size of large caches on disk and in the
+    /// backups. Compressed caches are detected on load regardless of this setting.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cache_compression: bool,
+
+    /// Remote storage used by `sync-store` command when it is not given explicitly.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync_remote: Option<String>,
+
+    /// Esplora-compatible servers the published transactions are also broadcast to, in addition
+    /// to the servers given with `--broadcast` arguments.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub broadcast: Vec<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             default_wallet: s!("default"),
+            hooks: none!(),
+            rates_file: None,
+            signer: None,
+            tls: none!(),
+            tx_store_size: None,
+            discreet: false,
+            thousands_sep: None,
+            display_btc: false,
+            btc_precision: None,
+            cache_compression: false,
+            sync_remote: None,
+            broadcast: vec![],
         }
     }
 }
 
 impl Config {
-    pub fn load(conf_path: &Path) -> Self {
+    pub fn load(conf_path: &Path, changes: &DryRun) -> Self {
         fs::read_to_string(conf_path)
             .map_err(|err| {
                 error!("Unable to read config file: {err:?}");
@@ -52,12 +120,13 @@ impl Config {
             .unwrap_or_else(|_| {
                 eprintln!("Unable to find or parse config file; using config defaults");
                 let conf = Config::default();
-                conf.store(conf_path);
+                conf.store(conf_path, changes);
                 conf
             })
     }
 
-    pub fn store(&self, conf_path: &Path) {
-        fs::write(conf_path, toml::to_string(self).expect("config must convert to TOML")).ok();
+    pub fn store(&self, conf_path: &Path, changes: &DryRun) {
+        let conf = toml::to_string(self).expect("config must convert to TOML");
+        changes.write(conf_path, conf).ok();
     }
 }
@@ -0,0 +1,380 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Formatting of addresses and identifiers in the command-line output.
+
+use std::fmt::Display;
+
+use bpstd::{Address, AddressPayload, DerivedAddr, KeyOrigin, Outpoint, Sats, Txid};
+
+use crate::{Counterparty, FeeRate, FeeUnit, Layer2Columns};
+
+/// Text replacing amounts in the discreet mode, see [`DisplayOpts::discreet`].
+pub const DISCREET_MASK: &str = "****";
+
+const BTC_DECIMALS: u8 = 8;
+
+/// Style of shortening long identifiers in tables.
+#[derive(ValueEnum, Copy, Clone, PartialEq, Eq, Hash, Debug, Default, Display)]
+#[display(lowercase)]
+pub enum Truncation {
+    /// Always print identifiers in full
+    Full,
+
+    /// Keep the first and the last 8 characters, replacing the rest with `..`
+    #[default]
+    Middle,
+
+    /// Keep the first 16 characters, followed by `..`
+    End,
+}
+
+impl Truncation {
+    /// Width of the identifiers truncated with this style, or `None` for the full style.
+    pub fn width(self) -> Option<usize> {
+        match self {
+            Truncation::Full => None,
+            Truncation::Middle | Truncation::End => Some(18),
+        }
+    }
+
+    pub fn apply(self, s: &str) -> String {
+        let len = s.chars().count();
+        match self.width() {
+            Some(width) if len > width => {}
+            _ => return s.to_owned(),
+        }
+        match self {
+            Truncation::Full => unreachable!(),
+            Truncation::Middle => {
+                let head = s.chars().take(8).collect::<String>();
+                let tail = s.chars().skip(len - 8).collect::<String>();
+                format!("{head}..{tail}")
+            }
+            Truncation::End => format!("{}..", s.chars().take(16).collect::<String>()),
+        }
+    }
+}
+
+#[derive(Args, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct DisplayOpts {
+    /// Print bech32 addresses in uppercase, which produces more compact QR codes
+    #[arg(long, global = true)]
+    pub uppercase: bool,
+
+    /// Print addresses as `bitcoin:` URIs
+    #[arg(long, global = true)]
+    pub uri: bool,
+
+    /// Style of shortening transaction ids in tables
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub truncate: Truncation,
+
+    /// Unit for fee rates, either `sat/vb` or `sat/wu`
+    #[arg(long, global = true, default_value_t)]
+    pub fee_unit: FeeUnit,
+
+    /// Mask amounts in the human-readable output, for instance when the screen is seen by
+    /// others. Can be also enabled with `discreet` option of the configuration file.
+    ///
+    /// Machine-readable output (PSBTs, transactions, JSON and CSV exports) is not affected.
+    #[arg(long, global = true)]
+    pub discreet: bool,
+
+    /// Separate thousands in amounts with the given character (`,` if no character is given).
+    /// Can be also set with `thousands_sep` option of the configuration file.
+    ///
+    /// With `.` as the thousands separator `,` is used as the decimal separator.
+    #[arg(
+        long,
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = ",",
+        value_name = "CHAR"
+    )]
+    pub thousands_sep: Option<char>,
+
+    /// Show amounts in BTC instead of satoshis. Can be also enabled with `display_btc` option of
+    /// the configuration file
+    #[arg(long, global = true)]
+    pub btc: bool,
+
+    /// Number of decimal digits of the amounts shown in BTC, up to 8. Can be also set with
+    /// `btc_precision` option of the configuration file
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIGITS",
+        value_parser = clap::value_parser!(u8).range(0..=8)
+    )]
+    pub precision: Option<u8>,
+}
+
+impl DisplayOpts {
+    /// Formats address according to the casing and URI options. Base58 addresses are
+    /// case-sensitive and are never uppercased.
+    pub fn address(&self, addr: &Address) -> String {
+        let bech32 = matches!(
+            addr.payload,
+            AddressPayload::Wpkh(_) | AddressPayload::Wsh(_) | AddressPayload::Tr(_)
+        );
+        let s = match self.uri {
+            true => format!("bitcoin:{addr}"),
+            false => addr.to_string(),
+        };
+        if self.uppercase && bech32 {
+            s.to_uppercase()
+        } else {
+            s
+        }
+    }
+
+    /// Formats address followed by its derivation terminal.
+    pub fn derived_addr(&self, derived: &DerivedAddr) -> String {
+        format!("{}{}", self.address(&derived.addr), derived.terminal)
+    }
+
+    /// Formats key origins of an address, separating origins of multi-key descriptors with
+    /// commas.
+    pub fn key_origins(&self, origins: &[KeyOrigin]) -> String {
+        origins.iter().map(|origin| format!("[{origin}]")).collect::<Vec<_>>().join(",")
+    }
+
+    pub fn counterparty(&self, counterparty: &Counterparty) -> String {
+        match counterparty {
+            Counterparty::Address(addr) => self.address(addr),
+            _ => counterparty.to_string(),
+        }
+    }
+
+    /// Formats transaction id for a table cell, according to the truncation style.
+    pub fn txid(&self, txid: &Txid) -> String { self.truncate.apply(&txid.to_string()) }
+
+    /// Formats outpoint for a table cell, truncating its transaction id.
+    pub fn outpoint(&self, outpoint: &Outpoint) -> String {
+        format!("{}:{}", self.txid(&outpoint.txid), outpoint.vout)
+    }
+
+    /// Formats fee rate for a table cell, without the unit suffix.
+    pub fn fee_rate(&self, fee_rate: FeeRate) -> String {
+        format!("{:#}", fee_rate.display(self.fee_unit))
+    }
+
+    /// Formats amount in satoshis or BTC, with the thousands separators, masking it in the
+    /// discreet mode.
+    pub fn amount(&self, sats: Sats) -> String { self.mask(self.format_amount(sats.sats(), false)) }
+
+    /// Formats signed amount like [`Self::amount`].
+    pub fn signed_amount(&self, sats: i64) -> String {
+        self.mask(self.format_amount(sats.unsigned_abs(), sats < 0))
+    }
+
+    /// Formats amount like [`Self::amount`], followed by the unit name.
+    pub fn amount_with_unit(&self, sats: Sats) -> String {
+        let unit = if self.btc { "BTC" } else { "sats" };
+        format!("{} {unit}", self.amount(sats))
+    }
+
+    /// Symbol of the amount unit, used in the table headers.
+    pub fn unit(&self) -> &'static str {
+        if self.btc {
+            "₿"
+        } else {
+            "ṩ"
+        }
+    }
+
+    fn format_amount(&self, sats: u64, negative: bool) -> String {
+        let precision = self.precision.unwrap_or(BTC_DECIMALS) as u32;
+        let (int, frac) = if self.btc {
+            let scale = 10u64.pow(BTC_DECIMALS as u32 - precision);
+            let rounded = sats / scale + (sats % scale >= scale.div_ceil(2)) as u64;
+            let denom = 10u64.pow(precision);
+            (rounded / denom, Some(rounded % denom))
+        } else {
+            (sats, None)
+        };
+        let digits = int.to_string();
+        let mut s = String::with_capacity(digits.len() + 12);
+        if negative {
+            s.push('-');
+        }
+        for (pos, digit) in digits.chars().enumerate() {
+            if pos > 0 && (digits.len() - pos) % 3 == 0 {
+                if let Some(sep) = self.thousands_sep {
+                    s.push(sep);
+                }
+            }
+            s.push(digit);
+        }
+        if let Some(frac) = frac.filter(|_| precision > 0) {
+            s.push(if self.thousands_sep == Some('.') { ',' } else { '.' });
+            s.push_str(&format!("{frac:0width$}", width = precision as usize));
+        }
+        s
+    }
+
+    /// Formats fiat amount with two decimal digits, masking it in the discreet mode.
+    pub fn fiat(&self, value: f64) -> String { self.mask(format!("{value:.2}")) }
+
+    /// Formats headers of the columns contributed by layer 2 data, each prefixed with a tab.
+    pub fn layer2_headers<T: Layer2Columns>(&self) -> String {
+        T::COLUMNS.iter().map(|column| format!("\t{}", column.header)).collect()
+    }
+
+    /// Formats values of the columns contributed by layer 2 data, each prefixed with a tab.
+    /// Values of multiple items, like several assets allocated to the same coin, are joined
+    /// with commas. Amount columns are masked in the discreet mode.
+    pub fn layer2_columns<T: Layer2Columns>(&self, items: &[T]) -> String {
+        let values = items.iter().map(T::column_values).collect::<Vec<_>>();
+        T::COLUMNS
+            .iter()
+            .enumerate()
+            .map(|(idx, column)| {
+                let value = values
+                    .iter()
+                    .filter_map(|values| values.get(idx))
+                    .map(|value| if column.amount { self.mask(value) } else { value.clone() })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                format!("\t{value}")
+            })
+            .collect()
+    }
+
+    fn mask(&self, value: impl Display) -> String {
+        match self.discreet {
+            true => DISCREET_MASK.to_owned(),
+            false => value.to_string(),
+        }
+    }
+
+    /// Width of transaction id table column.
+    pub fn txid_width(&self) -> usize { self.truncate.width().unwrap_or(64) }
+
+    /// Width of outpoint table column.
+    pub fn outpoint_width(&self) -> usize { self.txid_width() + 4 }
+
+    /// Width of address table column.
+    pub fn address_width(&self) -> usize {
+        if self.uri {
+            70
+        } else {
+            62
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_address_formatting() {
+        let addr = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        let opts = DisplayOpts {
+            uppercase: true,
+            uri: true,
+            truncate: Truncation::Middle,
+            fee_unit: FeeUnit::SatPerVb,
+            discreet: false,
+            thousands_sep: None,
+            btc: false,
+            precision: None,
+        };
+        assert_eq!(opts.address(&addr), "BITCOIN:BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ");
+        let legacy = Address::from_str("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap();
+        assert_eq!(opts.address(&legacy), "bitcoin:1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2");
+    }
+
+    #[test]
+    fn test_discreet() {
+        let mut opts = DisplayOpts::default();
+        assert_eq!(opts.amount(Sats(1500)), "1500");
+        assert_eq!(opts.signed_amount(-1500), "-1500");
+        assert_eq!(opts.fiat(12.345), "12.35");
+        opts.discreet = true;
+        assert_eq!(opts.amount(Sats(1500)), DISCREET_MASK);
+        assert_eq!(opts.signed_amount(-1500), DISCREET_MASK);
+        assert_eq!(format!("{: >6}", opts.fiat(12.345)), "  ****");
+    }
+
+    #[test]
+    fn test_amount_formatting() {
+        let mut opts = DisplayOpts {
+            thousands_sep: Some(','),
+            ..default!()
+        };
+        assert_eq!(opts.amount(Sats(123_456_789)), "123,456,789");
+        assert_eq!(opts.amount(Sats(999)), "999");
+        assert_eq!(opts.signed_amount(-1_500), "-1,500");
+        assert_eq!(opts.amount_with_unit(Sats(1_000)), "1,000 sats");
+
+        opts.btc = true;
+        assert_eq!(opts.amount(Sats(123_456_789_000)), "1,234.56789000");
+        assert_eq!(opts.signed_amount(-1_500), "-0.00001500");
+        opts.precision = Some(2);
+        assert_eq!(opts.amount(Sats(123_456_789)), "1.23");
+        assert_eq!(opts.amount(Sats(99_999_999)), "1.00");
+        assert_eq!(opts.amount_with_unit(Sats(150_000_000)), "1.50 BTC");
+        opts.precision = Some(0);
+        assert_eq!(opts.amount(Sats(150_000_000)), "2");
+
+        opts.thousands_sep = Some('.');
+        opts.precision = None;
+        assert_eq!(opts.amount(Sats(123_456_789_000)), "1.234,56789000");
+    }
+
+    #[test]
+    fn test_layer2_columns() {
+        use crate::{Layer2Column, Layer2Empty};
+
+        struct Allocation(&'static str, u64);
+        impl Layer2Columns for Allocation {
+            const COLUMNS: &'static [Layer2Column] =
+                &[Layer2Column::new("Asset"), Layer2Column::amount("Asset amount")];
+            fn column_values(&self) -> Vec<String> { vec![self.0.to_owned(), self.1.to_string()] }
+        }
+
+        let mut opts = DisplayOpts::default();
+        let coins = [Allocation("usdt", 10), Allocation("eurt", 20)];
+        assert_eq!(opts.layer2_headers::<Allocation>(), "\tAsset\tAsset amount");
+        assert_eq!(opts.layer2_columns(&coins), "\tusdt, eurt\t10, 20");
+        assert_eq!(opts.layer2_columns::<Allocation>(&[]), "\t\t");
+        opts.discreet = true;
+        assert_eq!(opts.layer2_columns(&coins[..1]), "\tusdt\t****");
+        assert_eq!(opts.layer2_headers::<Layer2Empty>(), "");
+        assert_eq!(opts.layer2_columns(&[Layer2Empty]), "");
+    }
+
+    #[test]
+    fn test_truncation() {
+        let s = "cca7507897abc89628f450e8b1e0c6fca4ec3f7b34cccf55f3f531c659ff4d79";
+        assert_eq!(Truncation::Full.apply(s), s);
+        assert_eq!(Truncation::Middle.apply(s), "cca75078..59ff4d79");
+        assert_eq!(Truncation::End.apply(s), "cca7507897abc896..");
+        assert_eq!(Truncation::Middle.apply("short"), "short");
+    }
+}
@@ -0,0 +1,237 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::process::{Command, ExitStatus, Stdio};
+
+use bpstd::{Address, Sats, Txid};
+
+use crate::{BlockHeight, Invoice, InvoiceStatus, TxStatus, WalletTx};
+
+/// External commands executed when wallet events are detected during the sync.
+///
+/// Each command is run with the system shell; the event details are passed both as `BP_*`
+/// environment variables and as a JSON object written to the command standard input.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct Hooks {
+    /// Command to run when a new incoming transaction is detected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_receive: Option<String>,
+
+    /// Command to run when a previously unconfirmed transaction gets mined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_confirm: Option<String>,
+
+    /// Command to run when an invoice gets paid or its payment gets mined.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_invoice: Option<String>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase", tag = "event")]
+pub enum WalletEvent {
+    /// New transaction increasing the wallet balance.
+    #[display("receive")]
+    Receive {
+        txid: Txid,
+        amount: Sats,
+        status: TxStatus<BlockHeight>,
+    },
+
+    /// Previously unconfirmed transaction was mined.
+    #[display("confirm")]
+    Confirm { txid: Txid, height: BlockHeight },
+
+    /// Invoice is paid (possibly with unmined transactions) by the transaction `txid`.
+    #[display("invoice")]
+    Invoice {
+        txid: Txid,
+        address: Address,
+        received: Sats,
+        status: InvoiceStatus,
+    },
+}
+
+#[derive(serde::Serialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct HookPayload<'a> {
+    wallet: &'a str,
+    #[serde(flatten)]
+    event: &'a WalletEvent,
+}
+
+impl WalletEvent {
+    /// Compares the set of transactions known before the sync (with their statuses) with the
+    /// transactions known after the sync and reports the detected events.
+    pub fn detect(known: &BTreeMap<Txid, TxStatus>, txs: &BTreeMap<Txid, WalletTx>) -> Vec<Self> {
+        let mut events = vec![];
+        for (txid, tx) in txs {
+            match known.get(txid) {
+                None => {
+                    let received = tx
+                        .outputs
+                        .iter()
+                        .filter(|out| out.is_ourself())
+                        .map(|out| out.value)
+                        .sum::<Sats>();
+                    let spent = tx
+                        .inputs
+                        .iter()
+                        .filter(|inp| inp.is_ourself())
+                        .map(|inp| inp.value)
+                        .sum::<Sats>();
+                    if received > spent {
+                        events.push(WalletEvent::Receive {
+                            txid: *txid,
+                            amount: received - spent,
+                            status: tx.status.map(|info| info.height),
+                        });
+                    }
+                }
+                Some(status) if !status.is_mined() => {
+                    if let TxStatus::Mined(info) = tx.status {
+                        events.push(WalletEvent::Confirm {
+                            txid: *txid,
+                            height: info.height,
+                        });
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+        events
+    }
+
+    /// Reports event for the invoice which status has changed, if the invoice got paid.
+    pub fn with_invoice(address: Address, invoice: &Invoice) -> Option<Self> {
+        if !matches!(invoice.status, InvoiceStatus::Pending | InvoiceStatus::Confirmed) {
+            return None;
+        }
+        Some(WalletEvent::Invoice {
+            txid: *invoice.payments.last()?,
+            address,
+            received: invoice.received,
+            status: invoice.status,
+        })
+    }
+
+    pub fn txid(&self) -> Txid {
+        match self {
+            WalletEvent::Receive { txid, .. }
+            | WalletEvent::Confirm { txid, .. }
+            | WalletEvent::Invoice { txid, .. } => *txid,
+        }
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![("BP_EVENT", self.to_string()), ("BP_TXID", self.txid().to_string())];
+        match self {
+            WalletEvent::Receive { amount, status, .. } => {
+                env.push(("BP_AMOUNT", amount.to_string()));
+                if let TxStatus::Mined(height) = status {
+                    env.push(("BP_HEIGHT", height.to_string()));
+                }
+            }
+            WalletEvent::Confirm { height, .. } => env.push(("BP_HEIGHT", height.to_string())),
+            WalletEvent::Invoice {
+                address,
+                received,
+                status,
+                ..
+            } => {
+                env.push(("BP_ADDRESS", address.to_string()));
+                env.push(("BP_AMOUNT", received.to_string()));
+                env.push(("BP_STATUS", status.to_string()));
+            }
+        }
+        env
+    }
+}
+
+impl Hooks {
+    pub fn is_empty(&self) -> bool {
+        self.on_receive.is_none() && self.on_confirm.is_none() && self.on_invoice.is_none()
+    }
+
+    pub fn command_for(&self, event: &WalletEvent) -> Option<&str> {
+        match event {
+            WalletEvent::Receive { .. } => self.on_receive.as_deref(),
+            WalletEvent::Confirm { .. } => self.on_confirm.as_deref(),
+            WalletEvent::Invoice { .. } => self.on_invoice.as_deref(),
+        }
+    }
+
+    /// Runs hook commands for each of the events. Failures of the hook commands are reported,
+    /// but do not abort processing of the rest of the events.
+    pub fn run(&self, wallet: &str, events: &[WalletEvent]) {
+        for event in events {
+            let Some(cmd) = self.command_for(event) else {
+                continue;
+            };
+            match run_hook(cmd, wallet, event) {
+                Ok(status) if status.success() => {
+                    debug!("Hook `{cmd}` for {event} event of {} succeeded", event.txid())
+                }
+                Ok(status) => {
+                    warn!(
+                        "Hook `{cmd}` for {event} event of {} failed with {status}",
+                        event.txid()
+                    );
+                    eprintln!("Warning: {event} hook has failed with {status}");
+                }
+                Err(err) => {
+                    warn!("Unable to execute hook `{cmd}`: {err}");
+                    eprintln!("Warning: unable to execute {event} hook: {err}");
+                }
+            }
+        }
+    }
+}
+
+fn run_hook(cmd: &str, wallet: &str, event: &WalletEvent) -> io::Result<ExitStatus> {
+    #[cfg(windows)]
+    let mut command = {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(cmd);
+        command
+    };
+    #[cfg(not(windows))]
+    let mut command = {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(cmd);
+        command
+    };
+    let mut child =
+        command.env("BP_WALLET", wallet).envs(event.env()).stdin(Stdio::piped()).spawn()?;
+
+    let payload = serde_json::to_string(&HookPayload { wallet, event })
+        .expect("hook payload must serialize to JSON");
+    if let Some(mut stdin) = child.stdin.take() {
+        // The hook may not read its input, so a broken pipe is not an error
+        stdin.write_all(payload.as_bytes()).ok();
+    }
+    child.wait()
+}
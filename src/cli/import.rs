@@ -0,0 +1,373 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Import of wallets exported by other wallet software.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use bpstd::{base58, Address, Network, Outpoint, Txid, XkeyParseError, XpubDerivable};
+use descriptors::{Descriptor, StdDescr, TrKey, Wpkh};
+use serde_json::Value;
+
+use crate::{Layer2, Wallet};
+
+/// Version bytes of standard mainnet extended public keys (`xpub`).
+const XPUB_MAINNET_MAGIC: [u8; 4] = [0x04, 0x88, 0xB2, 0x1E];
+/// Version bytes of standard testnet extended public keys (`tpub`).
+const XPUB_TESTNET_MAGIC: [u8; 4] = [0x04, 0x35, 0x87, 0xCF];
+/// SLIP-132 version bytes of native segwit mainnet extended public keys (`zpub`).
+const ZPUB_MAGIC: [u8; 4] = [0x04, 0xB2, 0x47, 0x46];
+/// SLIP-132 version bytes of native segwit testnet extended public keys (`vpub`).
+const VPUB_MAGIC: [u8; 4] = [0x04, 0x5F, 0x1C, 0xF6];
+/// SLIP-132 version bytes of nested segwit mainnet extended public keys (`ypub`).
+const YPUB_MAGIC: [u8; 4] = [0x04, 0x9D, 0x7C, 0xB2];
+/// SLIP-132 version bytes of nested segwit testnet extended public keys (`upub`).
+const UPUB_MAGIC: [u8; 4] = [0x04, 0x4A, 0x52, 0x62];
+
+/// Wallet software which export files can be imported.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[display(lowercase)]
+pub enum ImportSource {
+    /// Sparrow output descriptor export, or wallet export in Specter JSON format.
+    Sparrow,
+    /// Electrum wallet file (which must not be encrypted).
+    Electrum,
+    /// Bitcoin Core descriptor wallet dump, produced by `bitcoin-cli listdescriptors`.
+    Core,
+}
+
+/// Errors importing wallet exported by other wallet software.
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ImportError {
+    /// the file is not a valid JSON document - {0}
+    #[from]
+    Json(serde_json::Error),
+
+    /// the file doesn't contain a wallet descriptor or extended public key.
+    NoDescriptor,
+
+    /// '{0}' wallets are not supported; only single-signature wpkh and tr wallets can be
+    /// imported.
+    Unsupported(String),
+
+    /// extended public key doesn't provide information about the key origin; the import file
+    /// must contain master key fingerprint.
+    NoOrigin,
+
+    /// invalid extended public key - {0}
+    #[from]
+    Key(XkeyParseError),
+
+    /// invalid extended public key encoding - {0:?}
+    #[from]
+    Base58(base58::Error),
+
+    /// invalid BIP-329 label record '{0}'.
+    Label(String),
+
+    /// extended public keys of the imported wallet don't match the {0} network.
+    WrongNetwork(Network),
+}
+
+/// Labels for transactions, addresses and transaction outputs exported by other wallet
+/// software.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct WalletLabels {
+    pub tx: BTreeMap<Txid, String>,
+    pub addr: BTreeMap<Address, String>,
+    pub txout: BTreeMap<Outpoint, String>,
+}
+
+impl WalletLabels {
+    /// Parses labels in BIP-329 JSON lines format. Records of the types other than `tx`, `addr`
+    /// and `output`, as well as records without a label, are ignored.
+    pub fn from_bip329(s: &str) -> Result<Self, ImportError> {
+        let mut labels = WalletLabels::default();
+        for line in s.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let record = serde_json::from_str::<Value>(line)?;
+            let (Some(ty), Some(reference)) = (record["type"].as_str(), record["ref"].as_str())
+            else {
+                return Err(ImportError::Label(line.to_owned()));
+            };
+            let Some(label) = record["label"].as_str().filter(|label| !label.is_empty()) else {
+                continue;
+            };
+            match ty {
+                "tx" | "addr" | "output" if !labels.insert(reference, label.to_owned()) => {
+                    return Err(ImportError::Label(line.to_owned()));
+                }
+                _ => {}
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Adds a label for the given transaction id, address or outpoint, returning `false` if
+    /// the reference is neither of them.
+    fn insert(&mut self, reference: &str, label: String) -> bool {
+        if let Ok(txid) = Txid::from_str(reference) {
+            self.tx.insert(txid, label);
+        } else if let Ok(outpoint) = Outpoint::from_str(reference) {
+            self.txout.insert(outpoint, label);
+        } else if let Ok(addr) = Address::from_str(reference) {
+            self.addr.insert(addr, label);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Adds labels from another set, replacing the existing labels for the same references.
+    pub fn extend(&mut self, other: WalletLabels) {
+        self.tx.extend(other.tx);
+        self.addr.extend(other.addr);
+        self.txout.extend(other.txout);
+    }
+
+    pub fn len(&self) -> usize { self.tx.len() + self.addr.len() + self.txout.len() }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    /// Adds all the labels to the wallet annotations, replacing the existing ones.
+    pub fn apply<K, D: Descriptor<K>, L2: Layer2>(self, wallet: &mut Wallet<K, D, L2>) {
+        for (txid, label) in self.tx {
+            wallet.annotate_tx(txid, label);
+        }
+        for (addr, label) in self.addr {
+            wallet.annotate_addr(addr, label);
+        }
+        for (outpoint, label) in self.txout {
+            wallet.annotate_txout(outpoint, label);
+        }
+    }
+}
+
+/// Wallet imported from an export file of other wallet software.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ImportedWallet {
+    pub descriptor: StdDescr,
+    pub labels: WalletLabels,
+}
+
+impl ImportedWallet {
+    /// Parses export file content produced by the given wallet software.
+    pub fn parse(source: ImportSource, data: &str) -> Result<Self, ImportError> {
+        match source {
+            ImportSource::Sparrow => Self::from_sparrow(data),
+            ImportSource::Electrum => Self::from_electrum(&serde_json::from_str(data)?),
+            ImportSource::Core => Self::from_core(&serde_json::from_str(data)?),
+        }
+    }
+
+    /// Whether the wallet keys are testnet keys.
+    pub fn is_testnet(&self) -> bool { self.descriptor.keys().any(|key| key.xpub().is_testnet()) }
+
+    fn from_sparrow(data: &str) -> Result<Self, ImportError> {
+        if !data.trim_start().starts_with('{') {
+            // Output descriptor export: comments followed by descriptors, where the first one
+            // covers both receive and change keychains.
+            let descriptor = data
+                .lines()
+                .map(str::trim)
+                .find(|line| !line.is_empty() && !line.starts_with('#'))
+                .ok_or(ImportError::NoDescriptor)?;
+            return Ok(ImportedWallet {
+                descriptor: parse_descriptor(descriptor)?,
+                labels: none!(),
+            });
+        }
+        let json = serde_json::from_str::<Value>(data)?;
+        match json["descriptor"].as_str() {
+            Some(descriptor) => Ok(ImportedWallet {
+                descriptor: parse_descriptor(descriptor)?,
+                labels: none!(),
+            }),
+            // Sparrow also exports wallets in Electrum format
+            None => Self::from_electrum(&json),
+        }
+    }
+
+    fn from_electrum(json: &Value) -> Result<Self, ImportError> {
+        match json["wallet_type"].as_str() {
+            Some("standard") | None => {}
+            Some(other) => return Err(ImportError::Unsupported(other.to_owned())),
+        }
+        let keystore = &json["keystore"];
+        let xpub = keystore["xpub"].as_str().ok_or(ImportError::NoDescriptor)?;
+        let fp = keystore["root_fingerprint"].as_str().ok_or(ImportError::NoOrigin)?;
+        let derivation = keystore["derivation"].as_str().ok_or(ImportError::NoOrigin)?;
+        let path = derivation.trim_start_matches('m').trim_start_matches('/');
+
+        let (xpub, segwit) = standard_xpub(xpub)?;
+        let key = XpubDerivable::from_str(&format!("[{fp}/{path}]{xpub}/<0;1>/*"))?;
+        // Electrum uses SLIP-132 versions to distinguish segwit wallets; otherwise the script
+        // type is defined by the BIP-43 purpose.
+        let purpose = path.split('/').next().unwrap_or_default();
+        let descriptor = match purpose.trim_end_matches(['h', 'H', '\'']) {
+            _ if segwit => Wpkh::from(key).into(),
+            "84" => Wpkh::from(key).into(),
+            "86" => TrKey::from(key).into(),
+            _ => return Err(ImportError::Unsupported(s!("pkh"))),
+        };
+
+        let mut labels = WalletLabels::default();
+        if let Some(map) = json["labels"].as_object() {
+            for (reference, label) in map {
+                if let Some(label) = label.as_str().filter(|label| !label.is_empty()) {
+                    labels.insert(reference, label.to_owned());
+                }
+            }
+        }
+        Ok(ImportedWallet { descriptor, labels })
+    }
+
+    fn from_core(json: &Value) -> Result<Self, ImportError> {
+        let descriptors = json["descriptors"].as_array().ok_or(ImportError::NoDescriptor)?;
+        let active = descriptors
+            .iter()
+            .filter(|d| d["active"].as_bool() == Some(true))
+            .filter(|d| d["internal"].as_bool() != Some(true))
+            .filter_map(|d| d["desc"].as_str())
+            .collect::<Vec<_>>();
+        // Wallets created by Bitcoin Core have active descriptors for all script types; we
+        // prefer segwit v0 over taproot as the default Core address type.
+        let descriptor = active
+            .iter()
+            .find(|d| d.starts_with("wpkh("))
+            .or_else(|| active.iter().find(|d| d.starts_with("tr(")))
+            .or(active.first())
+            .ok_or(ImportError::NoDescriptor)?;
+        Ok(ImportedWallet {
+            descriptor: parse_descriptor(descriptor)?,
+            labels: none!(),
+        })
+    }
+}
+
+/// Parses single-signature `wpkh` or `tr` descriptor in the form used by other wallet software,
+/// i.e. with an optional checksum, `'` hardened derivation marks, and possibly with a receive
+/// keychain only.
+pub(crate) fn parse_descriptor(s: &str) -> Result<StdDescr, ImportError> {
+    let s = s.trim();
+    let s = s.split_once('#').map(|(descr, _)| descr).unwrap_or(s);
+    let (script, inner) = s
+        .split_once('(')
+        .and_then(|(script, rest)| Some((script, rest.strip_suffix(')')?)))
+        .ok_or_else(|| ImportError::Unsupported(s.to_owned()))?;
+    if !matches!(script, "wpkh" | "tr") || inner.contains([',', '(']) {
+        return Err(ImportError::Unsupported(script.to_owned()));
+    }
+    let key = parse_key(inner)?;
+    Ok(match script {
+        "wpkh" => Wpkh::from(key).into(),
+        _ => TrKey::from(key).into(),
+    })
+}
+
+/// Parses descriptor key, converting SLIP-132 extended keys to the standard ones and expanding
+/// receive-only derivation into both receive and change keychains.
+fn parse_key(s: &str) -> Result<XpubDerivable, ImportError> {
+    let (origin, rest) = s.split_once(']').ok_or(ImportError::NoOrigin)?;
+    let (xpub, terminal) = rest.split_once('/').unwrap_or((rest, "<0;1>/*"));
+    let (xpub, _) = standard_xpub(xpub)?;
+    let terminal = match terminal {
+        "0/*" | "1/*" => "<0;1>/*",
+        other => other,
+    };
+    let key = format!("{origin}]{xpub}/{terminal}").replace('\'', "h");
+    XpubDerivable::from_str(&key).map_err(ImportError::from)
+}
+
+/// Converts SLIP-132 encoded extended public key into the standard `xpub`/`tpub` encoding.
+/// Returns the converted key together with the flag whether the original key version signalled
+/// a native segwit wallet.
+fn standard_xpub(s: &str) -> Result<(String, bool), ImportError> {
+    let mut data = base58::decode_check(s)?;
+    if data.len() < 4 {
+        return Err(base58::Error::TooShort(data.len()).into());
+    }
+    let version = [data[0], data[1], data[2], data[3]];
+    let (magic, segwit) = match version {
+        XPUB_MAINNET_MAGIC | XPUB_TESTNET_MAGIC => return Ok((s.to_owned(), false)),
+        ZPUB_MAGIC => (XPUB_MAINNET_MAGIC, true),
+        VPUB_MAGIC => (XPUB_TESTNET_MAGIC, true),
+        YPUB_MAGIC | UPUB_MAGIC => return Err(ImportError::Unsupported(s!("sh(wpkh)"))),
+        other => return Err(base58::Error::InvalidExtendedKeyVersion(other).into()),
+    };
+    data[..4].copy_from_slice(&magic);
+    Ok((base58::encode_check(&data), segwit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TPUB: &str = "tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2";
+
+    fn expected() -> StdDescr {
+        let key = XpubDerivable::from_str(&format!("[643a7adc/84h/1h/0h]{TPUB}/<0;1>/*")).unwrap();
+        Wpkh::from(key).into()
+    }
+
+    #[test]
+    fn test_core() {
+        let dump = format!(
+            r#"{{"wallet_name": "core", "descriptors": [
+                {{"desc": "tr([643a7adc/86'/1'/0']{TPUB}/0/*)#00000000", "active": true, "internal": false}},
+                {{"desc": "wpkh([643a7adc/84'/1'/0']{TPUB}/1/*)#00000000", "active": true, "internal": true}},
+                {{"desc": "wpkh([643a7adc/84'/1'/0']{TPUB}/0/*)#00000000", "active": true, "internal": false}}
+            ]}}"#
+        );
+        let imported = ImportedWallet::parse(ImportSource::Core, &dump).unwrap();
+        assert_eq!(imported.descriptor, expected());
+        assert!(imported.is_testnet());
+        assert!(imported.labels.is_empty());
+    }
+
+    #[test]
+    fn test_electrum() {
+        let mut data = base58::decode_check(TPUB).unwrap();
+        data[..4].copy_from_slice(&VPUB_MAGIC);
+        let vpub = base58::encode_check(&data);
+        let txid = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+        let wallet = format!(
+            r#"{{"wallet_type": "standard", "keystore": {{"type": "bip32", "xpub": "{vpub}",
+                "derivation": "m/84'/1'/0'", "root_fingerprint": "643a7adc"}},
+                "labels": {{"{txid}": "salary", "{txid}:1": "change", "unknown": "ignored"}}}}"#
+        );
+        let imported = ImportedWallet::parse(ImportSource::Electrum, &wallet).unwrap();
+        assert_eq!(imported.descriptor, expected());
+        assert_eq!(imported.labels.len(), 2);
+
+        let bip329 = format!(
+            "{{\"type\": \"tx\", \"ref\": \"{txid}\", \"label\": \"bonus\"}}\n{{\"type\": \
+             \"xpub\", \"ref\": \"{TPUB}\", \"label\": \"account\"}}\n"
+        );
+        let mut labels = imported.labels;
+        labels.extend(WalletLabels::from_bip329(&bip329).unwrap());
+        assert_eq!(labels.tx[&Txid::from_str(txid).unwrap()], "bonus");
+        assert_eq!(labels.len(), 2);
+    }
+}
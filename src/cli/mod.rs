@@ -25,12 +25,41 @@ mod opts;
 mod args;
 mod config;
 mod command;
+mod hooks;
+mod display;
+mod signer;
+mod ops;
+mod completion;
+mod import;
 
-pub use args::{Args, Exec};
-pub use command::{BpCommand, Command, ExecError};
+pub use args::{Args, CliWallet, Exec, TX_STORE_FILE};
+#[cfg(feature = "signers")]
+pub use command::DevCommand;
+#[cfg(feature = "remote")]
+pub use command::SyncStoreCommand;
+pub use command::{
+    AddressCommand, AirgapCommand, AuditCommand, BpCommand, Command, ContactCommand,
+    DescriptorCommand, ExecError, ExportFormat, KeychainCommand, OutputFormat, PendingCommand,
+    PsbtCommand, ReportCommand, WorkspaceCommand,
+};
+pub use completion::{complete, wallet_names, write_completions, write_manpages, COMPLETE_ENV};
 pub use config::Config;
+pub use display::{DisplayOpts, Truncation};
+pub use hooks::{Hooks, WalletEvent};
+pub use import::{ImportError, ImportSource, ImportedWallet, WalletLabels};
 pub use loglevel::LogLevel;
+pub use ops::{
+    balance_at, bump_fee, clone_wallet, collab_party, collab_proofs, collab_session, construct,
+    construct_cpfp, create_wallet, derive_addresses, fee_overview, fee_report, files_report,
+    finalize_psbt, gains_report, health_report, import_tx, list_wallets, own_wallets, publish_tx,
+    sweep, verify_utxos, workspace_status, ChangeOutput, ConstructParams, Constructed, FeeOverview,
+    FeeReport, Finalization, GainsReport, HistoricalBalance, ProofRequest, SweepParams, Swept,
+    SweptTx, WalletBackup, WalletEntry, WorkspaceStatus,
+};
+#[cfg(feature = "remote")]
+pub use opts::RemoteOpts;
 pub use opts::{
     DescrStdOpts, DescriptorOpts, GeneralOpts, ResolverOpt, WalletOpts, DATA_DIR, DATA_DIR_ENV,
-    DEFAULT_ELECTRUM, DEFAULT_ESPLORA,
+    DEFAULT_ELECTRUM, DEFAULT_ESPLORA, DEFAULT_MEMPOOL,
 };
+pub use signer::{InvalidSigner, SignerBackend, SignerError};
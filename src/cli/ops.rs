@@ -0,0 +1,1256 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Library entry points of the `bp` commands.
+//!
+//! Functions here perform the commands on the provided wallets and return structured results,
+//! never printing to the terminal or terminating the process. This allows embedding the commands
+//! into other command-line tools, leaving to them the formatting of the results; the [`Exec`]
+//! implementations of `bp` are one of such formatters.
+//!
+//! [`Exec`]: crate::cli::Exec
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use amplify::hex::ToHex;
+use amplify::Bytes32;
+use bpstd::{
+    Address, AddressNetwork, DerivedAddr, IdxBase, Keychain, Network, NormalIndex, Outpoint, Sats,
+    ScriptPubkey, Terminal, Tx, Txid, Vout, XpubDerivable, XpubFp,
+};
+use descriptors::Descriptor;
+use psbt::{Beneficiary, Payment, Psbt, PsbtConstructor, PsbtMeta, PsbtVer, UnfinalizedInputs};
+use sha2::{Digest, Sha256};
+
+use crate::cli::{ExecError, GeneralOpts};
+use crate::fs::FsTextStore;
+use crate::{
+    check_dust, coinselect, rederive_script, standardness, AddressReuse, BlockHeight,
+    BroadcastFanout, BroadcastResult, BumpMeta, ChangeSplitter, CollabError, CollabInput,
+    CollabOutput, CollabParty, CollabSession, Confirmations, CpfpMeta, Date, DescriptorFp,
+    DescriptorValidity, Disposal, DryRun, FeeDeductor, FeeEstimator, FeeParams, FeeRate,
+    FeeSnapshot, FeeSpec, FeeTotal, HealthCheck, HealthReport, IndexGap, Indexer, InputSignatures,
+    Layer2, LotMethod, MerkleBlock, MiningInfo, NetworkMatch, NoLayer2, OutpointStatus, OwnWallets,
+    OwnershipProof, PackageConstructor, PackageMeta, PaymentResolver, PaymentSplitter,
+    PendingStatus, PendingTx, Period, PeriodBucket, PsbtMemo, Recipient, ResolveError,
+    ResolvedPayment, ShuffleSeed, StaleSync, TaprootKeys, TxStatus, TxStore, Wallet, WalletStore,
+    WalletStoreFactory, WalletSweeper, WalletUtxo, Workspace, DEFAULT_DOH_RESOLVER, SEQ_NO_NO_RBF,
+    SEQ_NO_RBF,
+};
+
+/// Wallet found in the data directory, see [`list_wallets`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct WalletEntry {
+    pub name: String,
+    pub is_default: bool,
+    /// Wallet descriptor, or `None` if the wallet is broken and its descriptor can't be loaded.
+    pub descriptor: Option<String>,
+}
+
+/// Lists named wallets kept in the data directory. Returns `None` if the data directory is not
+/// initialized.
+pub fn list_wallets<D, F>(
+    general: &GeneralOpts,
+    default: &str,
+    factory: &F,
+) -> Result<Option<Vec<WalletEntry>>, ExecError>
+where
+    D: Descriptor,
+    F: WalletStoreFactory,
+    F::Store: WalletStore<XpubDerivable, D, NoLayer2>,
+{
+    let dir = match fs::read_dir(general.base_dir()) {
+        Ok(dir) => dir,
+        Err(err) => {
+            error!("Error reading wallet directory: {err:?}");
+            return Ok(None);
+        }
+    };
+    let mut wallets = vec![];
+    for entry in dir.flatten() {
+        if !entry.metadata().is_ok_and(|meta| meta.is_dir()) {
+            continue;
+        }
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| ExecError::InvalidWalletName(name.to_string_lossy().into_owned()))?;
+        let provider = factory.open(entry.path())?;
+        let descriptor = match Wallet::<XpubDerivable, D>::load(provider, true) {
+            Ok(wallet) => Some(wallet.descriptor().to_string()),
+            Err(err) => {
+                error!("Error loading wallet descriptor: {err}");
+                None
+            }
+        };
+        wallets.push(WalletEntry {
+            is_default: name == default,
+            name,
+            descriptor,
+        });
+    }
+    Ok(Some(wallets))
+}
+
+/// Indexes scripts of the named wallets kept in the data directory, except the wallet with the
+/// given descriptor, for detecting transfers between them. Wallets which can't be loaded are
+/// skipped.
+pub fn own_wallets<D, F>(
+    general: &GeneralOpts,
+    factory: &F,
+    except: &D,
+) -> Result<OwnWallets, ExecError>
+where
+    D: Descriptor,
+    F: WalletStoreFactory,
+    F::Store: WalletStore<XpubDerivable, D, NoLayer2>,
+{
+    let mut own = OwnWallets::new();
+    let dir = match fs::read_dir(general.base_dir()) {
+        Ok(dir) => dir,
+        Err(err) => {
+            error!("Error reading wallet directory: {err:?}");
+            return Ok(own);
+        }
+    };
+    let except = except.to_string();
+    for entry in dir.flatten() {
+        if !entry.metadata().is_ok_and(|meta| meta.is_dir()) {
+            continue;
+        }
+        let name = entry
+            .file_name()
+            .into_string()
+            .map_err(|name| ExecError::InvalidWalletName(name.to_string_lossy().into_owned()))?;
+        let provider = factory.open(entry.path())?;
+        match Wallet::<XpubDerivable, D>::load(provider, false) {
+            Ok(wallet) if wallet.descriptor().to_string() == except => {}
+            Ok(wallet) => own.add(name, &wallet),
+            Err(err) => error!("Error loading wallet {name}: {err}"),
+        }
+    }
+    Ok(own)
+}
+
+/// Makes the wallet persistent under the given name, optionally switching it into the static
+/// address mode, and saves it.
+pub fn create_wallet<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    provider: impl WalletStore<K, D, L2>,
+    name: String,
+    static_address: Option<Terminal>,
+) -> Result<(), ExecError> {
+    wallet.set_static_terminal(static_address).map_err(ExecError::UnknownKeychain)?;
+    wallet.make_persistent(provider, true)?;
+    wallet.set_name(name);
+    wallet.store()?;
+    Ok(())
+}
+
+/// Copies a named wallet under a new name, optionally without the data retrieved from the
+/// indexer.
+pub fn clone_wallet<D, F>(
+    general: &GeneralOpts,
+    factory: &F,
+    name: &str,
+    new_name: &str,
+    without_cache: bool,
+) -> Result<(), ExecError>
+where
+    D: Descriptor,
+    F: WalletStoreFactory,
+    F::Store: WalletStore<XpubDerivable, D, NoLayer2>,
+{
+    let src = general.wallet_dir(name);
+    let dst = general.wallet_dir(new_name);
+    if !src.is_dir() {
+        return Err(ExecError::WalletNotFound(name.to_owned()));
+    }
+    if dst.exists() {
+        return Err(ExecError::WalletExists(new_name.to_owned()));
+    }
+    let mut wallet = Wallet::<XpubDerivable, D>::load(factory.open(src)?, false)?;
+    if without_cache {
+        wallet.reset_cache();
+    }
+    wallet.make_persistent(factory.open(dst)?, true)?;
+    wallet.set_name(new_name.to_owned());
+    wallet.store_all()?;
+    Ok(())
+}
+
+/// Information required to restore a wallet, see [`WalletBackup::with`].
+///
+/// Displays as a TOML document.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct WalletBackup {
+    pub name: String,
+    pub network: Network,
+    /// Height of the first wallet transaction, if any.
+    pub birthday: Option<BlockHeight>,
+    pub descriptor: String,
+}
+
+impl WalletBackup {
+    pub fn with<K, D: Descriptor<K>, L2: Layer2>(name: &str, wallet: &Wallet<K, D, L2>) -> Self {
+        WalletBackup {
+            name: name.to_owned(),
+            network: wallet.network(),
+            birthday: wallet.birthday(),
+            descriptor: wallet.descriptor().to_string(),
+        }
+    }
+}
+
+impl Display for WalletBackup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Wallet '{}' backup", self.name)?;
+        writeln!(f, "network = \"{}\"", self.network)?;
+        match self.birthday {
+            Some(height) => writeln!(f, "birthday = {height}")?,
+            None => writeln!(f, "# birthday is unknown: the wallet has no mined transactions")?,
+        }
+        writeln!(f, "descriptor = \"{}\"", self.descriptor)
+    }
+}
+
+/// Derives `count` addresses on the keychain starting from the given index or, if absent, from
+/// the next unused index, which is shifted when `shift` is set. In the static address mode only
+/// the static address of the keychain is returned.
+pub fn derive_addresses<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    keychain: Keychain,
+    index: Option<NormalIndex>,
+    shift: bool,
+    count: usize,
+) -> Result<Vec<DerivedAddr>, ExecError> {
+    if !wallet.keychains().contains(&keychain) {
+        return Err(ExecError::UnknownKeychain(keychain));
+    }
+    let is_static =
+        index.is_none() && wallet.static_terminal().is_some_and(|t| t.keychain == keychain);
+    let count = if is_static { 1 } else { count };
+    let index = index.unwrap_or_else(|| wallet.next_derivation_index(keychain, shift));
+    Ok(wallet.addresses(keychain).skip(index.index() as usize).take(count).collect())
+}
+
+/// Result of finalizing a PSBT, see [`finalize_psbt`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Finalization {
+    /// Number of inputs finalized by the call, or `None` if the PSBT was already finalized.
+    pub finalized: Option<usize>,
+    /// Total number of the PSBT inputs.
+    pub inputs: usize,
+    /// Signed transaction extracted from the PSBT, or the number of inputs which are still not
+    /// finalized.
+    pub tx: Result<Tx, UnfinalizedInputs>,
+}
+
+/// Finalizes PSBT inputs which are not finalized yet and extracts the signed transaction.
+///
+/// Once the transaction is extracted, its memo, draft registration and pending ledger entry are
+/// re-keyed by the final transaction id, taking the memo from the PSBT if the wallet doesn't
+/// have it, and the pending transaction gets marked as signed.
+pub fn finalize_psbt<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    psbt: &mut Psbt,
+) -> Finalization {
+    let finalized = (!psbt.is_finalized()).then(|| psbt.finalize(wallet.descriptor()));
+    let tx = psbt.extract();
+    if let Ok(tx) = &tx {
+        let txid = tx.txid();
+        wallet.rekey_tx(psbt.txid(), txid);
+        wallet.advance_pending(txid, PendingStatus::Signed);
+        if wallet.tx_annotation(txid).is_none() {
+            if let Some(memo) = psbt.memo() {
+                wallet.annotate_tx(txid, memo);
+            }
+        }
+    }
+    Finalization {
+        finalized,
+        inputs: psbt.inputs().count(),
+        tx,
+    }
+}
+
+/// Publishes signed transaction to all endpoints of the broadcaster, marking it as broadcast in
+/// the wallet pending ledger once at least one of them has accepted it. Returns the results of
+/// the endpoints which have refused the transaction. In dry-run mode the transaction is only
+/// recorded as broadcast. Transactions violating the standardness rules, which the nodes won't
+/// relay, are refused before reaching the indexers.
+pub fn publish_tx<K, D: Descriptor<K>, L2: Layer2, I: Indexer + Sync>(
+    wallet: &mut Wallet<K, D, L2>,
+    broadcaster: &BroadcastFanout<I>,
+    tx: &Tx,
+    changes: &DryRun,
+) -> Result<Vec<BroadcastResult<I::Error>>, ExecError>
+where
+    I::Error: Send,
+    ExecError: From<I::Error>,
+{
+    standardness::check(tx)?;
+    let mut refused = vec![];
+    if changes.perform(tx.txid()) {
+        refused = broadcaster.broadcast(tx);
+        if refused.iter().all(|res| res.result.is_err()) {
+            if let Err(err) = refused.swap_remove(0).result {
+                return Err(err.into());
+            }
+        }
+        refused.retain(|res| res.result.is_err());
+    }
+    wallet.advance_pending(tx.txid(), PendingStatus::Broadcast);
+    Ok(refused)
+}
+
+/// Signatures collected in a workspace, see [`workspace_status`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct WorkspaceStatus {
+    pub txid: Txid,
+    /// Names of the cosigners which have provided their signed PSBTs.
+    pub cosigners: Vec<String>,
+    pub inputs: Vec<InputSignatures>,
+    /// Descriptor keys by their master fingerprints, used to identify the signers.
+    pub keys: BTreeMap<XpubFp, String>,
+    /// Path to the finalized PSBT, which is saved once the signature threshold is met.
+    pub final_psbt: Option<PathBuf>,
+    /// Number of inputs which still require more signatures.
+    pub unsigned_inputs: usize,
+}
+
+/// Reports signatures collected in the workspace, combining them and saving the finalized
+/// PSBT once enough signatures are provided.
+pub fn workspace_status<K, D: Descriptor<K>>(
+    workspace: &Workspace,
+    descriptor: &D,
+) -> Result<WorkspaceStatus, ExecError> {
+    let mut psbt = workspace.combine();
+    if !psbt.is_finalized() {
+        psbt.finalize(descriptor);
+    }
+    let final_psbt = if psbt.is_finalized() { Some(workspace.save_final(&psbt)?) } else { None };
+    Ok(WorkspaceStatus {
+        txid: workspace.unsigned().txid(),
+        cosigners: workspace.cosigners().map(str::to_owned).collect(),
+        inputs: workspace.signatures(),
+        keys: descriptor.xpubs().map(|xpub| (xpub.master_fp(), xpub.to_string())).collect(),
+        final_psbt,
+        unsigned_inputs: psbt.inputs().filter(|input| !input.is_finalized()).count(),
+    })
+}
+
+/// Prepares the wallet party of a collaborative transaction session from the wallet coins with
+/// their proofs of ownership and the outputs the party pays to.
+///
+/// The value remaining after the fee share is returned to a new change address of the wallet,
+/// unless it is below the dust limit, in which case it is left to the miners.
+pub fn collab_party<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    session: &CollabSession,
+    coins: &BTreeMap<Outpoint, OwnershipProof>,
+    outputs: &[(Address, Sats)],
+) -> Result<CollabParty, CollabError> {
+    let mut party = CollabParty::default();
+    for (outpoint, proof) in coins {
+        let utxo = wallet.utxo(*outpoint).ok_or(CollabError::UnknownCoin(*outpoint))?;
+        let script_pubkey = wallet
+            .descriptor()
+            .derive(utxo.terminal.keychain, utxo.terminal.index)
+            .to_script_pubkey();
+        party.inputs.push(CollabInput {
+            outpoint: *outpoint,
+            value: utxo.value,
+            script_pubkey,
+            proof: *proof,
+        });
+    }
+    party.outputs = outputs
+        .iter()
+        .map(|(address, amount)| CollabOutput {
+            script_pubkey: address.script_pubkey(),
+            amount: *amount,
+        })
+        .collect();
+
+    let change = CollabOutput {
+        script_pubkey: wallet.next_address(Keychain::INNER, false).script_pubkey(),
+        amount: Sats::ZERO,
+    };
+    let mut with_change = party.clone();
+    with_change.outputs.push(change);
+    let required = with_change.spent() + session.fee_share(&with_change);
+    let remaining = with_change.available().checked_sub(required);
+    if let Some(amount) =
+        remaining.filter(|value| *value > wallet.descriptor().class().dust_limit())
+    {
+        wallet.next_address(Keychain::INNER, true);
+        with_change.outputs.last_mut().expect("change output is present").amount = amount;
+        return Ok(with_change);
+    }
+    Ok(party)
+}
+
+/// Parameters of a payment transaction, see [`construct`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ConstructParams {
+    pub recipients: Vec<Recipient>,
+    /// Fall back to the HTTPS well-known endpoint when resolving human-readable names.
+    pub well_known: bool,
+    pub memo: Option<String>,
+    /// Embed the memo into the PSBT.
+    pub embed_memo: bool,
+    pub no_rbf: bool,
+    /// Additional fee paid by a child transaction spending the change output (CPFP).
+    pub package_fee_boost: Option<Sats>,
+    /// Numbers of the recipients paying the fee from their amounts.
+    pub deduct_fee: Vec<usize>,
+    /// Minimal number of confirmations of the spent coins, overriding the wallet setting.
+    pub min_conf: Option<Confirmations>,
+    pub fee: FeeSpec,
+    pub version: PsbtVer,
+    /// Allow paying to the addresses of the wallet itself.
+    pub allow_self_send: bool,
+    /// Seed for shuffling the transaction inputs and outputs; if not given, inputs follow the
+    /// coin selection order and outputs the order of the recipients, with the change going last.
+    /// Outputs of a package parent are not shuffled, since the child spends its change.
+    pub shuffle: Option<ShuffleSeed>,
+    /// Number of outputs to split the change into, see [`ChangeSplitter::split_change`]. Not
+    /// applicable to packages, since the child spends a single parent change output.
+    pub change_outputs: Option<u8>,
+    /// Re-derive the change scripts from the account key, see [`rederive_script`].
+    pub verify_change: bool,
+}
+
+/// Change output of a transaction constructed by [`construct`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ChangeOutput {
+    pub vout: Vout,
+    pub amount: Sats,
+    pub address: Address,
+    pub terminal: Terminal,
+    /// Whether the address is derived by the wallet descriptor at the terminal.
+    pub own: bool,
+    /// Whether the script was re-derived from the account key, bypassing the descriptor.
+    pub verified: bool,
+}
+
+/// PSBTs constructed by [`construct`], together with the information about the construction.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Constructed {
+    pub psbt: Psbt,
+    /// Child transaction spending the parent change, when a package was requested.
+    pub child: Option<(Psbt, PackageMeta)>,
+    /// Fee paid by the transaction.
+    pub fee: Sats,
+    /// Fee rate used to compute the fee, when the fee was given as a fee rate.
+    pub fee_rate: Option<FeeRate>,
+    /// Human-readable names resolved into the payment addresses.
+    pub resolved: Vec<ResolvedPayment>,
+    /// DNS-over-HTTPS resolver used to resolve the names.
+    pub doh_resolver: Option<String>,
+    /// Whether the transaction aggregates all spendable coins, since it has no fixed payments.
+    pub aggregation: bool,
+    /// Balance of the coins which have too few confirmations to be aggregated.
+    pub locked: Sats,
+    /// Wallet addresses paid by the transaction, other than its change.
+    pub self_send: Vec<DerivedAddr>,
+    /// Change outputs of the transaction, in the order of their numbers.
+    pub change: Vec<ChangeOutput>,
+}
+
+impl Constructed {
+    /// Hash of the constructed PSBT, which is the same for the coordinators constructing the
+    /// transaction independently from the same wallet state and with the same parameters.
+    pub fn proposal_hash(&self) -> String {
+        Sha256::digest(self.psbt.serialize(self.psbt.version)).to_hex()
+    }
+}
+
+/// Constructs PSBT paying to the recipients, resolving their human-readable names and wallet
+/// contacts, and registers the transaction draft in the wallet.
+pub fn construct<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    network: Network,
+    params: &ConstructParams,
+) -> Result<Constructed, ExecError> {
+    let ConstructParams {
+        recipients,
+        well_known,
+        memo,
+        embed_memo,
+        no_rbf,
+        package_fee_boost,
+        deduct_fee,
+        min_conf,
+        fee,
+        version,
+        allow_self_send,
+        shuffle,
+        change_outputs,
+        verify_change,
+    } = params;
+    // The fee deducted from the outputs is not paid from the wallet funds
+    let mut fee_params = match fee {
+        _ if !deduct_fee.is_empty() => FeeParams::with_fee(Sats::ZERO),
+        fee => FeeParams::from(*fee),
+    };
+    fee_params.seq_no = if *no_rbf { SEQ_NO_NO_RBF } else { SEQ_NO_RBF };
+    let wallet_rate = match fee {
+        FeeSpec::Rate(fee_rate) if deduct_fee.is_empty() => Some(*fee_rate),
+        _ => None,
+    };
+
+    // Resolve human-readable names into addresses
+    let mut resolver = None;
+    let mut resolved = vec![];
+    let mut beneficiaries = Vec::with_capacity(recipients.len());
+    let mut shares = bmap! {};
+    for recipient in recipients {
+        let (name, amount) = match recipient {
+            Recipient::Beneficiary(beneficiary) => {
+                beneficiaries.push(*beneficiary);
+                continue;
+            }
+            Recipient::Share(share, address) => {
+                shares.insert(beneficiaries.len(), *share);
+                beneficiaries.push(Beneficiary::with_max(*address));
+                continue;
+            }
+            Recipient::Contact(name, amount) => {
+                let address = wallet
+                    .next_contact_address(name, true)
+                    .ok_or_else(|| ExecError::UnknownContact(name.clone()))?;
+                beneficiaries.push(Beneficiary::new(address, *amount));
+                continue;
+            }
+            Recipient::Name(name, amount) => (name, amount),
+        };
+        let resolver = match resolver {
+            Some(ref resolver) => resolver,
+            None => resolver.insert(
+                PaymentResolver::new(DEFAULT_DOH_RESOLVER, *well_known)
+                    .map_err(ResolveError::from)?,
+            ),
+        };
+        let payment = resolver.resolve(name, network)?;
+        beneficiaries.push(payment.to_beneficiary(*amount)?);
+        resolved.push(payment);
+    }
+    let address_network = AddressNetwork::from(wallet.network());
+    if let Some(b) = beneficiaries.iter().find(|b| b.address.network != address_network) {
+        return Err(ExecError::WrongNetwork(b.address));
+    }
+    // Amounts of the resolved names and contacts are not checked when parsed
+    for beneficiary in &beneficiaries {
+        check_dust(beneficiary)?;
+    }
+    let self_send = beneficiaries
+        .iter()
+        .filter_map(|b| {
+            wallet.terminal_of(&b.address).map(|t| DerivedAddr::new(b.address, t.keychain, t.index))
+        })
+        .collect::<Vec<_>>();
+    if let Some(derived) = self_send.first().filter(|_| !allow_self_send) {
+        return Err(ExecError::SelfSend(derived.addr, derived.terminal));
+    }
+
+    // Do coin selection
+    let total_amount = beneficiaries.iter().try_fold(Sats::ZERO, |sats, b| match b.amount {
+        Payment::Max => Err(()),
+        Payment::Fixed(s) => sats.checked_add(s).ok_or(()),
+    });
+    let min_conf = min_conf.unwrap_or(wallet.min_confirmations());
+    // TODO: Support lock time
+    let aggregation = !matches!(total_amount, Ok(sats) if sats > Sats::ZERO);
+    let locked = match aggregation {
+        true => wallet.locked_balance(min_conf),
+        false => Sats::ZERO,
+    };
+    // The coins are re-selected when the fee estimated at the fee rate grows, and the balance
+    // shares are split after the fee
+    let (wallet_fee, mut coins, beneficiaries) =
+        wallet.converge_fee_with(fee_params, |wallet, fee| -> Result<_, ExecError> {
+            let coins: Vec<_> = match total_amount {
+                Ok(sats) if !aggregation => {
+                    let required = sats + fee + package_fee_boost.unwrap_or_default();
+                    wallet.check_spendable(required, min_conf)?;
+                    wallet.coinselect_min_conf(required, min_conf, coinselect::all).collect()
+                }
+                _ => {
+                    let coins = wallet
+                        .spendable_utxos(min_conf)
+                        .map(WalletUtxo::into_outpoint)
+                        .collect::<Vec<_>>();
+                    if coins.is_empty() {
+                        wallet.check_spendable(locked, min_conf)?;
+                    }
+                    coins
+                }
+            };
+            wallet.check_confirmations(coins.iter().copied(), min_conf)?;
+            let payments = wallet.split_payments(&coins, &beneficiaries, &shares, fee)?;
+            Ok((coins, payments))
+        })?;
+
+    if let Some(seed) = shuffle {
+        seed.shuffle("inputs", &mut coins);
+    }
+
+    let params = fee_params.to_tx_params(wallet_fee);
+    let (mut psbt, mut meta, child) = match package_fee_boost {
+        None => {
+            let (psbt, meta) = wallet.construct_psbt(coins, &beneficiaries, params)?;
+            (psbt, meta, None)
+        }
+        Some(boost) => {
+            let (parent, child, meta) =
+                wallet.construct_package(coins, &beneficiaries, params, *boost)?;
+            (parent, meta.parent, Some((child, meta)))
+        }
+    };
+    // Split before the fee deduction, so the fee accounts for the additional outputs. The fee
+    // converged at the fee rate is increased by the split, which pays for the added outputs
+    let change_split = match change_outputs {
+        Some(count) if child.is_none() => {
+            wallet.split_change(&mut psbt, &meta, *count, wallet_rate)
+        }
+        _ => vec![],
+    };
+    let wallet_fee = match change_split.is_empty() {
+        true => wallet_fee,
+        false => psbt.fee().expect("constructed PSBT has the amounts of all inputs"),
+    };
+    let change_scripts = change_split
+        .iter()
+        .filter_map(|(vout, terminal)| {
+            psbt.outputs().nth(vout.into_usize()).map(|output| (output.script.clone(), *terminal))
+        })
+        .collect::<Vec<_>>();
+    let (fee, fee_rate) = match fee {
+        _ if deduct_fee.is_empty() => (wallet_fee, wallet_rate),
+        FeeSpec::Absolute(fee) => {
+            wallet.deduct_fee(&mut psbt, *fee, deduct_fee)?;
+            (*fee, None)
+        }
+        FeeSpec::Rate(fee_rate) => {
+            (wallet.deduct_fee_rate(&mut psbt, *fee_rate, deduct_fee)?, Some(*fee_rate))
+        }
+    };
+    if let Some(seed) = shuffle.as_ref().filter(|_| child.is_none()) {
+        seed.shuffle_outputs(&mut psbt, &mut meta.change_vout);
+    }
+    psbt.version = *version;
+    let change = change_outputs_of(wallet, &psbt, &meta, &change_scripts, *verify_change)?;
+    let txid = psbt.txid();
+    if let Some(change) = meta.change_terminal {
+        wallet.register_draft(txid, change);
+    }
+    // Payments to the wallet itself stay in the wallet, like the change
+    let amount = psbt
+        .outputs()
+        .filter(|output| Some(output.vout()) != meta.change_vout)
+        .filter(|output| !change_scripts.iter().any(|(script, _)| *script == output.script))
+        .filter(|output| {
+            !self_send.iter().any(|derived| derived.addr.script_pubkey() == output.script)
+        })
+        .map(|output| output.amount)
+        .sum::<Sats>();
+    wallet.register_pending(txid, PendingTx {
+        status: PendingStatus::Draft,
+        amount,
+        fee,
+    });
+    psbt.set_wallet_fp(DescriptorFp::with(wallet.descriptor()));
+    if let Some(memo) = memo {
+        // The id of the unsigned transaction is used until it gets finalized
+        wallet.annotate_tx(txid, memo.clone());
+        if *embed_memo {
+            psbt.set_memo(memo);
+        }
+    }
+
+    let child = child.map(|(mut child, meta)| {
+        child.version = *version;
+        child.set_wallet_fp(DescriptorFp::with(wallet.descriptor()));
+        let child_txid = child.txid();
+        wallet.register_draft(child_txid, meta.child_terminal);
+        wallet.register_pending(child_txid, PendingTx {
+            status: PendingStatus::Draft,
+            amount: Sats::ZERO,
+            fee: meta.child_fee,
+        });
+        (child, meta)
+    });
+
+    Ok(Constructed {
+        psbt,
+        child,
+        fee,
+        fee_rate,
+        resolved,
+        doh_resolver: resolver.map(|resolver| resolver.doh_url().to_owned()),
+        aggregation,
+        locked,
+        self_send,
+        change,
+    })
+}
+
+/// Collects the change outputs of the constructed PSBT, checking whether their addresses are
+/// derived by the wallet descriptor and, if `verify` is set, re-deriving their scripts from the
+/// account key.
+fn change_outputs_of<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &Wallet<K, D, L2>,
+    psbt: &Psbt,
+    meta: &PsbtMeta,
+    split: &[(ScriptPubkey, Terminal)],
+    verify: bool,
+) -> Result<Vec<ChangeOutput>, ExecError> {
+    let mut change = vec![];
+    for output in psbt.outputs() {
+        let vout = output.vout();
+        let terminal = match meta.change_vout.zip(meta.change_terminal) {
+            Some((change_vout, terminal)) if change_vout == vout => terminal,
+            _ => match split.iter().find(|(script, _)| *script == output.script) {
+                Some((_, terminal)) => *terminal,
+                None => continue,
+            },
+        };
+        let address = Address::with(&output.script, wallet.network())
+            .expect("change is paid to a descriptor-derived address");
+        let own = wallet.terminal_of(&address) == Some(terminal);
+        if verify {
+            let script = rederive_script(wallet.descriptor(), terminal)
+                .ok_or(ExecError::ChangeUnverifiable(vout.into_u32()))?;
+            if script != output.script {
+                let expected = Address::with(&script, wallet.network())
+                    .expect("P2WPKH and P2TR scripts have addresses");
+                return Err(ExecError::ChangeMismatch(
+                    vout.into_u32(),
+                    address,
+                    terminal,
+                    expected,
+                ));
+            }
+        }
+        change.push(ChangeOutput {
+            vout,
+            amount: output.amount,
+            address,
+            terminal,
+            own,
+            verified: verify,
+        });
+    }
+    Ok(change)
+}
+
+/// Wallet balance reconstructed from the transaction history, see [`balance_at`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct HistoricalBalance {
+    /// Height of the last block covered by the balance, or `None` if the wallet knows no blocks
+    /// before the requested date.
+    pub height: Option<BlockHeight>,
+    pub coins: Vec<WalletUtxo>,
+    pub balance: Sats,
+    /// Height of the last block the wallet is synced to.
+    pub synced: BlockHeight,
+}
+
+impl HistoricalBalance {
+    /// Detects whether the balance is requested after the last synced block, in which case the
+    /// later wallet transactions are missing from it.
+    pub fn is_ahead_of_sync(&self) -> bool {
+        self.height.is_some_and(|height| height > self.synced)
+    }
+}
+
+/// Reconstructs the wallet balance and coins as of the end of the block at the given height or,
+/// if the height is not given, as of the end of the given day. Without both of them the balance
+/// is reconstructed at the last synced block.
+pub fn balance_at<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &Wallet<K, D, L2>,
+    height: Option<BlockHeight>,
+    date: Option<Date>,
+) -> HistoricalBalance {
+    let synced = wallet.last_block().height;
+    let height = match (height, date) {
+        (Some(height), _) => Some(height),
+        (None, Some(date)) => wallet.height_at(date.end_timestamp()),
+        (None, None) => Some(synced),
+    };
+    let coins = height.map(|height| wallet.utxos_at(height)).unwrap_or_default();
+    HistoricalBalance {
+        height,
+        balance: coins.iter().map(|coin| coin.value).sum(),
+        coins,
+        synced,
+    }
+}
+
+/// Fee market state with the estimates derived from the mempool histogram, see
+/// [`fee_overview`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FeeOverview {
+    pub snapshot: FeeSnapshot,
+    /// Minimal fee rates for the inclusion into one of the next blocks, keyed by the number of
+    /// the blocks. Empty if the mempool is cleared by the next block.
+    pub next_blocks: BTreeMap<u16, FeeRate>,
+    /// Mempool histogram with the cumulative depth: the fee rate, the virtual size of the
+    /// transactions paying it and the virtual size of all the transactions paying at least it.
+    pub depth: Vec<(FeeRate, u64, u64)>,
+}
+
+/// Derives the inclusion estimates from the fee snapshot reported by an indexer.
+pub fn fee_overview(snapshot: FeeSnapshot) -> FeeOverview {
+    let next_blocks = [1, 2, 3]
+        .into_iter()
+        .filter_map(|blocks| Some((blocks, snapshot.histogram_rate(blocks)?)))
+        .collect();
+    let mut total = 0u64;
+    let depth = snapshot
+        .histogram
+        .iter()
+        .map(|(rate, vsize)| {
+            total += vsize;
+            (*rate, *vsize, total)
+        })
+        .collect();
+    FeeOverview {
+        snapshot,
+        next_blocks,
+        depth,
+    }
+}
+
+/// Miner fees paid by the wallet, see [`fee_report`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FeeReport {
+    pub periods: BTreeMap<PeriodBucket, FeeTotal>,
+    /// Fees paid over all the periods.
+    pub total: Sats,
+}
+
+/// Aggregates miner fees paid by the mined wallet transactions per calendar period, see
+/// [`Wallet::fee_report`].
+pub fn fee_report<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &Wallet<K, D, L2>,
+    period: Period,
+    own: &OwnWallets,
+) -> FeeReport {
+    let periods = wallet.fee_report(period, own);
+    let total = periods.values().map(|total| total.fees).sum();
+    FeeReport { periods, total }
+}
+
+/// Capital gains realized by the wallet, see [`gains_report`].
+#[derive(Clone, PartialEq, Debug)]
+pub struct GainsReport {
+    /// Disposals made within the reported time range.
+    pub disposals: Vec<Disposal>,
+    /// Total gain realized by the disposals; negative for a loss.
+    pub gain: f64,
+    /// Funds held by the wallet after all the disposals.
+    pub held: Sats,
+    /// Acquisition cost of the held funds.
+    pub cost: f64,
+}
+
+impl GainsReport {
+    /// Detects disposals of the funds not covered by the wallet history, which cost is unknown
+    /// and taken as zero.
+    pub fn has_unknown_cost(&self) -> bool {
+        self.disposals.iter().any(|disposal| disposal.acquired.is_none())
+    }
+}
+
+/// Computes capital gains of the wallet disposals made from the start of the `from` day until
+/// the end of the `to` day, matching them to the acquired lots with the given method. The
+/// exchange rates at the given UNIX timestamps are provided by `rate`.
+pub fn gains_report<K, D: Descriptor<K>, L2: Layer2, E>(
+    wallet: &Wallet<K, D, L2>,
+    method: LotMethod,
+    from: Option<Date>,
+    to: Option<Date>,
+    rate: impl FnMut(u64) -> Result<f64, E>,
+) -> Result<GainsReport, E> {
+    let tracker = wallet.track_lots(method, rate)?;
+    let since = from.map(|date| date.to_timestamp()).unwrap_or_default();
+    let until = to.map(|date| date.end_timestamp()).unwrap_or(u64::MAX);
+    let disposals = tracker
+        .disposals()
+        .iter()
+        .filter(|disposal| (since..=until).contains(&disposal.disposed))
+        .copied()
+        .collect::<Vec<_>>();
+    Ok(GainsReport {
+        gain: disposals.iter().map(|disposal| disposal.gain()).sum(),
+        disposals,
+        held: tracker.lots().map(|lot| lot.amount).sum(),
+        cost: tracker.lots().map(|lot| lot.cost).sum(),
+    })
+}
+
+/// Runs all the health checks against the wallet, including the integrity check of the wallet
+/// files if their store is given.
+pub fn health_report<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &Wallet<K, D, L2>,
+    store: Option<&FsTextStore>,
+) -> HealthReport {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let stale = StaleSync::with(now);
+    let mut checks: Vec<&dyn HealthCheck<K, D, L2>> =
+        vec![&NetworkMatch, &DescriptorValidity, &IndexGap, &stale, &AddressReuse, &TaprootKeys];
+    if let Some(store) = store {
+        checks.push(store);
+    }
+    HealthReport::run(wallet, &checks)
+}
+
+/// Checks integrity of the wallet files alone, for the wallets which can't be loaded.
+pub fn files_report(store: &FsTextStore) -> HealthReport {
+    let mut report = HealthReport::new();
+    report.extend("files", store.integrity_issues());
+    report
+}
+
+/// Parameters of moving all the wallet funds elsewhere, see [`sweep`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SweepParams {
+    /// Fee deducted from the swept funds; an absolute fee is paid by each of the transactions.
+    pub fee: FeeSpec,
+    pub no_rbf: bool,
+    /// Allow paying to the wallet own addresses, which is refused otherwise.
+    pub allow_self_send: bool,
+    pub version: PsbtVer,
+}
+
+/// Transaction sweeping a part of the wallet coins, see [`sweep`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SweptTx {
+    pub psbt: Psbt,
+    pub target: Address,
+    /// Amount paid to the target.
+    pub amount: Sats,
+    pub fee: Sats,
+}
+
+/// Transactions moving all the wallet funds, see [`sweep`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Swept {
+    pub txs: Vec<SweptTx>,
+    /// Balance which is not swept, since it has less confirmations than required for spending.
+    pub locked: Sats,
+    /// Confirmations required for spending.
+    pub min_conf: Confirmations,
+}
+
+/// Constructs PSBTs moving all the spendable wallet coins, each paying to the next address
+/// provided by `next_target`, and registers them as drafts in the pending ledger. Coins which
+/// don't fit into a single standard transaction are swept with multiple transactions, see
+/// [`WalletSweeper::sweep`].
+pub fn sweep<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    mut next_target: impl FnMut() -> Address,
+    params: &SweepParams,
+) -> Result<Swept, ExecError> {
+    let min_conf = wallet.min_confirmations();
+    let coins = wallet.spendable_utxos(min_conf).map(WalletUtxo::into_outpoint).collect::<Vec<_>>();
+    let locked = wallet.locked_balance(min_conf);
+    if coins.is_empty() {
+        wallet.check_spendable(locked, min_conf)?;
+    }
+    let targets = (0..wallet.sweep_chunks(&coins).len()).map(|_| next_target()).collect::<Vec<_>>();
+    let address_network = AddressNetwork::from(wallet.network());
+    if let Some(address) = targets.iter().find(|a| a.network != address_network) {
+        return Err(ExecError::WrongNetwork(*address));
+    }
+    if let Some((address, terminal)) = targets
+        .iter()
+        .filter(|_| !params.allow_self_send)
+        .find_map(|address| wallet.terminal_of(address).map(|t| (*address, t)))
+    {
+        return Err(ExecError::SelfSend(address, terminal));
+    }
+
+    let seq_no = if params.no_rbf { SEQ_NO_NO_RBF } else { SEQ_NO_RBF };
+    let swept = wallet.sweep(&coins, &targets, params.fee, seq_no)?;
+    let txs = swept
+        .into_iter()
+        .zip(targets)
+        .map(|((mut psbt, fee), target)| {
+            psbt.version = params.version;
+            psbt.set_wallet_fp(DescriptorFp::with(wallet.descriptor()));
+            let amount = psbt.outputs().map(|output| output.amount).sum::<Sats>();
+            wallet.register_pending(psbt.txid(), PendingTx {
+                status: PendingStatus::Draft,
+                amount,
+                fee,
+            });
+            SweptTx {
+                psbt,
+                target,
+                amount,
+                fee,
+            }
+        })
+        .collect();
+    Ok(Swept {
+        txs,
+        locked,
+        min_conf,
+    })
+}
+
+/// Constructs PSBT replacing an unconfirmed wallet transaction with the one paying a higher fee
+/// rate, see [`Wallet::bump_fee`]. The replacement is registered as a draft in the pending
+/// ledger and inherits the memo of the replaced transaction.
+pub fn bump_fee<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    txid: Txid,
+    fee_rate: FeeRate,
+    version: PsbtVer,
+) -> Result<(Psbt, BumpMeta), ExecError> {
+    let (mut psbt, meta) = wallet.bump_fee(txid, fee_rate)?;
+    psbt.version = version;
+    psbt.set_wallet_fp(DescriptorFp::with(wallet.descriptor()));
+    let replacement = psbt.txid();
+    wallet.register_pending(replacement, PendingTx {
+        status: PendingStatus::Draft,
+        amount: psbt.outputs().map(|output| output.amount).sum(),
+        fee: meta.fee,
+    });
+    if let Some(memo) = wallet.tx_annotation(txid).map(str::to_owned) {
+        wallet.annotate_tx(replacement, memo);
+    }
+    Ok((psbt, meta))
+}
+
+/// Constructs PSBT of a child transaction accelerating an unconfirmed transaction paying to the
+/// wallet, see [`Wallet::construct_cpfp`]. The child is registered as a draft in the pending
+/// ledger.
+pub fn construct_cpfp<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    txid: Txid,
+    fee_rate: FeeRate,
+    version: PsbtVer,
+) -> Result<(Psbt, CpfpMeta), ExecError> {
+    let (mut psbt, meta) = wallet.construct_cpfp(txid, fee_rate)?;
+    psbt.version = version;
+    psbt.set_wallet_fp(DescriptorFp::with(wallet.descriptor()));
+    wallet.register_pending(psbt.txid(), PendingTx {
+        status: PendingStatus::Draft,
+        amount: meta.change,
+        fee: meta.fee,
+    });
+    Ok((psbt, meta))
+}
+
+/// Adds the transaction to the wallet cache without an indexer: as mined in the block at the
+/// given height if the merkle proof shows its inclusion into the block, or as unconfirmed
+/// otherwise. The previous transactions resolve the inputs which are not known to the wallet.
+///
+/// Returns the status the transaction is added with.
+pub fn import_tx<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &mut Wallet<K, D, L2>,
+    tx: Tx,
+    proof: Option<(&MerkleBlock, BlockHeight)>,
+    prev: &[Tx],
+) -> Result<TxStatus, ExecError> {
+    let txid = tx.txid();
+    let status = match proof {
+        Some((proof, height)) => {
+            if !proof.verify()?.contains(&txid) {
+                return Err(ExecError::NotProven(txid));
+            }
+            TxStatus::Mined(MiningInfo {
+                height,
+                time: proof.header.time as u64,
+                block_hash: proof.header.block_hash(),
+            })
+        }
+        None => TxStatus::Mempool,
+    };
+    let mut store = TxStore::default();
+    prev.iter().cloned().for_each(|prev_tx| store.insert(prev_tx));
+    if !wallet.import_tx(tx, status, &mut store) {
+        return Err(ExecError::IrrelevantTx(txid));
+    }
+    Ok(status)
+}
+
+/// Checks with the indexer that the wallet coins are still unspent, returning the status of
+/// each of them.
+pub fn verify_utxos<K, D: Descriptor<K>, L2: Layer2, I: Indexer>(
+    wallet: &Wallet<K, D, L2>,
+    indexer: &I,
+) -> Result<Vec<(Outpoint, OutpointStatus)>, ExecError>
+where
+    ExecError: From<I::Error>,
+{
+    wallet
+        .coins()
+        .map(|row| -> Result<_, ExecError> {
+            Ok((row.outpoint, indexer.outpoint_status(row.outpoint)?))
+        })
+        .collect()
+}
+
+/// Creates a collaborative transaction session with a unique id, derived from the current time
+/// and the session file name.
+pub fn collab_session(session: &Path, fee_rate: FeeRate) -> CollabSession {
+    let mut engine = Sha256::new();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    engine.update(now.as_nanos().to_le_bytes());
+    engine.update(session.to_string_lossy().as_bytes());
+    CollabSession::new(Bytes32::from_byte_array(engine.finalize()), fee_rate)
+}
+
+/// Request to prove ownership of a wallet coin spent in a collaborative session, see
+/// [`collab_proofs`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ProofRequest {
+    pub outpoint: Outpoint,
+    pub terminal: Terminal,
+    /// Message which must be signed with the coin key.
+    pub message: [u8; 32],
+}
+
+/// Collects the proofs of ownership of the wallet coins spent in the collaborative session,
+/// returning them together with the requests for the proofs which are not provided yet.
+pub fn collab_proofs<K, D: Descriptor<K>, L2: Layer2>(
+    wallet: &Wallet<K, D, L2>,
+    session: &CollabSession,
+    coins: &[(Outpoint, Option<OwnershipProof>)],
+) -> Result<(BTreeMap<Outpoint, OwnershipProof>, Vec<ProofRequest>), ExecError> {
+    let mut proofs = BTreeMap::new();
+    let mut requests = vec![];
+    for (outpoint, proof) in coins {
+        if let Some(proof) = proof {
+            proofs.insert(*outpoint, *proof);
+            continue;
+        }
+        let utxo = wallet.utxo(*outpoint).ok_or(CollabError::UnknownCoin(*outpoint))?;
+        let script = wallet
+            .descriptor()
+            .derive(utxo.terminal.keychain, utxo.terminal.index)
+            .to_script_pubkey();
+        requests.push(ProofRequest {
+            outpoint: *outpoint,
+            terminal: utxo.terminal,
+            message: session.proof_message(*outpoint, &script),
+        });
+    }
+    Ok((proofs, requests))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{publish_payment, test_wallet};
+    use crate::SimIndexer;
+
+    #[test]
+    fn test_wallet_backup_display() {
+        let mut backup = WalletBackup {
+            name: s!("savings"),
+            network: Network::Testnet3,
+            birthday: BlockHeight::new(2_500_000),
+            descriptor: s!("wpkh([00000000/84h/1h/0h]tpub/<0;1>/*)"),
+        };
+        assert_eq!(
+            backup.to_string(),
+            "# Wallet 'savings' backup\nnetwork = \"testnet3\"\nbirthday = 2500000\ndescriptor = \
+             \"wpkh([00000000/84h/1h/0h]tpub/<0;1>/*)\"\n"
+        );
+        backup.birthday = None;
+        assert!(backup.to_string().contains("# birthday is unknown"));
+    }
+
+    #[test]
+    fn test_self_send_refused() {
+        let mut wallet = test_wallet();
+        // Not issued yet, but within the gap limit
+        let own = wallet.next_address(Keychain::OUTER, false);
+        let params = ConstructParams {
+            recipients: vec![Recipient::Beneficiary(Beneficiary::new(own, Sats(1000)))],
+            well_known: false,
+            memo: None,
+            embed_memo: false,
+            no_rbf: false,
+            package_fee_boost: None,
+            deduct_fee: vec![],
+            min_conf: None,
+            fee: FeeSpec::Absolute(Sats(500)),
+            version: PsbtVer::V0,
+            allow_self_send: false,
+            shuffle: None,
+            change_outputs: None,
+            verify_change: false,
+        };
+        let Err(ExecError::SelfSend(addr, terminal)) =
+            construct(&mut wallet, Network::Testnet3, &params)
+        else {
+            panic!("payment to the own address must be refused");
+        };
+        assert_eq!(addr, own);
+        assert_eq!(terminal, Terminal::new(Keychain::OUTER, NormalIndex::normal(0)));
+    }
+
+    #[test]
+    fn test_fee_overview() {
+        let snapshot = FeeSnapshot {
+            targets: bmap! { 1 => FeeRate::from_sat_per_vb(20) },
+            histogram: vec![
+                (FeeRate::from_sat_per_vb(30), 600_000),
+                (FeeRate::from_sat_per_vb(10), 900_000),
+                (FeeRate::from_sat_per_vb(2), 300_000),
+            ],
+        };
+        let overview = fee_overview(snapshot);
+        assert_eq!(overview.next_blocks, bmap! { 1 => FeeRate::from_sat_per_vb(10) });
+        assert_eq!(overview.depth.iter().map(|(_, _, depth)| *depth).collect::<Vec<_>>(), vec![
+            600_000, 1_500_000, 1_800_000
+        ]);
+    }
+
+    #[test]
+    fn test_bump_fee_draft() {
+        let mut wallet = test_wallet();
+        let indexer = SimIndexer::default();
+        let (_, payment) = publish_payment(&mut wallet, &indexer, true);
+        wallet.annotate_tx(payment.txid(), s!("rent"));
+
+        let fee_rate = FeeRate::from_sat_per_vb(10);
+        let (psbt, meta) = bump_fee(&mut wallet, payment.txid(), fee_rate, PsbtVer::V2).unwrap();
+        assert_eq!(psbt.version, PsbtVer::V2);
+        let pending = wallet.pending()[&psbt.txid()];
+        assert_eq!(pending.status, PendingStatus::Draft);
+        assert_eq!(pending.fee, meta.fee);
+        assert_eq!(wallet.tx_annotation(psbt.txid()), Some("rent"));
+    }
+}
@@ -25,9 +25,13 @@ use std::path::{Path, PathBuf};
 
 use bpstd::{Network, XpubDerivable};
 use clap::ValueHint;
+use clap_complete::ArgValueCandidates;
 use descriptors::{Descriptor, StdDescr, TrKey, Wpkh};
 use strict_encoding::Ident;
 
+use crate::cli::{wallet_names, Config};
+use crate::indexers::CertFingerprint;
+
 pub const DATA_DIR_ENV: &str = "LNPBP_DATA_DIR";
 #[cfg(target_os = "linux")]
 pub const DATA_DIR: &str = "~/.lnp-bp";
@@ -46,6 +50,24 @@ pub const DEFAULT_ELECTRUM: &str = "example.com:50001";
 pub const DEFAULT_ESPLORA: &str = "https://blockstream.info/{network}/api";
 pub const DEFAULT_MEMPOOL: &str = "https://mempool.space/{network}/api";
 
+/// Location and credentials of the remote storage used by `sync-store` command.
+#[cfg(feature = "remote")]
+#[derive(Args, Clone, PartialEq, Eq, Debug)]
+pub struct RemoteOpts {
+    /// Remote storage: either an `http(s)://` URL of a WebDAV server or of an S3-compatible
+    /// bucket, or a directory. Defaults to `sync_remote` option of the configuration file
+    #[arg(long, env = "BP_SYNC_REMOTE", value_name = "URL")]
+    pub remote: Option<String>,
+
+    /// Value of the `Authorization` header sent to the HTTP remote storage
+    #[arg(long, env = "BP_SYNC_AUTH", hide_env_values = true)]
+    pub auth: Option<String>,
+
+    /// Password encrypting the wallet files in the remote storage
+    #[arg(long, env = "BP_SYNC_PASSWORD", hide_env_values = true)]
+    pub password: String,
+}
+
 #[derive(Args, Clone, PartialEq, Eq, Debug)]
 #[group(args = ["electrum", "esplora", "mempool"])]
 pub struct ResolverOpt {
@@ -87,6 +109,53 @@ pub struct ResolverOpt {
         value_name = "URL"
     )]
     pub mempool: Option<String>,
+
+    /// Proxy server to connect to the indexer through, like `socks5://127.0.0.1:9050` for Tor.
+    /// Overrides the proxy configured for the wallet indexer
+    #[arg(long, global = true, env = "INDEXER_PROXY", value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// SHA-256 fingerprint of the TLS certificate of the indexer server to trust, for servers
+    /// with self-signed certificates. The pin is saved to the configuration file for the server.
+    #[arg(long, global = true, value_name = "FINGERPRINT")]
+    pub cert: Option<CertFingerprint>,
+
+    /// PEM file with additional certificate authorities to trust for the indexer server. The
+    /// bundle path is saved to the configuration file for the server.
+    #[arg(long, global = true, value_hint = ValueHint::FilePath, value_name = "FILE")]
+    pub ca_bundle: Option<PathBuf>,
+
+    /// Esplora-compatible server the published transactions are also broadcast to, like the
+    /// mempool.space transaction accelerator API. Can be used multiple times
+    #[arg(long, global = true, value_hint = ValueHint::Url, value_name = "URL")]
+    pub broadcast: Vec<String>,
+}
+
+impl ResolverOpt {
+    /// Returns URL of the indexer server, if any.
+    pub fn url(&self) -> Option<&str> {
+        self.electrum.as_deref().or(self.esplora.as_deref()).or(self.mempool.as_deref())
+    }
+
+    /// Saves TLS options given in the command line to the configuration for the indexer server
+    /// URL. Returns whether the configuration has changed.
+    pub fn persist_tls(&self, conf: &mut Config) -> bool {
+        let Some(url) = self.url() else {
+            return false;
+        };
+        if self.cert.is_none() && self.ca_bundle.is_none() {
+            return false;
+        }
+        let tls = conf.tls.entry(url.to_owned()).or_default();
+        let prev = tls.clone();
+        if let Some(cert) = self.cert {
+            tls.pin = Some(cert);
+        }
+        if let Some(ca_bundle) = &self.ca_bundle {
+            tls.ca_bundle = Some(ca_bundle.clone());
+        }
+        *tls != prev
+    }
 }
 
 pub trait DescriptorOpts: clap::Args + Clone + Eq + Debug {
@@ -124,7 +193,7 @@ impl DescriptorOpts for DescrStdOpts {
 #[group(multiple = false)]
 pub struct WalletOpts<O: DescriptorOpts = DescrStdOpts> {
     /// Use specific named wallet
-    #[arg(short = 'w', long = "wallet", global = true)]
+    #[arg(short = 'w', long = "wallet", global = true, add = ArgValueCandidates::new(wallet_names))]
     pub name: Option<Ident>,
 
     /// Use wallet from a given path
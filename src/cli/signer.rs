@@ -0,0 +1,221 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pass-through of PSBTs to external signers, allowing to drive the whole transaction flow from
+//! a single binary.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use amplify::IoError;
+use psbt::{Psbt, PsbtParseError};
+use serde_crate::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Signer backend to which PSBTs are routed for signing.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum SignerBackend {
+    /// Hardware wallet accessed via the `hwi` command, optionally selected by its master key
+    /// fingerprint. If the fingerprint is not given, a single fingerprint of the PSBT xpubs is
+    /// used.
+    Hwi(Option<String>),
+
+    /// Encrypted signing account file created with `bp-hot derive`.
+    Hot(PathBuf),
+
+    /// Arbitrary executable, which reads Base64-encoded PSBT from STDIN and writes the signed
+    /// PSBT to STDOUT.
+    Command(String),
+}
+
+/// invalid signer '{0}'; it must be `hwi[:<fingerprint>]`, `hot:<file>` or `command:<exe>`.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidSigner(String);
+
+impl Display for SignerBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SignerBackend::Hwi(None) => f.write_str("hwi"),
+            SignerBackend::Hwi(Some(fingerprint)) => write!(f, "hwi:{fingerprint}"),
+            SignerBackend::Hot(file) => write!(f, "hot:{}", file.display()),
+            SignerBackend::Command(exe) => write!(f, "command:{exe}"),
+        }
+    }
+}
+
+impl FromStr for SignerBackend {
+    type Err = InvalidSigner;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, arg) = match s.split_once(':') {
+            Some((kind, arg)) if !arg.is_empty() => (kind, Some(arg)),
+            Some(_) => return Err(InvalidSigner(s.to_owned())),
+            None => (s, None),
+        };
+        match (kind, arg) {
+            ("hwi", fingerprint) => Ok(SignerBackend::Hwi(fingerprint.map(str::to_owned))),
+            ("hot", Some(file)) => Ok(SignerBackend::Hot(PathBuf::from(file))),
+            ("command", Some(exe)) => Ok(SignerBackend::Command(exe.to_owned())),
+            _ => Err(InvalidSigner(s.to_owned())),
+        }
+    }
+}
+
+impl Serialize for SignerBackend {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for SignerBackend {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        SignerBackend::from_str(&s).map_err(serde_crate::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SignerError {
+    /// unable to run signer '{0}': {1}
+    Spawn(String, IoError),
+
+    /// signer '{0}' has failed with {1}.
+    Failed(String, String),
+
+    /// signer '{0}' has returned invalid PSBT: {1}
+    InvalidPsbt(String, PsbtParseError),
+
+    /// PSBT has {0} master key fingerprints; please specify the hardware wallet with
+    /// `hwi:<fingerprint>`.
+    HwiFingerprint(usize),
+
+    /// no signer is given; use `--signer` option or set `signer` in the configuration file.
+    NotConfigured,
+
+    /// hot signing requires `bp` to be compiled with `hot` feature.
+    NoHotSigner,
+
+    /// {0}
+    #[cfg(feature = "hot")]
+    #[from]
+    Hot(crate::hot::DataError),
+}
+
+impl SignerBackend {
+    /// Signs the PSBT, returning the signed PSBT.
+    pub fn sign(&self, psbt: &Psbt) -> Result<Psbt, SignerError> {
+        match self {
+            SignerBackend::Hwi(fingerprint) => {
+                let fingerprint = match fingerprint {
+                    Some(fingerprint) => fingerprint.clone(),
+                    None => {
+                        let mut fingerprints = psbt
+                            .xpubs
+                            .values()
+                            .map(|origin| origin.master_fp())
+                            .collect::<Vec<_>>();
+                        fingerprints.sort();
+                        fingerprints.dedup();
+                        match fingerprints.as_slice() {
+                            [fingerprint] => fingerprint.to_string(),
+                            _ => return Err(SignerError::HwiFingerprint(fingerprints.len())),
+                        }
+                    }
+                };
+                let output =
+                    run("hwi", &["-f", &fingerprint, "signtx", &format!("{psbt:0}")], None)?;
+                let reply = serde_json::from_str::<serde_json::Value>(&output)
+                    .map_err(|err| SignerError::Failed(s!("hwi"), err.to_string()))?;
+                match (reply["psbt"].as_str(), reply["error"].as_str()) {
+                    (Some(signed), _) => Psbt::from_str(signed)
+                        .map_err(|err| SignerError::InvalidPsbt(s!("hwi"), err)),
+                    (None, Some(err)) => Err(SignerError::Failed(s!("hwi"), err.to_owned())),
+                    (None, None) => Err(SignerError::Failed(s!("hwi"), output)),
+                }
+            }
+            SignerBackend::Command(exe) => {
+                let output = run(exe, &[], Some(&psbt.to_string()))?;
+                Psbt::from_str(output.trim())
+                    .map_err(|err| SignerError::InvalidPsbt(exe.clone(), err))
+            }
+            #[cfg(feature = "hot")]
+            SignerBackend::Hot(file) => {
+                use bpstd::signers::TestnetRefSigner;
+                use bpstd::XprivAccount;
+
+                use crate::hot::SecureIo;
+
+                let password = rpassword::prompt_password("Password: ")
+                    .map_err(|err| SignerError::Spawn(s!("hot"), err.into()))?;
+                let account = XprivAccount::read(file, &password)?;
+                eprintln!("Signing key: {}", account.to_xpub_account());
+                let mut psbt = psbt.clone();
+                let signer = TestnetRefSigner::new(&account);
+                let sig_count = psbt.sign(&signer).map_err(crate::hot::DataError::from)?;
+                eprintln!("Done {sig_count} signatures");
+                Ok(psbt)
+            }
+            #[cfg(not(feature = "hot"))]
+            SignerBackend::Hot(_) => Err(SignerError::NoHotSigner),
+        }
+    }
+}
+
+/// Runs the signer executable, optionally passing data to its STDIN, and returns its STDOUT.
+fn run(exe: &str, args: &[&str], input: Option<&str>) -> Result<String, SignerError> {
+    let spawn_err = |err: std::io::Error| SignerError::Spawn(exe.to_owned(), err.into());
+    let mut child = Command::new(exe)
+        .args(args)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(spawn_err)?;
+    if let Some(input) = input {
+        let mut stdin = child.stdin.take().expect("STDIN is piped");
+        writeln!(stdin, "{input}").map_err(spawn_err)?;
+    }
+    let output = child.wait_with_output().map_err(spawn_err)?;
+    if !output.status.success() {
+        return Err(SignerError::Failed(exe.to_owned(), output.status.to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signer_str_round_trip() {
+        for s in ["hwi", "hwi:643a7adc", "hot:account.xpriv", "command:/usr/bin/signer"] {
+            assert_eq!(SignerBackend::from_str(s).unwrap().to_string(), s);
+        }
+        assert!(SignerBackend::from_str("hot").is_err());
+        assert!(SignerBackend::from_str("command:").is_err());
+        assert!(SignerBackend::from_str("ledger").is_err());
+    }
+}
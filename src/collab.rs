@@ -0,0 +1,560 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local-party machinery for collaborative (coinjoin-style) transaction construction.
+//!
+//! A [`CollabSession`] collects inputs and outputs from several parties, each of which pays for
+//! the weight of its own inputs and outputs and an equal share of the common transaction data.
+//! Inputs are registered together with proofs of ownership, which are signatures made with the
+//! key of the spent output over a message committing to the session and the coin (see
+//! [`CollabSession::proof_message`]). Only P2WPKH and key-path P2TR coins can be registered.
+//!
+//! Exchange of the session data between the parties is out of scope of this module.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+#[cfg(feature = "fs")]
+use std::fs;
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use amplify::Bytes32;
+use bpstd::secp256k1::{ecdsa, schnorr as bip340, Message, XOnlyPublicKey, SECP256K1};
+use bpstd::{
+    Address, CompressedPk, ConsensusEncode, Descriptor, Outpoint, Sats, ScriptPubkey, SpkClass,
+    TxIn, TxOut, TxVer, UnsignedTx, UnsignedTxIn, VarIntArray, WPubkeyHash, Weight,
+};
+use psbt::{Psbt, PsbtConstructor, PsbtVer};
+use sha2::{Digest, Sha256};
+
+#[cfg(feature = "fs")]
+use crate::DryRun;
+use crate::{satisfaction_weight, FeeRate, Layer2, Wallet, SEQ_NO_NO_RBF};
+
+const PROOF_TAG: &[u8] = b"bp-wallet:collab-proof";
+
+/// Weight of the transaction data not belonging to any of the parties: version, lock time,
+/// input and output counts and the segwit marker with the flag.
+pub const COLLAB_SHARED_WEIGHT: u32 = (4 + 4 + 1 + 1) * 4 + 2;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum CollabError {
+    /// party '{0}' has already joined the session.
+    DuplicateParty(String),
+
+    /// coin {0} is already registered in the session.
+    DuplicateInput(Outpoint),
+
+    /// {0} is not an unspent output of the wallet.
+    UnknownCoin(Outpoint),
+
+    /// coin {0} can't be registered since only P2WPKH and P2TR coins are supported.
+    UnsupportedScript(Outpoint),
+
+    /// proof of ownership for coin {0} is invalid.
+    InvalidProof(Outpoint),
+
+    /// party '{party}' contributes {available} sats, which doesn't cover its outputs and fee
+    /// share of {required} sats.
+    Underfunded {
+        party: String,
+        available: Sats,
+        required: Sats,
+    },
+
+    /// the session has {0} parties, while at least two are required for a collaborative
+    /// transaction.
+    NotEnoughParties(usize),
+
+    /// I/O error accessing the session file: {0}
+    #[cfg(feature = "fs")]
+    #[from]
+    #[from(std::io::Error)]
+    Io(amplify::IoError),
+
+    /// invalid session file {0:?}: {1}
+    #[cfg(feature = "fs")]
+    InvalidSession(PathBuf, String),
+}
+
+/// Proof of ownership of a coin, which is a signature over
+/// [`CollabSession::proof_message`] made with the key of the coin.
+///
+/// Encoded as a hex string of either 64-byte BIP-340 signature (for P2TR coins) or a 33-byte
+/// compressed public key followed by a 64-byte compact ECDSA signature (for P2WPKH coins).
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", into = "String", try_from = "String")
+)]
+pub enum OwnershipProof {
+    /// BIP-340 signature made with the output key of a P2TR coin.
+    Bip340(bip340::Signature),
+    /// ECDSA signature made with the key of a P2WPKH coin.
+    Ecdsa(CompressedPk, ecdsa::Signature),
+}
+
+impl OwnershipProof {
+    /// Verifies the proof for the coin with the given script pubkey against the message
+    /// produced by [`CollabSession::proof_message`].
+    pub fn verify(&self, message: [u8; 32], script_pubkey: &ScriptPubkey) -> bool {
+        match self {
+            OwnershipProof::Bip340(sig) if script_pubkey.is_p2tr() => {
+                XOnlyPublicKey::from_slice(&script_pubkey[2..]).is_ok_and(|output_key| {
+                    SECP256K1.verify_schnorr(sig, &message, &output_key).is_ok()
+                })
+            }
+            OwnershipProof::Ecdsa(pk, sig) if script_pubkey.is_p2wpkh() => {
+                *script_pubkey == ScriptPubkey::p2wpkh(WPubkeyHash::from(*pk))
+                    && SECP256K1.verify_ecdsa(&Message::from_digest(message), sig, pk).is_ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for OwnershipProof {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            OwnershipProof::Bip340(sig) => f.write_str(&sig.to_byte_array().to_hex()),
+            OwnershipProof::Ecdsa(pk, sig) => {
+                f.write_str(&pk.to_byte_array().to_hex())?;
+                f.write_str(&sig.serialize_compact().to_hex())
+            }
+        }
+    }
+}
+
+/// invalid proof of ownership '{0}'; it must be a hex-encoded BIP-340 signature or a compressed
+/// public key followed by a compact ECDSA signature.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidOwnershipProof(String);
+
+impl FromStr for OwnershipProof {
+    type Err = InvalidOwnershipProof;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || InvalidOwnershipProof(s.to_owned());
+        let data = Vec::<u8>::from_hex(s).map_err(|_| err())?;
+        match data.len() {
+            64 => {
+                bip340::Signature::from_slice(&data).map(OwnershipProof::Bip340).map_err(|_| err())
+            }
+            97 => {
+                let pk = CompressedPk::from_bytes(&data[..33]).map_err(|_| err())?;
+                let sig = ecdsa::Signature::from_compact(&data[33..]).map_err(|_| err())?;
+                Ok(OwnershipProof::Ecdsa(pk, sig))
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+impl From<OwnershipProof> for String {
+    fn from(proof: OwnershipProof) -> Self { proof.to_string() }
+}
+
+impl TryFrom<String> for OwnershipProof {
+    type Error = InvalidOwnershipProof;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> { OwnershipProof::from_str(&s) }
+}
+
+/// Coin contributed to the session by one of the parties.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct CollabInput {
+    pub outpoint: Outpoint,
+    pub value: Sats,
+    pub script_pubkey: ScriptPubkey,
+    pub proof: OwnershipProof,
+}
+
+impl CollabInput {
+    /// Estimated weight of the input once it gets signed.
+    pub fn weight(&self) -> u32 {
+        let class = if self.script_pubkey.is_p2tr() { SpkClass::P2tr } else { SpkClass::P2wpkh };
+        // outpoint, empty script sig length byte and sequence number
+        (32 + 4 + 1 + 4) * 4 + satisfaction_weight(class)
+    }
+}
+
+/// Output requested by one of the parties.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct CollabOutput {
+    pub script_pubkey: ScriptPubkey,
+    pub amount: Sats,
+}
+
+impl CollabOutput {
+    #[inline]
+    pub fn weight(&self) -> u32 {
+        TxOut::new(self.script_pubkey.clone(), self.amount).weight_units().to_u32()
+    }
+}
+
+/// Inputs and outputs of a single party of the session.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct CollabParty {
+    pub inputs: Vec<CollabInput>,
+    pub outputs: Vec<CollabOutput>,
+}
+
+impl CollabParty {
+    /// Total value of the party inputs.
+    pub fn available(&self) -> Sats { self.inputs.iter().map(|input| input.value).sum() }
+
+    /// Total value of the party outputs.
+    pub fn spent(&self) -> Sats { self.outputs.iter().map(|output| output.amount).sum() }
+
+    /// Weight of the party inputs and outputs.
+    pub fn weight(&self) -> u32 {
+        self.inputs.iter().map(CollabInput::weight).sum::<u32>()
+            + self.outputs.iter().map(CollabOutput::weight).sum::<u32>()
+    }
+
+    /// Fee paid by the party in a session with the given number of parties: the fee for the
+    /// weight of the party inputs and outputs and an equal share of
+    /// [`COLLAB_SHARED_WEIGHT`], rounded up.
+    pub fn fee_share(&self, fee_rate: FeeRate, parties: usize) -> Sats {
+        let shared = COLLAB_SHARED_WEIGHT.div_ceil(parties.max(1) as u32);
+        fee_rate.fee_for_weight(self.weight() + shared)
+    }
+}
+
+/// State of a collaborative transaction construction session, see module-level docs for
+/// details.
+///
+/// Shares of the common transaction weight only decrease as more parties join, so a party
+/// funded at the moment of joining remains funded till the session end.
+#[derive(Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct CollabSession {
+    pub id: Bytes32,
+    pub fee_rate: FeeRate,
+    parties: BTreeMap<String, CollabParty>,
+}
+
+impl CollabSession {
+    pub fn new(id: Bytes32, fee_rate: FeeRate) -> Self {
+        CollabSession {
+            id,
+            fee_rate,
+            parties: none!(),
+        }
+    }
+
+    #[inline]
+    pub fn parties(&self) -> &BTreeMap<String, CollabParty> { &self.parties }
+
+    /// Message which has to be signed with the key of a coin to prove its ownership: tagged
+    /// SHA256 hash of the session id, the coin outpoint and its script pubkey.
+    pub fn proof_message(&self, outpoint: Outpoint, script_pubkey: &ScriptPubkey) -> [u8; 32] {
+        let mut engine = Sha256::new();
+        engine.update(PROOF_TAG);
+        engine.update(self.id.as_slice());
+        engine.update(outpoint.consensus_serialize());
+        engine.update(script_pubkey.consensus_serialize());
+        engine.finalize().into()
+    }
+
+    /// Fee the party has to pay if no other parties join the session.
+    pub fn fee_share(&self, party: &CollabParty) -> Sats {
+        party.fee_share(self.fee_rate, self.parties.len() + 1)
+    }
+
+    /// Registers a new party, verifying proofs of ownership of its coins and checking that the
+    /// coins cover the party outputs and its fee share.
+    pub fn join(&mut self, name: impl Into<String>, party: CollabParty) -> Result<(), CollabError> {
+        let name = name.into();
+        if self.parties.contains_key(&name) {
+            return Err(CollabError::DuplicateParty(name));
+        }
+        for (no, input) in party.inputs.iter().enumerate() {
+            let outpoint = input.outpoint;
+            if !input.script_pubkey.is_p2tr() && !input.script_pubkey.is_p2wpkh() {
+                return Err(CollabError::UnsupportedScript(outpoint));
+            }
+            if party.inputs[..no].iter().any(|prev| prev.outpoint == outpoint)
+                || self.inputs().any(|prev| prev.outpoint == outpoint)
+            {
+                return Err(CollabError::DuplicateInput(outpoint));
+            }
+            if !input
+                .proof
+                .verify(self.proof_message(outpoint, &input.script_pubkey), &input.script_pubkey)
+            {
+                return Err(CollabError::InvalidProof(outpoint));
+            }
+        }
+        let required = party.spent() + self.fee_share(&party);
+        if party.available() < required {
+            return Err(CollabError::Underfunded {
+                party: name,
+                available: party.available(),
+                required,
+            });
+        }
+        self.parties.insert(name, party);
+        Ok(())
+    }
+
+    /// Iterates over inputs of all the parties.
+    pub fn inputs(&self) -> impl Iterator<Item = &CollabInput> {
+        self.parties.values().flat_map(|party| &party.inputs)
+    }
+
+    /// Iterates over outputs of all the parties.
+    pub fn outputs(&self) -> impl Iterator<Item = &CollabOutput> {
+        self.parties.values().flat_map(|party| &party.outputs)
+    }
+
+    /// Assembles unsigned PSBT v2 from the inputs and outputs of all the parties.
+    ///
+    /// Inputs are ordered by their outpoints and outputs by their amounts and scripts (as in
+    /// BIP-69), so all the parties assemble the same transaction independently.
+    pub fn to_psbt(&self) -> Result<Psbt, CollabError> {
+        if self.parties.len() < 2 {
+            return Err(CollabError::NotEnoughParties(self.parties.len()));
+        }
+        for (name, party) in &self.parties {
+            let required = party.spent() + party.fee_share(self.fee_rate, self.parties.len());
+            if party.available() < required {
+                return Err(CollabError::Underfunded {
+                    party: name.clone(),
+                    available: party.available(),
+                    required,
+                });
+            }
+        }
+
+        let mut inputs = self.inputs().collect::<Vec<_>>();
+        inputs.sort_by_key(|input| input.outpoint);
+        let mut outputs = self
+            .outputs()
+            .map(|output| TxOut::new(output.script_pubkey.clone(), output.amount))
+            .collect::<Vec<_>>();
+        outputs.sort_by(|a, b| {
+            (a.value, a.script_pubkey.as_slice()).cmp(&(b.value, b.script_pubkey.as_slice()))
+        });
+        let tx = UnsignedTx {
+            version: TxVer::V2,
+            inputs: VarIntArray::from_iter_checked(inputs.iter().map(|input| {
+                UnsignedTxIn::with_sigs_removed(TxIn {
+                    prev_output: input.outpoint,
+                    sig_script: none!(),
+                    sequence: SEQ_NO_NO_RBF,
+                    witness: none!(),
+                })
+            })),
+            outputs: VarIntArray::from_iter_checked(outputs),
+            lock_time: none!(),
+        };
+        let mut psbt = Psbt::from_tx(tx);
+        psbt.version = PsbtVer::V2;
+        for (input, collab) in psbt.inputs_mut().zip(inputs) {
+            input.witness_utxo = Some(TxOut::new(collab.script_pubkey.clone(), collab.value));
+        }
+        Ok(psbt)
+    }
+
+    /// Reads session from a YAML file.
+    #[cfg(feature = "fs")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, CollabError> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)?;
+        serde_yaml::from_str(&data)
+            .map_err(|err| CollabError::InvalidSession(path.to_owned(), err.to_string()))
+    }
+
+    /// Saves session to a YAML file, routing the write through the [`DryRun`] collector.
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: impl AsRef<Path>, changes: &DryRun) -> Result<(), CollabError> {
+        let path = path.as_ref();
+        let data = serde_yaml::to_string(self)
+            .map_err(|err| CollabError::InvalidSession(path.to_owned(), err.to_string()))?;
+        changes.write(path, data)?;
+        Ok(())
+    }
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Adds derivation information to the inputs and outputs of the collaborative PSBT
+    /// belonging to the wallet, such that the wallet signers can sign them. Returns the number
+    /// of the wallet inputs.
+    pub fn complete_collab_psbt(&self, psbt: &mut Psbt) -> usize {
+        let mut count = 0;
+        let mut scratch = Psbt::create(PsbtVer::V2);
+        for input in psbt.inputs_mut() {
+            let Some(utxo) = self.utxo(input.previous_outpoint) else {
+                continue;
+            };
+            let own = scratch.construct_input_expect(
+                utxo.to_prevout(),
+                self.descriptor(),
+                utxo.terminal,
+                SEQ_NO_NO_RBF,
+            );
+            input.redeem_script = own.redeem_script.clone();
+            input.witness_script = own.witness_script.clone();
+            input.bip32_derivation = own.bip32_derivation.clone();
+            input.tap_leaf_script = own.tap_leaf_script.clone();
+            input.tap_bip32_derivation = own.tap_bip32_derivation.clone();
+            input.tap_internal_key = own.tap_internal_key;
+            input.tap_merkle_root = own.tap_merkle_root;
+            count += 1;
+        }
+        for output in psbt.outputs_mut() {
+            let Some(terminal) = Address::with(&output.script, self.network())
+                .ok()
+                .and_then(|addr| self.terminal_of(&addr))
+            else {
+                continue;
+            };
+            let own = scratch.construct_change_expect(self.descriptor(), terminal, output.amount);
+            output.redeem_script = own.redeem_script.clone();
+            output.witness_script = own.witness_script.clone();
+            output.bip32_derivation = own.bip32_derivation.clone();
+            output.tap_internal_key = own.tap_internal_key;
+            output.tap_tree = own.tap_tree.clone();
+            output.tap_bip32_derivation = own.tap_bip32_derivation.clone();
+        }
+        for spec in self.descriptor().xpubs() {
+            psbt.xpubs.insert(*spec.xpub(), spec.origin().clone());
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::secp256k1::{Keypair, SecretKey};
+    use bpstd::{OutputPk, Txid, Vout, XOnlyPk};
+
+    use super::*;
+
+    fn coin(session: &CollabSession, key: u8, taproot: bool, value: u64) -> CollabInput {
+        let sk = SecretKey::from_slice(&[key; 32]).unwrap();
+        let outpoint = Outpoint::new(Txid::from([key; 32]), Vout::from_u32(0));
+        let (script_pubkey, sign): (_, Box<dyn Fn([u8; 32]) -> OwnershipProof>) = if taproot {
+            let pair = Keypair::from_secret_key(SECP256K1, &sk);
+            let output_key = OutputPk::from_unchecked(XOnlyPk::from(pair.x_only_public_key().0));
+            (
+                ScriptPubkey::p2tr_tweaked(output_key),
+                Box::new(move |msg| {
+                    OwnershipProof::Bip340(SECP256K1.sign_schnorr_no_aux_rand(&msg, &pair))
+                }),
+            )
+        } else {
+            let pk = CompressedPk::from(sk.public_key(SECP256K1));
+            (
+                ScriptPubkey::p2wpkh(WPubkeyHash::from(pk)),
+                Box::new(move |msg| {
+                    OwnershipProof::Ecdsa(pk, SECP256K1.sign_ecdsa(&Message::from_digest(msg), &sk))
+                }),
+            )
+        };
+        let proof = sign(session.proof_message(outpoint, &script_pubkey));
+        assert_eq!(OwnershipProof::from_str(&proof.to_string()).unwrap(), proof);
+        CollabInput {
+            outpoint,
+            value: Sats::from(value),
+            script_pubkey,
+            proof,
+        }
+    }
+
+    fn output(key: u8, amount: u64) -> CollabOutput {
+        CollabOutput {
+            script_pubkey: ScriptPubkey::p2wpkh([key; 20]),
+            amount: Sats::from(amount),
+        }
+    }
+
+    #[test]
+    fn test_collab_session() {
+        let mut session =
+            CollabSession::new(Bytes32::from_byte_array([7u8; 32]), FeeRate::from_sat_per_vb(10));
+        let alice = CollabParty {
+            inputs: vec![coin(&session, 1, true, 100_000)],
+            outputs: vec![output(11, 50_000), output(12, 49_000)],
+        };
+        assert!(matches!(
+            session.join("alice", alice.clone()),
+            Err(CollabError::Underfunded { .. })
+        ));
+        let alice = CollabParty {
+            outputs: vec![output(11, 50_000), output(12, 48_000)],
+            ..alice
+        };
+        session.join("alice", alice.clone()).unwrap();
+        assert!(matches!(
+            session.join("alice", alice.clone()),
+            Err(CollabError::DuplicateParty(_))
+        ));
+        assert!(matches!(session.to_psbt(), Err(CollabError::NotEnoughParties(1))));
+
+        let mut bob = CollabParty {
+            inputs: vec![alice.inputs[0].clone()],
+            outputs: vec![output(21, 50_000)],
+        };
+        assert!(matches!(session.join("bob", bob.clone()), Err(CollabError::DuplicateInput(_))));
+        bob.inputs = vec![coin(&session, 2, false, 60_000)];
+        bob.inputs[0].proof = alice.inputs[0].proof;
+        assert!(matches!(session.join("bob", bob.clone()), Err(CollabError::InvalidProof(_))));
+        bob.inputs = vec![coin(&session, 2, false, 60_000)];
+        session.join("bob", bob.clone()).unwrap();
+
+        let psbt = session.to_psbt().unwrap();
+        assert_eq!(psbt.version, PsbtVer::V2);
+        assert_eq!(psbt.inputs().count(), 2);
+        assert!(psbt.inputs().all(|input| input.witness_utxo.is_some()));
+        let amounts = psbt.outputs().map(|output| output.amount.sats()).collect::<Vec<_>>();
+        assert_eq!(amounts, vec![48_000, 50_000, 50_000]);
+        assert_eq!(psbt.fee(), Some(Sats::from(12_000u64)));
+        let fees = alice.fee_share(session.fee_rate, 2) + bob.fee_share(session.fee_rate, 2);
+        assert!(fees <= psbt.fee().unwrap());
+    }
+}
@@ -0,0 +1,186 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wallet contacts, naming the counterparties paid by the wallet.
+
+use std::str::FromStr;
+
+use bpstd::{
+    Address, AddressNetwork, DeriveScripts, Idx, IdxBase, Keychain, NormalIndex, ScriptPubkey,
+    XkeyParseError, XpubDerivable,
+};
+use descriptors::{TrKey, Wpkh};
+
+use crate::parse_address;
+
+/// Script template used to derive addresses of a dynamic contact from its extended key.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[display(lowercase)]
+pub enum ContactTemplate {
+    /// Pay-to-witness-pubkey-hash output, `wpkh(<xpub>)`.
+    Wpkh,
+    /// Key-only pay-to-taproot output, `tr(<xpub>)`.
+    Tr,
+}
+
+/// Contact given by an extended public key, for which a fresh address is derived for each
+/// payment.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[display("{template}({key})")]
+pub struct DynamicContact {
+    pub template: ContactTemplate,
+    pub key: XpubDerivable,
+    /// Index of the address to be used for the next payment.
+    pub next_index: NormalIndex,
+}
+
+impl DynamicContact {
+    pub fn new(template: ContactTemplate, key: XpubDerivable) -> Self {
+        DynamicContact {
+            template,
+            key,
+            next_index: NormalIndex::ZERO,
+        }
+    }
+
+    /// Derives contact address with the given index from the receiving keychain of the key.
+    pub fn address(&self, network: AddressNetwork, index: NormalIndex) -> Address {
+        let key = self.key.clone();
+        match self.template {
+            ContactTemplate::Wpkh => {
+                Wpkh::from(key).derive_address(network, Keychain::OUTER, index)
+            }
+            ContactTemplate::Tr => TrKey::from(key).derive_address(network, Keychain::OUTER, index),
+        }
+        .expect("standard descriptor scripts always have addresses")
+    }
+
+    /// Iterates over the addresses already used for the payments.
+    pub fn used_addresses(&self, network: AddressNetwork) -> impl Iterator<Item = Address> + '_ {
+        (0..self.next_index.index()).map(move |index| {
+            let index = NormalIndex::try_from_index(index).expect("index below a normal index");
+            self.address(network, index)
+        })
+    }
+}
+
+/// Wallet contact, which is either a static address, or an extended public key avoiding address
+/// reuse when the same counterparty is paid repeatedly.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, From)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", untagged)
+)]
+pub enum Contact {
+    #[from]
+    #[display(inner)]
+    Static(Address),
+
+    #[from]
+    #[display(inner)]
+    Dynamic(DynamicContact),
+}
+
+impl Contact {
+    /// Checks whether the contact was paid to the script pubkey.
+    pub fn is_paid_to(&self, script: &ScriptPubkey, network: AddressNetwork) -> bool {
+        match self {
+            Contact::Static(addr) => addr.script_pubkey() == *script,
+            Contact::Dynamic(contact) => {
+                contact.used_addresses(network).any(|addr| addr.script_pubkey() == *script)
+            }
+        }
+    }
+}
+
+/// Errors parsing wallet contact.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ContactParseError {
+    /// invalid contact '{0}'; it must be either an address, or an extended public key with the
+    /// script template in form of `wpkh(<xpub>)` or `tr(<xpub>)`.
+    Invalid(String),
+
+    /// invalid contact extended public key: {0}
+    #[from]
+    Key(XkeyParseError),
+}
+
+impl FromStr for Contact {
+    type Err = ContactParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        for template in [ContactTemplate::Wpkh, ContactTemplate::Tr] {
+            let key = s
+                .strip_prefix(template.to_string().as_str())
+                .and_then(|s| s.strip_prefix('('))
+                .and_then(|s| s.strip_suffix(')'));
+            if let Some(key) = key {
+                let key = XpubDerivable::from_str(key)?;
+                return Ok(DynamicContact::new(template, key).into());
+            }
+        }
+        parse_address(s).map(Contact::from).map_err(|_| ContactParseError::Invalid(s.to_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "[643a7adc/86h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+    #[test]
+    fn test_contact_str_round_trip() {
+        let s = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+        assert!(matches!(Contact::from_str(s), Ok(Contact::Static(_))));
+        for template in ["wpkh", "tr"] {
+            let s = format!("{template}({KEY})");
+            let contact = Contact::from_str(&s).unwrap();
+            assert!(matches!(contact, Contact::Dynamic(_)));
+            assert_eq!(contact.to_string(), s);
+        }
+        assert!(Contact::from_str("sh(invalid)").is_err());
+    }
+
+    #[test]
+    fn test_used_addresses() {
+        let mut contact = DynamicContact::new(ContactTemplate::Wpkh, KEY.parse().unwrap());
+        let first = contact.address(AddressNetwork::Testnet, NormalIndex::ZERO);
+        let script = first.script_pubkey();
+        assert!(!Contact::from(contact.clone()).is_paid_to(&script, AddressNetwork::Testnet));
+        contact.next_index = NormalIndex::ONE;
+        assert_eq!(contact.used_addresses(AddressNetwork::Testnet).collect::<Vec<_>>(), [first]);
+        assert!(Contact::from(contact).is_paid_to(&script, AddressNetwork::Testnet));
+    }
+}
@@ -0,0 +1,224 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reporting which keys can sign the inputs of a PSBT and which of them have already signed,
+//! computed from the wallet descriptor and the public data of the PSBT only.
+
+use bpstd::{Descriptor, KeyOrigin, LegacyPk, Outpoint, TapDerivation, Terminal, XOnlyPk};
+use psbt::{Input, Psbt, PsbtConstructor};
+
+use crate::{Layer2, Wallet};
+
+const OP_1: u8 = 0x51;
+const OP_16: u8 = 0x60;
+const OP_NUMEQUAL: u8 = 0x9c;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKMULTISIG: u8 = 0xae;
+const OP_CHECKSIGADD: u8 = 0xba;
+
+/// Key able to sign a transaction input.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CosignerKey {
+    /// Origin of the key, identifying the cosigner by the master key fingerprint.
+    pub origin: KeyOrigin,
+    /// Whether the PSBT contains a signature made with the key.
+    pub signed: bool,
+}
+
+/// Keys able to sign a transaction input, with the progress of signing the input.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct InputCosigners {
+    pub outpoint: Outpoint,
+    /// Terminal of the spent wallet address. If the input doesn't spend a wallet coin, the keys
+    /// are taken from the key derivations present in the PSBT.
+    pub terminal: Option<Terminal>,
+    pub keys: Vec<CosignerKey>,
+    /// Number of signatures required to spend the input, if it can be determined from the
+    /// scripts given in the PSBT or from the number of keys.
+    pub threshold: Option<usize>,
+    pub finalized: bool,
+}
+
+impl InputCosigners {
+    /// Number of keys which have signed the input.
+    pub fn signed(&self) -> usize { self.keys.iter().filter(|key| key.signed).count() }
+
+    /// Checks whether the input has enough signatures to be finalized.
+    pub fn is_complete(&self) -> bool {
+        self.finalized || self.threshold.is_some_and(|threshold| self.signed() >= threshold)
+    }
+
+    /// Keys which have not signed the input yet.
+    pub fn missing(&self) -> impl Iterator<Item = &KeyOrigin> {
+        self.keys.iter().filter(|key| !key.signed).map(|key| &key.origin)
+    }
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Reports, for each of the PSBT inputs, the keys required to sign it and which of them
+    /// have already signed, together with the multisig threshold progress. Does not require
+    /// any private keys, allowing coordinators to find the cosigners they have to chase.
+    ///
+    /// Keys of the inputs spending wallet coins are derived from the wallet descriptor; for
+    /// other inputs the key derivations from the PSBT are used.
+    pub fn cosigners(&self, psbt: &Psbt) -> Vec<InputCosigners> {
+        let scripts = self.issuable_scripts();
+        let descriptor = self.descriptor();
+        psbt.inputs()
+            .map(|input| {
+                let outpoint = input.previous_outpoint;
+                let known = self
+                    .transactions()
+                    .get(&outpoint.txid)
+                    .and_then(|tx| tx.outputs.get(outpoint.vout_usize()))
+                    .and_then(|debit| debit.derived_addr());
+                let spent = match (&input.witness_utxo, &input.non_witness_tx) {
+                    (Some(txout), _) => Some(&txout.script_pubkey),
+                    (None, Some(tx)) => {
+                        tx.outputs.get(outpoint.vout_usize()).map(|txout| &txout.script_pubkey)
+                    }
+                    (None, None) => None,
+                };
+                let terminal = known
+                    .or_else(|| spent.and_then(|script| scripts.get(script)).copied())
+                    .map(|derived| derived.terminal);
+
+                let (legacy, xonly) = match terminal {
+                    Some(terminal) => (
+                        descriptor.legacy_keyset(terminal).into_iter().collect(),
+                        descriptor.xonly_keyset(terminal).into_iter().collect(),
+                    ),
+                    None => (
+                        input.bip32_derivation.iter().map(|(pk, o)| (*pk, o.clone())).collect(),
+                        input
+                            .tap_bip32_derivation
+                            .iter()
+                            .map(|(pk, derivation)| (*pk, derivation.clone()))
+                            .collect(),
+                    ),
+                };
+                let keys = input_keys(input, legacy, xonly);
+                InputCosigners {
+                    outpoint,
+                    terminal,
+                    threshold: threshold(input, keys.len()),
+                    keys,
+                    finalized: input.is_finalized(),
+                }
+            })
+            .collect()
+    }
+}
+
+fn input_keys(
+    input: &Input,
+    legacy: Vec<(LegacyPk, KeyOrigin)>,
+    xonly: Vec<(XOnlyPk, TapDerivation)>,
+) -> Vec<CosignerKey> {
+    let internal_key = input.tap_internal_key.map(|pk| pk.to_xonly_pk());
+    let mut keys = legacy
+        .into_iter()
+        .map(|(pk, origin)| CosignerKey {
+            origin,
+            signed: input.partial_sigs.contains_key(&pk),
+        })
+        .collect::<Vec<_>>();
+    keys.extend(xonly.into_iter().map(|(pk, derivation)| {
+        // The internal key signs the key path spending, while the other keys sign script leaves
+        let signed = if derivation.leaf_hashes.is_empty() || internal_key == Some(pk) {
+            input.tap_key_sig.is_some()
+        } else {
+            input.tap_script_sig.keys().any(|(key, _)| *key == pk)
+        };
+        CosignerKey {
+            origin: derivation.origin,
+            signed,
+        }
+    }));
+    keys
+}
+
+/// Number of signatures required to spend the input. Key path spending needs a single
+/// signature; otherwise the threshold is taken from the multisig scripts present in the PSBT,
+/// or is one for inputs with a single key.
+fn threshold(input: &Input, keys: usize) -> Option<usize> {
+    if input.tap_key_sig.is_some() {
+        return Some(1);
+    }
+    let scripts = input
+        .redeem_script
+        .iter()
+        .map(|script| script.as_slice())
+        .chain(input.witness_script.iter().map(|script| script.as_slice()))
+        .chain(input.tap_leaf_script.values().map(|leaf| leaf.script.as_slice()));
+    scripts.filter_map(multisig_threshold).min().or((keys == 1).then_some(1))
+}
+
+/// Extracts the number of required signatures from `multi` (using `OP_CHECKMULTISIG`) and
+/// `multi_a` (using `OP_CHECKSIGADD`) scripts.
+fn multisig_threshold(script: &[u8]) -> Option<usize> {
+    match script {
+        [m @ OP_1..=OP_16, .., OP_1..=OP_16, OP_CHECKMULTISIG] => Some((m - OP_1 + 1) as usize),
+        [.., OP_CHECKSIG | OP_CHECKSIGADD, m @ OP_1..=OP_16, OP_NUMEQUAL] => {
+            Some((m - OP_1 + 1) as usize)
+        }
+        // Thresholds above 16 are pushed as a single byte
+        [.., OP_CHECKSIG | OP_CHECKSIGADD, 1, m, OP_NUMEQUAL] => Some(*m as usize),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multisig_threshold() {
+        let pk = [0x02; 33];
+        let xonly = [0x03; 32];
+
+        let mut multi = vec![0x52];
+        for _ in 0..3 {
+            multi.push(33);
+            multi.extend(pk);
+        }
+        multi.extend([0x53, OP_CHECKMULTISIG]);
+        assert_eq!(multisig_threshold(&multi), Some(2));
+
+        let mut multi_a = vec![32];
+        multi_a.extend(xonly);
+        multi_a.push(OP_CHECKSIG);
+        multi_a.push(32);
+        multi_a.extend(xonly);
+        multi_a.extend([OP_CHECKSIGADD, 0x52, OP_NUMEQUAL]);
+        assert_eq!(multisig_threshold(&multi_a), Some(2));
+
+        let len = multi_a.len();
+        multi_a.splice(len - 2..len - 1, [1, 17]);
+        assert_eq!(multisig_threshold(&multi_a), Some(17));
+
+        let mut p2pk = vec![33];
+        p2pk.extend(pk);
+        p2pk.push(OP_CHECKSIG);
+        assert_eq!(multisig_threshold(&p2pk), None);
+    }
+}
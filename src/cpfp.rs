@@ -0,0 +1,168 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Acceleration of unconfirmed transactions paying to the wallet with a child transaction
+//! spending their outputs (child-pays-for-parent, CPFP).
+
+use std::collections::BTreeSet;
+
+use bpstd::{Descriptor, Keychain, Outpoint, Sats, Terminal, Txid};
+use psbt::{Prevout, Psbt, PsbtConstructor};
+
+use crate::package::child_psbt;
+use crate::{FeeDeductor, FeeRate, Layer2, Wallet, SEQ_NO_RBF};
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CpfpError {
+    /// transaction {0} is not known to the wallet.
+    UnknownTx(Txid),
+
+    /// transaction {0} is already mined.
+    Mined(Txid),
+
+    /// transaction {0} has no unspent outputs belonging to the wallet.
+    NoOwnOutput(Txid),
+
+    /// transaction {0} together with its unconfirmed ancestors already pays {1}.
+    Sufficient(Txid, FeeRate),
+
+    /// child fee of {fee} sats leaves the spent output of {value} sats below the dust limit.
+    InsufficientValue { value: Sats, fee: Sats },
+}
+
+/// Metadata of a child transaction constructed by [`Wallet::construct_cpfp`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CpfpMeta {
+    /// Parent output spent by the child.
+    pub anchor: Outpoint,
+    /// Unconfirmed wallet transactions the parent depends on, which get mined together with it.
+    pub ancestors: BTreeSet<Txid>,
+    /// Terminal of the single child output returning the funds to the wallet.
+    pub change_terminal: Terminal,
+    pub change: Sats,
+    /// Fee of the child transaction.
+    pub fee: Sats,
+    /// Fee rate of the whole package: the child, the parent and the parent ancestors.
+    pub package_fee_rate: FeeRate,
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Constructs PSBT of a child transaction spending the largest wallet output of an
+    /// unconfirmed parent transaction, paying the fee which brings the fee rate of the package
+    /// (the child, the parent and its unconfirmed ancestors known to the wallet) to the given
+    /// rate.
+    ///
+    /// The child has a single output returning the remaining value to the next change address.
+    pub fn construct_cpfp(
+        &mut self,
+        parent: Txid,
+        fee_rate: FeeRate,
+    ) -> Result<(Psbt, CpfpMeta), CpfpError> {
+        let tx = self.transactions().get(&parent).ok_or(CpfpError::UnknownTx(parent))?;
+        if tx.status.is_mined() {
+            return Err(CpfpError::Mined(parent));
+        }
+        let (anchor, value, terminal) = tx
+            .outputs
+            .iter()
+            .filter(|output| output.spent.is_none())
+            .filter_map(|output| Some((output.outpoint, output.value, output.derived_addr()?)))
+            .max_by_key(|(_, value, _)| *value)
+            .map(|(outpoint, value, derived)| (outpoint, value, derived.terminal))
+            .ok_or(CpfpError::NoOwnOutput(parent))?;
+        let ancestors = self.unconfirmed_ancestors(parent);
+        let (package_fee, package_weight) = ancestors
+            .iter()
+            .chain([&parent])
+            .filter_map(|txid| self.transactions().get(txid))
+            .fold((Sats::ZERO, 0u32), |(fee, weight), tx| (fee + tx.fee, weight + tx.weight));
+        let current = FeeRate::from_fee(package_fee, package_weight);
+        if current >= fee_rate {
+            return Err(CpfpError::Sufficient(parent, current));
+        }
+
+        let prevout = Prevout::new(anchor, value);
+        let index = self.next_derivation_index(Keychain::INNER, false);
+        let change_terminal = Terminal::new(Keychain::INNER, index);
+        let child_weight = self.estimate_weight(&child_psbt(
+            self.descriptor(),
+            prevout,
+            terminal,
+            change_terminal,
+            value,
+            SEQ_NO_RBF,
+        ));
+        let fee = fee_rate
+            .fee_for_weight(package_weight + child_weight)
+            .checked_sub(package_fee)
+            .unwrap_or(Sats::ZERO);
+        let change = value
+            .checked_sub(fee)
+            .filter(|change| *change > self.descriptor().class().dust_limit())
+            .ok_or(CpfpError::InsufficientValue { value, fee })?;
+
+        self.next_derivation_index(Keychain::INNER, true);
+        let psbt =
+            child_psbt(self.descriptor(), prevout, terminal, change_terminal, change, SEQ_NO_RBF);
+        Ok((psbt, CpfpMeta {
+            anchor,
+            ancestors,
+            change_terminal,
+            change,
+            fee,
+            package_fee_rate: FeeRate::from_fee(package_fee + fee, package_weight + child_weight),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::Vout;
+
+    use super::*;
+    use crate::fixtures::{publish_payment, test_wallet};
+    use crate::SimIndexer;
+
+    #[test]
+    fn test_construct_cpfp() {
+        let mut wallet = test_wallet();
+        let indexer = SimIndexer::default();
+        let (funding, payment) = publish_payment(&mut wallet, &indexer, false);
+
+        let fee_rate = FeeRate::from_sat_per_vb(5);
+        let err = wallet.construct_cpfp(funding.txid(), fee_rate).unwrap_err();
+        assert_eq!(err, CpfpError::NoOwnOutput(funding.txid()));
+
+        let (psbt, meta) = wallet.construct_cpfp(payment.txid(), fee_rate).unwrap();
+        assert_eq!(meta.anchor, Outpoint::new(payment.txid(), Vout::from_u32(1)));
+        assert_eq!(meta.ancestors, bset![funding.txid()]);
+        assert_eq!(meta.change_terminal.keychain, Keychain::INNER);
+        assert_eq!(psbt.fee(), Some(meta.fee));
+        assert_eq!(meta.change, Sats(69_500) - meta.fee);
+        assert!(meta.package_fee_rate >= fee_rate);
+        let package_weight = wallet.transactions()[&funding.txid()].weight
+            + wallet.transactions()[&payment.txid()].weight
+            + wallet.estimate_weight(&psbt);
+        assert_eq!(meta.fee + Sats(500), fee_rate.fee_for_weight(package_weight));
+    }
+}
@@ -33,8 +33,52 @@ use bpstd::{
 };
 use psbt::{Prevout, Utxo};
 
+use crate::FeeRate;
+
+/// Height of a mined block, starting from one for the first block after genesis.
 pub type BlockHeight = NonZeroU32;
 
+/// Number of confirmations of a transaction, which is zero for transactions not mined yet.
+///
+/// Unlike block heights and timestamps, which are absolute, confirmations are always counted
+/// against some chain tip, see [`TxStatus::confirmations`].
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Display, From)]
+#[display(inner)]
+pub struct Confirmations(#[from] u32);
+
+impl Confirmations {
+    pub const ZERO: Self = Confirmations(0);
+
+    pub const fn new(confirmations: u32) -> Self { Confirmations(confirmations) }
+
+    pub const fn to_u32(self) -> u32 { self.0 }
+
+    pub const fn is_confirmed(self) -> bool { self.0 > 0 }
+}
+
+impl FromStr for Confirmations {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> { u32::from_str(s).map(Confirmations) }
+}
+
+/// Largest input sequence number signalling BIP-125 transaction replaceability.
+pub const SEQ_NO_RBF: SeqNo = SeqNo::from_consensus_u32(0xFFFF_FFFD);
+
+/// Input sequence number not signalling replaceability while keeping the transaction lock time
+/// enabled.
+pub const SEQ_NO_NO_RBF: SeqNo = SeqNo::from_consensus_u32(0xFFFF_FFFE);
+
+/// Detects whether an input sequence number signals BIP-125 replaceability.
+pub fn signals_rbf(seq_no: SeqNo) -> bool {
+    seq_no.to_consensus_u32() <= SEQ_NO_RBF.to_consensus_u32()
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(serde::Serialize, serde::Deserialize),
@@ -118,6 +162,17 @@ impl<T> TxStatus<T> {
     pub fn is_mined(&self) -> bool { matches!(self, Self::Mined(_)) }
 }
 
+impl TxStatus {
+    /// Number of confirmations given the height of the most recent block. Unmined transactions
+    /// have no confirmations; if the tip is outdated, mined transactions have one confirmation.
+    pub fn confirmations(&self, tip: BlockHeight) -> Confirmations {
+        match self {
+            TxStatus::Mined(info) => Confirmations(tip.get().saturating_sub(info.height.get()) + 1),
+            _ => Confirmations::ZERO,
+        }
+    }
+}
+
 impl<T> Display for TxStatus<T>
 where T: Display
 {
@@ -131,6 +186,81 @@ where T: Display
     }
 }
 
+/// Lifecycle stage of a wallet-created transaction which is not mined yet. Once the transaction
+/// gets mined it is removed from the pending transaction ledger.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum PendingStatus {
+    /// PSBT is constructed, but not yet fully signed.
+    Draft,
+    /// PSBT is finalized and the signed transaction is extracted.
+    Signed,
+    /// Transaction is published or is seen in the mempool.
+    Broadcast,
+}
+
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PendingTx {
+    pub status: PendingStatus,
+    /// Amount paid to the beneficiaries, not including change and fee.
+    pub amount: Sats,
+    pub fee: Sats,
+}
+
+/// Way the wallet has learned about a transaction.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum TxSource {
+    /// Transaction is reported by an indexer during the wallet sync.
+    Sync,
+    /// Transaction is constructed by the wallet.
+    Constructed,
+    /// Transaction is added to the cache manually, without an indexer.
+    Imported,
+}
+
+/// Record of how and when the wallet has learned about a transaction.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TxProvenance {
+    pub source: TxSource,
+    /// Unix timestamp at which the transaction has become known to the wallet.
+    pub acquired: u64,
+    /// Unix timestamp of the first sync in which an indexer has reported the transaction, if
+    /// the transaction was learned in another way.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub synced: Option<u64>,
+}
+
+impl TxProvenance {
+    pub fn new(source: TxSource, acquired: u64) -> Self {
+        TxProvenance {
+            source,
+            acquired,
+            synced: None,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
 #[cfg_attr(
     feature = "serde",
@@ -218,6 +348,12 @@ impl WalletTx {
 
     pub fn credited_debited(&self) -> (Sats, Sats) { (self.credit_sum(), self.debit_sum()) }
 
+    pub fn fee_rate(&self) -> FeeRate { FeeRate::from_fee(self.fee, self.weight) }
+
+    /// Detects whether the transaction signals BIP-125 replaceability, i.e. has at least one
+    /// input with a signalling sequence number.
+    pub fn signals_rbf(&self) -> bool { self.inputs.iter().any(TxCredit::signals_rbf) }
+
     pub fn balance_change(&self) -> i64 {
         let credit = self.credit_sum().sats_i64();
         let debit = self.debit_sum().sats_i64();
@@ -316,6 +452,7 @@ impl TxCredit {
     pub fn is_ourself(&self) -> bool { self.payer.is_ourself() }
     pub fn is_external(&self) -> bool { !self.is_ourself() }
     pub fn derived_addr(&self) -> Option<DerivedAddr> { self.payer.derived_addr() }
+    pub fn signals_rbf(&self) -> bool { signals_rbf(self.sequence) }
 }
 
 #[cfg_attr(
@@ -430,6 +567,31 @@ mod tests {
         assert_eq!(Inpoint::from_str(s).unwrap().to_string(), s);
     }
 
+    #[test]
+    fn test_rbf_signalling() {
+        assert!(signals_rbf(SeqNo::ZERO));
+        assert!(signals_rbf(SEQ_NO_RBF));
+        assert!(!signals_rbf(SEQ_NO_NO_RBF));
+        assert!(!signals_rbf(SeqNo::from_consensus_u32(u32::MAX)));
+        assert!(SEQ_NO_RBF.time_lock_interval().is_none());
+    }
+
+    #[test]
+    fn test_confirmations() {
+        let mut info = MiningInfo::genesis();
+        info.height = BlockHeight::new(100).unwrap();
+        let status = TxStatus::Mined(info);
+        assert_eq!(status.confirmations(BlockHeight::new(105).unwrap()), Confirmations::new(6));
+        assert_eq!(status.confirmations(BlockHeight::new(100).unwrap()), Confirmations::new(1));
+        assert_eq!(status.confirmations(BlockHeight::MIN), Confirmations::new(1));
+        assert_eq!(
+            TxStatus::Mempool.confirmations(BlockHeight::new(105).unwrap()),
+            Confirmations::ZERO
+        );
+        assert!(!Confirmations::ZERO.is_confirmed());
+        assert_eq!(Confirmations::from_str("6"), Ok(Confirmations::new(6)));
+    }
+
     #[test]
     fn test_party_str_round_trip() {
         fn assert_from_str_to_str(party: Party) {
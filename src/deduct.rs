@@ -0,0 +1,170 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deduction of the transaction fee from the payment outputs.
+
+use bpstd::{Descriptor, Sats, SpkClass, Tx, Weight};
+use psbt::{Psbt, PsbtConstructor};
+
+use crate::standardness::dust_threshold;
+use crate::FeeRate;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DeductError {
+    /// no outputs are given to deduct the fee from.
+    NoOutputs,
+
+    /// transaction has no output #{0} to deduct the fee from.
+    UnknownOutput(usize),
+
+    /// fee of {fee} sats exceeds the value of the outputs it is deducted from ({value} sats).
+    InsufficientValue { fee: Sats, value: Sats },
+
+    /// after the fee deduction output #{vout} has {amount} sats, which is below the dust limit.
+    Dust { vout: usize, amount: Sats },
+
+    /// output #{vout} of {amount} sats can't pay its share of the fee of {share} sats.
+    InsufficientOutput {
+        vout: usize,
+        amount: Sats,
+        share: Sats,
+    },
+}
+
+/// Estimated weight of a single-key input satisfaction for the given script class, not
+/// including the empty script sig length byte already present in the unsigned transaction.
+///
+/// For the classes which may have arbitrary satisfaction, the estimate assumes a single-key
+/// legacy signature.
+pub fn satisfaction_weight(class: SpkClass) -> u32 {
+    match class {
+        // witness item count, signature and public key pushes
+        SpkClass::P2wpkh => 1 + 1 + 72 + 1 + 33,
+        // witness item count and a BIP-340 signature push
+        SpkClass::P2tr => 1 + 1 + 64,
+        // script sig with signature and public key pushes
+        SpkClass::Bare | SpkClass::P2pkh | SpkClass::P2sh | SpkClass::P2wsh => {
+            (1 + 72 + 1 + 33) * 4
+        }
+    }
+}
+
+/// Deducts transaction fee from the payment outputs, such that the fee is paid by the
+/// beneficiaries and not by the wallet (for instance when sweeping all the wallet funds).
+pub trait FeeDeductor: PsbtConstructor {
+    /// Estimates weight of the transaction once it gets signed, using
+    /// [`satisfaction_weight`] for each of the inputs.
+    fn estimate_weight(&self, psbt: &Psbt) -> u32 {
+        let class = self.descriptor().class();
+        let mut weight = Tx::from(psbt.to_unsigned_tx()).weight_units().to_u32()
+            + satisfaction_weight(class) * psbt.inputs().count() as u32;
+        if matches!(class, SpkClass::P2wpkh | SpkClass::P2wsh | SpkClass::P2tr) {
+            // segwit marker and flag
+            weight += 2;
+        }
+        weight
+    }
+
+    /// Deducts the fee computed from the estimated transaction weight (see
+    /// [`FeeDeductor::estimate_weight`]) and the fee rate from the outputs with the given
+    /// indexes, returning the fee.
+    fn deduct_fee_rate(
+        &self,
+        psbt: &mut Psbt,
+        fee_rate: FeeRate,
+        deduct_fee_from: &[usize],
+    ) -> Result<Sats, DeductError> {
+        let fee = fee_rate.fee_for_weight(self.estimate_weight(psbt));
+        self.deduct_fee(psbt, fee, deduct_fee_from)?;
+        Ok(fee)
+    }
+
+    /// Deducts the fee from the outputs with the given indexes, proportionally to their
+    /// amounts. The rounding remainder is deducted from the first of the outputs.
+    ///
+    /// The PSBT must not already include the fee, i.e. the change must be computed for the zero
+    /// fee.
+    fn deduct_fee(
+        &self,
+        psbt: &mut Psbt,
+        fee: Sats,
+        deduct_fee_from: &[usize],
+    ) -> Result<(), DeductError> {
+        if deduct_fee_from.is_empty() {
+            return Err(DeductError::NoOutputs);
+        }
+        if let Some(no) = deduct_fee_from.iter().find(|no| **no >= psbt.outputs().count()) {
+            return Err(DeductError::UnknownOutput(*no));
+        }
+        let marked = |no: &usize| deduct_fee_from.contains(no);
+        let value = psbt
+            .outputs()
+            .filter(|output| marked(&output.index()))
+            .map(|output| output.amount)
+            .sum::<Sats>();
+        if fee >= value {
+            return Err(DeductError::InsufficientValue { fee, value });
+        }
+
+        let mut remainder = fee;
+        let mut deductions = psbt
+            .outputs()
+            .filter(|output| marked(&output.index()))
+            .map(|output| {
+                let share = Sats(
+                    (fee.sats() as u128 * output.amount.sats() as u128 / value.sats() as u128)
+                        as u64,
+                );
+                remainder -= share;
+                (output.index(), share)
+            })
+            .collect::<Vec<_>>();
+        deductions[0].1 += remainder;
+
+        // All the amounts are checked before any of the outputs gets modified, so the PSBT is
+        // left untouched on error
+        let mut amounts = Vec::with_capacity(deductions.len());
+        for (no, deduction) in deductions {
+            let output =
+                psbt.outputs().find(|output| output.index() == no).expect("output is present");
+            let amount =
+                output.amount.checked_sub(deduction).ok_or(DeductError::InsufficientOutput {
+                    vout: no,
+                    amount: output.amount,
+                    share: deduction,
+                })?;
+            if amount.sats() < dust_threshold(&output.script) {
+                return Err(DeductError::Dust { vout: no, amount });
+            }
+            amounts.push((no, amount));
+        }
+        for (no, amount) in amounts {
+            let output =
+                psbt.outputs_mut().find(|output| output.index() == no).expect("output is present");
+            output.amount = amount;
+        }
+        Ok(())
+    }
+}
+
+impl<T: PsbtConstructor> FeeDeductor for T {}
@@ -0,0 +1,113 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use bpstd::Txid;
+
+use crate::{Layer1Changes, Layer2Cache, MiningInfo, TxStatus, WalletAddr, WalletCache, WalletTx};
+
+/// Changes of the wallet cache since its earlier state, such as the ones made by a sync. Allows
+/// external systems to maintain their own database of the wallet data.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CacheDelta {
+    pub last_block: MiningInfo,
+    /// Transactions which were not known before.
+    pub added: Vec<WalletTx>,
+    /// Known transactions which status has changed.
+    pub updated: Vec<WalletTx>,
+    /// Transactions which are not a part of the wallet anymore.
+    pub removed: Vec<Txid>,
+    /// Addresses which were not known before or which statistics have changed.
+    pub addresses: Vec<WalletAddr>,
+}
+
+impl CacheDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.updated.is_empty()
+            && self.removed.is_empty()
+            && self.addresses.is_empty()
+    }
+}
+
+impl<L2: Layer2Cache> WalletCache<L2> {
+    /// Computes changes of the cache since the state with the given transaction statuses and
+    /// address statistics.
+    pub fn delta(
+        &self,
+        known_tx: &BTreeMap<Txid, TxStatus>,
+        known_addr: &BTreeSet<WalletAddr>,
+    ) -> CacheDelta {
+        let changes = Layer1Changes::with(known_tx, &self.tx);
+        CacheDelta {
+            last_block: self.last_block,
+            added: changes.added.into_iter().cloned().collect(),
+            updated: changes.updated.into_iter().cloned().collect(),
+            removed: changes.removed,
+            addresses: self
+                .addr
+                .values()
+                .flatten()
+                .filter(|addr| known_addr.get(*addr) != Some(*addr))
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bpstd::{Address, Keychain, NormalIndex, Sats, Terminal};
+
+    use super::*;
+    use crate::Layer2Empty;
+
+    #[test]
+    fn test_cache_delta() {
+        let addr = |index: u16, used: u32| WalletAddr {
+            terminal: Terminal::new(Keychain::OUTER, NormalIndex::from(index)),
+            addr: Address::from_str("tb1qvyhk5mlhphul2r6kdknrxqqhl37d0z7ycf2uu9").unwrap(),
+            used,
+            volume: Sats::from(used as u64 * 1000),
+            balance: Sats::ZERO,
+        };
+        let mut cache = WalletCache::<Layer2Empty>::new_nonsync();
+        cache.addr.insert(Keychain::OUTER, bset![addr(0, 1), addr(1, 2), addr(2, 0)]);
+
+        let known = bset![addr(0, 1), addr(1, 1)];
+        let delta = cache.delta(&none!(), &known);
+        assert_eq!(delta.addresses, vec![addr(1, 2), addr(2, 0)]);
+        assert!(delta.added.is_empty() && delta.removed.is_empty());
+        assert!(!delta.is_empty());
+
+        let known = cache.addr[&Keychain::OUTER].clone();
+        assert!(cache.delta(&none!(), &known).is_empty());
+    }
+}
@@ -0,0 +1,230 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dry-run mode, in which commands are executed without persisting anything or broadcasting
+//! transactions. All the mutations are routed through the [`DryRun`] collector, which either
+//! performs them or, in dry-run mode, records them for a preview.
+
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+
+use bpstd::Txid;
+
+/// Maximal number of the changed lines reported for a single file.
+const MAX_DIFF_LINES: usize = 20;
+
+/// Change of the persisted state or of the outside world made by a command.
+#[derive(Clone, Eq, PartialEq, Debug, From)]
+pub enum Change {
+    /// File is written. For text files contains lines removed from the file and added to it.
+    Write {
+        path: PathBuf,
+        created: bool,
+        diff: Option<(Vec<String>, Vec<String>)>,
+    },
+    /// Transaction is broadcast to the network.
+    #[from]
+    Broadcast(Txid),
+    /// Other operation, which can't be previewed in details.
+    #[from]
+    Other(String),
+}
+
+impl Change {
+    /// Constructs write change, comparing the data with the current content of the file.
+    pub fn write(path: impl Into<PathBuf>, data: &[u8]) -> Self {
+        let path = path.into();
+        let current = fs::read(&path).ok();
+        let diff = match (&current, std::str::from_utf8(data)) {
+            (Some(current), Ok(new)) => {
+                std::str::from_utf8(current).ok().map(|current| diff_lines(current, new))
+            }
+            (None, Ok(new)) => Some((vec![], new.lines().map(str::to_owned).collect())),
+            (_, Err(_)) => None,
+        };
+        Change::Write {
+            path,
+            created: current.is_none(),
+            diff,
+        }
+    }
+
+    /// Whether the change leaves the state intact, i.e. the file is re-written with the same
+    /// content.
+    pub fn is_noop(&self) -> bool {
+        matches!(self, Change::Write { created: false, diff: Some((removed, added)), .. }
+            if removed.is_empty() && added.is_empty())
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Write {
+                path,
+                created,
+                diff,
+            } => {
+                let verb = if *created { "create" } else { "write" };
+                write!(f, "{verb} {}", path.display())?;
+                let Some((removed, added)) = diff else {
+                    return Ok(());
+                };
+                let lines = removed.iter().map(|line| ('-', line));
+                let mut lines = lines.chain(added.iter().map(|line| ('+', line)));
+                for (sign, line) in lines.by_ref().take(MAX_DIFF_LINES) {
+                    write!(f, "\n  {sign} {line}")?;
+                }
+                let rest = lines.count();
+                if rest > 0 {
+                    write!(f, "\n  ... and {rest} more lines")?;
+                }
+                Ok(())
+            }
+            Change::Broadcast(txid) => write!(f, "broadcast transaction {txid}"),
+            Change::Other(description) => f.write_str(description),
+        }
+    }
+}
+
+/// Collector of the changes made by a command.
+///
+/// By default, the collector is disabled and all the operations are performed. Once enabled
+/// with [`DryRun::enabled`], the operations are recorded instead; the clones of the collector
+/// share the recorded changes.
+#[derive(Clone, Debug, Default)]
+pub struct DryRun(Option<Arc<Mutex<Vec<Change>>>>);
+
+impl PartialEq for DryRun {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (None, None) => true,
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for DryRun {}
+
+impl DryRun {
+    pub fn enabled() -> Self { DryRun(Some(default!())) }
+
+    pub fn is_enabled(&self) -> bool { self.0.is_some() }
+
+    /// Records a change, replacing the previous write into the same file. Does nothing if the
+    /// dry-run mode is not enabled.
+    pub fn record(&self, change: Change) {
+        let Some(changes) = &self.0 else {
+            return;
+        };
+        let mut changes = changes.lock().expect("poisoned lock");
+        let mut change = change;
+        if let Change::Write { path, created, .. } = &mut change {
+            let prev = changes
+                .iter()
+                .position(|c| matches!(c, Change::Write { path: p, .. } if p == path));
+            // The file written multiple times is still created by the command
+            if let Some(Change::Write {
+                created: was_created,
+                ..
+            }) = prev.map(|pos| changes.remove(pos))
+            {
+                *created |= was_created;
+            }
+        }
+        changes.push(change);
+    }
+
+    /// Changes recorded so far, excluding the files re-written with the same content.
+    pub fn changes(&self) -> Vec<Change> {
+        let Some(changes) = &self.0 else {
+            return vec![];
+        };
+        let changes = changes.lock().expect("poisoned lock");
+        changes.iter().filter(|change| !change.is_noop()).cloned().collect()
+    }
+
+    /// Writes data to the file, or records the write in dry-run mode.
+    pub fn write(&self, path: impl AsRef<Path>, data: impl AsRef<[u8]>) -> io::Result<()> {
+        if self.is_enabled() {
+            self.record(Change::write(path.as_ref(), data.as_ref()));
+            Ok(())
+        } else {
+            fs::write(path, data)
+        }
+    }
+
+    /// Checks whether an operation must be performed. In dry-run mode the change made by the
+    /// operation is recorded instead and `false` is returned.
+    pub fn perform(&self, change: impl Into<Change>) -> bool {
+        if self.is_enabled() {
+            self.record(change.into());
+            return false;
+        }
+        true
+    }
+}
+
+/// Computes lines removed and added to the text, ignoring the common beginning and ending.
+fn diff_lines(old: &str, new: &str) -> (Vec<String>, Vec<String>) {
+    let old = old.lines().collect::<Vec<_>>();
+    let new = new.lines().collect::<Vec<_>>();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let lines = |text: &[&str]| text.iter().map(|line| line.to_string()).collect();
+    (lines(&old[prefix..old.len() - suffix]), lines(&new[prefix..new.len() - suffix]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let dry_run = DryRun::enabled();
+        let path = std::env::temp_dir().join(format!("bp-dry-run-{}", std::process::id()));
+        fs::write(&path, "a\nb\nc\n").unwrap();
+        dry_run.write(&path, "a\nx\nc\n").unwrap();
+        dry_run.write(&path, "a\ny\nc\n").unwrap();
+        assert!(!dry_run.perform(s!("remove everything")));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+        fs::remove_file(path.clone()).unwrap();
+
+        assert_eq!(dry_run.changes(), vec![
+            Change::Write {
+                path,
+                created: false,
+                diff: Some((vec![s!("b")], vec![s!("y")]))
+            },
+            Change::Other(s!("remove everything"))
+        ]);
+        assert!(DryRun::default().perform(Txid::from([1u8; 32])));
+    }
+}
@@ -0,0 +1,234 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Type-erased wallets, allowing to keep wallets with different descriptor types, selected at
+//! runtime, in a single collection.
+
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug, Formatter};
+
+use bpstd::{
+    Address, Derive, DeriveScripts, Descriptor, Keychain, Network, NormalIndex, Sats, SpkClass,
+    Terminal, Txid, XpubAccount, XpubDerivable,
+};
+use nonasync::persistence::PersistenceError;
+use psbt::PsbtConstructor;
+
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+use crate::indexers::{AnyIndexer, AnyIndexerError};
+#[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+use crate::MayError;
+use crate::{KeychainStatus, Layer2, Wallet, WalletTx, WalletUtxo};
+
+/// Object-safe subset of the [`Descriptor`] operations, implemented by all descriptors over
+/// extended public keys.
+pub trait DynDescriptor {
+    fn class(&self) -> SpkClass;
+    fn keychains(&self) -> BTreeSet<Keychain>;
+    fn xpubs(&self) -> Vec<XpubAccount>;
+    /// Derives address for the given terminal, if the descriptor can be represented with one.
+    fn derive_address(&self, network: Network, terminal: Terminal) -> Option<Address>;
+}
+
+impl<D: Descriptor<XpubDerivable>> DynDescriptor for D {
+    fn class(&self) -> SpkClass { Descriptor::class(self) }
+
+    fn keychains(&self) -> BTreeSet<Keychain> { <D as Derive<_>>::keychains(self) }
+
+    fn xpubs(&self) -> Vec<XpubAccount> { Descriptor::xpubs(self).cloned().collect() }
+
+    fn derive_address(&self, network: Network, terminal: Terminal) -> Option<Address> {
+        DeriveScripts::derive_address(self, network.into(), terminal.keychain, terminal.index).ok()
+    }
+}
+
+/// Object-safe wallet operations not depending on the wallet generic parameters.
+trait ErasedWallet {
+    fn name(&self) -> &str;
+    fn network(&self) -> Network;
+    fn descriptor(&self) -> &dyn DynDescriptor;
+    fn balance(&self) -> Sats;
+    fn transactions(&self) -> &BTreeMap<Txid, WalletTx>;
+    fn utxos(&self) -> Vec<WalletUtxo>;
+    fn keychain_status(&self, keychain: Keychain) -> KeychainStatus;
+    fn next_address(&mut self, keychain: Keychain, shift: bool) -> Address;
+    fn store(&mut self) -> Result<(), PersistenceError>;
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+    fn update(&mut self, indexer: &AnyIndexer) -> MayError<(), Vec<AnyIndexerError>>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<D, L2> ErasedWallet for Wallet<XpubDerivable, D, L2>
+where
+    D: Descriptor<XpubDerivable> + 'static,
+    L2: Layer2 + 'static,
+{
+    fn name(&self) -> &str { Wallet::name(self) }
+
+    fn network(&self) -> Network { PsbtConstructor::network(self) }
+
+    fn descriptor(&self) -> &dyn DynDescriptor { PsbtConstructor::descriptor(self) }
+
+    fn balance(&self) -> Sats { Wallet::balance(self) }
+
+    fn transactions(&self) -> &BTreeMap<Txid, WalletTx> { Wallet::transactions(self) }
+
+    fn utxos(&self) -> Vec<WalletUtxo> { Wallet::utxos(self).collect() }
+
+    fn keychain_status(&self, keychain: Keychain) -> KeychainStatus {
+        Wallet::keychain_status(self, keychain)
+    }
+
+    fn next_address(&mut self, keychain: Keychain, shift: bool) -> Address {
+        Wallet::next_address(self, keychain, shift)
+    }
+
+    fn store(&mut self) -> Result<(), PersistenceError> { Wallet::store(self) }
+
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+    fn update(&mut self, indexer: &AnyIndexer) -> MayError<(), Vec<AnyIndexerError>> {
+        Wallet::update(self, indexer)
+    }
+
+    fn as_any(&self) -> &dyn Any { self }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any { self }
+}
+
+/// Wallet with the descriptor and layer 2 types erased, such that wallets with different
+/// descriptors may be kept in a single collection.
+///
+/// Provides the operations which don't depend on the concrete wallet type; the concrete wallet
+/// can be recovered with [`DynWallet::downcast_ref`] and [`DynWallet::downcast_mut`].
+pub struct DynWallet(Box<dyn ErasedWallet>);
+
+impl<D, L2> From<Wallet<XpubDerivable, D, L2>> for DynWallet
+where
+    D: Descriptor<XpubDerivable> + 'static,
+    L2: Layer2 + 'static,
+{
+    fn from(wallet: Wallet<XpubDerivable, D, L2>) -> Self { DynWallet(Box::new(wallet)) }
+}
+
+impl Debug for DynWallet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynWallet")
+            .field("name", &self.name())
+            .field("network", &self.network())
+            .field("class", &self.descriptor().class())
+            .finish_non_exhaustive()
+    }
+}
+
+impl DynWallet {
+    pub fn name(&self) -> &str { self.0.name() }
+
+    pub fn network(&self) -> Network { self.0.network() }
+
+    pub fn descriptor(&self) -> &dyn DynDescriptor { self.0.descriptor() }
+
+    pub fn keychains(&self) -> BTreeSet<Keychain> { self.descriptor().keychains() }
+
+    pub fn balance(&self) -> Sats { self.0.balance() }
+
+    pub fn transactions(&self) -> &BTreeMap<Txid, WalletTx> { self.0.transactions() }
+
+    pub fn utxos(&self) -> Vec<WalletUtxo> { self.0.utxos() }
+
+    pub fn keychain_status(&self, keychain: impl Into<Keychain>) -> KeychainStatus {
+        self.0.keychain_status(keychain.into())
+    }
+
+    /// Derives wallet address without registering it as issued.
+    pub fn derive_address(
+        &self,
+        keychain: impl Into<Keychain>,
+        index: NormalIndex,
+    ) -> Option<Address> {
+        self.descriptor().derive_address(self.network(), Terminal::new(keychain.into(), index))
+    }
+
+    pub fn next_address(&mut self, keychain: impl Into<Keychain>, shift: bool) -> Address {
+        self.0.next_address(keychain.into(), shift)
+    }
+
+    pub fn store(&mut self) -> Result<(), PersistenceError> { self.0.store() }
+
+    #[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
+    pub fn update(&mut self, indexer: &AnyIndexer) -> MayError<(), Vec<AnyIndexerError>> {
+        self.0.update(indexer)
+    }
+
+    /// Returns the concrete wallet, if it has the given descriptor and layer 2 types.
+    pub fn downcast_ref<D, L2>(&self) -> Option<&Wallet<XpubDerivable, D, L2>>
+    where
+        D: Descriptor<XpubDerivable> + 'static,
+        L2: Layer2 + 'static,
+    {
+        self.0.as_any().downcast_ref()
+    }
+
+    /// Returns the concrete wallet, if it has the given descriptor and layer 2 types.
+    pub fn downcast_mut<D, L2>(&mut self) -> Option<&mut Wallet<XpubDerivable, D, L2>>
+    where
+        D: Descriptor<XpubDerivable> + 'static,
+        L2: Layer2 + 'static,
+    {
+        self.0.as_any_mut().downcast_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{Idx, IdxBase};
+    use descriptors::{TrKey, Wpkh};
+
+    use super::*;
+    use crate::fixtures::{test_wallet, test_xpub};
+    use crate::NoLayer2;
+
+    #[test]
+    fn heterogeneous_wallets() {
+        let wpkh = test_wallet();
+        let tr = Wallet::new_layer1(TrKey::from(test_xpub()), Network::Testnet3);
+        let expected = [
+            wpkh.addresses(Keychain::OUTER).next().unwrap().addr,
+            tr.addresses(Keychain::OUTER).next().unwrap().addr,
+        ];
+
+        let mut wallets = [DynWallet::from(wpkh), DynWallet::from(tr)];
+        for (wallet, addr) in wallets.iter_mut().zip(expected) {
+            assert_eq!(wallet.network(), Network::Testnet3);
+            assert_eq!(wallet.keychains(), bset![Keychain::OUTER, Keychain::INNER]);
+            assert_eq!(wallet.descriptor().xpubs().len(), 1);
+            assert_eq!(wallet.balance(), Sats::ZERO);
+            assert_eq!(wallet.derive_address(Keychain::OUTER, NormalIndex::ZERO), Some(addr));
+            assert_eq!(wallet.next_address(Keychain::OUTER, true), addr);
+            assert_eq!(wallet.keychain_status(Keychain::OUTER).next_index.index(), 1);
+        }
+        assert!(wallets[0].downcast_ref::<Wpkh<XpubDerivable>, NoLayer2>().is_some());
+        assert!(wallets[0].downcast_ref::<TrKey<XpubDerivable>, NoLayer2>().is_none());
+        assert!(wallets[1].downcast_mut::<TrKey<XpubDerivable>, NoLayer2>().is_some());
+    }
+}
@@ -0,0 +1,243 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Construction of transactions paying the fee at a given fee rate.
+
+use bpstd::{Keychain, Outpoint, Sats, SeqNo};
+use psbt::{Beneficiary, ConstructionError, Psbt, PsbtMeta, TxParams};
+
+use crate::{FeeDeductor, FeeRate, FeeSpec};
+
+/// Maximal number of the transaction re-constructions done while estimating its fee, see
+/// [`FeeEstimator::converge_fee`].
+pub const MAX_FEE_ITERATIONS: usize = 8;
+
+/// Parameters of a transaction with the fee given by a fee rate, which unlike [`TxParams`]
+/// don't require the fee to be known before the transaction weight.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct FeeParams {
+    /// Fee rate of the signed transaction.
+    pub fee_rate: FeeRate,
+    /// Absolute fee overriding the fee rate.
+    pub fee: Option<Sats>,
+    pub seq_no: SeqNo,
+    pub change_shift: bool,
+    pub change_keychain: Keychain,
+}
+
+impl FeeParams {
+    /// Parameters paying the fee at the given fee rate, with the other parameters being the
+    /// same as in [`TxParams::with`].
+    pub fn with(fee_rate: FeeRate) -> Self {
+        let params = TxParams::with(Sats::ZERO);
+        FeeParams {
+            fee_rate,
+            fee: None,
+            seq_no: params.seq_no,
+            change_shift: params.change_shift,
+            change_keychain: params.change_keychain,
+        }
+    }
+
+    /// Parameters paying the given absolute fee.
+    pub fn with_fee(fee: Sats) -> Self {
+        FeeParams {
+            fee: Some(fee),
+            ..FeeParams::with(FeeRate::ZERO)
+        }
+    }
+
+    /// Converts into the transaction parameters paying the given fee.
+    pub fn to_tx_params(&self, fee: Sats) -> TxParams {
+        TxParams {
+            fee,
+            seq_no: self.seq_no,
+            change_shift: self.change_shift,
+            change_keychain: self.change_keychain,
+            ..TxParams::with(fee)
+        }
+    }
+}
+
+impl From<FeeSpec> for FeeParams {
+    fn from(fee: FeeSpec) -> Self {
+        match fee {
+            FeeSpec::Absolute(fee) => FeeParams::with_fee(fee),
+            FeeSpec::Rate(fee_rate) => FeeParams::with(fee_rate),
+        }
+    }
+}
+
+/// Constructor of transactions paying the fee at a fee rate, computing the fee from the
+/// estimated weight of the final signed transaction, including its change output.
+pub trait FeeEstimator: FeeDeductor {
+    /// Computes the fee of the transaction paying to the beneficiaries at the fee rate.
+    ///
+    /// Since the fee affects the change and thus the transaction weight, the transaction is
+    /// re-constructed (without shifting the change derivation index) with the fee estimated
+    /// from its previous version, until the fee covers the estimated weight. Errors with
+    /// [`ConstructionError::NoFundsForFee`] if the fee doesn't converge within
+    /// [`MAX_FEE_ITERATIONS`]. If the absolute fee is given, it is returned as is.
+    fn converge_fee(
+        &mut self,
+        coins: &[Outpoint],
+        beneficiaries: &[Beneficiary],
+        params: FeeParams,
+    ) -> Result<Sats, ConstructionError> {
+        self.converge_fee_with(params, |_, _| Ok((coins.to_vec(), beneficiaries.to_vec())))
+            .map(|(fee, ..)| fee)
+    }
+
+    /// Computes the fee like [`FeeEstimator::converge_fee`], with the coins and the
+    /// beneficiaries selected by `select` for each estimated fee. This allows to re-select the
+    /// coins when they don't cover the grown fee, or to pay shares of the balance remaining after
+    /// the fee.
+    ///
+    /// Returns the fee together with the coins and the beneficiaries selected for it.
+    fn converge_fee_with<E: From<ConstructionError>>(
+        &mut self,
+        params: FeeParams,
+        mut select: impl FnMut(&mut Self, Sats) -> Result<(Vec<Outpoint>, Vec<Beneficiary>), E>,
+    ) -> Result<(Sats, Vec<Outpoint>, Vec<Beneficiary>), E> {
+        if let Some(fee) = params.fee {
+            let (coins, beneficiaries) = select(self, fee)?;
+            return Ok((fee, coins, beneficiaries));
+        }
+        let mut fee = Sats::ZERO;
+        let (mut coins, mut beneficiaries) = select(self, fee)?;
+        let mut iterations = 0;
+        loop {
+            let (psbt, _) =
+                self.construct_psbt(coins.iter().copied(), &beneficiaries, TxParams {
+                    change_shift: false,
+                    ..params.to_tx_params(fee)
+                })?;
+            let estimate = params.fee_rate.fee_for_weight(self.estimate_weight(&psbt));
+            if estimate <= fee {
+                return Ok((fee, coins, beneficiaries));
+            }
+            if iterations == MAX_FEE_ITERATIONS {
+                return Err(ConstructionError::NoFundsForFee {
+                    input_value: psbt.input_sum(),
+                    output_value: psbt.output_sum(),
+                    fee: estimate,
+                }
+                .into());
+            }
+            iterations += 1;
+            fee = estimate;
+            (coins, beneficiaries) = select(self, fee)?;
+        }
+    }
+
+    /// Constructs PSBT paying to the beneficiaries with the fee computed by
+    /// [`FeeEstimator::converge_fee`], returning the fee together with the PSBT.
+    fn construct_psbt_at(
+        &mut self,
+        coins: &[Outpoint],
+        beneficiaries: &[Beneficiary],
+        params: FeeParams,
+    ) -> Result<(Psbt, PsbtMeta, Sats), ConstructionError> {
+        let fee = self.converge_fee(coins, beneficiaries, params)?;
+        let (psbt, meta) =
+            self.construct_psbt(coins.iter().copied(), beneficiaries, params.to_tx_params(fee))?;
+        Ok((psbt, meta, fee))
+    }
+}
+
+impl<T: FeeDeductor> FeeEstimator for T {}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{Txid, Vout};
+    use psbt::PsbtConstructor;
+
+    use super::*;
+    use crate::fixtures::{test_payee, TestCoins};
+
+    #[test]
+    fn test_converge_fee() {
+        let mut coins = TestCoins::new(Sats(10_000));
+        let outpoints = (0..3)
+            .map(|vout| Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(vout)))
+            .collect::<Vec<_>>();
+        let beneficiaries = [Beneficiary::new(test_payee(), Sats(15_000))];
+
+        let fee_rate = FeeRate::from_sat_per_vb(2);
+        let (psbt, meta, fee) =
+            coins.construct_psbt_at(&outpoints, &beneficiaries, FeeParams::with(fee_rate)).unwrap();
+        assert!(meta.change_vout.is_some());
+        assert_eq!(psbt.fee(), Some(fee));
+        assert_eq!(fee, fee_rate.fee_for_weight(coins.estimate_weight(&psbt)));
+
+        let (psbt, _, fee) = coins
+            .construct_psbt_at(&outpoints, &beneficiaries, FeeParams::with_fee(Sats(500)))
+            .unwrap();
+        assert_eq!(fee, Sats(500));
+        assert_eq!(psbt.fee(), Some(Sats(500)));
+    }
+
+    #[test]
+    fn test_converge_fee_with() {
+        let mut coins = TestCoins::new(Sats(10_000));
+        let beneficiaries = vec![Beneficiary::new(test_payee(), Sats(19_800))];
+        // Selects the coins covering the payment with the fee, which requires the third coin
+        // only after the fee gets estimated
+        let select = |_: &mut TestCoins, fee: Sats| -> Result<_, ConstructionError> {
+            let required = (Sats(19_800) + fee).sats().div_ceil(10_000) as u32;
+            let coins = (0..required)
+                .map(|vout| Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(vout)))
+                .collect::<Vec<_>>();
+            Ok((coins, beneficiaries.clone()))
+        };
+
+        let fee_rate = FeeRate::from_sat_per_vb(2);
+        let (fee, outpoints, _) =
+            coins.converge_fee_with(FeeParams::with(fee_rate), select).unwrap();
+        assert_eq!(outpoints.len(), 3);
+        let (psbt, _) = coins
+            .construct_psbt(outpoints, &beneficiaries, FeeParams::with(fee_rate).to_tx_params(fee))
+            .unwrap();
+        assert_eq!(fee, fee_rate.fee_for_weight(coins.estimate_weight(&psbt)));
+    }
+
+    #[test]
+    fn test_converge_fee_with_exhausted() {
+        let mut coins = TestCoins::new(Sats(10_000));
+        let beneficiaries = vec![Beneficiary::new(test_payee(), Sats(5_000))];
+        // Adds a coin on each selection, so the fee never covers the grown weight
+        let mut count = 0;
+        let select = |_: &mut TestCoins, _: Sats| -> Result<_, ConstructionError> {
+            count += 1;
+            let coins = (0..count)
+                .map(|vout| Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(vout)))
+                .collect::<Vec<_>>();
+            Ok((coins, beneficiaries.clone()))
+        };
+
+        let fee_rate = FeeRate::from_sat_per_vb(2);
+        assert!(matches!(
+            coins.converge_fee_with(FeeParams::with(fee_rate), select),
+            Err(ConstructionError::NoFundsForFee { .. })
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bpstd::Sats;
+
+use crate::{parse_sats, AmountParseError};
+
+/// Number of weight units in a virtual byte.
+pub const WU_PER_VBYTE: u64 = 4;
+
+/// Unit used to express fee rates.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Display)]
+pub enum FeeUnit {
+    /// Satoshis per virtual byte.
+    #[default]
+    #[display("sat/vb")]
+    SatPerVb,
+
+    /// Satoshis per weight unit.
+    #[display("sat/wu")]
+    SatPerWu,
+}
+
+/// unknown fee rate unit '{0}'; use either `sat/vb` or `sat/wu`.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct UnknownFeeUnit(String);
+
+impl FromStr for FeeUnit {
+    type Err = UnknownFeeUnit;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sat/vb" | "sat/vbyte" => Ok(FeeUnit::SatPerVb),
+            "sat/wu" => Ok(FeeUnit::SatPerWu),
+            _ => Err(UnknownFeeUnit(s.to_owned())),
+        }
+    }
+}
+
+/// Fee rate, stored with the precision of a satoshi per 1000 weight units.
+///
+/// Any stored value is displayed exactly with three decimal digits both in satoshis per virtual
+/// byte and in satoshis per weight unit, which is the precision used by the [`Display`]
+/// implementation (unless overridden by the formatter). The opposite is not true: the step of
+/// the stored value is 0.004 sat/vB, so fractional sat/vB values which are not its multiples are
+/// rounded up when parsed. Fee rates computed from a fee and a transaction weight are rounded to
+/// the nearest value, while fees computed from a fee rate are rounded up, such that the
+/// resulting transaction never pays less than the requested rate.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    #[inline]
+    pub const fn from_sat_per_kwu(sat_per_kwu: u64) -> Self { FeeRate(sat_per_kwu) }
+
+    /// Constructs fee rate from a whole number of satoshis per virtual byte. The conversion is
+    /// exact, since a virtual byte is four weight units, except for the values exceeding the
+    /// maximal representable fee rate, which are saturated to it.
+    #[inline]
+    pub const fn from_sat_per_vb(sat_per_vb: u64) -> Self {
+        FeeRate(sat_per_vb.saturating_mul(1000 / WU_PER_VBYTE))
+    }
+
+    /// Converts fee rate in satoshis per virtual byte reported by indexers, rounding up to the
+    /// representable value. Negative and non-finite values give zero fee rate.
+    pub fn from_sat_per_vb_f64(sat_per_vb: f64) -> Self {
+        if !sat_per_vb.is_finite() || sat_per_vb <= 0.0 {
+            return FeeRate::ZERO;
+        }
+        let milli = (sat_per_vb * 1000.0 / WU_PER_VBYTE as f64).ceil();
+        FeeRate(milli.min(u64::MAX as f64) as u64)
+    }
+
+    /// Computes fee rate paid by a transaction of the given weight, rounding to the nearest
+    /// representable value. Zero weight gives zero fee rate.
+    pub fn from_fee(fee: Sats, weight: u32) -> Self {
+        if weight == 0 {
+            return FeeRate::ZERO;
+        }
+        let weight = weight as u128;
+        let rate = (fee.sats() as u128 * 1000 + weight / 2) / weight;
+        FeeRate(rate.min(u64::MAX as u128) as u64)
+    }
+
+    #[inline]
+    pub const fn sat_per_kwu(self) -> u64 { self.0 }
+
+    #[inline]
+    pub fn sat_per_vb(self) -> f64 { self.0 as f64 * WU_PER_VBYTE as f64 / 1000.0 }
+
+    #[inline]
+    pub fn sat_per_wu(self) -> f64 { self.0 as f64 / 1000.0 }
+
+    pub fn to_unit(self, unit: FeeUnit) -> f64 {
+        match unit {
+            FeeUnit::SatPerVb => self.sat_per_vb(),
+            FeeUnit::SatPerWu => self.sat_per_wu(),
+        }
+    }
+
+    /// Computes fee for a transaction of the given weight, rounding up.
+    pub fn fee_for_weight(self, weight: u32) -> Sats {
+        Sats((self.0 as u128 * weight as u128).div_ceil(1000).min(u64::MAX as u128) as u64)
+    }
+
+    /// Returns a value displaying the fee rate in the given unit.
+    pub fn display(self, unit: FeeUnit) -> FeeRateDisplay { FeeRateDisplay { rate: self, unit } }
+}
+
+impl Display for FeeRate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.display(FeeUnit::SatPerVb), f)
+    }
+}
+
+/// Errors parsing fee rate.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum FeeRateParseError {
+    /// invalid fee rate value '{0}'; it must be a decimal number with at most three fractional
+    /// digits followed by a unit.
+    InvalidValue(String),
+
+    /// {0}
+    #[from]
+    Unit(UnknownFeeUnit),
+}
+
+impl FromStr for FeeRate {
+    type Err = FeeRateParseError;
+
+    /// Parses fee rate given as a decimal number followed by `sat/vb` or `sat/wu` unit (for
+    /// instance `2.5 sat/vb`). Fractions of sat/vb which can't be represented exactly are rounded
+    /// up.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let err = || FeeRateParseError::InvalidValue(s.to_owned());
+        let split = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let unit = FeeUnit::from_str(unit.trim_start())?;
+        let (int, frac) = number.split_once('.').unwrap_or((number, ""));
+        if int.is_empty() || frac.len() > 3 || frac.contains('.') || number.ends_with('.') {
+            return Err(err());
+        }
+        let int = u64::from_str(int).map_err(|_| err())?;
+        let frac = if frac.is_empty() {
+            0
+        } else {
+            u64::from_str(&format!("{frac:0<3}")).map_err(|_| err())?
+        };
+        let milli =
+            int.checked_mul(1000).and_then(|milli| milli.checked_add(frac)).ok_or_else(err)?;
+        Ok(match unit {
+            FeeUnit::SatPerVb => FeeRate(milli.div_ceil(WU_PER_VBYTE)),
+            FeeUnit::SatPerWu => FeeRate(milli),
+        })
+    }
+}
+
+/// Transaction fee, given either as an absolute amount or as a fee rate.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, From)]
+pub enum FeeSpec {
+    #[from]
+    #[display(inner)]
+    Absolute(Sats),
+
+    #[from]
+    #[display(inner)]
+    Rate(FeeRate),
+}
+
+/// Errors parsing transaction fee, see [`parse_fee`].
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(inner)]
+pub enum FeeParseError {
+    #[from]
+    Amount(AmountParseError),
+
+    #[from]
+    Rate(FeeRateParseError),
+}
+
+/// Parses transaction fee, which is a fee rate when the string has a unit of fee rate (like
+/// `2 sat/vb`), or an amount otherwise. Can be used as a `clap` value parser.
+pub fn parse_fee(s: &str) -> Result<FeeSpec, FeeParseError> {
+    if s.contains('/') {
+        return Ok(FeeRate::from_str(s).map(FeeSpec::Rate)?);
+    }
+    Ok(parse_sats(s).map(FeeSpec::Absolute)?)
+}
+
+/// Fee rate displayed in a specific unit, see [`FeeRate::display`].
+///
+/// The alternate form (`{:#}`) omits the unit suffix, which is useful for tables.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct FeeRateDisplay {
+    rate: FeeRate,
+    unit: FeeUnit,
+}
+
+impl Display for FeeRateDisplay {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().unwrap_or(3);
+        let value = format!("{:.*}", precision, self.rate.to_unit(self.unit));
+        if f.alternate() {
+            f.pad_integral(true, "", &value)
+        } else {
+            f.pad_integral(true, "", &format!("{value} {}", self.unit))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fee_rate_rounding() {
+        let rate = FeeRate::from_fee(Sats(141), 562);
+        assert_eq!(rate.sat_per_kwu(), 251);
+        assert_eq!(rate.to_string(), "1.004 sat/vb");
+        assert_eq!(format!("{:.2}", rate.display(FeeUnit::SatPerWu)), "0.25 sat/wu");
+        assert_eq!(format!("{:>12.2}", rate.display(FeeUnit::SatPerVb)), " 1.00 sat/vb");
+        assert_eq!(format!("{:>#8.2}", rate.display(FeeUnit::SatPerVb)), "    1.00");
+        assert_eq!(FeeRate::from_sat_per_vb(1).fee_for_weight(561), Sats(141));
+        assert_eq!(FeeRate::from_fee(Sats(100), 0), FeeRate::ZERO);
+        assert_eq!(FeeUnit::from_str("sat/vB"), Ok(FeeUnit::SatPerVb));
+        assert_eq!(FeeRate::from_sat_per_vb_f64(2.5), FeeRate::from_sat_per_kwu(625));
+        assert_eq!(FeeRate::from_sat_per_vb_f64(-1.0), FeeRate::ZERO);
+    }
+
+    #[test]
+    fn test_from_sat_per_vb() {
+        assert_eq!(FeeRate::from_sat_per_vb(3), FeeRate::from_sat_per_kwu(750));
+        assert_eq!(FeeRate::from_sat_per_vb(u64::MAX), FeeRate::from_sat_per_kwu(u64::MAX));
+    }
+
+    #[test]
+    fn test_fee_parse() {
+        assert_eq!(FeeRate::from_str("2.5 sat/vb"), Ok(FeeRate::from_sat_per_kwu(625)));
+        assert_eq!(FeeRate::from_str("0.001sat/vb"), Ok(FeeRate::from_sat_per_kwu(1)));
+        assert_eq!(FeeRate::from_str("0.25 sat/wu"), Ok(FeeRate::from_sat_per_kwu(250)));
+        assert_eq!(
+            FeeRate::from_str(&FeeRate::from_sat_per_kwu(251).to_string()).unwrap().sat_per_kwu(),
+            251
+        );
+        assert!(FeeRate::from_str("1.0001 sat/vb").is_err());
+        assert!(FeeRate::from_str("2").is_err());
+        assert_eq!(parse_fee("1000"), Ok(FeeSpec::Absolute(Sats(1000))));
+        assert_eq!(parse_fee("2 sat/vb"), Ok(FeeSpec::Rate(FeeRate::from_sat_per_vb(2))));
+        assert!(matches!(
+            parse_fee("2 sat/kb"),
+            Err(FeeParseError::Rate(FeeRateParseError::Unit(_)))
+        ));
+        assert!(matches!(parse_fee("1,5"), Err(FeeParseError::Amount(_))));
+    }
+}
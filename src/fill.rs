@@ -0,0 +1,189 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Filling in PSBTs created elsewhere with the wallet knowledge, so that their inputs can be
+//! signed by the wallet signers.
+
+use bpstd::{Descriptor, InternalPk, Outpoint, Tx, TxOut, Txid};
+use psbt::{Psbt, PsbtConstructor};
+
+use crate::{Layer2, Wallet};
+
+/// Result of filling a PSBT with [`Wallet::fill_psbt`].
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct PsbtFill {
+    /// Number of inputs which got their spent transaction outputs or full transactions filled
+    /// in.
+    pub utxos: usize,
+    /// Number of inputs and outputs which got derivations of the wallet keys filled in.
+    pub derivations: usize,
+    /// Inputs which full spent transaction remains unknown.
+    pub unknown: Vec<Outpoint>,
+}
+
+/// Adds derivations of the wallet keys for the terminal to a PSBT input or output, keeping the
+/// existing entries. Evaluates to whether anything was added.
+macro_rules! fill_keys {
+    ($descriptor:expr, $terminal:expr, $script:expr, $target:expr) => {{
+        let mut filled = false;
+        let taproot = $script.is_p2tr();
+        for (pk, origin) in $descriptor.legacy_keyset($terminal) {
+            if !$target.bip32_derivation.contains_key(&pk) {
+                $target.bip32_derivation.insert(pk, origin);
+                filled = true;
+            }
+        }
+        for (pk, derivation) in $descriptor.xonly_keyset($terminal) {
+            // Only the internal key is derived without any of the script leaves
+            if taproot && derivation.leaf_hashes.is_empty() && $target.tap_internal_key.is_none() {
+                $target.tap_internal_key = Some(InternalPk::from_unchecked(pk));
+                filled = true;
+            }
+            if !$target.tap_bip32_derivation.contains_key(&pk) {
+                $target.tap_bip32_derivation.insert(pk, derivation);
+                filled = true;
+            }
+        }
+        filled
+    }};
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Enriches a PSBT created elsewhere with the wallet knowledge, allowing wallet signers to
+    /// sign it. For the inputs spending wallet coins fills in the spent outputs, BIP-32
+    /// derivations of the wallet keys and taproot internal keys; for the outputs paying to the
+    /// wallet addresses fills in the key derivations, so signers can recognize the change.
+    ///
+    /// Full spent transactions, which some hardware signers require, are filled in for all the
+    /// inputs using `prev_tx`, which may look them up in a [`crate::TxStore`] or an indexer.
+    /// Data already present in the PSBT are never overwritten.
+    pub fn fill_psbt(
+        &self,
+        psbt: &mut Psbt,
+        mut prev_tx: impl FnMut(Txid) -> Option<Tx>,
+    ) -> PsbtFill {
+        let scripts = self.issuable_scripts();
+        let descriptor = self.descriptor();
+        let mut report = PsbtFill::default();
+
+        for input in psbt.inputs_mut() {
+            let outpoint = input.previous_outpoint;
+            let mut filled = false;
+            if input.non_witness_tx.is_none() {
+                match prev_tx(outpoint.txid) {
+                    Some(tx) => {
+                        input.non_witness_tx = Some(tx);
+                        filled = true;
+                    }
+                    None => report.unknown.push(outpoint),
+                }
+            }
+
+            let known = self
+                .transactions()
+                .get(&outpoint.txid)
+                .and_then(|tx| tx.outputs.get(outpoint.vout_usize()))
+                .and_then(|debit| Some((debit.derived_addr()?, debit.value)));
+            let spent = input.non_witness_tx.as_ref().and_then(|tx| {
+                let txout = tx.outputs.get(outpoint.vout_usize())?;
+                Some((*scripts.get(&txout.script_pubkey)?, txout.value))
+            });
+            let Some((derived, value)) = known.or(spent) else {
+                report.utxos += filled as usize;
+                continue;
+            };
+            let script_pubkey = derived.addr.script_pubkey();
+            // Spent outputs of the legacy inputs are provided only as full transactions
+            if input.witness_utxo.is_none() && !script_pubkey.is_p2pkh() {
+                input.witness_utxo = Some(TxOut::new(script_pubkey.clone(), value));
+                filled = true;
+            }
+            report.utxos += filled as usize;
+            if fill_keys!(descriptor, derived.terminal, script_pubkey, input) {
+                report.derivations += 1;
+            }
+        }
+
+        for output in psbt.outputs_mut() {
+            let Some(derived) = scripts.get(&output.script) else {
+                continue;
+            };
+            if fill_keys!(descriptor, derived.terminal, output.script, output) {
+                report.derivations += 1;
+            }
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{
+        Keychain, LockTime, Sats, TxIn, TxVer, UnsignedTx, UnsignedTxIn, VarIntArray, Vout,
+    };
+
+    use super::*;
+    use crate::fixtures::test_wallet;
+    use crate::SEQ_NO_NO_RBF;
+
+    #[test]
+    fn test_fill_psbt() {
+        let wallet = test_wallet();
+        let script = wallet.addresses(Keychain::OUTER).next().unwrap().addr.script_pubkey();
+        let prev = Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::new(),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(script.clone(), Sats(10_000))]),
+            lock_time: LockTime::ZERO,
+        };
+        let outpoint = Outpoint::new(prev.txid(), Vout::from_u32(0));
+        let mut psbt = Psbt::from_tx(UnsignedTx {
+            version: TxVer::V2,
+            inputs: VarIntArray::from_checked(vec![UnsignedTxIn::with_sigs_removed(TxIn {
+                prev_output: outpoint,
+                sig_script: none!(),
+                sequence: SEQ_NO_NO_RBF,
+                witness: none!(),
+            })]),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(script.clone(), Sats(9_000))]),
+            lock_time: LockTime::ZERO,
+        });
+
+        let report = wallet.fill_psbt(&mut psbt.clone(), |_| None);
+        assert_eq!(report.unknown, vec![outpoint]);
+        assert_eq!((report.utxos, report.derivations), (0, 1));
+
+        let report =
+            wallet.fill_psbt(&mut psbt, |txid| (txid == prev.txid()).then(|| prev.clone()));
+        assert_eq!(report, PsbtFill {
+            utxos: 1,
+            derivations: 2,
+            unknown: vec![]
+        });
+        let input = psbt.inputs().next().unwrap();
+        assert_eq!(input.witness_utxo, Some(TxOut::new(script, Sats(10_000))));
+        assert_eq!(input.bip32_derivation.len(), 1);
+        assert_eq!(psbt.outputs().next().unwrap().bip32_derivation.len(), 1);
+        assert_eq!(wallet.fill_psbt(&mut psbt, |_| None), PsbtFill::default());
+    }
+}
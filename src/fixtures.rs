@@ -0,0 +1,139 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fixtures shared by the unit tests.
+
+use std::str::FromStr;
+
+use bpstd::{
+    Address, Idx, Keychain, LockTime, Network, NormalIndex, Outpoint, Sats, Terminal, Tx, TxIn,
+    TxOut, TxVer, VarIntArray, Vout, Wpkh, XpubDerivable,
+};
+use psbt::{PsbtConstructor, Utxo};
+
+use crate::{Indexer, SimIndexer, Wallet, SEQ_NO_RBF};
+
+/// Testnet account key with both the receiving and the change keychains.
+pub const TEST_XPUB: &str = "[643a7adc/86h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+/// Testnet address not belonging to the test wallet.
+pub const TEST_PAYEE: &str = "tb1qvyhk5mlhphul2r6kdknrxqqhl37d0z7ycf2uu9";
+
+pub type TestWallet = Wallet<XpubDerivable, Wpkh<XpubDerivable>>;
+
+pub fn test_xpub() -> XpubDerivable { XpubDerivable::from_str(TEST_XPUB).unwrap() }
+
+pub fn test_descriptor() -> Wpkh<XpubDerivable> { Wpkh::from(test_xpub()) }
+
+pub fn test_wallet() -> TestWallet { Wallet::new_layer1(test_descriptor(), Network::Testnet3) }
+
+pub fn test_payee() -> Address { Address::from_str(TEST_PAYEE).unwrap() }
+
+/// Coins of the test descriptor, allowing to construct PSBTs without a wallet. All coins have the
+/// same value and are controlled by the first receiving address.
+pub struct TestCoins {
+    pub descriptor: Wpkh<XpubDerivable>,
+    pub value: Sats,
+    /// Number of the known coins in each transaction: outputs with a greater number are unknown.
+    pub count: u32,
+    /// Next index of the derived addresses, which is the same for both keychains.
+    pub next_index: NormalIndex,
+}
+
+impl TestCoins {
+    pub fn new(value: Sats) -> Self {
+        TestCoins {
+            descriptor: test_descriptor(),
+            value,
+            count: u32::MAX,
+            next_index: NormalIndex::ZERO,
+        }
+    }
+
+    pub fn with_count(mut self, count: u32) -> Self {
+        self.count = count;
+        self
+    }
+}
+
+impl PsbtConstructor for TestCoins {
+    type Key = XpubDerivable;
+    type Descr = Wpkh<XpubDerivable>;
+
+    fn descriptor(&self) -> &Self::Descr { &self.descriptor }
+
+    fn utxo(&self, outpoint: Outpoint) -> Option<Utxo> {
+        (outpoint.vout.into_u32() < self.count).then(|| Utxo {
+            outpoint,
+            value: self.value,
+            terminal: Terminal::new(Keychain::OUTER, NormalIndex::ZERO),
+        })
+    }
+
+    fn network(&self) -> Network { Network::Testnet3 }
+
+    fn next_derivation_index(&mut self, _: impl Into<Keychain>, shift: bool) -> NormalIndex {
+        let index = self.next_index;
+        if shift {
+            self.next_index.saturating_inc_assign();
+        }
+        index
+    }
+}
+
+/// Funds the first receiving address of the wallet with 100 000 sats and publishes an unconfirmed
+/// RBF-signalling payment of 30 000 sats to [`TEST_PAYEE`] out of it, sending 69 500 sats of
+/// change to the first change address and paying 500 sats of fee. The funding transaction is
+/// mined if `mine_funding` is set.
+///
+/// Returns the funding and the payment transactions, with the wallet synced to the indexer.
+pub fn publish_payment(
+    wallet: &mut TestWallet,
+    indexer: &SimIndexer,
+    mine_funding: bool,
+) -> (Tx, Tx) {
+    let receive = wallet.addresses(Keychain::OUTER).next().unwrap().addr.script_pubkey();
+    let change = wallet.addresses(Keychain::INNER).next().unwrap().addr.script_pubkey();
+
+    let funding = indexer.chain().fund(receive, Sats(100_000));
+    indexer.publish(&funding).unwrap();
+    if mine_funding {
+        indexer.chain().mine(None);
+    }
+    let payment = Tx {
+        version: TxVer::V2,
+        inputs: VarIntArray::from_checked(vec![TxIn {
+            prev_output: Outpoint::new(funding.txid(), Vout::from_u32(0)),
+            sig_script: none!(),
+            sequence: SEQ_NO_RBF,
+            witness: none!(),
+        }]),
+        outputs: VarIntArray::from_checked(vec![
+            TxOut::new(test_payee().script_pubkey(), Sats(30_000)),
+            TxOut::new(change, Sats(69_500)),
+        ]),
+        lock_time: LockTime::ZERO,
+    };
+    indexer.publish(&payment).unwrap();
+    wallet.update(indexer).into_ok();
+    (funding, payment)
+}
@@ -20,15 +20,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
 use descriptors::Descriptor;
 use nonasync::persistence::{PersistenceError, PersistenceProvider};
 
 use super::*;
+use crate::summary::CacheParts;
 use crate::{
-    Layer2Cache, Layer2Data, Layer2Descriptor, NoLayer2, WalletCache, WalletData, WalletDescr,
+    CacheSummary, DryRun, HealthCheck, HealthIssue, Layer2, Layer2Cache, Layer2Data,
+    Layer2Descriptor, NoLayer2, Wallet, WalletCache, WalletData, WalletDescr, WalletStoreFactory,
 };
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -36,12 +38,52 @@ pub struct FsTextStore {
     pub descr: PathBuf,
     pub data: PathBuf,
     pub cache: PathBuf,
+    /// Summary of the cache, see [`CacheSummary`].
+    pub summary: PathBuf,
+    /// Journal of the sync events, see [`crate::Journal`].
+    pub journal: PathBuf,
     pub l2: PathBuf,
+    changes: DryRun,
+    #[cfg(feature = "cache_compression")]
+    compress: bool,
+}
+
+/// Magic bytes starting each zstd frame, which allow to detect compressed files on load.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Compression level for the cache, favoring speed over the compression ratio.
+#[cfg(feature = "cache_compression")]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Reads the file, decompressing it if it is compressed with zstd.
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if data.starts_with(&ZSTD_MAGIC) {
+        decompress(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+#[cfg(feature = "cache_compression")]
+fn decompress(data: &[u8]) -> io::Result<Vec<u8>> { zstd::decode_all(data) }
+
+#[cfg(not(feature = "cache_compression"))]
+fn decompress(_: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the file is compressed with zstd, which requires `cache_compression` feature",
+    ))
 }
 
 impl FsTextStore {
-    pub fn new(path: PathBuf) -> io::Result<Self> {
-        fs::create_dir_all(&path)?;
+    pub fn new(path: PathBuf) -> io::Result<Self> { Self::with_changes(path, &none!()) }
+
+    /// Constructs the store routing all the writes through the [`DryRun`] collector.
+    pub fn with_changes(path: PathBuf, changes: &DryRun) -> io::Result<Self> {
+        if !path.is_dir() && changes.perform(format!("create directory {}", path.display())) {
+            fs::create_dir_all(&path)?;
+        }
 
         let mut descr = path.clone();
         descr.push("descriptor.toml");
@@ -49,6 +91,10 @@ impl FsTextStore {
         data.push("data.toml");
         let mut cache = path.clone();
         cache.push("cache.yaml");
+        let mut summary = path.clone();
+        summary.push("summary.yaml");
+        let mut journal = path.clone();
+        journal.push("journal.yaml");
         let mut l2 = path;
         l2.push("layer2.yaml");
 
@@ -56,9 +102,105 @@ impl FsTextStore {
             descr,
             data,
             cache,
+            summary,
+            journal,
             l2,
+            changes: changes.clone(),
+            #[cfg(feature = "cache_compression")]
+            compress: false,
         })
     }
+
+    /// Makes the store to compress the cache with zstd. Compressed caches are always detected on
+    /// load, so the setting only affects the writes.
+    #[cfg(feature = "cache_compression")]
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Checks that the wallet files exist, are well-formed and can be written, without
+    /// requiring the wallet to be loaded.
+    pub fn integrity_issues(&self) -> Vec<HealthIssue> {
+        fn check(path: &Path, parse: impl FnOnce(&str) -> Result<(), String>) -> Option<String> {
+            let text = read_file(path).and_then(|data| {
+                String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            });
+            let text = match text {
+                Ok(text) => text,
+                Err(err) => return Some(format!("can't read {}: {err}", path.display())),
+            };
+            if let Err(err) = parse(&text) {
+                return Some(format!("{} is corrupted: {err}", path.display()));
+            }
+            match fs::metadata(path) {
+                Ok(meta) if meta.permissions().readonly() => Some(format!(
+                    "{} is read-only, so wallet changes can't be saved",
+                    path.display()
+                )),
+                _ => None,
+            }
+        }
+
+        let toml =
+            |s: &str| toml::from_str::<toml::Table>(s).map(|_| ()).map_err(|e| e.to_string());
+        let yaml = |s: &str| {
+            serde_yaml::from_str::<serde_yaml::Value>(s).map(|_| ()).map_err(|e| e.to_string())
+        };
+        let mut issues = vec![];
+        if let Some(problem) = check(&self.descr, toml) {
+            issues.push(HealthIssue::critical(problem, "restore the wallet from a backup"));
+        }
+        if let Some(problem) = check(&self.data, toml) {
+            issues.push(HealthIssue::critical(problem, "restore the file from a backup"));
+        }
+        if let Some(problem) = check(&self.cache, yaml) {
+            issues.push(HealthIssue::critical(
+                problem,
+                "re-create the wallet from its descriptor and sync it",
+            ));
+        }
+        issues
+    }
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for FsTextStore {
+    fn name(&self) -> &'static str { "files" }
+
+    fn check(&self, _: &Wallet<K, D, L2>) -> Vec<HealthIssue> { self.integrity_issues() }
+}
+
+/// Factory of [`FsTextStore`]s, keeping each wallet in a separate directory.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub struct FsStoreFactory;
+
+impl WalletStoreFactory for FsStoreFactory {
+    type Store = FsTextStore;
+
+    fn open(&self, location: PathBuf) -> Result<FsTextStore, PersistenceError> {
+        FsTextStore::new(location).map_err(PersistenceError::with)
+    }
+}
+
+/// Factory of [`FsTextStore`]s, which route all the writes through the [`DryRun`] collector.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct DryRunStoreFactory {
+    pub changes: DryRun,
+    /// Whether the stores compress the cache, see [`FsTextStore::with_compression`].
+    #[cfg(feature = "cache_compression")]
+    pub compress: bool,
+}
+
+impl WalletStoreFactory for DryRunStoreFactory {
+    type Store = FsTextStore;
+
+    fn open(&self, location: PathBuf) -> Result<FsTextStore, PersistenceError> {
+        let store =
+            FsTextStore::with_changes(location, &self.changes).map_err(PersistenceError::with)?;
+        #[cfg(feature = "cache_compression")]
+        let store = store.with_compression(self.compress);
+        Ok(store)
+    }
 }
 
 impl<K, D: Descriptor<K>, L2: Layer2Descriptor> PersistenceProvider<WalletDescr<K, D, L2>>
@@ -75,7 +217,7 @@ where
 
     fn store(&self, object: &WalletDescr<K, D, L2>) -> Result<(), PersistenceError> {
         let s = toml::to_string_pretty(object).map_err(PersistenceError::with)?;
-        fs::write(&self.descr, s).map_err(PersistenceError::with)?;
+        self.changes.write(&self.descr, s).map_err(PersistenceError::with)?;
         Ok(())
     }
 }
@@ -86,13 +228,57 @@ where
     for<'de> L2: serde::Serialize + serde::Deserialize<'de>,
 {
     fn load(&self) -> Result<WalletCache<L2>, PersistenceError> {
-        let file = fs::File::open(&self.cache).map_err(PersistenceError::with)?;
-        serde_yaml::from_reader(file).map_err(PersistenceError::with)
+        let data = read_file(&self.cache).map_err(PersistenceError::with)?;
+        let mut cache: WalletCache<L2> =
+            serde_yaml::from_slice(&data).map_err(PersistenceError::with)?;
+        // Wallets created by the previous versions have no journal
+        if self.journal.exists() {
+            let file = fs::File::open(&self.journal).map_err(PersistenceError::with)?;
+            cache.journal = serde_yaml::from_reader(file).map_err(PersistenceError::with)?;
+        }
+        Ok(cache)
     }
 
     fn store(&self, object: &WalletCache<L2>) -> Result<(), PersistenceError> {
-        let file = fs::File::create(&self.cache).map_err(PersistenceError::with)?;
-        serde_yaml::to_writer(file, object).map_err(PersistenceError::with)?;
+        let s = serde_yaml::to_string(object).map_err(PersistenceError::with)?;
+        #[cfg(feature = "cache_compression")]
+        let data = match self.compress {
+            true => zstd::encode_all(s.as_bytes(), ZSTD_LEVEL).map_err(PersistenceError::with)?,
+            false => s.into_bytes(),
+        };
+        #[cfg(not(feature = "cache_compression"))]
+        let data = s.into_bytes();
+        self.changes.write(&self.cache, data).map_err(PersistenceError::with)?;
+        if !object.journal.is_empty() {
+            let s = serde_yaml::to_string(&object.journal).map_err(PersistenceError::with)?;
+            self.changes.write(&self.journal, s).map_err(PersistenceError::with)?;
+        }
+        self.store(&object.summary())
+    }
+}
+
+impl PersistenceProvider<CacheSummary> for FsTextStore {
+    /// Loads the cache summary. If the summary is missing or is older than the cache, which
+    /// happens with caches written by the previous versions, it is computed from the full cache.
+    fn load(&self) -> Result<CacheSummary, PersistenceError> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+        match (modified(&self.summary), modified(&self.cache)) {
+            (Some(summary), Some(cache)) if summary >= cache => {
+                let file = fs::File::open(&self.summary).map_err(PersistenceError::with)?;
+                serde_yaml::from_reader(file).map_err(PersistenceError::with)
+            }
+            _ => {
+                let data = read_file(&self.cache).map_err(PersistenceError::with)?;
+                let parts: CacheParts =
+                    serde_yaml::from_slice(&data).map_err(PersistenceError::with)?;
+                Ok(parts.into())
+            }
+        }
+    }
+
+    fn store(&self, object: &CacheSummary) -> Result<(), PersistenceError> {
+        let s = serde_yaml::to_string(object).map_err(PersistenceError::with)?;
+        self.changes.write(&self.summary, s).map_err(PersistenceError::with)?;
         Ok(())
     }
 }
@@ -109,7 +295,7 @@ where
 
     fn store(&self, object: &WalletData<L2>) -> Result<(), PersistenceError> {
         let s = toml::to_string_pretty(object).map_err(PersistenceError::with)?;
-        fs::write(&self.data, s).map_err(PersistenceError::with)?;
+        self.changes.write(&self.data, s).map_err(PersistenceError::with)?;
         Ok(())
     }
 }
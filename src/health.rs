@@ -0,0 +1,473 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wallet health checks, run by `bp doctor`.
+//!
+//! Each check implements [`HealthCheck`] trait, such that applications can extend the suite
+//! with their own checks; [`HealthReport::run`] aggregates the problems found by the checks.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use bpstd::{AddressNetwork, DerivationPath, Idx, NormalIndex, Terminal, Txid};
+use descriptors::Descriptor;
+use psbt::PsbtConstructor;
+
+use crate::indexers::GAP_LIMIT;
+use crate::{
+    Bip43, BlockHeight, DerivationStandard, Layer2, TaprootAuditStatus, Wallet,
+    INDEX_EXHAUSTION_MARGIN,
+};
+
+/// Severity of a problem found by a health check, ordered from the least to the most severe.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum Severity {
+    /// The wallet works, but something is worth knowing.
+    Info,
+    /// The wallet works, but some of its operations may produce unexpected results.
+    Warning,
+    /// The wallet data are unreliable and must be fixed before the wallet is used.
+    Critical,
+}
+
+/// Problem found by a health check, together with the action fixing it.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HealthIssue {
+    pub severity: Severity,
+    pub problem: String,
+    pub action: String,
+}
+
+impl HealthIssue {
+    pub fn new(severity: Severity, problem: impl ToString, action: impl ToString) -> Self {
+        HealthIssue {
+            severity,
+            problem: problem.to_string(),
+            action: action.to_string(),
+        }
+    }
+
+    pub fn info(problem: impl ToString, action: impl ToString) -> Self {
+        Self::new(Severity::Info, problem, action)
+    }
+
+    pub fn warning(problem: impl ToString, action: impl ToString) -> Self {
+        Self::new(Severity::Warning, problem, action)
+    }
+
+    pub fn critical(problem: impl ToString, action: impl ToString) -> Self {
+        Self::new(Severity::Critical, problem, action)
+    }
+}
+
+/// Check of some aspect of the wallet health.
+pub trait HealthCheck<K, D: Descriptor<K>, L2: Layer2> {
+    /// Short name of the check, used in the reports.
+    fn name(&self) -> &'static str;
+
+    /// Runs the check, returning the problems found. Healthy wallets produce no issues.
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue>;
+}
+
+/// Problems found by the health checks, prioritized by their severity.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct HealthReport {
+    /// Number of the checks which were run.
+    pub checks: usize,
+    /// Found problems together with the names of the checks which found them.
+    pub issues: Vec<(&'static str, HealthIssue)>,
+}
+
+impl HealthReport {
+    pub fn new() -> Self { default!() }
+
+    /// Runs the checks against the wallet, starting with [`CacheConsistency`]. Since the other
+    /// checks rely on the cache, they are skipped if the cache is found to be inconsistent.
+    pub fn run<K, D: Descriptor<K>, L2: Layer2>(
+        wallet: &Wallet<K, D, L2>,
+        checks: &[&dyn HealthCheck<K, D, L2>],
+    ) -> Self {
+        let mut report = HealthReport::new();
+        report.add(&CacheConsistency, wallet);
+        if report.severity() == Some(Severity::Critical) {
+            return report;
+        }
+        for check in checks {
+            report.add(*check, wallet);
+        }
+        report
+    }
+
+    /// Runs a single check, adding the problems it found to the report.
+    pub fn add<K, D: Descriptor<K>, L2: Layer2>(
+        &mut self,
+        check: &dyn HealthCheck<K, D, L2>,
+        wallet: &Wallet<K, D, L2>,
+    ) {
+        let name = check.name();
+        self.extend(name, check.check(wallet));
+    }
+
+    /// Adds problems found by a check to the report.
+    pub fn extend(&mut self, check: &'static str, issues: impl IntoIterator<Item = HealthIssue>) {
+        self.checks += 1;
+        self.issues.extend(issues.into_iter().map(|issue| (check, issue)));
+        // Sort is stable, so issues of the same severity keep the order of the checks
+        self.issues.sort_by_key(|(_, issue)| Reverse(issue.severity));
+    }
+
+    /// Severity of the most severe problem, or `None` if the wallet is healthy.
+    pub fn severity(&self) -> Option<Severity> {
+        self.issues.iter().map(|(_, issue)| issue.severity).max()
+    }
+
+    pub fn is_healthy(&self) -> bool { self.issues.is_empty() }
+}
+
+const REBUILD_CACHE: &str =
+    "rebuild the cache by cloning the wallet with `--without-cache` and syncing the clone";
+
+/// Checks that each unspent output of the cache refers to a wallet output of a cached
+/// transaction, and that the cached addresses are the ones produced by the descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct CacheConsistency;
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for CacheConsistency {
+    fn name(&self) -> &'static str { "cache" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let mut issues = vec![];
+        let dangling = wallet.dangling_utxos();
+        if let Some(outpoint) = dangling.first() {
+            issues.push(HealthIssue::critical(
+                format!(
+                    "{} unspent output(s) don't match cached wallet transactions, for instance \
+                     {outpoint}",
+                    dangling.len()
+                ),
+                REBUILD_CACHE,
+            ));
+        }
+        let keychains = wallet.keychains();
+        let network = AddressNetwork::from(wallet.network());
+        let mismatched = wallet
+            .address_balance()
+            .filter(|addr| {
+                !keychains.contains(&addr.terminal.keychain)
+                    || wallet
+                        .descriptor()
+                        .derive_address(network, addr.terminal.keychain, addr.terminal.index)
+                        .map(|derived| derived.script_pubkey() != addr.addr.script_pubkey())
+                        .unwrap_or(true)
+            })
+            .collect::<Vec<_>>();
+        if let Some(addr) = mismatched.first() {
+            issues.push(HealthIssue::critical(
+                format!(
+                    "{} cached address(es) are not produced by the wallet descriptor, for \
+                     instance {} at {}",
+                    mismatched.len(),
+                    addr.addr,
+                    addr.terminal
+                ),
+                REBUILD_CACHE,
+            ));
+        }
+        issues
+    }
+}
+
+/// Checks that the keys of the descriptor and the cached addresses belong to the wallet network.
+///
+/// The network of the keys is deduced from the coin type of their BIP-43 derivation paths; keys
+/// with non-standard derivations are not checked.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct NetworkMatch;
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for NetworkMatch {
+    fn name(&self) -> &'static str { "network" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let mut issues = vec![];
+        let network = wallet.network();
+        for xpub in wallet.descriptor().xpubs() {
+            let path: DerivationPath = xpub.to_derivation();
+            let Some(testnet) = Bip43::deduce(&path).and_then(|std| std.is_testnet(&path).ok())
+            else {
+                continue;
+            };
+            if testnet != network.is_testnet() {
+                issues.push(HealthIssue::critical(
+                    format!(
+                        "key {} is derived for {} while the wallet is on {network}",
+                        xpub.account_fp(),
+                        if testnet { "testnet" } else { "mainnet" }
+                    ),
+                    "re-create the wallet specifying the network matching its keys",
+                ));
+            }
+        }
+        let address_network = AddressNetwork::from(network);
+        let foreign =
+            wallet.address_balance().filter(|addr| addr.addr.network != address_network).count();
+        if foreign > 0 {
+            issues.push(HealthIssue::critical(
+                format!("{foreign} cached address(es) belong to a network other than {network}"),
+                REBUILD_CACHE,
+            ));
+        }
+        issues
+    }
+}
+
+/// Checks that the descriptor produces addresses for each of its keychains, and that the
+/// static address of the wallet, if any, belongs to one of them.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct DescriptorValidity;
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for DescriptorValidity {
+    fn name(&self) -> &'static str { "descriptor" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let keychains = wallet.keychains();
+        if keychains.is_empty() {
+            return vec![HealthIssue::critical(
+                "descriptor defines no keychains",
+                "re-create the wallet with a descriptor using `<0;1>` keychain derivation",
+            )];
+        }
+        let mut issues = vec![];
+        let network = AddressNetwork::from(wallet.network());
+        for keychain in &keychains {
+            if let Err(err) =
+                wallet.descriptor().derive_address(network, *keychain, NormalIndex::ZERO)
+            {
+                issues.push(HealthIssue::critical(
+                    format!("descriptor doesn't produce addresses for keychain {keychain}: {err}"),
+                    "re-create the wallet with a descriptor producing standard scripts",
+                ));
+            }
+        }
+        if let Some(terminal) = wallet.static_terminal() {
+            if !keychains.contains(&terminal.keychain) {
+                issues.push(HealthIssue::critical(
+                    format!("static address terminal {terminal} is not a part of the descriptor"),
+                    "re-create the wallet with `--static-address` terminal from the descriptor \
+                     keychains",
+                ));
+            }
+        }
+        issues
+    }
+}
+
+/// Checks that the issued addresses don't exceed the gap limit and that the keychains are not
+/// about to run out of derivation indexes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct IndexGap;
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for IndexGap {
+    fn name(&self) -> &'static str { "index-gap" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let mut issues = vec![];
+        for keychain in wallet.keychains() {
+            let status = wallet.keychain_status(keychain);
+            if status.is_exhausting() {
+                issues.push(HealthIssue::critical(
+                    format!(
+                        "keychain {keychain} has only {} unused derivation indexes left, less \
+                         than {INDEX_EXHAUSTION_MARGIN}",
+                        status.remaining()
+                    ),
+                    "move the funds to a new wallet",
+                ));
+            }
+            if status.exceeds_gap_limit() {
+                issues.push(HealthIssue::warning(
+                    format!(
+                        "keychain {keychain} has {} addresses issued after the last used one, \
+                         exceeding the gap limit of {GAP_LIMIT}; payments to them may not be \
+                         discovered",
+                        status.unpublished()
+                    ),
+                    "re-use the already issued addresses for the next payments",
+                ));
+            }
+        }
+        issues
+    }
+}
+
+/// Checks that the wallet was synchronized with the blockchain recently.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct StaleSync {
+    /// Current UNIX timestamp.
+    pub now: u64,
+    /// Maximal age of the last known block, in seconds, after which the wallet is considered
+    /// stale.
+    pub max_age: u64,
+}
+
+impl StaleSync {
+    pub const DEFAULT_MAX_AGE: u64 = 24 * 60 * 60;
+
+    pub fn with(now: u64) -> Self {
+        StaleSync {
+            now,
+            max_age: Self::DEFAULT_MAX_AGE,
+        }
+    }
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for StaleSync {
+    fn name(&self) -> &'static str { "sync" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let last_block = wallet.last_block();
+        if last_block.height == BlockHeight::MIN {
+            return vec![HealthIssue::warning(
+                "wallet was never synchronized with the blockchain",
+                "run `bp sync`",
+            )];
+        }
+        let age = self.now.saturating_sub(last_block.time);
+        if age <= self.max_age {
+            return vec![];
+        }
+        vec![HealthIssue::warning(
+            format!(
+                "the last known block {} is {} hour(s) old, so the balance may be outdated",
+                last_block.height,
+                age / 3600
+            ),
+            "run `bp sync`",
+        )]
+    }
+}
+
+/// Checks whether the wallet addresses received more than a single payment, harming privacy.
+/// The static address of the wallet is re-used by design and is not reported.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct AddressReuse;
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for AddressReuse {
+    fn name(&self) -> &'static str { "address-reuse" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let mut payments = BTreeMap::<Terminal, BTreeSet<Txid>>::new();
+        for tx in wallet.transactions().values() {
+            for derived in tx.outputs.iter().filter_map(|out| out.derived_addr()) {
+                payments.entry(derived.terminal).or_default().insert(tx.txid);
+            }
+        }
+        let reused = payments
+            .into_iter()
+            .filter(|(terminal, txids)| {
+                txids.len() > 1 && Some(*terminal) != wallet.static_terminal()
+            })
+            .map(|(terminal, _)| terminal)
+            .collect::<Vec<_>>();
+        let Some(terminal) = reused.first() else {
+            return vec![];
+        };
+        vec![HealthIssue::warning(
+            format!(
+                "{} address(es) received multiple payments, for instance the one at {terminal}",
+                reused.len()
+            ),
+            "issue a fresh address for each payment with `bp address`",
+        )]
+    }
+}
+
+/// Runs the taproot audit (see [`Wallet::audit_taproot`]) over the wallet coins.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct TaprootKeys;
+
+impl<K, D: Descriptor<K>, L2: Layer2> HealthCheck<K, D, L2> for TaprootKeys {
+    fn name(&self) -> &'static str { "taproot" }
+
+    fn check(&self, wallet: &Wallet<K, D, L2>) -> Vec<HealthIssue> {
+        let audit = wallet.audit_taproot();
+        let mismatched =
+            audit.iter().filter(|a| matches!(a.status, TaprootAuditStatus::Mismatch { .. }));
+        let scripted =
+            audit.iter().filter(|a| matches!(a.status, TaprootAuditStatus::ScriptPaths(_)));
+        let mut issues = vec![];
+        match mismatched.count() {
+            0 => {}
+            count => issues.push(HealthIssue::critical(
+                format!("{count} taproot coin(s) have output keys not produced by the descriptor"),
+                "run `bp audit taproot` for the details",
+            )),
+        }
+        match scripted.count() {
+            0 => {}
+            count => issues.push(HealthIssue::info(
+                format!("{count} taproot coin(s) can be spent using script paths"),
+                "make sure the script paths are known to all wallet cosigners",
+            )),
+        }
+        issues
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::Network;
+
+    use super::*;
+    use crate::fixtures::{test_descriptor, test_wallet};
+
+    #[test]
+    fn test_fresh_wallet() {
+        let testnet = test_wallet();
+        let report = HealthReport::run(&testnet, &[
+            &NetworkMatch,
+            &DescriptorValidity,
+            &IndexGap,
+            &AddressReuse,
+            &StaleSync::with(0),
+        ]);
+        assert_eq!(report.checks, 6);
+        assert_eq!(report.severity(), Some(Severity::Warning));
+        assert_eq!(report.issues[0].0, "sync");
+
+        let mainnet = Wallet::<_, _>::new_layer1(test_descriptor(), Network::Mainnet);
+        let report = HealthReport::run(&mainnet, &[&NetworkMatch, &StaleSync::with(0)]);
+        assert_eq!(report.severity(), Some(Severity::Critical));
+        assert_eq!(report.issues[0].0, "network");
+        assert_eq!(report.issues[1].0, "sync");
+    }
+}
@@ -0,0 +1,177 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Signing account files, keeping account extended private key together with optional metadata.
+//!
+//! The decrypted account file contains the extended private key with its origin on the first
+//! line, followed by optional `<field>: <value>` metadata lines. Files created before metadata
+//! was introduced contain just the key, and unknown metadata fields are ignored.
+
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+use std::{fs, io};
+
+use bpstd::{XkeyParseError, XprivAccount};
+
+use crate::hot::{decrypt, encrypt, DataError, SecureIo};
+use crate::{Bip43, DescriptorFp};
+
+/// Errors parsing signing account file content.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AccountParseError {
+    /// invalid account extended private key - {0}
+    #[from]
+    Key(XkeyParseError),
+
+    /// invalid value '{1}' of account metadata field `{0}`.
+    Meta(String, String),
+}
+
+/// Optional metadata of a signing account, helping to distinguish account files.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct AccountMeta {
+    /// User-provided label of the account.
+    pub label: Option<String>,
+    /// UNIX timestamp of the account creation.
+    pub created: Option<u64>,
+    /// Derivation scheme the account is intended for.
+    pub scheme: Option<Bip43>,
+    /// Fingerprint of the wallet descriptor the account is bound to.
+    pub wallet: Option<DescriptorFp>,
+}
+
+impl AccountMeta {
+    pub fn is_empty(&self) -> bool {
+        self.label.is_none()
+            && self.created.is_none()
+            && self.scheme.is_none()
+            && self.wallet.is_none()
+    }
+}
+
+/// Signing account with its metadata, as it is kept in the account file.
+#[derive(Eq, PartialEq, Debug)]
+pub struct SigningAccount {
+    pub account: XprivAccount,
+    pub meta: AccountMeta,
+}
+
+impl From<XprivAccount> for SigningAccount {
+    fn from(account: XprivAccount) -> Self {
+        SigningAccount {
+            account,
+            meta: none!(),
+        }
+    }
+}
+
+impl Display for SigningAccount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.account)?;
+        let meta = &self.meta;
+        if let Some(label) = &meta.label {
+            // Labels are single-line, since each line keeps a separate field
+            write!(f, "\nlabel: {}", label.replace(['\n', '\r'], " "))?;
+        }
+        if let Some(created) = meta.created {
+            write!(f, "\ncreated: {created}")?;
+        }
+        if let Some(scheme) = &meta.scheme {
+            write!(f, "\nscheme: {scheme}")?;
+        }
+        if let Some(wallet) = meta.wallet {
+            write!(f, "\nwallet: {wallet}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SigningAccount {
+    type Err = AccountParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        let account = XprivAccount::from_str(lines.next().unwrap_or_default().trim())?;
+        let mut meta = AccountMeta::default();
+        for line in lines {
+            let Some((field, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            let err = || AccountParseError::Meta(field.to_owned(), value.to_owned());
+            match field.trim() {
+                "label" => meta.label = Some(value.to_owned()),
+                "created" => meta.created = Some(value.parse().map_err(|_| err())?),
+                "scheme" => meta.scheme = Some(value.parse().map_err(|_| err())?),
+                "wallet" => meta.wallet = Some(value.parse().map_err(|_| err())?),
+                _ => {}
+            }
+        }
+        Ok(SigningAccount { account, meta })
+    }
+}
+
+impl SecureIo for SigningAccount {
+    fn read<P>(file: P, password: &str) -> Result<Self, DataError>
+    where P: AsRef<Path> {
+        let data = fs::read(file)?;
+        let data = decrypt(&data, password).map_err(|_| DataError::AccountPassword)?;
+        let s = String::from_utf8(data).map_err(|_| DataError::AccountPassword)?;
+        SigningAccount::from_str(&s).map_err(|_| DataError::AccountPassword)
+    }
+
+    fn write<P>(&self, file: P, password: &str) -> io::Result<()>
+    where P: AsRef<Path> {
+        fs::write(file, encrypt(self.to_string().into_bytes(), password))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hot::SeedVector;
+
+    #[test]
+    fn test_account_meta_round_trip() {
+        let seed = &SeedVector::well_known()[0];
+        let mut account = SigningAccount::from(seed.account(Bip43::Bip86, true));
+        let legacy = account.account.to_string();
+        assert_eq!(SigningAccount::from_str(&legacy).as_ref(), Ok(&account));
+        assert_eq!(account.to_string(), legacy);
+
+        account.meta = AccountMeta {
+            label: Some(s!("cold\nstorage")),
+            created: Some(1_700_000_000),
+            scheme: Some(Bip43::Bip86),
+            wallet: Some(DescriptorFp::with(&"tr(...)")),
+        };
+        let s = account.to_string();
+        assert_eq!(s.lines().count(), 5);
+        let parsed = SigningAccount::from_str(&s).unwrap();
+        assert_eq!(parsed.meta.label.as_deref(), Some("cold storage"));
+        assert_eq!(parsed.meta.wallet, account.meta.wallet);
+        assert_eq!(parsed.meta.scheme, account.meta.scheme);
+        assert_eq!(parsed.account, account.account);
+    }
+}
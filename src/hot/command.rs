@@ -21,20 +21,31 @@
 // limitations under the License.
 
 use std::env::VarError;
+use std::io::{self, BufRead, Write};
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 use std::{env, fs};
 
 use amplify::hex::ToHex;
 use amplify::{Display, IoError};
 use bip39::Mnemonic;
 use bpstd::signers::TestnetRefSigner;
-use bpstd::{HardenedIndex, SighashCache, Tx, XprivAccount};
+use bpstd::{
+    DerivationParseError, DerivationPath, HardenedIndex, Idx, IdxBase, IndexParseError,
+    SighashCache, Tx, XprivAccount,
+};
 use clap::Subcommand;
+use clap_complete::Shell;
 use colored::Colorize;
 use psbt::Psbt;
 
-use crate::hot::{calculate_entropy, DataError, SecureIo, Seed, SeedType};
-use crate::Bip43;
+use crate::cli::{write_completions, write_manpages};
+use crate::hot::{
+    calculate_entropy, decrypt, encrypt, encrypt_hardened, is_hardened, AccountMeta, DataError,
+    SecureIo, Seed, SeedType, SigningAccount, KDF_ITERATIONS,
+};
+use crate::{Bip43, Date, DerivationStandard, DescriptorFp, InvalidDescriptorFp, PsbtMemo};
 
 const SEED_PASSWORD_ENVVAR: &str = "SEED_PASSWORD";
 
@@ -73,6 +84,10 @@ pub enum HotCommand {
     /// signing account. The seed password can be provided via the `SEED_PASSWORD` environment
     /// variable (security warning: don't set it on the command line, use instead the shell's
     /// builtin `read` and then export it).
+    ///
+    /// Protocols using non-BIP43 derivations are supported with a custom `--path`. With
+    /// `--accounts`, one account file is created per account number, named after the output
+    /// file with the account number suffix (like `account-0h.key`).
     #[display("derive")]
     Derive {
         /// Do not ask for a password and default to an empty-line password. For testing purposes
@@ -84,13 +99,36 @@ pub enum HotCommand {
         seed_file: PathBuf,
 
         /// Derivation scheme.
-        #[clap(short, long, default_value = "bip86")]
+        #[clap(short, long, default_value = "bip86", conflicts_with = "path")]
         scheme: Bip43,
 
         /// Account derivation number (should be hardened, i.e. with `h` suffix)
-        #[clap(short, long, default_value = "0h")]
+        #[clap(short, long, default_value = "0h", conflicts_with_all = ["path", "accounts"])]
         account: HardenedIndex,
 
+        /// Custom hardened derivation path, like `m/1000h/1h/0h`, used instead of the scheme
+        /// derivation. The second path segment must be the coin type (`0h` for mainnet and `1h`
+        /// for testnets). With `--accounts`, account numbers are appended to the path.
+        #[clap(long, value_parser = parse_account_path)]
+        path: Option<DerivationPath<HardenedIndex>>,
+
+        /// Inclusive range of account numbers to derive, like `0h..5h`
+        #[clap(long)]
+        accounts: Option<AccountRange>,
+
+        /// Do not ask for confirmation when a derivation path doesn't follow BIP43 standards
+        #[clap(short, long)]
+        yes: bool,
+
+        /// Label stored in the account file, shown by `info` command
+        #[clap(short, long)]
+        label: Option<String>,
+
+        /// Wallet to bind the account to, given either by its descriptor or the descriptor
+        /// fingerprint. Signing PSBTs constructed by other wallets produces a warning
+        #[clap(short, long, value_parser = parse_wallet_fp)]
+        wallet: Option<DescriptorFp>,
+
         /// Use the seed for bitcoin mainnet
         #[clap(long)]
         mainnet: bool,
@@ -112,7 +150,27 @@ pub enum HotCommand {
         print_private: bool,
     },
 
+    /// Change password of a seed or a signing account file
+    ///
+    /// Decrypts the file with the current password and encrypts the same content with a new
+    /// one, such that the password can be rotated without re-deriving and re-verifying accounts.
+    /// The file keeps its encryption format unless `--harden` is given.
+    #[display("rekey")]
+    Rekey {
+        /// Seed or signing account file, previously created with `seed` and `derive` commands
+        file: PathBuf,
+
+        /// Migrate the file to the format deriving the encryption key from the password with
+        /// PBKDF2
+        #[clap(long)]
+        harden: bool,
+    },
+
     /// Sign PSBT with the provided account keys
+    ///
+    /// Multiple PSBT files can be signed at once, either by listing them or by giving a
+    /// directory with `--all`; the account file is decrypted only once and a per-file summary is
+    /// reported.
     #[display("sign")]
     Sign {
         /// Do not ask for a password and default to an empty-line password. For testing purposes
@@ -120,11 +178,14 @@ pub enum HotCommand {
         #[clap(short = 'N', long)]
         no_password: bool,
 
-        /// File containing PSBT
-        psbt_file: PathBuf,
+        /// Sign all PSBT files (with `.psbt` extension) in the directory
+        #[clap(long, value_name = "DIR")]
+        all: Option<PathBuf>,
 
-        /// Signing account file used to (partially co-)sign PSBT
-        signing_account: PathBuf,
+        /// Files containing PSBTs, followed by the signing account file used to (partially
+        /// co-)sign them. With `--all` only the signing account file must be given
+        #[clap(required = true, value_name = "FILE")]
+        files: Vec<PathBuf>,
     },
 
     /// Analyze PSBT and print debug information
@@ -133,6 +194,22 @@ pub enum HotCommand {
         /// File containing PSBT
         psbt_file: PathBuf,
     },
+
+    /// Print shell script registering completion of `bp-hot` arguments. For instance, add
+    /// `source <(bp-hot completions bash)` to `~/.bashrc`
+    #[display("completions")]
+    Completions {
+        /// Shell to generate the script for
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+
+    /// Generate manual pages for `bp-hot` and all its commands
+    #[display("manpages")]
+    Manpages {
+        /// Directory to write the manual pages to
+        dir: PathBuf,
+    },
 }
 
 impl HotArgs {
@@ -143,20 +220,91 @@ impl HotArgs {
                 no_password,
                 seed_file,
                 scheme,
-                account,
+                account: account_no,
+                path,
+                accounts,
+                yes,
+                label,
+                wallet,
                 mainnet,
                 output_file,
-            } => derive(&seed_file, scheme, account, mainnet, &output_file, no_password)?,
+            } => {
+                let custom = path.is_some();
+                let derivation = |account: Option<HardenedIndex>| match (&path, account) {
+                    (Some(path), None) => path.clone(),
+                    (Some(path), Some(account)) => path.iter().copied().chain([account]).collect(),
+                    (None, account) => {
+                        scheme.to_account_derivation(account.unwrap_or(account_no), !mainnet)
+                    }
+                };
+                let outputs = match accounts {
+                    None => vec![(derivation(None), output_file)],
+                    Some(accounts) => accounts
+                        .iter()
+                        .map(|account| {
+                            (derivation(Some(account)), account_file(&output_file, account))
+                        })
+                        .collect::<Vec<_>>(),
+                };
+                // Account files keep the network in the coin type segment of the derivation path
+                let coin_type = if mainnet { HardenedIndex::ZERO } else { HardenedIndex::ONE };
+                if let Some((derivation, _)) =
+                    outputs.iter().find(|(derivation, _)| derivation.get(1) != Some(&coin_type))
+                {
+                    return Err(DataError::CoinType(derivation.clone(), coin_type));
+                }
+                let non_standard = outputs
+                    .iter()
+                    .map(|(derivation, _)| derivation)
+                    .find(|derivation| Bip43::deduce_account(derivation, !mainnet).is_none());
+                if let Some(derivation) = non_standard.filter(|_| custom && !yes) {
+                    let question = format!(
+                        "Derivation path m{derivation} doesn't follow BIP43 standards and wallets \
+                         may be unable to recover funds from it. Continue?"
+                    );
+                    if !confirm(&question)? {
+                        return Ok(());
+                    }
+                }
+                let meta = AccountMeta {
+                    label,
+                    created: SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .map(|elapsed| elapsed.as_secs())
+                        .ok(),
+                    scheme: (!custom).then_some(scheme),
+                    wallet,
+                };
+                derive(&seed_file, outputs, meta, mainnet, no_password)?
+            }
             HotCommand::Info {
                 file,
                 print_private,
             } => info(&file, print_private)?,
+            HotCommand::Rekey { file, harden } => rekey(&file, harden)?,
             HotCommand::Sign {
                 no_password,
-                psbt_file,
-                signing_account,
-            } => sign(&psbt_file, &signing_account, no_password)?,
+                all,
+                mut files,
+            } => {
+                let signing_account = files.pop().expect("clap requires at least one file");
+                let psbt_files = match all {
+                    Some(_) if !files.is_empty() => return Err(DataError::SignArgs),
+                    Some(dir) => psbt_files_in(&dir)?,
+                    None if files.is_empty() => return Err(DataError::SignArgs),
+                    None => files,
+                };
+                sign(&psbt_files, &signing_account, no_password)?
+            }
             HotCommand::Sighash { psbt_file } => sighash(&psbt_file)?,
+            HotCommand::Completions { shell } => {
+                write_completions("bp-hot", shell, &mut std::io::stdout())?
+            }
+            HotCommand::Manpages { dir } => {
+                for page in write_manpages::<HotArgs>("bp-hot", &dir)? {
+                    eprintln!("Written {}", page.display());
+                }
+            }
         };
         Ok(())
     }
@@ -208,6 +356,81 @@ fn get_password(
     Ok(password)
 }
 
+fn confirm(question: &str) -> Result<bool, io::Error> {
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn parse_wallet_fp(s: &str) -> Result<DescriptorFp, InvalidDescriptorFp> {
+    match DescriptorFp::from_str(s) {
+        Ok(fp) => Ok(fp),
+        // Descriptors always contain brackets, while fingerprints never do
+        Err(_) if s.contains('(') => Ok(DescriptorFp::with(&s.trim())),
+        Err(err) => Err(err),
+    }
+}
+
+fn parse_account_path(s: &str) -> Result<DerivationPath<HardenedIndex>, DerivationParseError> {
+    DerivationPath::from_str(s.strip_prefix("m/").unwrap_or(s))
+}
+
+/// Errors parsing range of account numbers.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum AccountRangeError {
+    /// invalid account number in the range - {0}
+    #[from]
+    Index(IndexParseError),
+
+    /// account range {0}..{1} is empty.
+    Empty(HardenedIndex, HardenedIndex),
+}
+
+/// Inclusive range of hardened account numbers, like `0h..5h`. A single account number is also
+/// accepted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct AccountRange {
+    pub first: HardenedIndex,
+    pub last: HardenedIndex,
+}
+
+impl AccountRange {
+    pub fn iter(&self) -> impl Iterator<Item = HardenedIndex> {
+        let last = self.last;
+        std::iter::successors(Some(self.first), |index| index.checked_inc())
+            .take_while(move |index| *index <= last)
+    }
+}
+
+impl FromStr for AccountRange {
+    type Err = AccountRangeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (first, last) = s.split_once("..").unwrap_or((s, s));
+        let first = HardenedIndex::from_str(first)?;
+        let last = HardenedIndex::from_str(last)?;
+        if first.child_number() > last.child_number() {
+            return Err(AccountRangeError::Empty(first, last));
+        }
+        Ok(AccountRange { first, last })
+    }
+}
+
+/// Name of the account file for the given account number, like `account-0h.key` for the
+/// `account.key` output file.
+fn account_file(output_file: &Path, account: HardenedIndex) -> PathBuf {
+    let mut name = output_file.file_stem().unwrap_or_default().to_owned();
+    name.push(format!("-{account}"));
+    if let Some(ext) = output_file.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    output_file.with_file_name(name)
+}
+
 fn seed(output_file: &Path) -> Result<(), DataError> {
     let seed = Seed::random(SeedType::Bit128);
     let seed_password = get_password(Some(SEED_PASSWORD_ENVVAR), "Seed password:", false)?;
@@ -227,14 +450,46 @@ fn info(file: &Path, print_private: bool) -> Result<(), IoError> {
     let password = rpassword::prompt_password("File password: ")?;
     if let Ok(seed) = Seed::read(file, &password) {
         info_seed(seed, print_private)
-    } else if let Ok(account) = XprivAccount::read(file, &password) {
-        info_account(account, print_private)
+    } else if let Ok(signing) = SigningAccount::read(file, &password) {
+        info_account(signing.account, print_private);
+        info_account_meta(&signing.meta);
     } else {
         eprintln!("{} can't detect file format for `{}`", "Error:".bright_red(), file.display());
     }
     Ok(())
 }
 
+fn rekey(file: &Path, harden: bool) -> Result<(), DataError> {
+    let encrypted = fs::read(file)?;
+    let password = rpassword::prompt_password("Current password: ")?;
+    let data = decrypt(&encrypted, &password).map_err(|_| DataError::Password)?;
+    let content = String::from_utf8(data.clone()).map_err(|_| DataError::UnknownFormat)?;
+    let kind = if Mnemonic::from_str(&content).is_ok() {
+        "Seed"
+    } else if SigningAccount::from_str(&content).is_ok() {
+        "Account"
+    } else {
+        return Err(DataError::UnknownFormat);
+    };
+
+    let new_password = get_password(None, "New password: ", false)?;
+    let hardened = harden || is_hardened(&encrypted);
+    let reencrypted = match hardened {
+        true => encrypt_hardened(data, &new_password, KDF_ITERATIONS),
+        false => encrypt(data, &new_password),
+    };
+
+    // The file is replaced only once the new content is completely written
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".rekey");
+    fs::write(&tmp, reencrypted)?;
+    fs::rename(&tmp, file)?;
+
+    let format = if hardened { " using PBKDF2 key derivation" } else { "" };
+    eprintln!("{kind} file {} is re-encrypted with the new password{format}", file.display());
+    Ok(())
+}
+
 fn info_seed(seed: Seed, print_private: bool) {
     if print_private {
         let mnemonic = Mnemonic::from_entropy(seed.as_entropy()).expect("invalid seed");
@@ -281,12 +536,26 @@ fn info_account(account: XprivAccount, print_private: bool) {
     // TODO: Add Zpub etc
 }
 
+fn info_account_meta(meta: &AccountMeta) {
+    if let Some(label) = &meta.label {
+        println!("{:-18} {}", "  - label:".bright_white(), label.bright_green());
+    }
+    if let Some(created) = meta.created {
+        println!("{:-18} {}", "  - created:".bright_white(), Date::from_timestamp(created));
+    }
+    if let Some(scheme) = &meta.scheme {
+        println!("{:-18} {scheme}", "  - scheme:".bright_white());
+    }
+    if let Some(wallet) = meta.wallet {
+        println!("{:-18} {wallet}", "  - wallet:".bright_white());
+    }
+}
+
 fn derive(
     seed_file: &Path,
-    scheme: Bip43,
-    account: HardenedIndex,
+    outputs: Vec<(DerivationPath<HardenedIndex>, PathBuf)>,
+    meta: AccountMeta,
     mainnet: bool,
-    output_file: &Path,
     no_password: bool,
 ) -> Result<(), DataError> {
     let seed_password = get_password(Some(SEED_PASSWORD_ENVVAR), "Seed password:", false)?;
@@ -298,34 +567,117 @@ fn derive(
     };
 
     let seed = Seed::read(seed_file, &seed_password)?;
-    let account = seed.derive(scheme, !mainnet, account);
+    for (derivation, output_file) in outputs {
+        let mut meta = meta.clone();
+        // Custom paths may still follow one of the standards
+        if meta.scheme.is_none() {
+            meta.scheme = Bip43::deduce_account(&derivation, !mainnet).map(|(scheme, _)| scheme);
+        }
+        let signing = SigningAccount {
+            account: seed.derive_path(derivation, !mainnet),
+            meta,
+        };
 
-    account.write(output_file, &account_password)?;
-    XprivAccount::read(output_file, &account_password).inspect_err(|_| {
-        eprintln!("Unable to save account file");
-        let _ = fs::remove_file(output_file);
-    })?;
+        signing.write(&output_file, &account_password)?;
+        SigningAccount::read(&output_file, &account_password).inspect_err(|_| {
+            eprintln!("Unable to save account file");
+            let _ = fs::remove_file(&output_file);
+        })?;
 
-    info_account(account, false);
+        info_account(signing.account, false);
+        info_account_meta(&signing.meta);
+        println!("{:-18} {}", "  - file:".bright_white(), output_file.display());
+    }
 
     Ok(())
 }
 
-fn sign(psbt_file: &Path, account_file: &Path, no_password: bool) -> Result<(), DataError> {
-    eprintln!("Signing {} with {}", psbt_file.display(), account_file.display());
+fn psbt_files_in(dir: &Path) -> Result<Vec<PathBuf>, DataError> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().and_then(|ext| ext.to_str()) == Some("psbt") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn sign(psbt_files: &[PathBuf], account_file: &Path, no_password: bool) -> Result<(), DataError> {
     let password = if no_password { s!("") } else { rpassword::prompt_password("Password: ")? };
-    let account = XprivAccount::read(account_file, &password)?;
+    let SigningAccount { account, meta } = SigningAccount::read(account_file, &password)?;
 
     eprintln!("Signing key: {}", account.to_xpub_account());
+    if let Some(label) = &meta.label {
+        eprintln!("Account label: {label}");
+    }
     eprintln!("Signing using testnet signer");
 
+    if let [psbt_file] = psbt_files {
+        let (psbt, _) = sign_file(psbt_file, &account, &meta)?;
+        println!("\n{}\n", psbt);
+        return Ok(());
+    }
+
+    // In batch mode failures are reported in the summary without stopping the signing
+    let results = psbt_files
+        .iter()
+        .map(|psbt_file| {
+            let res = sign_file(psbt_file, &account, &meta);
+            if let Err(err) = &res {
+                eprintln!("{} {err}\n", "Error:".bright_red());
+            }
+            (psbt_file, res)
+        })
+        .collect::<Vec<_>>();
+    println!("{}", "Summary:".bright_white());
+    let mut failed = 0;
+    for (psbt_file, res) in results {
+        match res {
+            Ok((psbt, sig_count)) => {
+                println!("{}\t{}\t{sig_count} signature(s)", psbt_file.display(), psbt.txid())
+            }
+            Err(err) => {
+                failed += 1;
+                println!("{}\t{}\t{err}", psbt_file.display(), "failed".bright_red())
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(DataError::BatchFailed(failed, psbt_files.len()));
+    }
+    Ok(())
+}
+
+fn sign_file(
+    psbt_file: &Path,
+    account: &XprivAccount,
+    meta: &AccountMeta,
+) -> Result<(Psbt, usize), DataError> {
+    eprintln!("Signing {}", psbt_file.display());
     let data = fs::read(psbt_file)?;
     let mut psbt = Psbt::deserialize(&data)?;
 
     eprintln!("PSBT version: {:#}", psbt.version);
     eprintln!("Transaction id: {}", psbt.txid());
+    if let Some(memo) = psbt.memo() {
+        eprintln!("Memo: {memo}");
+    }
+    match (meta.wallet, psbt.wallet_fp()) {
+        (Some(bound), Some(wallet)) if bound != wallet => eprintln!(
+            "{} the account is bound to wallet {bound}, but the PSBT is constructed by wallet \
+             {wallet}",
+            "Warning:".bright_yellow()
+        ),
+        (Some(bound), None) => eprintln!(
+            "{} the account is bound to wallet {bound}, but the PSBT doesn't specify its wallet",
+            "Warning:".bright_yellow()
+        ),
+        _ => {}
+    }
 
-    let signer = TestnetRefSigner::new(&account);
+    let signer = TestnetRefSigner::new(account);
     let sig_count = psbt.sign(&signer)?;
 
     fs::write(psbt_file, psbt.serialize(psbt.version))?;
@@ -334,8 +686,7 @@ fn sign(psbt_file: &Path, account_file: &Path, no_password: bool) -> Result<(),
         sig_count.to_string().bright_green(),
         psbt_file.display()
     );
-    println!("\n{}\n", psbt);
-    Ok(())
+    Ok((psbt, sig_count))
 }
 
 fn sighash(psbt_file: &Path) -> Result<(), DataError> {
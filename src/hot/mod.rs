@@ -21,17 +21,27 @@
 // limitations under the License.
 
 mod seed;
+mod account;
 #[cfg(feature = "cli")]
 mod command;
 #[cfg(feature = "cli")]
 pub mod signer;
 mod password;
+mod vectors;
 
+pub use account::{AccountMeta, AccountParseError, SigningAccount};
 #[cfg(feature = "cli")]
-pub use command::{HotArgs, HotCommand};
-pub use io::{decrypt, encrypt, DataError, SecureIo};
+pub use command::{AccountRange, AccountRangeError, HotArgs, HotCommand};
+pub use io::{
+    decrypt, encrypt, encrypt_hardened, is_hardened, kdf_iterations, DataError, SecureIo,
+    KDF_ITERATIONS, KDF_MAGIC,
+};
 pub use password::calculate_entropy;
 pub use seed::{Seed, SeedType};
+pub use vectors::{
+    AddressVector, PsbtVector, SeedVector, TestVectors, VECTOR_BIP32_SEED, VECTOR_FEE,
+    VECTOR_MNEMONIC, VECTOR_PREVOUT_VALUE, VECTOR_SCHEMES,
+};
 
 mod io {
     use std::io;
@@ -40,9 +50,23 @@ mod io {
     use aes_gcm::aead::{Aead, Nonce, OsRng};
     use aes_gcm::{AeadCore, Aes256Gcm, KeyInit};
     use amplify::IoError;
+    use bpstd::{DerivationPath, HardenedIndex};
+    use hmac::{Hmac, Mac};
     use psbt::{PsbtError, SignError};
+    use rand::RngCore;
     use sha2::{Digest, Sha256};
 
+    /// Prefix of the files encrypted with a key derived from the password by PBKDF2.
+    ///
+    /// Such files have the prefix followed by the big-endian number of PBKDF2 iterations, 16-byte
+    /// salt, the nonce and the encrypted data. Files without the prefix use a single SHA256 hash
+    /// of the password as the encryption key.
+    pub const KDF_MAGIC: &[u8; 6] = b"BPKDF1";
+    /// Number of PBKDF2-HMAC-SHA256 iterations used for the newly encrypted files.
+    pub const KDF_ITERATIONS: u32 = 600_000;
+    const KDF_SALT_LEN: usize = 16;
+    const NONCE_LEN: usize = 12;
+
     pub fn encrypt(source: Vec<u8>, key: impl AsRef<[u8]>) -> Vec<u8> {
         let key = Sha256::digest(key.as_ref());
         let key = aes_gcm::Key::<Aes256Gcm>::from_slice(key.as_slice());
@@ -58,11 +82,73 @@ mod io {
         data
     }
 
+    /// Encrypts data with a key derived from the password by PBKDF2 with the given number of
+    /// iterations, see [`KDF_MAGIC`] for the data format.
+    pub fn encrypt_hardened(
+        source: Vec<u8>,
+        password: impl AsRef<[u8]>,
+        iterations: u32,
+    ) -> Vec<u8> {
+        let mut salt = [0u8; KDF_SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = pbkdf2(password.as_ref(), &salt, iterations);
+
+        let mut data = KDF_MAGIC.to_vec();
+        data.extend(iterations.to_be_bytes());
+        data.extend(salt);
+        data.extend(encrypt(source, key));
+        data
+    }
+
+    /// Detects whether the data are encrypted with [`encrypt_hardened`].
+    pub fn is_hardened(encrypted: &[u8]) -> bool {
+        encrypted.len() > KDF_MAGIC.len() + 4 + KDF_SALT_LEN + NONCE_LEN
+            && encrypted.starts_with(KDF_MAGIC)
+    }
+
+    /// Number of PBKDF2 iterations declared by the data encrypted with [`encrypt_hardened`].
+    pub fn kdf_iterations(encrypted: &[u8]) -> Option<u32> {
+        if !is_hardened(encrypted) {
+            return None;
+        }
+        let iterations = &encrypted[KDF_MAGIC.len()..KDF_MAGIC.len() + 4];
+        Some(u32::from_be_bytes(iterations.try_into().expect("fixed length")))
+    }
+
+    /// Decrypts data encrypted either with [`encrypt`] or [`encrypt_hardened`].
     pub fn decrypt(encrypted: &[u8], key: impl AsRef<[u8]>) -> Result<Vec<u8>, aes_gcm::Error> {
+        if is_hardened(encrypted) {
+            let data = &encrypted[KDF_MAGIC.len()..];
+            let (iterations, data) = data.split_at(4);
+            let (salt, data) = data.split_at(KDF_SALT_LEN);
+            let iterations = u32::from_be_bytes(iterations.try_into().expect("fixed length"));
+            let key = pbkdf2(key.as_ref(), salt, iterations);
+            // Files in the legacy format may start with the prefix by a chance
+            if let Ok(data) = decrypt(data, key) {
+                return Ok(data);
+            }
+        }
+        if encrypted.len() < NONCE_LEN {
+            return Err(aes_gcm::Error);
+        }
         let key = Sha256::digest(key.as_ref());
         let key = aes_gcm::Key::<Aes256Gcm>::from_slice(key.as_slice());
-        let nonce = Nonce::<Aes256Gcm>::from_slice(&encrypted[..12]);
-        Aes256Gcm::new(key).decrypt(nonce, &encrypted[12..])
+        let nonce = Nonce::<Aes256Gcm>::from_slice(&encrypted[..NONCE_LEN]);
+        Aes256Gcm::new(key).decrypt(nonce, &encrypted[NONCE_LEN..])
+    }
+
+    /// PBKDF2-HMAC-SHA256 producing a single 32-byte block.
+    fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+        let prf =
+            <Hmac<Sha256> as Mac>::new_from_slice(password).expect("HMAC takes key of any size");
+        let mut block =
+            prf.clone().chain_update(salt).chain_update(1u32.to_be_bytes()).finalize().into_bytes();
+        let mut key = block;
+        for _ in 1..iterations {
+            block = prf.clone().chain_update(block).finalize().into_bytes();
+            key.iter_mut().zip(block).for_each(|(k, u)| *k ^= u);
+        }
+        key.into()
     }
 
     #[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
@@ -78,6 +164,27 @@ mod io {
         #[display("invalid account key password.")]
         AccountPassword,
 
+        #[display("invalid password.")]
+        Password,
+
+        #[display(
+            "PSBT files must be given either as arguments or with `--all`, followed by the \
+             signing account file."
+        )]
+        SignArgs,
+
+        #[display("{0} of {1} PSBT files were not signed.")]
+        BatchFailed(usize, usize),
+
+        #[display("file has unknown format.")]
+        UnknownFormat,
+
+        #[display(
+            "account derivation path m{0} must have coin type {1} as its second segment to be \
+             saved into an account file."
+        )]
+        CoinType(DerivationPath<HardenedIndex>, HardenedIndex),
+
         #[from]
         Psbt(PsbtError),
 
@@ -94,4 +201,36 @@ mod io {
         fn write<P>(&self, file: P, password: &str) -> io::Result<()>
         where P: AsRef<Path>;
     }
+
+    #[cfg(test)]
+    mod tests {
+        use amplify::hex::ToHex;
+
+        use super::*;
+
+        #[test]
+        fn test_hardened_encryption() {
+            // RFC 7914 test vectors for PBKDF2-HMAC-SHA256
+            assert_eq!(
+                pbkdf2(b"password", b"salt", 1).to_hex(),
+                "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+            );
+            assert_eq!(
+                pbkdf2(b"password", b"salt", 2).to_hex(),
+                "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"
+            );
+
+            let data = b"abandon ability able".to_vec();
+            let hardened = encrypt_hardened(data.clone(), "password", 16);
+            assert!(is_hardened(&hardened));
+            assert_eq!(kdf_iterations(&hardened), Some(16));
+            assert_eq!(decrypt(&hardened, "password").unwrap(), data);
+            assert!(decrypt(&hardened, "passw0rd").is_err());
+
+            let legacy = encrypt(data.clone(), "password");
+            assert!(!is_hardened(&legacy));
+            assert_eq!(kdf_iterations(&legacy), None);
+            assert_eq!(decrypt(&legacy, "password").unwrap(), data);
+        }
+    }
 }
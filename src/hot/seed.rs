@@ -25,11 +25,11 @@ use std::str::FromStr;
 use std::{fs, io};
 
 use bip39::Mnemonic;
-use bpstd::{HardenedIndex, XkeyOrigin, Xpriv, XprivAccount};
+use bpstd::{DerivationPath, HardenedIndex, XkeyOrigin, Xpriv, XprivAccount};
 use rand::RngCore;
 
 use crate::bip43::DerivationStandard;
-use crate::hot::{decrypt, encrypt, DataError, SecureIo};
+use crate::hot::{decrypt, encrypt, DataError, SecureIo, SigningAccount};
 use crate::Bip43;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
@@ -87,9 +87,18 @@ impl Seed {
     }
 
     pub fn derive(&self, scheme: Bip43, testnet: bool, account: HardenedIndex) -> XprivAccount {
+        self.derive_path(scheme.to_account_derivation(account, testnet), testnet)
+    }
+
+    /// Derives account with an arbitrary hardened derivation path, which may not follow any
+    /// derivation standard.
+    pub fn derive_path(
+        &self,
+        derivation: DerivationPath<HardenedIndex>,
+        testnet: bool,
+    ) -> XprivAccount {
         let master_xpriv = self.master_xpriv(testnet);
         let master_xpub = master_xpriv.to_xpub();
-        let derivation = scheme.to_account_derivation(account, testnet);
         let account_xpriv = master_xpriv.derive_priv(&derivation);
 
         let origin = XkeyOrigin::new(master_xpub.fingerprint(), derivation);
@@ -115,12 +124,10 @@ impl SecureIo for Seed {
 }
 
 impl SecureIo for XprivAccount {
+    /// Reads account key, ignoring the account metadata, see [`SigningAccount`].
     fn read<P>(file: P, password: &str) -> Result<Self, DataError>
     where P: AsRef<Path> {
-        let data = fs::read(file)?;
-        let data = decrypt(&data, password).map_err(|_| DataError::AccountPassword)?;
-        let s = String::from_utf8(data).map_err(|_| DataError::AccountPassword)?;
-        XprivAccount::from_str(&s).map_err(|_| DataError::AccountPassword)
+        SigningAccount::read(file, password).map(|signing| signing.account)
     }
 
     fn write<P>(&self, file: P, password: &str) -> io::Result<()>
@@ -0,0 +1,387 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic test vectors for descriptor and PSBT pipelines.
+//!
+//! Vectors are generated from well-known seeds: BIP-32 test vector 1 seed and BIP-39 seed of
+//! the `abandon ... about` mnemonic used by BIP-84 and BIP-86 test vectors. Transaction
+//! signatures are produced without auxiliary randomness, such that the generated vectors are
+//! identical on each run and can be used to cross-check other implementations.
+
+use amplify::{Bytes32, Wrapper};
+use bip39::Mnemonic;
+use bpstd::secp256k1::{ecdsa, schnorr as bip340, SECP256K1};
+use bpstd::{
+    Address, AddressNetwork, CompressedPk, DerivationIndex, DerivationPath, DeriveScripts,
+    HardenedIndex, Idx, InternalKeypair, InternalPk, KeyOrigin, Keychain, LegacyPk, NormalIndex,
+    Outpoint, PubkeyHash, RedeemScript, ScriptHash, ScriptPubkey, SeqNo, Sighash, SighashCache,
+    Sign, TapLeafHash, TapMerklePath, TapNodeHash, TapSighash, Terminal, Tx, Txid, WPubkeyHash,
+    XOnlyPk, XkeyOrigin, Xpriv, XprivAccount, XpubDerivable,
+};
+use descriptors::{StdDescr, TrKey, Wpkh};
+use psbt::{Prevout, Psbt, PsbtVer, Rejected, Signer};
+
+use crate::bip43::DerivationStandard;
+use crate::Bip43;
+
+/// Mnemonic used by BIP-84 and BIP-86 test vectors.
+pub const VECTOR_MNEMONIC: &str =
+    "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+/// Seed of the BIP-32 test vector 1.
+pub const VECTOR_BIP32_SEED: [u8; 16] = [
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f,
+];
+/// Derivation schemes covered by the address vectors.
+pub const VECTOR_SCHEMES: [Bip43; 4] = [Bip43::Bip44, Bip43::Bip49, Bip43::Bip84, Bip43::Bip86];
+/// Value of the synthetic output spent by PSBT vectors.
+pub const VECTOR_PREVOUT_VALUE: u64 = 100_000;
+/// Fee paid by the transactions in PSBT vectors.
+pub const VECTOR_FEE: u64 = 1_000;
+
+/// Seed for which the test vectors are generated.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SeedVector {
+    pub name: &'static str,
+    /// BIP-32 seed (for mnemonics it is the seed produced with an empty passphrase).
+    pub seed: Vec<u8>,
+    pub mainnet_master: Xpriv,
+    pub testnet_master: Xpriv,
+}
+
+impl SeedVector {
+    /// Well-known seeds used for the test vectors.
+    pub fn well_known() -> Vec<SeedVector> {
+        let mnemonic = Mnemonic::parse(VECTOR_MNEMONIC).expect("valid mnemonic");
+        vec![
+            SeedVector::with("bip32-vector-1", VECTOR_BIP32_SEED.to_vec()),
+            SeedVector::with("bip39-abandon-about", mnemonic.to_seed("").to_vec()),
+        ]
+    }
+
+    pub fn with(name: &'static str, seed: Vec<u8>) -> Self {
+        SeedVector {
+            name,
+            mainnet_master: Xpriv::new_master(false, &seed),
+            testnet_master: Xpriv::new_master(true, &seed),
+            seed,
+        }
+    }
+
+    /// Derives account extended private key for the given scheme.
+    pub fn account(&self, scheme: Bip43, testnet: bool) -> XprivAccount {
+        let master = if testnet { &self.testnet_master } else { &self.mainnet_master };
+        let derivation = scheme.to_account_derivation(HardenedIndex::ZERO, testnet);
+        let origin = XkeyOrigin::new(master.fingerprint(), derivation.clone());
+        XprivAccount::new(master.derive_priv(&derivation), origin).expect("seed must always derive")
+    }
+}
+
+/// Address derived from a seed using one of the BIP-43 schemes.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct AddressVector {
+    pub seed: &'static str,
+    pub scheme: Bip43,
+    /// Full derivation path of the address key.
+    pub derivation: DerivationPath,
+    pub address: Address,
+}
+
+/// Transaction spending a synthetic output of a seed account, at all stages of PSBT workflow.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct PsbtVector {
+    pub seed: &'static str,
+    pub scheme: Bip43,
+    pub descriptor: StdDescr,
+    pub unsigned: Psbt,
+    /// Signature hashes of each of the transaction inputs.
+    pub sighashes: Vec<Bytes32>,
+    pub signed: Psbt,
+    pub tx: Tx,
+}
+
+/// Full set of the test vectors.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TestVectors {
+    pub seeds: Vec<SeedVector>,
+    pub addresses: Vec<AddressVector>,
+    pub psbts: Vec<PsbtVector>,
+}
+
+impl TestVectors {
+    /// Generates test vectors for the well-known seeds, see [`SeedVector::well_known`].
+    pub fn generate() -> Self {
+        let seeds = SeedVector::well_known();
+        let mut addresses = vec![];
+        let mut psbts = vec![];
+        for seed in &seeds {
+            for testnet in [false, true] {
+                for scheme in VECTOR_SCHEMES {
+                    addresses.extend(address_vectors(seed, scheme, testnet));
+                }
+            }
+            for scheme in [Bip43::Bip84, Bip43::Bip86] {
+                psbts.push(psbt_vector(seed, scheme));
+            }
+        }
+        TestVectors {
+            seeds,
+            addresses,
+            psbts,
+        }
+    }
+}
+
+fn descriptor(account: &XprivAccount, scheme: Bip43) -> Option<StdDescr> {
+    let key = XpubDerivable::from(account.to_xpub_account());
+    match scheme {
+        Bip43::Bip84 => Some(Wpkh::from(key).into()),
+        Bip43::Bip86 => Some(TrKey::from(key).into()),
+        _ => None,
+    }
+}
+
+fn address_vectors(seed: &SeedVector, scheme: Bip43, testnet: bool) -> Vec<AddressVector> {
+    let network = if testnet { AddressNetwork::Testnet } else { AddressNetwork::Mainnet };
+    let account = seed.account(scheme, testnet);
+    let descriptor = descriptor(&account, scheme);
+    [(Keychain::OUTER, 0u16), (Keychain::OUTER, 1), (Keychain::INNER, 0)]
+        .into_iter()
+        .map(|(keychain, index)| {
+            let index = NormalIndex::from(index);
+            let address = match &descriptor {
+                Some(descriptor) => descriptor.derive_address(network, keychain, index),
+                None => {
+                    let keychain = NormalIndex::from(keychain);
+                    let pk = account.xpriv().to_xpub().derive_pub([keychain, index]).to_compr_pk();
+                    Address::with(&legacy_script(scheme, pk), network)
+                }
+            }
+            .expect("standard scripts always have addresses");
+            let derivation = account
+                .to_derivation()
+                .into_iter()
+                .chain([DerivationIndex::from(keychain), DerivationIndex::from(index)])
+                .collect();
+            AddressVector {
+                seed: seed.name,
+                scheme,
+                derivation,
+                address,
+            }
+        })
+        .collect()
+}
+
+fn legacy_script(scheme: Bip43, pk: CompressedPk) -> ScriptPubkey {
+    match scheme {
+        Bip43::Bip44 => ScriptPubkey::p2pkh(PubkeyHash::from(pk)),
+        _ => {
+            let redeem = RedeemScript::p2sh_wpkh(WPubkeyHash::from(pk));
+            ScriptPubkey::p2sh(ScriptHash::from(&redeem))
+        }
+    }
+}
+
+fn psbt_vector(seed: &SeedVector, scheme: Bip43) -> PsbtVector {
+    let account = seed.account(scheme, false);
+    let descriptor = descriptor(&account, scheme).expect("scheme with a standard descriptor");
+
+    let prevout = Outpoint::new(Txid::from([0x01; 32]), 0);
+    let mut psbt = Psbt::create(PsbtVer::V0);
+    psbt.construct_input_expect(
+        Prevout::new(prevout, VECTOR_PREVOUT_VALUE.into()),
+        &descriptor,
+        Terminal::new(Keychain::OUTER, NormalIndex::ZERO),
+        SeqNo::from_consensus_u32(0xFFFF_FFFD),
+    );
+    let script = |keychain, index| {
+        descriptor
+            .derive_address(AddressNetwork::Mainnet, keychain, index)
+            .expect("standard scripts always have addresses")
+            .script_pubkey()
+    };
+    let payment = script(Keychain::OUTER, NormalIndex::ONE);
+    let change = script(Keychain::INNER, NormalIndex::ZERO);
+    let amount = VECTOR_PREVOUT_VALUE / 2;
+    psbt.construct_output_expect(payment, amount.into());
+    psbt.construct_output_expect(change, (VECTOR_PREVOUT_VALUE - amount - VECTOR_FEE).into());
+    psbt.complete_construction();
+
+    let unsigned = psbt.clone();
+    let sighashes = sighashes(&psbt);
+    psbt.sign(&VectorSigner(&account)).expect("vector PSBT must be signable");
+    let signed = psbt.clone();
+    psbt.finalize(&descriptor);
+    let tx = psbt.extract().expect("vector PSBT must be fully signed");
+
+    PsbtVector {
+        seed: seed.name,
+        scheme,
+        descriptor,
+        unsigned,
+        sighashes,
+        signed,
+        tx,
+    }
+}
+
+/// Computes signature hashes for all PSBT inputs with their sighash types.
+fn sighashes(psbt: &Psbt) -> Vec<Bytes32> {
+    let tx = Tx::from(psbt.to_unsigned_tx());
+    let prevouts = psbt.inputs().map(psbt::Input::prev_txout).cloned().collect::<Vec<_>>();
+    let mut sig_hasher =
+        SighashCache::new(tx, prevouts).expect("inputs and prevouts match algorithmically");
+    psbt.inputs()
+        .map(|input| {
+            let sighash = if input.is_bip340() {
+                sig_hasher.tap_sighash_key(input.index(), input.sighash_type).map(Bytes32::from)
+            } else {
+                let script_code = input.script_code().expect("segwit v0 input");
+                sig_hasher
+                    .segwit_sighash(
+                        input.index(),
+                        &script_code,
+                        input.value(),
+                        input.sighash_type.unwrap_or_default(),
+                    )
+                    .map(Bytes32::from)
+            };
+            sighash.expect("valid PSBT input")
+        })
+        .collect()
+}
+
+/// Signer producing deterministic signatures: ECDSA signatures use RFC-6979 nonces and BIP-340
+/// signatures are created without auxiliary randomness.
+struct VectorSigner<'a>(&'a XprivAccount);
+
+impl Signer for VectorSigner<'_> {
+    type Sign<'s>
+        = &'s Self
+    where Self: 's;
+
+    fn approve(&self, _psbt: &Psbt) -> Result<Self::Sign<'_>, Rejected> { Ok(self) }
+}
+
+impl VectorSigner<'_> {
+    fn derive_subkey(&self, origin: Option<&KeyOrigin>) -> Option<Xpriv> {
+        let origin = origin?;
+        if !self.0.origin().is_subset_of(origin) {
+            return None;
+        }
+        Some(self.0.xpriv().derive_priv(&origin.derivation()[self.0.origin().derivation().len()..]))
+    }
+}
+
+impl Sign for &'_ VectorSigner<'_> {
+    fn sign_ecdsa(
+        &self,
+        message: Sighash,
+        pk: LegacyPk,
+        origin: Option<&KeyOrigin>,
+    ) -> Option<ecdsa::Signature> {
+        let sk = self.derive_subkey(origin)?;
+        if sk.to_compr_pk().to_inner() != pk.pubkey {
+            return None;
+        }
+        Some(sk.to_private_ecdsa().sign_ecdsa(message.into()))
+    }
+
+    fn sign_bip340_key_only(
+        &self,
+        message: TapSighash,
+        pk: InternalPk,
+        origin: Option<&KeyOrigin>,
+        merkle_root: Option<TapNodeHash>,
+    ) -> Option<bip340::Signature> {
+        let xpriv = self.derive_subkey(origin)?;
+        if xpriv.to_xonly_pk() != pk.to_xonly_pk() {
+            return None;
+        }
+        let output_pair =
+            InternalKeypair::from(xpriv.to_keypair_bip340()).to_output_keypair(merkle_root).0;
+        Some(SECP256K1.sign_schnorr_no_aux_rand(message.as_ref(), &output_pair))
+    }
+
+    fn sign_bip340_script_path(
+        &self,
+        message: TapSighash,
+        pk: XOnlyPk,
+        origin: Option<&KeyOrigin>,
+    ) -> Option<bip340::Signature> {
+        let sk = self.derive_subkey(origin)?;
+        if sk.to_xonly_pk() != pk {
+            return None;
+        }
+        Some(SECP256K1.sign_schnorr_no_aux_rand(message.as_ref(), &sk.to_keypair_bip340()))
+    }
+
+    fn should_sign_script_path(
+        &self,
+        _index: usize,
+        _merkle_path: &TapMerklePath,
+        _leaf: TapLeafHash,
+    ) -> bool {
+        true
+    }
+
+    fn should_sign_key_path(&self, _index: usize) -> bool { true }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_bip_vectors() {
+        let vectors = TestVectors::generate();
+        let address = |scheme: Bip43, derivation: &str| {
+            let derivation = DerivationPath::from_str(derivation).unwrap();
+            vectors
+                .addresses
+                .iter()
+                .find(|v| {
+                    v.seed == "bip39-abandon-about"
+                        && v.scheme == scheme
+                        && v.derivation == derivation
+                })
+                .map(|v| v.address.to_string())
+                .unwrap()
+        };
+        // Vectors from BIP-44, BIP-49, BIP-84 and BIP-86 for the `abandon ... about` mnemonic
+        assert_eq!(address(Bip43::Bip44, "44h/0h/0h/0/0"), "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+        assert_eq!(address(Bip43::Bip49, "49h/1h/0h/0/0"), "2Mww8dCYPUpKHofjgcXcBCEGmniw9CoaiD2");
+        assert_eq!(
+            address(Bip43::Bip84, "84h/0h/0h/0/0"),
+            "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu"
+        );
+        assert_eq!(
+            address(Bip43::Bip86, "86h/0h/0h/0/0"),
+            "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr"
+        );
+
+        assert_eq!(vectors, TestVectors::generate());
+        assert!(vectors.psbts.iter().all(|v| v.tx.inputs.iter().all(|i| !i.witness.is_empty())));
+    }
+}
@@ -19,10 +19,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bpstd::Tx;
+use bpstd::{Outpoint, Tx, Txid};
 use descriptors::Descriptor;
 
-use crate::{Indexer, Layer2, MayError, WalletCache, WalletDescr};
+use super::{FeeSnapshot, OutpointStatus, TxStore};
+use crate::{Indexer, Layer2, MayError, Timings, WalletCache, WalletDescr};
 
 /// Type that contains any of the client types implementing the Indexer trait
 #[derive(From)]
@@ -32,6 +33,10 @@ pub enum AnyIndexer {
     #[from]
     /// Electrum indexer
     Electrum(Box<electrum::client::Client>),
+    #[cfg(feature = "electrum")]
+    #[from]
+    /// Electrum indexer connected with a custom TLS configuration
+    ElectrumTls(Box<electrum::raw_client::RawClient<electrum::raw_client::ElectrumSslStream>>),
     #[cfg(feature = "esplora")]
     #[from]
     /// Esplora indexer
@@ -45,7 +50,7 @@ impl AnyIndexer {
     pub fn name(&self) -> &'static str {
         match self {
             #[cfg(feature = "electrum")]
-            AnyIndexer::Electrum(_) => "electrum",
+            AnyIndexer::Electrum(_) | AnyIndexer::ElectrumTls(_) => "electrum",
             #[cfg(feature = "esplora")]
             AnyIndexer::Esplora(_) => "esplora",
             #[cfg(feature = "mempool")]
@@ -85,6 +90,14 @@ impl Indexer for AnyIndexer {
                     err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
                 }
             }
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => {
+                let result = inner.create::<K, D, L2>(descr);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
             #[cfg(feature = "esplora")]
             AnyIndexer::Esplora(inner) => {
                 let result = inner.create::<K, D, L2>(descr);
@@ -118,6 +131,14 @@ impl Indexer for AnyIndexer {
                     err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
                 }
             }
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => {
+                let result = inner.update::<K, D, L2>(descr, cache);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
             #[cfg(feature = "esplora")]
             AnyIndexer::Esplora(inner) => {
                 let result = inner.update::<K, D, L2>(descr, cache);
@@ -137,14 +158,98 @@ impl Indexer for AnyIndexer {
         }
     }
 
+    fn update_with_store<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        store: &mut TxStore,
+        timings: &mut Timings,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => {
+                let result = inner.update_with_store::<K, D, L2>(descr, cache, store, timings);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => {
+                let result = inner.update_with_store::<K, D, L2>(descr, cache, store, timings);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => {
+                let result = inner.update_with_store::<K, D, L2>(descr, cache, store, timings);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => {
+                let result = inner.update_with_store::<K, D, L2>(descr, cache, store, timings);
+                MayError {
+                    ok: result.ok,
+                    err: result.err.map(|v| v.into_iter().map(|e| e.into()).collect()),
+                }
+            }
+        }
+    }
+
     fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
         match self {
             #[cfg(feature = "electrum")]
             AnyIndexer::Electrum(inner) => inner.publish(tx).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => inner.publish(tx).map_err(|e| e.into()),
             #[cfg(feature = "esplora")]
             AnyIndexer::Esplora(inner) => inner.publish(tx).map_err(|e| e.into()),
             #[cfg(feature = "mempool")]
             AnyIndexer::Mempool(inner) => inner.publish(tx).map_err(|e| e.into()),
         }
     }
+
+    fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.transaction(txid).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => inner.transaction(txid).map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.transaction(txid).map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.transaction(txid).map_err(|e| e.into()),
+        }
+    }
+
+    fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.outpoint_status(outpoint).map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => inner.outpoint_status(outpoint).map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.outpoint_status(outpoint).map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.outpoint_status(outpoint).map_err(|e| e.into()),
+        }
+    }
+
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> {
+        match self {
+            #[cfg(feature = "electrum")]
+            AnyIndexer::Electrum(inner) => inner.fee_snapshot().map_err(|e| e.into()),
+            #[cfg(feature = "electrum")]
+            AnyIndexer::ElectrumTls(inner) => inner.fee_snapshot().map_err(|e| e.into()),
+            #[cfg(feature = "esplora")]
+            AnyIndexer::Esplora(inner) => inner.fee_snapshot().map_err(|e| e.into()),
+            #[cfg(feature = "mempool")]
+            AnyIndexer::Mempool(inner) => inner.fee_snapshot().map_err(|e| e.into()),
+        }
+    }
 }
@@ -0,0 +1,1066 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Wallet sync from BIP-158 compact block filters, which are matched against the wallet scripts
+//! locally, such that the scripts are never revealed to a server. Filters and blocks are
+//! obtained from a [`FilterSource`], which is either a P2P peer serving filters according to
+//! BIP-157 ([`P2pPeer`]) or a directory with the filter files ([`FilterDir`]).
+//!
+//! Filters cover only mined transactions, so mempool transactions are not seen by the indexer.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use amplify::hex::FromHex;
+use amplify::ByteArray;
+use bpstd::{
+    BlockHash, BlockHeader, ConsensusDecode, ConsensusDecodeError, ConsensusEncode, Network,
+    Outpoint, ScriptPubkey, Tx, Txid, VarInt,
+};
+use descriptors::Descriptor;
+use sha2::{Digest, Sha256};
+
+use super::{extend_watched, FeeSnapshot, OutpointStatus, TxStore};
+use crate::{
+    BlockHeight, Indexer, Layer2, MayError, MiningInfo, Phase, Timings, TxStatus, WalletCache,
+    WalletDescr,
+};
+
+/// Golomb-Rice coding parameter of the basic block filters.
+pub const FILTER_P: u8 = 19;
+/// Inverse false positive rate of the basic block filters.
+pub const FILTER_M: u64 = 784_931;
+
+const PROTOCOL_VERSION: u32 = 70016;
+const NODE_COMPACT_FILTERS: u64 = 1 << 6;
+const MSG_WITNESS_BLOCK: u32 = 0x4000_0002;
+const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+const MAX_HEADERS: u64 = 2000;
+const FILTER_BATCH: u32 = 1000;
+/// Interval between the filter headers returned in the `cfcheckpt` message.
+const CHECKPOINT_INTERVAL: u32 = 1000;
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum FilterError {
+    /// I/O error accessing the filter source. Details: {0}
+    #[from]
+    Io(io::Error),
+
+    /// filter source provided data with invalid encoding. Details: {0}
+    #[from]
+    Encoding(ConsensusDecodeError),
+
+    /// no block header is known at height {0}.
+    NoHeader(u32),
+
+    /// compact filter of block {0} is not available.
+    NoFilter(BlockHash),
+
+    /// block {0} is not available.
+    NoBlock(BlockHash),
+
+    /// filter source provided block {0} instead of the requested block {1}.
+    BlockMismatch(BlockHash, BlockHash),
+
+    /// transactions of block {0} don't match the merkle root of the block header.
+    MerkleMismatch(BlockHash),
+
+    /// block {0} has duplicated transactions in its merkle tree, which may be used to fake the
+    /// block content (CVE-2012-2459).
+    MutatedBlock(BlockHash),
+
+    /// block header at height {0} doesn't satisfy the proof of work it declares, or declares a
+    /// target above the network limit.
+    InvalidPow(u32),
+
+    /// compact filter of block {0} doesn't match its filter header.
+    FilterHeaderMismatch(BlockHash),
+
+    /// filter header chain at height {0} doesn't match the checkpoint.
+    CheckpointMismatch(u32),
+
+    /// block header at height {0} doesn't connect to the previous header.
+    Disconnected(u32),
+
+    /// peer doesn't serve compact block filters.
+    NoFilterService,
+
+    /// peer violated P2P protocol: {0}.
+    Protocol(String),
+
+    /// {0} is not supported by compact filter sources.
+    Unsupported(&'static str),
+}
+
+/// Basic compact block filter defined in BIP-158: a Golomb-coded set of the scripts created and
+/// spent by the block transactions.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BlockFilter {
+    /// Number of the items in the set.
+    pub n: u64,
+    /// Golomb-Rice coded set items.
+    pub data: Vec<u8>,
+}
+
+impl BlockFilter {
+    /// Builds filter of the block from the scripts created and spent by its transactions.
+    /// Empty scripts are skipped; the caller must not provide `OP_RETURN` outputs, which are
+    /// excluded from the filters by BIP-158.
+    pub fn build<'s>(block_hash: BlockHash, scripts: impl IntoIterator<Item = &'s [u8]>) -> Self {
+        let mut unique =
+            scripts.into_iter().filter(|script| !script.is_empty()).collect::<Vec<_>>();
+        unique.sort_unstable();
+        unique.dedup();
+        let n = unique.len() as u64;
+        let items = hashed_set(block_hash, n, unique);
+        let mut writer = BitWriter::default();
+        let mut last = 0u64;
+        for item in items {
+            let delta = item - last;
+            last = item;
+            for _ in 0..(delta >> FILTER_P) {
+                writer.write_bit(true);
+            }
+            writer.write_bit(false);
+            writer.write_bits(delta, FILTER_P);
+        }
+        BlockFilter {
+            n,
+            data: writer.data,
+        }
+    }
+
+    /// Parses filter in the serialization used by the `cfilter` P2P message.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FilterError> {
+        let mut reader = Cursor::new(bytes);
+        let n = VarInt::consensus_decode(&mut reader)?.0;
+        let data = bytes[reader.position() as usize..].to_vec();
+        Ok(BlockFilter { n, data })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        VarInt(self.n).consensus_encode(&mut bytes).expect("in-memory writer");
+        bytes.extend(&self.data);
+        bytes
+    }
+
+    /// Checks whether any of the scripts may be present in the block. False positives happen
+    /// with probability of 1/[`FILTER_M`] per script, while false negatives are impossible.
+    pub fn match_any<'s>(
+        &self,
+        block_hash: BlockHash,
+        scripts: impl IntoIterator<Item = &'s [u8]>,
+    ) -> bool {
+        let query = hashed_set(block_hash, self.n, scripts);
+        let mut query = query.into_iter().peekable();
+        let mut reader = BitReader::new(&self.data);
+        let mut value = 0u64;
+        for _ in 0..self.n {
+            let Some(delta) = reader.read_golomb() else {
+                return false;
+            };
+            value += delta;
+            while query.next_if(|item| *item < value).is_some() {}
+            match query.peek() {
+                Some(item) if *item == value => return true,
+                None => return false,
+                Some(_) => {}
+            }
+        }
+        false
+    }
+}
+
+/// Hashes the scripts into the range of the filter with `n` items, returning them sorted.
+fn hashed_set<'s>(
+    block_hash: BlockHash,
+    n: u64,
+    scripts: impl IntoIterator<Item = &'s [u8]>,
+) -> Vec<u64> {
+    let key = block_hash.to_byte_array();
+    let k0 = u64::from_le_bytes(key[..8].try_into().expect("fixed size"));
+    let k1 = u64::from_le_bytes(key[8..16].try_into().expect("fixed size"));
+    let range = n as u128 * FILTER_M as u128;
+    let mut items = scripts
+        .into_iter()
+        .map(|script| ((siphash24(k0, k1, script) as u128 * range) >> 64) as u64)
+        .collect::<Vec<_>>();
+    items.sort_unstable();
+    items
+}
+
+fn siphash24(k0: u64, k1: u64, data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    fn round(v: &mut [u64; 4]) {
+        v[0] = v[0].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(13) ^ v[0];
+        v[0] = v[0].rotate_left(32);
+        v[2] = v[2].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(16) ^ v[2];
+        v[0] = v[0].wrapping_add(v[3]);
+        v[3] = v[3].rotate_left(21) ^ v[0];
+        v[2] = v[2].wrapping_add(v[1]);
+        v[1] = v[1].rotate_left(17) ^ v[2];
+        v[2] = v[2].rotate_left(32);
+    }
+    let mut compress = |m: u64| {
+        v[3] ^= m;
+        round(&mut v);
+        round(&mut v);
+        v[0] ^= m;
+    };
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        compress(u64::from_le_bytes(chunk.try_into().expect("exact chunk")));
+    }
+    let mut last = [0u8; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    compress(u64::from_le_bytes(last));
+    v[2] ^= 0xff;
+    for _ in 0..4 {
+        round(&mut v);
+    }
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+struct BitReader<'data> {
+    data: &'data [u8],
+    pos: usize,
+}
+
+impl<'data> BitReader<'data> {
+    fn new(data: &'data [u8]) -> Self { BitReader { data, pos: 0 } }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.data.get(self.pos / 8)?;
+        let bit = byte & (0x80 >> (self.pos % 8)) != 0;
+        self.pos += 1;
+        Some(bit)
+    }
+
+    fn read_golomb(&mut self) -> Option<u64> {
+        let mut quotient = 0u64;
+        while self.read_bit()? {
+            quotient += 1;
+        }
+        let mut remainder = 0u64;
+        for _ in 0..FILTER_P {
+            remainder = (remainder << 1) | self.read_bit()? as u64;
+        }
+        Some((quotient << FILTER_P) | remainder)
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.pos % 8 == 0 {
+            self.data.push(0);
+        }
+        if bit {
+            *self.data.last_mut().expect("byte is pushed") |= 0x80 >> (self.pos % 8);
+        }
+        self.pos += 1;
+    }
+
+    fn write_bits(&mut self, value: u64, count: u8) {
+        for no in (0..count).rev() {
+            self.write_bit(value & (1 << no) != 0);
+        }
+    }
+}
+
+/// Source of the block headers, compact block filters and blocks.
+pub trait FilterSource {
+    /// Height of the last block known to the source.
+    fn tip_height(&self) -> Result<u32, FilterError>;
+
+    /// Retrieves header of the block at the given non-zero height.
+    fn block_header(&self, height: u32) -> Result<BlockHeader, FilterError>;
+
+    /// Retrieves the basic compact filter of the block.
+    fn filter(&self, height: u32, block_hash: BlockHash) -> Result<BlockFilter, FilterError>;
+
+    /// Retrieves transactions of the block, checking them against the block header.
+    fn block(&self, block_hash: BlockHash) -> Result<Vec<Tx>, FilterError>;
+
+    fn publish(&self, tx: &Tx) -> Result<(), FilterError>;
+}
+
+/// Directory with the block headers, filters and blocks, which is usually filled in by an
+/// external tool from a trusted node. The directory contains:
+/// - `headers` file with the consensus-serialized headers of all blocks starting from the genesis;
+/// - `filters/<block hash>` files with the serialized basic filters of the blocks (see
+///   [`BlockFilter::from_bytes`]);
+/// - `blocks/<block hash>` files with the consensus-serialized blocks, which are required only for
+///   the blocks matching the wallet scripts.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FilterDir {
+    path: PathBuf,
+}
+
+impl FilterDir {
+    pub fn new(path: impl Into<PathBuf>) -> Self { FilterDir { path: path.into() } }
+}
+
+impl FilterSource for FilterDir {
+    fn tip_height(&self) -> Result<u32, FilterError> {
+        let len = fs::metadata(self.path.join("headers"))?.len();
+        match len / 80 {
+            0 => Err(FilterError::NoHeader(0)),
+            count => Ok(count as u32 - 1),
+        }
+    }
+
+    fn block_header(&self, height: u32) -> Result<BlockHeader, FilterError> {
+        let mut file = File::open(self.path.join("headers"))?;
+        file.seek(SeekFrom::Start(height as u64 * 80))?;
+        let mut data = [0u8; 80];
+        file.read_exact(&mut data).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => FilterError::NoHeader(height),
+            _ => err.into(),
+        })?;
+        Ok(BlockHeader::consensus_decode(&mut Cursor::new(data))?)
+    }
+
+    fn filter(&self, _height: u32, block_hash: BlockHash) -> Result<BlockFilter, FilterError> {
+        let path = self.path.join("filters").join(block_hash.to_string());
+        let data = fs::read(path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => FilterError::NoFilter(block_hash),
+            _ => err.into(),
+        })?;
+        BlockFilter::from_bytes(&data)
+    }
+
+    fn block(&self, block_hash: BlockHash) -> Result<Vec<Tx>, FilterError> {
+        let path = self.path.join("blocks").join(block_hash.to_string());
+        let data = fs::read(path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => FilterError::NoBlock(block_hash),
+            _ => err.into(),
+        })?;
+        decode_block(&data, block_hash)
+    }
+
+    fn publish(&self, _tx: &Tx) -> Result<(), FilterError> {
+        Err(FilterError::Unsupported("transaction broadcasting"))
+    }
+}
+
+/// Decodes consensus-serialized block, checking its header hash and the merkle root of its
+/// transactions.
+fn decode_block(data: &[u8], block_hash: BlockHash) -> Result<Vec<Tx>, FilterError> {
+    let mut reader = Cursor::new(data);
+    let header = BlockHeader::consensus_decode(&mut reader)?;
+    if header.block_hash() != block_hash {
+        return Err(FilterError::BlockMismatch(header.block_hash(), block_hash));
+    }
+    let count = VarInt::consensus_decode(&mut reader)?.0;
+    let txs =
+        (0..count).map(|_| Tx::consensus_decode(&mut reader)).collect::<Result<Vec<_>, _>>()?;
+    let root = merkle_root(txs.iter().map(|tx| tx.txid().to_byte_array()).collect(), block_hash)?;
+    if root != header.merkle_root.to_byte_array() {
+        return Err(FilterError::MerkleMismatch(block_hash));
+    }
+    Ok(txs)
+}
+
+/// Computes merkle root of the block transaction ids. Fails if two sibling nodes are equal, since
+/// such trees may be produced from different transaction lists (CVE-2012-2459).
+fn merkle_root(mut level: Vec<[u8; 32]>, block_hash: BlockHash) -> Result<[u8; 32], FilterError> {
+    while level.len() > 1 {
+        if level.chunks_exact(2).any(|pair| pair[0] == pair[1]) {
+            return Err(FilterError::MutatedBlock(block_hash));
+        }
+        level = level
+            .chunks(2)
+            .map(|pair| {
+                let mut engine = Sha256::new();
+                engine.update(pair[0]);
+                engine.update(pair.get(1).unwrap_or(&pair[0]));
+                Sha256::digest(engine.finalize()).into()
+            })
+            .collect();
+    }
+    level.first().copied().ok_or(FilterError::MerkleMismatch(block_hash))
+}
+
+/// Decodes the compact target representation used in the block headers, returning the target
+/// as big-endian bytes. Negative, zero and overflowing targets are rejected.
+fn target(bits: u32) -> Option<[u8; 32]> {
+    let exp = (bits >> 24) as usize;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 || mantissa == 0 {
+        return None;
+    }
+    let mut target = [0u8; 32];
+    for (no, byte) in mantissa.to_be_bytes()[1..].iter().enumerate() {
+        match (32 + no).checked_sub(exp) {
+            // Bytes below the unit position are shifted out
+            _ if no >= exp => {}
+            Some(pos) => target[pos] = *byte,
+            None if *byte != 0 => return None,
+            None => {}
+        }
+    }
+    Some(target)
+}
+
+/// Checks that the block hash satisfies the target declared by the header, and that the target
+/// doesn't exceed the network proof-of-work limit given in the compact form.
+///
+/// The declared target is not checked against the difficulty retargeting rules, so a peer may
+/// provide a chain of headers mined at the minimal difficulty allowed by the network. This only
+/// protects from headers without any work behind them; the peer is still trusted to provide the
+/// most-work chain.
+fn check_pow(header: &BlockHeader, pow_limit: u32) -> bool {
+    let (Some(target), Some(limit)) = (target(header.bits), target(pow_limit)) else {
+        return false;
+    };
+    let mut hash = header.block_hash().to_byte_array();
+    hash.reverse();
+    target <= limit && hash <= target
+}
+
+fn double_sha256(data: impl AsRef<[u8]>) -> [u8; 32] { Sha256::digest(Sha256::digest(data)).into() }
+
+/// Bitcoin P2P peer serving compact block filters (BIP-157).
+///
+/// Block headers are downloaded from the peer on each tip request and are checked to form a
+/// chain starting from the genesis, with each header satisfying the proof of work it declares
+/// within the network limit. Difficulty retargeting is not verified, so the peer must still be
+/// trusted to provide the most-work chain.
+///
+/// Filters are checked against the filter header chain (`cfheaders`), which in turn is checked
+/// against the filter header checkpoints (`cfcheckpt`) provided by the peer for each 1000 blocks.
+pub struct P2pPeer {
+    stream: RefCell<TcpStream>,
+    magic: [u8; 4],
+    genesis: BlockHash,
+    /// Proof-of-work limit of the network in the compact form.
+    pow_limit: u32,
+    /// Headers of the blocks starting from the height 1.
+    headers: RefCell<Vec<BlockHeader>>,
+    /// Filter headers at the heights multiple of [`CHECKPOINT_INTERVAL`], starting from it.
+    checkpoints: RefCell<Vec<[u8; 32]>>,
+    /// Height and filter header of the last block with a verified filter hash.
+    last_filter_header: RefCell<Option<(u32, [u8; 32])>>,
+    /// Filters received in a batch, which were not requested yet.
+    filters: RefCell<BTreeMap<BlockHash, BlockFilter>>,
+}
+
+impl P2pPeer {
+    /// Connects to the peer and performs the P2P handshake, checking that the peer serves
+    /// compact block filters.
+    pub fn connect(addr: impl ToSocketAddrs, network: Network) -> Result<Self, FilterError> {
+        let (magic, genesis, pow_limit) = match network {
+            Network::Mainnet => (
+                [0xf9, 0xbe, 0xb4, 0xd9],
+                "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+                0x1d00_ffff,
+            ),
+            Network::Testnet3 => (
+                [0x0b, 0x11, 0x09, 0x07],
+                "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+                0x1d00_ffff,
+            ),
+            Network::Testnet4 => (
+                [0x1c, 0x16, 0x3f, 0x28],
+                "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+                0x1d00_ffff,
+            ),
+            Network::Signet => (
+                [0x0a, 0x03, 0xcf, 0x40],
+                "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+                0x1e03_77ae,
+            ),
+            Network::Regtest => (
+                [0xfa, 0xbf, 0xb5, 0xda],
+                "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+                0x207f_ffff,
+            ),
+        };
+        let stream = TcpStream::connect(addr)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let peer = P2pPeer {
+            stream: RefCell::new(stream),
+            magic,
+            genesis: BlockHash::from_hex(genesis).expect("hardcoded genesis hash"),
+            pow_limit,
+            headers: none!(),
+            checkpoints: none!(),
+            last_filter_header: none!(),
+            filters: none!(),
+        };
+        peer.handshake()?;
+        Ok(peer)
+    }
+
+    fn handshake(&self) -> Result<(), FilterError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+        let mut payload = PROTOCOL_VERSION.to_le_bytes().to_vec();
+        payload.extend(0u64.to_le_bytes());
+        payload.extend(timestamp.to_le_bytes());
+        // Network addresses of the receiver and the sender, which are ignored by the peers
+        payload.extend([0u8; 52]);
+        payload.extend(rand_nonce().to_le_bytes());
+        let user_agent = concat!("/bp-wallet:", env!("CARGO_PKG_VERSION"), "/");
+        VarInt::with(user_agent.len()).consensus_encode(&mut payload).expect("in-memory writer");
+        payload.extend(user_agent.as_bytes());
+        payload.extend(0u32.to_le_bytes());
+        // Transactions are not relayed to us, since we don't track the mempool
+        payload.push(0);
+        self.send("version", &payload)?;
+
+        let version = self.expect("version")?;
+        let services = version
+            .get(4..12)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("fixed size")))
+            .ok_or_else(|| FilterError::Protocol("truncated version message".to_owned()))?;
+        if services & NODE_COMPACT_FILTERS == 0 {
+            return Err(FilterError::NoFilterService);
+        }
+        self.send("verack", &[])?;
+        self.expect("verack")?;
+        Ok(())
+    }
+
+    fn send(&self, command: &str, payload: &[u8]) -> Result<(), FilterError> {
+        let mut message = Vec::with_capacity(24 + payload.len());
+        message.extend(self.magic);
+        let mut name = [0u8; 12];
+        name[..command.len()].copy_from_slice(command.as_bytes());
+        message.extend(name);
+        message.extend((payload.len() as u32).to_le_bytes());
+        message.extend(checksum(payload));
+        message.extend(payload);
+        self.stream.borrow_mut().write_all(&message)?;
+        Ok(())
+    }
+
+    /// Receives the next message, answering pings.
+    fn receive(&self) -> Result<(String, Vec<u8>), FilterError> {
+        loop {
+            let mut header = [0u8; 24];
+            self.stream.borrow_mut().read_exact(&mut header)?;
+            if header[..4] != self.magic {
+                return Err(FilterError::Protocol("invalid network magic".to_owned()));
+            }
+            let command = String::from_utf8_lossy(&header[4..16]).trim_end_matches('\0').to_owned();
+            let len = u32::from_le_bytes(header[16..20].try_into().expect("fixed size")) as usize;
+            if len > MAX_MESSAGE_SIZE {
+                return Err(FilterError::Protocol(format!("{command} message is too large")));
+            }
+            let mut payload = vec![0u8; len];
+            self.stream.borrow_mut().read_exact(&mut payload)?;
+            if checksum(&payload) != header[20..24] {
+                let msg = format!("invalid checksum of {command} message");
+                return Err(FilterError::Protocol(msg));
+            }
+            if command == "ping" {
+                self.send("pong", &payload)?;
+                continue;
+            }
+            return Ok((command, payload));
+        }
+    }
+
+    /// Receives messages until the one with the given command, skipping the others.
+    fn expect(&self, command: &str) -> Result<Vec<u8>, FilterError> {
+        loop {
+            let (received, payload) = self.receive()?;
+            if received == command {
+                return Ok(payload);
+            }
+        }
+    }
+
+    fn sync_headers(&self) -> Result<u32, FilterError> {
+        loop {
+            let last = self.headers.borrow().last().map(BlockHeader::block_hash);
+            let mut payload = PROTOCOL_VERSION.to_le_bytes().to_vec();
+            payload.push(1);
+            payload.extend(last.unwrap_or(self.genesis).to_byte_array());
+            payload.extend([0u8; 32]);
+            self.send("getheaders", &payload)?;
+
+            let payload = self.expect("headers")?;
+            let mut reader = Cursor::new(&payload);
+            let count = VarInt::consensus_decode(&mut reader)?.0;
+            let mut headers = self.headers.borrow_mut();
+            for _ in 0..count {
+                let header = BlockHeader::consensus_decode(&mut reader)?;
+                // Headers message contains an empty transaction count after each header
+                VarInt::consensus_decode(&mut reader)?;
+                let prev = headers.last().map(BlockHeader::block_hash).unwrap_or(self.genesis);
+                let height = headers.len() as u32 + 1;
+                if header.prev_block_hash != prev {
+                    return Err(FilterError::Disconnected(height));
+                }
+                if !check_pow(&header, self.pow_limit) {
+                    return Err(FilterError::InvalidPow(height));
+                }
+                headers.push(header);
+            }
+            if count < MAX_HEADERS {
+                let tip = headers.len() as u32;
+                drop(headers);
+                self.sync_checkpoints(tip)?;
+                return Ok(tip);
+            }
+        }
+    }
+
+    /// Downloads filter header checkpoints up to the tip.
+    fn sync_checkpoints(&self, tip: u32) -> Result<(), FilterError> {
+        if tip < CHECKPOINT_INTERVAL {
+            return Ok(());
+        }
+        let mut payload = vec![0u8];
+        payload.extend(self.block_header(tip)?.block_hash().to_byte_array());
+        self.send("getcfcheckpt", &payload)?;
+
+        let payload = self.expect("cfcheckpt")?;
+        let mut reader = Cursor::new(&payload);
+        let _filter_type = u8::consensus_decode(&mut reader)?;
+        let _stop_hash = <[u8; 32]>::consensus_decode(&mut reader)?;
+        let count = VarInt::consensus_decode(&mut reader)?.0;
+        if count != (tip / CHECKPOINT_INTERVAL) as u64 {
+            return Err(FilterError::Protocol(format!("invalid number of checkpoints {count}")));
+        }
+        let checkpoints = (0..count)
+            .map(|_| <[u8; 32]>::consensus_decode(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        *self.checkpoints.borrow_mut() = checkpoints;
+        Ok(())
+    }
+
+    /// Downloads hashes of the filters of the blocks in the range, checking them against the
+    /// filter header chain, and returns them by the block hash.
+    fn filter_hashes(
+        &self,
+        height: u32,
+        stop_height: u32,
+    ) -> Result<BTreeMap<BlockHash, [u8; 32]>, FilterError> {
+        let stop_hash = self.block_header(stop_height)?.block_hash();
+        let mut payload = vec![0u8];
+        payload.extend(height.to_le_bytes());
+        payload.extend(stop_hash.to_byte_array());
+        self.send("getcfheaders", &payload)?;
+
+        let payload = self.expect("cfheaders")?;
+        let mut reader = Cursor::new(&payload);
+        let _filter_type = u8::consensus_decode(&mut reader)?;
+        let _stop_hash = <[u8; 32]>::consensus_decode(&mut reader)?;
+        let mut filter_header = <[u8; 32]>::consensus_decode(&mut reader)?;
+        let count = VarInt::consensus_decode(&mut reader)?.0;
+        if count != (stop_height - height + 1) as u64 {
+            return Err(FilterError::Protocol(format!("invalid number of filter hashes {count}")));
+        }
+
+        let checkpoints = self.checkpoints.borrow();
+        let check = |height: u32, filter_header: [u8; 32]| {
+            let checkpoint = (height > 0 && height % CHECKPOINT_INTERVAL == 0)
+                .then(|| checkpoints.get((height / CHECKPOINT_INTERVAL - 1) as usize))
+                .flatten();
+            match checkpoint {
+                Some(checkpoint) if *checkpoint != filter_header => {
+                    Err(FilterError::CheckpointMismatch(height))
+                }
+                _ => Ok(()),
+            }
+        };
+        let prev_height = height - 1;
+        check(prev_height, filter_header)?;
+        if let Some((last_height, last_header)) = *self.last_filter_header.borrow() {
+            if last_height == prev_height && last_header != filter_header {
+                return Err(FilterError::CheckpointMismatch(prev_height));
+            }
+        }
+
+        let mut hashes = BTreeMap::new();
+        for height in height..=stop_height {
+            let filter_hash = <[u8; 32]>::consensus_decode(&mut reader)?;
+            let mut data = filter_hash.to_vec();
+            data.extend(filter_header);
+            filter_header = double_sha256(data);
+            check(height, filter_header)?;
+            hashes.insert(self.block_header(height)?.block_hash(), filter_hash);
+        }
+        *self.last_filter_header.borrow_mut() = Some((stop_height, filter_header));
+        Ok(hashes)
+    }
+}
+
+impl FilterSource for P2pPeer {
+    fn tip_height(&self) -> Result<u32, FilterError> { self.sync_headers() }
+
+    fn block_header(&self, height: u32) -> Result<BlockHeader, FilterError> {
+        let headers = self.headers.borrow();
+        height
+            .checked_sub(1)
+            .and_then(|index| headers.get(index as usize))
+            .copied()
+            .ok_or(FilterError::NoHeader(height))
+    }
+
+    fn filter(&self, height: u32, block_hash: BlockHash) -> Result<BlockFilter, FilterError> {
+        if let Some(filter) = self.filters.borrow_mut().remove(&block_hash) {
+            return Ok(filter);
+        }
+        // Filters are requested in batches, keeping the ones of the following blocks for the
+        // next calls
+        let stop_height = (height + FILTER_BATCH - 1).min(self.headers.borrow().len() as u32);
+        let stop_hash = self.block_header(stop_height)?.block_hash();
+        let filter_hashes = self.filter_hashes(height, stop_height)?;
+        let mut payload = vec![0u8];
+        payload.extend(height.to_le_bytes());
+        payload.extend(stop_hash.to_byte_array());
+        self.send("getcfilters", &payload)?;
+
+        loop {
+            let payload = self.expect("cfilter")?;
+            let mut reader = Cursor::new(&payload);
+            let _filter_type = u8::consensus_decode(&mut reader)?;
+            let hash = BlockHash::from(<[u8; 32]>::consensus_decode(&mut reader)?);
+            let len = VarInt::consensus_decode(&mut reader)?.0 as usize;
+            let data = &payload[reader.position() as usize..];
+            if data.len() != len {
+                return Err(FilterError::Protocol(format!("invalid length of filter for {hash}")));
+            }
+            if filter_hashes.get(&hash) != Some(&double_sha256(data)) {
+                return Err(FilterError::FilterHeaderMismatch(hash));
+            }
+            self.filters.borrow_mut().insert(hash, BlockFilter::from_bytes(data)?);
+            if hash == stop_hash {
+                break;
+            }
+        }
+        self.filters.borrow_mut().remove(&block_hash).ok_or(FilterError::NoFilter(block_hash))
+    }
+
+    fn block(&self, block_hash: BlockHash) -> Result<Vec<Tx>, FilterError> {
+        let mut payload = vec![1u8];
+        payload.extend(MSG_WITNESS_BLOCK.to_le_bytes());
+        payload.extend(block_hash.to_byte_array());
+        self.send("getdata", &payload)?;
+        loop {
+            match self.receive()? {
+                (command, data) if command == "block" => return decode_block(&data, block_hash),
+                (command, _) if command == "notfound" => {
+                    return Err(FilterError::NoBlock(block_hash));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<(), FilterError> {
+        self.send("tx", &tx.consensus_serialize())
+    }
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let hash = double_sha256(payload);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Nonce of the version message, which only has to differ between the connections.
+fn rand_nonce() -> u64 {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let hash = Sha256::digest(time.as_nanos().to_le_bytes());
+    u64::from_le_bytes(hash[..8].try_into().expect("fixed size"))
+}
+
+/// Indexer syncing wallets from compact block filters provided by a [`FilterSource`].
+///
+/// A sync continues from the last block known to the wallet cache, or from the birth height for
+/// a new wallet. Addresses are watched up to [`super::GAP_LIMIT`] after the last used one,
+/// extending the watched range as the wallet history gets discovered.
+pub struct FilterIndexer<S: FilterSource> {
+    source: S,
+    birth_height: u32,
+}
+
+impl<S: FilterSource> FilterIndexer<S> {
+    pub fn new(source: S) -> Self { FilterIndexer::with_birth_height(source, 1) }
+
+    /// Creates indexer which skips blocks preceding the given height when syncing a new wallet.
+    /// The height must not be greater than the height of the first wallet transaction.
+    pub fn with_birth_height(source: S, birth_height: u32) -> Self {
+        FilterIndexer {
+            source,
+            birth_height: birth_height.max(1),
+        }
+    }
+
+    pub fn source(&self) -> &S { &self.source }
+}
+
+impl<S: FilterSource> Indexer for FilterIndexer<S> {
+    type Error = FilterError;
+
+    fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+        let mut cache = WalletCache::new_nonsync();
+        self.update::<K, D, L2>(descr, &mut cache).map(|_| cache)
+    }
+
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        self.update_with_store::<K, D, L2>(
+            descr,
+            cache,
+            &mut TxStore::default(),
+            &mut Timings::default(),
+        )
+    }
+
+    fn update_with_store<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        store: &mut TxStore,
+        timings: &mut Timings,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let tip = match timings.measure(Phase::Fetch("tip"), || self.source.tip_height()) {
+            Ok(tip) => tip,
+            Err(err) => return MayError::err(0, vec![err]),
+        };
+
+        let mut from = self.birth_height;
+        let last = cache.last_block;
+        if last.block_hash != MiningInfo::genesis().block_hash {
+            match self.source.block_header(last.height.get()) {
+                Ok(header) if header.block_hash() == last.block_hash => {
+                    from = from.max(last.height.get() + 1)
+                }
+                // The last synced block was re-organized, so the history is rebuilt from scratch
+                Ok(_) => {
+                    cache.tx.clear();
+                    cache.utxo.clear();
+                    cache.addr.clear();
+                    cache.reindex();
+                }
+                Err(err) => return MayError::err(0, vec![err]),
+            }
+        }
+
+        let network = descr.network();
+        let sliced = descr.slice_range().is_some();
+        let mut scripts = BTreeMap::new();
+        let mut keychains = descr
+            .watched_keychains()
+            .into_iter()
+            .map(|keychain| (keychain, (descr.watched_addresses(keychain), 0u32)))
+            .collect::<BTreeMap<_, _>>();
+        timings
+            .measure(Phase::Derive, || extend_watched(&mut scripts, &mut keychains, cache, sliced));
+
+        let mut updated = 0usize;
+        let mut errors = vec![];
+        for height in from..=tip {
+            let header = match self.source.block_header(height) {
+                Ok(header) => header,
+                Err(err) => {
+                    errors.push(err);
+                    break;
+                }
+            };
+            let block_hash = header.block_hash();
+            let filter = match timings
+                .measure(Phase::Fetch("filter"), || self.source.filter(height, block_hash))
+            {
+                Ok(filter) => filter,
+                Err(err) => {
+                    errors.push(err);
+                    break;
+                }
+            };
+            let mined = MiningInfo {
+                height: BlockHeight::new(height).expect("heights start from 1"),
+                time: header.time as u64,
+                block_hash,
+            };
+            if filter.match_any(block_hash, scripts.keys().map(ScriptPubkey::as_ref)) {
+                let mut pending = match timings
+                    .measure(Phase::Fetch("block"), || self.source.block(block_hash))
+                {
+                    Ok(txs) => txs,
+                    Err(err) => {
+                        errors.push(err);
+                        break;
+                    }
+                };
+                // Transactions paying to the addresses which get watched only after processing
+                // the previous transactions of the block are picked up by the repeated pass
+                loop {
+                    let watched = scripts.len();
+                    pending.retain(|tx| {
+                        let status = TxStatus::Mined(mined);
+                        if !cache.import_tx(tx.clone(), status, &scripts, network, store) {
+                            return true;
+                        }
+                        store.insert(tx.clone());
+                        updated += 1;
+                        false
+                    });
+                    timings.measure(Phase::Derive, || {
+                        extend_watched(&mut scripts, &mut keychains, cache, sliced)
+                    });
+                    if scripts.len() == watched || pending.is_empty() {
+                        break;
+                    }
+                }
+            }
+            // The cache is kept consistent with the last processed block, so an interrupted sync
+            // is resumed from the next block
+            cache.last_block = mined;
+        }
+
+        for (keychain, (_, watched)) in keychains {
+            cache.mark_scanned(keychain, 0..watched);
+        }
+
+        if errors.is_empty() {
+            MayError::ok(updated)
+        } else {
+            MayError::err(updated, errors)
+        }
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> { self.source.publish(tx) }
+
+    fn transaction(&self, _txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        // Filters don't allow looking up transactions outside the wallet history
+        Ok(None)
+    }
+
+    fn outpoint_status(&self, _outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        Ok(OutpointStatus::Unknown)
+    }
+
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> {
+        Err(FilterError::Unsupported("fee estimation"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matching() {
+        // Test vector from BIP-158: the filter of the testnet genesis block
+        let block_hash =
+            BlockHash::from_hex("000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943")
+                .unwrap();
+        let script = Vec::<u8>::from_hex(
+            "4104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f\
+             35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac",
+        )
+        .unwrap();
+        let filter = BlockFilter::from_bytes(&[0x01, 0x9d, 0xfc, 0xa8]).unwrap();
+        assert_eq!(BlockFilter::build(block_hash, [script.as_slice()]), filter);
+        assert_eq!(filter.to_bytes(), vec![0x01, 0x9d, 0xfc, 0xa8]);
+        assert!(filter.match_any(block_hash, [[0u8; 22].as_slice(), script.as_slice()]));
+        assert!(!filter.match_any(block_hash, [[0u8; 22].as_slice()]));
+        assert!(!filter.match_any(block_hash, []));
+
+        let scripts = (0u8..50).map(|no| vec![0x00, 0x14, no]).collect::<Vec<_>>();
+        let filter = BlockFilter::build(block_hash, scripts.iter().map(Vec::as_slice));
+        assert_eq!(filter.n, 50);
+        assert!(scripts.iter().all(|script| filter.match_any(block_hash, [script.as_slice()])));
+    }
+
+    #[test]
+    fn test_merkle_mutation() {
+        let block_hash = BlockHash::from([0u8; 32]);
+        let txids = vec![[1u8; 32], [2u8; 32], [3u8; 32]];
+        let root = merkle_root(txids.clone(), block_hash).unwrap();
+        // Duplicating the last transaction produces the same root (CVE-2012-2459)
+        let mut mutated = txids;
+        mutated.push([3u8; 32]);
+        assert!(matches!(merkle_root(mutated, block_hash), Err(FilterError::MutatedBlock(_))));
+        assert_eq!(merkle_root(vec![[1u8; 32]], block_hash).unwrap(), [1u8; 32]);
+        assert_ne!(root, [0u8; 32]);
+    }
+
+    #[test]
+    fn test_pow() {
+        let decode = |hex: &str| {
+            BlockHeader::consensus_decode(&mut Cursor::new(Vec::<u8>::from_hex(hex).unwrap()))
+                .unwrap()
+        };
+        let mainnet = decode(
+            "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b2\
+             7ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c",
+        );
+        let regtest = decode(
+            "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b2\
+             7ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4adae5494dffff7f2002000000",
+        );
+        assert!(check_pow(&mainnet, 0x1d00_ffff));
+        assert!(check_pow(&regtest, 0x207f_ffff));
+        assert!(!check_pow(&regtest, 0x1d00_ffff));
+        assert!(!check_pow(
+            &BlockHeader {
+                nonce: 0,
+                ..mainnet
+            },
+            0x1d00_ffff
+        ));
+
+        let mut expected = [0u8; 32];
+        expected[4..6].copy_from_slice(&[0xff, 0xff]);
+        assert_eq!(target(0x1d00_ffff), Some(expected));
+        assert_eq!(target(0x0100_0000), None);
+        assert_eq!(target(0x0180_0001), None);
+        assert_eq!(target(0x2301_0000), None);
+    }
+}
@@ -20,19 +20,25 @@
 // limitations under the License.
 
 use std::collections::BTreeMap;
-use std::num::NonZeroU32;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::ops::Range;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
-use bpstd::{Address, BlockHash, ConsensusEncode, Outpoint, Sats, Tx, TxIn, Txid, Weight};
+use bpstd::{BlockHash, ConsensusEncode, IdxBase, Outpoint, Sats, Tx, TxIn, Txid, Weight};
 use descriptors::Descriptor;
+use electrum::raw_client::{ElectrumSslStream, RawClient};
 use electrum::{Client, ElectrumApi, GetHistoryRes, Param};
 pub use electrum::{Config, ConfigBuilder, Error, Socks5Config};
 use serde_json::Value;
 
-use super::BATCH_SIZE;
+use super::rebuild::rebuild_cache;
+use super::{FeeSnapshot, OutpointStatus, SyncCursor, TxStore, BATCH_SIZE, FEE_TARGETS};
 use crate::{
-    Indexer, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr,
-    WalletCache, WalletDescr, WalletTx,
+    BlockHeight, FeeRate, Indexer, Layer2, MayError, MiningInfo, Party, Phase, Timings, TxCredit,
+    TxDebit, TxStatus, WalletAddr, WalletCache, WalletDescr, WalletTx,
 };
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
@@ -49,6 +55,8 @@ pub enum ElectrumApiError {
     /// electrum indexer returned invalid previous transaction, which doesn't have an output spent
     /// by transaction {0} input {1:?}.
     PrevOutTxMismatch(Txid, TxIn),
+    /// electrum indexer returned invalid mempool fee histogram.
+    InvalidFeeHistogram,
 }
 
 #[derive(Debug, Display, Error, From)]
@@ -60,7 +68,33 @@ pub enum ElectrumError {
     Client(Error),
 }
 
-impl Indexer for Client {
+/// Electrum clients which can be used as wallet indexers.
+pub trait ElectrumClient: ElectrumApi {}
+
+impl ElectrumClient for Client {}
+
+impl<S: Read + Write> ElectrumClient for RawClient<S> {}
+
+/// Connects to an Electrum server over TLS using a custom TLS configuration, for instance with
+/// a pinned server certificate (see [`crate::indexers::TlsOpts`]).
+///
+/// The URL has form of `[ssl://]host:port`. Unlike [`Client`], the returned client doesn't
+/// reconnect if the connection drops.
+pub fn connect_tls(
+    url: &str,
+    tls_config: Arc<rustls::ClientConfig>,
+) -> Result<RawClient<ElectrumSslStream>, Error> {
+    let addr = url.trim_start_matches("ssl://");
+    let host = addr.rsplit_once(':').map(|(host, _)| host).unwrap_or(addr);
+    let server_name = rustls::pki_types::ServerName::try_from(host.to_owned())
+        .map_err(|_| Error::InvalidDNSNameError(host.to_owned()))?;
+    let stream = TcpStream::connect(addr)?;
+    let session = rustls::ClientConnection::new(tls_config, server_name)
+        .map_err(Error::CouldNotCreateConnection)?;
+    Ok(RawClient::from(rustls::StreamOwned::new(session, stream)))
+}
+
+impl<C: ElectrumClient> Indexer for C {
     type Error = ElectrumError;
 
     fn create<K, D: Descriptor<K>, L2: Layer2>(
@@ -75,28 +109,77 @@ impl Indexer for Client {
         &self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
         cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        self.update_with_store::<K, D, L2>(
+            descriptor,
+            cache,
+            &mut TxStore::default(),
+            &mut Timings::default(),
+        )
+    }
+
+    fn update_with_store<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        store: &mut TxStore,
+        timings: &mut Timings,
     ) -> MayError<usize, Vec<Self::Error>> {
         let mut errors = Vec::<ElectrumError>::new();
 
+        match timings.measure(Phase::Fetch("tip"), || self.block_headers_subscribe()) {
+            Ok(tip) => {
+                cache.last_block = MiningInfo {
+                    height: BlockHeight::try_from(tip.height as u32).unwrap_or(BlockHeight::MIN),
+                    time: tip.header.time as u64,
+                    block_hash: tip.header.block_hash(),
+                }
+            }
+            Err(err) => errors.push(err.into()),
+        }
+
         let mut address_index = BTreeMap::new();
-        for keychain in descriptor.keychains() {
-            let mut empty_count = 0usize;
+        // The whole range of a sliced descriptor is scanned regardless of the gap limit
+        let sliced = descriptor.slice_range().is_some();
+        for keychain in descriptor.watched_keychains() {
+            // An interrupted sync is resumed from the first address which was not processed
+            let cursor = if sliced { None } else { cache.sync_cursors.remove(&keychain) };
+            let mut empty_count = cursor.map(|c| c.empty_count as usize).unwrap_or_default();
+            let mut scanned = None::<Range<u32>>;
             #[cfg(feature = "cli")]
             eprint!(" keychain {keychain} ");
-            for derive in descriptor.addresses(keychain) {
+            let mut addresses = descriptor
+                .watched_addresses_from(keychain, cursor.map(|c| c.next_index).unwrap_or_default());
+            while let Some(derive) = timings.measure(Phase::Derive, || addresses.next()) {
                 let script = derive.addr.script_pubkey();
 
                 #[cfg(feature = "cli")]
                 eprint!(".");
                 let mut txids = Vec::new();
-                let Ok(hres) =
-                    self.script_get_history(&script).map_err(|err| errors.push(err.into()))
+                // Script hash is taken from the cache, so it is not re-computed on each sync
+                let script_hash = cache.script_hash(&derive);
+                let Ok(hres) = timings
+                    .measure(Phase::Fetch("history"), || {
+                        self.raw_call("blockchain.scripthash.get_history", vec![Param::String(
+                            script_hash.to_string(),
+                        )])
+                    })
+                    .and_then(|res| Ok(serde_json::from_value::<Vec<GetHistoryRes>>(res)?))
+                    .map_err(|err| errors.push(err.into()))
                 else {
+                    if !sliced && derive.terminal.index.index() > 0 {
+                        cache.sync_cursors.insert(keychain, SyncCursor {
+                            next_index: derive.terminal.index,
+                            empty_count: empty_count as u32,
+                        });
+                    }
                     break;
                 };
+                let index = derive.terminal.index.index();
+                scanned.get_or_insert(index..index).end = index + 1;
                 if hres.is_empty() {
                     empty_count += 1;
-                    if empty_count >= BATCH_SIZE {
+                    if empty_count >= BATCH_SIZE && !sliced {
                         break;
                     }
                     continue;
@@ -110,10 +193,12 @@ impl Indexer for Client {
                         txids.push(txid);
 
                         // get the tx details (requires electrum verbose support)
-                        let tx_details = self.raw_call("blockchain.transaction.get", vec![
-                            Param::String(hr.tx_hash.to_string()),
-                            Param::Bool(true),
-                        ])?;
+                        let tx_details = timings.measure(Phase::Fetch("transaction"), || {
+                            self.raw_call("blockchain.transaction.get", vec![
+                                Param::String(hr.tx_hash.to_string()),
+                                Param::Bool(true),
+                            ])
+                        })?;
 
                         let tx = tx_details
                             .get("hex")
@@ -134,7 +219,7 @@ impl Indexer for Client {
                                 .get("blocktime")
                                 .and_then(Value::as_u64)
                                 .ok_or(ElectrumApiError::InvalidBlockTime(txid))?;
-                            let height = NonZeroU32::try_from(hr.height as u32)
+                            let height = BlockHeight::try_from(hr.height as u32)
                                 .map_err(|_| ElectrumApiError::InvalidBlockHeight(txid))?;
                             TxStatus::Mined(MiningInfo {
                                 height,
@@ -142,6 +227,7 @@ impl Indexer for Client {
                                 block_hash,
                             })
                         };
+                        store.insert(tx.clone());
                         let tx_size = tx.consensus_serialize().len();
                         let weight = tx.weight_units().to_u32();
 
@@ -150,11 +236,21 @@ impl Indexer for Client {
                         let mut input_total = Sats::ZERO;
                         let mut inputs = Vec::with_capacity(tx.inputs.len());
                         for input in tx.inputs {
-                            // get value from previous output tx
-                            let prev_tx = self.transaction_get(&input.prev_output.txid)?;
-                            let prev_out = prev_tx
-                                .outputs
-                                .get(input.prev_output.vout.into_usize())
+                            // get value from previous output tx, downloading it only if it is
+                            // not known to the store
+                            let prev_txid = input.prev_output.txid;
+                            if !store.contains(&prev_txid) {
+                                let prev_tx = timings.measure(Phase::Fetch("prevout"), || {
+                                    self.transaction_get(&prev_txid)
+                                })?;
+                                store.insert(prev_tx);
+                            }
+                            let prev_out = store
+                                .get(&prev_txid)
+                                .and_then(|prev_tx| {
+                                    prev_tx.outputs.get(input.prev_output.vout.into_usize())
+                                })
+                                .cloned()
                                 .ok_or_else(|| {
                                     ElectrumApiError::PrevOutTxMismatch(txid, input.clone())
                                 })?;
@@ -162,7 +258,7 @@ impl Indexer for Client {
                             input_total += value;
                             inputs.push(TxCredit {
                                 outpoint: input.prev_output,
-                                payer: Party::Unknown(prev_out.script_pubkey.clone()),
+                                payer: Party::Unknown(prev_out.script_pubkey),
                                 sequence: input.sequence,
                                 coinbase: false,
                                 script_sig: input.sig_script,
@@ -211,76 +307,16 @@ impl Indexer for Client {
                 let wallet_addr = WalletAddr::<i64>::from(derive);
                 address_index.insert(script, (wallet_addr, txids));
             }
-        }
-
-        // TODO: Update headers & tip
-
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for debit in &mut tx.outputs {
-                    let Some(s) = debit.beneficiary.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        cache.utxo.insert(debit.outpoint);
-                        debit.beneficiary = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.used = wallet_addr.used.saturating_add(1);
-                        wallet_addr.volume.saturating_add_assign(debit.value);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
-                    } else if debit.beneficiary.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                debit.beneficiary = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
+            if let Some(range) = scanned {
+                cache.mark_scanned(keychain, range);
             }
         }
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for credit in &mut tx.inputs {
-                    let Some(s) = credit.payer.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        credit.payer = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
-                    } else if credit.payer.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                credit.payer = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                    if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
-                        if let Some(txout) =
-                            prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
-                        {
-                            let outpoint = txout.outpoint;
-                            if tx.status.is_mined() {
-                                cache.utxo.remove(&outpoint);
-                            }
-                            txout.spent = Some(credit.outpoint.into())
-                        };
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
-            }
-            cache
-                .addr
-                .entry(wallet_addr.terminal.keychain)
-                .or_default()
-                .insert(wallet_addr.expect_transmute());
-        }
+        // TODO: Update headers
+
+        let rebuild = Instant::now();
+        rebuild_cache(cache, address_index, descriptor.network());
+        timings.record(Phase::Rebuild, rebuild.elapsed());
 
         if errors.is_empty() {
             MayError::ok(0)
@@ -293,4 +329,62 @@ impl Indexer for Client {
         self.transaction_broadcast(tx)?;
         Ok(())
     }
+
+    fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        // Electrum reports unknown transactions as server errors, which can't be distinguished
+        // from other failures
+        Ok(Some(self.transaction_get(&txid)?))
+    }
+
+    fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        let tx = self.transaction_get(&outpoint.txid)?;
+        let Some(out) = tx.outputs.get(outpoint.vout_usize()) else {
+            return Ok(OutpointStatus::Unknown);
+        };
+        let script = &out.script_pubkey;
+        if self
+            .script_list_unspent(script)?
+            .iter()
+            .any(|utxo| utxo.tx_hash == outpoint.txid && utxo.tx_pos == outpoint.vout_usize())
+        {
+            return Ok(OutpointStatus::Unspent);
+        }
+        // Electrum doesn't index spending transactions, so we look for the spender among the
+        // other transactions touching the same script
+        for item in self.script_get_history(script)? {
+            if item.tx_hash == outpoint.txid {
+                continue;
+            }
+            let spender = self.transaction_get(&item.tx_hash)?;
+            if spender.inputs.iter().any(|input| input.prev_output == outpoint) {
+                return Ok(OutpointStatus::Spent(item.tx_hash));
+            }
+        }
+        Ok(OutpointStatus::Unknown)
+    }
+
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> {
+        let mut targets = BTreeMap::new();
+        for target in FEE_TARGETS {
+            // Electrum reports fee rates in BTC per kilobyte, or a negative value if the server
+            // is unable to estimate the fee for the target
+            let btc_per_kvb = self.estimate_fee(target as usize)?;
+            if btc_per_kvb > 0.0 {
+                targets.insert(target, FeeRate::from_sat_per_vb_f64(btc_per_kvb * 100_000.0));
+            }
+        }
+        let histogram = self
+            .raw_call("mempool.get_fee_histogram", vec![])?
+            .as_array()
+            .ok_or(ElectrumApiError::InvalidFeeHistogram)?
+            .iter()
+            .map(|bin| {
+                let rate = bin.get(0).and_then(Value::as_f64)?;
+                let vsize = bin.get(1).and_then(Value::as_u64)?;
+                Some((FeeRate::from_sat_per_vb_f64(rate), vsize))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(ElectrumApiError::InvalidFeeHistogram)?;
+        Ok(FeeSnapshot { targets, histogram })
+    }
 }
@@ -21,20 +21,25 @@
 // limitations under the License.
 
 use std::collections::BTreeMap;
-use std::num::NonZeroU32;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Deref, DerefMut, Range};
+use std::sync::Arc;
+use std::time::Instant;
 
-use bpstd::{Address, DerivedAddr, LockTime, Outpoint, SeqNo, Tx, TxVer, Witness};
+use bpstd::{
+    DerivedAddr, IdxBase, LockTime, NormalIndex, Outpoint, ScriptPubkey, SeqNo, Tx, TxVer, Txid,
+    Witness,
+};
 use descriptors::Descriptor;
 use esplora::BlockingClient;
 pub use esplora::{Builder, Config, Error};
 
 #[cfg(feature = "mempool")]
-use super::mempool::Mempool;
-use super::BATCH_SIZE;
+use super::mempool::{Mempool, MempoolBackend};
+use super::rebuild::rebuild_cache;
+use super::{FeeSnapshot, OutpointStatus, SyncCursor, TxStore, BATCH_SIZE};
 use crate::{
-    Indexer, Layer2, MayError, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr,
-    WalletCache, WalletDescr, WalletTx,
+    BlockHeight, FeeRate, Indexer, Layer2, MayError, MiningInfo, Party, Phase, Timings, TxCredit,
+    TxDebit, TxStatus, WalletAddr, WalletCache, WalletDescr, WalletTx,
 };
 
 /// Represents a client for interacting with the Esplora indexer.
@@ -42,6 +47,9 @@ use crate::{
 pub struct Client {
     pub(crate) inner: BlockingClient,
     pub(crate) kind: ClientKind,
+    /// Whether the backend supports bulk address requests, detected on the first such request.
+    #[cfg(feature = "mempool")]
+    pub(crate) bulk_support: Arc<std::sync::OnceLock<bool>>,
 }
 
 impl Deref for Client {
@@ -79,9 +87,85 @@ impl Client {
         let client = Self {
             inner,
             kind: ClientKind::Esplora,
+            #[cfg(feature = "mempool")]
+            bulk_support: none!(),
         };
         Ok(client)
     }
+
+    /// Creates a new client of the given kind using a custom TLS configuration, for instance
+    /// trusting additional certificate authorities (see [`crate::indexers::TlsOpts`]).
+    pub fn with_tls(url: &str, kind: ClientKind, tls_config: Arc<rustls::ClientConfig>) -> Self {
+        let agent = ureq::AgentBuilder::new().tls_config(tls_config).build();
+        Self {
+            inner: BlockingClient::from_agent(url.to_owned(), agent),
+            kind,
+            #[cfg(feature = "mempool")]
+            bulk_support: none!(),
+        }
+    }
+
+    /// Creates a new client of the given kind connecting to the server through a proxy, like
+    /// `socks5://127.0.0.1:9050` for Tor.
+    #[allow(clippy::result_large_err)]
+    pub fn with_proxy(url: &str, kind: ClientKind, proxy: &str) -> Result<Self, Error> {
+        let inner = esplora::Builder::new(url).proxy(proxy).build_blocking()?;
+        Ok(Self {
+            inner,
+            kind,
+            #[cfg(feature = "mempool")]
+            bulk_support: none!(),
+        })
+    }
+
+    /// Requests retrieving address histories specific to the client kind.
+    pub(crate) fn history_backend(&self) -> Box<dyn HistoryBackend + '_> {
+        match self.kind {
+            ClientKind::Esplora => Box::new(&self.inner),
+            #[cfg(feature = "mempool")]
+            ClientKind::Mempool => Box::new(MempoolBackend {
+                client: &self.inner,
+                bulk_support: &self.bulk_support,
+            }),
+        }
+    }
+}
+
+/// Number of confirmed transactions returned by Esplora backends in a page of an address
+/// history.
+pub(crate) const PAGE_SIZE: usize = 25;
+
+/// Requests retrieving address histories, allowing backend-specific optimizations of the
+/// requests made by the Esplora indexer.
+pub trait HistoryBackend {
+    /// Retrieves a page of the address history. Without `last_seen` the page contains the
+    /// unconfirmed transactions followed by the most recent confirmed ones; otherwise it
+    /// contains the confirmed transactions preceding the `last_seen` one.
+    #[allow(clippy::result_large_err)]
+    fn history_page(
+        &self,
+        derive: &DerivedAddr,
+        last_seen: Option<Txid>,
+    ) -> Result<Vec<esplora::Tx>, Error>;
+
+    /// Retrieves transactions of a batch of addresses with a single request. Returns `None` if
+    /// the backend doesn't support bulk requests, or if the returned history may be incomplete,
+    /// in which case the histories are retrieved address by address.
+    #[allow(clippy::result_large_err)]
+    fn bulk_history(&self, batch: &[DerivedAddr]) -> Result<Option<Vec<esplora::Tx>>, Error> {
+        let _ = batch;
+        Ok(None)
+    }
+}
+
+impl HistoryBackend for &BlockingClient {
+    fn history_page(
+        &self,
+        derive: &DerivedAddr,
+        last_seen: Option<Txid>,
+    ) -> Result<Vec<esplora::Tx>, Error> {
+        self.scripthash_txs(&derive.addr.script_pubkey(), last_seen)
+    }
 }
 
 impl From<esplora::TxStatus> for TxStatus {
@@ -94,7 +178,7 @@ impl From<esplora::TxStatus> for TxStatus {
         } = status
         {
             TxStatus::Mined(MiningInfo {
-                height: NonZeroU32::try_from(height).unwrap_or(NonZeroU32::MIN),
+                height: BlockHeight::try_from(height).unwrap_or(BlockHeight::MIN),
                 time: ts,
                 block_hash: hash,
             })
@@ -148,48 +232,84 @@ impl From<esplora::Tx> for WalletTx {
     }
 }
 
-/// Retrieves all transactions associated with a given script hash.
-///
-/// # Arguments
-///
-/// * `client` - The Esplora client.
-/// * `derive` - The derived address.
+/// Retrieves information about the most recent block of the chain.
+#[allow(clippy::result_large_err)]
+fn get_tip(client: &Client) -> Result<MiningInfo, Error> {
+    // The most recent blocks are returned first
+    let tip = client.blocks(None)?.into_iter().next().ok_or(Error::HttpResponse(404))?;
+    Ok(MiningInfo {
+        height: BlockHeight::try_from(tip.time.height).unwrap_or(BlockHeight::MIN),
+        time: tip.time.timestamp,
+        block_hash: tip.id,
+    })
+}
+
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate")]
+struct MempoolStats {
+    fee_histogram: Vec<(f64, u64)>,
+}
+
+/// Retrieves recommended fee rates and the mempool fee histogram.
 ///
-/// # Errors
+/// Esplora fee estimates are used for the recommended fee rates, unless the client is a
+/// mempool.space one, which uses its own recommendations.
+#[allow(clippy::result_large_err)]
+fn get_fee_snapshot(client: &Client) -> Result<FeeSnapshot, Error> {
+    let targets = match client.kind {
+        ClientKind::Esplora => client
+            .inner
+            .fee_estimates()?
+            .into_iter()
+            .filter_map(|(target, rate)| {
+                Some((target.parse().ok()?, FeeRate::from_sat_per_vb_f64(rate)))
+            })
+            .collect(),
+        #[cfg(feature = "mempool")]
+        ClientKind::Mempool => client.inner.recommended_fees()?,
+    };
+    let stats: MempoolStats =
+        client.agent().get(&format!("{}/mempool", client.url())).call()?.into_json()?;
+    let histogram = stats
+        .fee_histogram
+        .into_iter()
+        .map(|(rate, vsize)| (FeeRate::from_sat_per_vb_f64(rate), vsize))
+        .collect();
+    Ok(FeeSnapshot { targets, histogram })
+}
+
+/// Retrieves all transactions associated with the address, page by page.
 ///
-/// Returns an error if there was a problem retrieving the transactions.
+/// Unconfirmed transactions are returned only with the first page, so the pages are requested
+/// until a page has less than [`PAGE_SIZE`] confirmed transactions.
 #[allow(clippy::result_large_err)]
-fn get_scripthash_txs_all(
-    client: &Client,
+fn get_history_all(
+    backend: &dyn HistoryBackend,
     derive: &DerivedAddr,
 ) -> Result<Vec<esplora::Tx>, Error> {
-    const PAGE_SIZE: usize = 25;
     let mut res = Vec::new();
     let mut last_seen = None;
-    let script = derive.addr.script_pubkey();
-    #[cfg(feature = "mempool")]
-    let address = derive.addr.to_string();
-
     loop {
-        let r = match client.kind {
-            ClientKind::Esplora => client.inner.scripthash_txs(&script, last_seen)?,
-            #[cfg(feature = "mempool")]
-            ClientKind::Mempool => client.inner.address_txs(&address, last_seen)?,
-        };
-        match &r[..] {
-            [a @ .., esplora::Tx { txid, .. }] if a.len() >= PAGE_SIZE - 1 => {
-                last_seen = Some(*txid);
-                res.extend(r);
-            }
-            _ => {
-                res.extend(r);
-                break;
-            }
+        let page = backend.history_page(derive, last_seen)?;
+        let confirmed = page.iter().filter(|tx| tx.status.confirmed).count();
+        last_seen = page.iter().rev().find(|tx| tx.status.confirmed).map(|tx| tx.txid);
+        res.extend(page);
+        if confirmed < PAGE_SIZE || last_seen.is_none() {
+            break;
         }
     }
     Ok(res)
 }
 
+/// Checks whether the transaction spends from or pays to the script.
+fn touches_script(tx: &esplora::Tx, script: &ScriptPubkey) -> bool {
+    tx.vout.iter().any(|vout| &vout.scriptpubkey == script)
+        || tx
+            .vin
+            .iter()
+            .any(|vin| vin.prevout.as_ref().is_some_and(|prevout| &prevout.scriptpubkey == script))
+}
+
 impl Indexer for Client {
     type Error = Error;
 
@@ -205,114 +325,127 @@ impl Indexer for Client {
         &self,
         descriptor: &WalletDescr<K, D, L2::Descr>,
         cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        self.update_with_store::<K, D, L2>(
+            descriptor,
+            cache,
+            &mut TxStore::default(),
+            &mut Timings::default(),
+        )
+    }
+
+    /// Esplora returns transactions together with the values of the spent outputs, so the
+    /// transaction store is not used.
+    fn update_with_store<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        _store: &mut TxStore,
+        timings: &mut Timings,
     ) -> MayError<usize, Vec<Self::Error>> {
         let mut errors = vec![];
 
+        match timings.measure(Phase::Fetch("tip"), || get_tip(self)) {
+            Ok(tip) => cache.last_block = tip,
+            Err(err) => errors.push(err),
+        }
+
+        let backend = self.history_backend();
         let mut address_index = BTreeMap::new();
-        for keychain in descriptor.keychains() {
-            let mut empty_count = 0usize;
+        // The whole range of a sliced descriptor is scanned regardless of the gap limit
+        let sliced = descriptor.slice_range().is_some();
+        for keychain in descriptor.watched_keychains() {
+            // An interrupted sync is resumed from the first address which was not processed
+            let cursor = if sliced { None } else { cache.sync_cursors.remove(&keychain) };
+            let mut empty_count = cursor.map(|c| c.empty_count as usize).unwrap_or_default();
+            let mut scanned = None::<Range<u32>>;
             #[cfg(feature = "cli")]
             eprint!(" keychain {keychain} ");
-            for derive in descriptor.addresses(keychain) {
-                let script = derive.addr.script_pubkey();
-
-                #[cfg(feature = "cli")]
-                eprint!(".");
-                let mut txids = Vec::new();
-                match get_scripthash_txs_all(self, &derive) {
+            let mut addresses = descriptor
+                .watched_addresses_from(keychain, cursor.map(|c| c.next_index).unwrap_or_default());
+            let interrupt = |cache: &mut WalletCache<_>, index: NormalIndex, empty: usize| {
+                if !sliced && index.index() > 0 {
+                    cache.sync_cursors.insert(keychain, SyncCursor {
+                        next_index: index,
+                        empty_count: empty as u32,
+                    });
+                }
+            };
+            'scan: loop {
+                let batch = timings.measure(Phase::Derive, || {
+                    addresses.by_ref().take(BATCH_SIZE).collect::<Vec<_>>()
+                });
+                if batch.is_empty() {
+                    break;
+                }
+                // Backends supporting bulk requests return histories of the whole batch at once,
+                // which saves requests for the unused addresses past the last used one
+                let bulk = match timings
+                    .measure(Phase::Fetch("history"), || backend.bulk_history(&batch))
+                {
+                    Ok(bulk) => bulk,
                     Err(err) => {
+                        interrupt(cache, batch[0].terminal.index, empty_count);
                         errors.push(err);
                         break;
                     }
-                    Ok(txes) if txes.is_empty() => {
-                        empty_count += 1;
-                        if empty_count >= BATCH_SIZE {
-                            break;
-                        }
-                    }
-                    Ok(txes) => {
-                        empty_count = 0;
-                        txids = txes.iter().map(|tx| tx.txid).collect();
-                        cache
-                            .tx
-                            .extend(txes.into_iter().map(WalletTx::from).map(|tx| (tx.txid, tx)));
-                    }
-                }
-
-                let wallet_addr = WalletAddr::<i64>::from(derive);
-                address_index.insert(script, (wallet_addr, txids));
-            }
-        }
-
-        // TODO: Update headers & tip
-
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for debit in &mut tx.outputs {
-                    let Some(s) = debit.beneficiary.script_pubkey() else {
-                        continue;
-                    };
-                    if &s == script {
-                        cache.utxo.insert(debit.outpoint);
-                        debit.beneficiary = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.used = wallet_addr.used.saturating_add(1);
-                        wallet_addr.volume.saturating_add_assign(debit.value);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
-                    } else if debit.beneficiary.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                debit.beneficiary = Party::Counterparty(addr);
-                            })
-                            .ok();
-                    }
-                }
-                cache.tx.insert(tx.txid, tx);
-            }
-        }
+                };
+                for derive in batch {
+                    let script = derive.addr.script_pubkey();
 
-        for (script, (wallet_addr, txids)) in &mut address_index {
-            for txid in txids {
-                let mut tx = cache.tx.remove(txid).expect("broken logic");
-                for credit in &mut tx.inputs {
-                    let Some(s) = credit.payer.script_pubkey() else {
-                        continue;
+                    #[cfg(feature = "cli")]
+                    eprint!(".");
+                    let mut txids = Vec::new();
+                    let res = match &bulk {
+                        Some(txes) => Ok(txes
+                            .iter()
+                            .filter(|tx| touches_script(tx, &script))
+                            .cloned()
+                            .collect::<Vec<_>>()),
+                        None => timings.measure(Phase::Fetch("history"), || {
+                            get_history_all(backend.as_ref(), &derive)
+                        }),
                     };
-                    if &s == script {
-                        credit.payer = Party::from_wallet_addr(wallet_addr);
-                        wallet_addr.balance = wallet_addr
-                            .balance
-                            .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
-                    } else if credit.payer.is_unknown() {
-                        Address::with(&s, descriptor.network())
-                            .map(|addr| {
-                                credit.payer = Party::Counterparty(addr);
-                            })
-                            .ok();
+                    if res.is_ok() {
+                        let index = derive.terminal.index.index();
+                        scanned.get_or_insert(index..index).end = index + 1;
                     }
-                    if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
-                        if let Some(txout) =
-                            prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
-                        {
-                            let outpoint = txout.outpoint;
-                            if tx.status.is_mined() {
-                                cache.utxo.remove(&outpoint);
+                    match res {
+                        Err(err) => {
+                            interrupt(cache, derive.terminal.index, empty_count);
+                            errors.push(err);
+                            break 'scan;
+                        }
+                        Ok(txes) if txes.is_empty() => {
+                            empty_count += 1;
+                            if empty_count >= BATCH_SIZE && !sliced {
+                                break 'scan;
                             }
-                            txout.spent = Some(credit.outpoint.into())
-                        };
+                        }
+                        Ok(txes) => {
+                            empty_count = 0;
+                            txids = txes.iter().map(|tx| tx.txid).collect();
+                            cache.tx.extend(
+                                txes.into_iter().map(WalletTx::from).map(|tx| (tx.txid, tx)),
+                            );
+                        }
                     }
+
+                    let wallet_addr = WalletAddr::<i64>::from(derive);
+                    address_index.insert(script, (wallet_addr, txids));
                 }
-                cache.tx.insert(tx.txid, tx);
             }
-            cache
-                .addr
-                .entry(wallet_addr.terminal.keychain)
-                .or_default()
-                .insert(wallet_addr.expect_transmute());
+            if let Some(range) = scanned {
+                cache.mark_scanned(keychain, range);
+            }
         }
 
+        // TODO: Update headers
+
+        let rebuild = Instant::now();
+        rebuild_cache(cache, address_index, descriptor.network());
+        timings.record(Phase::Rebuild, rebuild.elapsed());
+
         if errors.is_empty() {
             MayError::ok(0)
         } else {
@@ -321,4 +454,76 @@ impl Indexer for Client {
     }
 
     fn publish(&self, tx: &Tx) -> Result<(), Self::Error> { self.inner.broadcast(tx) }
+
+    fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> { self.inner.tx(&txid) }
+
+    fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        let status = self.inner.output_status(&outpoint.txid, outpoint.vout_u32() as u64)?;
+        Ok(match status {
+            Some(esplora::OutputStatus {
+                spent: true,
+                txid: Some(txid),
+                ..
+            }) => OutpointStatus::Spent(txid),
+            Some(esplora::OutputStatus { spent: false, .. }) => OutpointStatus::Unspent,
+            _ => OutpointStatus::Unknown,
+        })
+    }
+
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> { get_fee_snapshot(self) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::str::FromStr;
+
+    use super::*;
+
+    struct Pages(RefCell<Vec<Option<Txid>>>);
+
+    impl HistoryBackend for Pages {
+        fn history_page(
+            &self,
+            _derive: &DerivedAddr,
+            last_seen: Option<Txid>,
+        ) -> Result<Vec<esplora::Tx>, Error> {
+            self.0.borrow_mut().push(last_seen);
+            let tx = |id: u8, confirmed: bool| esplora::Tx {
+                txid: Txid::from([id; 32]),
+                version: 2,
+                locktime: 0,
+                vin: vec![],
+                vout: vec![],
+                status: esplora::TxStatus {
+                    confirmed,
+                    block_height: None,
+                    block_hash: None,
+                    block_time: None,
+                },
+                fee: 0,
+                size: 0,
+                weight: 0,
+            };
+            // Two unconfirmed transactions go first, followed by 30 confirmed ones
+            Ok(match last_seen {
+                None => (0..2)
+                    .map(|id| tx(id, false))
+                    .chain((2..2 + PAGE_SIZE as u8).map(|id| tx(id, true)))
+                    .collect(),
+                Some(_) => (2 + PAGE_SIZE as u8..32).map(|id| tx(id, true)).collect(),
+            })
+        }
+    }
+
+    #[test]
+    fn test_history_pagination() {
+        let derive =
+            DerivedAddr::from_str("tb1qvyhk5mlhphul2r6kdknrxqqhl37d0z7ycf2uu9&0/1").unwrap();
+        let backend = Pages(none!());
+        let txs = get_history_all(&backend, &derive).unwrap();
+        assert_eq!(txs.len(), 32);
+        assert_eq!(*backend.0.borrow(), vec![None, Some(Txid::from([1 + PAGE_SIZE as u8; 32]))]);
+        assert!(!touches_script(&txs[0], &derive.addr.script_pubkey()));
+    }
 }
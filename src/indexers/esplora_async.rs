@@ -0,0 +1,220 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Non-blocking Esplora client implementing [`AsyncIndexer`].
+//!
+//! Electrum client library provides only blocking API, so there is no non-blocking Electrum
+//! indexer.
+
+use std::collections::BTreeMap;
+use std::ops::{Deref, Range};
+
+use bpstd::{DerivedAddr, IdxBase, Outpoint, Tx, Txid};
+use descriptors::Descriptor;
+use esplora::AsyncClient;
+pub use esplora::Error;
+
+use super::esplora::PAGE_SIZE;
+use super::rebuild::rebuild_cache;
+use super::{AsyncIndexer, FeeSnapshot, OutpointStatus, SyncCursor, BATCH_SIZE};
+use crate::{
+    BlockHeight, FeeRate, Layer2, MayError, MiningInfo, WalletAddr, WalletCache, WalletDescr,
+    WalletTx,
+};
+
+/// Non-blocking client of an Esplora indexer. Unlike [`super::esplora::Client`], it doesn't
+/// support mempool.space-specific requests and doesn't report the mempool fee histogram.
+#[derive(Debug, Clone)]
+pub struct Client {
+    inner: AsyncClient,
+}
+
+impl Deref for Client {
+    type Target = AsyncClient;
+
+    fn deref(&self) -> &Self::Target { &self.inner }
+}
+
+impl Client {
+    /// Creates a new non-blocking Esplora client with the specified URL.
+    #[allow(clippy::result_large_err)]
+    pub fn new_esplora(url: &str) -> Result<Self, Error> {
+        let inner = esplora::Builder::new(url).build_async()?;
+        Ok(Self { inner })
+    }
+
+    /// Creates a new non-blocking Esplora client connecting to the server through a proxy, like
+    /// `socks5://127.0.0.1:9050` for Tor.
+    #[allow(clippy::result_large_err)]
+    pub fn with_proxy(url: &str, proxy: &str) -> Result<Self, Error> {
+        let inner = esplora::Builder::new(url).proxy(proxy).build_async()?;
+        Ok(Self { inner })
+    }
+}
+
+/// Retrieves information about the most recent block of the chain.
+#[allow(clippy::result_large_err)]
+async fn get_tip(client: &AsyncClient) -> Result<MiningInfo, Error> {
+    // The most recent blocks are returned first
+    let tip = client.blocks(None).await?.into_iter().next().ok_or(Error::HttpResponse(404))?;
+    Ok(MiningInfo {
+        height: BlockHeight::try_from(tip.time.height).unwrap_or(BlockHeight::MIN),
+        time: tip.time.timestamp,
+        block_hash: tip.id,
+    })
+}
+
+/// Retrieves all transactions associated with the address, page by page, like the blocking
+/// client does.
+#[allow(clippy::result_large_err)]
+async fn get_history_all(
+    client: &AsyncClient,
+    derive: &DerivedAddr,
+) -> Result<Vec<esplora::Tx>, Error> {
+    let script = derive.addr.script_pubkey();
+    let mut res = Vec::new();
+    let mut last_seen = None;
+    loop {
+        let page = client.scripthash_txs(&script, last_seen).await?;
+        let confirmed = page.iter().filter(|tx| tx.status.confirmed).count();
+        last_seen = page.iter().rev().find(|tx| tx.status.confirmed).map(|tx| tx.txid);
+        res.extend(page);
+        if confirmed < PAGE_SIZE || last_seen.is_none() {
+            break;
+        }
+    }
+    Ok(res)
+}
+
+impl AsyncIndexer for Client {
+    type Error = Error;
+
+    async fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+        let mut cache = WalletCache::new_nonsync();
+        self.update::<K, D, L2>(descriptor, &mut cache).await.map(|_| cache)
+    }
+
+    async fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let mut errors = vec![];
+
+        match get_tip(&self.inner).await {
+            Ok(tip) => cache.last_block = tip,
+            Err(err) => errors.push(err),
+        }
+
+        let mut address_index = BTreeMap::new();
+        // The whole range of a sliced descriptor is scanned regardless of the gap limit
+        let sliced = descriptor.slice_range().is_some();
+        for keychain in descriptor.watched_keychains() {
+            // An interrupted sync is resumed from the first address which was not processed
+            let cursor = if sliced { None } else { cache.sync_cursors.remove(&keychain) };
+            let mut empty_count = cursor.map(|c| c.empty_count as usize).unwrap_or_default();
+            let mut scanned = None::<Range<u32>>;
+            let addresses = descriptor
+                .watched_addresses_from(keychain, cursor.map(|c| c.next_index).unwrap_or_default());
+            for derive in addresses {
+                let txes = match get_history_all(&self.inner, &derive).await {
+                    Ok(txes) => txes,
+                    Err(err) => {
+                        if !sliced && derive.terminal.index.index() > 0 {
+                            cache.sync_cursors.insert(keychain, SyncCursor {
+                                next_index: derive.terminal.index,
+                                empty_count: empty_count as u32,
+                            });
+                        }
+                        errors.push(err);
+                        break;
+                    }
+                };
+                let index = derive.terminal.index.index();
+                scanned.get_or_insert(index..index).end = index + 1;
+
+                let mut txids = Vec::new();
+                if txes.is_empty() {
+                    empty_count += 1;
+                    if empty_count >= BATCH_SIZE && !sliced {
+                        break;
+                    }
+                } else {
+                    empty_count = 0;
+                    txids = txes.iter().map(|tx| tx.txid).collect();
+                    cache.tx.extend(txes.into_iter().map(WalletTx::from).map(|tx| (tx.txid, tx)));
+                }
+                let script = derive.addr.script_pubkey();
+                address_index.insert(script, (WalletAddr::<i64>::from(derive), txids));
+            }
+            if let Some(range) = scanned {
+                cache.mark_scanned(keychain, range);
+            }
+        }
+
+        rebuild_cache(cache, address_index, descriptor.network());
+
+        if errors.is_empty() {
+            MayError::ok(0)
+        } else {
+            MayError::err(0, errors)
+        }
+    }
+
+    async fn publish(&self, tx: &Tx) -> Result<(), Self::Error> { self.inner.broadcast(tx).await }
+
+    async fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        self.inner.tx(&txid).await
+    }
+
+    async fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        let status = self.inner.output_status(&outpoint.txid, outpoint.vout_u32() as u64).await?;
+        Ok(match status {
+            Some(esplora::OutputStatus {
+                spent: true,
+                txid: Some(txid),
+                ..
+            }) => OutpointStatus::Spent(txid),
+            Some(esplora::OutputStatus { spent: false, .. }) => OutpointStatus::Unspent,
+            _ => OutpointStatus::Unknown,
+        })
+    }
+
+    async fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> {
+        let targets = self
+            .inner
+            .fee_estimates()
+            .await?
+            .into_iter()
+            .filter_map(|(target, rate)| {
+                Some((target.parse().ok()?, FeeRate::from_sat_per_vb_f64(rate)))
+            })
+            .collect();
+        Ok(FeeSnapshot {
+            targets,
+            histogram: none!(),
+        })
+    }
+}
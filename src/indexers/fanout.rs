@@ -0,0 +1,180 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::thread;
+
+use bpstd::{Outpoint, Tx, Txid};
+use descriptors::Descriptor;
+
+use super::{FeeSnapshot, OutpointStatus, TxStore};
+use crate::{Indexer, Layer2, MayError, Timings, WalletCache, WalletDescr};
+
+/// Result of publishing a transaction to one of the [`BroadcastFanout`] endpoints.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct BroadcastResult<E> {
+    /// Name of the endpoint, as given when it was added to the fanout.
+    pub endpoint: String,
+    pub result: Result<(), E>,
+}
+
+/// Indexer publishing transactions to several endpoints at once, such that a transaction
+/// propagates even if some of the servers are down or refuse to relay it.
+///
+/// The first endpoint is the primary one, which serves all other requests; the rest are used
+/// only for publishing and may include broadcast-only services, like the mempool.space
+/// transaction accelerator API.
+#[derive(Clone, Debug)]
+pub struct BroadcastFanout<I: Indexer> {
+    /// Named endpoints; never empty.
+    endpoints: Vec<(String, I)>,
+}
+
+impl<I: Indexer> BroadcastFanout<I> {
+    /// Constructs fanout with the primary endpoint.
+    pub fn new(name: impl ToString, primary: I) -> Self {
+        BroadcastFanout {
+            endpoints: vec![(name.to_string(), primary)],
+        }
+    }
+
+    /// Adds an endpoint which the transactions are published to.
+    pub fn push(&mut self, name: impl ToString, indexer: I) {
+        self.endpoints.push((name.to_string(), indexer));
+    }
+
+    /// Adds an endpoint which the transactions are published to.
+    pub fn with(mut self, name: impl ToString, indexer: I) -> Self {
+        self.push(name, indexer);
+        self
+    }
+
+    /// Indexer serving all requests other than publishing transactions.
+    pub fn primary(&self) -> &I { &self.endpoints[0].1 }
+
+    /// Names of the endpoints, starting with the primary one.
+    pub fn endpoints(&self) -> impl Iterator<Item = &str> {
+        self.endpoints.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Publishes the transaction to all endpoints in parallel, reporting the result for each of
+    /// them in the order the endpoints were added.
+    pub fn broadcast(&self, tx: &Tx) -> Vec<BroadcastResult<I::Error>>
+    where
+        I: Sync,
+        I::Error: Send,
+    {
+        thread::scope(|scope| {
+            let handles = self
+                .endpoints
+                .iter()
+                .map(|(name, indexer)| (name, scope.spawn(move || indexer.publish(tx))))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|(name, handle)| BroadcastResult {
+                    endpoint: name.clone(),
+                    result: handle.join().expect("indexer panicked during publishing"),
+                })
+                .collect()
+        })
+    }
+}
+
+impl<I: Indexer + Sync> Indexer for BroadcastFanout<I>
+where I::Error: Send
+{
+    type Error = I::Error;
+
+    fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+        self.primary().create::<K, D, L2>(descr)
+    }
+
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        self.primary().update::<K, D, L2>(descr, cache)
+    }
+
+    fn update_with_store<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        store: &mut TxStore,
+        timings: &mut Timings,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        self.primary().update_with_store::<K, D, L2>(descr, cache, store, timings)
+    }
+
+    /// Publishes the transaction to all endpoints, succeeding if at least one of them has
+    /// accepted it; otherwise the error of the primary endpoint is returned. Use
+    /// [`BroadcastFanout::broadcast`] to get the results for each endpoint.
+    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
+        let mut results = self.broadcast(tx).into_iter().map(|res| res.result);
+        let first = results.next().expect("fanout has at least one endpoint");
+        if first.is_ok() || results.any(|res| res.is_ok()) {
+            return Ok(());
+        }
+        first
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        self.primary().transaction(txid)
+    }
+
+    fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        self.primary().outpoint_status(outpoint)
+    }
+
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> { self.primary().fee_snapshot() }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{Sats, ScriptPubkey};
+
+    use super::*;
+    use crate::{SimIndexer, Simulator};
+
+    #[test]
+    fn publish_to_all() {
+        let mut sim = Simulator::new();
+        let first = sim.fund(ScriptPubkey::new(), Sats(10_000));
+        let second = sim.fund(ScriptPubkey::new(), Sats(20_000));
+
+        let fanout = BroadcastFanout::new("primary", SimIndexer::default())
+            .with("secondary", SimIndexer::default());
+        assert_eq!(fanout.endpoints().collect::<Vec<_>>(), ["primary", "secondary"]);
+        let results = fanout.broadcast(&first);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|res| res.result.is_ok()));
+        fanout.publish(&second).unwrap();
+
+        for (_, indexer) in &fanout.endpoints {
+            assert_eq!(indexer.chain().mempool(), &[first.clone(), second.clone()]);
+        }
+    }
+}
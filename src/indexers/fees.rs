@@ -0,0 +1,118 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use crate::FeeRate;
+
+/// Maximal virtual size of a block.
+pub const BLOCK_MAX_VSIZE: u64 = 1_000_000;
+
+/// Confirmation targets, in blocks, for which indexers provide recommended fee rates.
+pub const FEE_TARGETS: [u16; 4] = [1, 3, 6, 144];
+
+/// Current fee market state reported by an indexer.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct FeeSnapshot {
+    /// Recommended fee rates keyed by the confirmation target in blocks.
+    pub targets: BTreeMap<u16, FeeRate>,
+
+    /// Mempool fee histogram: pairs of a fee rate and the total virtual size of the mempool
+    /// transactions paying at least this rate (but less than the rate of the previous pair),
+    /// ordered by a decreasing fee rate. Empty if the indexer doesn't provide the histogram.
+    pub histogram: Vec<(FeeRate, u64)>,
+}
+
+impl FeeSnapshot {
+    /// Total virtual size of the transactions in the mempool, as given by the histogram.
+    pub fn mempool_vsize(&self) -> u64 { self.histogram.iter().map(|(_, vsize)| vsize).sum() }
+
+    /// Number of blocks required to clear the mempool, as given by the histogram.
+    pub fn mempool_blocks(&self) -> u64 { self.mempool_vsize().div_ceil(BLOCK_MAX_VSIZE) }
+
+    /// Recommended fee rate for a transaction to be confirmed within the given number of blocks,
+    /// taken from the largest known target not exceeding it. Targets below the smallest known
+    /// one use its rate. Returns `None` if the indexer provides no recommendations.
+    pub fn target_rate(&self, blocks: u16) -> Option<FeeRate> {
+        self.targets
+            .range(..=blocks)
+            .next_back()
+            .or_else(|| self.targets.first_key_value())
+            .map(|(_, rate)| *rate)
+    }
+
+    /// Minimal fee rate required for a transaction to be included into one of the next `blocks`
+    /// blocks, estimated from the histogram. Returns `None` if the mempool is expected to be
+    /// cleared by then.
+    pub fn histogram_rate(&self, blocks: u16) -> Option<FeeRate> {
+        let limit = blocks as u64 * BLOCK_MAX_VSIZE;
+        let mut depth = 0u64;
+        for (rate, vsize) in &self.histogram {
+            depth += vsize;
+            if depth >= limit {
+                return Some(*rate);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_congestion() {
+        let snapshot = FeeSnapshot {
+            targets: none!(),
+            histogram: vec![
+                (FeeRate::from_sat_per_vb(20), 600_000),
+                (FeeRate::from_sat_per_vb(10), 900_000),
+                (FeeRate::from_sat_per_vb(2), 200_000),
+            ],
+        };
+        assert_eq!(snapshot.mempool_vsize(), 1_700_000);
+        assert_eq!(snapshot.mempool_blocks(), 2);
+        assert_eq!(snapshot.histogram_rate(1), Some(FeeRate::from_sat_per_vb(10)));
+        assert_eq!(snapshot.histogram_rate(2), None);
+    }
+
+    #[test]
+    fn test_target_rate() {
+        let mut snapshot = FeeSnapshot::default();
+        assert_eq!(snapshot.target_rate(6), None);
+        snapshot.targets = bmap! {
+            2 => FeeRate::from_sat_per_vb(20),
+            6 => FeeRate::from_sat_per_vb(10),
+            144 => FeeRate::from_sat_per_vb(2),
+        };
+        assert_eq!(snapshot.target_rate(1), Some(FeeRate::from_sat_per_vb(20)));
+        assert_eq!(snapshot.target_rate(6), Some(FeeRate::from_sat_per_vb(10)));
+        assert_eq!(snapshot.target_rate(10), Some(FeeRate::from_sat_per_vb(10)));
+        assert_eq!(snapshot.target_rate(1008), Some(FeeRate::from_sat_per_vb(2)));
+    }
+}
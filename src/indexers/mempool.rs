@@ -20,9 +20,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use bpstd::Txid;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use bpstd::{DerivedAddr, Txid};
 use esplora::BlockingClient;
 
+use super::esplora::{HistoryBackend, PAGE_SIZE};
+use crate::FeeRate;
+
 impl super::esplora::Client {
     /// Creates a new mempool client with the specified URL.
     ///
@@ -40,6 +46,7 @@ impl super::esplora::Client {
         let client = Self {
             inner,
             kind: super::esplora::ClientKind::Mempool,
+            bulk_support: none!(),
         };
         Ok(client)
     }
@@ -52,6 +59,25 @@ pub trait Mempool {
         address: &str,
         last_seen: Option<Txid>,
     ) -> Result<Vec<esplora::Tx>, esplora::Error>;
+
+    /// Retrieves the transactions associated with any of the addresses with a single request,
+    /// using the bulk endpoint of mempool.space.
+    #[allow(clippy::result_large_err)]
+    fn addresses_txs(&self, addresses: &[String]) -> Result<Vec<esplora::Tx>, esplora::Error>;
+
+    /// Retrieves fee rates recommended by mempool.space, keyed by the confirmation target in
+    /// blocks.
+    #[allow(clippy::result_large_err)]
+    fn recommended_fees(&self) -> Result<BTreeMap<u16, FeeRate>, esplora::Error>;
+}
+
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+struct RecommendedFees {
+    fastest_fee: f64,
+    half_hour_fee: f64,
+    hour_fee: f64,
+    economy_fee: f64,
 }
 
 impl Mempool for BlockingClient {
@@ -82,4 +108,60 @@ impl Mempool for BlockingClient {
         let resp = agent.get(&url).call()?.into_json()?;
         Ok(resp)
     }
+
+    fn addresses_txs(&self, addresses: &[String]) -> Result<Vec<esplora::Tx>, esplora::Error> {
+        let url = format!("{}/addresses/txs", self.url());
+        let resp = self.agent().post(&url).send_json(addresses)?.into_json()?;
+        Ok(resp)
+    }
+
+    fn recommended_fees(&self) -> Result<BTreeMap<u16, FeeRate>, esplora::Error> {
+        let url = format!("{}/v1/fees/recommended", self.url());
+        let fees: RecommendedFees = self.agent().get(&url).call()?.into_json()?;
+        Ok(bmap! {
+            1 => FeeRate::from_sat_per_vb_f64(fees.fastest_fee),
+            3 => FeeRate::from_sat_per_vb_f64(fees.half_hour_fee),
+            6 => FeeRate::from_sat_per_vb_f64(fees.hour_fee),
+            144 => FeeRate::from_sat_per_vb_f64(fees.economy_fee),
+        })
+    }
+}
+
+/// History requests of mempool.space, which uses the bulk address endpoint if the server
+/// supports it, falling back to the address-by-address requests otherwise.
+pub(crate) struct MempoolBackend<'client> {
+    pub(crate) client: &'client BlockingClient,
+    pub(crate) bulk_support: &'client OnceLock<bool>,
+}
+
+impl HistoryBackend for MempoolBackend<'_> {
+    fn history_page(
+        &self,
+        derive: &DerivedAddr,
+        last_seen: Option<Txid>,
+    ) -> Result<Vec<esplora::Tx>, esplora::Error> {
+        self.client.address_txs(&derive.addr.to_string(), last_seen)
+    }
+
+    fn bulk_history(
+        &self,
+        batch: &[DerivedAddr],
+    ) -> Result<Option<Vec<esplora::Tx>>, esplora::Error> {
+        if self.bulk_support.get() == Some(&false) {
+            return Ok(None);
+        }
+        let addresses = batch.iter().map(|derive| derive.addr.to_string()).collect::<Vec<_>>();
+        let txs = match self.client.addresses_txs(&addresses) {
+            Ok(txs) => txs,
+            // Servers without the bulk endpoint reject the request
+            Err(esplora::Error::Ureq(ureq::Error::Status(400 | 404 | 405 | 501, _))) => {
+                let _ = self.bulk_support.set(false);
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+        let _ = self.bulk_support.set(true);
+        // A full page may be truncated, so histories are requested address by address then
+        Ok(Some(txs).filter(|txs| txs.len() < PAGE_SIZE))
+    }
 }
@@ -20,24 +20,129 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+#[cfg(feature = "bip158")]
+pub mod bip158;
 #[cfg(feature = "electrum")]
 pub mod electrum;
 #[cfg(feature = "esplora")]
 pub mod esplora;
+#[cfg(feature = "async")]
+pub mod esplora_async;
 #[cfg(feature = "mempool")]
 pub mod mempool;
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
 mod any;
+#[cfg(any(feature = "electrum", feature = "esplora"))]
+mod rebuild;
+#[cfg(feature = "tls")]
+mod tls;
+mod store;
+mod fees;
+mod fanout;
+
+use std::collections::BTreeMap;
 
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
 pub use any::{AnyIndexer, AnyIndexerError};
-use bpstd::Tx;
+use bpstd::{Keychain, NormalIndex, Outpoint, Tx, Txid};
 use descriptors::Descriptor;
+pub use fanout::{BroadcastFanout, BroadcastResult};
+pub use fees::{FeeSnapshot, BLOCK_MAX_VSIZE, FEE_TARGETS};
+pub use store::TxStore;
+#[cfg(feature = "tls")]
+pub use tls::{CertFingerprint, InvalidFingerprint, TlsError, TlsOpts};
+
+use crate::{FeeRate, Layer2, MayError, Phase, Timings, WalletCache, WalletDescr};
 
-use crate::{Layer2, MayError, WalletCache, WalletDescr};
+/// Number of consecutive unused addresses after which indexers stop scanning a keychain.
+pub const GAP_LIMIT: u32 = 10;
 
 #[cfg(any(feature = "electrum", feature = "esplora"))]
-const BATCH_SIZE: usize = 10;
+const BATCH_SIZE: usize = GAP_LIMIT as usize;
+
+/// Results of a wallet synchronization with an indexer.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct SyncReport {
+    /// Number of updated items reported by the indexer.
+    pub updated: usize,
+    /// Time spent in the synchronization phases.
+    pub timings: Timings,
+    /// Error of the layer 2 sync hook (see [`crate::Layer2Sync`]), if it has failed.
+    pub layer2_error: Option<String>,
+    /// Keychains which scan was resumed from an interrupted sync, with the derivation index the
+    /// scan was resumed from.
+    pub resumed: BTreeMap<Keychain, NormalIndex>,
+}
+
+/// Position in a keychain at which a sync was interrupted, for instance by a network failure,
+/// allowing the next sync to resume from it instead of rescanning the keychain from the start.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SyncCursor {
+    /// Derivation index of the first address which was not processed.
+    pub next_index: NormalIndex,
+    /// Number of consecutive addresses without transactions preceding `next_index`, such that
+    /// the gap limit is applied after resuming the same way as in an uninterrupted sync.
+    pub empty_count: u32,
+}
+
+/// Status of a transaction output as seen by an indexer.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+pub enum OutpointStatus {
+    /// The output exists and is not spent.
+    #[display("unspent")]
+    Unspent,
+    /// The output is spent by the transaction with the given id.
+    #[display("spent by {0}")]
+    Spent(Txid),
+    /// The indexer doesn't know the output, or can't tell whether it is spent.
+    #[display("unknown")]
+    Unknown,
+}
+
+impl OutpointStatus {
+    pub fn is_unspent(self) -> bool { self == OutpointStatus::Unspent }
+}
+
+/// Kind of the indexer server protocol.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[display(lowercase)]
+pub enum IndexerKind {
+    /// Electrum server.
+    Electrum,
+    /// Esplora server.
+    Esplora,
+    /// Mempool.space server.
+    Mempool,
+}
+
+/// Indexer server used by a wallet when no indexer is given explicitly, allowing different
+/// wallets to use different backends.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[display("{kind} server {url}")]
+pub struct IndexerConfig {
+    pub kind: IndexerKind,
+    /// Server URL, which may contain `{network}` placeholder for Esplora and Mempool servers.
+    pub url: String,
+    /// Proxy server the indexer is accessed through, like `socks5://127.0.0.1:9050` for Tor.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub proxy: Option<String>,
+}
 
 pub trait Indexer {
     type Error;
@@ -53,5 +158,104 @@ pub trait Indexer {
         cache: &mut WalletCache<L2::Cache>,
     ) -> MayError<usize, Vec<Self::Error>>;
 
+    /// Updates wallet cache using (and filling in) the transaction store to avoid re-downloading
+    /// transactions known from the previous syncs.
+    ///
+    /// Indexers which do not download raw transactions ignore the store. Time spent in the
+    /// update phases is added to `timings`; indexers which do not instrument their updates
+    /// record the whole update as a single fetch.
+    fn update_with_store<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+        store: &mut TxStore,
+        timings: &mut Timings,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let _ = store;
+        timings.measure(Phase::Fetch("update"), || self.update::<K, D, L2>(descr, cache))
+    }
+
     fn publish(&self, tx: &Tx) -> Result<(), Self::Error>;
+
+    /// Retrieves the raw transaction, returning `None` if it is not known to the indexer.
+    fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error>;
+
+    /// Checks whether the transaction output is known to the indexer and whether it is spent.
+    fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error>;
+
+    /// Retrieves recommended fee rates and, when available, the mempool fee histogram.
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error>;
+
+    /// Retrieves recommended fee rates keyed by the confirmation target in blocks.
+    fn fee_estimates(&self) -> Result<BTreeMap<u16, FeeRate>, Self::Error> {
+        self.fee_snapshot().map(|snapshot| snapshot.targets)
+    }
+}
+
+/// Extends the watched scripts up to the gap limit after the last used address of each keychain;
+/// for a sliced descriptor the whole slice is watched.
+#[cfg(any(feature = "bip158", feature = "testing", test))]
+pub(crate) fn extend_watched<K, D: Descriptor<K>, L2: crate::Layer2Cache>(
+    scripts: &mut BTreeMap<bpstd::ScriptPubkey, bpstd::DerivedAddr>,
+    keychains: &mut BTreeMap<Keychain, (crate::wallet::AddrIter<'_, K, D>, u32)>,
+    cache: &WalletCache<L2>,
+    sliced: bool,
+) {
+    use bpstd::IdxBase;
+
+    for (keychain, (addresses, watched)) in keychains {
+        let used = cache
+            .addr
+            .get(keychain)
+            .and_then(|addrs| {
+                addrs
+                    .iter()
+                    .filter(|addr| addr.used > 0)
+                    .map(|addr| addr.terminal.index.index() + 1)
+                    .max()
+            })
+            .unwrap_or_default();
+        while sliced || *watched < used + GAP_LIMIT {
+            let Some(derived) = addresses.next() else {
+                break;
+            };
+            *watched = derived.terminal.index.index() + 1;
+            scripts.insert(derived.addr.script_pubkey(), derived);
+        }
+    }
+}
+
+/// Non-blocking counterpart of [`Indexer`], allowing applications running an async runtime to
+/// sync wallets without blocking a thread.
+#[cfg(feature = "async")]
+#[allow(async_fn_in_trait)]
+pub trait AsyncIndexer {
+    type Error;
+
+    async fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>>;
+
+    async fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>>;
+
+    async fn publish(&self, tx: &Tx) -> Result<(), Self::Error>;
+
+    /// Retrieves the raw transaction, returning `None` if it is not known to the indexer.
+    async fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error>;
+
+    /// Checks whether the transaction output is known to the indexer and whether it is spent.
+    async fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error>;
+
+    /// Retrieves recommended fee rates and, when available, the mempool fee histogram.
+    async fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error>;
+
+    /// Retrieves recommended fee rates keyed by the confirmation target in blocks.
+    async fn fee_estimates(&self) -> Result<BTreeMap<u16, FeeRate>, Self::Error> {
+        self.fee_snapshot().await.map(|snapshot| snapshot.targets)
+    }
 }
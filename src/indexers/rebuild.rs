@@ -0,0 +1,102 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use bpstd::{Address, Network, ScriptPubkey, Txid};
+
+use crate::{Layer2Cache, Party, WalletAddr, WalletCache};
+
+/// Resolves the wallet inputs and outputs of the transactions fetched for the wallet addresses,
+/// updating the unspent outputs and the address statistics.
+pub(crate) fn rebuild_cache<L2C: Layer2Cache>(
+    cache: &mut WalletCache<L2C>,
+    mut address_index: BTreeMap<ScriptPubkey, (WalletAddr<i64>, Vec<Txid>)>,
+    network: Network,
+) {
+    for (script, (wallet_addr, txids)) in &mut address_index {
+        for txid in txids {
+            let mut tx = cache.tx.remove(txid).expect("broken logic");
+            for debit in &mut tx.outputs {
+                let Some(s) = debit.beneficiary.script_pubkey() else {
+                    continue;
+                };
+                if &s == script {
+                    cache.utxo.insert(debit.outpoint);
+                    debit.beneficiary = Party::from_wallet_addr(wallet_addr);
+                    wallet_addr.used = wallet_addr.used.saturating_add(1);
+                    wallet_addr.volume.saturating_add_assign(debit.value);
+                    wallet_addr.balance = wallet_addr
+                        .balance
+                        .saturating_add(debit.value.sats().try_into().expect("sats overflow"));
+                } else if debit.beneficiary.is_unknown() {
+                    Address::with(&s, network)
+                        .map(|addr| {
+                            debit.beneficiary = Party::Counterparty(addr);
+                        })
+                        .ok();
+                }
+            }
+            cache.tx.insert(tx.txid, tx);
+        }
+    }
+
+    for (script, (wallet_addr, txids)) in &mut address_index {
+        for txid in txids {
+            let mut tx = cache.tx.remove(txid).expect("broken logic");
+            for credit in &mut tx.inputs {
+                let Some(s) = credit.payer.script_pubkey() else {
+                    continue;
+                };
+                if &s == script {
+                    credit.payer = Party::from_wallet_addr(wallet_addr);
+                    wallet_addr.balance = wallet_addr
+                        .balance
+                        .saturating_sub(credit.value.sats().try_into().expect("sats overflow"));
+                } else if credit.payer.is_unknown() {
+                    Address::with(&s, network)
+                        .map(|addr| {
+                            credit.payer = Party::Counterparty(addr);
+                        })
+                        .ok();
+                }
+                if let Some(prev_tx) = cache.tx.get_mut(&credit.outpoint.txid) {
+                    if let Some(txout) =
+                        prev_tx.outputs.get_mut(credit.outpoint.vout_u32() as usize)
+                    {
+                        let outpoint = txout.outpoint;
+                        if tx.status.is_mined() {
+                            cache.utxo.remove(&outpoint);
+                        }
+                        txout.spent = Some(credit.outpoint.into())
+                    };
+                }
+            }
+            cache.tx.insert(tx.txid, tx);
+        }
+        cache
+            .addr
+            .entry(wallet_addr.terminal.keychain)
+            .or_default()
+            .insert(wallet_addr.expect_transmute());
+    }
+}
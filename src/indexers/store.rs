@@ -0,0 +1,188 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap};
+#[cfg(feature = "fs")]
+use std::path::Path;
+#[cfg(feature = "fs")]
+use std::str::FromStr;
+#[cfg(feature = "fs")]
+use std::{fs, io};
+
+use bpstd::{Tx, Txid};
+
+/// Bounded store of raw transactions previously downloaded from an indexer, keyed by txid.
+///
+/// The store is shared by all indexers (and all wallets using the same data directory), such
+/// that transactions - most notably the ones providing prevouts for wallet transaction inputs -
+/// are not re-downloaded on each sync. When the number of transactions exceeds the store
+/// capacity, the least recently used ones are evicted.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct TxStore {
+    capacity: usize,
+    txs: HashMap<Txid, (Tx, u64)>,
+    lru: BTreeMap<u64, Txid>,
+    tick: u64,
+    dirty: bool,
+}
+
+impl Default for TxStore {
+    fn default() -> Self { TxStore::new(TxStore::DEFAULT_CAPACITY) }
+}
+
+impl TxStore {
+    /// Default number of transactions kept in the store.
+    pub const DEFAULT_CAPACITY: usize = 10_000;
+
+    /// Constructs empty store keeping at most `capacity` transactions.
+    pub fn new(capacity: usize) -> Self {
+        TxStore {
+            capacity,
+            txs: none!(),
+            lru: none!(),
+            tick: 0,
+            dirty: false,
+        }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize { self.capacity }
+
+    #[inline]
+    pub fn len(&self) -> usize { self.txs.len() }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.txs.is_empty() }
+
+    /// Detects whether the store has new transactions since it was loaded or saved.
+    #[inline]
+    pub fn is_dirty(&self) -> bool { self.dirty }
+
+    #[inline]
+    pub fn contains(&self, txid: &Txid) -> bool { self.txs.contains_key(txid) }
+
+    /// Returns transaction from the store, marking it as the most recently used.
+    pub fn get(&mut self, txid: &Txid) -> Option<&Tx> {
+        let tick = self.next_tick();
+        let (tx, used) = self.txs.get_mut(txid)?;
+        self.lru.remove(used);
+        self.lru.insert(tick, *txid);
+        *used = tick;
+        Some(tx)
+    }
+
+    /// Adds transaction to the store, evicting the least recently used transactions if the
+    /// store capacity is exceeded.
+    pub fn insert(&mut self, tx: Tx) {
+        let txid = tx.txid();
+        if self.get(&txid).is_some() {
+            return;
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        while self.txs.len() >= self.capacity {
+            let Some((_, evicted)) = self.lru.pop_first() else {
+                break;
+            };
+            self.txs.remove(&evicted);
+        }
+        let tick = self.next_tick();
+        self.lru.insert(tick, txid);
+        self.txs.insert(txid, (tx, tick));
+        self.dirty = true;
+    }
+
+    /// Iterates over transactions starting from the least recently used one.
+    pub fn iter(&self) -> impl Iterator<Item = &Tx> {
+        self.lru.values().map(|txid| &self.txs[txid].0)
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+#[cfg(feature = "fs")]
+impl TxStore {
+    /// Loads store from a file with hex-encoded transactions, one per line, ordered from the
+    /// least recently used. A missing file gives an empty store.
+    pub fn load(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let mut store = TxStore::new(capacity);
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(store),
+            Err(err) => return Err(err),
+        };
+        for line in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let tx = Tx::from_str(line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            store.insert(tx);
+        }
+        store.dirty = false;
+        Ok(store)
+    }
+
+    /// Saves the store into a file, creating the parent directory if needed.
+    pub fn save(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let data = self.iter().map(|tx| format!("{tx:x}\n")).collect::<String>();
+        fs::write(path, data)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{LockTime, Sats, ScriptPubkey, TxOut, TxVer, VarIntArray};
+
+    use super::*;
+
+    fn tx(value: u64) -> Tx {
+        Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::new(),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(ScriptPubkey::new(), Sats(value))]),
+            lock_time: LockTime::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut store = TxStore::new(2);
+        let (tx1, tx2, tx3) = (tx(1), tx(2), tx(3));
+        store.insert(tx1.clone());
+        store.insert(tx2.clone());
+        assert!(store.get(&tx1.txid()).is_some());
+        store.insert(tx3.clone());
+        assert_eq!(store.len(), 2);
+        assert!(store.contains(&tx1.txid()));
+        assert!(!store.contains(&tx2.txid()));
+        assert_eq!(store.iter().cloned().collect::<Vec<_>>(), vec![tx1, tx3]);
+        assert!(store.is_dirty());
+    }
+}
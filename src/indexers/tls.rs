@@ -0,0 +1,219 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TLS configuration for indexer connections, supporting servers with self-signed certificates
+//! via certificate pinning and custom certificate authorities.
+
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use amplify::hex::{FromHex, ToHex};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+
+/// SHA-256 fingerprint of a DER-encoded server certificate.
+///
+/// Parsed from a hex string, optionally with `:` byte separators, as printed by
+/// `openssl x509 -fingerprint -sha256`.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", into = "String", try_from = "String")
+)]
+pub struct CertFingerprint([u8; 32]);
+
+impl CertFingerprint {
+    pub fn with(cert: &CertificateDer) -> Self { CertFingerprint(Sha256::digest(cert).into()) }
+}
+
+/// invalid certificate fingerprint '{0}'; it must be a hex-encoded SHA-256 hash.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidFingerprint(String);
+
+impl Display for CertFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&self.0.to_hex()) }
+}
+
+impl FromStr for CertFingerprint {
+    type Err = InvalidFingerprint;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Vec::<u8>::from_hex(&s.replace(':', "").to_ascii_lowercase())
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(CertFingerprint)
+            .ok_or_else(|| InvalidFingerprint(s.to_owned()))
+    }
+}
+
+impl From<CertFingerprint> for String {
+    fn from(fingerprint: CertFingerprint) -> Self { fingerprint.to_string() }
+}
+
+impl TryFrom<String> for CertFingerprint {
+    type Error = InvalidFingerprint;
+    fn try_from(s: String) -> Result<Self, Self::Error> { CertFingerprint::from_str(&s) }
+}
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum TlsError {
+    /// unable to read CA bundle {0:?}: {1}
+    Pem(PathBuf, rustls::pki_types::pem::Error),
+
+    /// CA bundle {0:?} doesn't contain any certificates.
+    NoCerts(PathBuf),
+
+    /// invalid TLS configuration: {0}
+    #[from]
+    Rustls(rustls::Error),
+}
+
+/// TLS options for a specific indexer endpoint.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+pub struct TlsOpts {
+    /// Fingerprint of the server certificate. If given, the server is trusted only if it
+    /// presents exactly this certificate, and no certificate authority is checked.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub pin: Option<CertFingerprint>,
+
+    /// PEM file with certificates of additional trusted certificate authorities.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl TlsOpts {
+    pub fn is_empty(&self) -> bool { self.pin.is_none() && self.ca_bundle.is_none() }
+
+    /// Constructs TLS client configuration. Without the pin, the server certificate is
+    /// validated against the standard web roots and the certificates from the CA bundle.
+    pub fn client_config(&self) -> Result<Arc<ClientConfig>, TlsError> {
+        let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        if let Some(pin) = self.pin {
+            let verifier = PinnedVerifier { pin, provider };
+            return Ok(Arc::new(
+                builder
+                    .dangerous()
+                    .with_custom_certificate_verifier(Arc::new(verifier))
+                    .with_no_client_auth(),
+            ));
+        }
+        let mut roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        if let Some(path) = &self.ca_bundle {
+            let mut count = 0usize;
+            for cert in CertificateDer::pem_file_iter(path)
+                .map_err(|err| TlsError::Pem(path.clone(), err))?
+            {
+                roots.add(cert.map_err(|err| TlsError::Pem(path.clone(), err))?)?;
+                count += 1;
+            }
+            if count == 0 {
+                return Err(TlsError::NoCerts(path.clone()));
+            }
+        }
+        Ok(Arc::new(builder.with_root_certificates(roots).with_no_client_auth()))
+    }
+}
+
+/// Verifier trusting only the server certificate with a pinned fingerprint.
+#[derive(Debug)]
+struct PinnedVerifier {
+    pin: CertFingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = CertFingerprint::with(end_entity);
+        if fingerprint != self.pin {
+            return Err(rustls::Error::General(format!(
+                "server certificate fingerprint {fingerprint} doesn't match the pinned one {}",
+                self.pin
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_str_round_trip() {
+        let hex = "5e1c0ef3c18d7dd2b2b0e4b7da5f6e9e1f1e7d4c2cd04a8c3b6d2e1f0a9b8c7d";
+        let fingerprint = CertFingerprint::from_str(hex).unwrap();
+        assert_eq!(fingerprint.to_string(), hex);
+        let colons = hex
+            .as_bytes()
+            .chunks(2)
+            .map(|byte| String::from_utf8_lossy(byte).to_uppercase())
+            .collect::<Vec<_>>()
+            .join(":");
+        assert_eq!(CertFingerprint::from_str(&colons), Ok(fingerprint));
+        assert!(CertFingerprint::from_str("5e1c").is_err());
+    }
+}
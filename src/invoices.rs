@@ -0,0 +1,119 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking of expected incoming payments (invoices).
+//!
+//! Each invoice receives a fresh address of the wallet, so the payments are matched to the
+//! invoices by the addresses they pay to.
+
+use bpstd::{Sats, Terminal, Txid};
+
+/// Status of an invoice.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display(lowercase)]
+pub enum InvoiceStatus {
+    /// Payment is not received yet.
+    Open,
+    /// Expected amount is received, but some of the payments are not mined yet.
+    Pending,
+    /// Expected amount is received with mined transactions.
+    Confirmed,
+    /// Invoice has expired without being paid.
+    Expired,
+}
+
+/// Expected incoming payment.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Invoice {
+    pub terminal: Terminal,
+    pub amount: Sats,
+    /// UNIX timestamp of the invoice creation.
+    pub created: u64,
+    /// UNIX timestamp after which unpaid invoice is considered expired.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub expires: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub memo: Option<String>,
+    pub status: InvoiceStatus,
+    /// Amount received to the invoice address, including unmined transactions.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub received: Sats,
+    /// Transactions paying to the invoice address, with the mined ones going first in the
+    /// order of their heights.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Vec::is_empty"))]
+    pub payments: Vec<Txid>,
+}
+
+impl Invoice {
+    /// Determines invoice status from the received amounts. Paid invoices never expire.
+    pub fn status_for(&self, received: Sats, confirmed: Sats, now: u64) -> InvoiceStatus {
+        if confirmed >= self.amount {
+            InvoiceStatus::Confirmed
+        } else if received >= self.amount {
+            InvoiceStatus::Pending
+        } else if self.expires.is_some_and(|expires| now >= expires) {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Open
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{Idx, Keychain, NormalIndex};
+
+    use super::*;
+
+    #[test]
+    fn test_invoice_status() {
+        let invoice = Invoice {
+            terminal: Terminal::new(Keychain::OUTER, NormalIndex::ZERO),
+            amount: Sats::from(1_000u64),
+            created: 100,
+            expires: Some(200),
+            memo: None,
+            status: InvoiceStatus::Open,
+            received: Sats::ZERO,
+            payments: vec![],
+        };
+        let status = |received: u64, confirmed: u64, now| {
+            invoice.status_for(Sats::from(received), Sats::from(confirmed), now)
+        };
+        assert_eq!(status(0, 0, 150), InvoiceStatus::Open);
+        assert_eq!(status(999, 0, 150), InvoiceStatus::Open);
+        assert_eq!(status(999, 999, 200), InvoiceStatus::Expired);
+        assert_eq!(status(1_500, 500, 150), InvoiceStatus::Pending);
+        assert_eq!(status(1_500, 500, 300), InvoiceStatus::Pending);
+        assert_eq!(status(1_000, 1_000, 300), InvoiceStatus::Confirmed);
+    }
+}
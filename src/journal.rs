@@ -0,0 +1,154 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeSet;
+
+use bpstd::{Outpoint, Txid};
+
+use crate::{Layer1Changes, TxStatus};
+
+/// Event derived from a wallet sync and recorded in the [`Journal`].
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase", tag = "type")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum JournalEvent {
+    /// Transaction which was not known before.
+    TxAdded { txid: Txid, status: TxStatus },
+    /// Known transaction which status has changed.
+    StatusChanged { txid: Txid, status: TxStatus },
+    /// Transaction which is not a part of the wallet anymore.
+    TxRemoved { txid: Txid },
+    /// Wallet output spent by a transaction.
+    UtxoSpent { outpoint: Outpoint, spender: Txid },
+}
+
+/// Journal entry, carrying the sequence number of the event.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct JournalEntry {
+    /// Sequence number of the event, starting from 1 and increasing without gaps.
+    pub seq: u64,
+    pub event: JournalEvent,
+}
+
+/// Append-only journal of the events derived from wallet syncs. Allows incremental consumers to
+/// catch up after a downtime by replaying events from the last sequence number they have
+/// processed, see [`crate::Wallet::events_since`].
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Journal(Vec<JournalEntry>);
+
+impl Journal {
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    pub fn len(&self) -> usize { self.0.len() }
+
+    /// Returns the sequence number of the last recorded event, or zero for an empty journal.
+    pub fn last_seq(&self) -> u64 { self.0.last().map(|entry| entry.seq).unwrap_or_default() }
+
+    /// Appends the event to the journal, returning its sequence number.
+    pub fn append(&mut self, event: JournalEvent) -> u64 {
+        let seq = self.last_seq() + 1;
+        self.0.push(JournalEntry { seq, event });
+        seq
+    }
+
+    /// Returns the entries recorded after the event with the given sequence number.
+    pub fn since(&self, seq: u64) -> &[JournalEntry] {
+        let pos = self.0.partition_point(|entry| entry.seq <= seq);
+        &self.0[pos..]
+    }
+
+    /// Records the transaction changes of a sync together with the wallet outputs which stopped
+    /// being unspent. Outputs which aren't spent by the added or updated transactions, for
+    /// instance the ones of removed transactions, are not recorded.
+    pub fn record(&mut self, changes: &Layer1Changes, unspent_before: &BTreeSet<Outpoint>) {
+        for txid in &changes.removed {
+            self.append(JournalEvent::TxRemoved { txid: *txid });
+        }
+        for tx in &changes.added {
+            self.append(JournalEvent::TxAdded {
+                txid: tx.txid,
+                status: tx.status,
+            });
+        }
+        for tx in &changes.updated {
+            self.append(JournalEvent::StatusChanged {
+                txid: tx.txid,
+                status: tx.status,
+            });
+        }
+        for tx in changes.added.iter().chain(&changes.updated) {
+            for input in &tx.inputs {
+                if unspent_before.contains(&input.outpoint) {
+                    self.append(JournalEvent::UtxoSpent {
+                        outpoint: input.outpoint,
+                        spender: tx.txid,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_journal_since() {
+        let txid =
+            Txid::from_str("9a5c3e2d0b7f8a1c4e6d2b9f0a3c5e7d1b8f2a4c6e0d9b3f5a7c1e8d2b4f6a0c")
+                .unwrap();
+        let mut journal = Journal::default();
+        assert_eq!(journal.last_seq(), 0);
+        assert!(journal.since(0).is_empty());
+
+        let changes = Layer1Changes {
+            removed: vec![txid, txid],
+            ..none!()
+        };
+        journal.record(&changes, &none!());
+        assert_eq!(journal.append(JournalEvent::TxRemoved { txid }), 3);
+        assert_eq!(journal.len(), 3);
+        assert_eq!(journal.since(0).len(), 3);
+        assert_eq!(journal.since(2), &[JournalEntry {
+            seq: 3,
+            event: JournalEvent::TxRemoved { txid }
+        }]);
+        assert!(journal.since(3).is_empty());
+        assert!(journal.since(10).is_empty());
+    }
+}
@@ -20,13 +20,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::error;
 use std::fmt::Debug;
 
+use bpstd::{Outpoint, Txid};
 use nonasync::persistence::{CloneNoPersistence, Persistence, Persisting};
 
-pub trait Layer2: Debug + CloneNoPersistence + Persisting {
+use crate::{TxStatus, WalletTx};
+
+pub trait Layer2: Debug + CloneNoPersistence + Persisting + Layer2Sync {
     type Descr: Layer2Descriptor;
     type Data: Layer2Data;
     type Cache: Layer2Cache;
@@ -34,6 +38,63 @@ pub trait Layer2: Debug + CloneNoPersistence + Persisting {
     type StoreError: error::Error;
 }
 
+/// Hook keeping layer 2 state consistent with the bitcoin wallet cache, which is invoked by
+/// [`crate::Wallet::update`] after each successful sync of the bitcoin transactions.
+pub trait Layer2Sync {
+    type SyncError: error::Error;
+
+    /// Updates layer 2 data and cache according to the transactions added, changed or removed
+    /// by the sync.
+    ///
+    /// Returns whether the layer 2 data were changed and must be saved; the layer 2 cache is
+    /// always saved after a sync.
+    fn sync_layer2(
+        &mut self,
+        data: &mut <Self as Layer2>::Data,
+        cache: &mut <Self as Layer2>::Cache,
+        changes: &Layer1Changes,
+    ) -> Result<bool, Self::SyncError>
+    where
+        Self: Layer2,
+    {
+        let _ = (data, cache, changes);
+        Ok(false)
+    }
+}
+
+/// Transactions of the bitcoin wallet cache changed by a sync.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Layer1Changes<'tx> {
+    /// Transactions which were not known before the sync.
+    pub added: Vec<&'tx WalletTx>,
+    /// Known transactions which status (like the number of confirmations) has changed.
+    pub updated: Vec<&'tx WalletTx>,
+    /// Transactions which are not a part of the wallet anymore, for instance after being
+    /// replaced or reorganized out.
+    pub removed: Vec<Txid>,
+}
+
+impl<'tx> Layer1Changes<'tx> {
+    /// Detects changes by comparing statuses of the transactions known before the sync with the
+    /// transactions after it.
+    pub fn with(known: &BTreeMap<Txid, TxStatus>, txs: &'tx BTreeMap<Txid, WalletTx>) -> Self {
+        let mut changes = Layer1Changes::default();
+        for (txid, tx) in txs {
+            match known.get(txid) {
+                None => changes.added.push(tx),
+                Some(status) if *status != tx.status => changes.updated.push(tx),
+                Some(_) => {}
+            }
+        }
+        changes.removed = known.keys().filter(|txid| !txs.contains_key(*txid)).copied().collect();
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+}
+
 pub trait Layer2Descriptor: Debug + Clone {}
 
 pub trait Layer2Data: Debug + Clone + Default {}
@@ -41,20 +102,69 @@ pub trait Layer2Data: Debug + Clone + Default {}
 pub trait Layer2Cache: Debug + Clone + Default {
     type Tx: Layer2Tx;
     type Coin: Layer2Coin;
+
+    /// Layer 2 information on a wallet transaction, put into its history row.
+    fn tx_layer2(&self, txid: Txid) -> Self::Tx {
+        let _ = txid;
+        default!()
+    }
+
+    /// Layer 2 allocations on a wallet coin, put into its coin row.
+    fn coin_layer2(&self, outpoint: Outpoint) -> Vec<Self::Coin> {
+        let _ = outpoint;
+        vec![]
+    }
+}
+
+/// Column which layer 2 data contribute to the tables of wallet coins and history.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Layer2Column {
+    pub header: &'static str,
+    /// Whether the column contains amounts, which are hidden in the discreet display mode.
+    pub amount: bool,
+}
+
+impl Layer2Column {
+    pub const fn new(header: &'static str) -> Self {
+        Layer2Column {
+            header,
+            amount: false,
+        }
+    }
+
+    pub const fn amount(header: &'static str) -> Self {
+        Layer2Column {
+            header,
+            amount: true,
+        }
+    }
+}
+
+/// Layer 2 data displayed as additional columns (like asset id and amount) in the tables of
+/// wallet coins and history.
+pub trait Layer2Columns {
+    /// Columns contributed by the type; no columns are added by default.
+    const COLUMNS: &'static [Layer2Column] = &[];
+
+    /// Values for each of [`Self::COLUMNS`].
+    fn column_values(&self) -> Vec<String> { vec![] }
 }
 
 #[cfg(not(feature = "serde"))]
-pub trait Layer2Tx: Debug + Default {}
+pub trait Layer2Tx: Debug + Default + Layer2Columns {}
 
 #[cfg(feature = "serde")]
-pub trait Layer2Tx: Debug + Default + serde::Serialize + for<'de> serde::Deserialize<'de> {}
+pub trait Layer2Tx:
+    Debug + Default + Layer2Columns + serde::Serialize + for<'de> serde::Deserialize<'de>
+{
+}
 
 #[cfg(not(feature = "serde"))]
-pub trait Layer2Coin: Debug + Default {}
+pub trait Layer2Coin: Debug + Default + Layer2Columns {}
 
 #[cfg(feature = "serde")]
 pub trait Layer2Coin:
-    Debug + Default + serde::Serialize + for<'de> serde::Deserialize<'de>
+    Debug + Default + Layer2Columns + serde::Serialize + for<'de> serde::Deserialize<'de>
 {
 }
 
@@ -116,6 +226,8 @@ impl Persisting for NoLayer2 {
     fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
     #[inline]
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+    #[inline]
+    fn is_dirty(&self) -> bool { self.persistence.as_ref().map(|p| p.dirty).unwrap_or(true) }
 }
 
 impl Layer2 for NoLayer2 {
@@ -126,6 +238,10 @@ impl Layer2 for NoLayer2 {
     type StoreError = Infallible;
 }
 
+impl Layer2Sync for NoLayer2 {
+    type SyncError = Infallible;
+}
+
 impl Layer2Descriptor for Layer2Empty {}
 
 impl Layer2Data for Layer2Empty {}
@@ -135,5 +251,44 @@ impl Layer2Cache for Layer2Empty {
     type Coin = Layer2Empty;
 }
 
+impl Layer2Columns for Layer2Empty {}
+
 impl Layer2Tx for Layer2Empty {}
 impl Layer2Coin for Layer2Empty {}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{LockTime, Sats, TxVer};
+
+    use super::*;
+
+    #[test]
+    fn test_layer1_changes() {
+        let tx = |id: u8, status: TxStatus| WalletTx {
+            txid: Txid::from([id; 32]),
+            status,
+            inputs: vec![],
+            outputs: vec![],
+            fee: Sats::ZERO,
+            size: 0,
+            weight: 0,
+            version: TxVer::V2,
+            locktime: LockTime::ZERO,
+        };
+        let txs = bmap! {
+            Txid::from([1; 32]) => tx(1, TxStatus::Mempool),
+            Txid::from([2; 32]) => tx(2, TxStatus::Mempool),
+            Txid::from([3; 32]) => tx(3, TxStatus::Mempool),
+        };
+        let known = bmap! {
+            Txid::from([2; 32]) => TxStatus::Mempool,
+            Txid::from([3; 32]) => TxStatus::Unknown,
+            Txid::from([4; 32]) => TxStatus::Mempool,
+        };
+        let changes = Layer1Changes::with(&known, &txs);
+        assert_eq!(changes.added, vec![&txs[&Txid::from([1; 32])]]);
+        assert_eq!(changes.updated, vec![&txs[&Txid::from([3; 32])]]);
+        assert_eq!(changes.removed, vec![Txid::from([4; 32])]);
+        assert!(Layer1Changes::with(&known, &none!()).added.is_empty());
+    }
+}
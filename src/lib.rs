@@ -33,35 +33,164 @@ extern crate log;
 
 pub mod indexers;
 mod util;
+mod amount;
+mod collab;
+mod cosigners;
+mod cpfp;
+mod amend;
+mod bump;
+mod change;
+mod fee;
+mod fill;
+mod deduct;
+mod estimate;
+mod memo;
+mod merkle;
+mod package;
+mod payments;
+mod pool;
+mod preview;
+mod split;
+mod sweep;
+mod templates;
+mod summary;
+mod taproot;
+mod timings;
 mod data;
+mod delta;
 mod rows;
+mod scripthash;
+mod shuffle;
 mod wallet;
 mod layer2;
+mod report;
+mod lots;
+mod journal;
+mod transfers;
+mod health;
+mod invoices;
 pub mod coinselect;
+pub mod standardness;
 #[cfg(feature = "cli")]
 pub mod cli;
 #[cfg(feature = "signers")]
 pub mod hot;
+#[cfg(feature = "tui")]
+pub mod tui;
 mod bip43;
+mod contacts;
+mod dynamic;
+#[cfg(feature = "fs")]
+mod airgap;
+mod dryrun;
 #[cfg(feature = "fs")]
 pub mod fs;
+#[cfg(feature = "rates")]
+pub mod rates;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "fs")]
+mod workspace;
+#[cfg(any(test, feature = "testing"))]
+mod simulator;
+#[cfg(test)]
+mod fixtures;
 
+#[cfg(feature = "fs")]
+pub use airgap::{
+    AirgapDir, AirgapError, SignedPsbt, AIRGAP_CHECKSUM, AIRGAP_PSBT, AIRGAP_SIGNED, AIRGAP_TXS,
+};
+pub use amend::{AmendError, PsbtAmender};
+pub use amount::{
+    check_dust, parse_address, parse_beneficiary, parse_payment, parse_sats, Amount,
+    AmountParseError, PaymentParseError, Share, Unit, MAX_MONEY,
+};
 pub use bip43::{Bip43, DerivationStandard, ParseBip43Error};
 pub use bpstd::*;
+pub use bump::{BumpError, BumpMeta, INCREMENTAL_RELAY_FEE};
+pub use change::{
+    change_window_offset, rederive_script, ChangeSplitter, MAX_CHANGE_OUTPUTS, MAX_CHANGE_WINDOW,
+    MIN_CHANGE_SPLIT,
+};
+pub use collab::{
+    CollabError, CollabInput, CollabOutput, CollabParty, CollabSession, InvalidOwnershipProof,
+    OwnershipProof, COLLAB_SHARED_WEIGHT,
+};
+pub use contacts::{Contact, ContactParseError, ContactTemplate, DynamicContact};
+pub use cosigners::{CosignerKey, InputCosigners};
+pub use cpfp::{CpfpError, CpfpMeta};
 pub use data::{
-    BlockHeight, BlockInfo, MiningInfo, Party, TxCredit, TxDebit, TxStatus, WalletAddr, WalletTx,
-    WalletUtxo,
+    signals_rbf, BlockHeight, BlockInfo, Confirmations, MiningInfo, Party, PendingStatus,
+    PendingTx, TxCredit, TxDebit, TxProvenance, TxSource, TxStatus, WalletAddr, WalletTx,
+    WalletUtxo, SEQ_NO_NO_RBF, SEQ_NO_RBF,
+};
+pub use deduct::{satisfaction_weight, DeductError, FeeDeductor};
+pub use delta::CacheDelta;
+pub use dryrun::{Change, DryRun};
+pub use dynamic::{DynDescriptor, DynWallet};
+pub use estimate::{FeeEstimator, FeeParams, MAX_FEE_ITERATIONS};
+pub use fee::{
+    parse_fee, FeeParseError, FeeRate, FeeRateDisplay, FeeRateParseError, FeeSpec, FeeUnit,
+    UnknownFeeUnit, WU_PER_VBYTE,
+};
+pub use fill::PsbtFill;
+pub use health::{
+    AddressReuse, CacheConsistency, DescriptorValidity, HealthCheck, HealthIssue, HealthReport,
+    IndexGap, NetworkMatch, Severity, StaleSync, TaprootKeys,
 };
 #[cfg(feature = "hot")]
 pub use hot::{HotArgs, HotCommand};
 #[cfg(feature = "signers")]
 pub use hot::{Seed, SeedType};
-pub use indexers::Indexer;
+#[cfg(feature = "async")]
+pub use indexers::AsyncIndexer;
 #[cfg(any(feature = "electrum", feature = "esplora", feature = "mempool"))]
 pub use indexers::{AnyIndexer, AnyIndexerError};
+pub use indexers::{
+    BroadcastFanout, BroadcastResult, FeeSnapshot, Indexer, IndexerConfig, IndexerKind,
+    OutpointStatus, SyncCursor, SyncReport, TxStore,
+};
+pub use invoices::{Invoice, InvoiceStatus};
+pub use journal::{Journal, JournalEntry, JournalEvent};
 pub use layer2::{
-    Layer2, Layer2Cache, Layer2Coin, Layer2Data, Layer2Descriptor, Layer2Empty, Layer2Tx, NoLayer2,
+    Layer1Changes, Layer2, Layer2Cache, Layer2Coin, Layer2Column, Layer2Columns, Layer2Data,
+    Layer2Descriptor, Layer2Empty, Layer2Sync, Layer2Tx, NoLayer2,
+};
+pub use lots::{Disposal, Lot, LotMethod, LotTracker};
+pub use memo::{
+    memo_key, wallet_key, DescriptorFp, InvalidDescriptorFp, PsbtMemo, PSBT_BP_PREFIX,
+    PSBT_GLOBAL_MEMO, PSBT_GLOBAL_WALLET,
+};
+pub use merkle::{MerkleBlock, MerkleProofError};
+pub use package::{PackageConstructor, PackageError, PackageMeta};
+pub use payments::{parse_recipient, HumanReadableName, InvalidName, Recipient};
+#[cfg(feature = "payment-resolvers")]
+pub use payments::{
+    PaymentResolver, ResolutionSource, ResolveError, ResolvedPayment, DEFAULT_DOH_RESOLVER,
+    WELL_KNOWN_PATH,
 };
-pub use rows::{CoinRow, Counterparty, OpType, TxRow};
+pub use pool::{PoolEvent, PoolScheduler, PoolSlot, WalletPool};
+pub use preview::SpendPreview;
+pub use report::{aggregate_by_period, Date, FeeTotal, InvalidDate, Period, PeriodBucket};
+pub use rows::{AddrRow, CoinRow, Counterparty, OpType, TxRow};
+pub use scripthash::{InvalidScriptHash, ScriptHash};
+pub use shuffle::{InvalidShuffleSeed, ShuffleSeed};
+#[cfg(any(test, feature = "testing"))]
+pub use simulator::{SimBlock, SimIndexer, Simulator, SIM_START_TIME};
+pub use split::{PaymentSplitter, SplitError};
+pub use summary::CacheSummary;
+pub use sweep::{SweepError, WalletSweeper, MAX_STANDARD_TX_WEIGHT};
+pub use taproot::{address_output_key, bip86_output_key, TaprootAudit, TaprootAuditStatus};
+pub use templates::{TemplateError, WalletTemplate};
+pub use timings::{Phase, PhaseTiming, Timings};
+pub use transfers::OwnWallets;
 pub use util::MayError;
-pub use wallet::{Wallet, WalletCache, WalletData, WalletDescr};
+pub use wallet::{
+    AbandonedDraft, ConfirmationError, DraftMeta, KeychainStatus, Wallet, WalletCache, WalletData,
+    WalletDescr, WalletStore, WalletStoreFactory, INDEX_EXHAUSTION_MARGIN,
+};
+#[cfg(feature = "fs")]
+pub use workspace::{
+    InputSignatures, Workspace, WorkspaceError, WORKSPACE_FINAL, WORKSPACE_SIGNED,
+    WORKSPACE_UNSIGNED,
+};
@@ -0,0 +1,263 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tracking of the acquisition cost of the wallet funds in lots, and of the capital gains
+//! realized when the funds are disposed.
+
+use std::collections::VecDeque;
+
+use bpstd::{Sats, Txid};
+
+use crate::{Layer2Cache, TxStatus, WalletCache};
+
+/// Order in which the lots are used up by the disposals.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[display(lowercase)]
+pub enum LotMethod {
+    /// First in, first out: the earliest acquired lots are disposed first.
+    #[default]
+    Fifo,
+    /// Last in, first out: the latest acquired lots are disposed first.
+    Lifo,
+}
+
+/// Funds acquired by a single transaction, valued at the exchange rate of its mining time.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Lot {
+    pub txid: Txid,
+    /// UNIX timestamp of the acquisition.
+    pub acquired: u64,
+    /// Amount remaining in the lot.
+    pub amount: Sats,
+    /// Acquisition cost of the remaining amount, in a fiat currency.
+    pub cost: f64,
+}
+
+impl Lot {
+    /// Splits off a part of the lot, moving the proportional share of the cost into it.
+    fn take(&mut self, amount: Sats) -> Lot {
+        let cost = self.cost * amount.sats() as f64 / self.amount.sats() as f64;
+        self.amount -= amount;
+        self.cost -= cost;
+        Lot {
+            amount,
+            cost,
+            ..*self
+        }
+    }
+}
+
+/// Part of the funds disposed by a transaction, coming from a single lot.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Disposal {
+    /// Transaction disposing the funds.
+    pub txid: Txid,
+    /// UNIX timestamp of the lot acquisition, or `None` if the funds are not covered by the
+    /// known lots (for instance, when the wallet history is incomplete). The cost of such funds
+    /// is unknown and taken as zero.
+    pub acquired: Option<u64>,
+    /// UNIX timestamp of the disposal.
+    pub disposed: u64,
+    pub amount: Sats,
+    /// Value of the disposed funds at the exchange rate of the disposal time.
+    pub proceeds: f64,
+    /// Acquisition cost of the disposed funds.
+    pub cost: f64,
+}
+
+impl Disposal {
+    /// Realized capital gain; negative for a loss.
+    pub fn gain(&self) -> f64 { self.proceeds - self.cost }
+}
+
+/// Lots of the wallet funds and the disposals of the funds from them.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct LotTracker {
+    method: LotMethod,
+    lots: VecDeque<Lot>,
+    disposals: Vec<Disposal>,
+}
+
+impl LotTracker {
+    pub fn new(method: LotMethod) -> Self {
+        LotTracker {
+            method,
+            lots: none!(),
+            disposals: none!(),
+        }
+    }
+
+    /// Adds a lot of acquired funds. Lots must be added in the order of their acquisition.
+    pub fn acquire(&mut self, lot: Lot) {
+        if lot.amount > Sats::ZERO {
+            self.lots.push_back(lot);
+        }
+    }
+
+    /// Disposes the funds from the lots in the order of the tracker method, at the given
+    /// exchange `rate` of a single bitcoin.
+    pub fn dispose(&mut self, txid: Txid, timestamp: u64, mut amount: Sats, rate: f64) {
+        let value = |sats: Sats| sats.sats() as f64 * rate / Sats::BTC.0 as f64;
+        while amount > Sats::ZERO {
+            let lot = match self.method {
+                LotMethod::Fifo => self.lots.front_mut(),
+                LotMethod::Lifo => self.lots.back_mut(),
+            };
+            let Some(lot) = lot else {
+                self.disposals.push(Disposal {
+                    txid,
+                    acquired: None,
+                    disposed: timestamp,
+                    amount,
+                    proceeds: value(amount),
+                    cost: 0.0,
+                });
+                return;
+            };
+            let part = lot.take(amount.min(lot.amount));
+            if lot.amount == Sats::ZERO {
+                match self.method {
+                    LotMethod::Fifo => self.lots.pop_front(),
+                    LotMethod::Lifo => self.lots.pop_back(),
+                };
+            }
+            amount -= part.amount;
+            self.disposals.push(Disposal {
+                txid,
+                acquired: Some(part.acquired),
+                disposed: timestamp,
+                amount: part.amount,
+                proceeds: value(part.amount),
+                cost: part.cost,
+            });
+        }
+    }
+
+    /// Lots of the funds which are still held, in the order of their acquisition.
+    pub fn lots(&self) -> impl Iterator<Item = &Lot> { self.lots.iter() }
+
+    /// Disposals in the order of the disposing transactions.
+    pub fn disposals(&self) -> &[Disposal] { &self.disposals }
+}
+
+impl<L2: Layer2Cache> WalletCache<L2> {
+    /// Tracks lots of the wallet funds over the mined transaction history. Transactions
+    /// increasing the wallet balance acquire a lot, while transactions decreasing it dispose the
+    /// decrease (including the miner fee) from the lots.
+    ///
+    /// The `rate` function provides the exchange rate of a single bitcoin at the given UNIX
+    /// timestamp.
+    pub fn track_lots<E>(
+        &self,
+        method: LotMethod,
+        mut rate: impl FnMut(u64) -> Result<f64, E>,
+    ) -> Result<LotTracker, E> {
+        let mut changes = self
+            .history_by_height()
+            .filter_map(|row| {
+                let TxStatus::Mined(timestamp) = row.time else {
+                    return None;
+                };
+                let change = row.own.iter().map(|(_, value)| *value).sum::<i64>();
+                (change != 0).then_some((row.height, change < 0, timestamp, row.txid, change))
+            })
+            .collect::<Vec<_>>();
+        // Within a block acquisitions go first, since their funds may be spent by the other
+        // transactions of the same block
+        changes.sort_by_key(|(height, disposal, ..)| (*height, *disposal));
+
+        let mut tracker = LotTracker::new(method);
+        for (_, disposal, timestamp, txid, change) in changes {
+            let rate = rate(timestamp)?;
+            let amount = Sats::from(change.unsigned_abs());
+            if disposal {
+                tracker.dispose(txid, timestamp, amount, rate);
+            } else {
+                tracker.acquire(Lot {
+                    txid,
+                    acquired: timestamp,
+                    amount,
+                    cost: amount.sats() as f64 * rate / Sats::BTC.0 as f64,
+                });
+            }
+        }
+        Ok(tracker)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lot(id: u8, amount: u64, cost: f64) -> Lot {
+        Lot {
+            txid: Txid::from([id; 32]),
+            acquired: id as u64,
+            amount: Sats(amount),
+            cost,
+        }
+    }
+
+    #[test]
+    fn lot_methods() {
+        let mut fifo = LotTracker::new(LotMethod::Fifo);
+        fifo.acquire(lot(1, 100_000_000, 10_000.0));
+        fifo.acquire(lot(2, 100_000_000, 20_000.0));
+        let mut lifo = fifo.clone();
+        lifo.method = LotMethod::Lifo;
+
+        fifo.dispose(Txid::from([3; 32]), 3, Sats(150_000_000), 30_000.0);
+        let disposals = fifo.disposals();
+        assert_eq!(disposals.len(), 2);
+        assert_eq!(disposals[0].acquired, Some(1));
+        assert_eq!(disposals[0].gain(), 20_000.0);
+        assert_eq!(disposals[1].acquired, Some(2));
+        assert_eq!(disposals[1].amount, Sats(50_000_000));
+        assert_eq!(disposals[1].cost, 10_000.0);
+        assert_eq!(disposals[1].gain(), 5_000.0);
+        assert_eq!(fifo.lots().map(|lot| (lot.acquired, lot.cost)).collect::<Vec<_>>(), vec![(
+            2, 10_000.0
+        )]);
+
+        lifo.dispose(Txid::from([3; 32]), 3, Sats(150_000_000), 30_000.0);
+        let disposals = lifo.disposals();
+        assert_eq!(disposals[0].acquired, Some(2));
+        assert_eq!(disposals[0].gain(), 10_000.0);
+        assert_eq!(disposals[1].acquired, Some(1));
+        assert_eq!(disposals[1].gain(), 10_000.0);
+        assert_eq!(lifo.lots().map(|lot| lot.amount).collect::<Vec<_>>(), vec![Sats(50_000_000)]);
+    }
+
+    #[test]
+    fn uncovered_disposal() {
+        let mut tracker = LotTracker::new(LotMethod::Fifo);
+        tracker.acquire(lot(1, 1_000, 1.0));
+        tracker.dispose(Txid::from([2; 32]), 2, Sats(3_000), 100_000_000.0);
+        let disposals = tracker.disposals();
+        assert_eq!(disposals.len(), 2);
+        assert_eq!(disposals[1].acquired, None);
+        assert_eq!(disposals[1].amount, Sats(2_000));
+        assert_eq!(disposals[1].gain(), 2_000.0);
+        assert_eq!(tracker.lots().count(), 0);
+    }
+}
@@ -0,0 +1,142 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transaction memos and wallet fingerprints embedded into PSBTs, so cosigners can see them at
+//! signing time.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use psbt::{PropKey, Psbt};
+use sha2::{Digest, Sha256};
+
+/// Proprietary PSBT key prefix used by BP wallet.
+pub const PSBT_BP_PREFIX: &str = "BP";
+/// Proprietary global key subtype holding UTF-8 encoded transaction memo.
+pub const PSBT_GLOBAL_MEMO: u64 = 0x00;
+/// Proprietary global key subtype holding [`DescriptorFp`] of the wallet constructed the PSBT.
+pub const PSBT_GLOBAL_WALLET: u64 = 0x01;
+
+/// Fingerprint of a wallet descriptor: first four bytes of SHA256 hash of the descriptor string
+/// representation, displayed as a hex string.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct DescriptorFp([u8; 4]);
+
+impl DescriptorFp {
+    pub fn with(descriptor: &impl Display) -> Self {
+        let hash = Sha256::digest(descriptor.to_string().as_bytes());
+        DescriptorFp([hash[0], hash[1], hash[2], hash[3]])
+    }
+}
+
+impl Display for DescriptorFp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&self.0.to_hex()) }
+}
+
+/// invalid descriptor fingerprint '{0}'; it must be an 8-character hex string.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidDescriptorFp(String);
+
+impl FromStr for DescriptorFp {
+    type Err = InvalidDescriptorFp;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <[u8; 4]>::from_hex(s).map(DescriptorFp).map_err(|_| InvalidDescriptorFp(s.to_owned()))
+    }
+}
+
+/// Constructs [`PSBT_GLOBAL_MEMO`] proprietary key.
+pub fn memo_key() -> PropKey {
+    PropKey {
+        identifier: PSBT_BP_PREFIX.to_owned(),
+        subtype: PSBT_GLOBAL_MEMO,
+        data: none!(),
+    }
+}
+
+/// Constructs [`PSBT_GLOBAL_WALLET`] proprietary key.
+pub fn wallet_key() -> PropKey {
+    PropKey {
+        identifier: PSBT_BP_PREFIX.to_owned(),
+        subtype: PSBT_GLOBAL_WALLET,
+        data: none!(),
+    }
+}
+
+/// Extension trait for reading and embedding transaction memo in a PSBT.
+pub trait PsbtMemo {
+    /// Returns transaction memo, if it is present and is a valid UTF-8 string.
+    fn memo(&self) -> Option<String>;
+
+    /// Embeds transaction memo, replacing the existing one.
+    fn set_memo(&mut self, memo: &str);
+
+    /// Returns fingerprint of the wallet which has constructed the PSBT, if present.
+    fn wallet_fp(&self) -> Option<DescriptorFp>;
+
+    /// Embeds fingerprint of the wallet constructing the PSBT, replacing the existing one.
+    fn set_wallet_fp(&mut self, fp: DescriptorFp);
+}
+
+impl PsbtMemo for Psbt {
+    fn memo(&self) -> Option<String> {
+        let value = self.proprietary.get(&memo_key())?;
+        String::from_utf8(value.to_vec()).ok()
+    }
+
+    fn set_memo(&mut self, memo: &str) {
+        self.proprietary.insert(memo_key(), memo.as_bytes().to_vec().into());
+    }
+
+    fn wallet_fp(&self) -> Option<DescriptorFp> {
+        let value = self.proprietary.get(&wallet_key())?;
+        <[u8; 4]>::try_from(value.as_slice()).ok().map(DescriptorFp)
+    }
+
+    fn set_wallet_fp(&mut self, fp: DescriptorFp) {
+        self.proprietary.insert(wallet_key(), fp.0.to_vec().into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memo_round_trip() {
+        let mut psbt = Psbt::create(psbt::PsbtVer::V0);
+        assert_eq!(psbt.memo(), None);
+        psbt.set_memo("rent for March");
+        let psbt = Psbt::deserialize(psbt.serialize(psbt::PsbtVer::V0)).unwrap();
+        assert_eq!(psbt.memo().as_deref(), Some("rent for March"));
+
+        let fp = DescriptorFp::with(&"wpkh([00000000/84h/1h/0h]tpub/<0;1>/*)");
+        assert_eq!(DescriptorFp::from_str(&fp.to_string()), Ok(fp));
+        let mut psbt = psbt;
+        psbt.set_wallet_fp(fp);
+        let psbt = Psbt::deserialize(psbt.serialize(psbt::PsbtVer::V0)).unwrap();
+        assert_eq!(psbt.wallet_fp(), Some(fp));
+        assert_eq!(psbt.memo().as_deref(), Some("rent for March"));
+    }
+}
@@ -0,0 +1,260 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Merkle proofs of transaction inclusion into a block in the format of BIP-37 `merkleblock`
+//! message, which is also produced by the `gettxoutproof` RPC command of Bitcoin Core.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use amplify::ByteArray;
+use bpstd::{BlockHeader, ConsensusDecode, ConsensusDecodeError, ConsensusEncode, Txid, VarInt};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum MerkleProofError {
+    /// merkle proof is not a valid hex string.
+    #[from(amplify::hex::Error)]
+    Hex,
+
+    /// merkle proof has invalid encoding. Details: {0}
+    #[from]
+    Encoding(ConsensusDecodeError),
+
+    /// merkle proof has data after the end of the proof.
+    DataNotConsumed,
+
+    /// merkle proof doesn't commit to any transactions.
+    NoTransactions,
+
+    /// merkle proof contains more hashes than the block has transactions.
+    TooManyHashes,
+
+    /// merkle proof has not enough hashes or flag bits to reconstruct the merkle tree.
+    Truncated,
+
+    /// merkle proof has unused hashes or flag bits.
+    Unused,
+
+    /// merkle proof contains duplicated nodes, which may be used to fake a transaction
+    /// inclusion (CVE-2012-2459).
+    DuplicatedNodes,
+
+    /// merkle proof doesn't match the merkle root of the block header.
+    RootMismatch,
+}
+
+/// Block header with the partial merkle tree of the block transactions, proving inclusion of
+/// some of them into the block.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct MerkleBlock {
+    pub header: BlockHeader,
+    /// Total number of the transactions in the block.
+    pub total_txs: u32,
+    /// Hashes of the partial merkle tree in depth-first order.
+    pub hashes: Vec<[u8; 32]>,
+    /// Flag bits of the partial merkle tree in depth-first order, packed least significant bit
+    /// first.
+    pub flags: Vec<u8>,
+}
+
+impl MerkleBlock {
+    /// Verifies the partial merkle tree against the merkle root of the block header, returning
+    /// ids of the transactions whose inclusion into the block is proven.
+    pub fn verify(&self) -> Result<Vec<Txid>, MerkleProofError> {
+        if self.total_txs == 0 {
+            return Err(MerkleProofError::NoTransactions);
+        }
+        if self.hashes.len() > self.total_txs as usize {
+            return Err(MerkleProofError::TooManyHashes);
+        }
+        if self.flags.len() * 8 < self.hashes.len() {
+            return Err(MerkleProofError::Truncated);
+        }
+        let mut height = 0;
+        while self.tree_width(height) > 1 {
+            height += 1;
+        }
+        let mut walker = TreeWalker {
+            block: self,
+            bits_used: 0,
+            hashes_used: 0,
+            matched: vec![],
+        };
+        let root = walker.traverse(height, 0)?;
+        if walker.bits_used.div_ceil(8) != self.flags.len()
+            || walker.hashes_used != self.hashes.len()
+        {
+            return Err(MerkleProofError::Unused);
+        }
+        if root != self.header.merkle_root.to_byte_array() {
+            return Err(MerkleProofError::RootMismatch);
+        }
+        Ok(walker.matched)
+    }
+
+    fn tree_width(&self, height: u32) -> u32 {
+        ((self.total_txs as u64 + (1 << height) - 1) >> height) as u32
+    }
+}
+
+struct TreeWalker<'block> {
+    block: &'block MerkleBlock,
+    bits_used: usize,
+    hashes_used: usize,
+    matched: Vec<Txid>,
+}
+
+impl TreeWalker<'_> {
+    fn traverse(&mut self, height: u32, pos: u32) -> Result<[u8; 32], MerkleProofError> {
+        let flag = self
+            .block
+            .flags
+            .get(self.bits_used / 8)
+            .map(|byte| byte & (1 << (self.bits_used % 8)) != 0)
+            .ok_or(MerkleProofError::Truncated)?;
+        self.bits_used += 1;
+        if height == 0 || !flag {
+            let hash =
+                *self.block.hashes.get(self.hashes_used).ok_or(MerkleProofError::Truncated)?;
+            self.hashes_used += 1;
+            if height == 0 && flag {
+                self.matched.push(Txid::from_byte_array(hash));
+            }
+            return Ok(hash);
+        }
+        let left = self.traverse(height - 1, pos * 2)?;
+        let right = if pos * 2 + 1 < self.block.tree_width(height - 1) {
+            let right = self.traverse(height - 1, pos * 2 + 1)?;
+            if right == left {
+                return Err(MerkleProofError::DuplicatedNodes);
+            }
+            right
+        } else {
+            left
+        };
+        let mut engine = Sha256::new();
+        engine.update(left);
+        engine.update(right);
+        Ok(Sha256::digest(engine.finalize()).into())
+    }
+}
+
+impl Display for MerkleBlock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut data = self.header.consensus_serialize();
+        data.extend(self.total_txs.to_le_bytes());
+        VarInt::with(self.hashes.len()).consensus_encode(&mut data).expect("in-memory writer");
+        self.hashes.iter().for_each(|hash| data.extend(hash));
+        VarInt::with(self.flags.len()).consensus_encode(&mut data).expect("in-memory writer");
+        data.extend(&self.flags);
+        f.write_str(&data.to_hex())
+    }
+}
+
+impl FromStr for MerkleBlock {
+    type Err = MerkleProofError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let data = Vec::<u8>::from_hex(s.trim())?;
+        let mut reader = Cursor::new(&data);
+        let header = BlockHeader::consensus_decode(&mut reader)?;
+        let total_txs = u32::consensus_decode(&mut reader)?;
+        let count = VarInt::consensus_decode(&mut reader)?.0;
+        let hashes = (0..count)
+            .map(|_| <[u8; 32]>::consensus_decode(&mut reader))
+            .collect::<Result<_, _>>()?;
+        let count = VarInt::consensus_decode(&mut reader)?.0;
+        let flags =
+            (0..count).map(|_| u8::consensus_decode(&mut reader)).collect::<Result<_, _>>()?;
+        if reader.position() as usize != data.len() {
+            return Err(MerkleProofError::DataNotConsumed);
+        }
+        Ok(MerkleBlock {
+            header,
+            total_txs,
+            hashes,
+            flags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{BlockHash, BlockMerkleRoot};
+
+    use super::*;
+
+    #[test]
+    fn test_merkle_block() {
+        let txid0 = [0x11u8; 32];
+        let txid1 = [0x22u8; 32];
+        let mut engine = Sha256::new();
+        engine.update(txid0);
+        engine.update(txid1);
+        let root: [u8; 32] = Sha256::digest(engine.finalize()).into();
+        let header = BlockHeader {
+            version: 1,
+            prev_block_hash: BlockHash::from([0u8; 32]),
+            merkle_root: BlockMerkleRoot::from(root),
+            time: 1_700_000_000,
+            bits: 0x1d00ffff,
+            nonce: 0,
+        };
+
+        // Root node and the first leaf are flagged, proving inclusion of the first transaction
+        let block = MerkleBlock {
+            header,
+            total_txs: 2,
+            hashes: vec![txid0, txid1],
+            flags: vec![0b011],
+        };
+        assert_eq!(block.verify().unwrap(), vec![Txid::from(txid0)]);
+        assert_eq!(MerkleBlock::from_str(&block.to_string()).unwrap(), block);
+
+        let mut forged = block.clone();
+        forged.hashes[1] = [0x33u8; 32];
+        assert_eq!(forged.verify(), Err(MerkleProofError::RootMismatch));
+
+        let mut duplicated = block.clone();
+        duplicated.hashes[1] = txid0;
+        assert_eq!(duplicated.verify(), Err(MerkleProofError::DuplicatedNodes));
+
+        let mut unused = block.clone();
+        unused.flags.push(0);
+        assert_eq!(unused.verify(), Err(MerkleProofError::Unused));
+
+        let single = MerkleBlock {
+            header: BlockHeader {
+                merkle_root: BlockMerkleRoot::from(txid1),
+                ..header
+            },
+            total_txs: 1,
+            hashes: vec![txid1],
+            flags: vec![0b1],
+        };
+        assert_eq!(single.verify().unwrap(), vec![Txid::from(txid1)]);
+    }
+}
@@ -0,0 +1,134 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Construction of transaction packages, where a child transaction spending the parent change
+//! (CPFP) is pre-built together with the parent.
+
+use bpstd::{Descriptor, Outpoint, Sats, SeqNo, Terminal, Vout};
+use psbt::{
+    Beneficiary, ConstructionError, Prevout, Psbt, PsbtConstructor, PsbtMeta, PsbtVer, TxParams,
+};
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PackageError {
+    /// {0}
+    #[from]
+    Construction(ConstructionError),
+
+    /// parent transaction doesn't have a change output which can be spent by the child
+    /// transaction.
+    NoChange,
+
+    /// child transaction fee {fee} leaves the change value {change} below the dust limit.
+    ChildDust { change: Sats, fee: Sats },
+}
+
+/// Metadata of a transaction package.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PackageMeta {
+    /// Metadata of the parent transaction.
+    pub parent: PsbtMeta,
+    /// Parent change output spent by the child transaction.
+    pub anchor: Outpoint,
+    /// Output of the child transaction returning funds to the wallet.
+    pub child_vout: Vout,
+    pub child_terminal: Terminal,
+    pub child_fee: Sats,
+}
+
+/// Constructor of transaction packages consisting of a parent paying to beneficiaries and a
+/// child spending the parent change with an additional fee (CPFP), which allows to boost the
+/// package fee rate without re-signing the parent.
+///
+/// The child spends the parent by its unsigned transaction id, thus the package is valid only if
+/// the parent id doesn't change on signing, i.e. if all the parent inputs are segwit.
+pub trait PackageConstructor: PsbtConstructor {
+    fn construct_package<'b>(
+        &mut self,
+        coins: impl IntoIterator<Item = Outpoint>,
+        beneficiaries: impl IntoIterator<Item = &'b Beneficiary>,
+        params: TxParams,
+        child_fee: Sats,
+    ) -> Result<(Psbt, Psbt, PackageMeta), PackageError> {
+        let (parent, parent_meta) = self.construct_psbt(coins, beneficiaries, params)?;
+        let (Some(vout), Some(terminal)) = (parent_meta.change_vout, parent_meta.change_terminal)
+        else {
+            return Err(PackageError::NoChange);
+        };
+        let change = parent
+            .outputs()
+            .find(|output| output.vout() == vout)
+            .expect("change output is present in the parent")
+            .amount;
+        let anchor = Outpoint::new(parent.txid(), vout);
+
+        let remaining = change
+            .checked_sub(child_fee)
+            .filter(|value| *value > self.descriptor().class().dust_limit())
+            .ok_or(PackageError::ChildDust {
+                change,
+                fee: child_fee,
+            })?;
+
+        let child_index = self.next_derivation_index(params.change_keychain, true);
+        let child_terminal = Terminal::new(params.change_keychain, child_index);
+        let child = child_psbt(
+            self.descriptor(),
+            Prevout::new(anchor, change),
+            terminal,
+            child_terminal,
+            remaining,
+            params.seq_no,
+        );
+        let child_vout = Vout::from_u32(0);
+
+        Ok((parent, child, PackageMeta {
+            parent: parent_meta,
+            anchor,
+            child_vout,
+            child_terminal,
+            child_fee,
+        }))
+    }
+}
+
+impl<T: PsbtConstructor> PackageConstructor for T {}
+
+/// Constructs PSBT of a child transaction spending a single wallet output and returning the
+/// value less the fee to the change terminal, which is the only output of the child.
+pub(crate) fn child_psbt<K, D: Descriptor<K>>(
+    descriptor: &D,
+    prevout: Prevout,
+    terminal: Terminal,
+    change_terminal: Terminal,
+    change: Sats,
+    seq_no: SeqNo,
+) -> Psbt {
+    let mut psbt = Psbt::create(PsbtVer::V2);
+    for spec in descriptor.xpubs() {
+        psbt.xpubs.insert(*spec.xpub(), spec.origin().clone());
+    }
+    psbt.construct_input_expect(prevout, descriptor, terminal, seq_no);
+    psbt.construct_change_expect(descriptor, change_terminal, change);
+    psbt
+}
@@ -0,0 +1,417 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Payment recipients, including human-readable payment names (BIP-353).
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bpstd::Address;
+use psbt::{Beneficiary, Payment};
+
+#[cfg(feature = "payment-resolvers")]
+pub use self::resolvers::{
+    PaymentResolver, ResolutionSource, ResolveError, ResolvedPayment, DEFAULT_DOH_RESOLVER,
+    WELL_KNOWN_PATH,
+};
+use crate::{parse_address, parse_beneficiary, parse_payment, PaymentParseError, Share};
+
+fn is_label(label: &str) -> bool {
+    !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Human-readable bitcoin payment name in form of `user@domain`, as defined in BIP-353.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HumanReadableName {
+    pub user: String,
+    pub domain: String,
+}
+
+/// invalid human-readable payment name '{0}'; it must have form of `user@domain.tld`.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidName(String);
+
+impl HumanReadableName {
+    /// Domain name holding the payment instructions DNS record.
+    pub fn dns_name(&self) -> String {
+        format!("{}.user._bitcoin-payment.{}", self.user, self.domain)
+    }
+}
+
+impl Display for HumanReadableName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "₿{}@{}", self.user, self.domain)
+    }
+}
+
+impl FromStr for HumanReadableName {
+    type Err = InvalidName;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || InvalidName(s.to_owned());
+        let name = s.strip_prefix('₿').unwrap_or(s);
+        let (user, domain) = name.split_once('@').ok_or_else(err)?;
+        if !is_label(user) || !domain.contains('.') || !domain.split('.').all(is_label) {
+            return Err(err());
+        }
+        Ok(HumanReadableName {
+            user: user.to_ascii_lowercase(),
+            domain: domain.to_ascii_lowercase(),
+        })
+    }
+}
+
+/// Payment recipient, which is either given by an address or by a human-readable name, which
+/// has to be resolved into an address before the payment.
+#[derive(Clone, Eq, PartialEq, Debug, Display)]
+pub enum Recipient {
+    #[display(inner)]
+    Beneficiary(Beneficiary),
+
+    /// Human-readable name with an optional payment amount. If the amount is not given, it must
+    /// be provided by the resolved payment instructions.
+    #[display("{0}")]
+    Name(HumanReadableName, Option<Payment>),
+
+    /// Address paid with a weighted or percentage share of the balance.
+    #[display("{0}@{1}")]
+    Share(Share, Address),
+
+    /// Wallet contact, which has to be looked up in the wallet data, with the payment amount.
+    #[display("{0}")]
+    Contact(String, Payment),
+}
+
+impl Recipient {
+    pub fn as_beneficiary(&self) -> Option<&Beneficiary> {
+        match self {
+            Recipient::Beneficiary(beneficiary) => Some(beneficiary),
+            Recipient::Name(..) | Recipient::Share(..) | Recipient::Contact(..) => None,
+        }
+    }
+}
+
+/// Parses payment recipient, which is either `<amount>@<address>`, `<share>@<address>`, a
+/// human-readable name in form of `user@domain.tld[:<amount>]` or a wallet contact in form of
+/// `<contact>:<amount>`. Can be used as a `clap` value parser.
+pub fn parse_recipient(s: &str) -> Result<Recipient, PaymentParseError> {
+    let err = match parse_beneficiary(s) {
+        Ok(beneficiary) => return Ok(Recipient::Beneficiary(beneficiary)),
+        Err(err) => err,
+    };
+    if let Some((share, address)) = s.rsplit_once('@') {
+        if let (Ok(share), Ok(address)) = (Share::from_str(share), parse_address(address)) {
+            return Ok(Recipient::Share(share, address));
+        }
+    }
+    let (name, amount) = match s.rsplit_once(':') {
+        Some((name, amount)) => (name, Some(amount)),
+        None => (s, None),
+    };
+    let Ok(name) = HumanReadableName::from_str(name) else {
+        return match amount {
+            Some(amount) if is_label(name) => {
+                Ok(Recipient::Contact(name.to_owned(), parse_payment(amount)?))
+            }
+            _ => Err(err),
+        };
+    };
+    let amount = amount.map(parse_payment).transpose()?;
+    Ok(Recipient::Name(name, amount))
+}
+
+#[cfg(feature = "payment-resolvers")]
+mod resolvers {
+    use bpstd::{Address, AddressNetwork, Network, Sats};
+    use esplora::BlockingClient;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::Amount;
+
+    /// Path of the HTTPS endpoint on the name domain used as a fallback when DNS doesn't have
+    /// the payment instructions. The endpoint must return a `bitcoin:` URI as a plain text.
+    pub const WELL_KNOWN_PATH: &str = ".well-known/bitcoin-payment";
+
+    /// DNS-over-HTTPS resolver used by default.
+    pub const DEFAULT_DOH_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+
+    const DNS_TYPE_TXT: u64 = 16;
+
+    #[allow(clippy::large_enum_variant)]
+    #[derive(Debug, Display, Error, From)]
+    #[display(doc_comments)]
+    pub enum ResolveError {
+        /// payment name resolution request failed with {0}
+        #[from]
+        Http(esplora::Error),
+
+        /// DNS resolver returned error status {1} for {0}.
+        DnsStatus(HumanReadableName, u64),
+
+        /// DNS records for {0} are not DNSSEC-validated.
+        NotAuthenticated(HumanReadableName),
+
+        /// no payment instructions found for {0}.
+        NotFound(HumanReadableName),
+
+        /// multiple payment instructions found for {0}, which is prohibited by BIP-353.
+        Ambiguous(HumanReadableName),
+
+        /// payment instructions '{1}' for {0} is not a valid bitcoin URI.
+        InvalidUri(HumanReadableName, String),
+
+        /// payment instructions for {0} do not contain on-chain address.
+        NoAddress(HumanReadableName),
+
+        /// address {1} for {0} belongs to a different network.
+        NetworkMismatch(HumanReadableName, Address),
+
+        /// neither payment amount nor payment instructions for {0} provide the amount to pay.
+        NoAmount(HumanReadableName),
+    }
+
+    /// Way the payment instructions were obtained.
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    pub enum ResolutionSource {
+        #[display("DNSSEC-validated DNS record")]
+        Dnssec,
+
+        #[display("HTTPS well-known endpoint (not DNSSEC-validated)")]
+        WellKnown,
+    }
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    pub struct ResolvedPayment {
+        pub name: HumanReadableName,
+        pub uri: String,
+        pub address: Address,
+        pub amount: Option<Sats>,
+        pub source: ResolutionSource,
+    }
+
+    impl ResolvedPayment {
+        /// Constructs beneficiary, using the given payment amount or, if absent, the amount from
+        /// the payment instructions.
+        pub fn to_beneficiary(&self, amount: Option<Payment>) -> Result<Beneficiary, ResolveError> {
+            let amount = amount
+                .or(self.amount.map(Payment::Fixed))
+                .ok_or_else(|| ResolveError::NoAmount(self.name.clone()))?;
+            Ok(Beneficiary::new(self.address, amount))
+        }
+    }
+
+    /// Resolver of human-readable payment names using DNS-over-HTTPS.
+    pub struct PaymentResolver {
+        doh: BlockingClient,
+        well_known: bool,
+    }
+
+    impl PaymentResolver {
+        /// Constructs resolver using a DNS-over-HTTPS resolver supporting JSON API. If
+        /// `well_known` is set, names which don't have DNS records are looked up at the
+        /// [`WELL_KNOWN_PATH`] endpoint of the name domain.
+        #[allow(clippy::result_large_err)]
+        pub fn new(doh_url: &str, well_known: bool) -> Result<Self, esplora::Error> {
+            let doh = esplora::Builder::new(doh_url).build_blocking()?;
+            Ok(Self { doh, well_known })
+        }
+
+        pub fn doh_url(&self) -> &str { self.doh.url() }
+
+        pub fn resolve(
+            &self,
+            name: &HumanReadableName,
+            network: Network,
+        ) -> Result<ResolvedPayment, ResolveError> {
+            let (uri, source) = match self.resolve_dns(name)? {
+                Some(uri) => (uri, ResolutionSource::Dnssec),
+                None if self.well_known => {
+                    (self.resolve_well_known(name)?, ResolutionSource::WellKnown)
+                }
+                None => return Err(ResolveError::NotFound(name.clone())),
+            };
+            let (address, amount) = parse_uri(&uri)
+                .ok_or_else(|| ResolveError::InvalidUri(name.clone(), uri.clone()))?;
+            let address = address.ok_or_else(|| ResolveError::NoAddress(name.clone()))?;
+            if address.network != AddressNetwork::from(network) {
+                return Err(ResolveError::NetworkMismatch(name.clone(), address));
+            }
+            Ok(ResolvedPayment {
+                name: name.clone(),
+                uri,
+                address,
+                amount,
+                source,
+            })
+        }
+
+        fn resolve_dns(&self, name: &HumanReadableName) -> Result<Option<String>, ResolveError> {
+            let url = format!("{}?name={}&type=TXT", self.doh.url(), name.dns_name());
+            let resp: Value = self
+                .doh
+                .agent()
+                .get(&url)
+                .set("accept", "application/dns-json")
+                .call()
+                .map_err(esplora::Error::from)?
+                .into_json()
+                .map_err(esplora::Error::from)?;
+            match resp.get("Status").and_then(Value::as_u64) {
+                Some(0) => {}
+                // NXDOMAIN
+                Some(3) => return Ok(None),
+                status => {
+                    return Err(ResolveError::DnsStatus(name.clone(), status.unwrap_or(u64::MAX)))
+                }
+            }
+            let mut uris = resp
+                .get("Answer")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter(|answer| answer.get("type").and_then(Value::as_u64) == Some(DNS_TYPE_TXT))
+                .filter_map(|answer| answer.get("data").and_then(Value::as_str))
+                .map(txt_data)
+                .filter(|txt| txt.to_ascii_lowercase().starts_with("bitcoin:"));
+            let Some(uri) = uris.next() else {
+                return Ok(None);
+            };
+            if uris.next().is_some() {
+                return Err(ResolveError::Ambiguous(name.clone()));
+            }
+            if resp.get("AD").and_then(Value::as_bool) != Some(true) {
+                return Err(ResolveError::NotAuthenticated(name.clone()));
+            }
+            Ok(Some(uri))
+        }
+
+        fn resolve_well_known(&self, name: &HumanReadableName) -> Result<String, ResolveError> {
+            let url = format!("https://{}/{WELL_KNOWN_PATH}/{}", name.domain, name.user);
+            let uri = self
+                .doh
+                .agent()
+                .get(&url)
+                .call()
+                .map_err(esplora::Error::from)?
+                .into_string()
+                .map_err(esplora::Error::from)?;
+            let uri = uri.trim();
+            if !uri.to_ascii_lowercase().starts_with("bitcoin:") {
+                return Err(ResolveError::NotFound(name.clone()));
+            }
+            Ok(uri.to_owned())
+        }
+    }
+
+    /// Joins TXT record character strings, which are quoted and may be split into chunks.
+    fn txt_data(data: &str) -> String {
+        data.split('"').skip(1).step_by(2).collect::<Vec<_>>().concat()
+    }
+
+    /// Parses BIP-21 URI, returning address (if present) and amount (if present).
+    pub(super) fn parse_uri(uri: &str) -> Option<(Option<Address>, Option<Sats>)> {
+        let (scheme, rest) = uri.split_once(':')?;
+        if !scheme.eq_ignore_ascii_case("bitcoin") {
+            return None;
+        }
+        let (address, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let address = match address {
+            "" => None,
+            s => Some(parse_address(s).ok()?),
+        };
+        let amount = query
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| key.eq_ignore_ascii_case("amount"))
+            .map(|(_, btc)| Amount::from_str(&format!("{btc}btc")).map(Amount::sats))
+            .transpose()
+            .ok()?;
+        Some((address, amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_str_round_trip() {
+        let name = HumanReadableName::from_str("₿Satoshi@Example.com").unwrap();
+        assert_eq!(name.dns_name(), "satoshi.user._bitcoin-payment.example.com");
+        assert_eq!(HumanReadableName::from_str(&name.to_string()).unwrap(), name);
+        assert!(HumanReadableName::from_str("satoshi@localhost").is_err());
+        assert!(HumanReadableName::from_str("@example.com").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "payment-resolvers")]
+    fn test_parse_uri() {
+        use bpstd::Sats;
+
+        use super::resolvers::parse_uri;
+
+        for address in ["1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", "3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy"]
+        {
+            let (parsed, amount) = parse_uri(&format!("bitcoin:{address}?amount=0.5")).unwrap();
+            assert_eq!(parsed, Some(Address::from_str(address).unwrap()));
+            assert_eq!(amount, Some(Sats(50_000_000)));
+        }
+        let (parsed, amount) =
+            parse_uri("BITCOIN:BC1QAR0SRRR7XFKVY5L643LYDNW9RE59GTZZWF5MDQ").unwrap();
+        assert_eq!(
+            parsed,
+            Some(Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap())
+        );
+        assert_eq!(amount, None);
+        assert!(parse_uri("bitcoin:1A1ZP1EP5QGEFI2DMPTFTL5SLMV7DIVFNA").is_none());
+    }
+
+    #[test]
+    fn test_parse_recipient() {
+        assert!(matches!(
+            parse_recipient("1000@bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"),
+            Ok(Recipient::Beneficiary(_))
+        ));
+        assert!(matches!(
+            parse_recipient("satoshi@example.com:0.5btc"),
+            Ok(Recipient::Name(_, Some(Payment::Fixed(_))))
+        ));
+        assert!(matches!(parse_recipient("satoshi@example.com"), Ok(Recipient::Name(_, None))));
+        assert!(parse_recipient("satoshi@example.com:1.5eur").is_err());
+        assert!(matches!(
+            parse_recipient("MAX*2@bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"),
+            Ok(Recipient::Share(Share::Weight(2), _))
+        ));
+        assert!(matches!(
+            parse_recipient("50%@bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"),
+            Ok(Recipient::Share(Share::Percent(50), _))
+        ));
+        assert!(parse_recipient("1000@invalid").is_err());
+        assert!(matches!(
+            parse_recipient("alice:0.5btc"),
+            Ok(Recipient::Contact(name, Payment::Fixed(_))) if name == "alice"
+        ));
+        assert!(parse_recipient("alice").is_err());
+    }
+}
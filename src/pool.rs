@@ -0,0 +1,315 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Runtime managing multiple wallets for servers and daemons which embed the library, sharing
+//! indexer clients between the wallets and syncing them in background.
+
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use descriptors::Descriptor;
+
+use crate::{Indexer, JournalEntry, Layer2, NoLayer2, SyncReport, TxStore, Wallet};
+
+/// Granularity at which the background sync scheduler checks for due wallets and stop requests.
+const SCHEDULER_TICK: Duration = Duration::from_millis(250);
+
+/// Event of a wallet managed by a [`WalletPool`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum PoolEvent {
+    /// Wallet was synced, updating the given number of items.
+    Synced { wallet: String, updated: usize },
+    /// Some of the indexer requests of a wallet sync have failed.
+    SyncFailed { wallet: String, errors: Vec<String> },
+    /// Event recorded into the wallet journal by a sync.
+    Journal { wallet: String, entry: JournalEntry },
+}
+
+/// Wallet managed by a pool, together with its sync state. Locked as a whole, such that
+/// different wallets can be used and synced concurrently.
+pub struct PoolSlot<I, K, D: Descriptor<K>, L2: Layer2> {
+    pub wallet: Wallet<K, D, L2>,
+    /// Indexer client used to sync the wallet, which may be shared with other wallets.
+    pub indexer: Arc<I>,
+    /// Transactions downloaded by the previous syncs of the wallet.
+    pub store: TxStore,
+    /// Sequence number of the last journal event emitted to the pool subscribers.
+    journal_seq: u64,
+    /// Time of the next background sync.
+    next_sync: Instant,
+}
+
+/// Pool slot shared between the pool and the threads operating the wallet.
+type SharedSlot<I, K, D, L2> = Arc<Mutex<PoolSlot<I, K, D, L2>>>;
+
+struct PoolInner<I, K, D: Descriptor<K>, L2: Layer2> {
+    wallets: RwLock<BTreeMap<String, SharedSlot<I, K, D, L2>>>,
+    indexers: Mutex<BTreeMap<String, Arc<I>>>,
+    subscribers: Mutex<Vec<Sender<PoolEvent>>>,
+}
+
+/// Collection of wallets with shared indexer clients, per-wallet locking, background sync and a
+/// unified stream of the wallet events.
+///
+/// The pool is a cheaply cloneable handle, which can be passed to the server threads.
+pub struct WalletPool<I: Indexer, K, D: Descriptor<K>, L2: Layer2 = NoLayer2> {
+    inner: Arc<PoolInner<I, K, D, L2>>,
+}
+
+impl<I: Indexer, K, D: Descriptor<K>, L2: Layer2> Clone for WalletPool<I, K, D, L2> {
+    fn clone(&self) -> Self {
+        WalletPool {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<I: Indexer, K, D: Descriptor<K>, L2: Layer2> Default for WalletPool<I, K, D, L2> {
+    fn default() -> Self {
+        WalletPool {
+            inner: Arc::new(PoolInner {
+                wallets: default!(),
+                indexers: default!(),
+                subscribers: default!(),
+            }),
+        }
+    }
+}
+
+impl<I: Indexer, K, D: Descriptor<K>, L2: Layer2> WalletPool<I, K, D, L2> {
+    pub fn new() -> Self { Self::default() }
+
+    /// Registers indexer client under the given key (like the server URL), returning the
+    /// client already registered under the key, if any, such that wallets using the same server
+    /// share a single client.
+    pub fn add_indexer(&self, key: impl Into<String>, indexer: I) -> Arc<I> {
+        let mut indexers = lock(&self.inner.indexers);
+        indexers.entry(key.into()).or_insert_with(|| Arc::new(indexer)).clone()
+    }
+
+    /// Returns indexer client registered under the given key.
+    pub fn indexer(&self, key: &str) -> Option<Arc<I>> {
+        lock(&self.inner.indexers).get(key).cloned()
+    }
+
+    /// Adds wallet to the pool under the given name, replacing the wallet with the same name.
+    /// Events already present in the wallet journal are not emitted to the subscribers.
+    pub fn insert(&self, name: impl Into<String>, wallet: Wallet<K, D, L2>, indexer: Arc<I>) {
+        let journal_seq = wallet.events_since(0).last().map(|entry| entry.seq).unwrap_or_default();
+        let slot = PoolSlot {
+            wallet,
+            indexer,
+            store: TxStore::default(),
+            journal_seq,
+            next_sync: Instant::now(),
+        };
+        let mut wallets = self.inner.wallets.write().unwrap_or_else(|err| err.into_inner());
+        wallets.insert(name.into(), Arc::new(Mutex::new(slot)));
+    }
+
+    /// Removes wallet from the pool, returning whether it was present. The wallet is dropped
+    /// (and saved, if it has autosave enabled) once it is not used by other threads anymore.
+    pub fn remove(&self, name: &str) -> bool {
+        let mut wallets = self.inner.wallets.write().unwrap_or_else(|err| err.into_inner());
+        wallets.remove(name).is_some()
+    }
+
+    /// Names of the wallets in the pool.
+    pub fn names(&self) -> Vec<String> {
+        let wallets = self.inner.wallets.read().unwrap_or_else(|err| err.into_inner());
+        wallets.keys().cloned().collect()
+    }
+
+    /// Locks the wallet for exclusive use, blocking while it is used or synced by other threads.
+    /// Returns `None` if the pool has no wallet with the given name.
+    pub fn with_wallet<R>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut PoolSlot<I, K, D, L2>) -> R,
+    ) -> Option<R> {
+        let slot = self.slot(name)?;
+        let mut slot = lock(&slot);
+        Some(f(&mut slot))
+    }
+
+    /// Subscribes to the events of all the pool wallets. Subscribers which have dropped their
+    /// receivers are removed on the next event.
+    pub fn subscribe(&self) -> Receiver<PoolEvent> {
+        let (sender, receiver) = mpsc::channel();
+        lock(&self.inner.subscribers).push(sender);
+        receiver
+    }
+
+    /// Syncs the wallet with its indexer, emitting the sync events to the subscribers. Returns
+    /// `None` if the pool has no wallet with the given name.
+    pub fn sync(&self, name: &str) -> Option<SyncReport>
+    where I::Error: Display {
+        let slot = self.slot(name)?;
+        let mut slot = lock(&slot);
+        let slot = &mut *slot;
+        let (report, errors) =
+            slot.wallet.update_with_store(&*slot.indexer, &mut slot.store).split();
+
+        let mut events = Vec::new();
+        if let Some(errors) = errors {
+            let errors = errors.iter().map(|err| err.to_string()).collect();
+            events.push(PoolEvent::SyncFailed {
+                wallet: name.to_owned(),
+                errors,
+            });
+        }
+        events.push(PoolEvent::Synced {
+            wallet: name.to_owned(),
+            updated: report.updated,
+        });
+        for entry in slot.wallet.events_since(slot.journal_seq) {
+            slot.journal_seq = entry.seq;
+            events.push(PoolEvent::Journal {
+                wallet: name.to_owned(),
+                entry: *entry,
+            });
+        }
+        self.emit(events);
+        Some(report)
+    }
+
+    /// Syncs all the pool wallets one by one.
+    pub fn sync_all(&self)
+    where I::Error: Display {
+        for name in self.names() {
+            self.sync(&name);
+        }
+    }
+
+    /// Starts syncing the pool wallets in background, each wallet once per `interval` extended
+    /// by a random delay of up to `jitter`, such that the syncs of many wallets don't hit the
+    /// indexers at the same moment. Due wallets are synced concurrently.
+    ///
+    /// The syncs are stopped when the returned scheduler is stopped or dropped.
+    pub fn spawn_sync(&self, interval: Duration, jitter: Duration) -> PoolScheduler
+    where
+        Self: Send + Sync + 'static,
+        I::Error: Display,
+    {
+        let stop = Arc::new(AtomicBool::new(false));
+        let pool = self.clone();
+        let stop_flag = stop.clone();
+        let handle = thread::spawn(move || {
+            while !stop_flag.load(Ordering::Relaxed) {
+                let now = Instant::now();
+                let due = pool
+                    .names()
+                    .into_iter()
+                    .filter(|name| {
+                        pool.with_wallet(name, |slot| {
+                            let due = slot.next_sync <= now;
+                            if due {
+                                slot.next_sync = now + interval + random_delay(jitter);
+                            }
+                            due
+                        })
+                        .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>();
+                thread::scope(|scope| {
+                    for name in &due {
+                        let pool = &pool;
+                        scope.spawn(move || pool.sync(name));
+                    }
+                });
+                thread::sleep(SCHEDULER_TICK);
+            }
+        });
+        PoolScheduler {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    fn slot(&self, name: &str) -> Option<SharedSlot<I, K, D, L2>> {
+        let wallets = self.inner.wallets.read().unwrap_or_else(|err| err.into_inner());
+        wallets.get(name).cloned()
+    }
+
+    fn emit(&self, events: Vec<PoolEvent>) {
+        let mut subscribers = lock(&self.inner.subscribers);
+        subscribers
+            .retain(|subscriber| events.iter().all(|event| subscriber.send(event.clone()).is_ok()));
+    }
+}
+
+/// Background sync of the pool wallets started with [`WalletPool::spawn_sync`].
+pub struct PoolScheduler {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl PoolScheduler {
+    /// Stops the background sync, waiting for the syncs in progress to complete.
+    pub fn stop(mut self) { self.shutdown(); }
+
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            // Panics of the wallet syncs are not propagated into the thread stopping the sync
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for PoolScheduler {
+    fn drop(&mut self) { self.shutdown(); }
+}
+
+/// Locks the mutex, ignoring poisoning by a panicked thread, since the pool data remain
+/// consistent between the operations.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn random_delay(max: Duration) -> Duration {
+    let millis = max.as_millis() as u64;
+    if millis == 0 {
+        return Duration::ZERO;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (millis + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_delay() {
+        assert_eq!(random_delay(Duration::ZERO), Duration::ZERO);
+        let max = Duration::from_secs(10);
+        assert!((0..100).all(|_| random_delay(max) <= max));
+    }
+}
@@ -0,0 +1,154 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Preview of a payment from the wallet funds, computed without constructing a PSBT, which gives
+//! user interfaces instant feedback while the payment details are typed in.
+
+use bpstd::{Descriptor, Idx, Keychain, NormalIndex, Sats, SpkClass};
+use psbt::{Beneficiary, Payment, PsbtConstructor};
+
+use crate::{satisfaction_weight, FeeRate, Layer2, Wallet, WalletUtxo};
+
+/// Outcome of [`Wallet::simulate_spend`].
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SpendPreview {
+    /// Coins selected for spending, in the order of the selection.
+    pub selected_utxos: Vec<WalletUtxo>,
+    /// Fee of the transaction. When the change would be below the dust limit, it is left to the
+    /// miners and is included into the fee.
+    pub fee: Sats,
+    /// Change returned to the wallet.
+    pub change: Sats,
+    /// Wallet balance after the payment, including the change.
+    pub balance: Sats,
+    /// Amount missing to cover the payments and the fee, if the spendable coins are
+    /// insufficient.
+    pub insufficient: Option<Sats>,
+}
+
+/// Length of a compact size encoding of the number.
+fn var_int_len(n: usize) -> u32 {
+    match n {
+        0..=0xFC => 1,
+        0xFD..=0xFFFF => 3,
+        _ => 5,
+    }
+}
+
+/// Weight of a transaction output with the script of the given length.
+fn output_weight(script_len: usize) -> u32 { (8 + var_int_len(script_len) + script_len as u32) * 4 }
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Simulates a payment to the beneficiaries at the given fee rate, running the coin selection
+    /// and the fee estimation of [`crate::FeeDeductor::estimate_weight`] on the spendable
+    /// coins.
+    ///
+    /// Unlike the PSBT construction, the simulation neither creates PSBT objects nor shifts the
+    /// derivation indexes, so it can be repeated on each change of the payment details. If any of
+    /// the beneficiaries is paid with [`Payment::Max`], all the spendable coins are selected and
+    /// the transaction has no change.
+    pub fn simulate_spend(&self, beneficiaries: &[Beneficiary], fee_rate: FeeRate) -> SpendPreview {
+        let class = self.descriptor().class();
+        let change_script_len = self
+            .descriptor()
+            .derive_address(self.network().into(), Keychain::INNER, NormalIndex::ZERO)
+            .map(|addr| addr.script_pubkey().as_slice().len())
+            .unwrap_or_default();
+        let max = beneficiaries.iter().any(|b| matches!(b.amount, Payment::Max));
+        let amount = beneficiaries
+            .iter()
+            .filter_map(|b| match b.amount {
+                Payment::Fixed(sats) => Some(sats),
+                Payment::Max => None,
+            })
+            .sum::<Sats>();
+        let payments_weight = beneficiaries
+            .iter()
+            .map(|b| output_weight(b.address.script_pubkey().as_slice().len()))
+            .sum::<u32>();
+        let fee_for = |inputs: usize, change: bool| {
+            let outputs = beneficiaries.len() + change as usize;
+            let mut weight = (4 + var_int_len(inputs) + var_int_len(outputs) + 4) * 4
+                + ((32 + 4 + 1 + 4) * 4 + satisfaction_weight(class)) * inputs as u32
+                + payments_weight;
+            if change {
+                weight += output_weight(change_script_len);
+            }
+            if matches!(class, SpkClass::P2wpkh | SpkClass::P2wsh | SpkClass::P2tr) {
+                // segwit marker and flag
+                weight += 2;
+            }
+            fee_rate.fee_for_weight(weight)
+        };
+
+        let mut selected_utxos = vec![];
+        let mut value = Sats::ZERO;
+        for utxo in self.spendable_utxos(self.min_confirmations()) {
+            if !max && value >= amount + fee_for(selected_utxos.len(), true) {
+                break;
+            }
+            value += utxo.value;
+            selected_utxos.push(utxo);
+        }
+
+        let inputs = selected_utxos.len();
+        let dust_limit = class.dust_limit();
+        let (fee, change) = match value.checked_sub(amount + fee_for(inputs, true)) {
+            Some(change) if !max && change > dust_limit => (fee_for(inputs, true), change),
+            // The change below the dust limit is left to the miners
+            _ if !max && value > amount + fee_for(inputs, false) => (value - amount, Sats::ZERO),
+            _ => (fee_for(inputs, false), Sats::ZERO),
+        };
+        let insufficient =
+            (amount + fee).checked_sub(value).filter(|missing| *missing > Sats::ZERO);
+        SpendPreview {
+            balance: self.balance() - value + change,
+            selected_utxos,
+            fee,
+            change,
+            insufficient,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{test_payee, test_wallet};
+
+    #[test]
+    fn test_simulate_spend_insufficient() {
+        let wallet = test_wallet();
+        let address = test_payee();
+        let fee_rate = FeeRate::from_sat_per_vb(2);
+
+        let preview = wallet.simulate_spend(&[Beneficiary::new(address, Sats(10_000))], fee_rate);
+        assert!(preview.selected_utxos.is_empty());
+        assert_eq!(preview.change, Sats::ZERO);
+        assert_eq!(preview.balance, Sats::ZERO);
+        assert_eq!(preview.insufficient, Some(Sats(10_000) + preview.fee));
+        assert!(preview.fee > Sats::ZERO);
+
+        let preview = wallet.simulate_spend(&[Beneficiary::with_max(address)], fee_rate);
+        assert_eq!(preview.insufficient, Some(preview.fee));
+    }
+}
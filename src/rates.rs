@@ -0,0 +1,266 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Fiat exchange rates used for displaying wallet amounts in fiat currencies.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use bpstd::Sats;
+use esplora::BlockingClient;
+use nonasync::persistence::PersistenceError;
+use serde_json::Value;
+
+/// Public mempool.space API endpoint providing exchange rates.
+pub const MEMPOOL_PRICE_API: &str = "https://mempool.space/api";
+
+/// Granularity of the historical rates, in seconds.
+pub const RATE_PERIOD: u64 = 3600;
+
+/// Fiat currency code in ISO 4217 format (like `USD` or `EUR`).
+#[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", try_from = "String", into = "String")]
+#[display(inner)]
+pub struct Currency(String);
+
+/// invalid fiat currency code '{0}'; it must consist of three latin letters.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidCurrency(String);
+
+impl FromStr for Currency {
+    type Err = InvalidCurrency;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 3 || !s.chars().all(|c| c.is_ascii_alphabetic()) {
+            return Err(InvalidCurrency(s.to_owned()));
+        }
+        Ok(Currency(s.to_ascii_uppercase()))
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = InvalidCurrency;
+    fn try_from(s: String) -> Result<Self, Self::Error> { Currency::from_str(&s) }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> Self { currency.0 }
+}
+
+impl Currency {
+    /// Converts satoshi amount into the fiat value using the rate of a single bitcoin.
+    pub fn convert(sats: Sats, rate: f64) -> f64 { sats.sats() as f64 * rate / Sats::BTC.0 as f64 }
+}
+
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum RatesError {
+    /// exchange rate provider failed with {0}
+    #[from]
+    Esplora(esplora::Error),
+
+    /// exchange rate provider doesn't know currency {0}.
+    UnknownCurrency(Currency),
+
+    /// exchange rate for {0} at {1} is not known.
+    NoRate(Currency, u64),
+}
+
+/// Source of bitcoin exchange rates, expressed as a value of a single bitcoin in a fiat currency.
+pub trait RateProvider {
+    /// Current exchange rate.
+    fn current_rate(&self, currency: &Currency) -> Result<f64, RatesError>;
+
+    /// Exchange rate at the given UNIX timestamp (for instance, a block mining time).
+    fn historical_rate(&self, currency: &Currency, timestamp: u64) -> Result<f64, RatesError>;
+}
+
+/// Rate provider using mempool.space price API.
+pub struct MempoolRates {
+    inner: BlockingClient,
+}
+
+impl MempoolRates {
+    #[allow(clippy::result_large_err)]
+    pub fn new(url: &str) -> Result<Self, esplora::Error> {
+        let inner = esplora::Builder::new(url).build_blocking()?;
+        Ok(Self { inner })
+    }
+
+    fn get(&self, path: &str) -> Result<Value, esplora::Error> {
+        let url = format!("{}{path}", self.inner.url());
+        let resp = self.inner.agent().get(&url).call()?.into_json()?;
+        Ok(resp)
+    }
+}
+
+impl RateProvider for MempoolRates {
+    fn current_rate(&self, currency: &Currency) -> Result<f64, RatesError> {
+        self.get("/v1/prices")?
+            .get(&currency.0)
+            .and_then(Value::as_f64)
+            .ok_or_else(|| RatesError::UnknownCurrency(currency.clone()))
+    }
+
+    fn historical_rate(&self, currency: &Currency, timestamp: u64) -> Result<f64, RatesError> {
+        self.get(&format!("/v1/historical-price?currency={currency}&timestamp={timestamp}"))?
+            .get("prices")
+            .and_then(Value::as_array)
+            .and_then(|prices| prices.first())
+            .and_then(|price| price.get(&currency.0))
+            .and_then(Value::as_f64)
+            .filter(|rate| *rate > 0.0)
+            .ok_or_else(|| RatesError::NoRate(currency.clone(), timestamp))
+    }
+}
+
+/// Rate provider using rates from a static TOML file.
+///
+/// The file has a `current` table mapping currency codes to the current rates, and optional
+/// `historical` table mapping currency codes to a table of UNIX timestamps and rates. For the
+/// historical requests the rate with the closest preceding timestamp is used.
+#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct StaticRates {
+    #[serde(default)]
+    pub current: BTreeMap<Currency, f64>,
+    #[serde(default)]
+    pub historical: BTreeMap<Currency, BTreeMap<String, f64>>,
+}
+
+impl StaticRates {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PersistenceError> {
+        let s = fs::read_to_string(path).map_err(PersistenceError::with)?;
+        toml::from_str(&s).map_err(PersistenceError::with)
+    }
+}
+
+impl RateProvider for StaticRates {
+    fn current_rate(&self, currency: &Currency) -> Result<f64, RatesError> {
+        self.current
+            .get(currency)
+            .copied()
+            .ok_or_else(|| RatesError::UnknownCurrency(currency.clone()))
+    }
+
+    fn historical_rate(&self, currency: &Currency, timestamp: u64) -> Result<f64, RatesError> {
+        self.historical
+            .get(currency)
+            .ok_or_else(|| RatesError::UnknownCurrency(currency.clone()))?
+            .iter()
+            .filter_map(|(ts, rate)| Some((u64::from_str(ts).ok()?, *rate)))
+            .filter(|(ts, _)| *ts <= timestamp)
+            .max_by_key(|(ts, _)| *ts)
+            .map(|(_, rate)| rate)
+            .ok_or_else(|| RatesError::NoRate(currency.clone(), timestamp))
+    }
+}
+
+/// Historical exchange rates persisted per wallet, so the historical valuation doesn't
+/// require repeated requests to the rate provider.
+#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub struct RatesCache {
+    pub historical: BTreeMap<Currency, BTreeMap<u64, f64>>,
+}
+
+impl RatesCache {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, PersistenceError> {
+        let file = fs::File::open(path).map_err(PersistenceError::with)?;
+        serde_yaml::from_reader(file).map_err(PersistenceError::with)
+    }
+
+    pub fn store(&self, path: impl AsRef<Path>) -> Result<(), PersistenceError> {
+        let file = fs::File::create(path).map_err(PersistenceError::with)?;
+        serde_yaml::to_writer(file, self).map_err(PersistenceError::with)
+    }
+}
+
+/// Rate provider caching historical rates from some other provider. Current rates are not
+/// cached.
+pub struct CachedRates<P: RateProvider> {
+    provider: P,
+    cache: RefCell<RatesCache>,
+    path: Option<PathBuf>,
+}
+
+impl<P: RateProvider> CachedRates<P> {
+    /// Constructs provider with the cache persisted to the given file. If the file doesn't exist
+    /// or can't be read, starts with an empty cache.
+    pub fn with(provider: P, path: Option<PathBuf>) -> Self {
+        let cache = path.as_ref().and_then(|path| RatesCache::load(path).ok()).unwrap_or_default();
+        Self {
+            provider,
+            cache: RefCell::new(cache),
+            path,
+        }
+    }
+
+    /// Saves cached rates, if the cache file was provided.
+    pub fn store(&self) -> Result<(), PersistenceError> {
+        match self.path {
+            Some(ref path) => self.cache.borrow().store(path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<P: RateProvider> RateProvider for CachedRates<P> {
+    fn current_rate(&self, currency: &Currency) -> Result<f64, RatesError> {
+        self.provider.current_rate(currency)
+    }
+
+    fn historical_rate(&self, currency: &Currency, timestamp: u64) -> Result<f64, RatesError> {
+        let period = timestamp - timestamp % RATE_PERIOD;
+        if let Some(rate) =
+            self.cache.borrow().historical.get(currency).and_then(|rates| rates.get(&period))
+        {
+            return Ok(*rate);
+        }
+        let rate = self.provider.historical_rate(currency, period)?;
+        self.cache
+            .borrow_mut()
+            .historical
+            .entry(currency.clone())
+            .or_default()
+            .insert(period, rate);
+        Ok(rate)
+    }
+}
+
+impl<P: RateProvider + ?Sized> RateProvider for Box<P> {
+    fn current_rate(&self, currency: &Currency) -> Result<f64, RatesError> {
+        self.as_ref().current_rate(currency)
+    }
+
+    fn historical_rate(&self, currency: &Currency, timestamp: u64) -> Result<f64, RatesError> {
+        self.as_ref().historical_rate(currency, timestamp)
+    }
+}
@@ -0,0 +1,546 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Synchronization of the wallet files between machines through a remote key-value storage,
+//! like a WebDAV server, an S3-compatible bucket or a shared directory.
+//!
+//! The files are encrypted client-side, so the storage never sees the wallet descriptor or any
+//! other wallet data. Each wallet copy tracks the changes with a [`VersionVector`], which allows
+//! to detect concurrent modifications of the same wallet on different machines.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use amplify::hex::ToHex;
+use amplify::IoError;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+use crate::hot::{decrypt, encrypt_hardened, kdf_iterations, KDF_ITERATIONS};
+use crate::DryRun;
+
+/// Wallet files synchronized with the remote storage, if they are present.
+pub const SYNCED_FILES: [&str; 4] = ["descriptor.toml", "data.toml", "cache.yaml", "journal.yaml"];
+
+/// Maximal number of PBKDF2 iterations accepted from the remote files.
+///
+/// The number of iterations is stored in the file header, so without the limit a tampered remote
+/// file could make the wallet spend hours deriving the key.
+pub const MAX_KDF_ITERATIONS: u32 = 10 * KDF_ITERATIONS;
+
+/// Name of the remote object listing the synchronized files.
+pub const MANIFEST: &str = "manifest.toml";
+
+/// Name of the file in the wallet directory keeping the state of the last sync.
+pub const SYNC_STATE_FILE: &str = "sync.toml";
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum RemoteError {
+    /// I/O error: {0}
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// remote storage request has failed: {0}
+    Backend(String),
+
+    /// unable to decrypt {0}; check the password.
+    Decrypt(String),
+
+    /// {0} declares {1} key derivation iterations, which is above the accepted maximum; the
+    /// remote copy may have been tampered with.
+    KdfIterations(String, u32),
+
+    /// {0} is corrupted: {1}
+    Corrupted(String, String),
+
+    /// remote storage has no copy of the wallet; push it first.
+    NoRemote,
+
+    /// remote copy of the wallet has changes which are not pulled yet; pull them first or force
+    /// the push to overwrite them.
+    RemoteAhead,
+
+    /// both local and remote copies of the wallet were changed since the last sync; force either
+    /// push or pull to keep one of them.
+    Conflict,
+
+    /// remote {0} doesn't match the hash listed in the manifest.
+    HashMismatch(String),
+}
+
+/// Generic key-value storage keeping the encrypted wallet files.
+pub trait KvBackend {
+    /// Reads the value, returning `None` if the key is not known.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, RemoteError>;
+
+    /// Writes the value, replacing the existing one.
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), RemoteError>;
+}
+
+/// Backend keeping each value in a file of a directory, which may be shared between the
+/// machines by other means, like a network file system.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct DirBackend(pub PathBuf);
+
+impl KvBackend for DirBackend {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, RemoteError> {
+        match fs::read(self.0.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), RemoteError> {
+        fs::create_dir_all(&self.0)?;
+        fs::write(self.0.join(key), value)?;
+        Ok(())
+    }
+}
+
+/// Backend reading and writing values with HTTP `GET` and `PUT` requests to `<url>/<key>`, as
+/// supported by WebDAV servers and S3-compatible storages.
+#[derive(Clone, Debug)]
+pub struct HttpBackend {
+    url: String,
+    authorization: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl HttpBackend {
+    pub fn new(url: impl Into<String>) -> Self {
+        HttpBackend {
+            url: url.into().trim_end_matches('/').to_owned(),
+            authorization: None,
+            agent: ureq::Agent::new(),
+        }
+    }
+
+    /// Sets the value of the `Authorization` header sent with each request.
+    pub fn with_authorization(mut self, authorization: impl Into<String>) -> Self {
+        self.authorization = Some(authorization.into());
+        self
+    }
+
+    fn request(&self, method: &str, key: &str) -> ureq::Request {
+        let request = self.agent.request(method, &format!("{}/{key}", self.url));
+        match &self.authorization {
+            Some(authorization) => request.set("Authorization", authorization),
+            None => request,
+        }
+    }
+}
+
+impl KvBackend for HttpBackend {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, RemoteError> {
+        match self.request("GET", key).call() {
+            Ok(response) => {
+                let mut data = vec![];
+                response.into_reader().read_to_end(&mut data)?;
+                Ok(Some(data))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(err) => Err(RemoteError::Backend(err.to_string())),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), RemoteError> {
+        self.request("PUT", key)
+            .send_bytes(value)
+            .map_err(|err| RemoteError::Backend(err.to_string()))?;
+        Ok(())
+    }
+}
+
+/// Backend selected by the location of the remote storage: `http://` and `https://` URLs are
+/// served by [`HttpBackend`], and all other locations are treated as directories.
+#[derive(Clone, Debug)]
+pub enum AnyBackend {
+    Dir(DirBackend),
+    Http(HttpBackend),
+}
+
+impl AnyBackend {
+    pub fn with(location: &str, authorization: Option<String>) -> Self {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            let backend = HttpBackend::new(location);
+            AnyBackend::Http(match authorization {
+                Some(authorization) => backend.with_authorization(authorization),
+                None => backend,
+            })
+        } else {
+            AnyBackend::Dir(DirBackend(PathBuf::from(location)))
+        }
+    }
+}
+
+impl KvBackend for AnyBackend {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, RemoteError> {
+        match self {
+            AnyBackend::Dir(backend) => backend.get(key),
+            AnyBackend::Http(backend) => backend.get(key),
+        }
+    }
+
+    fn put(&self, key: &str, value: &[u8]) -> Result<(), RemoteError> {
+        match self {
+            AnyBackend::Dir(backend) => backend.put(key, value),
+            AnyBackend::Http(backend) => backend.put(key, value),
+        }
+    }
+}
+
+/// Version vector, counting the changes made by each of the wallet copies.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", transparent)
+)]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct VersionVector(BTreeMap<String, u64>);
+
+impl VersionVector {
+    /// Number of changes made by the wallet copy.
+    pub fn get(&self, replica: &str) -> u64 { self.0.get(replica).copied().unwrap_or_default() }
+
+    /// Registers a change made by the wallet copy.
+    pub fn increment(&mut self, replica: &str) {
+        *self.0.entry(replica.to_owned()).or_default() += 1;
+    }
+
+    /// Merges the changes known to the other vector.
+    pub fn merge(&mut self, other: &VersionVector) {
+        for (replica, count) in &other.0 {
+            let known = self.0.entry(replica.clone()).or_default();
+            *known = (*known).max(*count);
+        }
+    }
+}
+
+/// Vectors are ordered only if one of them includes all the changes of the other; `None` means
+/// the vectors have concurrent changes.
+impl PartialOrd for VersionVector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let (mut less, mut greater) = (false, false);
+        for replica in self.0.keys().chain(other.0.keys()) {
+            match self.get(replica).cmp(&other.get(replica)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// List of the files of the remote wallet copy with their SHA256 hashes.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Manifest {
+    pub version: VersionVector,
+    pub files: BTreeMap<String, String>,
+}
+
+/// State of the local wallet copy as of the last sync with the remote storage.
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SyncState {
+    /// Random identifier of the local wallet copy.
+    pub replica: String,
+    pub version: VersionVector,
+    /// Hashes of the files as of the last sync, used to detect local changes.
+    pub synced: BTreeMap<String, String>,
+}
+
+impl SyncState {
+    fn new() -> Self {
+        let mut id = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut id);
+        SyncState {
+            replica: id.to_hex(),
+            version: none!(),
+            synced: none!(),
+        }
+    }
+
+    /// Loads the state from the wallet directory, starting a new one for the wallets which were
+    /// never synced.
+    pub fn load(dir: &Path) -> Result<Self, RemoteError> {
+        let path = dir.join(SYNC_STATE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(s) => toml::from_str(&s)
+                .map_err(|err| RemoteError::Corrupted(path.display().to_string(), err.to_string())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(SyncState::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn store(&self, dir: &Path, changes: &DryRun) -> Result<(), RemoteError> {
+        let s = toml::to_string_pretty(self).expect("sync state is always serializable");
+        changes.write(dir.join(SYNC_STATE_FILE), s)?;
+        Ok(())
+    }
+}
+
+/// Result of a sync with the remote storage.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum SyncOutcome {
+    /// Both copies of the wallet are the same.
+    UpToDate,
+    /// Files were uploaded to the remote storage.
+    Pushed(Vec<String>),
+    /// Files were downloaded from the remote storage, and the local files deleted from the remote
+    /// copy were removed.
+    Pulled {
+        downloaded: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+/// Remote storage of the encrypted wallet files.
+#[derive(Clone)]
+pub struct RemoteStore<B: KvBackend> {
+    backend: B,
+    namespace: String,
+    password: String,
+}
+
+impl<B: KvBackend> RemoteStore<B> {
+    /// Constructs the store keeping the files under the namespace, which is usually the wallet
+    /// name, and encrypting them with the password.
+    pub fn new(backend: B, namespace: impl Into<String>, password: impl Into<String>) -> Self {
+        RemoteStore {
+            backend,
+            namespace: namespace.into(),
+            password: password.into(),
+        }
+    }
+
+    fn key(&self, name: &str) -> String { format!("{}.{name}.enc", self.namespace) }
+
+    fn download(&self, name: &str) -> Result<Option<Vec<u8>>, RemoteError> {
+        let key = self.key(name);
+        let Some(data) = self.backend.get(&key)? else {
+            return Ok(None);
+        };
+        match kdf_iterations(&data) {
+            None => return Err(RemoteError::Decrypt(key)),
+            Some(iterations) if iterations > MAX_KDF_ITERATIONS => {
+                return Err(RemoteError::KdfIterations(key, iterations));
+            }
+            Some(_) => {}
+        }
+        decrypt(&data, &self.password).map(Some).map_err(|_| RemoteError::Decrypt(key))
+    }
+
+    fn upload(&self, name: &str, data: Vec<u8>, changes: &DryRun) -> Result<(), RemoteError> {
+        let key = self.key(name);
+        if changes.perform(format!("upload {key}")) {
+            let encrypted = encrypt_hardened(data, &self.password, KDF_ITERATIONS);
+            self.backend.put(&key, &encrypted)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the manifest of the remote wallet copy, if any.
+    pub fn manifest(&self) -> Result<Option<Manifest>, RemoteError> {
+        let Some(data) = self.download(MANIFEST)? else {
+            return Ok(None);
+        };
+        let corrupted = |err: String| RemoteError::Corrupted(self.key(MANIFEST), err);
+        let s = String::from_utf8(data).map_err(|err| corrupted(err.to_string()))?;
+        toml::from_str(&s).map(Some).map_err(|err| corrupted(err.to_string()))
+    }
+
+    /// Uploads the changed files of the wallet directory. Fails if the remote copy has changes
+    /// which were not pulled, unless `force` is set.
+    pub fn push(
+        &self,
+        dir: &Path,
+        force: bool,
+        changes: &DryRun,
+    ) -> Result<SyncOutcome, RemoteError> {
+        let mut state = SyncState::load(dir)?;
+        let files = local_files(dir)?;
+        let hashes = hashes(&files);
+        let manifest = self.manifest()?.unwrap_or_default();
+        let changed = hashes != state.synced;
+
+        match manifest.version.partial_cmp(&state.version) {
+            _ if force => state.version.merge(&manifest.version),
+            Some(Ordering::Equal) if !changed && manifest.files == hashes => {
+                return Ok(SyncOutcome::UpToDate);
+            }
+            Some(Ordering::Less | Ordering::Equal) => {}
+            Some(Ordering::Greater) if !changed => return Err(RemoteError::RemoteAhead),
+            _ => return Err(RemoteError::Conflict),
+        }
+
+        state.version.increment(&state.replica);
+        let mut uploaded = vec![];
+        for (name, data) in files {
+            if manifest.files.get(&name) != hashes.get(&name) {
+                self.upload(&name, data, changes)?;
+                uploaded.push(name);
+            }
+        }
+        let manifest = Manifest {
+            version: state.version.clone(),
+            files: hashes.clone(),
+        };
+        let s = toml::to_string_pretty(&manifest).expect("manifest is always serializable");
+        self.upload(MANIFEST, s.into_bytes(), changes)?;
+        state.synced = hashes;
+        state.store(dir, changes)?;
+        Ok(SyncOutcome::Pushed(uploaded))
+    }
+
+    /// Downloads the files of the remote wallet copy changed since the last sync and removes the
+    /// local files which are no longer present in the remote copy. Fails if the local copy was
+    /// changed as well, unless `force` is set, in which case the local changes are overwritten.
+    pub fn pull(
+        &self,
+        dir: &Path,
+        force: bool,
+        changes: &DryRun,
+    ) -> Result<SyncOutcome, RemoteError> {
+        let mut state = SyncState::load(dir)?;
+        let hashes = hashes(&local_files(dir)?);
+        let manifest = self.manifest()?.ok_or(RemoteError::NoRemote)?;
+        let changed = hashes != state.synced;
+
+        match manifest.version.partial_cmp(&state.version) {
+            _ if force => {}
+            Some(Ordering::Less | Ordering::Equal) => return Ok(SyncOutcome::UpToDate),
+            Some(Ordering::Greater) if !changed => {}
+            _ => return Err(RemoteError::Conflict),
+        }
+
+        if !dir.is_dir() && changes.perform(format!("create directory {}", dir.display())) {
+            fs::create_dir_all(dir)?;
+        }
+        let mut downloaded = vec![];
+        for (name, hash) in &manifest.files {
+            if hashes.get(name) == Some(hash) {
+                continue;
+            }
+            let key = self.key(name);
+            let data =
+                self.download(name)?.ok_or_else(|| RemoteError::HashMismatch(key.clone()))?;
+            if Sha256::digest(&data).to_hex() != *hash {
+                return Err(RemoteError::HashMismatch(key));
+            }
+            changes.write(dir.join(name), data)?;
+            downloaded.push(name.clone());
+        }
+        let mut removed = vec![];
+        for name in hashes.keys().filter(|name| !manifest.files.contains_key(*name)) {
+            let path = dir.join(name);
+            if changes.perform(format!("remove {}", path.display())) {
+                fs::remove_file(path)?;
+            }
+            removed.push(name.clone());
+        }
+        state.version.merge(&manifest.version);
+        state.synced = manifest.files;
+        state.store(dir, changes)?;
+        Ok(SyncOutcome::Pulled {
+            downloaded,
+            removed,
+        })
+    }
+}
+
+/// Reads the synchronized files present in the wallet directory.
+fn local_files(dir: &Path) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let mut files = BTreeMap::new();
+    for name in SYNCED_FILES {
+        match fs::read(dir.join(name)) {
+            Ok(data) => {
+                files.insert(name.to_owned(), data);
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(files)
+}
+
+fn hashes(files: &BTreeMap<String, Vec<u8>>) -> BTreeMap<String, String> {
+    files.iter().map(|(name, data)| (name.clone(), Sha256::digest(data).to_hex())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_vector() {
+        let mut a = VersionVector::default();
+        let mut b = VersionVector::default();
+        assert_eq!(a.partial_cmp(&b), Some(Ordering::Equal));
+        a.increment("a");
+        assert!(a > b);
+        b.merge(&a);
+        assert_eq!(a, b);
+        b.increment("b");
+        assert!(a < b);
+        a.increment("a");
+        assert_eq!(a.partial_cmp(&b), None);
+        a.merge(&b);
+        assert!(a > b);
+        assert_eq!((a.get("a"), a.get("b"), a.get("c")), (2, 1, 0));
+    }
+
+    #[test]
+    fn test_kdf_iterations_limit() {
+        let dir = std::env::temp_dir().join(format!("bp-remote-{}", std::process::id()));
+        let store = RemoteStore::new(DirBackend(dir.clone()), "wallet", "password");
+        let mut data = crate::hot::KDF_MAGIC.to_vec();
+        data.extend((MAX_KDF_ITERATIONS + 1).to_be_bytes());
+        data.extend([0u8; 64]);
+        store.backend.put(&store.key(MANIFEST), &data).unwrap();
+        assert!(matches!(
+            store.manifest(),
+            Err(RemoteError::KdfIterations(_, iterations)) if iterations == MAX_KDF_ITERATIONS + 1
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Aggregation of the wallet history over calendar periods.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bpstd::Sats;
+
+use crate::{Layer2Cache, OwnWallets, TxStatus, WalletCache};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Calendar period used to bucket the wallet history.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Default, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+#[display(lowercase)]
+pub enum Period {
+    /// Calendar day.
+    Day,
+    /// Calendar month.
+    #[default]
+    Month,
+    /// Quarter of a calendar year.
+    Quarter,
+    /// Calendar year.
+    Year,
+}
+
+/// Calendar date in UTC.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+#[display("{year:04}-{month:02}-{day:02}")]
+pub struct Date {
+    pub year: u32,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// Converts UNIX timestamp into the date in UTC.
+    pub fn from_timestamp(timestamp: u64) -> Self {
+        // Conversion of days since 1970-01-01 into the proleptic Gregorian calendar date, working
+        // in 400-year eras starting from 0000-03-01.
+        let days = timestamp / SECS_PER_DAY + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let mp = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+        let year = (year_of_era + era * 400) as u32 + (month <= 2) as u32;
+        Date { year, month, day }
+    }
+
+    /// UNIX timestamp of the start of the day in UTC.
+    pub fn to_timestamp(&self) -> u64 {
+        // Inverse of the conversion in `Date::from_timestamp`
+        let year = self.year as u64 - (self.month <= 2) as u64;
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let mp = (self.month as u64 + 9) % 12;
+        let day_of_year = (153 * mp + 2) / 5 + self.day as u64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        (era * 146_097 + day_of_era - 719_468) * SECS_PER_DAY
+    }
+
+    /// UNIX timestamp of the last second of the day in UTC.
+    pub fn end_timestamp(&self) -> u64 { self.to_timestamp() + SECS_PER_DAY - 1 }
+}
+
+/// invalid date '{0}'; it must be given as YYYY-MM-DD and be not earlier than 1970-01-01.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidDate(String);
+
+impl FromStr for Date {
+    type Err = InvalidDate;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidDate(s.to_owned());
+        let mut parts = s.splitn(3, '-');
+        let mut next = || parts.next().filter(|part| part.chars().all(|c| c.is_ascii_digit()));
+        let (Some(year), Some(month), Some(day)) = (next(), next(), next()) else {
+            return Err(invalid());
+        };
+        let date = Date {
+            year: year.parse().map_err(|_| invalid())?,
+            month: month.parse().map_err(|_| invalid())?,
+            day: day.parse().map_err(|_| invalid())?,
+        };
+        // Days past the end of a month are detected by the round trip via the timestamp
+        if date.year < 1970
+            || !(1..=12).contains(&date.month)
+            || !(1..=31).contains(&date.day)
+            || Date::from_timestamp(date.to_timestamp()) != date
+        {
+            return Err(invalid());
+        }
+        Ok(date)
+    }
+}
+
+/// Calendar period identified by its first day.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct PeriodBucket {
+    pub period: Period,
+    pub start: Date,
+}
+
+impl PeriodBucket {
+    /// Detects the period of the given kind containing the UNIX timestamp.
+    pub fn with(period: Period, timestamp: u64) -> Self {
+        let mut start = Date::from_timestamp(timestamp);
+        match period {
+            Period::Day => {}
+            Period::Month => start.day = 1,
+            Period::Quarter => {
+                start.day = 1;
+                start.month = (start.month - 1) / 3 * 3 + 1;
+            }
+            Period::Year => {
+                start.day = 1;
+                start.month = 1;
+            }
+        }
+        PeriodBucket { period, start }
+    }
+}
+
+impl Display for PeriodBucket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let Date { year, month, .. } = self.start;
+        match self.period {
+            Period::Day => Display::fmt(&self.start, f),
+            Period::Month => write!(f, "{year:04}-{month:02}"),
+            Period::Quarter => write!(f, "{year:04}-Q{}", (month - 1) / 3 + 1),
+            Period::Year => write!(f, "{year:04}"),
+        }
+    }
+}
+
+/// Folds items into the accumulators of the calendar periods they belong to.
+///
+/// The `time` function provides UNIX timestamp of an item; items without a timestamp, like
+/// unmined transactions, are skipped.
+pub fn aggregate_by_period<T, A: Default>(
+    items: impl IntoIterator<Item = T>,
+    period: Period,
+    time: impl Fn(&T) -> Option<u64>,
+    mut fold: impl FnMut(&mut A, T),
+) -> BTreeMap<PeriodBucket, A> {
+    let mut buckets = BTreeMap::<PeriodBucket, A>::new();
+    for item in items {
+        let Some(timestamp) = time(&item) else {
+            continue;
+        };
+        fold(buckets.entry(PeriodBucket::with(period, timestamp)).or_default(), item);
+    }
+    buckets
+}
+
+/// Miner fees paid by the wallet over a calendar period.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct FeeTotal {
+    /// Number of the transactions spending wallet coins.
+    pub txs: usize,
+    /// Total of the miner fees paid by these transactions.
+    pub fees: Sats,
+    /// Number of the transactions which are transfers to other wallets of the user.
+    pub transfers: usize,
+}
+
+impl<L2: Layer2Cache> WalletCache<L2> {
+    /// Aggregates miner fees paid by the mined transactions spending wallet coins per calendar
+    /// period, using the time of the mining block.
+    ///
+    /// Transactions paying to any of the `own` wallets are counted as transfers; empty
+    /// [`OwnWallets`] disables the detection.
+    pub fn fee_report(&self, period: Period, own: &OwnWallets) -> BTreeMap<PeriodBucket, FeeTotal> {
+        let rows = self.history().filter(|row| !row.our_inputs.is_empty());
+        aggregate_by_period(
+            rows,
+            period,
+            |row| match row.time {
+                TxStatus::Mined(time) => Some(time),
+                _ => None,
+            },
+            |total: &mut FeeTotal, row| {
+                total.txs += 1;
+                total.fees += row.fee;
+                if !own.transfer_wallets(&row).is_empty() {
+                    total.transfers += 1;
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn date_from_timestamp() {
+        assert_eq!(Date::from_timestamp(0).to_string(), "1970-01-01");
+        // Genesis block time
+        assert_eq!(Date::from_timestamp(1_231_006_505).to_string(), "2009-01-03");
+        assert_eq!(Date::from_timestamp(1_709_164_800).to_string(), "2024-02-29");
+        assert_eq!(Date::from_timestamp(1_735_689_599).to_string(), "2024-12-31");
+    }
+
+    #[test]
+    fn date_parse() {
+        let date = Date::from_str("2024-02-29").unwrap();
+        assert_eq!(date.to_timestamp(), 1_709_164_800);
+        assert_eq!(date.end_timestamp(), 1_709_251_199);
+        assert_eq!(Date::from_str("1970-01-01").unwrap().to_timestamp(), 0);
+        assert_eq!(Date::from_str("2024-12-31").unwrap().end_timestamp(), 1_735_689_599);
+        assert!(Date::from_str("2023-02-29").is_err());
+        assert!(Date::from_str("2024-13-01").is_err());
+        assert!(Date::from_str("1969-12-31").is_err());
+        assert!(Date::from_str("2024-1-+1").is_err());
+        assert!(Date::from_str("2024-01").is_err());
+    }
+
+    #[test]
+    fn period_buckets() {
+        let timestamps = [1_709_164_800, 1_711_929_599, 1_711_929_600, 1_735_689_599];
+        let totals = aggregate_by_period(timestamps, Period::Quarter, |t| Some(*t), |n, _| *n += 1);
+        let totals = totals.into_iter().map(|(b, n)| (b.to_string(), n)).collect::<Vec<_>>();
+        assert_eq!(totals, [(s!("2024-Q1"), 2), (s!("2024-Q2"), 1), (s!("2024-Q4"), 1)]);
+        assert_eq!(PeriodBucket::with(Period::Month, 1_709_164_800).to_string(), "2024-02");
+        assert_eq!(PeriodBucket::with(Period::Year, 1_709_164_800).to_string(), "2024");
+        assert_eq!(PeriodBucket::with(Period::Day, 1_709_164_800).to_string(), "2024-02-29");
+    }
+}
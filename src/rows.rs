@@ -24,10 +24,12 @@ use std::fmt::{self, Display, Formatter, LowerHex};
 use std::str::FromStr;
 
 use amplify::hex::FromHex;
-use bpstd::{Address, DerivedAddr, Outpoint, Sats, ScriptPubkey, Txid};
+use bpstd::{Address, DerivedAddr, Outpoint, Sats, ScriptPubkey, Terminal, Txid};
 
+use crate::data::Inpoint;
 use crate::{
-    BlockHeight, Layer2Cache, Layer2Coin, Layer2Empty, Layer2Tx, Party, TxStatus, WalletCache,
+    BlockHeight, FeeRate, Layer2Cache, Layer2Coin, Layer2Empty, Layer2Tx, Party, TxStatus,
+    WalletCache, WalletTx,
 };
 
 #[cfg_attr(
@@ -57,6 +59,17 @@ pub enum Counterparty {
     Unknown(ScriptPubkey),
 }
 
+impl Counterparty {
+    /// Script pubkey of the counterparty, or `None` for the miner.
+    pub fn script_pubkey(&self) -> Option<ScriptPubkey> {
+        match self {
+            Counterparty::Miner => None,
+            Counterparty::Address(addr) => Some(addr.script_pubkey()),
+            Counterparty::Unknown(script) => Some(script.clone()),
+        }
+    }
+}
+
 impl From<Party> for Counterparty {
     fn from(party: Party) -> Self {
         match party {
@@ -109,18 +122,21 @@ impl FromStr for Counterparty {
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct TxRow<L2: Layer2Tx = Layer2Empty> {
     pub height: TxStatus<BlockHeight>,
-    // TODO: Add date/time
+    /// UNIX timestamp of the block mining the transaction.
+    pub time: TxStatus<u64>,
     pub operation: OpType,
     pub our_inputs: Vec<u32>,
     pub counterparties: Vec<(Counterparty, i64)>,
     pub own: Vec<(DerivedAddr, i64)>,
     pub txid: Txid,
     pub fee: Sats,
+    pub fee_rate: FeeRate,
     pub weight: u32,
     pub size: u32,
     pub total: Sats,
     pub amount: Sats,
     pub balance: Sats,
+    pub rbf: bool,
     pub layer2: L2,
 }
 
@@ -146,79 +162,188 @@ pub struct CoinRow<L2: Layer2Coin> {
     pub layer2: Vec<L2>,
 }
 
+/// Credit or debit of a single wallet address, see [`WalletCache::address_history`].
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AddrRow {
+    pub height: TxStatus<BlockHeight>,
+    pub operation: OpType,
+    pub txid: Txid,
+    /// Output paying to the address for credits, or the output spent from it for debits.
+    pub outpoint: Outpoint,
+    pub amount: Sats,
+    /// Input spending the credited output, if the output is already spent.
+    pub spent: Option<Inpoint>,
+    /// External payers (with positive amounts) and beneficiaries (with negative amounts) of the
+    /// transaction.
+    pub counterparties: Vec<(Counterparty, i64)>,
+}
+
 impl<L2: Layer2Cache> WalletCache<L2> {
     pub fn coins(&self) -> impl Iterator<Item = CoinRow<L2::Coin>> + '_ {
-        self.utxo.iter().map(|outpoint| {
-            let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
-            let out = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
-            CoinRow {
-                height: tx.status.map(|info| info.height),
-                outpoint: *outpoint,
-                address: out.derived_addr().expect("cache data inconsistency"),
-                amount: out.value,
-                layer2: none!(), // TODO: Add support to WalletTx
-            }
+        self.utxo.iter().map(|outpoint| self.coin_row(*outpoint))
+    }
+
+    /// Iterates over the addresses holding unspent outputs, together with their coins.
+    pub fn address_coins(
+        &self,
+    ) -> impl Iterator<Item = (DerivedAddr, impl Iterator<Item = CoinRow<L2::Coin>> + '_)> + '_
+    {
+        self.coin_index().addr_coins.values().map(|(derived, outpoints)| {
+            (*derived, outpoints.iter().map(|outpoint| self.coin_row(*outpoint)))
         })
     }
 
+    /// Iterates over unspent outputs held by the address with the given terminal.
+    pub fn coins_on(&self, terminal: Terminal) -> impl Iterator<Item = CoinRow<L2::Coin>> + '_ {
+        self.coin_index()
+            .addr_coins
+            .get(&terminal)
+            .into_iter()
+            .flat_map(|(_, outpoints)| outpoints.iter().map(|outpoint| self.coin_row(*outpoint)))
+    }
+
+    fn coin_row(&self, outpoint: Outpoint) -> CoinRow<L2::Coin> {
+        let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
+        let out = tx.outputs.get(outpoint.vout_usize()).expect("cache data inconsistency");
+        CoinRow {
+            height: tx.status.map(|info| info.height),
+            outpoint,
+            address: out.derived_addr().expect("cache data inconsistency"),
+            amount: out.value,
+            layer2: self.layer2.coin_layer2(outpoint),
+        }
+    }
+
     pub fn history(&self) -> impl Iterator<Item = TxRow<L2::Tx>> + '_ {
-        self.tx.values().map(|tx| {
-            let (credit, debit) = tx.credited_debited();
-            let mut row = TxRow {
-                height: tx.status.map(|info| info.height),
-                operation: OpType::Credit,
-                our_inputs: tx
-                    .inputs
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(idx, inp)| inp.derived_addr().map(|_| idx as u32))
-                    .collect(),
-                counterparties: none!(),
-                own: none!(),
-                txid: tx.txid,
-                fee: tx.fee,
-                weight: tx.weight,
-                size: tx.size,
-                total: tx.total_moved(),
-                amount: Sats::ZERO,
-                balance: Sats::ZERO,
-                layer2: none!(), // TODO: Add support to WalletTx
-            };
-            // TODO: Add balance calculation
-            row.own = tx
-                .inputs
-                .iter()
-                .filter_map(|i| i.derived_addr().map(|a| (a, -i.value.sats_i64())))
-                .chain(
-                    tx.outputs
-                        .iter()
-                        .filter_map(|o| o.derived_addr().map(|a| (a, o.value.sats_i64()))),
-                )
-                .collect();
-            if credit.is_non_zero() {
-                row.counterparties = tx.credits().fold(Vec::new(), |mut cp, inp| {
-                    let party = Counterparty::from(inp.payer.clone());
-                    cp.push((party, inp.value.sats_i64()));
-                    cp
+        self.tx.values().map(|tx| self.tx_row(tx))
+    }
+
+    /// Iterates over the history in the order of transaction heights, with unconfirmed
+    /// transactions going first.
+    ///
+    /// Only the index of transaction heights is kept in memory, while history rows are
+    /// constructed lazily, so the history of large wallets can be processed row by row.
+    pub fn history_by_height(&self) -> impl Iterator<Item = TxRow<L2::Tx>> + '_ {
+        let mut index = self
+            .tx
+            .values()
+            .map(|tx| (tx.status.map(|info| info.height), tx.txid))
+            .collect::<Vec<_>>();
+        index.sort_unstable();
+        index.into_iter().map(|(_, txid)| self.tx_row(&self.tx[&txid]))
+    }
+
+    /// Lists every credit and debit of the wallet address with the given terminal, in the
+    /// order of transaction heights, with unconfirmed transactions going first.
+    ///
+    /// Transactions are looked up with the address index, which is rebuilt lazily after each
+    /// cache update, so the history is not scanned for each of the addresses.
+    pub fn address_history(&self, terminal: Terminal) -> Vec<AddrRow> {
+        let Some(txids) = self.coin_index().addr_txs.get(&terminal) else {
+            return vec![];
+        };
+        let mut txs = txids.iter().map(|txid| &self.tx[txid]).collect::<Vec<_>>();
+        txs.sort_unstable_by_key(|tx| (tx.status.map(|info| info.height), tx.txid));
+        let mut rows = vec![];
+        for tx in txs {
+            let height = tx.status.map(|info| info.height);
+            let payers = tx
+                .credits()
+                .map(|inp| (Counterparty::from(inp.payer.clone()), inp.value.sats_i64()));
+            let beneficiaries = tx
+                .debits()
+                .map(|out| (Counterparty::from(out.beneficiary.clone()), -out.value.sats_i64()));
+            let counterparties = payers.chain(beneficiaries).collect::<Vec<_>>();
+            let on_terminal =
+                |party: &Party| party.derived_addr().is_some_and(|d| d.terminal == terminal);
+            for inp in tx.inputs.iter().filter(|inp| on_terminal(&inp.payer)) {
+                rows.push(AddrRow {
+                    height,
+                    operation: OpType::Debit,
+                    txid: tx.txid,
+                    outpoint: inp.outpoint,
+                    amount: inp.value,
+                    spent: None,
+                    counterparties: counterparties.clone(),
                 });
-                row.counterparties.extend(tx.debits().fold(Vec::new(), |mut cp, out| {
-                    let party = Counterparty::from(out.beneficiary.clone());
-                    cp.push((party, -out.value.sats_i64()));
-                    cp
-                }));
-                row.operation = OpType::Credit;
-                row.amount = credit - debit - tx.fee;
-            } else if debit.is_non_zero() {
-                row.counterparties = tx.debits().fold(Vec::new(), |mut cp, out| {
-                    let party = Counterparty::from(out.beneficiary.clone());
-                    cp.push((party, -out.value.sats_i64()));
-                    cp
+            }
+            for out in tx.outputs.iter().filter(|out| on_terminal(&out.beneficiary)) {
+                rows.push(AddrRow {
+                    height,
+                    operation: OpType::Credit,
+                    txid: tx.txid,
+                    outpoint: out.outpoint,
+                    amount: out.value,
+                    spent: out.spent,
+                    counterparties: counterparties.clone(),
                 });
-                row.operation = OpType::Debit;
-                row.amount = debit;
             }
-            row
-        })
+        }
+        rows
+    }
+
+    fn tx_row(&self, tx: &WalletTx) -> TxRow<L2::Tx> {
+        let (credit, debit) = tx.credited_debited();
+        let mut row = TxRow {
+            height: tx.status.map(|info| info.height),
+            time: tx.status.map(|info| info.time),
+            operation: OpType::Credit,
+            our_inputs: tx
+                .inputs
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, inp)| inp.derived_addr().map(|_| idx as u32))
+                .collect(),
+            counterparties: none!(),
+            own: none!(),
+            txid: tx.txid,
+            fee: tx.fee,
+            fee_rate: tx.fee_rate(),
+            weight: tx.weight,
+            size: tx.size,
+            total: tx.total_moved(),
+            amount: Sats::ZERO,
+            balance: Sats::ZERO,
+            rbf: tx.signals_rbf(),
+            layer2: self.layer2.tx_layer2(tx.txid),
+        };
+        // TODO: Add balance calculation
+        row.own = tx
+            .inputs
+            .iter()
+            .filter_map(|i| i.derived_addr().map(|a| (a, -i.value.sats_i64())))
+            .chain(
+                tx.outputs.iter().filter_map(|o| o.derived_addr().map(|a| (a, o.value.sats_i64()))),
+            )
+            .collect();
+        if credit.is_non_zero() {
+            row.counterparties = tx.credits().fold(Vec::new(), |mut cp, inp| {
+                let party = Counterparty::from(inp.payer.clone());
+                cp.push((party, inp.value.sats_i64()));
+                cp
+            });
+            row.counterparties.extend(tx.debits().fold(Vec::new(), |mut cp, out| {
+                let party = Counterparty::from(out.beneficiary.clone());
+                cp.push((party, -out.value.sats_i64()));
+                cp
+            }));
+            row.operation = OpType::Credit;
+            row.amount = credit - debit - tx.fee;
+        } else if debit.is_non_zero() {
+            row.counterparties = tx.debits().fold(Vec::new(), |mut cp, out| {
+                let party = Counterparty::from(out.beneficiary.clone());
+                cp.push((party, -out.value.sats_i64()));
+                cp
+            });
+            row.operation = OpType::Debit;
+            row.amount = debit;
+        }
+        row
     }
 }
 
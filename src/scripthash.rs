@@ -0,0 +1,97 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use bpstd::ScriptPubkey;
+use sha2::{Digest, Sha256};
+
+/// Script hash in the format used by Electrum servers to identify addresses: SHA-256 hash of
+/// the script pubkey with the reversed byte order.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", into = "String", try_from = "String")
+)]
+pub struct ScriptHash([u8; 32]);
+
+impl ScriptHash {
+    pub fn with(script: &ScriptPubkey) -> Self {
+        let mut hash: [u8; 32] = Sha256::digest(script.as_slice()).into();
+        hash.reverse();
+        ScriptHash(hash)
+    }
+
+    #[inline]
+    pub const fn to_byte_array(self) -> [u8; 32] { self.0 }
+}
+
+/// invalid script hash '{0}'; it must be a hex-encoded 32-byte value.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidScriptHash(String);
+
+impl Display for ScriptHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&self.0.to_hex()) }
+}
+
+impl FromStr for ScriptHash {
+    type Err = InvalidScriptHash;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Vec::<u8>::from_hex(s)
+            .ok()
+            .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            .map(ScriptHash)
+            .ok_or_else(|| InvalidScriptHash(s.to_owned()))
+    }
+}
+
+impl From<ScriptHash> for String {
+    fn from(hash: ScriptHash) -> Self { hash.to_string() }
+}
+
+impl TryFrom<String> for ScriptHash {
+    type Error = InvalidScriptHash;
+    fn try_from(s: String) -> Result<Self, Self::Error> { ScriptHash::from_str(&s) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_electrum_vector() {
+        // Example from the Electrum protocol documentation for the genesis block address
+        let script =
+            ScriptPubkey::from_hex("76a91462e907b15cbf27d5425399ebf6f0fb50ebb88f1888ac").unwrap();
+        let hash = ScriptHash::with(&script);
+        assert_eq!(
+            hash.to_string(),
+            "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161"
+        );
+        assert_eq!(ScriptHash::from_str(&hash.to_string()), Ok(hash));
+    }
+}
@@ -0,0 +1,145 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic shuffling of transaction inputs and outputs.
+//!
+//! PSBT construction doesn't use any randomness, so the same wallet state and parameters always
+//! produce the same PSBT. When the order of inputs and outputs has to be randomized (for
+//! instance, to not reveal the change output by its position), the permutation is derived from a
+//! seed shared by the coordinators, which keeps the construction reproducible.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use amplify::hex::{FromHex, ToHex};
+use bpstd::Vout;
+use psbt::Psbt;
+use sha2::{Digest, Sha256};
+
+const SHUFFLE_TAG: &[u8] = b"bp-wallet:shuffle";
+
+/// Seed of deterministic shuffling, given as a hex string of arbitrary non-zero length.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct ShuffleSeed(Vec<u8>);
+
+impl Display for ShuffleSeed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result { f.write_str(&self.0.to_hex()) }
+}
+
+/// invalid shuffle seed '{0}'; it must be a non-empty hex string.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub struct InvalidShuffleSeed(String);
+
+impl FromStr for ShuffleSeed {
+    type Err = InvalidShuffleSeed;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Vec::<u8>::from_hex(s)
+            .ok()
+            .filter(|seed| !seed.is_empty())
+            .map(ShuffleSeed)
+            .ok_or_else(|| InvalidShuffleSeed(s.to_owned()))
+    }
+}
+
+impl ShuffleSeed {
+    /// Shuffles items with Fisher-Yates algorithm, taking random numbers from SHA256 hashes of the
+    /// seed. Different `domain`s give independent permutations for the same seed.
+    pub fn shuffle<T>(&self, domain: &str, items: &mut [T]) {
+        for (step, i) in (1..items.len()).rev().enumerate() {
+            let mut engine = Sha256::new();
+            engine.update(SHUFFLE_TAG);
+            engine.update((domain.len() as u64).to_le_bytes());
+            engine.update(domain.as_bytes());
+            engine.update(&self.0);
+            engine.update((step as u64).to_le_bytes());
+            let hash = engine.finalize();
+            let mut random = [0u8; 8];
+            random.copy_from_slice(&hash[..8]);
+            // The modulo bias is negligible for the number of transaction outputs
+            let j = (u64::from_le_bytes(random) % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+
+    /// Shuffles outputs of a newly constructed PSBT, updating the change output number.
+    ///
+    /// # Panics
+    ///
+    /// If the PSBT outputs are not modifiable.
+    pub fn shuffle_outputs(&self, psbt: &mut Psbt, change_vout: &mut Option<Vout>) {
+        let mut order = (0..psbt.outputs().count()).collect::<Vec<_>>();
+        self.shuffle("outputs", &mut order);
+        let mut position = vec![0; order.len()];
+        for (pos, index) in order.iter().enumerate() {
+            position[*index] = pos;
+        }
+        psbt.sort_outputs_by(|output| position[output.index()])
+            .expect("PSBT outputs are expected to be modifiable");
+        if let Some(vout) = change_vout {
+            *vout = Vout::from_u32(position[vout.into_usize()] as u32);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shuffle() {
+        let seed = ShuffleSeed::from_str("00c0ffee").unwrap();
+        assert_eq!(seed.to_string(), "00c0ffee");
+        assert!(ShuffleSeed::from_str("").is_err());
+        assert!(ShuffleSeed::from_str("c0ffe").is_err());
+
+        let items = (0..16).collect::<Vec<_>>();
+        let mut first = items.clone();
+        seed.shuffle("outputs", &mut first);
+        let mut second = items.clone();
+        seed.shuffle("outputs", &mut second);
+        assert_eq!(first, second);
+        assert_ne!(first, items);
+
+        let mut inputs = items.clone();
+        seed.shuffle("inputs", &mut inputs);
+        assert_ne!(first, inputs);
+        let mut other = items.clone();
+        ShuffleSeed::from_str("c0ffee").unwrap().shuffle("outputs", &mut other);
+        assert_ne!(first, other);
+
+        first.sort();
+        assert_eq!(first, items);
+
+        let mut psbt = Psbt::create(psbt::PsbtVer::V2);
+        for sats in 1..=8u64 {
+            psbt.construct_output_expect(bpstd::ScriptPubkey::new(), bpstd::Sats(sats * 1000));
+        }
+        let mut change_vout = Some(Vout::from_u32(7));
+        seed.shuffle_outputs(&mut psbt, &mut change_vout);
+        let change = psbt.output(change_vout.unwrap().into_usize()).unwrap();
+        assert_eq!(change.amount, bpstd::Sats(8000));
+        assert_ne!(change_vout, Some(Vout::from_u32(7)));
+        assert!(psbt.outputs().enumerate().all(|(index, output)| output.index() == index));
+    }
+}
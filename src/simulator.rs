@@ -0,0 +1,384 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! In-memory chain simulator for deterministic tests of the wallet cache updates, including
+//! re-organizations, transaction replacements and coinbase outputs, without external services.
+//!
+//! Transactions and blocks are not validated: the simulator only tracks which transactions are
+//! mined in which block and which ones are in the mempool.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::sync::{Mutex, MutexGuard};
+
+use bpstd::{
+    BlockHash, BlockHeader, BlockMerkleRoot, LockTime, Outpoint, Sats, ScriptPubkey, SeqNo, Tx,
+    TxIn, TxOut, TxVer, Txid, VarIntArray,
+};
+use descriptors::Descriptor;
+use sha2::{Digest, Sha256};
+
+use crate::indexers::extend_watched;
+use crate::{
+    BlockHeight, FeeSnapshot, Indexer, Layer2, MayError, MiningInfo, OutpointStatus, TxStatus,
+    TxStore, WalletCache, WalletDescr,
+};
+
+/// Timestamp of the first simulated block; each next block is mined ten minutes later.
+pub const SIM_START_TIME: u32 = 1_700_000_000;
+
+/// Simulated block.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct SimBlock {
+    pub header: BlockHeader,
+    pub txs: Vec<Tx>,
+}
+
+/// Simulated chain and mempool.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Simulator {
+    blocks: Vec<SimBlock>,
+    mempool: Vec<Tx>,
+    /// Counter making the synthesized blocks and transactions unique.
+    nonce: u32,
+}
+
+impl Simulator {
+    pub fn new() -> Self { default!() }
+
+    /// Height of the last block, which is zero before any block is mined.
+    pub fn height(&self) -> u32 { self.blocks.len() as u32 }
+
+    pub fn blocks(&self) -> &[SimBlock] { &self.blocks }
+
+    pub fn mempool(&self) -> &[Tx] { &self.mempool }
+
+    /// Mining information of the last block.
+    pub fn tip(&self) -> MiningInfo {
+        match self.blocks.last() {
+            None => MiningInfo::genesis(),
+            Some(block) => MiningInfo {
+                height: BlockHeight::new(self.height()).expect("non-zero height"),
+                time: block.header.time as u64,
+                block_hash: block.header.block_hash(),
+            },
+        }
+    }
+
+    /// Synthesizes transaction paying to the script from an input unknown to any wallet.
+    pub fn fund(&mut self, script: ScriptPubkey, value: Sats) -> Tx {
+        self.nonce += 1;
+        let hash: [u8; 32] = Sha256::digest(self.nonce.to_le_bytes()).into();
+        let prev_txid = Txid::from(hash);
+        Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::from_checked(vec![TxIn {
+                prev_output: Outpoint::new(prev_txid, 0u32),
+                sig_script: none!(),
+                sequence: SeqNo::from_consensus_u32(0xFFFF_FFFD),
+                witness: none!(),
+            }]),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(script, value)]),
+            lock_time: LockTime::ZERO,
+        }
+    }
+
+    /// Adds transaction to the mempool, evicting the mempool transactions spending the same
+    /// outputs as the new one (as in a replace-by-fee) together with their descendants.
+    ///
+    /// Returns ids of the evicted transactions.
+    pub fn broadcast(&mut self, tx: Tx) -> Vec<Txid> {
+        let mut spent =
+            tx.inputs.iter().map(|input| input.prev_output).collect::<BTreeSet<Outpoint>>();
+        let mut evicted = vec![];
+        loop {
+            let conflicts =
+                |other: &Tx| other.inputs.iter().any(|input| spent.contains(&input.prev_output));
+            let Some(pos) = self.mempool.iter().position(conflicts) else {
+                break;
+            };
+            let other = self.mempool.remove(pos);
+            let txid = other.txid();
+            // Descendants of the evicted transaction become invalid as well
+            spent.extend((0..other.outputs.len()).map(|vout| Outpoint::new(txid, vout as u32)));
+            evicted.push(txid);
+        }
+        self.mempool.push(tx);
+        evicted
+    }
+
+    /// Mines block with all the mempool transactions and a coinbase transaction paying the
+    /// reward to the script. Returns hash of the mined block.
+    pub fn mine(&mut self, reward: Option<(ScriptPubkey, Sats)>) -> BlockHash {
+        self.nonce += 1;
+        let height = self.height() + 1;
+        let (script, value) = reward.unwrap_or_else(|| (ScriptPubkey::new(), Sats::ZERO));
+        let coinbase = Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::from_checked(vec![TxIn {
+                prev_output: Outpoint::new(Txid::coinbase(), u32::MAX),
+                sig_script: none!(),
+                sequence: SeqNo::from_consensus_u32(u32::MAX),
+                witness: none!(),
+            }]),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(script, value)]),
+            // Makes coinbase transactions of different blocks distinct, like BIP-34 does
+            lock_time: LockTime::from_consensus_u32(height),
+        };
+        let mut txs = vec![coinbase];
+        txs.append(&mut self.mempool);
+        let header = BlockHeader {
+            version: 0x2000_0000,
+            prev_block_hash: self.tip().block_hash,
+            merkle_root: BlockMerkleRoot::from([0u8; 32]),
+            time: SIM_START_TIME + height * 600,
+            bits: 0x207f_ffff,
+            nonce: self.nonce,
+        };
+        let block_hash = header.block_hash();
+        self.blocks.push(SimBlock { header, txs });
+        block_hash
+    }
+
+    /// Mines the given number of blocks without transactions paying to anybody. The mempool
+    /// transactions are kept in the mempool.
+    pub fn mine_empty(&mut self, count: u32) {
+        let mempool = std::mem::take(&mut self.mempool);
+        for _ in 0..count {
+            self.mine(None);
+        }
+        self.mempool = mempool;
+    }
+
+    /// Disconnects the given number of the last blocks, returning their transactions except
+    /// the coinbase ones to the mempool. New blocks mined afterwards replace the disconnected
+    /// ones.
+    pub fn disconnect(&mut self, depth: u32) {
+        let keep = self.blocks.len().saturating_sub(depth as usize);
+        let mut returned = self
+            .blocks
+            .drain(keep..)
+            .flat_map(|block| block.txs.into_iter().skip(1))
+            .collect::<Vec<_>>();
+        returned.append(&mut self.mempool);
+        self.mempool = returned;
+    }
+
+    /// Removes transaction from the mempool, like if it was evicted by the nodes.
+    pub fn drop_tx(&mut self, txid: Txid) -> bool {
+        let count = self.mempool.len();
+        self.mempool.retain(|tx| tx.txid() != txid);
+        self.mempool.len() != count
+    }
+
+    fn txs(&self) -> impl Iterator<Item = &Tx> {
+        self.blocks.iter().flat_map(|block| &block.txs).chain(&self.mempool)
+    }
+}
+
+/// Indexer serving wallet history from a [`Simulator`].
+///
+/// Each update rebuilds the wallet transactions from the simulated chain and mempool, so the
+/// transactions which are no longer in the chain or the mempool get removed from the cache.
+#[derive(Debug, Default)]
+pub struct SimIndexer {
+    chain: Mutex<Simulator>,
+}
+
+impl SimIndexer {
+    pub fn new(chain: Simulator) -> Self {
+        SimIndexer {
+            chain: Mutex::new(chain),
+        }
+    }
+
+    pub fn chain(&self) -> MutexGuard<'_, Simulator> { self.chain.lock().expect("poisoned lock") }
+}
+
+impl Indexer for SimIndexer {
+    type Error = Infallible;
+
+    fn create<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+    ) -> MayError<WalletCache<L2::Cache>, Vec<Self::Error>> {
+        let mut cache = WalletCache::new_nonsync();
+        self.update::<K, D, L2>(descr, &mut cache).map(|_| cache)
+    }
+
+    fn update<K, D: Descriptor<K>, L2: Layer2>(
+        &self,
+        descr: &WalletDescr<K, D, L2::Descr>,
+        cache: &mut WalletCache<L2::Cache>,
+    ) -> MayError<usize, Vec<Self::Error>> {
+        let chain = self.chain();
+        let network = descr.network();
+        let sliced = descr.slice_range().is_some();
+        let mut scripts = BTreeMap::new();
+        let mut keychains = descr
+            .watched_keychains()
+            .into_iter()
+            .map(|keychain| (keychain, (descr.watched_addresses(keychain), 0u32)))
+            .collect::<BTreeMap<_, _>>();
+
+        cache.tx.clear();
+        cache.utxo.clear();
+        cache.addr.clear();
+        extend_watched(&mut scripts, &mut keychains, cache, sliced);
+
+        let mut store = TxStore::default();
+        let mut updated = 0usize;
+        let mined = chain.blocks.iter().enumerate().flat_map(|(no, block)| {
+            let status = TxStatus::Mined(MiningInfo {
+                height: BlockHeight::new(no as u32 + 1).expect("non-zero height"),
+                time: block.header.time as u64,
+                block_hash: block.header.block_hash(),
+            });
+            block.txs.iter().map(move |tx| (tx, status))
+        });
+        for (tx, status) in mined.chain(chain.mempool.iter().map(|tx| (tx, TxStatus::Mempool))) {
+            store.insert(tx.clone());
+            if cache.import_tx(tx.clone(), status, &scripts, network, &mut store) {
+                updated += 1;
+                extend_watched(&mut scripts, &mut keychains, cache, sliced);
+            }
+        }
+
+        for (keychain, (_, watched)) in keychains {
+            cache.mark_scanned(keychain, 0..watched);
+        }
+        cache.last_block = chain.tip();
+        MayError::ok(updated)
+    }
+
+    fn publish(&self, tx: &Tx) -> Result<(), Self::Error> {
+        self.chain().broadcast(tx.clone());
+        Ok(())
+    }
+
+    fn transaction(&self, txid: Txid) -> Result<Option<Tx>, Self::Error> {
+        Ok(self.chain().txs().find(|tx| tx.txid() == txid).cloned())
+    }
+
+    fn outpoint_status(&self, outpoint: Outpoint) -> Result<OutpointStatus, Self::Error> {
+        let chain = self.chain();
+        let known = chain.txs().any(|tx| {
+            tx.txid() == outpoint.txid && tx.outputs.len() > outpoint.vout_u32() as usize
+        });
+        if !known {
+            return Ok(OutpointStatus::Unknown);
+        }
+        let status = chain
+            .txs()
+            .find(|tx| tx.inputs.iter().any(|input| input.prev_output == outpoint))
+            .map(|tx| OutpointStatus::Spent(tx.txid()))
+            .unwrap_or(OutpointStatus::Unspent);
+        Ok(status)
+    }
+
+    fn fee_snapshot(&self) -> Result<FeeSnapshot, Self::Error> { Ok(none!()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{Keychain, Network, XpubDerivable};
+    use descriptors::Wpkh;
+
+    use super::*;
+    use crate::fixtures::test_descriptor;
+    use crate::Layer2Empty;
+
+    fn descr() -> WalletDescr<XpubDerivable, Wpkh<XpubDerivable>> {
+        WalletDescr::new_standard(test_descriptor(), Network::Testnet3)
+    }
+
+    fn sync(
+        indexer: &SimIndexer,
+        descr: &WalletDescr<XpubDerivable, Wpkh<XpubDerivable>>,
+    ) -> WalletCache<Layer2Empty> {
+        indexer.create::<_, _, crate::NoLayer2>(descr).into_ok()
+    }
+
+    #[test]
+    fn test_reorg() {
+        let descr = descr();
+        let addrs = descr.addresses(Keychain::OUTER).take(11).collect::<Vec<_>>();
+        let indexer = SimIndexer::default();
+        let funding = indexer.chain().fund(addrs[0].addr.script_pubkey(), Sats(50_000));
+        indexer.publish(&funding).unwrap();
+        indexer.chain().mine(None);
+        // The address beyond the initial gap limit is found once the first one is used
+        let late = indexer.chain().fund(addrs[10].addr.script_pubkey(), Sats(20_000));
+        indexer.publish(&late).unwrap();
+        indexer.chain().mine(None);
+
+        let cache = sync(&indexer, &descr);
+        assert_eq!(cache.utxo.len(), 2);
+        assert_eq!(cache.last_block.height.get(), 2);
+        assert!(cache.tx[&late.txid()].status.is_mined());
+
+        // The last block is re-organized and its transaction returns to the mempool
+        indexer.chain().disconnect(1);
+        indexer.chain().mine_empty(2);
+        let cache = sync(&indexer, &descr);
+        assert_eq!(cache.last_block.height.get(), 3);
+        assert_eq!(cache.tx[&late.txid()].status, TxStatus::Mempool);
+
+        indexer.chain().drop_tx(late.txid());
+        let cache = sync(&indexer, &descr);
+        assert!(!cache.tx.contains_key(&late.txid()));
+        assert_eq!(cache.utxo.len(), 1);
+    }
+
+    #[test]
+    fn test_replacement_and_coinbase() {
+        let descr = descr();
+        let addr = descr.addresses(Keychain::OUTER).next().unwrap();
+        let script = addr.addr.script_pubkey();
+        let indexer = SimIndexer::default();
+        let block_hash = indexer.chain().mine(Some((script.clone(), Sats(5_000_000_000))));
+
+        let first = indexer.chain().fund(script.clone(), Sats(10_000));
+        let mut replacement = first.clone();
+        replacement.outputs = VarIntArray::from_checked(vec![TxOut::new(script, Sats(9_000))]);
+        indexer.publish(&first).unwrap();
+        assert_eq!(indexer.chain().broadcast(replacement.clone()), vec![first.txid()]);
+        assert_eq!(
+            indexer.outpoint_status(first.inputs[0].prev_output).unwrap(),
+            OutpointStatus::Unknown
+        );
+
+        let cache = sync(&indexer, &descr);
+        assert!(!cache.tx.contains_key(&first.txid()));
+        assert_eq!(cache.tx[&replacement.txid()].status, TxStatus::Mempool);
+        let coinbase = cache
+            .tx
+            .values()
+            .find(|tx| matches!(tx.status, TxStatus::Mined(info) if info.block_hash == block_hash))
+            .unwrap();
+        assert!(coinbase.inputs[0].coinbase);
+        assert_eq!(
+            indexer.outpoint_status(coinbase.outputs[0].outpoint).unwrap(),
+            OutpointStatus::Unspent
+        );
+    }
+}
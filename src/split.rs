@@ -0,0 +1,212 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Splitting of the wallet funds between beneficiaries paid with a share of the balance.
+
+use std::collections::BTreeMap;
+
+use bpstd::{Address, Outpoint, Sats};
+use psbt::{Beneficiary, ConstructionError, Payment, PsbtConstructor};
+
+use crate::standardness::dust_threshold;
+use crate::Share;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SplitError {
+    /// {0}
+    #[from]
+    Construction(ConstructionError),
+
+    /// beneficiary #{0} has a fixed payment amount and can't receive a share of the balance.
+    FixedAmount(usize),
+
+    /// percentage shares sum up to {0}%, exceeding the whole balance.
+    PercentOverflow(u32),
+
+    /// share of {address} is {amount} sats, which is below the dust threshold.
+    Dust { address: Address, amount: Sats },
+}
+
+/// Resolves payments of balance shares into fixed amounts.
+pub trait PaymentSplitter: PsbtConstructor {
+    /// Computes amounts of beneficiaries paid with [`Payment::Max`], returning beneficiaries
+    /// having only fixed payments, which can be passed to [`PsbtConstructor::construct_psbt`].
+    ///
+    /// The funds being split are the value of `coins` remaining after the fixed payments and
+    /// the `fee`. The share of each `MAX` beneficiary is taken from `shares` by the beneficiary
+    /// index, defaulting to [`Share::MAX`]. Percentage shares are allocated first, and the rest
+    /// is split between the weighted shares, with the rounding remainder going to the largest
+    /// of them. If there are no weighted shares, the rest is left for the change.
+    ///
+    /// Errors if any of the shares is below the dust threshold of its beneficiary address.
+    fn split_payments(
+        &self,
+        coins: &[Outpoint],
+        beneficiaries: &[Beneficiary],
+        shares: &BTreeMap<usize, Share>,
+        fee: Sats,
+    ) -> Result<Vec<Beneficiary>, SplitError> {
+        if let Some(no) = shares.keys().find(|no| {
+            beneficiaries.get(**no).map(|beneficiary| !beneficiary.is_max()).unwrap_or_default()
+        }) {
+            return Err(SplitError::FixedAmount(*no));
+        }
+
+        let input_value = coins
+            .iter()
+            .map(|coin| self.utxo(*coin).expect("wallet data inconsistency").value)
+            .sum::<Sats>();
+        let output_value = beneficiaries.iter().try_fold(Sats::ZERO, |sum, beneficiary| {
+            sum.checked_add(beneficiary.amount.unwrap_or(Sats::ZERO))
+                .ok_or(ConstructionError::Overflow(sum))
+        })?;
+        let available = input_value
+            .checked_sub(output_value)
+            .ok_or(ConstructionError::OutputExceedsInputs {
+                input_value,
+                output_value,
+            })?
+            .checked_sub(fee)
+            .ok_or(ConstructionError::NoFundsForFee {
+                input_value,
+                output_value,
+                fee,
+            })?;
+
+        let share_of = |no: usize| shares.get(&no).copied().unwrap_or(Share::MAX);
+        let max = beneficiaries
+            .iter()
+            .enumerate()
+            .filter(|(_, beneficiary)| beneficiary.is_max())
+            .map(|(no, _)| (no, share_of(no)));
+        let percents = max
+            .clone()
+            .map(|(_, share)| match share {
+                Share::Percent(percent) => percent as u32,
+                Share::Weight(_) => 0,
+            })
+            .sum::<u32>();
+        if percents > 100 {
+            return Err(SplitError::PercentOverflow(percents));
+        }
+        let weights = max
+            .clone()
+            .map(|(_, share)| match share {
+                Share::Weight(weight) => weight as u64,
+                Share::Percent(_) => 0,
+            })
+            .sum::<u64>();
+
+        let portion = |value: Sats, part: u64, whole: u64| {
+            Sats::from((value.sats() as u128 * part as u128 / whole as u128) as u64)
+        };
+        let mut amounts = BTreeMap::new();
+        for (no, share) in max.clone() {
+            if let Share::Percent(percent) = share {
+                amounts.insert(no, portion(available, percent as u64, 100));
+            }
+        }
+        let rest = available - amounts.values().copied().sum::<Sats>();
+        for (no, share) in max.clone() {
+            if let Share::Weight(weight) = share {
+                amounts.insert(no, portion(rest, weight as u64, weights));
+            }
+        }
+        let largest = max
+            .filter_map(|(no, share)| match share {
+                Share::Weight(weight) => Some((weight, no)),
+                Share::Percent(_) => None,
+            })
+            // The first of the largest shares gets the remainder
+            .max_by_key(|(weight, no)| (*weight, usize::MAX - no))
+            .map(|(_, no)| no);
+        if let Some(no) = largest {
+            let remainder = available - amounts.values().copied().sum::<Sats>();
+            *amounts.get_mut(&no).expect("largest share is present") += remainder;
+        }
+
+        for (no, amount) in &amounts {
+            let address = beneficiaries[*no].address;
+            if amount.sats() < dust_threshold(&address.script_pubkey()) {
+                return Err(SplitError::Dust {
+                    address,
+                    amount: *amount,
+                });
+            }
+        }
+
+        Ok(beneficiaries
+            .iter()
+            .enumerate()
+            .map(|(no, beneficiary)| match amounts.get(&no) {
+                Some(amount) => Beneficiary::new(beneficiary.address, Payment::Fixed(*amount)),
+                None => *beneficiary,
+            })
+            .collect())
+    }
+}
+
+impl<T: PsbtConstructor> PaymentSplitter for T {}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bpstd::{Txid, Vout};
+
+    use super::*;
+    use crate::fixtures::TestCoins;
+
+    #[test]
+    fn test_split_payments() {
+        let coins = TestCoins::new(Sats::from(10_000u64));
+        let outpoint = Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(0));
+        let address = Address::from_str("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        let beneficiaries = [
+            Beneficiary::new(address, Sats::from(1_000u64)),
+            Beneficiary::with_max(address),
+            Beneficiary::with_max(address),
+            Beneficiary::with_max(address),
+        ];
+        let shares = bmap! { 1 => Share::Percent(50), 2 => Share::Weight(2) };
+
+        let amounts = |shares: BTreeMap<usize, Share>| {
+            coins.split_payments(&[outpoint], &beneficiaries, &shares, Sats::from(1_000u64)).map(
+                |beneficiaries| {
+                    beneficiaries
+                        .iter()
+                        .map(|b| b.amount.sats().unwrap().sats())
+                        .collect::<Vec<_>>()
+                },
+            )
+        };
+        assert_eq!(amounts(shares).unwrap(), vec![1_000, 4_000, 2_667, 1_333]);
+        assert_eq!(amounts(bmap! {}).unwrap(), vec![1_000, 2_668, 2_666, 2_666]);
+        assert!(matches!(amounts(bmap! { 0 => Share::MAX }), Err(SplitError::FixedAmount(0))));
+        assert!(matches!(
+            amounts(bmap! { 1 => Share::Percent(90), 2 => Share::Percent(20) }),
+            Err(SplitError::PercentOverflow(110))
+        ));
+        assert!(matches!(amounts(bmap! { 1 => Share::Percent(95) }), Err(SplitError::Dust { .. })));
+    }
+}
@@ -0,0 +1,387 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Local validation of transactions against the standardness rules of the Bitcoin Core nodes,
+//! which allows to refuse publication of transactions the network won't relay with a clear
+//! explanation instead of an opaque indexer error.
+
+use bpstd::{ConsensusEncode, ScriptPubkey, Tx, Weight};
+
+use crate::MAX_STANDARD_TX_WEIGHT;
+
+/// Minimal size of a transaction serialized without witnesses, preventing confusion of
+/// transactions with 64-byte merkle tree nodes.
+pub const MIN_STANDARD_TX_NONWITNESS_SIZE: usize = 65;
+
+/// Maximal size of an input script sig.
+pub const MAX_STANDARD_SCRIPTSIG_SIZE: usize = 1650;
+
+/// Maximal total size of `OP_RETURN` output scripts relayed by Bitcoin Core since v30.
+pub const MAX_OP_RETURN_RELAY: usize = 100_000;
+
+/// Maximal size of an `OP_RETURN` output script, including the opcode itself, relayed by
+/// Bitcoin Core before v30.
+pub const MAX_OP_RETURN_RELAY_LEGACY: usize = 83;
+
+/// Maximal signature operation cost of a transaction.
+pub const MAX_STANDARD_TX_SIGOPS_COST: u32 = 16_000;
+
+/// Fee rate, in sats per virtual byte, used to compute dust thresholds.
+pub const DUST_RELAY_FEE: u64 = 3;
+
+const OP_PUSHDATA1: u8 = 0x4c;
+const OP_PUSHDATA2: u8 = 0x4d;
+const OP_PUSHDATA4: u8 = 0x4e;
+const OP_1: u8 = 0x51;
+const OP_3: u8 = 0x53;
+const OP_16: u8 = 0x60;
+const OP_RETURN: u8 = 0x6a;
+const OP_CHECKSIG: u8 = 0xac;
+const OP_CHECKSIGVERIFY: u8 = 0xad;
+const OP_CHECKMULTISIG: u8 = 0xae;
+const OP_CHECKMULTISIGVERIFY: u8 = 0xaf;
+
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum StandardnessError {
+    /// transaction weight {0} WU exceeds the standard limit of 400000 WU; spend fewer coins or
+    /// split the payment into several transactions.
+    Weight(u32),
+
+    /// transaction has only {0} bytes without witnesses, while standard transactions have at
+    /// least 65 bytes; add another output or spend more coins.
+    TooSmall(usize),
+
+    /// script sig of input #{0} has {1} bytes, exceeding the standard limit of 1650 bytes.
+    ScriptSigSize(u32, usize),
+
+    /// script sig of input #{0} contains operations other than data pushes.
+    ScriptSigNotPushOnly(u32),
+
+    /// output #{0} has a non-standard script; only P2PKH, P2SH, P2PK, witness, bare multisig
+    /// with up to 3 keys and `OP_RETURN` outputs are relayed.
+    NonStandardScript(u32),
+
+    /// `OP_RETURN` outputs have {size} bytes, exceeding the relay limit of {limit} bytes; reduce
+    /// the size of the embedded data.
+    OpReturnSize { size: usize, limit: usize },
+
+    /// transaction has {0} `OP_RETURN` outputs, while the relay policy allows only a single one;
+    /// merge the embedded data into a single output.
+    MultipleOpReturn(usize),
+
+    /// output #{vout} of {value} sats is below the dust threshold of {threshold} sats; increase
+    /// the amount or remove the output.
+    Dust {
+        vout: u32,
+        value: u64,
+        threshold: u64,
+    },
+
+    /// transaction signature operations cost {0} exceeds the standard limit of 16000.
+    SigOps(u32),
+}
+
+/// Relay policy options which differ between the Bitcoin Core versions and node configurations.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RelayPolicy {
+    /// Maximal total size of the `OP_RETURN` output scripts, including the opcodes.
+    pub max_op_return_size: usize,
+    /// Whether only a single `OP_RETURN` output is allowed.
+    pub single_op_return: bool,
+}
+
+impl RelayPolicy {
+    /// Policy of Bitcoin Core v30 and later with the default configuration.
+    pub const CURRENT: RelayPolicy = RelayPolicy {
+        max_op_return_size: MAX_OP_RETURN_RELAY,
+        single_op_return: false,
+    };
+
+    /// Policy of Bitcoin Core before v30 with the default configuration.
+    pub const LEGACY: RelayPolicy = RelayPolicy {
+        max_op_return_size: MAX_OP_RETURN_RELAY_LEGACY,
+        single_op_return: true,
+    };
+}
+
+impl Default for RelayPolicy {
+    fn default() -> Self { RelayPolicy::CURRENT }
+}
+
+/// Checks the transaction against the standardness rules of the current Bitcoin Core version,
+/// see [`check_with`].
+pub fn check(tx: &Tx) -> Result<(), StandardnessError> { check_with(tx, RelayPolicy::CURRENT) }
+
+/// Checks the transaction against the standardness rules with the given relay policy, returning
+/// the first violation found.
+///
+/// Signature operations are counted in the scripts of the transaction itself; operations of the
+/// spent outputs, redeem scripts and witnesses, which require the spent outputs to be known,
+/// are not counted.
+pub fn check_with(tx: &Tx, policy: RelayPolicy) -> Result<(), StandardnessError> {
+    let weight = tx.weight_units().to_u32();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        return Err(StandardnessError::Weight(weight));
+    }
+    // Weight counts the non-witness data four times and the witness data once
+    let total = tx.consensus_serialize().len();
+    let base = (weight as usize - total) / 3;
+    if base < MIN_STANDARD_TX_NONWITNESS_SIZE {
+        return Err(StandardnessError::TooSmall(base));
+    }
+
+    let mut sigops = 0u32;
+    for (vin, input) in tx.inputs.iter().enumerate() {
+        let script = input.sig_script.as_slice();
+        if script.len() > MAX_STANDARD_SCRIPTSIG_SIZE {
+            return Err(StandardnessError::ScriptSigSize(vin as u32, script.len()));
+        }
+        match parse_ops(script) {
+            Some(ops) if ops.iter().all(|(op, _)| *op <= OP_16) => {}
+            _ => return Err(StandardnessError::ScriptSigNotPushOnly(vin as u32)),
+        }
+        sigops += legacy_sigops(script);
+    }
+
+    let mut op_returns = 0usize;
+    let mut op_return_size = 0usize;
+    for (vout, output) in tx.outputs.iter().enumerate() {
+        let script = output.script_pubkey.as_slice();
+        let vout = vout as u32;
+        if is_null_data(script) {
+            op_returns += 1;
+            op_return_size += script.len();
+            continue;
+        }
+        if !is_standard_script(script) {
+            return Err(StandardnessError::NonStandardScript(vout));
+        }
+        let threshold = dust_threshold(&output.script_pubkey);
+        if output.value.sats() < threshold {
+            return Err(StandardnessError::Dust {
+                vout,
+                value: output.value.sats(),
+                threshold,
+            });
+        }
+        sigops += legacy_sigops(script);
+    }
+    if op_return_size > policy.max_op_return_size {
+        return Err(StandardnessError::OpReturnSize {
+            size: op_return_size,
+            limit: policy.max_op_return_size,
+        });
+    }
+    if policy.single_op_return && op_returns > 1 {
+        return Err(StandardnessError::MultipleOpReturn(op_returns));
+    }
+
+    // Legacy signature operations are scaled by the witness discount factor
+    if sigops * 4 > MAX_STANDARD_TX_SIGOPS_COST {
+        return Err(StandardnessError::SigOps(sigops * 4));
+    }
+    Ok(())
+}
+
+/// Computes the value, in sats, below which the output is considered dust: spending the output
+/// at [`DUST_RELAY_FEE`] rate would cost more than a third of its value.
+pub fn dust_threshold(script: &ScriptPubkey) -> u64 {
+    let script = script.as_slice();
+    if is_null_data(script) {
+        return 0;
+    }
+    // value, script length prefix and the script itself
+    let output = 8 + if script.len() < 0xFD { 1 } else { 3 } + script.len();
+    // outpoint, script sig length, sequence number and the signature: in a witness for the
+    // witness programs, and in the script sig otherwise
+    let input = match witness_program(script).is_some() {
+        true => 32 + 4 + 1 + 4 + 107 / 4,
+        false => 32 + 4 + 1 + 4 + 107,
+    };
+    (output + input) as u64 * DUST_RELAY_FEE
+}
+
+/// Splits the script into operations, each with the data it pushes. Returns `None` if a push
+/// overruns the script.
+fn parse_ops(script: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut ops = vec![];
+    let mut pos = 0usize;
+    while pos < script.len() {
+        let op = script[pos];
+        pos += 1;
+        let len = match op {
+            0x01..=0x4b => op as usize,
+            OP_PUSHDATA1 => {
+                let len = *script.get(pos)? as usize;
+                pos += 1;
+                len
+            }
+            OP_PUSHDATA2 => {
+                let len = u16::from_le_bytes(script.get(pos..pos + 2)?.try_into().ok()?);
+                pos += 2;
+                len as usize
+            }
+            OP_PUSHDATA4 => {
+                let len = u32::from_le_bytes(script.get(pos..pos + 4)?.try_into().ok()?);
+                pos += 4;
+                len as usize
+            }
+            _ => 0,
+        };
+        let data = script.get(pos..pos.checked_add(len)?)?;
+        pos += len;
+        ops.push((op, data));
+    }
+    Some(ops)
+}
+
+/// Detects the witness program, returning its version.
+fn witness_program(script: &[u8]) -> Option<u8> {
+    let (&version, program) = script.split_first()?;
+    let (&len, program) = program.split_first()?;
+    let version = match version {
+        0 => 0,
+        OP_1..=OP_16 => version - OP_1 + 1,
+        _ => return None,
+    };
+    (matches!(len, 2..=40) && program.len() == len as usize).then_some(version)
+}
+
+fn is_null_data(script: &[u8]) -> bool {
+    match script.split_first() {
+        Some((&OP_RETURN, data)) => {
+            matches!(parse_ops(data), Some(ops) if ops.iter().all(|(op, _)| *op <= OP_16))
+        }
+        _ => false,
+    }
+}
+
+fn is_standard_script(script: &[u8]) -> bool {
+    let p2pkh = script.len() == 25
+        && script[..3] == [0x76, 0xa9, 0x14]
+        && script[23..] == [0x88, OP_CHECKSIG];
+    let p2sh = script.len() == 23 && script[..2] == [0xa9, 0x14] && script[22] == 0x87;
+    if p2pkh || p2sh || witness_program(script).is_some() {
+        return true;
+    }
+    let Some(ops) = parse_ops(script) else {
+        return false;
+    };
+    let is_key = |(op, data): &(u8, &[u8])| (*op == 33 || *op == 65) && data.len() == *op as usize;
+    match ops.as_slice() {
+        // P2PK
+        [key, (OP_CHECKSIG, _)] => is_key(key),
+        // Bare multisig
+        [(m, _), keys @ .., (n, _), (OP_CHECKMULTISIG, _)] => {
+            (OP_1..=OP_3).contains(n)
+                && (OP_1..=*n).contains(m)
+                && keys.len() == (*n - OP_1 + 1) as usize
+                && keys.iter().all(is_key)
+        }
+        _ => false,
+    }
+}
+
+/// Counts signature operations the way it is done for the legacy scripts, where each
+/// `OP_CHECKMULTISIG` is counted as 20 operations.
+fn legacy_sigops(script: &[u8]) -> u32 {
+    let Some(ops) = parse_ops(script) else {
+        return 0;
+    };
+    ops.iter()
+        .map(|(op, _)| match *op {
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => 1,
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => 20,
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{LockTime, Sats, TxOut, TxVer, VarIntArray};
+
+    use super::*;
+
+    fn script(bytes: Vec<u8>) -> ScriptPubkey { ScriptPubkey::from_unsafe(bytes) }
+
+    fn tx(outputs: Vec<(Vec<u8>, u64)>) -> Tx {
+        Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::new(),
+            outputs: VarIntArray::from_checked(
+                outputs
+                    .into_iter()
+                    .map(|(bytes, value)| TxOut::new(script(bytes), Sats(value)))
+                    .collect(),
+            ),
+            lock_time: LockTime::ZERO,
+        }
+    }
+
+    #[test]
+    fn test_dust_threshold() {
+        let p2wpkh = [vec![0x00, 0x14], vec![0; 20]].concat();
+        let p2tr = [vec![OP_1, 0x20], vec![0; 32]].concat();
+        let p2pkh = [vec![0x76, 0xa9, 0x14], vec![0; 20], vec![0x88, OP_CHECKSIG]].concat();
+        assert_eq!(dust_threshold(&script(p2wpkh)), 294);
+        assert_eq!(dust_threshold(&script(p2tr)), 330);
+        assert_eq!(dust_threshold(&script(p2pkh)), 546);
+        assert_eq!(dust_threshold(&script(vec![OP_RETURN, 0x01, 0xff])), 0);
+    }
+
+    #[test]
+    fn test_check() {
+        let p2wpkh = [vec![0x00, 0x14], vec![0; 20]].concat();
+        let op_return =
+            |len: usize| [vec![OP_RETURN, OP_PUSHDATA1, len as u8], vec![0; len]].concat();
+        assert_eq!(check(&tx(vec![(p2wpkh.clone(), 1000)])), Err(StandardnessError::TooSmall(41)));
+        assert_eq!(check(&tx(vec![(p2wpkh.clone(), 1000), (op_return(80), 0)])), Ok(()));
+        assert_eq!(check(&tx(vec![(p2wpkh.clone(), 1000), (op_return(81), 0)])), Ok(()));
+        assert_eq!(
+            check_with(&tx(vec![(p2wpkh.clone(), 1000), (op_return(81), 0)]), RelayPolicy::LEGACY),
+            Err(StandardnessError::OpReturnSize {
+                size: 84,
+                limit: 83
+            })
+        );
+        assert_eq!(
+            check(&tx(vec![(p2wpkh.clone(), 1000), (p2wpkh.clone(), 293)])),
+            Err(StandardnessError::Dust {
+                vout: 1,
+                value: 293,
+                threshold: 294
+            })
+        );
+        assert_eq!(
+            check(&tx(vec![(p2wpkh.clone(), 1000), (vec![0x51; 30], 1000)])),
+            Err(StandardnessError::NonStandardScript(1))
+        );
+        let op_returns = tx(vec![(p2wpkh, 1000), (op_return(10), 0), (op_return(10), 0)]);
+        assert_eq!(check(&op_returns), Ok(()));
+        assert_eq!(
+            check_with(&op_returns, RelayPolicy::LEGACY),
+            Err(StandardnessError::MultipleOpReturn(2))
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Lightweight summary of the wallet cache.
+//!
+//! The summary is persisted next to the cache and contains only the data required to report
+//! the wallet balances, so it can be loaded without deserializing the transaction bodies, which
+//! make up most of the cache size.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(feature = "serde")]
+use bpstd::Txid;
+use bpstd::{Keychain, Outpoint, Sats};
+use nonasync::persistence::{PersistenceError, PersistenceProvider};
+
+#[cfg(feature = "serde")]
+use crate::WalletTx;
+use crate::{Layer2Cache, MiningInfo, WalletAddr, WalletCache};
+
+/// Summary of the wallet cache: the last known block, address statistics and unspent outputs,
+/// without the transaction bodies. See [`WalletCache::load_summary`].
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(crate = "serde_crate", rename_all = "camelCase")
+)]
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct CacheSummary {
+    pub last_block: MiningInfo,
+    pub addr: BTreeMap<Keychain, BTreeSet<WalletAddr>>,
+    /// Values of the unspent outputs.
+    pub utxo: BTreeMap<Outpoint, Sats>,
+}
+
+impl CacheSummary {
+    /// Total value of the unspent outputs.
+    pub fn balance(&self) -> Sats { self.utxo.values().copied().sum() }
+
+    pub fn address_balance(&self) -> impl Iterator<Item = WalletAddr> + '_ {
+        self.addr.values().flat_map(|set| set.iter()).copied()
+    }
+}
+
+/// Parts of the persisted wallet cache required to compute its summary, which can be read
+/// regardless of the layer 2 cache type.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+#[serde(crate = "serde_crate", rename_all = "camelCase")]
+pub(crate) struct CacheParts {
+    last_block: MiningInfo,
+    tx: BTreeMap<Txid, WalletTx>,
+    utxo: BTreeSet<Outpoint>,
+    addr: BTreeMap<Keychain, BTreeSet<WalletAddr>>,
+}
+
+#[cfg(feature = "serde")]
+impl From<CacheParts> for CacheSummary {
+    fn from(parts: CacheParts) -> Self {
+        let utxo = parts
+            .utxo
+            .into_iter()
+            .filter_map(|outpoint| {
+                let tx = parts.tx.get(&outpoint.txid)?;
+                Some((outpoint, tx.outputs.get(outpoint.vout_usize())?.value))
+            })
+            .collect();
+        CacheSummary {
+            last_block: parts.last_block,
+            addr: parts.addr,
+            utxo,
+        }
+    }
+}
+
+impl<L2: Layer2Cache> WalletCache<L2> {
+    /// Builds the summary of the cache.
+    pub fn summary(&self) -> CacheSummary {
+        CacheSummary {
+            last_block: self.last_block,
+            addr: self.addr.clone(),
+            utxo: self.utxos().map(|utxo| (utxo.outpoint, utxo.value)).collect(),
+        }
+    }
+
+    /// Loads only the cache summary, which is enough for reporting wallet balances, without
+    /// loading the transaction bodies.
+    pub fn load_summary(
+        provider: &impl PersistenceProvider<CacheSummary>,
+    ) -> Result<CacheSummary, PersistenceError> {
+        provider.load()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Layer2Empty;
+
+    #[test]
+    fn test_summary_balance() {
+        let cache = WalletCache::<Layer2Empty>::new_nonsync();
+        let mut summary = cache.summary();
+        assert_eq!(summary.balance(), Sats::ZERO);
+        assert_eq!(summary.address_balance().count(), 0);
+
+        summary.utxo.insert(Outpoint::coinbase(), Sats(1000));
+        summary.utxo.insert(Outpoint::new(bpstd::Txid::coinbase(), 1), Sats(500));
+        assert_eq!(summary.balance(), Sats(1500));
+    }
+}
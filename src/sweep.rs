@@ -0,0 +1,169 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sweeping of the wallet funds into the addresses of another wallet or descriptor.
+
+use bpstd::{Address, Descriptor, Outpoint, Sats, SeqNo};
+use psbt::{Beneficiary, ConstructionError, Psbt, PsbtConstructor, TxParams};
+
+use crate::{satisfaction_weight, DeductError, FeeDeductor, FeeSpec};
+
+/// Maximal weight of a transaction relayed by the nodes under the standardness rules.
+pub const MAX_STANDARD_TX_WEIGHT: u32 = 400_000;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum SweepError {
+    /// {0}
+    #[from]
+    Construction(ConstructionError),
+
+    /// {0}
+    #[from]
+    Deduct(DeductError),
+
+    /// no coins are given to sweep.
+    NoCoins,
+
+    /// sweeping requires {required} transactions, but only {provided} target addresses are
+    /// given.
+    NoTargets { required: usize, provided: usize },
+}
+
+/// Moves the whole value of the coins to other addresses, using multiple transactions when the
+/// coins don't fit into a single standard one.
+pub trait WalletSweeper: FeeDeductor {
+    /// Maximal number of inputs of a sweeping transaction, which keeps its weight within
+    /// [`MAX_STANDARD_TX_WEIGHT`].
+    fn max_sweep_inputs(&self) -> usize {
+        // outpoint, script sig length and sequence number
+        let input = (32 + 4 + 1 + 4) * 4 + satisfaction_weight(self.descriptor().class());
+        // version, input and output counts, lock time, segwit marker and flag, and a single
+        // output with a script of up to 43 bytes (P2WSH or P2TR)
+        let overhead = (4 + 3 + 1 + 4) * 4 + 2 + (8 + 1 + 43) * 4;
+        ((MAX_STANDARD_TX_WEIGHT - overhead) / input) as usize
+    }
+
+    /// Splits the coins into chunks spent by the separate sweeping transactions, such that the
+    /// transactions have nearly equal number of inputs.
+    fn sweep_chunks<'c>(&self, coins: &'c [Outpoint]) -> Vec<&'c [Outpoint]> {
+        if coins.is_empty() {
+            return vec![];
+        }
+        let count = coins.len().div_ceil(self.max_sweep_inputs());
+        coins.chunks(coins.len().div_ceil(count)).collect()
+    }
+
+    /// Constructs transactions spending the chunks of the coins (see
+    /// [`WalletSweeper::sweep_chunks`]), each paying the whole value to the next of the
+    /// `targets` addresses. The fee is deducted from the payment; an absolute fee is paid by each
+    /// of the transactions. Returns PSBTs together with their fees.
+    fn sweep(
+        &mut self,
+        coins: &[Outpoint],
+        targets: &[Address],
+        fee: FeeSpec,
+        seq_no: SeqNo,
+    ) -> Result<Vec<(Psbt, Sats)>, SweepError> {
+        let chunks = self.sweep_chunks(coins);
+        if chunks.is_empty() {
+            return Err(SweepError::NoCoins);
+        }
+        if targets.len() < chunks.len() {
+            return Err(SweepError::NoTargets {
+                required: chunks.len(),
+                provided: targets.len(),
+            });
+        }
+        let mut params = TxParams::with(Sats::ZERO);
+        params.seq_no = seq_no;
+        chunks
+            .into_iter()
+            .zip(targets)
+            .map(|(chunk, target)| {
+                let beneficiary = Beneficiary::with_max(*target);
+                let (mut psbt, _) =
+                    self.construct_psbt(chunk.iter().copied(), &[beneficiary], params)?;
+                let fee = match fee {
+                    FeeSpec::Absolute(fee) => {
+                        self.deduct_fee(&mut psbt, fee, &[0])?;
+                        fee
+                    }
+                    FeeSpec::Rate(fee_rate) => self.deduct_fee_rate(&mut psbt, fee_rate, &[0])?,
+                };
+                Ok((psbt, fee))
+            })
+            .collect()
+    }
+}
+
+impl<T: PsbtConstructor> WalletSweeper for T {}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bpstd::{Txid, Vout};
+
+    use super::*;
+    use crate::fixtures::{test_payee, TestCoins};
+    use crate::FeeRate;
+
+    #[test]
+    fn test_sweep() {
+        let mut coins = TestCoins::new(Sats(10_000));
+        let max = coins.max_sweep_inputs();
+        assert_eq!(max, 1469);
+        let outpoints = (0..max as u32 + 1)
+            .map(|vout| Outpoint::new(Txid::from([0u8; 32]), Vout::from_u32(vout)))
+            .collect::<Vec<_>>();
+        let chunks = coins.sweep_chunks(&outpoints);
+        assert_eq!(chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(), vec![735, 735]);
+
+        let target = test_payee();
+        let fee_rate = FeeRate::from_str("2sat/vb").unwrap();
+        assert!(matches!(
+            coins.sweep(&outpoints, &[target], FeeSpec::Rate(fee_rate), SeqNo::ZERO),
+            Err(SweepError::NoTargets {
+                required: 2,
+                provided: 1
+            })
+        ));
+        let swept = coins
+            .sweep(&outpoints, &[target, target], FeeSpec::Rate(fee_rate), SeqNo::ZERO)
+            .unwrap();
+        assert_eq!(swept.len(), 2);
+        for ((psbt, fee), chunk) in swept.iter().zip(&chunks) {
+            assert_eq!(psbt.outputs().count(), 1);
+            assert!(coins.estimate_weight(psbt) <= MAX_STANDARD_TX_WEIGHT);
+            assert_eq!(psbt.fee(), Some(*fee));
+            assert_eq!(
+                psbt.outputs().next().unwrap().amount,
+                Sats(10_000 * chunk.len() as u64) - *fee
+            );
+        }
+        assert!(matches!(
+            coins.sweep(&[], &[target], FeeSpec::Absolute(Sats(500)), SeqNo::ZERO),
+            Err(SweepError::NoCoins)
+        ));
+    }
+}
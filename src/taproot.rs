@@ -0,0 +1,131 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bpstd::{AddressPayload, DerivedAddr, DerivedScript, InternalPk, Outpoint, OutputPk, Terminal};
+use descriptors::Descriptor;
+use psbt::PsbtConstructor;
+
+use crate::{Layer2, Wallet};
+
+/// Computes BIP-86 output key, which is the internal key tweaked without committing to any
+/// script tree.
+#[inline]
+pub fn bip86_output_key(internal_key: InternalPk) -> OutputPk { internal_key.to_output_pk(None).0 }
+
+/// Extracts taproot output key from an address, if the address is a P2TR one.
+pub fn address_output_key(addr: &DerivedAddr) -> Option<OutputPk> {
+    match addr.addr.payload {
+        AddressPayload::Tr(output_key) => Some(output_key),
+        _ => None,
+    }
+}
+
+/// Result of verifying a P2TR coin against the wallet descriptor.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum TaprootAuditStatus {
+    /// output key matches BIP-86 tweak of the internal key
+    Verified,
+
+    /// the descriptor commits to script paths for the internal key {0}
+    ScriptPaths(InternalPk),
+
+    /// the descriptor doesn't produce a taproot script for the coin terminal
+    NotTaproot,
+
+    /// output key doesn't match BIP-86 tweak {expected} of the internal key {internal_key}
+    Mismatch {
+        internal_key: InternalPk,
+        expected: OutputPk,
+    },
+}
+
+impl TaprootAuditStatus {
+    #[inline]
+    pub fn is_verified(&self) -> bool { matches!(self, TaprootAuditStatus::Verified) }
+}
+
+/// Audit record for a single P2TR coin of the wallet.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TaprootAudit {
+    pub outpoint: Outpoint,
+    pub terminal: Terminal,
+    pub output_key: OutputPk,
+    pub status: TaprootAuditStatus,
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
+    /// Verifies that the output key of each P2TR coin of the wallet is a BIP-86 tweak of the
+    /// internal key derived from the wallet descriptor, i.e. that the coin can't be spent using
+    /// some hidden script path. Coins of other types are skipped.
+    pub fn audit_taproot(&self) -> Vec<TaprootAudit> {
+        self.coins()
+            .filter_map(|coin| {
+                let output_key = address_output_key(&coin.address)?;
+                let terminal = coin.address.terminal;
+                let status = match self.descriptor().derive(terminal.keychain, terminal.index) {
+                    DerivedScript::TaprootKeyOnly(internal_key) => {
+                        let expected = bip86_output_key(internal_key);
+                        if expected == output_key {
+                            TaprootAuditStatus::Verified
+                        } else {
+                            TaprootAuditStatus::Mismatch {
+                                internal_key,
+                                expected,
+                            }
+                        }
+                    }
+                    DerivedScript::TaprootScript(internal_key, _) => {
+                        TaprootAuditStatus::ScriptPaths(internal_key)
+                    }
+                    _ => TaprootAuditStatus::NotTaproot,
+                };
+                Some(TaprootAudit {
+                    outpoint: coin.outpoint,
+                    terminal,
+                    output_key,
+                    status,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_bip86_tweak() {
+        // Test vector from BIP-86 for m/86'/0'/0'/0/0
+        let internal_key = InternalPk::from_str(
+            "cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115",
+        )
+        .unwrap();
+        let output_key =
+            OutputPk::from_str("a60869f0dbcf1dc659c9cecbaf8050135ea9e8cdc487053f1dc6880949dc684c")
+                .unwrap();
+        assert_eq!(bip86_output_key(internal_key), output_key);
+    }
+}
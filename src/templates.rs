@@ -0,0 +1,193 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Named wallet templates expanding into wallet descriptors with the standard derivation
+//! schemes for their keys.
+
+use bpstd::{DerivationPath, XpubDerivable, XpubFp};
+use descriptors::{Descriptor, StdDescr, TrKey, Wpkh};
+
+use crate::{Bip43, DerivationStandard};
+
+/// Errors expanding a wallet template into a descriptor.
+#[derive(Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum TemplateError {
+    /// template {0} is a placeholder; its descriptor is not supported by the wallet yet.
+    Unsupported(WalletTemplate),
+
+    /// template {template} requires {expected} key(s), while {provided} were given.
+    KeyCount {
+        template: WalletTemplate,
+        expected: usize,
+        provided: usize,
+    },
+
+    /// key {key} is not derived according to {scheme}; keys of the template must have
+    /// `{derivation}` origin.
+    Derivation {
+        key: XpubFp,
+        scheme: Bip43,
+        derivation: String,
+    },
+}
+
+/// Named wallet setup, selecting the descriptor type and the derivation scheme of its keys.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[cfg_attr(feature = "clap", derive(ValueEnum))]
+pub enum WalletTemplate {
+    /// Single-key native segwit wallet with BIP-84 key derivation.
+    #[display("single-sig-segwit")]
+    SingleSigSegwit,
+
+    /// Single-key taproot wallet with BIP-86 key derivation.
+    #[display("single-sig-taproot")]
+    SingleSigTaproot,
+
+    /// Two-of-three native segwit multisig with sorted keys and BIP-48 key derivation.
+    #[display("2of3-native-multisig")]
+    #[cfg_attr(feature = "clap", value(name = "2of3-native-multisig"))]
+    Multisig2of3,
+
+    /// Two-of-two multisig which decays into a single recovery key after about a year without
+    /// spending.
+    #[display("decaying-multisig")]
+    DecayingMultisig,
+}
+
+impl WalletTemplate {
+    /// All the known templates.
+    pub const ALL: [WalletTemplate; 4] = [
+        WalletTemplate::SingleSigSegwit,
+        WalletTemplate::SingleSigTaproot,
+        WalletTemplate::Multisig2of3,
+        WalletTemplate::DecayingMultisig,
+    ];
+
+    /// Descriptor the template expands into, with the key placeholders.
+    pub fn descriptor(self) -> &'static str {
+        match self {
+            WalletTemplate::SingleSigSegwit => "wpkh(KEY)",
+            WalletTemplate::SingleSigTaproot => "tr(KEY)",
+            WalletTemplate::Multisig2of3 => "wsh(sortedmulti(2,KEY1,KEY2,KEY3))",
+            WalletTemplate::DecayingMultisig => {
+                "wsh(or_d(multi(2,KEY1,KEY2),and_v(v:pk(RECOVERY),older(52560))))"
+            }
+        }
+    }
+
+    /// Derivation scheme of the template keys.
+    pub fn scheme(self) -> Bip43 {
+        match self {
+            WalletTemplate::SingleSigSegwit => Bip43::WPKH,
+            WalletTemplate::SingleSigTaproot => Bip43::TR_SINGLE,
+            WalletTemplate::Multisig2of3 => Bip43::MULTI_WSH,
+            WalletTemplate::DecayingMultisig => Bip43::DESCRIPTOR,
+        }
+    }
+
+    /// Number of keys the template requires.
+    pub fn keys(self) -> usize {
+        match self {
+            WalletTemplate::SingleSigSegwit | WalletTemplate::SingleSigTaproot => 1,
+            WalletTemplate::Multisig2of3 | WalletTemplate::DecayingMultisig => 3,
+        }
+    }
+
+    /// Checks whether the wallet supports descriptors of the template.
+    pub fn is_supported(self) -> bool {
+        matches!(self, WalletTemplate::SingleSigSegwit | WalletTemplate::SingleSigTaproot)
+    }
+
+    /// Expands the template into a wallet descriptor using the provided keys, checking that
+    /// the keys are derived according to the template derivation scheme for the network.
+    pub fn expand(self, keys: &[XpubDerivable], testnet: bool) -> Result<StdDescr, TemplateError> {
+        if !self.is_supported() {
+            return Err(TemplateError::Unsupported(self));
+        }
+        if keys.len() != self.keys() {
+            return Err(TemplateError::KeyCount {
+                template: self,
+                expected: self.keys(),
+                provided: keys.len(),
+            });
+        }
+        let descriptor: StdDescr = match self {
+            WalletTemplate::SingleSigSegwit => Wpkh::from(keys[0].clone()).into(),
+            _ => TrKey::from(keys[0].clone()).into(),
+        };
+        let scheme = self.scheme();
+        for xpub in descriptor.xpubs() {
+            let path: DerivationPath = xpub.to_derivation();
+            if Bip43::deduce(&path) != Some(scheme) || scheme.is_testnet(&path) != Ok(testnet) {
+                return Err(TemplateError::Derivation {
+                    key: xpub.master_fp(),
+                    scheme,
+                    derivation: scheme.account_template_string(testnet),
+                });
+            }
+        }
+        Ok(descriptor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    const KEY: &str = "[643a7adc/86h/1h/0h]tpubDCNiWHaiSkgnQjuhsg9kjwaUzaxQjUcmhagvYzqQ3TYJTgFGJstVaqnu4yhtFktBhCVFmBNLQ5sN53qKzZbMksm3XEyGJsEhQPfVZdWmTE2/<0;1>/*";
+
+    #[test]
+    fn test_expand() {
+        let key = XpubDerivable::from_str(KEY).unwrap();
+        let keys = [key.clone()];
+
+        let descriptor = WalletTemplate::SingleSigTaproot.expand(&keys, true).unwrap();
+        assert_eq!(descriptor, TrKey::from(key.clone()).into());
+
+        assert!(matches!(
+            WalletTemplate::SingleSigTaproot.expand(&keys, false),
+            Err(TemplateError::Derivation { .. })
+        ));
+        assert!(matches!(
+            WalletTemplate::SingleSigSegwit.expand(&keys, true),
+            Err(TemplateError::Derivation {
+                scheme: Bip43::Bip84,
+                ..
+            })
+        ));
+        assert_eq!(
+            WalletTemplate::SingleSigTaproot.expand(&[key.clone(), key], true),
+            Err(TemplateError::KeyCount {
+                template: WalletTemplate::SingleSigTaproot,
+                expected: 1,
+                provided: 2
+            })
+        );
+        assert_eq!(
+            WalletTemplate::Multisig2of3.expand(&keys, true),
+            Err(TemplateError::Unsupported(WalletTemplate::Multisig2of3))
+        );
+    }
+}
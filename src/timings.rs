@@ -0,0 +1,150 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Phase of a wallet operation, for which the time spent is recorded by [`Timings`].
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Display)]
+pub enum Phase {
+    /// Loading the wallet from the persistence provider.
+    #[display("load")]
+    Load,
+
+    /// Deriving wallet addresses from the descriptor.
+    #[display("derive")]
+    Derive,
+
+    /// Network request to the indexer, grouped by the class of the call.
+    #[display("fetch {0}")]
+    Fetch(&'static str),
+
+    /// Re-building wallet cache from the fetched data.
+    #[display("cache rebuild")]
+    Rebuild,
+
+    /// Saving the wallet to the persistence provider.
+    #[display("store")]
+    Store,
+}
+
+/// Total duration and number of measurements of a single phase.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct PhaseTiming {
+    pub total: Duration,
+    pub count: u32,
+}
+
+impl PhaseTiming {
+    /// Average duration of a single measurement.
+    pub fn average(&self) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        self.total / self.count
+    }
+}
+
+/// Durations of the phases of a wallet operation, used to diagnose slow operations.
+///
+/// The [`Display`] implementation prints a summary table with a row per phase.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Timings(BTreeMap<Phase, PhaseTiming>);
+
+impl Timings {
+    #[inline]
+    pub fn new() -> Self { Self::default() }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.0.is_empty() }
+
+    /// Adds the measured duration to the phase.
+    pub fn record(&mut self, phase: Phase, elapsed: Duration) {
+        let timing = self.0.entry(phase).or_default();
+        timing.total += elapsed;
+        timing.count += 1;
+    }
+
+    /// Runs the closure, recording its duration for the phase.
+    pub fn measure<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.record(phase, start.elapsed());
+        res
+    }
+
+    /// Adds all measurements from the other timings.
+    pub fn extend(&mut self, other: &Timings) {
+        for (phase, timing) in other.iter() {
+            let total = self.0.entry(phase).or_default();
+            total.total += timing.total;
+            total.count += timing.count;
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, phase: Phase) -> Option<PhaseTiming> { self.0.get(&phase).copied() }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Phase, PhaseTiming)> + '_ {
+        self.0.iter().map(|(phase, timing)| (*phase, *timing))
+    }
+
+    /// Total time spent in all the phases.
+    pub fn total(&self) -> Duration { self.0.values().map(|timing| timing.total).sum() }
+}
+
+impl Display for Timings {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<24}\t{:>8}\t{:>10}\t{:>10}", "Phase", "Calls", "Total, ms", "Avg, ms")?;
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        for (phase, timing) in self.iter() {
+            writeln!(
+                f,
+                "{:<24}\t{:>8}\t{:>10.1}\t{:>10.1}",
+                phase.to_string(),
+                timing.count,
+                ms(timing.total),
+                ms(timing.average())
+            )?;
+        }
+        write!(f, "{:<24}\t{:>8}\t{:>10.1}", "total", "", ms(self.total()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut timings = Timings::new();
+        timings.record(Phase::Fetch("history"), Duration::from_millis(10));
+        timings.record(Phase::Fetch("history"), Duration::from_millis(30));
+        timings.record(Phase::Load, Duration::from_millis(5));
+        let history = timings.get(Phase::Fetch("history")).unwrap();
+        assert_eq!(history.count, 2);
+        assert_eq!(history.average(), Duration::from_millis(20));
+        assert_eq!(timings.total(), Duration::from_millis(45));
+        assert_eq!(timings.get(Phase::Store), None);
+    }
+}
@@ -0,0 +1,99 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeSet, HashMap};
+
+use bpstd::{Descriptor, IdxBase, ScriptPubkey};
+
+use crate::{Counterparty, Layer2, Layer2Tx, TxRow, Wallet};
+
+/// Index of the script pubkeys belonging to other wallets of the same user, such that payments
+/// between the wallets can be told apart from payments to external counterparties.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct OwnWallets {
+    scripts: HashMap<ScriptPubkey, String>,
+}
+
+impl OwnWallets {
+    pub fn new() -> Self { default!() }
+
+    /// Adds scripts of a wallet under the given name. These include all addresses known to the
+    /// wallet cache and all addresses issued by the wallet, even if they are not used yet.
+    pub fn add<K, D: Descriptor<K>, L2: Layer2>(
+        &mut self,
+        name: impl ToString,
+        wallet: &Wallet<K, D, L2>,
+    ) {
+        let name = name.to_string();
+        for addr in wallet.address_balance() {
+            self.scripts.insert(addr.addr.script_pubkey(), name.clone());
+        }
+        for keychain in wallet.keychains() {
+            let status = wallet.keychain_status(keychain);
+            let issued = status.next_index.max(status.next_published);
+            for derived in wallet.addresses(keychain).take(issued.index() as usize) {
+                self.scripts.insert(derived.addr.script_pubkey(), name.clone());
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.scripts.is_empty() }
+
+    /// Returns name of the wallet owning the script pubkey.
+    pub fn wallet_of(&self, script: &ScriptPubkey) -> Option<&str> {
+        self.scripts.get(script).map(String::as_str)
+    }
+
+    /// Returns name of the wallet which is the counterparty, if any.
+    pub fn counterparty_wallet(&self, counterparty: &Counterparty) -> Option<&str> {
+        counterparty.script_pubkey().and_then(|script| self.wallet_of(&script))
+    }
+
+    /// Returns names of the wallets taking part in the transaction, which makes it an internal
+    /// transfer. Empty set means that the transaction involves only external counterparties.
+    pub fn transfer_wallets<L2: Layer2Tx>(&self, row: &TxRow<L2>) -> BTreeSet<&str> {
+        row.counterparties
+            .iter()
+            .filter_map(|(counterparty, _)| self.counterparty_wallet(counterparty))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::test_wallet;
+
+    #[test]
+    fn test_issued_addresses() {
+        let mut wallet = test_wallet();
+        let issued = wallet.next_address(0, true);
+        let unissued = wallet.next_address(0, false);
+
+        let mut own = OwnWallets::new();
+        own.add("savings", &wallet);
+        assert_eq!(own.counterparty_wallet(&Counterparty::from(issued)), Some("savings"));
+        assert_eq!(own.counterparty_wallet(&Counterparty::from(unissued)), None);
+        assert_eq!(own.counterparty_wallet(&Counterparty::Miner), None);
+    }
+}
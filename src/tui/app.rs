@@ -0,0 +1,224 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bpstd::DerivedAddr;
+use ratatui::crossterm::event::KeyCode;
+
+/// Dashboard tab.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default, Display)]
+pub enum Tab {
+    /// Balance, keychain usage and sync status.
+    #[default]
+    #[display("Overview")]
+    Overview,
+
+    /// Wallet transaction history.
+    #[display("History")]
+    History,
+
+    /// Unspent wallet outputs.
+    #[display("Coins")]
+    Coins,
+}
+
+impl Tab {
+    pub const ALL: [Tab; 3] = [Tab::Overview, Tab::History, Tab::Coins];
+
+    pub fn index(self) -> usize {
+        Self::ALL.iter().position(|tab| *tab == self).unwrap_or_default()
+    }
+
+    fn next(self) -> Self { Self::ALL[(self.index() + 1) % Self::ALL.len()] }
+
+    fn prev(self) -> Self { Self::ALL[(self.index() + Self::ALL.len() - 1) % Self::ALL.len()] }
+}
+
+/// Input fields of the PSBT construction dialog.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ConstructForm {
+    /// Recipient in the same form as used by `construct --to`.
+    pub recipient: String,
+    /// Fee in the same form as used by `construct`.
+    pub fee: String,
+    /// File to save the PSBT to.
+    pub file: String,
+    /// Number of the field being edited.
+    pub focus: usize,
+}
+
+impl ConstructForm {
+    pub const FIELDS: [&'static str; 3] = ["Recipient (amount@address)", "Fee", "PSBT file"];
+
+    pub fn field(&self, no: usize) -> &str {
+        match no {
+            0 => &self.recipient,
+            1 => &self.fee,
+            _ => &self.file,
+        }
+    }
+
+    fn field_mut(&mut self) -> &mut String {
+        match self.focus {
+            0 => &mut self.recipient,
+            1 => &mut self.fee,
+            _ => &mut self.file,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        !self.recipient.trim().is_empty()
+            && !self.fee.trim().is_empty()
+            && !self.file.trim().is_empty()
+    }
+}
+
+/// Dialog shown over the dashboard tabs.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Dialog {
+    /// Newly generated addresses.
+    Address(Vec<DerivedAddr>),
+    /// Construction of a new PSBT.
+    Construct(ConstructForm),
+    /// Key bindings.
+    Help,
+}
+
+/// Action to be performed on the wallet, produced by [`App::handle_key`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Action {
+    Quit,
+    Sync,
+    NewAddress,
+    Construct(ConstructForm),
+}
+
+/// State of the dashboard, which doesn't depend on the wallet data.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct App {
+    pub tab: Tab,
+    /// Number of the first row shown in the tab table.
+    pub scroll: usize,
+    pub dialog: Option<Dialog>,
+    /// Result of the last performed action.
+    pub status: String,
+}
+
+impl App {
+    pub fn new() -> Self {
+        App {
+            status: s!("press ? for help"),
+            ..default!()
+        }
+    }
+
+    pub fn open(&mut self, dialog: Dialog) { self.dialog = Some(dialog); }
+
+    pub fn set_status(&mut self, status: impl ToString) { self.status = status.to_string(); }
+
+    /// Updates the state according to the pressed key, returning the action which has to be
+    /// performed on the wallet, if any.
+    pub fn handle_key(&mut self, key: KeyCode) -> Option<Action> {
+        match (&mut self.dialog, key) {
+            (Some(Dialog::Construct(_)), KeyCode::Esc) => self.dialog = None,
+            (Some(Dialog::Construct(form)), KeyCode::Tab | KeyCode::Down) => {
+                form.focus = (form.focus + 1) % ConstructForm::FIELDS.len()
+            }
+            (Some(Dialog::Construct(form)), KeyCode::BackTab | KeyCode::Up) => {
+                form.focus =
+                    (form.focus + ConstructForm::FIELDS.len() - 1) % ConstructForm::FIELDS.len()
+            }
+            (Some(Dialog::Construct(form)), KeyCode::Backspace) => {
+                form.field_mut().pop();
+            }
+            (Some(Dialog::Construct(form)), KeyCode::Char(c)) => form.field_mut().push(c),
+            (Some(Dialog::Construct(form)), KeyCode::Enter) if form.is_complete() => {
+                let form = form.clone();
+                self.dialog = None;
+                return Some(Action::Construct(form));
+            }
+            (Some(Dialog::Construct(_)), _) => {}
+            (Some(_), _) => self.dialog = None,
+            (None, KeyCode::Char('q') | KeyCode::Esc) => return Some(Action::Quit),
+            (None, KeyCode::Char('?')) => self.open(Dialog::Help),
+            (None, KeyCode::Char('s')) => return Some(Action::Sync),
+            (None, KeyCode::Char('a')) => return Some(Action::NewAddress),
+            (None, KeyCode::Char('c')) => self.open(Dialog::Construct(none!())),
+            (None, KeyCode::Tab | KeyCode::Right) => self.switch(self.tab.next()),
+            (None, KeyCode::BackTab | KeyCode::Left) => self.switch(self.tab.prev()),
+            (None, KeyCode::Down | KeyCode::Char('j')) => self.scroll += 1,
+            (None, KeyCode::Up | KeyCode::Char('k')) => self.scroll = self.scroll.saturating_sub(1),
+            (None, KeyCode::Home) => self.scroll = 0,
+            (None, _) => {}
+        }
+        None
+    }
+
+    fn switch(&mut self, tab: Tab) {
+        self.tab = tab;
+        self.scroll = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_navigation() {
+        let mut app = App::new();
+        assert_eq!(app.handle_key(KeyCode::Left), None);
+        assert_eq!(app.tab, Tab::Coins);
+        app.handle_key(KeyCode::Down);
+        app.handle_key(KeyCode::Down);
+        assert_eq!(app.scroll, 2);
+        app.handle_key(KeyCode::Tab);
+        assert_eq!((app.tab, app.scroll), (Tab::Overview, 0));
+        app.handle_key(KeyCode::Char('?'));
+        assert_eq!(app.handle_key(KeyCode::Char('q')), None);
+        assert_eq!(app.dialog, None);
+        assert_eq!(app.handle_key(KeyCode::Char('q')), Some(Action::Quit));
+    }
+
+    #[test]
+    fn test_construct_dialog() {
+        let mut app = App::new();
+        app.handle_key(KeyCode::Char('c'));
+        for c in "1000@addr".chars() {
+            app.handle_key(KeyCode::Char(c));
+        }
+        assert_eq!(app.handle_key(KeyCode::Enter), None);
+        app.handle_key(KeyCode::Tab);
+        app.handle_key(KeyCode::Char('5'));
+        app.handle_key(KeyCode::Char('0'));
+        app.handle_key(KeyCode::Backspace);
+        app.handle_key(KeyCode::Tab);
+        app.handle_key(KeyCode::Char('q'));
+        let Some(Action::Construct(form)) = app.handle_key(KeyCode::Enter) else {
+            panic!("construction is not requested");
+        };
+        assert_eq!(
+            (form.recipient.as_str(), form.fee.as_str(), form.file.as_str()),
+            ("1000@addr", "5", "q")
+        );
+        assert_eq!(app.dialog, None);
+    }
+}
@@ -0,0 +1,164 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Interactive terminal dashboard of a wallet, run by `bp ui` command.
+//!
+//! The dashboard state is kept by [`App`], which turns key presses into [`Action`]s performed on
+//! the wallet by [`run`]. All wallet data are taken from the library APIs; the dashboard never
+//! parses output of the other commands.
+
+mod app;
+mod view;
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use bpstd::{Network, XpubDerivable};
+use descriptors::Descriptor;
+use psbt::PsbtVer;
+use ratatui::crossterm::event::{self, Event, KeyEventKind};
+use ratatui::DefaultTerminal;
+
+pub use self::app::{Action, App, ConstructForm, Dialog, Tab};
+use crate::cli::{
+    construct, derive_addresses, ConstructParams, DisplayOpts, ExecError, WalletEvent,
+};
+use crate::{parse_fee, parse_recipient, AnyIndexer, Wallet};
+
+/// Runs the dashboard until the user quits it. The terminal is restored on exit, including exit
+/// with an error.
+///
+/// Sync is available only if the indexer is provided. Amounts are masked if the display options
+/// request the discreet mode.
+pub fn run<D: Descriptor>(
+    wallet: &mut Wallet<XpubDerivable, D>,
+    network: Network,
+    indexer: Option<&AnyIndexer>,
+    display: &DisplayOpts,
+) -> Result<(), ExecError> {
+    let mut terminal = ratatui::init();
+    let res = event_loop(&mut terminal, wallet, network, indexer, display);
+    ratatui::restore();
+    res
+}
+
+fn event_loop<D: Descriptor>(
+    terminal: &mut DefaultTerminal,
+    wallet: &mut Wallet<XpubDerivable, D>,
+    network: Network,
+    indexer: Option<&AnyIndexer>,
+    display: &DisplayOpts,
+) -> Result<(), ExecError> {
+    let mut app = App::new();
+    loop {
+        terminal.draw(|frame| view::draw(frame, &app, wallet, display))?;
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let Some(action) = app.handle_key(key.code) else {
+            continue;
+        };
+        let status = match action {
+            Action::Quit => return Ok(()),
+            Action::Sync => sync(wallet, indexer),
+            Action::NewAddress => new_address(wallet, &mut app),
+            Action::Construct(form) => construct_psbt(wallet, network, &form),
+        };
+        app.set_status(status);
+    }
+}
+
+fn sync<D: Descriptor>(
+    wallet: &mut Wallet<XpubDerivable, D>,
+    indexer: Option<&AnyIndexer>,
+) -> String {
+    let Some(indexer) = indexer else {
+        return s!("no indexer is specified; restart with --esplora, --mempool or --electrum");
+    };
+    let known = wallet.transactions().iter().map(|(txid, tx)| (*txid, tx.status)).collect();
+    let (_, errors) = wallet.update(indexer).split();
+    let events = WalletEvent::detect(&known, wallet.transactions());
+    match errors {
+        Some(errors) => format!("sync is partial, {} request(s) have failed", errors.len()),
+        None => format!("synced, {} new event(s)", events.len()),
+    }
+}
+
+fn new_address<D: Descriptor>(wallet: &mut Wallet<XpubDerivable, D>, app: &mut App) -> String {
+    let keychain = wallet.default_keychain();
+    match derive_addresses(wallet, keychain, None, true, 1) {
+        Ok(addresses) => {
+            let status = match addresses.first() {
+                Some(derived) => format!("new address {} at {}", derived.addr, derived.terminal),
+                None => s!("no address can be derived"),
+            };
+            app.open(Dialog::Address(addresses));
+            status
+        }
+        Err(err) => err.to_string(),
+    }
+}
+
+fn construct_psbt<D: Descriptor>(
+    wallet: &mut Wallet<XpubDerivable, D>,
+    network: Network,
+    form: &ConstructForm,
+) -> String {
+    match try_construct_psbt(wallet, network, form) {
+        Ok(status) => status,
+        Err(err) => format!("unable to construct PSBT: {err}"),
+    }
+}
+
+fn try_construct_psbt<D: Descriptor>(
+    wallet: &mut Wallet<XpubDerivable, D>,
+    network: Network,
+    form: &ConstructForm,
+) -> Result<String, ExecError> {
+    let invalid = |err: String| io::Error::new(io::ErrorKind::InvalidInput, err);
+    let recipient = parse_recipient(form.recipient.trim()).map_err(|e| invalid(e.to_string()))?;
+    let fee = parse_fee(form.fee.trim()).map_err(|e| invalid(e.to_string()))?;
+    let params = ConstructParams {
+        recipients: vec![recipient],
+        well_known: false,
+        memo: None,
+        embed_memo: false,
+        no_rbf: false,
+        package_fee_boost: None,
+        deduct_fee: vec![],
+        min_conf: None,
+        fee,
+        version: PsbtVer::V0,
+        allow_self_send: false,
+        shuffle: None,
+        change_outputs: None,
+        verify_change: false,
+    };
+    let constructed = construct(wallet, network, &params)?;
+    let path = Path::new(form.file.trim());
+    constructed.psbt.encode(constructed.psbt.version, &mut File::create(path)?)?;
+    Ok(format!("PSBT {} is saved to {}", constructed.psbt.txid(), path.display()))
+}
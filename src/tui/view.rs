@@ -0,0 +1,227 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use bpstd::XpubDerivable;
+use descriptors::Descriptor;
+use psbt::PsbtConstructor;
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Clear, Paragraph, Row, Table, Tabs, Wrap};
+use ratatui::Frame;
+
+use super::{App, ConstructForm, Dialog, Tab};
+use crate::cli::DisplayOpts;
+use crate::{Sats, TxStatus, Wallet};
+
+const HELP: &[(&str, &str)] = &[
+    ("Tab / ← →", "switch tab"),
+    ("↑ ↓ / j k", "scroll"),
+    ("s", "sync with the indexer"),
+    ("a", "generate new receiving address"),
+    ("c", "construct PSBT"),
+    ("q / Esc", "quit"),
+];
+
+pub fn draw<D: Descriptor>(
+    frame: &mut Frame,
+    app: &App,
+    wallet: &Wallet<XpubDerivable, D>,
+    display: &DisplayOpts,
+) {
+    let [tabs, body, status] =
+        Layout::vertical([Constraint::Length(3), Constraint::Min(0), Constraint::Length(1)])
+            .areas(frame.area());
+
+    let titles = Tab::ALL.iter().map(Tab::to_string);
+    let title = format!(" {} ", if wallet.name().is_empty() { "bp" } else { wallet.name() });
+    frame.render_widget(
+        Tabs::new(titles)
+            .select(app.tab.index())
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+            .block(Block::bordered().title(title)),
+        tabs,
+    );
+
+    match app.tab {
+        Tab::Overview => draw_overview(frame, body, wallet, display),
+        Tab::History => draw_history(frame, body, app.scroll, wallet, display),
+        Tab::Coins => draw_coins(frame, body, app.scroll, wallet, display),
+    }
+    frame.render_widget(Line::from(app.status.as_str()).fg(Color::Yellow), status);
+
+    match &app.dialog {
+        None => {}
+        Some(Dialog::Help) => {
+            let rows = HELP.iter().map(|(key, action)| Row::new([*key, *action]));
+            let table = Table::new(rows, [Constraint::Length(12), Constraint::Min(0)])
+                .block(Block::bordered().title(" Keys "));
+            draw_popup(frame, table, HELP.len() as u16 + 2);
+        }
+        Some(Dialog::Address(addresses)) => {
+            let lines = addresses
+                .iter()
+                .map(|derived| Line::from(format!("{}\t{}", derived.terminal, derived.addr)))
+                .collect::<Vec<_>>();
+            let paragraph = Paragraph::new(lines)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(" New address (press any key) "));
+            draw_popup(frame, paragraph, addresses.len() as u16 + 3);
+        }
+        Some(Dialog::Construct(form)) => {
+            let lines = ConstructForm::FIELDS
+                .iter()
+                .enumerate()
+                .flat_map(|(no, name)| {
+                    let value = Line::from(format!("> {}", form.field(no)));
+                    let value = if no == form.focus { value.reversed() } else { value };
+                    [Line::from(*name).bold(), value]
+                })
+                .collect::<Vec<_>>();
+            let paragraph = Paragraph::new(lines)
+                .block(Block::bordered().title(" Construct PSBT (Tab: next field, Enter: save) "));
+            draw_popup(frame, paragraph, ConstructForm::FIELDS.len() as u16 * 2 + 2);
+        }
+    }
+}
+
+fn draw_popup(frame: &mut Frame, widget: impl ratatui::widgets::Widget, height: u16) {
+    let [area] = Layout::vertical([Constraint::Length(height)]).flex(Flex::Center).areas(
+        Layout::horizontal([Constraint::Percentage(80)])
+            .flex(Flex::Center)
+            .areas::<1>(frame.area())[0],
+    );
+    frame.render_widget(Clear, area);
+    frame.render_widget(widget, area);
+}
+
+fn draw_overview<D: Descriptor>(
+    frame: &mut Frame,
+    area: Rect,
+    wallet: &Wallet<XpubDerivable, D>,
+    display: &DisplayOpts,
+) {
+    let min_conf = wallet.min_confirmations();
+    let locked = display.amount_with_unit(wallet.locked_balance(min_conf));
+    let last_block = wallet.last_block();
+    let mut lines = vec![
+        Line::from(format!("Descriptor:    {}", wallet.descriptor())),
+        Line::from(format!("Network:       {}", wallet.network())),
+        Line::from(format!("Balance:       {}", display.amount_with_unit(wallet.balance()))).bold(),
+        Line::from(format!("Locked:        {locked} (less than {min_conf} confirmation(s))")),
+        Line::from(format!("Transactions:  {}", wallet.transactions().len())),
+        Line::from(format!("Pending:       {}", wallet.pending().len())),
+        Line::from(format!("Last block:    {} ({})", last_block.height, last_block.block_hash)),
+        Line::from(""),
+        Line::from("Keychain  Next index  # used  # unpublished  Remaining").bold(),
+    ];
+    lines.extend(wallet.keychains().into_iter().map(|keychain| {
+        let status = wallet.keychain_status(keychain);
+        let line = Line::from(format!(
+            "{keychain:>8}  {:>10}  {:>6}  {:>13}  {:>9}",
+            status.next_index,
+            status.used,
+            status.unpublished(),
+            status.remaining()
+        ));
+        if status.is_exhausting() {
+            line.red()
+        } else {
+            line
+        }
+    }));
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered()), area);
+}
+
+fn draw_history<D: Descriptor>(
+    frame: &mut Frame,
+    area: Rect,
+    scroll: usize,
+    wallet: &Wallet<XpubDerivable, D>,
+    display: &DisplayOpts,
+) {
+    // Only the visible rows are constructed
+    let rows = wallet.history_by_height().skip(scroll).take(area.height as usize).map(|row| {
+        let height = match row.height {
+            TxStatus::Mined(height) => height.to_string(),
+            status => status.to_string(),
+        };
+        Row::new([
+            height,
+            row.txid.to_string(),
+            format!("{}{}", row.operation, display.amount(row.amount)),
+            display.amount(row.fee),
+            wallet.tx_annotation(row.txid).unwrap_or_default().to_owned(),
+        ])
+    });
+    let widths = [
+        Constraint::Length(10),
+        Constraint::Length(64),
+        Constraint::Length(14),
+        Constraint::Length(10),
+        Constraint::Min(0),
+    ];
+    let table = Table::new(rows, widths)
+        .header(
+            Row::new([
+                s!("Height"),
+                s!("Txid"),
+                format!("Amount, {}", display.unit()),
+                format!("Fee, {}", display.unit()),
+                s!("Memo"),
+            ])
+            .bold(),
+        )
+        .block(Block::bordered());
+    frame.render_widget(table, area);
+}
+
+fn draw_coins<D: Descriptor>(
+    frame: &mut Frame,
+    area: Rect,
+    scroll: usize,
+    wallet: &Wallet<XpubDerivable, D>,
+    display: &DisplayOpts,
+) {
+    let rows = wallet.coins().skip(scroll).take(area.height as usize).map(|row| {
+        Row::new([
+            row.height.to_string(),
+            display.amount(row.amount),
+            row.outpoint.to_string(),
+            row.address.terminal.to_string(),
+            row.address.addr.to_string(),
+        ])
+    });
+    let widths = [
+        Constraint::Length(10),
+        Constraint::Length(14),
+        Constraint::Length(68),
+        Constraint::Length(8),
+        Constraint::Min(0),
+    ];
+    let total = display.amount_with_unit(wallet.coins().map(|row| row.amount).sum::<Sats>());
+    let amount = format!("Amount, {}", display.unit());
+    let table = Table::new(rows, widths)
+        .header(Row::new([s!("Height"), amount, s!("Outpoint"), s!("Term."), s!("Address")]).bold())
+        .block(Block::bordered().title(format!(" Total: {total} ")));
+    frame.render_widget(table, area);
+}
@@ -21,22 +21,33 @@
 // limitations under the License.
 
 use std::cmp;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{btree_map, BTreeMap, BTreeSet, HashMap};
 use std::marker::PhantomData;
-use std::ops::{AddAssign, Deref};
+use std::ops::{AddAssign, Deref, Range};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use bpstd::{
-    Address, AddressNetwork, DerivedAddr, Descriptor, Idx, IdxBase, Keychain, Network, NormalIndex,
-    Outpoint, Sats, Txid, Vout,
+    Address, AddressNetwork, ConsensusEncode, DerivedAddr, Descriptor, Idx, IdxBase, KeyOrigin,
+    Keychain, Network, NormalIndex, Outpoint, Sats, ScriptPubkey, Terminal, Tx, Txid, Vout, Weight,
 };
 use nonasync::persistence::{
     CloneNoPersistence, Persistence, PersistenceError, PersistenceProvider, Persisting,
 };
-use psbt::{PsbtConstructor, Utxo};
+use psbt::{Beneficiary, ConstructionError, PsbtConstructor, TxParams, Utxo};
 
+use crate::data::Inpoint;
+#[cfg(feature = "async")]
+use crate::indexers::AsyncIndexer;
+use crate::indexers::{IndexerConfig, SyncCursor, SyncReport, TxStore, GAP_LIMIT};
 use crate::{
-    BlockInfo, CoinRow, Indexer, Layer2, Layer2Cache, Layer2Data, Layer2Descriptor, Layer2Empty,
-    MayError, MiningInfo, NoLayer2, Party, TxRow, WalletAddr, WalletTx, WalletUtxo,
+    change_window_offset, AddrRow, BlockHeight, BlockInfo, CacheDelta, CoinRow, Confirmations,
+    Contact, Counterparty, FeeTotal, Indexer, Invoice, InvoiceStatus, Journal, JournalEntry,
+    Layer1Changes, Layer2, Layer2Cache, Layer2Data, Layer2Descriptor, Layer2Empty, LotMethod,
+    LotTracker, MayError, MiningInfo, NoLayer2, OwnWallets, Party, PendingStatus, PendingTx,
+    Period, PeriodBucket, ScriptHash, Timings, TxCredit, TxDebit, TxProvenance, TxRow, TxSource,
+    TxStatus, WalletAddr, WalletTx, WalletUtxo, MAX_CHANGE_WINDOW,
 };
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
@@ -50,11 +61,52 @@ pub enum NonWalletItem {
     NonWalletUtxo(Outpoint),
 }
 
+/// Errors selecting coins which have enough confirmations for spending, see
+/// [`Wallet::min_confirmations`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ConfirmationError {
+    /// coin {outpoint} has {confirmations} confirmation(s), while at least {required} are
+    /// required for spending it.
+    Unconfirmed {
+        outpoint: Outpoint,
+        confirmations: Confirmations,
+        required: Confirmations,
+    },
+
+    /// insufficient funds: {required} sats are required, but only {available} sats are
+    /// spendable; {locked} sats are locked until they get {min_conf} confirmation(s).
+    Locked {
+        required: Sats,
+        available: Sats,
+        locked: Sats,
+        min_conf: Confirmations,
+    },
+}
+
+/// Information about a transaction draft constructed with [`Wallet::draft_tx`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DraftMeta {
+    /// Fee paid by the transaction.
+    pub fee: Sats,
+    /// Number of the change output, if the transaction has one.
+    pub change_vout: Option<Vout>,
+    /// Derivation terminal of the change output.
+    pub change_terminal: Option<Terminal>,
+}
+
+/// Number of remaining derivation indexes in a keychain below which the wallet warns about
+/// keychain exhaustion.
+pub const INDEX_EXHAUSTION_MARGIN: u32 = 1000;
+
+fn unix_now() -> u64 { SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() }
+
 pub struct AddrIter<'descr, K, D: Descriptor<K>> {
     generator: &'descr D,
     network: AddressNetwork,
     keychain: Keychain,
-    index: NormalIndex,
+    index: Option<NormalIndex>,
+    last: Option<NormalIndex>,
     _phantom: PhantomData<K>,
 }
 
@@ -62,13 +114,54 @@ impl<K, D: Descriptor<K>> Iterator for AddrIter<'_, K, D> {
     type Item = DerivedAddr;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let addr = self.generator.derive_address(self.network, self.keychain, self.index).ok()?;
-        let derived = DerivedAddr::new(addr, self.keychain, self.index);
-        self.index.wrapping_inc_assign();
+        let index = self.index?;
+        let addr = self.generator.derive_address(self.network, self.keychain, index).ok()?;
+        let derived = DerivedAddr::new(addr, self.keychain, index);
+        // Iteration stops at the last normal index and never wraps to the start of the keychain
+        self.index = index.checked_inc().filter(|_| Some(index) != self.last);
         Some(derived)
     }
 }
 
+/// Result of abandoning a draft transaction with [`Wallet::abandon_draft`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct AbandonedDraft {
+    /// Change terminal reserved by the draft, if the draft was known to the wallet.
+    pub change: Option<Terminal>,
+    /// Whether the change derivation index was released for the re-use.
+    pub index_released: bool,
+    /// Whether the transaction was removed from the wallet cache.
+    pub tx_removed: bool,
+}
+
+/// Usage of derivation indexes in a wallet keychain.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct KeychainStatus {
+    pub keychain: Keychain,
+    /// Index which will be used for the next address.
+    pub next_index: NormalIndex,
+    /// Index following the last address which has received funds, as known from the indexer.
+    pub next_published: NormalIndex,
+    /// Number of addresses which were used in transactions.
+    pub used: u32,
+}
+
+impl KeychainStatus {
+    /// Number of addresses issued after the last address known to the indexer.
+    pub fn unpublished(&self) -> u32 {
+        self.next_index.index().saturating_sub(self.next_published.index())
+    }
+
+    /// Number of indexes which can still be used for new addresses.
+    pub fn remaining(&self) -> u32 { NormalIndex::MAX.index() - self.next_index.index() }
+
+    pub fn is_exhausting(&self) -> bool { self.remaining() < INDEX_EXHAUSTION_MARGIN }
+
+    /// Detects whether the issued addresses are out of the range scanned by the indexers, such
+    /// that payments to them won't be discovered during the sync.
+    pub fn exceeds_gap_limit(&self) -> bool { self.unpublished() >= GAP_LIMIT }
+}
+
 #[cfg_attr(
     feature = "serde",
     derive(serde::Serialize, serde::Deserialize),
@@ -95,6 +188,16 @@ where
     #[getter(as_copy)]
     network: Network,
     layer2: L2,
+    /// Terminal of the only receiving address of the wallet, when the wallet operates in a
+    /// static address (merchant) mode.
+    #[getter(as_copy)]
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    static_terminal: Option<Terminal>,
+    /// Keychain and the range of its derivation indexes the descriptor view is restricted to,
+    /// see [`WalletDescr::slice`].
+    #[getter(skip)]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    slice: Option<(Keychain, Range<u32>)>,
     #[cfg_attr(feature = "serde", serde(skip))]
     _phantom: PhantomData<K>,
 }
@@ -106,6 +209,8 @@ impl<K, D: Descriptor<K>> WalletDescr<K, D, Layer2Empty> {
             generator: descr,
             network,
             layer2: none!(),
+            static_terminal: None,
+            slice: None,
             _phantom: PhantomData,
         }
     }
@@ -118,20 +223,135 @@ impl<K, D: Descriptor<K>, L2: Layer2Descriptor> WalletDescr<K, D, L2> {
             generator: descr,
             network,
             layer2,
+            static_terminal: None,
+            slice: None,
             _phantom: PhantomData,
         }
     }
 
-    pub fn addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<K, D> {
+    pub fn addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<'_, K, D> {
+        self.addresses_from(keychain, NormalIndex::ZERO)
+    }
+
+    /// Iterates over addresses starting from a given index, without deriving the addresses
+    /// preceding it.
+    pub fn addresses_from(
+        &self,
+        keychain: impl Into<Keychain>,
+        from: NormalIndex,
+    ) -> AddrIter<'_, K, D> {
         AddrIter {
             generator: &self.generator,
             network: self.network.into(),
             keychain: keychain.into(),
-            index: NormalIndex::ZERO,
+            index: Some(from),
+            last: None,
             _phantom: PhantomData,
         }
     }
 
+    /// Full origins (master key fingerprint and derivation path) of the keys used by the address
+    /// with the given terminal, allowing to cross-verify the address with hardware wallets.
+    /// Multi-key descriptors provide an origin for each of their keys.
+    pub fn key_origin(&self, terminal: Terminal) -> Vec<KeyOrigin> {
+        let legacy = self.generator.legacy_keyset(terminal).into_values();
+        let xonly = self.generator.xonly_keyset(terminal).into_values().map(|der| der.origin);
+        legacy.chain(xonly).collect()
+    }
+
+    /// Computes Electrum script hashes for the range of the keychain derivation indexes, for
+    /// integrators talking to Electrum servers directly.
+    pub fn script_hashes(
+        &self,
+        keychain: impl Into<Keychain>,
+        range: Range<u32>,
+    ) -> impl Iterator<Item = (Terminal, ScriptHash)> + '_ {
+        let keychain = keychain.into();
+        let len = range.len();
+        NormalIndex::try_from_index(range.start)
+            .ok()
+            .into_iter()
+            .flat_map(move |from| self.addresses_from(keychain, from).take(len))
+            .map(|derived| (derived.terminal, ScriptHash::with(&derived.addr.script_pubkey())))
+    }
+
+    /// Produces a view of the descriptor restricted to a range of derivation indexes of a single
+    /// keychain, which can be used to scan or export only a part of the wallet addresses.
+    ///
+    /// Indexers scan the whole range of a sliced descriptor, not stopping at the gap limit, so
+    /// enormous wallets can be scanned in shards by multiple processes; the scanned ranges are
+    /// recorded in [`WalletCache::scanned`]. The slice takes precedence over the static address
+    /// mode. The view is never persisted.
+    pub fn slice(&self, keychain: impl Into<Keychain>, range: Range<u32>) -> Self {
+        let mut view = self.clone_no_persistence();
+        view.slice = Some((keychain.into(), range));
+        view
+    }
+
+    /// Keychain and the range of its derivation indexes, if the descriptor is a view produced
+    /// with [`Self::slice`].
+    pub fn slice_range(&self) -> Option<(Keychain, Range<u32>)> { self.slice.clone() }
+
+    /// Keychains which have to be watched by indexers, which is the only keychain of a sliced
+    /// descriptor.
+    pub fn watched_keychains(&self) -> BTreeSet<Keychain> {
+        let mut keychains = self.generator.keychains();
+        if let Some((keychain, _)) = self.slice {
+            keychains.retain(|k| *k == keychain);
+        }
+        keychains
+    }
+
+    /// Iterates over addresses which have to be watched by indexers. In the static address mode
+    /// the keychain of the static terminal is represented by that terminal only; for a sliced
+    /// descriptor only the addresses in the slice range are watched.
+    pub fn watched_addresses(&self, keychain: impl Into<Keychain>) -> AddrIter<'_, K, D> {
+        let keychain = keychain.into();
+        if let Some((sliced, range)) = &self.slice {
+            return AddrIter {
+                index: NormalIndex::try_from_index(range.start)
+                    .ok()
+                    .filter(|_| *sliced == keychain && !range.is_empty()),
+                last: Some(
+                    NormalIndex::try_from_index(range.end.saturating_sub(1))
+                        .unwrap_or(NormalIndex::MAX),
+                ),
+                ..self.addresses(keychain)
+            };
+        }
+        match self.static_terminal {
+            Some(terminal) if terminal.keychain == keychain => AddrIter {
+                last: Some(terminal.index),
+                ..self.addresses_from(keychain, terminal.index)
+            },
+            _ => self.addresses(keychain),
+        }
+    }
+
+    /// Iterates over the watched addresses like [`Self::watched_addresses`], skipping the ones
+    /// preceding the given index, for instance to resume an interrupted sync.
+    pub fn watched_addresses_from(
+        &self,
+        keychain: impl Into<Keychain>,
+        from: NormalIndex,
+    ) -> AddrIter<'_, K, D> {
+        let iter = self.watched_addresses(keychain);
+        AddrIter {
+            index: iter
+                .index
+                .filter(|_| !matches!(iter.last, Some(last) if from > last))
+                .map(|index| index.max(from)),
+            ..iter
+        }
+    }
+
+    /// Switches the wallet into the static address mode, where the given terminal is used as
+    /// the only receiving address, or back to the normal mode if `None` is provided.
+    pub fn set_static_terminal(&mut self, terminal: Option<Terminal>) {
+        self.static_terminal = terminal;
+        self.mark_dirty();
+    }
+
     pub fn with_descriptor_mut<E>(
         &mut self,
         f: impl FnOnce(&mut D) -> Result<(), E>,
@@ -155,6 +375,8 @@ impl<K, D: Descriptor<K>, L2: Layer2Descriptor> CloneNoPersistence for WalletDes
             generator: self.generator.clone(),
             network: self.network,
             layer2: self.layer2.clone(),
+            static_terminal: self.static_terminal,
+            slice: self.slice.clone(),
             _phantom: PhantomData,
         }
     }
@@ -167,6 +389,8 @@ impl<K, D: Descriptor<K>, L2: Layer2Descriptor> Persisting for WalletDescr<K, D,
     fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
     #[inline]
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+    #[inline]
+    fn is_dirty(&self) -> bool { self.persistence.as_ref().map(|p| p.dirty).unwrap_or(true) }
 }
 
 impl<K, D: Descriptor<K>, L2: Layer2Descriptor> Drop for WalletDescr<K, D, L2> {
@@ -205,6 +429,31 @@ pub struct WalletData<L2: Layer2Data> {
     pub txin_annotations: BTreeMap<Outpoint, String>,
     pub addr_annotations: BTreeMap<Address, String>,
     pub last_used: BTreeMap<Keychain, NormalIndex>,
+    /// Constructed but not yet mined transactions, mapped to the change terminal they reserved.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub drafts: BTreeMap<Txid, Terminal>,
+    /// Ledger of wallet-created transactions which are not mined yet.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub pending: BTreeMap<Txid, PendingTx>,
+    /// How and when the wallet has learned about its transactions.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub provenance: BTreeMap<Txid, TxProvenance>,
+    /// Minimal number of confirmations an unspent output must have to be selected for spending.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub min_confirmations: Confirmations,
+    /// Counterparties under the names assigned by the user.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub contacts: BTreeMap<String, Contact>,
+    /// Expected incoming payments by the addresses issued for them.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub invoices: BTreeMap<Address, Invoice>,
+    /// Indexer used for this wallet unless another one is given explicitly.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "Option::is_none"))]
+    pub indexer: Option<IndexerConfig>,
+    /// Size of the window the change derivation index is selected from, see
+    /// [`change_window_offset`]. Zero or one select the change indexes sequentially.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub change_window: u8,
     pub layer2: L2,
 }
 
@@ -220,6 +469,14 @@ impl<L2: Layer2Data> CloneNoPersistence for WalletData<L2> {
             addr_annotations: self.addr_annotations.clone(),
             layer2: self.layer2.clone(),
             last_used: self.last_used.clone(),
+            drafts: self.drafts.clone(),
+            pending: self.pending.clone(),
+            provenance: self.provenance.clone(),
+            min_confirmations: self.min_confirmations,
+            contacts: self.contacts.clone(),
+            invoices: self.invoices.clone(),
+            indexer: self.indexer.clone(),
+            change_window: self.change_window,
         }
     }
 }
@@ -231,6 +488,8 @@ impl<L2: Layer2Data> Persisting for WalletData<L2> {
     fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
     #[inline]
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+    #[inline]
+    fn is_dirty(&self) -> bool { self.persistence.as_ref().map(|p| p.dirty).unwrap_or(true) }
 }
 
 impl WalletData<Layer2Empty> {
@@ -245,6 +504,14 @@ impl WalletData<Layer2Empty> {
             addr_annotations: empty!(),
             layer2: none!(),
             last_used: empty!(),
+            drafts: empty!(),
+            pending: empty!(),
+            provenance: empty!(),
+            min_confirmations: Confirmations::ZERO,
+            contacts: empty!(),
+            invoices: empty!(),
+            indexer: None,
+            change_window: 0,
         }
     }
 }
@@ -262,6 +529,14 @@ impl<L2: Layer2Data> WalletData<L2> {
             addr_annotations: empty!(),
             layer2: none!(),
             last_used: empty!(),
+            drafts: empty!(),
+            pending: empty!(),
+            provenance: empty!(),
+            min_confirmations: Confirmations::ZERO,
+            contacts: empty!(),
+            invoices: empty!(),
+            indexer: None,
+            change_window: 0,
         }
     }
 }
@@ -302,7 +577,65 @@ pub struct WalletCache<L2: Layer2Cache> {
     pub tx: BTreeMap<Txid, WalletTx>,
     pub utxo: BTreeSet<Outpoint>,
     pub addr: BTreeMap<Keychain, BTreeSet<WalletAddr>>,
+    /// Electrum script hashes of the scanned addresses, kept to avoid re-computing them on each
+    /// sync.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub script_hashes: BTreeMap<Terminal, ScriptHash>,
+    /// Sorted non-overlapping ranges of the derivation indexes scanned by indexers, for each of
+    /// the keychains. See [`WalletCache::mark_scanned`].
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub scanned: BTreeMap<Keychain, Vec<Range<u32>>>,
+    /// Positions at which the last sync of the keychains was interrupted, from which the next
+    /// sync resumes.
+    #[cfg_attr(feature = "serde", serde(default, skip_serializing_if = "BTreeMap::is_empty"))]
+    pub sync_cursors: BTreeMap<Keychain, SyncCursor>,
     pub layer2: L2,
+    /// Events derived from the syncs, which are persisted separately from the rest of the cache.
+    /// See [`Wallet::events_since`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub journal: Journal,
+    /// Aggregates over the unspent outputs, built on the first use and dropped each time the
+    /// cache is updated. See [`WalletCache::reindex`].
+    #[cfg_attr(feature = "serde", serde(skip))]
+    coin_index: OnceLock<CoinIndex>,
+}
+
+/// Aggregates over the wallet outputs, allowing to look up coins and transactions of an address
+/// and the last used derivation index without walking the whole set of coins.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub(crate) struct CoinIndex {
+    /// Unspent outputs grouped by the addresses holding them.
+    pub(crate) addr_coins: BTreeMap<Terminal, (DerivedAddr, Vec<Outpoint>)>,
+    /// Maximal index of an address holding coins, for each of the keychains.
+    pub(crate) last_used: BTreeMap<Keychain, NormalIndex>,
+    /// Transactions paying to or spending from the addresses, keyed by the same terminals as
+    /// [`WalletCache::script_hashes`].
+    pub(crate) addr_txs: BTreeMap<Terminal, BTreeSet<Txid>>,
+}
+
+impl CoinIndex {
+    fn with(tx: &BTreeMap<Txid, WalletTx>, utxo: &BTreeSet<Outpoint>) -> Self {
+        let mut index = CoinIndex::default();
+        for outpoint in utxo {
+            let derived = tx
+                .get(&outpoint.txid)
+                .and_then(|tx| tx.outputs.get(outpoint.vout_usize()))
+                .and_then(|out| out.derived_addr())
+                .expect("cache data inconsistency");
+            let terminal = derived.terminal;
+            index.addr_coins.entry(terminal).or_insert_with(|| (derived, vec![])).1.push(*outpoint);
+            let last = index.last_used.entry(terminal.keychain).or_insert(terminal.index);
+            *last = cmp::max(*last, terminal.index);
+        }
+        for tx in tx.values() {
+            let inputs = tx.inputs.iter().filter_map(TxCredit::derived_addr);
+            let outputs = tx.outputs.iter().filter_map(TxDebit::derived_addr);
+            for derived in inputs.chain(outputs) {
+                index.addr_txs.entry(derived.terminal).or_default().insert(tx.txid);
+            }
+        }
+        index
+    }
 }
 
 impl<L2C: Layer2Cache> WalletCache<L2C> {
@@ -316,8 +649,205 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
             tx: none!(),
             utxo: none!(),
             addr: none!(),
+            script_hashes: none!(),
+            scanned: none!(),
+            sync_cursors: none!(),
             layer2: none!(),
+            journal: none!(),
+            coin_index: none!(),
+        }
+    }
+
+    pub(crate) fn coin_index(&self) -> &CoinIndex {
+        self.coin_index.get_or_init(|| CoinIndex::with(&self.tx, &self.utxo))
+    }
+
+    /// Drops coin aggregates, such that they get rebuilt on the next use. Must be called after
+    /// modifying [`Self::tx`] or [`Self::utxo`] directly, which is not required when the cache
+    /// is modified with its own methods.
+    pub fn reindex(&mut self) { self.coin_index = none!(); }
+
+    /// Returns the maximal derivation index of an address on the keychain holding unspent
+    /// outputs, if any.
+    pub fn last_used_index(&self, keychain: impl Into<Keychain>) -> Option<NormalIndex> {
+        self.coin_index().last_used.get(&keychain.into()).copied()
+    }
+
+    /// Returns Electrum script hash for the derived address, computing and caching it if it is
+    /// not known yet.
+    pub fn script_hash(&mut self, derived: &DerivedAddr) -> ScriptHash {
+        *self
+            .script_hashes
+            .entry(derived.terminal)
+            .or_insert_with(|| ScriptHash::with(&derived.addr.script_pubkey()))
+    }
+
+    /// Records the range of derivation indexes of the keychain as scanned, merging it with the
+    /// overlapping and adjacent ranges scanned before.
+    pub fn mark_scanned(&mut self, keychain: impl Into<Keychain>, range: Range<u32>) {
+        if range.is_empty() {
+            return;
         }
+        let ranges = self.scanned.entry(keychain.into()).or_default();
+        let mut merged = range;
+        // Ranges are sorted and never adjacent, so a single pass merges all ranges touching it
+        ranges.retain(|r| {
+            if r.start > merged.end || r.end < merged.start {
+                return true;
+            }
+            merged = merged.start.min(r.start)..merged.end.max(r.end);
+            false
+        });
+        let pos = ranges.partition_point(|r| r.start < merged.start);
+        ranges.insert(pos, merged);
+    }
+
+    /// Adds a transaction to the cache, resolving its inputs and outputs against the given wallet
+    /// scripts. Previous outputs are looked up in the cache and then in the transaction store;
+    /// the ones unknown to both remain unresolved, and the transaction fee is set to zero in this
+    /// case.
+    ///
+    /// If the transaction is already known, only its status is updated. Returns `false` if the
+    /// transaction neither spends from nor pays to the wallet, leaving the cache unchanged.
+    pub(crate) fn import_tx(
+        &mut self,
+        tx: Tx,
+        status: TxStatus,
+        scripts: &BTreeMap<ScriptPubkey, DerivedAddr>,
+        network: Network,
+        store: &mut TxStore,
+    ) -> bool {
+        let txid = tx.txid();
+        if let Some(known) = self.tx.get_mut(&txid) {
+            known.status = status;
+            if status.is_mined() {
+                for input in known.inputs.iter().filter(|input| input.is_ourself()) {
+                    self.utxo.remove(&input.outpoint);
+                }
+            }
+            self.reindex();
+            self.mark_dirty();
+            return true;
+        }
+
+        let resolve = |script: ScriptPubkey| match scripts.get(&script) {
+            Some(derived) => Party::Wallet(*derived),
+            None => match Address::with(&script, network) {
+                Ok(addr) => Party::Counterparty(addr),
+                Err(_) => Party::Unknown(script),
+            },
+        };
+
+        let size = tx.consensus_serialize().len() as u32;
+        let weight = tx.weight_units().to_u32();
+        let mut input_total = Some(Sats::ZERO);
+        let inputs = tx
+            .inputs
+            .into_iter()
+            .map(|input| {
+                let prevout = input.prev_output;
+                let coinbase = prevout.txid.is_coinbase();
+                let known = self
+                    .tx
+                    .get(&prevout.txid)
+                    .and_then(|prev_tx| prev_tx.outputs.get(prevout.vout_usize()))
+                    .map(|debit| (debit.beneficiary.clone(), debit.value))
+                    .or_else(|| {
+                        let txout = store.get(&prevout.txid)?.outputs.get(prevout.vout_usize())?;
+                        Some((resolve(txout.script_pubkey.clone()), txout.value))
+                    });
+                input_total =
+                    input_total.zip(known.as_ref()).map(|(total, (_, value))| total + *value);
+                let (payer, value) = known.unwrap_or_else(|| match coinbase {
+                    true => (Party::Subsidy, Sats::ZERO),
+                    false => (Party::Unknown(ScriptPubkey::new()), Sats::ZERO),
+                });
+                TxCredit {
+                    outpoint: prevout,
+                    payer,
+                    sequence: input.sequence,
+                    coinbase,
+                    script_sig: input.sig_script,
+                    witness: input.witness,
+                    value,
+                }
+            })
+            .collect::<Vec<_>>();
+        let outputs = tx
+            .outputs
+            .into_iter()
+            .enumerate()
+            .map(|(no, txout)| TxDebit {
+                outpoint: Outpoint::new(txid, no as u32),
+                beneficiary: resolve(txout.script_pubkey),
+                value: txout.value,
+                spent: None,
+            })
+            .collect::<Vec<_>>();
+        if !inputs.iter().any(TxCredit::is_ourself) && !outputs.iter().any(TxDebit::is_ourself) {
+            return false;
+        }
+
+        let output_total = outputs.iter().map(|debit| debit.value).sum::<Sats>();
+        for (vin, credit) in inputs.iter().enumerate().filter(|(_, credit)| credit.is_ourself()) {
+            if let Some(debit) = self
+                .tx
+                .get_mut(&credit.outpoint.txid)
+                .and_then(|prev_tx| prev_tx.outputs.get_mut(credit.outpoint.vout_usize()))
+            {
+                debit.spent = Some(Inpoint::new(txid, vin as u32));
+            }
+            if status.is_mined() {
+                self.utxo.remove(&credit.outpoint);
+            }
+        }
+        for (derived, value, received) in inputs
+            .iter()
+            .filter_map(|credit| {
+                credit.derived_addr().map(|derived| (derived, credit.value, false))
+            })
+            .chain(outputs.iter().filter_map(|debit| {
+                debit.derived_addr().map(|derived| (derived, debit.value, true))
+            }))
+        {
+            let addresses = self.addr.entry(derived.terminal.keychain).or_default();
+            let mut wallet_addr = addresses
+                .take(&WalletAddr::from(derived))
+                .unwrap_or_else(|| WalletAddr::from(derived));
+            if received {
+                wallet_addr.used = wallet_addr.used.saturating_add(1);
+                wallet_addr.volume.saturating_add_assign(value);
+                wallet_addr.balance.saturating_add_assign(value);
+            } else {
+                wallet_addr.balance = wallet_addr.balance.saturating_sub(value);
+            }
+            addresses.insert(wallet_addr);
+        }
+        for debit in outputs.iter().filter(|debit| debit.is_ourself()) {
+            self.utxo.insert(debit.outpoint);
+        }
+
+        self.tx.insert(txid, WalletTx {
+            txid,
+            status,
+            inputs,
+            outputs,
+            fee: input_total.and_then(|total| total.checked_sub(output_total)).unwrap_or_default(),
+            size,
+            weight,
+            version: tx.version,
+            locktime: tx.lock_time,
+        });
+        self.reindex();
+        self.mark_dirty();
+        true
+    }
+
+    /// Checks whether the address with the given terminal was scanned by an indexer.
+    pub fn is_scanned(&self, terminal: Terminal) -> bool {
+        self.scanned.get(&terminal.keychain).is_some_and(|ranges| {
+            ranges.iter().any(|range| range.contains(&terminal.index.index()))
+        })
     }
 
     pub fn with<I: Indexer, K, D: Descriptor<K>, L2: Layer2<Cache = L2C>>(
@@ -333,10 +863,63 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
         indexer: &I,
     ) -> MayError<usize, Vec<I::Error>> {
         let res = indexer.update::<K, D, L2>(descriptor, self);
+        self.reindex();
+        self.mark_dirty();
+        res
+    }
+
+    /// Updates the cache like [`Self::update`], using the transaction store to avoid
+    /// re-downloading already known transactions and recording durations of the update phases.
+    pub fn update_with_store<I: Indexer, K, D: Descriptor<K>, L2: Layer2<Cache = L2C>>(
+        &mut self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        indexer: &I,
+        store: &mut TxStore,
+        timings: &mut Timings,
+    ) -> MayError<usize, Vec<I::Error>> {
+        let res = indexer.update_with_store::<K, D, L2>(descriptor, self, store, timings);
+        self.reindex();
+        self.mark_dirty();
+        res
+    }
+
+    /// Non-blocking counterpart of [`Self::update`].
+    #[cfg(feature = "async")]
+    pub async fn update_async<I: AsyncIndexer, K, D: Descriptor<K>, L2: Layer2<Cache = L2C>>(
+        &mut self,
+        descriptor: &WalletDescr<K, D, L2::Descr>,
+        indexer: &I,
+    ) -> MayError<usize, Vec<I::Error>> {
+        let res = indexer.update::<K, D, L2>(descriptor, self).await;
+        self.reindex();
         self.mark_dirty();
         res
     }
 
+    /// Removes not yet mined transaction, returning the wallet outputs it spends back to the set
+    /// of unspent outputs. Address statistics are not updated until the next sync.
+    ///
+    /// Returns `false` if the transaction is unknown or is already mined.
+    pub fn remove_unmined_tx(&mut self, txid: Txid) -> bool {
+        if !matches!(self.tx.get(&txid), Some(tx) if !matches!(tx.status, TxStatus::Mined(_))) {
+            return false;
+        }
+        let tx = self.tx.remove(&txid).expect("transaction presence is checked above");
+        self.utxo.retain(|outpoint| outpoint.txid != txid);
+        for input in tx.inputs.iter().filter(|input| input.is_ourself()) {
+            let prevout = input.outpoint;
+            if let Some(prev_tx) = self.tx.get_mut(&prevout.txid) {
+                if let Some(debit) = prev_tx.outputs.get_mut(prevout.vout_usize()) {
+                    debit.spent = None;
+                }
+                self.utxo.insert(prevout);
+            }
+        }
+        self.reindex();
+        self.mark_dirty();
+        true
+    }
+
     pub fn addresses_on(&self, keychain: Keychain) -> &BTreeSet<WalletAddr> {
         self.addr.get(&keychain).unwrap_or_else(|| {
             panic!("keychain #{keychain} is not supported by the wallet descriptor")
@@ -389,6 +972,74 @@ impl<L2C: Layer2Cache> WalletCache<L2C> {
         })
     }
 
+    /// Unspent outputs which don't refer to a wallet output of a cached transaction, which
+    /// indicates cache data inconsistency.
+    pub fn dangling_utxos(&self) -> Vec<Outpoint> {
+        self.utxo
+            .iter()
+            .filter(|outpoint| {
+                self.tx
+                    .get(&outpoint.txid)
+                    .and_then(|tx| tx.outputs.get(outpoint.vout_usize()))
+                    .and_then(|out| out.derived_addr())
+                    .is_none()
+            })
+            .copied()
+            .collect()
+    }
+
+    /// Wallet coins which were unspent after the block at the `height`, reconstructed from the
+    /// transaction graph. These are the outputs of the transactions mined at or below the height,
+    /// which are not spent by the inputs of the transactions mined at or below it, so the spend
+    /// height of a coin is the height of its spending transaction. Unmined transactions are
+    /// ignored.
+    pub fn utxos_at(&self, height: BlockHeight) -> Vec<WalletUtxo> {
+        let mined =
+            |status: &TxStatus| matches!(status, TxStatus::Mined(info) if info.height <= height);
+        let spent = self
+            .tx
+            .values()
+            .filter(|tx| mined(&tx.status))
+            .flat_map(|tx| tx.inputs.iter().map(|input| input.outpoint))
+            .collect::<BTreeSet<_>>();
+        self.txos().filter(|utxo| mined(&utxo.status) && !spent.contains(&utxo.outpoint)).collect()
+    }
+
+    /// Unconfirmed wallet transactions which outputs are spent by the transaction or by its other
+    /// unconfirmed ancestors, such that the transaction can't be mined before them. Ancestors
+    /// unknown to the wallet are not included.
+    pub fn unconfirmed_ancestors(&self, txid: Txid) -> BTreeSet<Txid> {
+        let mut ancestors = BTreeSet::new();
+        let mut queue = vec![txid];
+        while let Some(txid) = queue.pop() {
+            let Some(tx) = self.tx.get(&txid) else {
+                continue;
+            };
+            for input in &tx.inputs {
+                let parent = input.outpoint.txid;
+                if self.tx.get(&parent).is_some_and(|tx| !tx.status.is_mined())
+                    && ancestors.insert(parent)
+                {
+                    queue.push(parent);
+                }
+            }
+        }
+        ancestors
+    }
+
+    /// Height of the last block containing a wallet transaction mined not later than the UNIX
+    /// `timestamp`, or `None` if there were no wallet transactions by that time. The wallet
+    /// coins at the time are the coins at this height, see [`WalletCache::utxos_at`].
+    pub fn height_at(&self, timestamp: u64) -> Option<BlockHeight> {
+        self.tx
+            .values()
+            .filter_map(|tx| match tx.status {
+                TxStatus::Mined(info) if info.time <= timestamp => Some(info.height),
+                _ => None,
+            })
+            .max()
+    }
+
     pub fn utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ {
         self.utxo.iter().map(|outpoint| {
             let tx = self.tx.get(&outpoint.txid).expect("cache data inconsistency");
@@ -417,7 +1068,12 @@ impl<L2: Layer2Cache> CloneNoPersistence for WalletCache<L2> {
             tx: self.tx.clone(),
             utxo: self.utxo.clone(),
             addr: self.addr.clone(),
+            script_hashes: self.script_hashes.clone(),
+            scanned: self.scanned.clone(),
+            sync_cursors: self.sync_cursors.clone(),
             layer2: self.layer2.clone(),
+            journal: self.journal.clone(),
+            coin_index: self.coin_index.clone(),
         }
     }
 }
@@ -429,6 +1085,8 @@ impl<L2: Layer2Cache> Persisting for WalletCache<L2> {
     fn persistence_mut(&mut self) -> Option<&mut Persistence<Self>> { self.persistence.as_mut() }
     #[inline]
     fn as_mut_persistence(&mut self) -> &mut Option<Persistence<Self>> { &mut self.persistence }
+    #[inline]
+    fn is_dirty(&self) -> bool { self.persistence.as_ref().map(|p| p.dirty).unwrap_or(true) }
 }
 
 impl<L2: Layer2Cache> Drop for WalletCache<L2> {
@@ -483,9 +1141,51 @@ impl<K, D: Descriptor<K>, L2: Layer2> PsbtConstructor for Wallet<K, D, L2> {
 
     fn next_derivation_index(&mut self, keychain: impl Into<Keychain>, shift: bool) -> NormalIndex {
         let keychain = keychain.into();
+        // Static address is re-used by design, so neither index shifts nor warnings apply
+        if let Some(terminal) = self.descr.static_terminal.filter(|t| t.keychain == keychain) {
+            return terminal.index;
+        }
+        let status = self.keychain_status(keychain);
+        if shift && status.is_exhausting() {
+            #[cfg(feature = "log")]
+            log::warn!(
+                "keychain {keychain} has only {} unused derivation indexes left",
+                status.remaining()
+            );
+            #[cfg(not(feature = "log"))]
+            eprintln!(
+                "Warning: keychain {keychain} has only {} unused derivation indexes left",
+                status.remaining()
+            );
+        }
+        if shift && status.exceeds_gap_limit() {
+            #[cfg(feature = "log")]
+            log::warn!(
+                "keychain {keychain} has {} addresses issued after the last used one, which \
+                 exceeds the gap limit of {GAP_LIMIT}; payments to them may not be discovered",
+                status.unpublished()
+            );
+            #[cfg(not(feature = "log"))]
+            eprintln!(
+                "Warning: keychain {keychain} has {} addresses issued after the last used one, \
+                 which exceeds the gap limit of {GAP_LIMIT}; payments to them may not be \
+                 discovered",
+                status.unpublished()
+            );
+        }
         let mut idx = self.last_published_derivation_index(keychain);
+        idx = cmp::max(self.data.last_used.get(&keychain).copied().unwrap_or_default(), idx);
+        // Change indexes skipped by the window are never used later, so the gaps between the
+        // used change addresses stay below the gap limit
+        if keychain == Keychain::INNER && self.data.change_window > 1 {
+            let offset = change_window_offset(
+                &self.descr.generator,
+                Terminal::new(keychain, idx),
+                self.data.change_window,
+            );
+            idx = idx.saturating_add(offset);
+        }
         let last_index = self.data.last_used.entry(keychain).or_default();
-        idx = cmp::max(*last_index, idx);
         if shift {
             *last_index = idx.saturating_add(1u32);
             self.data.mark_dirty();
@@ -515,11 +1215,353 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         }
     }
 
+    /// Terminal of the only receiving address used in the static address mode.
+    #[inline]
+    pub fn static_terminal(&self) -> Option<Terminal> { self.descr.static_terminal }
+
+    /// Switches the wallet into the static address (merchant) mode, or back to the normal
+    /// mode if `None` is provided. Fails if the terminal keychain is not a part of the wallet
+    /// descriptor.
+    pub fn set_static_terminal(&mut self, terminal: Option<Terminal>) -> Result<(), Keychain> {
+        if let Some(terminal) = terminal {
+            if !self.descr.keychains().contains(&terminal.keychain) {
+                return Err(terminal.keychain);
+            }
+        }
+        self.descr.set_static_terminal(terminal);
+        Ok(())
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str { &self.data.name }
+
     pub fn set_name(&mut self, name: String) {
         self.data.name = name;
         self.data.mark_dirty();
     }
 
+    /// Drops all the information retrieved from the indexers, such that the next update has to
+    /// re-scan the blockchain. Wallet descriptor and data are kept intact.
+    pub fn reset_cache(&mut self) {
+        let mut cache = WalletCache::new_nonsync();
+        cache.persistence = self.cache.persistence.take();
+        cache.id = self.cache.id.take();
+        self.cache = cache;
+        self.cache.mark_dirty();
+    }
+
+    #[inline]
+    pub fn drafts(&self) -> &BTreeMap<Txid, Terminal> { &self.data.drafts }
+
+    /// Registers constructed transaction as a draft reserving the given change terminal, such
+    /// that the reservation can be released if the transaction gets abandoned.
+    pub fn register_draft(&mut self, txid: Txid, change: Terminal) {
+        self.data.drafts.insert(txid, change);
+        self.data.mark_dirty();
+    }
+
+    #[inline]
+    pub fn pending(&self) -> &BTreeMap<Txid, PendingTx> { &self.data.pending }
+
+    /// Minimal number of confirmations an unspent output must have to be selected for spending
+    /// by [`Self::coinselect`].
+    #[inline]
+    pub fn min_confirmations(&self) -> Confirmations { self.data.min_confirmations }
+
+    pub fn set_min_confirmations(&mut self, confirmations: Confirmations) {
+        self.data.min_confirmations = confirmations;
+        self.data.mark_dirty();
+    }
+
+    /// Size of the window the change derivation indexes are selected from, see
+    /// [`change_window_offset`].
+    #[inline]
+    pub fn change_window(&self) -> u8 { self.data.change_window }
+
+    /// Sets the size of the window the change derivation indexes are selected from, which is
+    /// bounded by [`MAX_CHANGE_WINDOW`].
+    pub fn set_change_window(&mut self, window: u8) {
+        self.data.change_window = window.min(MAX_CHANGE_WINDOW);
+        self.data.mark_dirty();
+    }
+
+    /// Indexer configured for the wallet, which is used when no indexer is given explicitly.
+    #[inline]
+    pub fn indexer(&self) -> Option<&IndexerConfig> { self.data.indexer.as_ref() }
+
+    pub fn set_indexer(&mut self, indexer: Option<IndexerConfig>) {
+        self.data.indexer = indexer;
+        self.data.mark_dirty();
+    }
+
+    #[inline]
+    pub fn contacts(&self) -> &BTreeMap<String, Contact> { &self.data.contacts }
+
+    #[inline]
+    pub fn contact(&self, name: &str) -> Option<&Contact> { self.data.contacts.get(name) }
+
+    /// Returns address for the next payment to the contact. For the contacts given by an
+    /// extended key a fresh address is derived; if `shift` is set, the address is marked as used,
+    /// such that the next call derives a new one.
+    pub fn next_contact_address(&mut self, name: &str, shift: bool) -> Option<Address> {
+        let network = self.network.into();
+        match self.data.contacts.get_mut(name)? {
+            Contact::Static(addr) => Some(*addr),
+            Contact::Dynamic(contact) => {
+                let addr = contact.address(network, contact.next_index);
+                if shift {
+                    contact.next_index.saturating_inc_assign();
+                    self.data.mark_dirty();
+                }
+                Some(addr)
+            }
+        }
+    }
+
+    /// Returns name of the contact paid to the script pubkey.
+    pub fn contact_of(&self, script: &ScriptPubkey) -> Option<&str> {
+        let network = self.network.into();
+        self.data
+            .contacts
+            .iter()
+            .find(|(_, contact)| contact.is_paid_to(script, network))
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// Returns name of the contact which is the counterparty, if any.
+    pub fn counterparty_contact(&self, counterparty: &Counterparty) -> Option<&str> {
+        counterparty.script_pubkey().and_then(|script| self.contact_of(&script))
+    }
+
+    /// Assigns name to the counterparty, returning the contact previously known under this name.
+    pub fn add_contact(&mut self, name: String, contact: impl Into<Contact>) -> Option<Contact> {
+        let prev = self.data.contacts.insert(name, contact.into());
+        self.data.mark_dirty();
+        prev
+    }
+
+    pub fn remove_contact(&mut self, name: &str) -> Option<Contact> {
+        let prev = self.data.contacts.remove(name);
+        if prev.is_some() {
+            self.data.mark_dirty();
+        }
+        prev
+    }
+
+    /// Issues a new receiving address for the invoice and starts tracking payments to it.
+    pub fn create_invoice(
+        &mut self,
+        amount: Sats,
+        created: u64,
+        expires: Option<u64>,
+        memo: Option<String>,
+    ) -> Address {
+        let index = self.next_derivation_index(Keychain::OUTER, true);
+        let terminal = Terminal::new(Keychain::OUTER, index);
+        let address = self
+            .addresses(Keychain::OUTER)
+            .nth(index.index() as usize)
+            .expect("address iterator always can produce address")
+            .addr;
+        self.data.invoices.insert(address, Invoice {
+            terminal,
+            amount,
+            created,
+            expires,
+            memo,
+            status: InvoiceStatus::Open,
+            received: Sats::ZERO,
+            payments: vec![],
+        });
+        self.data.mark_dirty();
+        address
+    }
+
+    #[inline]
+    pub fn invoices(&self) -> &BTreeMap<Address, Invoice> { &self.data.invoices }
+
+    /// Matches transactions from the wallet cache to the invoices by their addresses, updating
+    /// the received amounts and the invoice statuses as of `now` UNIX timestamp.
+    ///
+    /// Returns the invoices which status has changed.
+    pub fn update_invoices(&mut self, now: u64) -> Vec<(Address, Invoice)> {
+        let mut received = BTreeMap::<Terminal, (Sats, Sats, Vec<_>)>::new();
+        for tx in self.cache.tx.values() {
+            for out in &tx.outputs {
+                let Some(derived) = out.derived_addr() else {
+                    continue;
+                };
+                let (total, confirmed, payments) = received.entry(derived.terminal).or_default();
+                *total += out.value;
+                if tx.status.is_mined() {
+                    *confirmed += out.value;
+                }
+                payments.push((tx.status.map(|info| info.height), tx.txid));
+            }
+        }
+
+        let mut dirty = false;
+        let mut changed = vec![];
+        for (address, invoice) in &mut self.data.invoices {
+            let (total, confirmed, mut payments) =
+                received.remove(&invoice.terminal).unwrap_or_default();
+            payments.sort_unstable_by_key(|(height, txid)| match height {
+                TxStatus::Mined(height) => (false, *height, *txid),
+                _ => (true, BlockHeight::MAX, *txid),
+            });
+            payments.dedup();
+            let payments = payments.into_iter().map(|(_, txid)| txid).collect::<Vec<_>>();
+            let status = invoice.status_for(total, confirmed, now);
+            if invoice.received == total && invoice.payments == payments && invoice.status == status
+            {
+                continue;
+            }
+            let status_changed = invoice.status != status;
+            invoice.received = total;
+            invoice.payments = payments;
+            invoice.status = status;
+            if status_changed {
+                changed.push((*address, invoice.clone()));
+            }
+            dirty = true;
+        }
+        if dirty {
+            self.data.mark_dirty();
+        }
+        changed
+    }
+
+    /// Adds wallet-created transaction to the ledger of pending transactions, recording it as
+    /// constructed by the wallet.
+    pub fn register_pending(&mut self, txid: Txid, pending: PendingTx) {
+        self.data.pending.insert(txid, pending);
+        self.data
+            .provenance
+            .entry(txid)
+            .or_insert_with(|| TxProvenance::new(TxSource::Constructed, unix_now()));
+        self.data.mark_dirty();
+    }
+
+    /// Reports how and when the wallet has learned about the transaction.
+    pub fn tx_provenance(&self, txid: Txid) -> Option<TxProvenance> {
+        self.data.provenance.get(&txid).copied()
+    }
+
+    /// Updates amount paid to the beneficiaries and fee of a pending transaction, which has
+    /// been amended with additional inputs or outputs.
+    ///
+    /// Returns `false` if the transaction is not in the pending ledger.
+    pub fn amend_pending(&mut self, txid: Txid, amount: Sats, fee: Sats) -> bool {
+        let Some(pending) = self.data.pending.get_mut(&txid) else {
+            return false;
+        };
+        pending.amount = amount;
+        pending.fee = fee;
+        self.data.mark_dirty();
+        true
+    }
+
+    /// Advances lifecycle status of a pending transaction. The status is never moved backwards.
+    ///
+    /// Returns `false` if the transaction is not in the pending ledger.
+    pub fn advance_pending(&mut self, txid: Txid, status: PendingStatus) -> bool {
+        let Some(pending) = self.data.pending.get_mut(&txid) else {
+            return false;
+        };
+        if pending.status < status {
+            pending.status = status;
+            self.data.mark_dirty();
+        }
+        true
+    }
+
+    /// Abandons draft transaction. The reserved change derivation index is released if no other
+    /// index was issued after it, and the transaction is removed from the cache if it was seen
+    /// in the mempool.
+    pub fn abandon_draft(&mut self, txid: Txid) -> AbandonedDraft {
+        let change = self.data.drafts.remove(&txid);
+        if self.data.pending.remove(&txid).is_some() {
+            self.data.mark_dirty();
+        }
+        if self.data.provenance.get(&txid).is_some_and(|p| p.source == TxSource::Constructed) {
+            self.data.provenance.remove(&txid);
+            self.data.mark_dirty();
+        }
+        let mut index_released = false;
+        if let Some(terminal) = change {
+            self.data.mark_dirty();
+            let last_used = self.data.last_used.get_mut(&terminal.keychain);
+            if let Some(last_used) =
+                last_used.filter(|idx| **idx == terminal.index.saturating_inc())
+            {
+                *last_used = terminal.index;
+                index_released = true;
+            }
+        }
+        AbandonedDraft {
+            change,
+            index_released,
+            tx_removed: self.cache.remove_unmined_tx(txid),
+        }
+    }
+
+    pub fn tx_annotation(&self, txid: Txid) -> Option<&str> {
+        self.data.tx_annotations.get(&txid).map(String::as_str)
+    }
+
+    pub fn annotate_tx(&mut self, txid: Txid, memo: String) {
+        self.data.tx_annotations.insert(txid, memo);
+        self.data.mark_dirty();
+    }
+
+    pub fn txout_annotation(&self, outpoint: Outpoint) -> Option<&str> {
+        self.data.txout_annotations.get(&outpoint).map(String::as_str)
+    }
+
+    pub fn annotate_txout(&mut self, outpoint: Outpoint, memo: String) {
+        self.data.txout_annotations.insert(outpoint, memo);
+        self.data.mark_dirty();
+    }
+
+    pub fn addr_annotation(&self, addr: &Address) -> Option<&str> {
+        self.data.addr_annotations.get(addr).map(String::as_str)
+    }
+
+    pub fn annotate_addr(&mut self, addr: Address, memo: String) {
+        self.data.addr_annotations.insert(addr, memo);
+        self.data.mark_dirty();
+    }
+
+    /// Moves transaction annotation, draft registration, pending ledger entry and provenance to a
+    /// new transaction id. Required when the id of a constructed transaction changes on
+    /// finalization, which happens for non-segwit inputs.
+    pub fn rekey_tx(&mut self, from: Txid, to: Txid) -> bool {
+        if from == to {
+            return false;
+        }
+        let memo = self.data.tx_annotations.remove(&from);
+        let draft = self.data.drafts.remove(&from);
+        let pending = self.data.pending.remove(&from);
+        let provenance = self.data.provenance.remove(&from);
+        if memo.is_none() && draft.is_none() && pending.is_none() && provenance.is_none() {
+            return false;
+        }
+        if let Some(memo) = memo {
+            self.data.tx_annotations.insert(to, memo);
+        }
+        if let Some(terminal) = draft {
+            self.data.drafts.insert(to, terminal);
+        }
+        if let Some(pending) = pending {
+            self.data.pending.insert(to, pending);
+        }
+        if let Some(provenance) = provenance {
+            self.data.provenance.insert(to, provenance);
+        }
+        self.data.mark_dirty();
+        true
+    }
+
     pub fn descriptor_mut<R>(
         &mut self,
         f: impl FnOnce(&mut WalletDescr<K, D, L2::Descr>) -> R,
@@ -544,7 +1586,143 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
     }
 
     pub fn update<I: Indexer>(&mut self, indexer: &I) -> MayError<(), Vec<I::Error>> {
-        self.cache.update::<I, K, D, L2>(&self.descr, indexer).map(|_| ())
+        self.update_with_store(indexer, &mut TxStore::default()).map(|_| ())
+    }
+
+    /// Syncs the wallet using the transaction store shared between wallets, such that the
+    /// transactions downloaded during the previous syncs are not downloaded again.
+    ///
+    /// Returns report with the durations of the synchronization phases.
+    pub fn update_with_store<I: Indexer>(
+        &mut self,
+        indexer: &I,
+        store: &mut TxStore,
+    ) -> MayError<SyncReport, Vec<I::Error>> {
+        self.sync(None, indexer, store)
+    }
+
+    /// Syncs only the range of derivation indexes of a keychain, scanning the whole range
+    /// regardless of the gap limit. See [`WalletDescr::slice`].
+    pub fn update_slice<I: Indexer>(
+        &mut self,
+        keychain: impl Into<Keychain>,
+        range: Range<u32>,
+        indexer: &I,
+        store: &mut TxStore,
+    ) -> MayError<SyncReport, Vec<I::Error>> {
+        self.sync(Some((keychain.into(), range)), indexer, store)
+    }
+
+    fn sync<I: Indexer>(
+        &mut self,
+        slice: Option<(Keychain, Range<u32>)>,
+        indexer: &I,
+        store: &mut TxStore,
+    ) -> MayError<SyncReport, Vec<I::Error>> {
+        let mut timings = Timings::new();
+        let known =
+            self.cache.tx.iter().map(|(txid, tx)| (*txid, tx.status)).collect::<BTreeMap<_, _>>();
+        let unspent = self.cache.utxo.clone();
+        // Indexers resume interrupted syncs only when the whole descriptor is scanned
+        let resumed = match slice {
+            None => self.cache.sync_cursors.iter().map(|(k, c)| (*k, c.next_index)).collect(),
+            Some(_) => none!(),
+        };
+        let view = slice.map(|(keychain, range)| self.descr.slice(keychain, range));
+        let mut res = self
+            .cache
+            .update_with_store::<I, K, D, L2>(
+                view.as_ref().unwrap_or(&self.descr),
+                indexer,
+                store,
+                &mut timings,
+            )
+            .map(|updated| SyncReport {
+                updated,
+                timings,
+                layer2_error: None,
+                resumed,
+            });
+        let changes = Layer1Changes::with(&known, &self.cache.tx);
+        self.cache.journal.record(&changes, &unspent);
+        let now = unix_now();
+        let mut dirty = false;
+        for tx in changes.added.iter().chain(&changes.updated) {
+            match self.data.provenance.entry(tx.txid) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(TxProvenance::new(TxSource::Sync, now));
+                    dirty = true;
+                }
+                btree_map::Entry::Occupied(mut entry) => {
+                    let provenance = entry.get_mut();
+                    if provenance.source != TxSource::Sync && provenance.synced.is_none() {
+                        provenance.synced = Some(now);
+                        dirty = true;
+                    }
+                }
+            }
+        }
+        if res.err.is_none() {
+            match self.layer2.sync_layer2(&mut self.data.layer2, &mut self.cache.layer2, &changes) {
+                Ok(true) => self.data.mark_dirty(),
+                Ok(false) => {}
+                Err(err) => res.ok.layer2_error = Some(err.to_string()),
+            }
+        }
+        let count = self.data.drafts.len() + self.data.pending.len();
+        let cache = &self.cache;
+        let is_mined = |txid: &Txid| matches!(cache.tx.get(txid), Some(tx) if matches!(tx.status, TxStatus::Mined(_)));
+        self.data.drafts.retain(|txid, _| !is_mined(txid));
+        self.data.pending.retain(|txid, _| !is_mined(txid));
+        dirty |= self.data.drafts.len() + self.data.pending.len() != count;
+        for (txid, pending) in &mut self.data.pending {
+            if cache.tx.contains_key(txid) && pending.status < PendingStatus::Broadcast {
+                pending.status = PendingStatus::Broadcast;
+                dirty = true;
+            }
+        }
+        if dirty {
+            self.data.mark_dirty();
+        }
+        res
+    }
+
+    /// Scripts of the issued wallet addresses and of the addresses within the gap limit after
+    /// them, which may be issued next.
+    pub(crate) fn issuable_scripts(&self) -> BTreeMap<ScriptPubkey, DerivedAddr> {
+        let mut scripts = BTreeMap::new();
+        for keychain in self.keychains() {
+            let status = self.keychain_status(keychain);
+            let issued = status.next_index.max(status.next_published).index();
+            for derived in self.addresses(keychain).take((issued + GAP_LIMIT) as usize) {
+                scripts.insert(derived.addr.script_pubkey(), derived);
+            }
+        }
+        scripts
+    }
+
+    /// Adds a transaction obtained without an indexer, for instance received from a
+    /// counterparty, to the wallet cache. Inputs and outputs are resolved against the issued
+    /// wallet addresses and the gap limit of addresses which may be issued next. Previous outputs
+    /// are looked up in the cache and then in the transaction store; the ones unknown to both
+    /// remain unresolved, and the transaction fee is set to zero in this case.
+    ///
+    /// If the transaction is already known, only its status is updated. Transactions the wallet
+    /// hasn't learned about before are recorded as imported, see [`Self::tx_provenance`]. Returns
+    /// `false` if the transaction neither spends from nor pays to the wallet, leaving the cache
+    /// unchanged.
+    pub fn import_tx(&mut self, tx: Tx, status: TxStatus, store: &mut TxStore) -> bool {
+        let scripts = self.issuable_scripts();
+        let network = self.network();
+        let txid = tx.txid();
+        if !self.cache.import_tx(tx, status, &scripts, network, store) {
+            return false;
+        }
+        if let btree_map::Entry::Vacant(entry) = self.data.provenance.entry(txid) {
+            entry.insert(TxProvenance::new(TxSource::Imported, unix_now()));
+            self.data.mark_dirty();
+        }
+        true
     }
 
     pub fn to_deriver(&self) -> D
@@ -556,12 +1734,8 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
     }
 
     fn last_published_derivation_index(&self, keychain: impl Into<Keychain>) -> NormalIndex {
-        let keychain = keychain.into();
-        self.address_coins()
-            .keys()
-            .filter(|ad| ad.terminal.keychain == keychain)
-            .map(|ad| ad.terminal.index)
-            .max()
+        self.cache
+            .last_used_index(keychain)
             .as_ref()
             .map(NormalIndex::saturating_inc)
             .unwrap_or_default()
@@ -573,6 +1747,21 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         cmp::max(last_index, self.last_published_derivation_index(keychain))
     }
 
+    pub fn keychain_status(&self, keychain: impl Into<Keychain>) -> KeychainStatus {
+        let keychain = keychain.into();
+        KeychainStatus {
+            keychain,
+            next_index: self.last_derivation_index(keychain),
+            next_published: self.last_published_derivation_index(keychain),
+            used: self
+                .cache
+                .addr
+                .get(&keychain)
+                .map(|set| set.iter().filter(|addr| addr.used > 0).count() as u32)
+                .unwrap_or_default(),
+        }
+    }
+
     pub fn next_address(&mut self, keychain: impl Into<Keychain>, shift: bool) -> Address {
         let keychain = keychain.into();
         let index = self.next_derivation_index(keychain, shift);
@@ -582,11 +1771,46 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
             .addr
     }
 
+    /// Looks up the terminal of a wallet address among the addresses known to the indexer, the
+    /// issued addresses and the gap limit of addresses which may be issued next.
+    pub fn terminal_of(&self, address: &Address) -> Option<Terminal> {
+        let script = address.script_pubkey();
+        if let Some(addr) = self.address_balance().find(|addr| addr.addr.script_pubkey() == script)
+        {
+            return Some(addr.terminal);
+        }
+        self.keychains().into_iter().find_map(|keychain| {
+            let status = self.keychain_status(keychain);
+            let issued = status.next_index.max(status.next_published).index();
+            self.addresses(keychain)
+                .take((issued + GAP_LIMIT) as usize)
+                .find(|derived| derived.addr.script_pubkey() == script)
+                .map(|derived| derived.terminal)
+        })
+    }
+
     pub fn balance(&self) -> Sats { self.cache.coins().map(|utxo| utxo.amount).sum::<Sats>() }
 
     #[inline]
     pub fn transactions(&self) -> &BTreeMap<Txid, WalletTx> { &self.cache.tx }
 
+    /// Last block known to the wallet, which is updated on each sync.
+    #[inline]
+    pub fn last_block(&self) -> MiningInfo { self.cache.last_block }
+
+    /// Height of the first block containing a wallet transaction, known from the cache, which
+    /// can be used to speed up wallet restoration from a backup.
+    pub fn birthday(&self) -> Option<BlockHeight> {
+        self.cache
+            .tx
+            .values()
+            .filter_map(|tx| match tx.status {
+                TxStatus::Mined(info) => Some(info.height),
+                _ => None,
+            })
+            .min()
+    }
+
     #[inline]
     pub fn coins(&self) -> impl Iterator<Item = CoinRow<<L2::Cache as Layer2Cache>::Coin>> + '_ {
         self.cache.coins()
@@ -595,22 +1819,66 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
     pub fn address_coins(
         &self,
     ) -> HashMap<DerivedAddr, Vec<CoinRow<<L2::Cache as Layer2Cache>::Coin>>> {
-        let map = HashMap::new();
-        self.coins().fold(map, |mut acc, txo| {
-            acc.entry(txo.address).or_default().push(txo);
-            acc
-        })
+        self.cache.address_coins().map(|(derived, coins)| (derived, coins.collect())).collect()
     }
 
     pub fn address_balance(&self) -> impl Iterator<Item = WalletAddr> + '_ {
         self.cache.addr.values().flat_map(|set| set.iter()).copied()
     }
 
+    /// Computes changes of the wallet cache since the state with the given transaction statuses
+    /// and address statistics. See [`WalletCache::delta`].
+    pub fn cache_delta(
+        &self,
+        known_tx: &BTreeMap<Txid, TxStatus>,
+        known_addr: &BTreeSet<WalletAddr>,
+    ) -> CacheDelta {
+        self.cache.delta(known_tx, known_addr)
+    }
+
+    /// Returns the events recorded by the syncs after the event with the given sequence number,
+    /// allowing incremental consumers to catch up after a downtime. Use zero to replay the whole
+    /// journal.
+    pub fn events_since(&self, seq: u64) -> &[JournalEntry] { self.cache.journal.since(seq) }
+
     #[inline]
     pub fn history(&self) -> impl Iterator<Item = TxRow<<L2::Cache as Layer2Cache>::Tx>> + '_ {
         self.cache.history()
     }
 
+    /// Aggregates miner fees paid by the wallet per calendar period, see
+    /// [`WalletCache::fee_report`].
+    #[inline]
+    pub fn fee_report(&self, period: Period, own: &OwnWallets) -> BTreeMap<PeriodBucket, FeeTotal> {
+        self.cache.fee_report(period, own)
+    }
+
+    /// Tracks lots of the wallet funds and the capital gains of their disposals, see
+    /// [`WalletCache::track_lots`].
+    #[inline]
+    pub fn track_lots<E>(
+        &self,
+        method: LotMethod,
+        rate: impl FnMut(u64) -> Result<f64, E>,
+    ) -> Result<LotTracker, E> {
+        self.cache.track_lots(method, rate)
+    }
+
+    /// Iterates over the history in the order of transaction heights, see
+    /// [`WalletCache::history_by_height`].
+    #[inline]
+    pub fn history_by_height(
+        &self,
+    ) -> impl Iterator<Item = TxRow<<L2::Cache as Layer2Cache>::Tx>> + '_ {
+        self.cache.history_by_height()
+    }
+
+    /// Lists credits and debits of the wallet address, see [`WalletCache::address_history`].
+    #[inline]
+    pub fn address_history(&self, terminal: Terminal) -> Vec<AddrRow> {
+        self.cache.address_history(terminal)
+    }
+
     pub fn has_outpoint(&self, outpoint: Outpoint) -> bool { self.cache.has_outpoint(outpoint) }
     pub fn is_unspent(&self, outpoint: Outpoint) -> bool { self.cache.is_unspent(outpoint) }
 
@@ -620,14 +1888,136 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
 
     pub fn txos(&self) -> impl Iterator<Item = WalletUtxo> + '_ { self.cache.txos() }
     pub fn utxos(&self) -> impl Iterator<Item = WalletUtxo> + '_ { self.cache.utxos() }
+    pub fn dangling_utxos(&self) -> Vec<Outpoint> { self.cache.dangling_utxos() }
+
+    /// Unconfirmed wallet transactions the transaction depends on, see
+    /// [`WalletCache::unconfirmed_ancestors`].
+    pub fn unconfirmed_ancestors(&self, txid: Txid) -> BTreeSet<Txid> {
+        self.cache.unconfirmed_ancestors(txid)
+    }
+
+    /// Wallet coins after the block at the `height`, see [`WalletCache::utxos_at`].
+    pub fn utxos_at(&self, height: BlockHeight) -> Vec<WalletUtxo> { self.cache.utxos_at(height) }
+
+    /// Wallet balance after the block at the `height`, for accounting cut-offs and audits.
+    pub fn balance_at(&self, height: BlockHeight) -> Sats {
+        self.cache.utxos_at(height).iter().map(|utxo| utxo.value).sum()
+    }
+
+    /// Height of the last wallet transaction mined by the UNIX `timestamp`, see
+    /// [`WalletCache::height_at`].
+    pub fn height_at(&self, timestamp: u64) -> Option<BlockHeight> {
+        self.cache.height_at(timestamp)
+    }
+
+    /// Unspent outputs having at least `min_conf` confirmations, counted against the chain tip
+    /// known from the last sync.
+    pub fn spendable_utxos(
+        &self,
+        min_conf: Confirmations,
+    ) -> impl Iterator<Item = WalletUtxo> + '_ {
+        let tip = self.cache.last_block.height;
+        self.utxos().filter(move |utxo| utxo.status.confirmations(tip) >= min_conf)
+    }
+
+    /// Balance of unspent outputs which have less than `min_conf` confirmations.
+    pub fn locked_balance(&self, min_conf: Confirmations) -> Sats {
+        let tip = self.cache.last_block.height;
+        self.utxos()
+            .filter(|utxo| utxo.status.confirmations(tip) < min_conf)
+            .map(|utxo| utxo.value)
+            .sum()
+    }
+
+    /// Checks that all the coins have at least `min_conf` confirmations. Coins not belonging to
+    /// the wallet are ignored.
+    pub fn check_confirmations(
+        &self,
+        coins: impl IntoIterator<Item = Outpoint>,
+        min_conf: Confirmations,
+    ) -> Result<(), ConfirmationError> {
+        let tip = self.cache.last_block.height;
+        for utxo in coins.into_iter().filter_map(|outpoint| self.outpoint_by(outpoint).ok()) {
+            let confirmations = utxo.status.confirmations(tip);
+            if confirmations < min_conf {
+                return Err(ConfirmationError::Unconfirmed {
+                    outpoint: utxo.outpoint,
+                    confirmations,
+                    required: min_conf,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether the coins having at least `min_conf` confirmations are sufficient to pay
+    /// `required` amount. Fails only if the funds are insufficient because some of the coins
+    /// are locked by the confirmation threshold.
+    pub fn check_spendable(
+        &self,
+        required: Sats,
+        min_conf: Confirmations,
+    ) -> Result<(), ConfirmationError> {
+        let available = self.spendable_utxos(min_conf).map(|utxo| utxo.value).sum::<Sats>();
+        let locked = self.locked_balance(min_conf);
+        if available < required && locked > Sats::ZERO {
+            return Err(ConfirmationError::Locked {
+                required,
+                available,
+                locked,
+                min_conf,
+            });
+        }
+        Ok(())
+    }
 
+    /// Constructs an unsigned transaction spending the coins, without assembling a PSBT, for
+    /// integrations having their own signing flow. The validation of the coins and the change
+    /// logic are the same as in [`PsbtConstructor::construct_psbt`], so the change derivation
+    /// index gets shifted when the transaction has a change output.
+    ///
+    /// Returns the transaction together with the spent coins, in the order of the transaction
+    /// inputs.
+    pub fn draft_tx(
+        &mut self,
+        coins: impl IntoIterator<Item = Outpoint>,
+        beneficiaries: &[Beneficiary],
+        params: TxParams,
+    ) -> Result<(Tx, Vec<WalletUtxo>, DraftMeta), ConstructionError> {
+        let (psbt, meta) = self.construct_psbt(coins, beneficiaries, params)?;
+        let spent = psbt
+            .inputs()
+            .map(|input| {
+                self.outpoint_by(input.previous_outpoint)
+                    .expect("PSBT constructor spends only the wallet coins")
+            })
+            .collect::<Vec<_>>();
+        let meta = DraftMeta {
+            fee: psbt.fee().expect("PSBT constructor doesn't overspend the coins"),
+            change_vout: meta.change_vout,
+            change_terminal: meta.change_terminal,
+        };
+        Ok((Tx::from(psbt.to_unsigned_tx()), spent, meta))
+    }
+
+    /// Selects coins having at least [`Self::min_confirmations`] confirmations.
     pub fn coinselect<'a>(
         &'a self,
         up_to: Sats,
         selector: impl Fn(&WalletUtxo) -> bool + 'a,
+    ) -> impl Iterator<Item = Outpoint> + 'a {
+        self.coinselect_min_conf(up_to, self.min_confirmations(), selector)
+    }
+
+    /// Selects coins having at least `min_conf` confirmations.
+    pub fn coinselect_min_conf<'a>(
+        &'a self,
+        up_to: Sats,
+        min_conf: Confirmations,
+        selector: impl Fn(&WalletUtxo) -> bool + 'a,
     ) -> impl Iterator<Item = Outpoint> + 'a {
         let mut selected = Sats::ZERO;
-        self.utxos()
+        self.spendable_utxos(min_conf)
             .filter(selector)
             .take_while(move |utxo| {
                 if selected <= up_to {
@@ -641,14 +2031,45 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
     }
 }
 
+/// Persistence provider able to store all the objects a wallet is made of: its descriptor,
+/// data, cache and layer 2 state.
+///
+/// The trait is implemented automatically for any cloneable type implementing
+/// [`PersistenceProvider`] for all of these objects, so downstream crates only need to provide
+/// the persistence implementations to have their store (like an encrypted file or a network
+/// key-value storage) accepted by [`Wallet::load`] and [`Wallet::make_persistent`].
+pub trait WalletStore<K, D: Descriptor<K>, L2: Layer2>:
+    Clone
+    + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
+    + PersistenceProvider<WalletData<L2::Data>>
+    + PersistenceProvider<WalletCache<L2::Cache>>
+    + PersistenceProvider<L2>
+    + 'static
+{
+}
+
+impl<K, D: Descriptor<K>, L2: Layer2, P> WalletStore<K, D, L2> for P where P: Clone
+        + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
+        + PersistenceProvider<WalletData<L2::Data>>
+        + PersistenceProvider<WalletCache<L2::Cache>>
+        + PersistenceProvider<L2>
+        + 'static
+{
+}
+
+/// Factory opening [`WalletStore`]s for wallets given by their location, which is a path
+/// for file-based stores and may be used as a key by other store kinds.
+pub trait WalletStoreFactory {
+    type Store: Clone + 'static;
+
+    fn open(&self, location: PathBuf) -> Result<Self::Store, PersistenceError>;
+}
+
 impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
-    pub fn load<P>(provider: P, autosave: bool) -> Result<Wallet<K, D, L2>, PersistenceError>
-    where P: Clone
-            + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
-            + PersistenceProvider<WalletData<L2::Data>>
-            + PersistenceProvider<WalletCache<L2::Cache>>
-            + PersistenceProvider<L2>
-            + 'static {
+    pub fn load(
+        provider: impl WalletStore<K, D, L2>,
+        autosave: bool,
+    ) -> Result<Wallet<K, D, L2>, PersistenceError> {
         let descr = WalletDescr::<K, D, L2::Descr>::load(provider.clone(), autosave)?;
         let data = WalletData::<L2::Data>::load(provider.clone(), autosave)?;
         let cache = WalletCache::<L2::Cache>::load(provider.clone(), autosave)?;
@@ -667,19 +2088,11 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         self.cache.id = Some(id.to_string());
     }
 
-    pub fn make_persistent<P>(
+    pub fn make_persistent(
         &mut self,
-        provider: P,
+        provider: impl WalletStore<K, D, L2>,
         autosave: bool,
-    ) -> Result<bool, PersistenceError>
-    where
-        P: Clone
-            + PersistenceProvider<WalletDescr<K, D, L2::Descr>>
-            + PersistenceProvider<WalletData<L2::Data>>
-            + PersistenceProvider<WalletCache<L2::Cache>>
-            + PersistenceProvider<L2>
-            + 'static,
-    {
+    ) -> Result<bool, PersistenceError> {
         let a = self.descr.make_persistent(provider.clone(), autosave)?;
         let b = self.data.make_persistent(provider.clone(), autosave)?;
         let c = self.cache.make_persistent(provider.clone(), autosave)?;
@@ -687,6 +2100,42 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         Ok(a && b && c && d)
     }
 
+    /// Detects whether the wallet is persisted, i.e. is loaded from or saved to a persistence
+    /// provider.
+    pub fn is_persisted(&self) -> bool {
+        self.descr.is_persisted()
+            && self.data.is_persisted()
+            && self.cache.is_persisted()
+            && self.layer2.is_persisted()
+    }
+
+    /// Detects whether some of the persisted wallet components have changes which are not yet
+    /// stored.
+    pub fn is_dirty(&self) -> bool {
+        (self.descr.is_persisted() && self.descr.is_dirty())
+            || (self.data.is_persisted() && self.data.is_dirty())
+            || (self.cache.is_persisted() && self.cache.is_dirty())
+            || (self.layer2.is_persisted() && self.layer2.is_dirty())
+    }
+
+    /// Stores all wallet components, including the ones which have no changes.
+    pub fn store_all(&mut self) -> Result<(), PersistenceError> {
+        if let Some(p) = self.descr.persistence_mut() {
+            p.dirty = true;
+        }
+        if let Some(p) = self.data.persistence_mut() {
+            p.dirty = true;
+        }
+        if let Some(p) = self.cache.persistence_mut() {
+            p.dirty = true;
+        }
+        if let Some(p) = self.layer2.persistence_mut() {
+            p.dirty = true;
+        }
+        self.store()
+    }
+
+    /// Stores wallet components which have changes.
     pub fn store(&mut self) -> Result<(), PersistenceError> {
         // TODO: Revert on failure
 
@@ -698,3 +2147,185 @@ impl<K, D: Descriptor<K>, L2: Layer2> Wallet<K, D, L2> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bpstd::{LockTime, TxIn, TxOut, TxVer, VarIntArray};
+
+    use super::*;
+    use crate::fixtures::{test_descriptor, test_wallet};
+    use crate::{OpType, SimIndexer, SEQ_NO_NO_RBF};
+
+    #[test]
+    fn test_slice_scanned() {
+        let descr = WalletDescr::new_standard(test_descriptor(), Network::Testnet3);
+        let slice = descr.slice(Keychain::OUTER, 5..8);
+        assert_eq!(slice.watched_keychains(), bset![Keychain::OUTER]);
+        assert_eq!(slice.watched_addresses(Keychain::INNER).count(), 0);
+        assert_eq!(
+            slice.watched_addresses(Keychain::OUTER).collect::<Vec<_>>(),
+            descr.addresses(Keychain::OUTER).skip(5).take(3).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            descr.slice(Keychain::OUTER, 5..5).watched_addresses(Keychain::OUTER).count(),
+            0
+        );
+        assert_eq!(
+            slice.watched_addresses_from(Keychain::OUTER, NormalIndex::normal(6)).count(),
+            2
+        );
+        assert_eq!(
+            slice.watched_addresses_from(Keychain::OUTER, NormalIndex::normal(8)).count(),
+            0
+        );
+        assert_eq!(
+            descr.watched_addresses_from(Keychain::OUTER, NormalIndex::normal(5)).next(),
+            descr.addresses(Keychain::OUTER).nth(5)
+        );
+
+        let mut cache = WalletCache::<Layer2Empty>::new_nonsync();
+        cache.mark_scanned(Keychain::OUTER, 10..20);
+        cache.mark_scanned(Keychain::OUTER, 0..5);
+        cache.mark_scanned(Keychain::OUTER, 30..40);
+        assert_eq!(cache.scanned[&Keychain::OUTER], vec![0..5, 10..20, 30..40]);
+        cache.mark_scanned(Keychain::OUTER, 5..30);
+        assert_eq!(cache.scanned[&Keychain::OUTER], vec![0..40]);
+        assert!(cache.is_scanned(Terminal::new(Keychain::OUTER, NormalIndex::normal(39))));
+        assert!(!cache.is_scanned(Terminal::new(Keychain::OUTER, NormalIndex::normal(40))));
+        assert!(!cache.is_scanned(Terminal::new(Keychain::INNER, NormalIndex::normal(0))));
+    }
+
+    #[test]
+    fn test_key_origin() {
+        let descr = WalletDescr::new_standard(test_descriptor(), Network::Testnet3);
+        let origins = descr.key_origin(Terminal::new(Keychain::INNER, NormalIndex::normal(5)));
+        assert_eq!(origins.len(), 1);
+        assert_eq!(origins[0].master_fp().to_string(), "643a7adc");
+        assert_eq!(origins[0].derivation().len(), 5);
+    }
+
+    #[test]
+    fn test_utxos_at() {
+        let descr = WalletDescr::new_standard(test_descriptor(), Network::Testnet3);
+        let addrs = descr.addresses(Keychain::OUTER).take(2).collect::<Vec<_>>();
+        let mined = |height: u32| {
+            let mut info = MiningInfo::genesis();
+            info.height = BlockHeight::new(height).unwrap();
+            info.time = 1_700_000_000 + height as u64 * 600;
+            TxStatus::Mined(info)
+        };
+        let wallet_tx = |id: u8, status, inputs: Vec<Outpoint>, outputs: Vec<(usize, u64)>| {
+            let txid = Txid::from([id; 32]);
+            WalletTx {
+                txid,
+                status,
+                inputs: inputs
+                    .into_iter()
+                    .map(|outpoint| TxCredit {
+                        outpoint,
+                        payer: Party::Wallet(addrs[0]),
+                        sequence: SEQ_NO_NO_RBF,
+                        coinbase: false,
+                        script_sig: none!(),
+                        witness: none!(),
+                        value: Sats::ZERO,
+                    })
+                    .collect(),
+                outputs: outputs
+                    .into_iter()
+                    .enumerate()
+                    .map(|(vout, (addr, value))| TxDebit {
+                        outpoint: Outpoint::new(txid, vout as u32),
+                        beneficiary: Party::Wallet(addrs[addr]),
+                        value: Sats(value),
+                        spent: None,
+                    })
+                    .collect(),
+                fee: Sats::ZERO,
+                size: 0,
+                weight: 0,
+                version: TxVer::V2,
+                locktime: LockTime::ZERO,
+            }
+        };
+        let funding = Outpoint::new(Txid::from([1; 32]), 0u32);
+        let change = Outpoint::new(Txid::from([2; 32]), 0u32);
+        let mut cache = WalletCache::<Layer2Empty>::new_nonsync();
+        for tx in [
+            wallet_tx(1, mined(100), vec![], vec![(0, 50_000)]),
+            wallet_tx(2, mined(110), vec![funding], vec![(1, 30_000)]),
+            wallet_tx(3, TxStatus::Mempool, vec![change], vec![]),
+        ] {
+            cache.tx.insert(tx.txid, tx);
+        }
+
+        assert!(cache.utxos_at(BlockHeight::new(99).unwrap()).is_empty());
+        let coins = cache.utxos_at(BlockHeight::new(109).unwrap());
+        assert_eq!(coins.iter().map(|coin| coin.outpoint).collect::<Vec<_>>(), vec![funding]);
+        let coins = cache.utxos_at(BlockHeight::new(110).unwrap());
+        assert_eq!(coins.iter().map(|coin| (coin.value, coin.terminal)).collect::<Vec<_>>(), vec![
+            (Sats(30_000), addrs[1].terminal)
+        ]);
+        // Unmined spend doesn't affect the snapshots
+        assert_eq!(cache.utxos_at(BlockHeight::new(1000).unwrap()), coins);
+
+        assert_eq!(cache.height_at(1_700_000_000 + 100 * 600 - 1), None);
+        assert_eq!(cache.height_at(1_700_000_000 + 109 * 600), BlockHeight::new(100));
+        assert_eq!(cache.height_at(u64::MAX), BlockHeight::new(110));
+
+        let history = cache.address_history(addrs[0].terminal);
+        assert_eq!(history.iter().map(|row| (row.txid, row.operation)).collect::<Vec<_>>(), vec![
+            (Txid::from([3; 32]), OpType::Debit),
+            (Txid::from([1; 32]), OpType::Credit),
+            (Txid::from([2; 32]), OpType::Debit),
+        ]);
+        let history = cache.address_history(addrs[1].terminal);
+        assert_eq!(history.iter().map(|row| (row.outpoint, row.amount)).collect::<Vec<_>>(), vec![
+            (Outpoint::new(Txid::from([2; 32]), 0u32), Sats(30_000))
+        ]);
+    }
+
+    #[test]
+    fn test_tx_provenance() {
+        let mut wallet = test_wallet();
+        let receive = wallet.addresses(Keychain::OUTER).next().unwrap().addr.script_pubkey();
+        let change = wallet.addresses(Keychain::INNER).next().unwrap().addr.script_pubkey();
+
+        let indexer = SimIndexer::default();
+        let funding = indexer.chain().fund(receive.clone(), Sats(100_000));
+        indexer.publish(&funding).unwrap();
+        wallet.update(&indexer).into_ok();
+        let provenance = wallet.tx_provenance(funding.txid()).unwrap();
+        assert_eq!(provenance.source, TxSource::Sync);
+        assert_eq!(provenance.synced, None);
+
+        let payment = Tx {
+            version: TxVer::V2,
+            inputs: VarIntArray::from_checked(vec![TxIn {
+                prev_output: Outpoint::new(funding.txid(), Vout::from_u32(0)),
+                sig_script: none!(),
+                sequence: SEQ_NO_NO_RBF,
+                witness: none!(),
+            }]),
+            outputs: VarIntArray::from_checked(vec![TxOut::new(change, Sats(99_000))]),
+            lock_time: LockTime::ZERO,
+        };
+        wallet.register_pending(payment.txid(), PendingTx {
+            status: PendingStatus::Draft,
+            amount: Sats::ZERO,
+            fee: Sats(1_000),
+        });
+        let provenance = wallet.tx_provenance(payment.txid()).unwrap();
+        assert_eq!(provenance.source, TxSource::Constructed);
+        assert_eq!(provenance.synced, None);
+        indexer.publish(&payment).unwrap();
+        wallet.update(&indexer).into_ok();
+        let provenance = wallet.tx_provenance(payment.txid()).unwrap();
+        assert_eq!(provenance.source, TxSource::Constructed);
+        assert!(provenance.synced.is_some_and(|synced| synced >= provenance.acquired));
+
+        let received = indexer.chain().fund(receive, Sats(50_000));
+        assert!(wallet.import_tx(received.clone(), TxStatus::Mempool, &mut TxStore::default()));
+        assert_eq!(wallet.tx_provenance(received.txid()).unwrap().source, TxSource::Imported);
+    }
+}
@@ -0,0 +1,269 @@
+// Modern, minimalistic & standard-compliant cold wallet library.
+//
+// SPDX-License-Identifier: Apache-2.0
+//
+// Written in 2020-2024 by
+//     Dr Maxim Orlovsky <orlovsky@lnp-bp.org>
+//
+// Copyright (C) 2020-2024 LNP/BP Standards Association. All rights reserved.
+// Copyright (C) 2020-2024 Dr Maxim Orlovsky. All rights reserved.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Directory-based workspace for collecting signatures from multiple cosigners.
+//!
+//! Workspace directory contains the unsigned PSBT in `unsigned.psbt` file and a `signed`
+//! subdirectory, where each cosigner puts its signed copy of the PSBT named after the cosigner
+//! (like `signed/alice.psbt`). Once enough signatures are collected, the combined and finalized
+//! PSBT is saved as `final.psbt`.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use amplify::IoError;
+use bpstd::{Txid, XOnlyPk, XpubFp};
+use psbt::{DecodeError, Input, Psbt};
+
+use crate::DryRun;
+
+/// Name of the file in the workspace directory containing the unsigned PSBT.
+pub const WORKSPACE_UNSIGNED: &str = "unsigned.psbt";
+/// Name of the workspace subdirectory containing PSBTs signed by cosigners.
+pub const WORKSPACE_SIGNED: &str = "signed";
+/// Name of the file in the workspace directory receiving the finalized PSBT.
+pub const WORKSPACE_FINAL: &str = "final.psbt";
+
+/// Errors working with PSBT workspaces.
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum WorkspaceError {
+    /// I/O error in the workspace directory: {0}
+    #[from]
+    #[from(io::Error)]
+    Io(IoError),
+
+    /// directory {0:?} already contains a PSBT workspace.
+    AlreadyInitialized(PathBuf),
+
+    /// directory {0:?} is not a PSBT workspace since it has no unsigned PSBT file.
+    NotInitialized(PathBuf),
+
+    /// invalid PSBT file {0:?}: {1}
+    InvalidPsbt(PathBuf, DecodeError),
+
+    /// PSBT file {0:?} spends transaction {1}, while the workspace is created for {2}.
+    TxMismatch(PathBuf, Txid, Txid),
+}
+
+/// Signatures collected for a single transaction input.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct InputSignatures {
+    /// Master key fingerprints of the keys which signed the input, grouped by the cosigner
+    /// files they were found in. `None` corresponds to a signature for a key which has no
+    /// derivation information in the PSBT.
+    pub signers: BTreeMap<String, Vec<Option<XpubFp>>>,
+    /// Whether the input was finalized by any of the cosigners.
+    pub finalized: bool,
+}
+
+impl InputSignatures {
+    /// Number of distinct keys which signed the input.
+    pub fn count(&self) -> usize {
+        let mut keys = self.signers.values().flatten().collect::<Vec<_>>();
+        keys.sort();
+        keys.dedup();
+        keys.len()
+    }
+}
+
+/// Workspace for collecting signatures from multiple cosigners, see module-level docs for the
+/// directory layout.
+#[derive(Clone, Debug)]
+pub struct Workspace {
+    dir: PathBuf,
+    unsigned: Psbt,
+    signed: BTreeMap<String, Psbt>,
+    changes: DryRun,
+}
+
+impl Workspace {
+    /// Creates a new workspace in the given directory for the provided unsigned PSBT.
+    pub fn init(dir: impl Into<PathBuf>, psbt: Psbt) -> Result<Self, WorkspaceError> {
+        Self::init_with_changes(dir, psbt, &none!())
+    }
+
+    /// Creates a new workspace like [`Self::init`], routing all the writes through the
+    /// [`DryRun`] collector.
+    pub fn init_with_changes(
+        dir: impl Into<PathBuf>,
+        psbt: Psbt,
+        changes: &DryRun,
+    ) -> Result<Self, WorkspaceError> {
+        let dir = dir.into();
+        if dir.join(WORKSPACE_UNSIGNED).exists() {
+            return Err(WorkspaceError::AlreadyInitialized(dir));
+        }
+        let signed_dir = dir.join(WORKSPACE_SIGNED);
+        if changes.perform(format!("create directory {}", signed_dir.display())) {
+            fs::create_dir_all(signed_dir)?;
+        }
+        changes.write(dir.join(WORKSPACE_UNSIGNED), psbt.serialize(psbt.version))?;
+        Ok(Workspace {
+            dir,
+            unsigned: psbt,
+            signed: none!(),
+            changes: changes.clone(),
+        })
+    }
+
+    /// Opens an existing workspace, reading all the cosigner PSBTs from it.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, WorkspaceError> {
+        Self::open_with_changes(dir, &none!())
+    }
+
+    /// Opens an existing workspace like [`Self::open`], routing all the writes through the
+    /// [`DryRun`] collector.
+    pub fn open_with_changes(
+        dir: impl Into<PathBuf>,
+        changes: &DryRun,
+    ) -> Result<Self, WorkspaceError> {
+        let dir = dir.into();
+        let unsigned_path = dir.join(WORKSPACE_UNSIGNED);
+        if !unsigned_path.is_file() {
+            return Err(WorkspaceError::NotInitialized(dir));
+        }
+        let unsigned = read_psbt(&unsigned_path)?;
+        let txid = unsigned.txid();
+
+        let mut signed = BTreeMap::new();
+        let signed_dir = dir.join(WORKSPACE_SIGNED);
+        if signed_dir.is_dir() {
+            for entry in fs::read_dir(signed_dir)? {
+                let path = entry?.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("psbt") {
+                    continue;
+                }
+                let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+                    continue;
+                };
+                let psbt = read_psbt(&path)?;
+                if psbt.txid() != txid {
+                    return Err(WorkspaceError::TxMismatch(path, psbt.txid(), txid));
+                }
+                signed.insert(name.to_owned(), psbt);
+            }
+        }
+
+        Ok(Workspace {
+            dir,
+            unsigned,
+            signed,
+            changes: changes.clone(),
+        })
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path { &self.dir }
+
+    #[inline]
+    pub fn unsigned(&self) -> &Psbt { &self.unsigned }
+
+    /// Names of the cosigners which have provided their signed PSBTs.
+    pub fn cosigners(&self) -> impl Iterator<Item = &str> { self.signed.keys().map(String::as_str) }
+
+    /// Reports signatures provided by the cosigners for each of the transaction inputs.
+    pub fn signatures(&self) -> Vec<InputSignatures> {
+        let mut report = vec![InputSignatures::default(); self.unsigned.inputs().count()];
+        for (name, psbt) in &self.signed {
+            for (status, input) in report.iter_mut().zip(psbt.inputs()) {
+                status.finalized |= input.is_finalized();
+                let keys = signing_keys(input);
+                if !keys.is_empty() {
+                    status.signers.insert(name.clone(), keys);
+                }
+            }
+        }
+        report
+    }
+
+    /// Constructs PSBT combining signatures from all the cosigners.
+    pub fn combine(&self) -> Psbt {
+        let mut psbt = self.unsigned.clone();
+        for signed in self.signed.values() {
+            for (input, other) in psbt.inputs_mut().zip(signed.inputs()) {
+                combine_input(input, other);
+            }
+        }
+        psbt
+    }
+
+    /// Saves finalized PSBT into the workspace, returning the path to the saved file.
+    pub fn save_final(&self, psbt: &Psbt) -> Result<PathBuf, WorkspaceError> {
+        let path = self.dir.join(WORKSPACE_FINAL);
+        self.changes.write(&path, psbt.serialize(psbt.version))?;
+        Ok(path)
+    }
+}
+
+fn read_psbt(path: &Path) -> Result<Psbt, WorkspaceError> {
+    let mut file = File::open(path)?;
+    Psbt::decode(&mut file).map_err(|err| WorkspaceError::InvalidPsbt(path.to_owned(), err))
+}
+
+fn signing_keys(input: &Input) -> Vec<Option<XpubFp>> {
+    let xonly_fp = |pk: &XOnlyPk| {
+        input.tap_bip32_derivation.get(pk).map(|derivation| derivation.origin.master_fp())
+    };
+    let mut keys = input
+        .partial_sigs
+        .keys()
+        .map(|pk| input.bip32_derivation.get(pk).map(|origin| origin.master_fp()))
+        .collect::<Vec<_>>();
+    if input.tap_key_sig.is_some() {
+        keys.push(input.tap_internal_key.and_then(|pk| xonly_fp(&pk.to_xonly_pk())));
+    }
+    keys.extend(input.tap_script_sig.keys().map(|(pk, _)| xonly_fp(pk)));
+    keys
+}
+
+fn combine_input(input: &mut Input, other: &Input) {
+    for (pk, sig) in &other.partial_sigs {
+        input.partial_sigs.entry(*pk).or_insert(*sig);
+    }
+    for (key, sig) in &other.tap_script_sig {
+        input.tap_script_sig.entry(*key).or_insert(*sig);
+    }
+    if input.tap_key_sig.is_none() {
+        input.tap_key_sig = other.tap_key_sig;
+    }
+    if !input.is_finalized() && other.is_finalized() {
+        input.final_script_sig = other.final_script_sig.clone();
+        input.final_witness = other.final_witness.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_count() {
+        let fp = XpubFp::from([1, 2, 3, 4]);
+        let mut status = InputSignatures::default();
+        status.signers.insert(s!("alice"), vec![Some(fp)]);
+        status.signers.insert(s!("bob"), vec![Some(fp), None]);
+        assert_eq!(status.count(), 2);
+    }
+}